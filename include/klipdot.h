@@ -0,0 +1,42 @@
+/*
+ * KlipDot C API
+ *
+ * Build with `cargo build --release --features ffi`; the shared library is
+ * written to target/release/libklipdot.{so,dylib} or klipdot.dll.
+ *
+ * All functions are safe to call from any thread. The string returned by
+ * klipdot_last_path() is owned by KlipDot and stays valid until the next
+ * call to klipdot_poll_event() or klipdot_stop().
+ */
+#ifndef KLIPDOT_H
+#define KLIPDOT_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define KLIPDOT_OK 0
+#define KLIPDOT_EVENT_NONE 0
+#define KLIPDOT_EVENT_IMAGE 1
+#define KLIPDOT_ERR_NOT_STARTED (-1)
+#define KLIPDOT_ERR_ALREADY_STARTED (-2)
+#define KLIPDOT_ERR_INVALID_ARG (-3)
+#define KLIPDOT_ERR_INIT (-4)
+
+/* Start monitoring. Pass NULL to use the default config file. */
+int klipdot_start(const char *config_path);
+
+/* Non-blocking: returns KLIPDOT_EVENT_IMAGE, KLIPDOT_EVENT_NONE or an error. */
+int klipdot_poll_event(void);
+
+/* Path of the most recently polled image, or NULL. */
+const char *klipdot_last_path(void);
+
+/* Stop monitoring and free all resources. */
+int klipdot_stop(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* KLIPDOT_H */
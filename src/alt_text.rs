@@ -0,0 +1,182 @@
+//! Short descriptions of stored images for accessible alt text.
+//!
+//! The description comes from a configurable command, so it can be a local
+//! model (`ollama run llava`) or a hosted one through a CLI such as `llm`.
+//! It runs in the background after an image is stored and is appended to the
+//! metadata index, where `klipdot snippet` picks it up.
+
+use crate::{
+    command_runner::{CommandRunner, SharedRunner},
+    config::AltTextConfig,
+    error::Result,
+    metadata, Error,
+};
+use std::path::Path;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Placeholder in the configured command replaced by the image path
+const PATH_PLACEHOLDER: &str = "{path}";
+
+/// Longest description kept, in characters
+const MAX_ALT_TEXT_LEN: usize = 200;
+
+/// Describe the image at `path` with the configured command
+pub async fn describe(runner: &dyn CommandRunner, config: &AltTextConfig, path: &Path) -> Result<String> {
+    let Some((program, args)) = config.command.split_first() else {
+        return Err(Error::Config("alt_text.command is empty".to_string()));
+    };
+    if !runner.is_available(program) {
+        return Err(Error::Unsupported(format!("{} is needed to describe images", program)));
+    }
+
+    let path = path.to_string_lossy();
+    let mut args: Vec<String> = args.iter().map(|arg| arg.replace(PATH_PLACEHOLDER, &path)).collect();
+    if !config.command.iter().any(|arg| arg.contains(PATH_PLACEHOLDER)) {
+        args.push(path.to_string());
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let output = tokio::time::timeout(timeout, runner.run(program, &args, None))
+        .await
+        .map_err(|_| Error::Process(format!("{} took longer than {}s", program, config.timeout_secs)))?
+        .map_err(|e| Error::Process(format!("Failed to run {}: {}", program, e)))?;
+    if !output.success {
+        return Err(Error::Process(format!("{} failed: {}", program, output.stderr_lossy().trim())));
+    }
+
+    let description = clean(&output.stdout_lossy());
+    if description.is_empty() {
+        return Err(Error::Process(format!("{} printed no description", program)));
+    }
+    Ok(description)
+}
+
+/// Describe the stored image at `path` and add the description to its metadata
+pub async fn generate_and_record(runner: &dyn CommandRunner, config: &AltTextConfig, path: &Path) -> Result<String> {
+    let (Some(dir), Some(filename)) = (path.parent(), path.file_name()) else {
+        return Err(Error::InvalidInput(format!("Not an image file: {:?}", path)));
+    };
+    let filename = filename.to_string_lossy();
+    let description = describe(runner, config, path).await?;
+
+    // The index is append-only and later entries win, so the updated entry replaces the original
+    let Some(mut entry) = metadata::load(dir).await?.remove(filename.as_ref()) else {
+        debug!("{:?} has no metadata entry, not recording its description", path);
+        return Ok(description);
+    };
+    entry.alt_text = Some(description.clone());
+    metadata::record(dir, &entry).await?;
+    Ok(description)
+}
+
+/// Generate the description for a newly stored image without holding up processing
+pub fn spawn_generation(runner: SharedRunner, config: AltTextConfig, path: std::path::PathBuf) {
+    tokio::spawn(async move {
+        match generate_and_record(runner.as_ref(), &config, &path).await {
+            Ok(description) => info!("Described {:?}: {}", path, description),
+            Err(e) => {
+                warn!("Failed to describe {:?}: {}", path, e);
+                crate::error_history::record_error("alt_text", &e);
+            }
+        }
+    });
+}
+
+/// First paragraph of model output on one line, without wrapping quotes,
+/// cut at a word boundary to [`MAX_ALT_TEXT_LEN`]
+fn clean(output: &str) -> String {
+    let paragraph = output
+        .trim()
+        .split("\n\n")
+        .next()
+        .unwrap_or_default()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let paragraph = paragraph.trim_matches(['"', '\'']);
+
+    if paragraph.chars().count() <= MAX_ALT_TEXT_LEN {
+        return paragraph.to_string();
+    }
+    let cut: String = paragraph.chars().take(MAX_ALT_TEXT_LEN).collect();
+    match cut.rfind(' ') {
+        Some(space) => format!("{}…", cut[..space].trim_end_matches([',', ';', ':'])),
+        None => cut,
+    }
+}
+
+/// Markdown image for `path` with `alt` text escaped
+pub fn markdown(alt: &str, path: &Path) -> String {
+    markdown_link(alt, &path.to_string_lossy())
+}
+
+/// Markdown image linking to `destination`, a path or URL
+pub fn markdown_link(alt: &str, destination: &str) -> String {
+    let alt: String = alt
+        .chars()
+        .flat_map(|c| match c {
+            '[' | ']' | '\\' => vec!['\\', c],
+            '\n' | '\r' => vec![' '],
+            c => vec![c],
+        })
+        .collect();
+    // Angle brackets let link destinations contain spaces and parentheses
+    if destination.contains([' ', '(', ')']) {
+        format!("![{}](<{}>)", alt, destination)
+    } else {
+        format!("![{}]({})", alt, destination)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+    use crate::metadata::ImageMetadata;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_clean() {
+        assert_eq!(clean("  \"A terminal showing   a failed build.\"\n\nMore detail.\n"), "A terminal showing a failed build.");
+        let long = clean(&"word ".repeat(100));
+        assert!(long.ends_with("word…") && long.chars().count() <= MAX_ALT_TEXT_LEN + 1);
+    }
+
+    #[test]
+    fn test_markdown() {
+        assert_eq!(markdown("Login [beta] page", Path::new("/tmp/a.png")), r"![Login \[beta\] page](/tmp/a.png)");
+        assert_eq!(markdown("Shot", Path::new("/tmp/my shot.png")), "![Shot](</tmp/my shot.png>)");
+    }
+
+    #[tokio::test]
+    async fn test_generate_and_record() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("clipboard-1.png");
+        std::fs::write(&path, b"png").unwrap();
+        metadata::record(temp_dir.path(), &ImageMetadata {
+            filename: "clipboard-1.png".to_string(),
+            source: "clipboard".to_string(),
+            app: Some("firefox".to_string()),
+            output: None,
+            resized_from: None,
+            hash: None,
+            alt_text: None,
+            tags: Vec::new(),
+            window: None,
+            cold: None,
+        }).await.unwrap();
+
+        let runner = FakeRunner::new().with_output("ollama", CommandOutput::ok("A login form with an error banner.\n"));
+        let config = AltTextConfig::default();
+        let description = generate_and_record(&runner, &config, &path).await.unwrap();
+        assert_eq!(description, "A login form with an error banner.");
+
+        let args = &runner.calls_to("ollama")[0].args;
+        assert!(args.last().unwrap().ends_with(&*path.to_string_lossy()));
+        let entry = &metadata::load(temp_dir.path()).await.unwrap()["clipboard-1.png"];
+        assert_eq!(entry.alt_text.as_deref(), Some("A login form with an error banner."));
+        assert_eq!(entry.app.as_deref(), Some("firefox"));
+    }
+}
@@ -0,0 +1,366 @@
+//! The `klipdot annotate` terminal UI.
+//!
+//! The image is shown in truecolor half blocks on the alternate screen with a
+//! cursor moved by keys. A box, arrow or crop is drawn by starting it at one
+//! point and confirming it at another; text is typed in place. Each change
+//! redraws the preview, which is the image scaled down once with the markup
+//! drawn over it at the same scale.
+
+use super::{Annotation, Markup, Point, Rect, Shape, Style, COLORS};
+use crate::{
+    error::Result,
+    pixel_view::{self, Cursor, ScreenGuard, Viewport},
+    Error,
+};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::io::{self, IsTerminal};
+
+/// Share of their brightness kept by pixels outside the crop in the preview
+const CROP_DIM: f32 = 0.35;
+
+/// Shapes drawn by starting at one point and confirming at another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    Box,
+    Arrow,
+    Crop,
+}
+
+impl Tool {
+    fn shape(self, anchor: Point, cursor: Point) -> Option<Shape> {
+        match self {
+            Tool::Box => Some(Shape::Box(Rect::spanning(anchor, cursor))),
+            Tool::Arrow if anchor != cursor => Some(Shape::Arrow { from: anchor, to: cursor }),
+            Tool::Arrow => None,
+            Tool::Crop => Some(Shape::Crop(Rect::spanning(anchor, cursor))),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Tool::Box => "box",
+            Tool::Arrow => "arrow",
+            Tool::Crop => "crop",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Mode {
+    Idle,
+    Drawing { tool: Tool, anchor: Point },
+    Typing { at: Point, text: String },
+}
+
+/// What a keystroke asks the editor to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorAction {
+    Redraw,
+    Save,
+    Quit,
+    Ignored,
+}
+
+/// Markup being made on an image, and the cursor making it
+#[derive(Debug, Clone)]
+pub struct Editor {
+    cursor: Cursor,
+    mode: Mode,
+    color: usize,
+    markup: Markup,
+}
+
+impl Editor {
+    /// An editor for an image of `width` × `height`, the cursor in its middle
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            cursor: Cursor::centered(width, height),
+            mode: Mode::Idle,
+            color: 0,
+            markup: Markup::default(),
+        }
+    }
+
+    pub fn set_step(&mut self, step: u32) {
+        self.cursor.set_step(step);
+    }
+
+    pub fn cursor(&self) -> Point {
+        let (x, y) = self.cursor.position();
+        Point { x, y }
+    }
+
+    pub fn markup(&self) -> &Markup {
+        &self.markup
+    }
+
+    pub fn into_markup(self) -> Markup {
+        self.markup
+    }
+
+    fn color(&self) -> Rgba<u8> {
+        COLORS[self.color].1
+    }
+
+    /// The annotation being drawn or typed, as it would be placed now
+    pub fn pending(&self) -> Option<Annotation> {
+        let shape = match &self.mode {
+            Mode::Idle => return None,
+            Mode::Drawing { tool, anchor } => tool.shape(*anchor, self.cursor())?,
+            Mode::Typing { at, text } => Shape::Text { at: *at, text: text.clone() },
+        };
+        Some(Annotation { shape, color: self.color() })
+    }
+
+    pub fn apply(&mut self, key: KeyEvent) -> EditorAction {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        if ctrl && key.code == KeyCode::Char('c') {
+            return EditorAction::Quit;
+        }
+        // Letters are text while typing, so only arrows move then
+        let typing = matches!(self.mode, Mode::Typing { .. });
+        if self.cursor.handle(key, !typing) {
+            // Text follows the cursor until it's placed
+            let cursor = self.cursor();
+            if let Mode::Typing { at, .. } = &mut self.mode {
+                *at = cursor;
+            }
+            return EditorAction::Redraw;
+        }
+
+        match std::mem::replace(&mut self.mode, Mode::Idle) {
+            Mode::Typing { at, mut text } => match key.code {
+                KeyCode::Enter => {
+                    if !text.is_empty() {
+                        self.markup.push(Annotation { shape: Shape::Text { at, text }, color: self.color() });
+                    }
+                    EditorAction::Redraw
+                }
+                KeyCode::Esc => EditorAction::Redraw,
+                KeyCode::Backspace => {
+                    text.pop();
+                    self.mode = Mode::Typing { at, text };
+                    EditorAction::Redraw
+                }
+                KeyCode::Char(c) if !ctrl => {
+                    text.push(c);
+                    self.mode = Mode::Typing { at, text };
+                    EditorAction::Redraw
+                }
+                _ => {
+                    self.mode = Mode::Typing { at, text };
+                    EditorAction::Ignored
+                }
+            },
+            Mode::Drawing { tool, anchor } => match key.code {
+                KeyCode::Enter | KeyCode::Char(' ') => {
+                    if let Some(shape) = tool.shape(anchor, self.cursor()) {
+                        self.markup.push(Annotation { shape, color: self.color() });
+                    }
+                    EditorAction::Redraw
+                }
+                KeyCode::Esc => EditorAction::Redraw,
+                _ => {
+                    self.mode = Mode::Drawing { tool, anchor };
+                    EditorAction::Ignored
+                }
+            },
+            Mode::Idle => match key.code {
+                KeyCode::Char('b') => self.start(Tool::Box),
+                KeyCode::Char('a') => self.start(Tool::Arrow),
+                KeyCode::Char('c') => self.start(Tool::Crop),
+                KeyCode::Char('t') => {
+                    self.mode = Mode::Typing { at: self.cursor(), text: String::new() };
+                    EditorAction::Redraw
+                }
+                KeyCode::Char('n') => {
+                    self.color = (self.color + 1) % COLORS.len();
+                    EditorAction::Redraw
+                }
+                KeyCode::Char('u') => {
+                    self.markup.undo();
+                    EditorAction::Redraw
+                }
+                KeyCode::Char('s') | KeyCode::Enter => EditorAction::Save,
+                KeyCode::Char('q') | KeyCode::Esc => EditorAction::Quit,
+                _ => EditorAction::Ignored,
+            },
+        }
+    }
+
+    fn start(&mut self, tool: Tool) -> EditorAction {
+        self.mode = Mode::Drawing { tool, anchor: self.cursor() };
+        EditorAction::Redraw
+    }
+
+    /// What the editor is doing, for the status line
+    pub fn status(&self) -> String {
+        let color = COLORS[self.color].0;
+        let (x, y) = self.cursor.position();
+        match &self.mode {
+            Mode::Idle => format!(
+                "{},{} {} · {} marks · move: arrows/hjkl (shift: faster)  b box  a arrow  t text  c crop  n color  u undo  s save  q quit",
+                x, y, color, self.markup.items.len()
+            ),
+            Mode::Drawing { tool, .. } => format!("{},{} {} {} · move to the other end, Enter to place, Esc to cancel", x, y, color, tool.name()),
+            Mode::Typing { text, .. } => format!("{},{} {} text: {}▏ · Enter to place, Esc to cancel", x, y, color, text),
+        }
+    }
+}
+
+/// The image scaled to fit the terminal
+struct View {
+    viewport: Viewport,
+    style: Style,
+}
+
+impl View {
+    fn fit(img: &DynamicImage, size: (u16, u16)) -> Self {
+        Self {
+            viewport: Viewport::fit(img, size),
+            style: Style::for_size(img.width().max(1), img.height().max(1)),
+        }
+    }
+
+    fn frame(&self, editor: &Editor) -> String {
+        let scale = self.viewport.scale();
+        let mut canvas = self.viewport.canvas();
+        editor.markup().draw(&mut canvas, self.style, scale);
+        let pending = editor.pending();
+        if let Some(pending) = &pending {
+            super::draw_annotation(&mut canvas, pending, self.style, scale);
+        }
+
+        let crop = match pending.map(|pending| pending.shape) {
+            Some(Shape::Crop(rect)) => Some(rect),
+            _ => editor.markup().crop(),
+        };
+        if let Some(rect) = crop {
+            self.dim_outside(&mut canvas, rect);
+        }
+
+        self.viewport.render(canvas, editor.cursor.position())
+    }
+
+    fn dim_outside(&self, canvas: &mut RgbaImage, rect: Rect) {
+        let scale = self.viewport.scale();
+        let left = rect.x as f32 * scale;
+        let top = rect.y as f32 * scale;
+        let right = (rect.x + rect.width) as f32 * scale;
+        let bottom = (rect.y + rect.height) as f32 * scale;
+        for (x, y, pixel) in canvas.enumerate_pixels_mut() {
+            let (x, y) = (x as f32 + 0.5, y as f32 + 0.5);
+            if x < left || x > right || y < top || y > bottom {
+                for channel in 0..3 {
+                    pixel[channel] = (pixel[channel] as f32 * CROP_DIM) as u8;
+                }
+            }
+        }
+    }
+}
+
+/// Let the user mark up `img`, returning the markup to save, or `None` when
+/// they quit without saving
+pub fn run(img: &DynamicImage) -> Result<Option<Markup>> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        return Err(Error::Unsupported("annotate needs an interactive terminal".to_string()));
+    }
+
+    let _screen = ScreenGuard::enter()?;
+    let mut editor = Editor::new(img.width(), img.height());
+    let mut view = View::fit(img, pixel_view::terminal_size()?);
+    editor.set_step(view.viewport.step());
+    pixel_view::draw(&view.frame(&editor), &editor.status())?;
+
+    loop {
+        match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => match editor.apply(key) {
+                EditorAction::Redraw => pixel_view::draw(&view.frame(&editor), &editor.status())?,
+                EditorAction::Save if editor.markup().is_empty() => return Ok(None),
+                EditorAction::Save => return Ok(Some(editor.into_markup())),
+                EditorAction::Quit => return Ok(None),
+                EditorAction::Ignored => {}
+            },
+            Event::Resize(cols, rows) => {
+                view = View::fit(img, (cols, rows));
+                editor.set_step(view.viewport.step());
+                pixel_view::draw(&view.frame(&editor), &editor.status())?;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn keys(editor: &mut Editor, codes: &[KeyCode]) {
+        for code in codes {
+            editor.apply(key(*code));
+        }
+    }
+
+    #[test]
+    fn test_drawing_shapes() {
+        let mut editor = Editor::new(100, 50);
+        editor.set_step(5);
+        assert_eq!(editor.cursor(), Point { x: 50, y: 25 });
+
+        keys(&mut editor, &[KeyCode::Char('b'), KeyCode::Char('l'), KeyCode::Char('j'), KeyCode::Enter]);
+        assert_eq!(
+            editor.markup().items[0].shape,
+            Shape::Box(Rect { x: 50, y: 25, width: 6, height: 6 })
+        );
+
+        // Shifted movement is faster, and the cursor stays in the image
+        editor.apply(KeyEvent::new(KeyCode::Right, KeyModifiers::SHIFT));
+        assert_eq!(editor.cursor(), Point { x: 99, y: 30 });
+
+        keys(&mut editor, &[KeyCode::Char('n'), KeyCode::Char('a'), KeyCode::Char('H'), KeyCode::Char(' ')]);
+        assert_eq!(
+            editor.markup().items[1],
+            Annotation { shape: Shape::Arrow { from: Point { x: 99, y: 30 }, to: Point { x: 49, y: 30 } }, color: COLORS[1].1 }
+        );
+
+        // A cancelled crop and a zero-length arrow add nothing
+        keys(&mut editor, &[KeyCode::Char('c'), KeyCode::Char('k'), KeyCode::Esc, KeyCode::Char('a'), KeyCode::Enter]);
+        assert_eq!(editor.markup().items.len(), 2);
+
+        keys(&mut editor, &[KeyCode::Char('u')]);
+        assert_eq!(editor.markup().items.len(), 1);
+        assert_eq!(editor.apply(key(KeyCode::Char('s'))), EditorAction::Save);
+    }
+
+    #[test]
+    fn test_typing_text() {
+        let mut editor = Editor::new(100, 100);
+        keys(&mut editor, &[KeyCode::Char('t'), KeyCode::Char('h'), KeyCode::Char('i'), KeyCode::Char('x'), KeyCode::Backspace]);
+        // Letters are text while typing, and arrows still move it
+        editor.apply(key(KeyCode::Left));
+        assert_eq!(editor.pending().unwrap().shape, Shape::Text { at: Point { x: 49, y: 50 }, text: "hi".to_string() });
+        assert_eq!(editor.apply(key(KeyCode::Char('q'))), EditorAction::Redraw);
+        editor.apply(key(KeyCode::Enter));
+
+        assert_eq!(editor.markup().items[0].shape, Shape::Text { at: Point { x: 49, y: 50 }, text: "hiq".to_string() });
+        assert!(editor.pending().is_none());
+        assert_eq!(editor.apply(key(KeyCode::Char('q'))), EditorAction::Quit);
+    }
+
+    #[test]
+    fn test_view_fits_terminal() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::new(1000, 1000));
+        let view = View::fit(&img, (200, 52));
+        // 50 rows for the image, two pixels each
+        assert_eq!(view.viewport.canvas().dimensions(), (100, 100));
+        assert_eq!(view.viewport.step(), 10);
+
+        let frame = view.frame(&Editor::new(1000, 1000));
+        assert_eq!(frame.lines().count(), 50);
+    }
+}
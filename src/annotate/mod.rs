@@ -0,0 +1,295 @@
+//! Markup drawn onto stored images by `klipdot annotate`: boxes, arrows, text
+//! labels and a crop, rendered with the image crate.
+//!
+//! Coordinates are in pixels of the original image. Stroke widths and text
+//! size follow the image's size, so markup looks the same on a small window
+//! capture as on a 4K screenshot; the editor draws the same markup scaled
+//! down onto its preview.
+
+mod font;
+#[cfg(feature = "preview")]
+mod editor;
+
+#[cfg(feature = "preview")]
+pub use editor::{run, Editor, EditorAction, Tool};
+
+use image::{imageops, DynamicImage, Rgba, RgbaImage};
+
+/// Colors markup can be drawn in, cycled through in the editor
+pub const COLORS: &[(&str, Rgba<u8>)] = &[
+    ("red", Rgba([230, 40, 40, 255])),
+    ("yellow", Rgba([250, 200, 20, 255])),
+    ("green", Rgba([40, 190, 70, 255])),
+    ("blue", Rgba([40, 120, 240, 255])),
+    ("white", Rgba([255, 255, 255, 255])),
+    ("black", Rgba([0, 0, 0, 255])),
+];
+
+/// A pixel position in the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Point {
+    pub x: u32,
+    pub y: u32,
+}
+
+/// A rectangle of pixels in the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// The rectangle with corners `a` and `b`, both included
+    pub fn spanning(a: Point, b: Point) -> Self {
+        Self {
+            x: a.x.min(b.x),
+            y: a.y.min(b.y),
+            width: a.x.abs_diff(b.x) + 1,
+            height: a.y.abs_diff(b.y) + 1,
+        }
+    }
+}
+
+/// What a piece of markup draws
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Shape {
+    /// Outline of a rectangle
+    Box(Rect),
+    /// Line with an arrowhead at `to`
+    Arrow { from: Point, to: Point },
+    /// A label with its top left corner at `at`
+    Text { at: Point, text: String },
+    /// Part of the image to keep; the last crop wins
+    Crop(Rect),
+}
+
+/// A shape and the color it's drawn in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    pub shape: Shape,
+    pub color: Rgba<u8>,
+}
+
+/// Stroke width and text size for an image, in its pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Style {
+    pub stroke: f32,
+    /// Size of one font pixel
+    pub text_scale: f32,
+}
+
+impl Style {
+    pub fn for_size(width: u32, height: u32) -> Self {
+        let short = width.min(height) as f32;
+        Self {
+            stroke: (short / 250.0).max(2.0),
+            text_scale: (short / 200.0).max(2.0),
+        }
+    }
+}
+
+/// Annotations in the order they were made
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Markup {
+    pub items: Vec<Annotation>,
+}
+
+impl Markup {
+    pub fn push(&mut self, annotation: Annotation) {
+        self.items.push(annotation);
+    }
+
+    /// Remove the last annotation
+    pub fn undo(&mut self) -> Option<Annotation> {
+        self.items.pop()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The crop in effect, if any
+    pub fn crop(&self) -> Option<Rect> {
+        self.items.iter().rev().find_map(|item| match item.shape {
+            Shape::Crop(rect) => Some(rect),
+            _ => None,
+        })
+    }
+
+    /// Draw everything but the crop onto `canvas`, which shows the image
+    /// `scale` times its size
+    pub fn draw(&self, canvas: &mut RgbaImage, style: Style, scale: f32) {
+        for item in &self.items {
+            draw_annotation(canvas, item, style, scale);
+        }
+    }
+
+    /// `img` with the markup drawn on and the crop applied
+    pub fn apply(&self, img: &DynamicImage) -> RgbaImage {
+        let mut canvas = img.to_rgba8();
+        self.draw(&mut canvas, Style::for_size(img.width(), img.height()), 1.0);
+        match self.crop() {
+            Some(rect) => {
+                let x = rect.x.min(canvas.width().saturating_sub(1));
+                let y = rect.y.min(canvas.height().saturating_sub(1));
+                let width = rect.width.min(canvas.width() - x);
+                let height = rect.height.min(canvas.height() - y);
+                imageops::crop_imm(&canvas, x, y, width, height).to_image()
+            }
+            None => canvas,
+        }
+    }
+}
+
+/// Draw one annotation onto `canvas` at `scale`; crops aren't drawn
+pub fn draw_annotation(canvas: &mut RgbaImage, item: &Annotation, style: Style, scale: f32) {
+    let stroke = (style.stroke * scale).round().max(1.0);
+    let at = |point: Point| (point.x as f32 * scale, point.y as f32 * scale);
+    match &item.shape {
+        Shape::Box(rect) => {
+            let (left, top) = at(Point { x: rect.x, y: rect.y });
+            let (right, bottom) = at(Point { x: rect.x + rect.width - 1, y: rect.y + rect.height - 1 });
+            for (from, to) in [
+                ((left, top), (right, top)),
+                ((right, top), (right, bottom)),
+                ((right, bottom), (left, bottom)),
+                ((left, bottom), (left, top)),
+            ] {
+                draw_line(canvas, from, to, stroke, item.color);
+            }
+        }
+        Shape::Arrow { from, to } => {
+            let (from, to) = (at(*from), at(*to));
+            draw_line(canvas, from, to, stroke, item.color);
+
+            let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+            let length = dx.hypot(dy);
+            if length > 0.0 {
+                let head = (stroke * 5.0).max(4.0).min(length * 0.6);
+                let angle = dy.atan2(dx);
+                for side in [-0.5f32, 0.5] {
+                    let back = angle + std::f32::consts::PI + side;
+                    let end = (to.0 + head * back.cos(), to.1 + head * back.sin());
+                    draw_line(canvas, to, end, stroke, item.color);
+                }
+            }
+        }
+        Shape::Text { at: point, text } => {
+            let (x, y) = at(*point);
+            draw_text(canvas, (x, y), text, (style.text_scale * scale).round().max(1.0), item.color);
+        }
+        Shape::Crop(_) => {}
+    }
+}
+
+/// Fill the square of side `size` centered on `(x, y)`
+fn stamp(canvas: &mut RgbaImage, (x, y): (f32, f32), size: f32, color: Rgba<u8>) {
+    let half = size / 2.0;
+    fill_rect(canvas, (x - half).round() as i64, (y - half).round() as i64, size as i64, size as i64, color);
+}
+
+/// Fill a rectangle, clipped to the canvas
+fn fill_rect(canvas: &mut RgbaImage, x: i64, y: i64, width: i64, height: i64, color: Rgba<u8>) {
+    let (canvas_width, canvas_height) = (canvas.width() as i64, canvas.height() as i64);
+    for py in y.max(0)..(y + height).min(canvas_height) {
+        for px in x.max(0)..(x + width).min(canvas_width) {
+            canvas.put_pixel(px as u32, py as u32, color);
+        }
+    }
+}
+
+fn draw_line(canvas: &mut RgbaImage, from: (f32, f32), to: (f32, f32), stroke: f32, color: Rgba<u8>) {
+    let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).ceil().max(1.0) as u32;
+    for step in 0..=steps {
+        let t = step as f32 / steps as f32;
+        stamp(canvas, (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t), stroke, color);
+    }
+}
+
+/// Draw `text` on a label in a contrasting color, one font pixel being
+/// `scale` canvas pixels
+fn draw_text(canvas: &mut RgbaImage, (x, y): (f32, f32), text: &str, scale: f32, color: Rgba<u8>) {
+    let scale = scale as i64;
+    let (x, y) = (x.round() as i64, y.round() as i64);
+    let count = text.chars().count() as i64;
+    if count == 0 {
+        return;
+    }
+
+    let luminance = 0.299 * color[0] as f32 + 0.587 * color[1] as f32 + 0.114 * color[2] as f32;
+    let label = if luminance > 128.0 { Rgba([0, 0, 0, 255]) } else { Rgba([255, 255, 255, 255]) };
+    let width = (count * font::ADVANCE as i64 + 1) * scale;
+    let height = (font::GLYPH_HEIGHT as i64 + 2) * scale;
+    fill_rect(canvas, x, y, width, height, label);
+
+    for (index, c) in text.chars().enumerate() {
+        let left = x + (index as i64 * font::ADVANCE as i64 + 1) * scale;
+        for (row, bits) in font::glyph(c).iter().enumerate() {
+            for col in 0..font::GLYPH_WIDTH {
+                if bits & (1 << (font::GLYPH_WIDTH - 1 - col)) != 0 {
+                    let top = y + (row as i64 + 1) * scale;
+                    fill_rect(canvas, left + col as i64 * scale, top, scale, scale, color);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([230, 40, 40, 255]);
+    const GRAY: Rgba<u8> = Rgba([128, 128, 128, 255]);
+
+    fn gray(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, GRAY))
+    }
+
+    #[test]
+    fn test_box_and_crop() {
+        let mut markup = Markup::default();
+        markup.push(Annotation {
+            shape: Shape::Box(Rect::spanning(Point { x: 30, y: 30 }, Point { x: 10, y: 10 })),
+            color: RED,
+        });
+        let drawn = markup.apply(&gray(100, 100));
+        assert_eq!(*drawn.get_pixel(10, 20), RED);
+        assert_eq!(*drawn.get_pixel(30, 10), RED);
+        assert_eq!(*drawn.get_pixel(20, 20), GRAY);
+
+        markup.push(Annotation { shape: Shape::Crop(Rect { x: 5, y: 5, width: 40, height: 200 }), color: RED });
+        let cropped = markup.apply(&gray(100, 100));
+        assert_eq!(cropped.dimensions(), (40, 95));
+        assert_eq!(*cropped.get_pixel(5, 15), RED);
+
+        markup.undo();
+        assert_eq!(markup.crop(), None);
+        assert_eq!(markup.apply(&gray(100, 100)).dimensions(), (100, 100));
+    }
+
+    #[test]
+    fn test_arrow_and_text() {
+        let mut markup = Markup::default();
+        markup.push(Annotation { shape: Shape::Arrow { from: Point { x: 10, y: 50 }, to: Point { x: 90, y: 50 } }, color: RED });
+        let drawn = markup.apply(&gray(100, 100));
+        assert_eq!(*drawn.get_pixel(50, 50), RED);
+        // The head spreads out behind the tip
+        assert_eq!(*drawn.get_pixel(85, 47), RED);
+        assert_eq!(*drawn.get_pixel(85, 53), RED);
+        assert_eq!(*drawn.get_pixel(50, 40), GRAY);
+
+        let mut markup = Markup::default();
+        markup.push(Annotation { shape: Shape::Text { at: Point { x: 0, y: 0 }, text: "Hi".to_string() }, color: RED });
+        let drawn = markup.apply(&gray(100, 100));
+        // Font pixels are two pixels wide here, on a white label one font pixel
+        // bigger than the text
+        assert_eq!(*drawn.get_pixel(0, 0), Rgba([255, 255, 255, 255]));
+        assert_eq!(*drawn.get_pixel(2, 2), RED);
+        assert_eq!(*drawn.get_pixel(4, 2), Rgba([255, 255, 255, 255]));
+        assert_eq!(*drawn.get_pixel(40, 40), GRAY);
+    }
+}
@@ -0,0 +1,150 @@
+//! Images as text files for terminals (`klipdot export-ansi`), to `cat` on
+//! another machine over a plain SSH session.
+//!
+//! The default is truecolor half blocks: each character cell shows two pixels
+//! stacked, `▀` in the top one's color on the bottom one's. Any terminal with
+//! 24-bit color shows them and nothing else is needed on either machine.
+//! Sixel keeps full resolution but needs `img2sixel` here and a terminal with
+//! sixel support there.
+
+use crate::{
+    command_runner::CommandRunner,
+    config::{BitDepth, DecodeLimits},
+    decode_guard,
+    error::Result,
+    tone_map, Error,
+};
+use image::{imageops::FilterType, DynamicImage, Rgba, RgbaImage};
+use std::fmt::Write;
+use std::path::Path;
+
+/// Width of exports when none is given, in columns
+pub const DEFAULT_COLUMNS: u32 = 80;
+
+/// Pixel width assumed for a terminal cell when sizing sixel output
+const CELL_WIDTH_PX: u32 = 10;
+
+/// Alpha below which a pixel is left to the terminal's background
+const ALPHA_THRESHOLD: u8 = 128;
+
+const RESET: &str = "\x1b[0m";
+
+/// How an export encodes the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum AnsiFormat {
+    /// Truecolor half blocks, shown by any 24-bit color terminal
+    #[default]
+    Blocks,
+    /// Sixel graphics, made with `img2sixel`
+    Sixel,
+}
+
+impl AnsiFormat {
+    /// Extension for files in this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            AnsiFormat::Blocks => "ans",
+            AnsiFormat::Sixel => "six",
+        }
+    }
+}
+
+/// The image at `path`, decoded within `limits`, as terminal output `cols` columns wide at most
+pub async fn export(runner: &dyn CommandRunner, path: &Path, format: AnsiFormat, cols: u32, limits: &DecodeLimits) -> Result<Vec<u8>> {
+    if cols == 0 {
+        return Err(Error::InvalidInput("Exports need at least one column".to_string()));
+    }
+    match format {
+        AnsiFormat::Blocks => {
+            let data = tokio::fs::read(path).await?;
+            let img = tone_map::prepare(decode_guard::load(&data, limits)?, BitDepth::Reduce);
+            Ok(render_blocks(&img, cols).into_bytes())
+        }
+        AnsiFormat::Sixel => {
+            let width = (cols * CELL_WIDTH_PX).to_string();
+            let path = path.to_string_lossy();
+            let output = runner
+                .run("img2sixel", &["-w", &width, &path], None)
+                .await
+                .map_err(|e| Error::Unsupported(format!("img2sixel is needed for sixel exports: {}", e)))?;
+            if !output.success {
+                return Err(Error::Process(format!("img2sixel failed: {}", output.stderr_lossy().trim())));
+            }
+            Ok(output.stdout)
+        }
+    }
+}
+
+/// `img` as rows of truecolor half blocks, `cols` wide unless the image is
+/// narrower. Every row ends with a reset, so the text can be cut anywhere.
+pub fn render_blocks(img: &DynamicImage, cols: u32) -> String {
+    let cols = cols.min(img.width()).max(1);
+    // Cells are about twice as tall as wide, and each holds two pixels
+    let rows = ((img.height() as f64 * cols as f64 / img.width().max(1) as f64) / 2.0).round().max(1.0) as u32;
+    let pixels: RgbaImage = img.resize_exact(cols, rows * 2, FilterType::Triangle).to_rgba8();
+
+    let mut text = String::new();
+    for row in 0..rows {
+        for col in 0..cols {
+            let top = visible(pixels.get_pixel(col, row * 2));
+            let bottom = visible(pixels.get_pixel(col, row * 2 + 1));
+            match (top, bottom) {
+                (Some(top), Some(bottom)) => {
+                    let _ = write!(text, "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m▀", top[0], top[1], top[2], bottom[0], bottom[1], bottom[2]);
+                }
+                (Some(top), None) => {
+                    let _ = write!(text, "\x1b[49m\x1b[38;2;{};{};{}m▀", top[0], top[1], top[2]);
+                }
+                (None, Some(bottom)) => {
+                    let _ = write!(text, "\x1b[49m\x1b[38;2;{};{};{}m▄", bottom[0], bottom[1], bottom[2]);
+                }
+                (None, None) => {
+                    let _ = write!(text, "{} ", RESET);
+                }
+            }
+        }
+        text.push_str(RESET);
+        text.push('\n');
+    }
+    text
+}
+
+fn visible(pixel: &Rgba<u8>) -> Option<&Rgba<u8>> {
+    (pixel[3] >= ALPHA_THRESHOLD).then_some(pixel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+
+    #[test]
+    fn test_render_blocks() {
+        let mut img = RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 255]));
+        img.put_pixel(0, 1, Rgba([0, 0, 255, 255]));
+        img.put_pixel(1, 0, Rgba([0, 0, 0, 0]));
+        img.put_pixel(1, 1, Rgba([0, 0, 0, 0]));
+
+        let text = render_blocks(&DynamicImage::ImageRgba8(img), 80);
+        assert_eq!(
+            text,
+            "\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m▀\x1b[0m \x1b[0m\n"
+        );
+
+        // Rows keep the aspect ratio: 40 columns of a 4:1 image is 5 rows
+        let wide = DynamicImage::ImageRgba8(RgbaImage::from_pixel(400, 100, Rgba([1, 2, 3, 255])));
+        let text = render_blocks(&wide, 40);
+        assert_eq!(text.lines().count(), 5);
+        assert_eq!(text.lines().next().unwrap().matches('▀').count(), 40);
+    }
+
+    #[tokio::test]
+    async fn test_sixel_export() {
+        let runner = FakeRunner::new().with_output("img2sixel", CommandOutput::ok("\x1bPq#0;2;0;0;0\x1b\\"));
+        let data = export(&runner, Path::new("/shots/a.png"), AnsiFormat::Sixel, 60, &DecodeLimits::default()).await.unwrap();
+        assert!(data.starts_with(b"\x1bPq"));
+        assert_eq!(runner.calls_to("img2sixel")[0].args, ["-w", "600", "/shots/a.png"]);
+
+        assert!(export(&FakeRunner::new(), Path::new("/shots/a.png"), AnsiFormat::Sixel, 60, &DecodeLimits::default()).await.is_err());
+    }
+}
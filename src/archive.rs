@@ -0,0 +1,256 @@
+//! Images inside `.zip` and `.tar` archives, such as CI artifact bundles.
+//!
+//! A member is named `archive.zip::screenshots/fail.png`. Archives are read
+//! with `unzip` and `tar`, and a member is only extracted when it appears in
+//! the archive's own listing, so the name can't smuggle in options or globs.
+
+use crate::{command_runner::CommandRunner, error::Result, temp_files::{self, TempFile}, Error};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Separates an archive path from the member inside it
+pub const MEMBER_SEPARATOR: &str = "::";
+
+/// Extensions of archives that can be looked into
+const ARCHIVE_SUFFIXES: &[(&str, ArchiveKind)] = &[
+    (".zip", ArchiveKind::Zip),
+    (".tar", ArchiveKind::Tar),
+    (".tar.gz", ArchiveKind::Tar),
+    (".tgz", ArchiveKind::Tar),
+    (".tar.bz2", ArchiveKind::Tar),
+    (".tbz2", ArchiveKind::Tar),
+    (".tar.xz", ArchiveKind::Tar),
+    (".txz", ArchiveKind::Tar),
+    (".tar.zst", ArchiveKind::Tar),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+}
+
+impl ArchiveKind {
+    /// The kind of archive `path` is, going by its name
+    pub fn of(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        ARCHIVE_SUFFIXES
+            .iter()
+            .find(|(suffix, _)| name.ends_with(suffix))
+            .map(|(_, kind)| *kind)
+    }
+
+    fn program(self) -> &'static str {
+        match self {
+            ArchiveKind::Zip => "unzip",
+            ArchiveKind::Tar => "tar",
+        }
+    }
+}
+
+/// Split `archive.zip::member.png` into the archive path and member name
+pub fn split_member(spec: &str) -> Option<(PathBuf, String)> {
+    let (archive, member) = spec.split_once(MEMBER_SEPARATOR)?;
+    let archive = PathBuf::from(archive);
+    (ArchiveKind::of(&archive).is_some() && !member.is_empty()).then(|| (archive, member.to_string()))
+}
+
+/// Image files in `archive`, in archive order
+pub async fn list_images(runner: &dyn CommandRunner, archive: &Path) -> Result<Vec<String>> {
+    let kind = kind_of(archive)?;
+    let archive_arg = archive.to_string_lossy();
+    let list_flag = match kind {
+        ArchiveKind::Zip => "-Z1",
+        ArchiveKind::Tar => "-tf",
+    };
+    let listing = run(runner, kind.program(), &[list_flag, &archive_arg]).await?;
+
+    Ok(String::from_utf8_lossy(&listing)
+        .lines()
+        .filter(|name| !name.ends_with('/') && crate::has_image_extension(Path::new(name)))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Extract the image `member` of `archive` into a temporary file
+pub async fn extract(runner: &dyn CommandRunner, archive: &Path, member: &str) -> Result<TempFile> {
+    let data = read_member(runner, archive, member).await?;
+    let extension = Path::new(member).extension().map(|ext| ext.to_string_lossy().to_string()).unwrap_or_default();
+    let extracted = temp_files::write("archive-", &extension, &data)?;
+    debug!("Extracted {} from {:?} to {:?}", member, archive, extracted.path());
+    Ok(extracted)
+}
+
+/// The content of the image `member` of `archive`. Members listed as larger
+/// than [`crate::MAX_FILE_SIZE`] aren't read, and reading stops there for
+/// archives that understate them, so a small archive can't expand into
+/// gigabytes in memory.
+pub async fn read_member(runner: &dyn CommandRunner, archive: &Path, member: &str) -> Result<Vec<u8>> {
+    let kind = kind_of(archive)?;
+    if !list_images(runner, archive).await?.iter().any(|name| name == member) {
+        return Err(Error::NotFound(format!("No image {} in {:?}", member, archive)));
+    }
+    let too_large = || {
+        Error::InvalidInput(format!(
+            "{} in {:?} is larger than {}",
+            member,
+            archive,
+            crate::format_file_size(crate::MAX_FILE_SIZE)
+        ))
+    };
+    if listed_size(runner, kind, archive, member).await?.is_some_and(|size| size > crate::MAX_FILE_SIZE) {
+        return Err(too_large());
+    }
+
+    let archive_arg = archive.to_string_lossy();
+    let args: &[&str] = match kind {
+        // unzip treats member names as wildcards
+        ArchiveKind::Zip => &["-p", &archive_arg, &escape_zip_pattern(member)],
+        ArchiveKind::Tar => &["-xOf", &archive_arg, "--", member],
+    };
+    let data = run_limited(runner, kind.program(), args).await?.ok_or_else(too_large)?;
+    if data.is_empty() {
+        return Err(Error::Format(format!("{} in {:?} is empty", member, archive)));
+    }
+    Ok(data)
+}
+
+/// The uncompressed size `archive` lists for `member`, when it can be read
+async fn listed_size(runner: &dyn CommandRunner, kind: ArchiveKind, archive: &Path, member: &str) -> Result<Option<u64>> {
+    let archive_arg = archive.to_string_lossy();
+    let args: &[&str] = match kind {
+        ArchiveKind::Zip => &["-Z", "-s", &archive_arg],
+        ArchiveKind::Tar => &["-tvf", &archive_arg],
+    };
+    let listing = run(runner, kind.program(), args).await?;
+    Ok(String::from_utf8_lossy(&listing).lines().find_map(|line| parse_listed_size(kind, line, member)))
+}
+
+/// The size in one line of `unzip -Z -s` or `tar -tv` output, if it lists `member`
+fn parse_listed_size(kind: ArchiveKind, line: &str, member: &str) -> Option<u64> {
+    let details = line.strip_suffix(member)?;
+    if !details.ends_with(char::is_whitespace) {
+        return None;
+    }
+    let fields: Vec<&str> = details.split_whitespace().collect();
+    let size = match kind {
+        // -rw-r--r--  3.0 unx  1234 bx defN 23-Jan-01 12:00
+        ArchiveKind::Zip if fields.len() == 8 => fields[3],
+        // GNU: -rw-r--r-- user/group 1234 2023-01-01 12:00
+        ArchiveKind::Tar if fields.get(1).is_some_and(|owner| owner.contains('/')) => fields.get(2)?,
+        // bsdtar: -rw-r--r--  0 user group 1234 Jan  1 12:00
+        ArchiveKind::Tar => fields.get(4)?,
+        ArchiveKind::Zip => return None,
+    };
+    size.parse().ok()
+}
+
+fn kind_of(archive: &Path) -> Result<ArchiveKind> {
+    ArchiveKind::of(archive).ok_or_else(|| Error::InvalidInput(format!("Not a .zip or .tar archive: {:?}", archive)))
+}
+
+fn escape_zip_pattern(member: &str) -> String {
+    member
+        .chars()
+        .map(|c| match c {
+            '*' | '?' | '[' => format!("[{}]", c),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+async fn run(runner: &dyn CommandRunner, program: &str, args: &[&str]) -> Result<Vec<u8>> {
+    run_limited(runner, program, args).await?.ok_or_else(|| Error::InvalidInput(format!("{} printed too much", program)))
+}
+
+/// Run `program`, or `None` once it printed more than [`crate::MAX_FILE_SIZE`]
+async fn run_limited(runner: &dyn CommandRunner, program: &str, args: &[&str]) -> Result<Option<Vec<u8>>> {
+    if !runner.is_available(program) {
+        return Err(Error::Unsupported(format!("{} is needed to read this archive", program)));
+    }
+
+    let Some(output) = runner
+        .run_limited(program, args, crate::MAX_FILE_SIZE as usize)
+        .await
+        .map_err(|e| Error::Process(format!("Failed to run {}: {}", program, e)))?
+    else {
+        return Ok(None);
+    };
+    if !output.success {
+        return Err(Error::Process(format!("{} failed: {}", program, output.stderr_lossy().trim())));
+    }
+    Ok(Some(output.stdout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+
+    #[test]
+    fn test_split_member() {
+        assert_eq!(
+            split_member("artifacts.zip::screenshots/fail.png"),
+            Some((PathBuf::from("artifacts.zip"), "screenshots/fail.png".to_string()))
+        );
+        assert_eq!(split_member("logs.TAR.GZ::a.png").map(|(_, member)| member).as_deref(), Some("a.png"));
+        assert_eq!(split_member("notes.txt::a.png"), None);
+        assert_eq!(split_member("artifacts.zip::"), None);
+        assert_eq!(split_member("shot.png"), None);
+    }
+
+    #[tokio::test]
+    async fn test_list_and_extract_zip() {
+        let archive = Path::new("artifacts.zip");
+        let runner = FakeRunner::new()
+            .with_output_for("unzip", &["-Z1", "artifacts.zip"], CommandOutput::ok("screenshots/\nscreenshots/fail[1].png\nreport.xml\n"))
+            .with_output_for(
+                "unzip",
+                &["-Z", "-s", "artifacts.zip"],
+                CommandOutput::ok("Archive:  artifacts.zip\n-rw-r--r--  3.0 unx     2048 bx defN 24-Mar-02 10:15 screenshots/fail[1].png\n"),
+            )
+            .with_output("unzip", CommandOutput::ok("\u{89}PNG"));
+
+        assert_eq!(list_images(&runner, archive).await.unwrap(), ["screenshots/fail[1].png"]);
+        assert!(extract(&runner, archive, "report.xml").await.is_err());
+
+        let extracted = extract(&runner, archive, "screenshots/fail[1].png").await.unwrap();
+        assert_eq!(extracted.extension().unwrap(), "png");
+        assert_eq!(std::fs::read(&extracted).unwrap(), "\u{89}PNG".as_bytes());
+        let path = extracted.to_path_buf();
+        drop(extracted);
+        assert!(!path.exists());
+        let calls = runner.calls_to("unzip");
+        assert_eq!(calls.last().unwrap().args, ["-p", "artifacts.zip", "screenshots/fail[[]1].png"]);
+    }
+
+    #[test]
+    fn test_listed_sizes() {
+        let zip = "-rw-r--r--  3.0 unx 60000000 bx defN 24-Mar-02 10:15 shots/big one.png";
+        assert_eq!(parse_listed_size(ArchiveKind::Zip, zip, "shots/big one.png"), Some(60_000_000));
+        assert_eq!(parse_listed_size(ArchiveKind::Zip, zip, "one.png"), None);
+        let gnu = "-rw-r--r-- ci/ci        4096 2024-03-02 10:15 a.png";
+        assert_eq!(parse_listed_size(ArchiveKind::Tar, gnu, "a.png"), Some(4096));
+        let bsd = "-rw-r--r--  0 ci     ci       4096 Mar  2 10:15 a.png";
+        assert_eq!(parse_listed_size(ArchiveKind::Tar, bsd, "a.png"), Some(4096));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_members_are_refused() {
+        let archive = Path::new("bomb.tar");
+        let runner = FakeRunner::new()
+            .with_output_for("tar", &["-tf", "bomb.tar"], CommandOutput::ok("a.png\nb.png\n"))
+            .with_output_for(
+                "tar",
+                &["-tvf", "bomb.tar"],
+                CommandOutput::ok("-rw-r--r-- ci/ci 999999999 2024-03-02 10:15 a.png\n-rw-r--r-- ci/ci 10 2024-03-02 10:15 b.png\n"),
+            )
+            .with_output("tar", CommandOutput::ok(vec![0; crate::MAX_FILE_SIZE as usize + 1]));
+
+        // Listed as too large: never extracted
+        assert!(matches!(read_member(&runner, archive, "a.png").await, Err(Error::InvalidInput(_))));
+        assert!(runner.calls_to("tar").iter().all(|call| call.args[0] != "-xOf"));
+        // Listed as small but isn't
+        assert!(matches!(read_member(&runner, archive, "b.png").await, Err(Error::InvalidInput(_))));
+    }
+}
@@ -0,0 +1,282 @@
+//! Uploading stored images as GitHub or GitLab attachments, for
+//! `klipdot gh-attach`.
+//!
+//! GitLab has an upload API for attachments; it's called with `curl` and a
+//! token from `GITLAB_TOKEN` or `glab`. GitHub has no public attachment API,
+//! so images are committed through `gh api` to a branch of their own
+//! (`klipdot-attachments` by default, created from the default branch) and
+//! linked from there, where issues and pull requests of the repository can
+//! show them. `gh` picks up `GH_TOKEN` or `GITHUB_TOKEN` when set.
+
+use crate::{command_runner::CommandRunner, error::Result, retry::RetryPolicy, Error};
+use base64::Engine;
+use std::path::Path;
+use tracing::{debug, info};
+
+/// Branch images are committed to on GitHub unless another is given
+pub const DEFAULT_BRANCH: &str = "klipdot-attachments";
+
+/// Where a repository is hosted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Forge {
+    Github,
+    Gitlab,
+}
+
+/// A repository on a forge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repo {
+    pub forge: Forge,
+    pub host: String,
+    /// "owner/name", or "group/subgroup/name" on GitLab
+    pub path: String,
+}
+
+impl Repo {
+    /// The repository `spec` names: a remote URL, `host/owner/name` or
+    /// `owner/name` on github.com, hosted on `forge` when given
+    pub fn parse(spec: &str, forge: Option<Forge>) -> Option<Self> {
+        let spec = spec.trim().trim_end_matches('/');
+        let spec = spec.strip_suffix(".git").unwrap_or(spec);
+
+        let (host, path) = if let Some((_, rest)) = spec.split_once("://") {
+            // https://host/owner/name, ssh://git@host:22/owner/name
+            let (authority, path) = rest.split_once('/')?;
+            let host = authority.rsplit('@').next()?.split(':').next()?;
+            (host.to_string(), path.to_string())
+        } else if let Some((user_host, path)) = spec.split_once(':') {
+            // git@host:owner/name
+            (user_host.rsplit('@').next()?.to_string(), path.to_string())
+        } else if spec.split('/').count() > 2 && spec.split('/').next()?.contains('.') {
+            let (host, path) = spec.split_once('/')?;
+            (host.to_string(), path.to_string())
+        } else {
+            ("github.com".to_string(), spec.to_string())
+        };
+
+        if host.is_empty() || path.split('/').filter(|part| !part.is_empty()).count() < 2 {
+            return None;
+        }
+        let forge = forge.unwrap_or(if host.contains("gitlab") { Forge::Gitlab } else { Forge::Github });
+        Some(Self { forge, host, path })
+    }
+
+    /// The repository of the `origin` remote of the current directory
+    pub async fn detect(runner: &dyn CommandRunner, forge: Option<Forge>) -> Result<Self> {
+        let output = runner
+            .run("git", &["remote", "get-url", "origin"], None)
+            .await
+            .map_err(|e| Error::Process(format!("Failed to run git: {}", e)))?;
+        if !output.success {
+            return Err(Error::NotFound("No origin remote here; pass --repo".to_string()));
+        }
+        let url = output.stdout_lossy();
+        Self::parse(&url, forge).ok_or_else(|| Error::Parse(format!("Can't tell the repository from remote {:?}", url.trim())))
+    }
+}
+
+/// An uploaded image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    /// Absolute URL of the image
+    pub url: String,
+    /// What markdown should link to; relative to the project on GitLab
+    pub link: String,
+}
+
+/// Upload the image at `path` to `repo`, retrying failed requests with
+/// `retry`. `branch` is used on GitHub, `token` on GitLab.
+pub async fn upload(runner: &dyn CommandRunner, retry: &RetryPolicy, repo: &Repo, path: &Path, branch: &str, token: Option<&str>) -> Result<Attachment> {
+    match repo.forge {
+        // Safe to repeat: an image that made it on the first try is found and reused
+        Forge::Github => retry.run("attach on github", || upload_github(runner, repo, path, branch)).await,
+        Forge::Gitlab => {
+            let token = token.ok_or_else(|| Error::Permission(format!("No GitLab token for {}; set GITLAB_TOKEN or log in with glab", repo.host)))?;
+            retry.run("attach on gitlab", || upload_gitlab(runner, repo, path, token)).await
+        }
+    }
+}
+
+/// Token for GitLab `host`, from `GITLAB_TOKEN` or glab's configuration
+pub async fn gitlab_token(runner: &dyn CommandRunner, host: &str) -> Option<String> {
+    if let Some(token) = std::env::var("GITLAB_TOKEN").ok().filter(|token| !token.is_empty()) {
+        return Some(token);
+    }
+    let output = runner.run("glab", &["config", "get", "token", "--host", host], None).await.ok()?;
+    let token = output.stdout_lossy().trim().to_string();
+    (output.success && !token.is_empty()).then_some(token)
+}
+
+async fn upload_github(runner: &dyn CommandRunner, repo: &Repo, path: &Path, branch: &str) -> Result<Attachment> {
+    if !runner.is_available("gh") {
+        return Err(Error::Unsupported("gh is needed to attach images on GitHub".to_string()));
+    }
+    let data = tokio::fs::read(path).await?;
+    // The content hash keeps names unique, and uploads of the same image reuse
+    // the first; the slug keeps them readable without needing URL encoding
+    let hash = crate::dedup::content_hash(&data);
+    let slug = crate::rename::slugify(&path.file_stem().unwrap_or_default().to_string_lossy());
+    let ext = crate::rename::slugify(&path.extension().unwrap_or_default().to_string_lossy());
+    let target = if slug.is_empty() {
+        format!("{}.{}", &hash[..12], ext)
+    } else {
+        format!("{}-{}.{}", &hash[..12], slug, ext)
+    };
+    let url = format!("https://{}/{}/blob/{}/{}?raw=true", repo.host, repo.path, branch, target);
+    let attachment = Attachment { link: url.clone(), url };
+
+    let contents = format!("repos/{}/contents/{}", repo.path, target);
+    if gh(runner, repo, &[&format!("{}?ref={}", contents, branch)], None).await.is_ok() {
+        debug!("{} is already on {}", target, branch);
+        return Ok(attachment);
+    }
+
+    if gh(runner, repo, &[&format!("repos/{}/branches/{}", repo.path, branch)], None).await.is_err() {
+        let default_branch = gh(runner, repo, &[&format!("repos/{}", repo.path), "--jq", ".default_branch"], None).await?;
+        let head = format!("repos/{}/git/ref/heads/{}", repo.path, default_branch.trim());
+        let sha = gh(runner, repo, &[&head, "--jq", ".object.sha"], None).await?;
+        let refs = format!("repos/{}/git/refs", repo.path);
+        let (branch_ref, sha) = (format!("ref=refs/heads/{}", branch), format!("sha={}", sha.trim()));
+        gh(runner, repo, &["--method", "POST", &refs, "-f", &branch_ref, "-f", &sha], None).await?;
+        info!("Created branch {} in {}", branch, repo.path);
+    }
+
+    // Sent on stdin, since images are larger than a command-line argument may be
+    let body = serde_json::json!({
+        "message": format!("Add {}", target),
+        "branch": branch,
+        "content": base64::engine::general_purpose::STANDARD.encode(&data),
+    });
+    gh(runner, repo, &["--method", "PUT", &contents, "--input", "-"], Some(&serde_json::to_vec(&body)?)).await?;
+    Ok(attachment)
+}
+
+/// Run `gh api` against the host of `repo`, returning its output
+async fn gh(runner: &dyn CommandRunner, repo: &Repo, args: &[&str], stdin: Option<&[u8]>) -> Result<String> {
+    let mut full = vec!["api", "--hostname", repo.host.as_str()];
+    full.extend_from_slice(args);
+    let output = runner
+        .run("gh", &full, stdin)
+        .await
+        .map_err(|e| Error::Process(format!("Failed to run gh: {}", e)))?;
+    if !output.success {
+        return Err(Error::Network(format!("gh api {} failed: {}", args.join(" "), output.stderr_lossy().trim())));
+    }
+    Ok(output.stdout_lossy())
+}
+
+async fn upload_gitlab(runner: &dyn CommandRunner, repo: &Repo, path: &Path, token: &str) -> Result<Attachment> {
+    if !runner.is_available("curl") {
+        return Err(Error::Unsupported("curl is needed to attach images on GitLab".to_string()));
+    }
+    let project = repo.path.replace('/', "%2F");
+    let api = format!("https://{}/api/v4/projects/{}/uploads", repo.host, project);
+    // curl reads a double-quoted file name literally, with \ escaping
+    let file = format!("file=@\"{}\"", path.to_string_lossy().replace('\\', "\\\\").replace('"', "\\\""));
+    // The token goes in on stdin so it doesn't show up in the process list
+    let header = format!("PRIVATE-TOKEN: {}\n", token);
+    let output = runner
+        .run("curl", &["-sS", "--fail-with-body", "-H", "@-", "-F", &file, &api], Some(header.as_bytes()))
+        .await
+        .map_err(|e| Error::Process(format!("Failed to run curl: {}", e)))?;
+    if !output.success {
+        return Err(Error::Network(format!("Upload to {} failed: {}", repo.host, output.stdout_lossy().trim())));
+    }
+
+    let response: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    let link = response["url"]
+        .as_str()
+        .ok_or_else(|| Error::Parse(format!("Unexpected response from {}: {}", repo.host, output.stdout_lossy())))?
+        .to_string();
+    let url = match response["full_path"].as_str() {
+        Some(full_path) => format!("https://{}{}", repo.host, full_path),
+        None => format!("https://{}/{}{}", repo.host, repo.path, link),
+    };
+    Ok(Attachment { url, link })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+    use tempfile::TempDir;
+
+    fn repo(forge: Forge, host: &str, path: &str) -> Repo {
+        Repo {
+            forge,
+            host: host.to_string(),
+            path: path.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_repo() {
+        let github = repo(Forge::Github, "github.com", "KooshaPari/KlipDot");
+        assert_eq!(Repo::parse("git@github.com:KooshaPari/KlipDot.git\n", None).unwrap(), github);
+        assert_eq!(Repo::parse("https://github.com/KooshaPari/KlipDot", None).unwrap(), github);
+        assert_eq!(Repo::parse("KooshaPari/KlipDot", None).unwrap(), github);
+        assert_eq!(
+            Repo::parse("ssh://git@gitlab.example.com:2222/group/sub/app.git", None).unwrap(),
+            repo(Forge::Gitlab, "gitlab.example.com", "group/sub/app")
+        );
+        assert_eq!(
+            Repo::parse("code.example.com/team/app", Some(Forge::Gitlab)).unwrap(),
+            repo(Forge::Gitlab, "code.example.com", "team/app")
+        );
+        assert!(Repo::parse("KlipDot", None).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_github_upload_reuses_existing() {
+        let temp_dir = TempDir::new().unwrap();
+        let image = temp_dir.path().join("my shot.png");
+        std::fs::write(&image, b"png").unwrap();
+        let runner = FakeRunner::new().with_output("gh", CommandOutput::ok("{}"));
+
+        let repo = repo(Forge::Github, "github.com", "me/app");
+        let attachment = upload(&runner, &RetryPolicy::none(), &repo, &image, DEFAULT_BRANCH, None).await.unwrap();
+        let hash = &crate::dedup::content_hash(b"png")[..12];
+        assert_eq!(
+            attachment.url,
+            format!("https://github.com/me/app/blob/klipdot-attachments/{}-my-shot.png?raw=true", hash)
+        );
+        // Already on the branch: nothing is committed
+        let calls = runner.calls_to("gh");
+        assert_eq!(calls.len(), 1);
+        assert!(calls[0].args[3].ends_with("?ref=klipdot-attachments"));
+    }
+
+    #[tokio::test]
+    async fn test_gitlab_upload() {
+        let temp_dir = TempDir::new().unwrap();
+        let image = temp_dir.path().join("shot.png");
+        std::fs::write(&image, b"png").unwrap();
+        let response = r#"{"url": "/uploads/abc/shot.png", "full_path": "/-/project/7/uploads/abc/shot.png", "markdown": "![shot](/uploads/abc/shot.png)"}"#;
+        let runner = FakeRunner::new().with_output("curl", CommandOutput::ok(response));
+
+        let repo = repo(Forge::Gitlab, "gitlab.com", "group/app");
+        assert!(upload(&runner, &RetryPolicy::none(), &repo, &image, DEFAULT_BRANCH, None).await.is_err());
+        let attachment = upload(&runner, &RetryPolicy::none(), &repo, &image, DEFAULT_BRANCH, Some("secret")).await.unwrap();
+        assert_eq!(attachment.link, "/uploads/abc/shot.png");
+        assert_eq!(attachment.url, "https://gitlab.com/-/project/7/uploads/abc/shot.png");
+
+        let call = &runner.calls_to("curl")[0];
+        assert_eq!(call.args.last().unwrap(), "https://gitlab.com/api/v4/projects/group%2Fapp/uploads");
+        assert!(!call.args.iter().any(|arg| arg.contains("secret")));
+        assert_eq!(call.stdin.as_deref(), Some(&b"PRIVATE-TOKEN: secret\n"[..]));
+    }
+
+    #[tokio::test]
+    async fn test_failed_uploads_are_retried() {
+        let temp_dir = TempDir::new().unwrap();
+        let image = temp_dir.path().join("shot.png");
+        std::fs::write(&image, b"png").unwrap();
+        let runner = FakeRunner::new().with_output("curl", CommandOutput::failed("502 Bad Gateway"));
+        let retry = RetryPolicy { max_attempts: 3, initial_delay_ms: 1, ..RetryPolicy::default() };
+
+        let repo = repo(Forge::Gitlab, "gitlab.com", "group/app");
+        assert!(upload(&runner, &retry, &repo, &image, DEFAULT_BRANCH, Some("secret")).await.is_err());
+        assert_eq!(runner.calls_to("curl").len(), 3);
+        assert!(crate::retry::metrics()["attach on gitlab"].exhausted >= 1);
+    }
+}
@@ -0,0 +1,379 @@
+//! Append-only audit log of interceptions, for environments that need a
+//! record of what was captured and what the clipboard was changed to.
+//!
+//! Each line of the log is a JSON [`Entry`]: when an image was stored
+//! (with SHA-256 hashes of what was captured and what was written) or when
+//! the clipboard was rewritten to hold its path. Every entry carries the
+//! hash of the entry before it and a hash over its own contents, so changing,
+//! removing or reordering entries breaks the chain where it happened, which
+//! `klipdot audit verify` reports. Removing entries from the end can only be
+//! noticed against a hash noted earlier, so `verify` prints the latest one.
+
+use crate::{config::AuditConfig, error::Result, error_history, Error};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::io::AsyncReadExt;
+use tracing::{debug, warn};
+
+/// `previous` of the first entry
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Serializes appends from this process; other processes are held off with a file lock
+static APPEND: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    /// An image was stored from the clipboard, a capture, a file or a stream
+    Intercept,
+    /// The clipboard was rewritten to hold the path of a stored image
+    ClipboardRewrite,
+}
+
+/// What happened, before it's added to the chain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    pub event: Event,
+    pub source: Option<String>,
+    pub app: Option<String>,
+    /// File the image was taken from
+    pub input: Option<PathBuf>,
+    /// The stored image
+    pub path: PathBuf,
+    /// SHA-256 of the image as it was captured
+    pub original_sha256: Option<String>,
+    /// SHA-256 of the stored file
+    pub stored_sha256: Option<String>,
+    /// Text the clipboard was set to
+    pub clipboard: Option<String>,
+}
+
+impl Record {
+    pub fn new(event: Event, path: &Path) -> Self {
+        Self {
+            event,
+            source: None,
+            app: None,
+            input: None,
+            path: path.to_path_buf(),
+            original_sha256: None,
+            stored_sha256: None,
+            clipboard: None,
+        }
+    }
+}
+
+/// A line of the log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Position in the log, from 0
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub record: Record,
+    /// `hash` of the entry before, or [`GENESIS`]
+    pub previous: String,
+    /// SHA-256 of the entry serialized with an empty `hash`
+    pub hash: String,
+}
+
+impl Entry {
+    fn compute_hash(&self) -> Result<String> {
+        let unsigned = Entry { hash: String::new(), ..self.clone() };
+        Ok(sha256(serde_json::to_string(&unsigned)?.as_bytes()))
+    }
+}
+
+/// Outcome of checking a log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    /// Entries that check out, from the start
+    pub valid: u64,
+    /// Hash of the last valid entry
+    pub head: Option<String>,
+    /// The first problem found, with its line number
+    pub problem: Option<String>,
+}
+
+/// Formats the log can be exported in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+/// The log file `settings` names
+pub fn log_path(settings: &AuditConfig) -> Result<PathBuf> {
+    match &settings.file {
+        Some(file) => Ok(file.clone()),
+        None => Ok(crate::get_home_dir()?.join(crate::AUDIT_LOG_FILE)),
+    }
+}
+
+/// Append `record` to the log `settings` names when auditing is enabled;
+/// failures are logged rather than returned, so they never stop an interception
+pub async fn record(settings: &AuditConfig, record: Record) {
+    if !settings.enabled {
+        return;
+    }
+    let result = match log_path(settings) {
+        Ok(path) => tokio::task::spawn_blocking(move || append(&path, record))
+            .await
+            .unwrap_or_else(|e| Err(Error::Internal(format!("Task join error: {}", e)))),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(entry) => debug!("Audit entry {} recorded", entry.seq),
+        Err(e) => {
+            warn!("Failed to write the audit log: {}", e);
+            error_history::record_error("audit", &e);
+        }
+    }
+}
+
+/// Add `record` to the log at `path`, chained to its last entry
+pub fn append(path: &Path, record: Record) -> Result<Entry> {
+    let _guard = APPEND.lock().unwrap_or_else(|p| p.into_inner());
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut options = OpenOptions::new();
+    options.read(true).append(true).create(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    crate::lock_file(&file)?;
+
+    let (seq, previous) = match last_line(&mut file)? {
+        Some(line) => {
+            let last: Entry = serde_json::from_str(&line)
+                .map_err(|e| Error::Parse(format!("Audit log {:?} ends with an invalid entry: {}", path, e)))?;
+            (last.seq + 1, last.hash)
+        }
+        None => (0, GENESIS.to_string()),
+    };
+    let mut entry = Entry { seq, timestamp: Utc::now(), record, previous, hash: String::new() };
+    entry.hash = entry.compute_hash()?;
+
+    let mut line = serde_json::to_string(&entry)?;
+    line.push('\n');
+    file.write_all(line.as_bytes())?;
+    file.sync_data()?;
+    Ok(entry)
+}
+
+
+/// The last line of `file`, read from the end
+fn last_line(file: &mut File) -> Result<Option<String>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let mut window = 4096;
+    loop {
+        let start = len.saturating_sub(window);
+        file.seek(SeekFrom::Start(start))?;
+        let mut tail = Vec::new();
+        (&mut *file).take(len - start).read_to_end(&mut tail)?;
+        let tail = tail.strip_suffix(b"\n").unwrap_or(&tail);
+        if let Some(newline) = tail.iter().rposition(|&b| b == b'\n') {
+            return Ok(Some(String::from_utf8_lossy(&tail[newline + 1..]).to_string()));
+        }
+        if start == 0 {
+            return Ok((!tail.is_empty()).then(|| String::from_utf8_lossy(tail).to_string()));
+        }
+        window *= 4;
+    }
+}
+
+/// Every entry in the log at `path`, oldest first
+pub fn entries(path: &Path) -> Result<Vec<Entry>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut entries = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let entry = serde_json::from_str(&line?)
+            .map_err(|e| Error::Parse(format!("Audit log {:?} line {}: {}", path, number + 1, e)))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Check that every entry in the log at `path` is intact and chained to the one before
+pub fn verify(path: &Path) -> Result<Verification> {
+    let mut verification = Verification { valid: 0, head: None, problem: None };
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(verification),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut previous = GENESIS.to_string();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let problem = match serde_json::from_str::<Entry>(&line?) {
+            Err(e) => Some(format!("not an entry: {}", e)),
+            Ok(entry) if entry.seq != verification.valid => {
+                Some(format!("entry {} where {} was expected", entry.seq, verification.valid))
+            }
+            Ok(entry) if entry.previous != previous => Some("doesn't follow the entry before it".to_string()),
+            Ok(entry) if entry.compute_hash()? != entry.hash => Some("contents don't match its hash".to_string()),
+            Ok(entry) => {
+                previous = entry.hash;
+                None
+            }
+        };
+        if let Some(problem) = problem {
+            verification.problem = Some(format!("Line {}: {}", number + 1, problem));
+            break;
+        }
+        verification.valid += 1;
+        verification.head = Some(previous.clone());
+    }
+    Ok(verification)
+}
+
+/// `entries` as a JSON array or CSV with a header row
+pub fn export(entries: &[Entry], format: ExportFormat) -> Result<String> {
+    match format {
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(entries)? + "\n"),
+        ExportFormat::Csv => {
+            let mut csv = String::from(
+                "seq,timestamp,event,source,app,input,path,original_sha256,stored_sha256,clipboard,previous,hash\n",
+            );
+            for entry in entries {
+                let record = &entry.record;
+                let event = match record.event {
+                    Event::Intercept => "intercept",
+                    Event::ClipboardRewrite => "clipboard_rewrite",
+                };
+                let fields = [
+                    entry.seq.to_string(),
+                    entry.timestamp.to_rfc3339(),
+                    event.to_string(),
+                    record.source.clone().unwrap_or_default(),
+                    record.app.clone().unwrap_or_default(),
+                    record.input.as_ref().map(|input| input.display().to_string()).unwrap_or_default(),
+                    record.path.display().to_string(),
+                    record.original_sha256.clone().unwrap_or_default(),
+                    record.stored_sha256.clone().unwrap_or_default(),
+                    record.clipboard.clone().unwrap_or_default(),
+                    entry.previous.clone(),
+                    entry.hash.clone(),
+                ];
+                let quoted: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+                csv.push_str(&quoted.join(","));
+                csv.push('\n');
+            }
+            Ok(csv)
+        }
+    }
+}
+
+/// `field` quoted when it holds a separator, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Hex SHA-256 of `data`
+pub fn sha256(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// Hex SHA-256 of the file at `path`, read in chunks
+pub async fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(hex::encode(hasher.finalize()));
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn intercept(name: &str) -> Record {
+        Record {
+            source: Some("clipboard".to_string()),
+            original_sha256: Some(sha256(name.as_bytes())),
+            ..Record::new(Event::Intercept, Path::new(name))
+        }
+    }
+
+    #[test]
+    fn test_chain() {
+        let temp_dir = TempDir::new().unwrap();
+        let log = temp_dir.path().join("audit.jsonl");
+        assert_eq!(verify(&log).unwrap(), Verification { valid: 0, head: None, problem: None });
+
+        let first = append(&log, intercept("a.png")).unwrap();
+        let rewrite = Record { clipboard: Some("'a.png'".to_string()), ..Record::new(Event::ClipboardRewrite, Path::new("a.png")) };
+        let second = append(&log, rewrite).unwrap();
+        let third = append(&log, intercept("b.png")).unwrap();
+        assert_eq!((first.seq, first.previous.as_str()), (0, GENESIS));
+        assert_eq!((second.previous.as_str(), third.previous.as_str()), (first.hash.as_str(), second.hash.as_str()));
+        assert_eq!(entries(&log).unwrap(), [first, second.clone(), third.clone()]);
+        assert_eq!(verify(&log).unwrap(), Verification { valid: 3, head: Some(third.hash), problem: None });
+
+        // Changing an entry breaks the chain there
+        let content = std::fs::read_to_string(&log).unwrap();
+        std::fs::write(&log, content.replacen("b.png", "c.png", 1)).unwrap();
+        let tampered = verify(&log).unwrap();
+        assert_eq!((tampered.valid, tampered.head), (2, Some(second.hash.clone())));
+        assert_eq!(tampered.problem.as_deref(), Some("Line 3: contents don't match its hash"));
+
+        // So does removing one, even with its hash recomputed
+        let lines: Vec<&str> = content.lines().collect();
+        std::fs::write(&log, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        assert_eq!(verify(&log).unwrap().problem.as_deref(), Some("Line 2: entry 2 where 1 was expected"));
+    }
+
+    #[test]
+    fn test_export() {
+        let temp_dir = TempDir::new().unwrap();
+        let log = temp_dir.path().join("audit.jsonl");
+        append(&log, intercept("a.png")).unwrap();
+        let rewrite = Record { clipboard: Some("'a, \"b\".png'".to_string()), ..Record::new(Event::ClipboardRewrite, Path::new("a.png")) };
+        append(&log, rewrite).unwrap();
+        let entries = entries(&log).unwrap();
+
+        let json: Vec<Entry> = serde_json::from_str(&export(&entries, ExportFormat::Json).unwrap()).unwrap();
+        assert_eq!(json, entries);
+
+        let csv = export(&entries, ExportFormat::Csv).unwrap();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.len(), 3);
+        assert!(rows[1].starts_with("0,") && rows[1].contains(",intercept,clipboard,,,a.png,"));
+        assert!(rows[2].contains(",clipboard_rewrite,,,,a.png,,,\"'a, \"\"b\"\".png'\","));
+    }
+
+    #[tokio::test]
+    async fn test_record() {
+        let temp_dir = TempDir::new().unwrap();
+        let image = temp_dir.path().join("a.png");
+        std::fs::write(&image, b"png").unwrap();
+        assert_eq!(sha256_file(&image).await.unwrap(), sha256(b"png"));
+
+        let settings = AuditConfig { enabled: false, file: Some(temp_dir.path().join("audit.jsonl")) };
+        record(&settings, intercept("a.png")).await;
+        assert!(!temp_dir.path().join("audit.jsonl").exists());
+        record(&AuditConfig { enabled: true, ..settings.clone() }, intercept("a.png")).await;
+        assert_eq!(verify(settings.file.as_ref().unwrap()).unwrap().valid, 1);
+    }
+}
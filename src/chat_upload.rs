@@ -0,0 +1,292 @@
+//! Sharing stored images in Slack channels and through Discord webhooks, for
+//! `klipdot upload --to`.
+//!
+//! Both go through `curl`. Credentials come from [`crate::secrets`] and are
+//! passed to curl as a config file on stdin, so they never show up in the
+//! process list:
+//!
+//! - `slack:#channel` or `slack:C0123ABCD` posts with the bot token in the
+//!   `slack` secret (scopes `files:write`, plus `channels:read` /
+//!   `groups:read` to find channels by name). Uploads use Slack's external
+//!   upload flow, which replaced `files.upload`.
+//! - `discord` or `discord:<name>` posts to the webhook URL in the `discord`
+//!   or `discord-<name>` secret.
+
+use crate::{command_runner::CommandRunner, error::Result, retry::RetryPolicy, secrets, Error};
+use serde_json::Value;
+use std::path::Path;
+use tracing::{debug, info};
+
+const SLACK_API: &str = "https://slack.com/api";
+
+/// Channels listed per `conversations.list` page, Slack's maximum
+const SLACK_PAGE_SIZE: u32 = 1000;
+
+/// Pages of channels looked through for a channel name
+const SLACK_MAX_PAGES: usize = 20;
+
+/// Where `klipdot upload` sends an image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    /// A channel name starting with `#`, or a channel id
+    Slack { channel: String },
+    /// A webhook, by the name of the secret holding its URL
+    Discord { secret: String },
+}
+
+impl Destination {
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (kind, target) = spec.split_once(':').unwrap_or((spec, ""));
+        match kind {
+            "slack" if !target.is_empty() && target != "#" => Some(Self::Slack { channel: target.to_string() }),
+            "discord" if target.is_empty() => Some(Self::Discord { secret: "discord".to_string() }),
+            "discord" => Some(Self::Discord { secret: format!("discord-{}", target) }),
+            _ => None,
+        }
+    }
+
+    /// Service the destination is on, as named in policies
+    pub fn service(&self) -> &'static str {
+        match self {
+            Destination::Slack { .. } => "slack",
+            Destination::Discord { .. } => "discord",
+        }
+    }
+
+    /// Host images are uploaded to
+    pub fn host(&self) -> &'static str {
+        match self {
+            Destination::Slack { .. } => "slack.com",
+            Destination::Discord { .. } => "discord.com",
+        }
+    }
+
+    /// Name of the secret the destination needs
+    pub fn secret(&self) -> &str {
+        match self {
+            Destination::Slack { .. } => "slack",
+            Destination::Discord { secret } => secret,
+        }
+    }
+}
+
+/// Upload the image at `path` to `destination` with an optional `comment`,
+/// returning a link to the posted file. Failed requests are retried with `retry`.
+pub async fn upload(runner: &dyn CommandRunner, retry: &RetryPolicy, destination: &Destination, path: &Path, comment: Option<&str>) -> Result<String> {
+    if !runner.is_available("curl") {
+        return Err(Error::Unsupported("curl is needed to upload images".to_string()));
+    }
+    let secret = secrets::get(runner, destination.secret()).await?.ok_or_else(|| {
+        Error::Permission(format!(
+            "No {} secret; store it with `klipdot secret set {}` or set {}",
+            destination.secret(),
+            destination.secret(),
+            secrets::env_var(destination.secret())
+        ))
+    })?;
+
+    retry
+        .run(&format!("upload to {}", destination.service()), || async {
+            match destination {
+                Destination::Slack { channel } => upload_slack(runner, &secret, channel, path, comment).await,
+                Destination::Discord { .. } => upload_discord(runner, &secret, path, comment).await,
+            }
+        })
+        .await
+}
+
+async fn upload_discord(runner: &dyn CommandRunner, webhook: &str, path: &Path, comment: Option<&str>) -> Result<String> {
+    // `wait` makes Discord answer with the message, attachment URLs included
+    let separator = if webhook.contains('?') { '&' } else { '?' };
+    let config = curl_config(&[("url", &format!("{}{}wait=true", webhook, separator))]);
+    let payload = format!("payload_json={}", serde_json::json!({ "content": comment.unwrap_or_default() }));
+    let file = format!("files[0]=@{}", curl_form_path(path));
+    let response = curl(runner, &config, &["-F", &payload, "-F", &file]).await?;
+
+    response["attachments"][0]["url"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| Error::Network(format!("Discord didn't return the upload: {}", response)))
+}
+
+async fn upload_slack(runner: &dyn CommandRunner, token: &str, channel: &str, path: &Path, comment: Option<&str>) -> Result<String> {
+    let auth = curl_config(&[("header", &format!("Authorization: Bearer {}", token))]);
+    let channel_id = match channel.strip_prefix('#') {
+        Some(name) => slack_channel_id(runner, &auth, name).await?,
+        None => channel.to_string(),
+    };
+
+    let filename = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let length = tokio::fs::metadata(path).await?.len();
+    let reserved = slack_call(
+        runner,
+        &auth,
+        "files.getUploadURLExternal",
+        &["--data-urlencode", &format!("filename={}", filename), "--data-urlencode", &format!("length={}", length)],
+    )
+    .await?;
+    let (Some(upload_url), Some(file_id)) = (reserved["upload_url"].as_str(), reserved["file_id"].as_str()) else {
+        return Err(Error::Network(format!("Unexpected response from Slack: {}", reserved)));
+    };
+
+    // The upload URL is signed, so the file itself goes without the token
+    let file = format!("file=@{}", curl_form_path(path));
+    let output = runner
+        .run("curl", &["-sS", "--fail", "-F", &file, upload_url], None)
+        .await
+        .map_err(|e| Error::Process(format!("Failed to run curl: {}", e)))?;
+    if !output.success {
+        return Err(Error::Network(format!("Upload to Slack failed: {}", output.stderr_lossy().trim())));
+    }
+
+    let mut complete = serde_json::json!({
+        "files": [{ "id": file_id, "title": filename }],
+        "channel_id": channel_id,
+    });
+    if let Some(comment) = comment {
+        complete["initial_comment"] = Value::from(comment);
+    }
+    let completed = slack_call(
+        runner,
+        &auth,
+        "files.completeUploadExternal",
+        &["-H", "Content-Type: application/json; charset=utf-8", "--data", &complete.to_string()],
+    )
+    .await?;
+    info!("Shared {:?} in Slack channel {}", path, channel_id);
+    Ok(completed["files"][0]["permalink"].as_str().unwrap_or(file_id).to_string())
+}
+
+/// Id of the Slack channel called `name`
+async fn slack_channel_id(runner: &dyn CommandRunner, auth: &str, name: &str) -> Result<String> {
+    let mut cursor = String::new();
+    for _ in 0..SLACK_MAX_PAGES {
+        let page = slack_call(
+            runner,
+            auth,
+            "conversations.list",
+            &[
+                "-G",
+                "-d",
+                "types=public_channel,private_channel",
+                "-d",
+                "exclude_archived=true",
+                "-d",
+                &format!("limit={}", SLACK_PAGE_SIZE),
+                "--data-urlencode",
+                &format!("cursor={}", cursor),
+            ],
+        )
+        .await?;
+        let channels = page["channels"].as_array().map(Vec::as_slice).unwrap_or_default();
+        if let Some(id) = channels.iter().find(|channel| channel["name"] == name).and_then(|channel| channel["id"].as_str()) {
+            debug!("Slack channel #{} is {}", name, id);
+            return Ok(id.to_string());
+        }
+        cursor = page["response_metadata"]["next_cursor"].as_str().unwrap_or_default().to_string();
+        if cursor.is_empty() {
+            break;
+        }
+    }
+    Err(Error::NotFound(format!("No Slack channel #{} the bot can see", name)))
+}
+
+/// Call the Slack Web API `method`, failing when it answers `"ok": false`
+async fn slack_call(runner: &dyn CommandRunner, auth: &str, method: &str, args: &[&str]) -> Result<Value> {
+    let config = format!("{}{}", auth, curl_config(&[("url", &format!("{}/{}", SLACK_API, method))]));
+    let response = curl(runner, &config, args).await?;
+    if response["ok"] != Value::Bool(true) {
+        let error = response["error"].as_str().unwrap_or("unknown error");
+        return Err(Error::Network(format!("Slack {} failed: {}", method, error)));
+    }
+    Ok(response)
+}
+
+/// Run curl with `config` on stdin and parse the JSON it prints
+async fn curl(runner: &dyn CommandRunner, config: &str, args: &[&str]) -> Result<Value> {
+    let mut full = vec!["-sS", "--fail-with-body", "-K", "-"];
+    full.extend_from_slice(args);
+    let output = runner
+        .run("curl", &full, Some(config.as_bytes()))
+        .await
+        .map_err(|e| Error::Process(format!("Failed to run curl: {}", e)))?;
+    if !output.success {
+        let detail = [output.stdout_lossy(), output.stderr_lossy()].join(" ");
+        return Err(Error::Network(format!("Upload request failed: {}", detail.trim())));
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// A curl config file setting each `(option, value)`
+pub fn curl_config(options: &[(&str, &str)]) -> String {
+    options
+        .iter()
+        .map(|(option, value)| format!("{} = \"{}\"\n", option, value.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect()
+}
+
+/// `path` for a curl `-F name=@...` form field, double-quoted so commas and
+/// semicolons in it are taken literally
+fn curl_form_path(path: &Path) -> String {
+    format!("\"{}\"", path.to_string_lossy().replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_destination() {
+        assert_eq!(Destination::parse("slack:#bugs"), Some(Destination::Slack { channel: "#bugs".to_string() }));
+        assert_eq!(Destination::parse("slack:C0123"), Some(Destination::Slack { channel: "C0123".to_string() }));
+        assert_eq!(Destination::parse("discord"), Some(Destination::Discord { secret: "discord".to_string() }));
+        assert_eq!(Destination::parse("discord:alerts").unwrap().secret(), "discord-alerts");
+        assert_eq!(Destination::parse("slack"), None);
+        assert_eq!(Destination::parse("teams:general"), None);
+    }
+
+    #[test]
+    fn test_curl_config() {
+        assert_eq!(
+            curl_config(&[("url", "https://example.com/a\"b"), ("header", "Authorization: Bearer x")]),
+            "url = \"https://example.com/a\\\"b\"\nheader = \"Authorization: Bearer x\"\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_discord_upload() {
+        let temp_dir = TempDir::new().unwrap();
+        let image = temp_dir.path().join("shot, final.png");
+        std::fs::write(&image, b"png").unwrap();
+        let response = r#"{"id": "1", "attachments": [{"url": "https://cdn.discordapp.com/attachments/1/2/shot.png"}]}"#;
+        let runner = FakeRunner::new().with_output("curl", CommandOutput::ok(response));
+        let webhook = "https://discord.com/api/webhooks/1/secret-token";
+
+        let url = upload_discord(&runner, webhook, &image, Some("Broken layout")).await.unwrap();
+        assert_eq!(url, "https://cdn.discordapp.com/attachments/1/2/shot.png");
+
+        let call = &runner.calls_to("curl")[0];
+        assert!(!call.args.iter().any(|arg| arg.contains("secret-token")));
+        assert_eq!(
+            String::from_utf8(call.stdin.clone().unwrap()).unwrap(),
+            format!("url = \"{}?wait=true\"\n", webhook)
+        );
+        assert!(call.args.contains(&format!("files[0]=@\"{}\"", image.display())));
+        assert!(call.args.contains(&r#"payload_json={"content":"Broken layout"}"#.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_slack_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        let image = temp_dir.path().join("shot.png");
+        std::fs::write(&image, b"png").unwrap();
+        let runner = FakeRunner::new().with_output("curl", CommandOutput::ok(r#"{"ok": false, "error": "not_authed"}"#));
+
+        let error = upload_slack(&runner, "xoxb-1", "C0123", &image, None).await.unwrap_err();
+        assert!(error.to_string().contains("not_authed"));
+        let call = &runner.calls_to("curl")[0];
+        assert!(String::from_utf8(call.stdin.clone().unwrap()).unwrap().contains("Authorization: Bearer xoxb-1"));
+    }
+}
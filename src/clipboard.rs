@@ -1,50 +1,247 @@
-use crate::{config::Config, error::Result, image_processor::ImageProcessor, Error};
-use std::time::Duration;
+use crate::{
+    audit, clipboard_handlers, clipboard_history, clipboard_payload::{self, ClipboardPayload}, clipboard_types, clipboard_watch::ClipboardWatch, command_runner::{self, CommandOutput, SharedRunner},
+    config::{ClipboardAction, Config, CopiedPathMode, PathFormat}, error::Result, error_history, events::{EventBus, InterceptEvent}, focus, image_processor::ImageProcessor, intercept_switches::{self, InterceptSource}, paste_image, path_format, pause, poll_schedule::PollSchedule,
+    processing_queue::{ProcessedImage, ProcessingQueue}, sensitive, sniff, substitution, transforms::{self, TransformMetadata}, undo, url_download::{self, UrlDownloader}, window_crop, Error,
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{self, error::TryRecvError};
 use tokio::time::sleep;
 use tracing::{debug, info, warn, error};
 
+/// How long after a screenshot tool exits a new clipboard image is still attributed to it
+const SCREENSHOT_ATTRIBUTION_WINDOW: Duration = Duration::from_secs(5);
+
+/// How long a running tool (e.g. waiting for an area selection) can claim the next clipboard image
+const SCREENSHOT_TOOL_MAX_RUNTIME: Duration = Duration::from_secs(60);
+
+/// How often the clipboard is still read while change events arrive, in case one is missed
+const WATCH_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Source used for clipboard images no screenshot tool accounts for
+const CLIPBOARD_SOURCE: &str = "clipboard";
+
+/// A screenshot tool announced by the interceptor whose capture has not been seen yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PendingScreenshot {
+    source: String,
+    tool: String,
+    started: Instant,
+    finished: Option<Instant>,
+}
+
+/// Correlates screenshot tool events with the next clipboard image, so captures
+/// that never touch the filesystem are named after the tool's source
+#[derive(Debug, Default)]
+struct ScreenshotAttribution {
+    pending: Option<PendingScreenshot>,
+}
+
+impl ScreenshotAttribution {
+    fn observe(&mut self, event: &InterceptEvent, now: Instant) {
+        match event {
+            InterceptEvent::ScreenshotToolStarted { tool, source } => {
+                self.pending = Some(PendingScreenshot {
+                    source: source.clone(),
+                    tool: tool.clone(),
+                    started: now,
+                    finished: None,
+                });
+            }
+            InterceptEvent::ScreenshotToolFinished { tool, source } => match &mut self.pending {
+                Some(pending) if pending.finished.is_none() && &pending.source == source => {
+                    pending.finished = Some(now);
+                }
+                _ => {
+                    self.pending = Some(PendingScreenshot {
+                        source: source.clone(),
+                        tool: tool.clone(),
+                        started: now,
+                        finished: Some(now),
+                    });
+                }
+            },
+            InterceptEvent::ImageIntercepted { .. } => {}
+        }
+    }
+
+    /// Whether a screenshot tool exited recently and its capture may still land on the clipboard
+    fn awaiting_capture(&self, now: Instant) -> bool {
+        matches!(
+            &self.pending,
+            Some(PendingScreenshot { finished: Some(finished), .. })
+                if now.duration_since(*finished) <= SCREENSHOT_ATTRIBUTION_WINDOW
+        )
+    }
+
+    /// The screenshot tool a clipboard image seen at `now` came from; a pending capture is only used once
+    fn take(&mut self, now: Instant) -> Option<PendingScreenshot> {
+        let pending = self.pending.take()?;
+        let in_window = match pending.finished {
+            None => now.duration_since(pending.started) <= SCREENSHOT_TOOL_MAX_RUNTIME,
+            Some(finished) => now.duration_since(finished) <= SCREENSHOT_ATTRIBUTION_WINDOW,
+        };
+        in_window.then_some(pending)
+    }
+}
+
 pub struct ClipboardMonitor {
     config: Config,
-    image_processor: ImageProcessor,
-    last_content: Option<String>,
+    queue: ProcessingQueue,
+    /// Job whose path should replace the clipboard; cleared when the clipboard changes again
+    awaiting_job: Option<u64>,
+    /// App the awaited image was copied in, for its path format
+    awaiting_app: Option<String>,
+    /// The awaited image as copied, kept for `klipdot undo` once it's replaced
+    awaiting_original: Option<Vec<u8>>,
+    /// What transforms said about images still being processed, by job
+    transform_metadata: HashMap<u64, TransformMetadata>,
+    events: EventBus,
+    screenshot_events: broadcast::Receiver<InterceptEvent>,
+    attribution: ScreenshotAttribution,
+    /// Poll intervals and debouncing, see [`crate::poll_schedule`]
+    schedule: PollSchedule,
+    runner: SharedRunner,
+    last_content: Option<ClipboardPayload>,
+    /// Set when a read found the clipboard marked sensitive and left it unread
+    sensitive_skipped: AtomicBool,
+    /// Type the last read chose, see [`crate::clipboard_types`]
+    read_type: Mutex<Option<String>>,
+    /// Fetches images linked from copied HTML
+    downloader: UrlDownloader,
+    /// Clipboard change events, when they replace polling
+    watch: Option<ClipboardWatch>,
     running: bool,
 }
 
 impl ClipboardMonitor {
     pub async fn new(config: Config) -> Result<Self> {
         let image_processor = ImageProcessor::new(config.clone()).await?;
+        let queue = ProcessingQueue::new(image_processor, &config.processing);
+        let events = EventBus::new();
+        let downloader = UrlDownloader::new(config.url_downloads.clone(), command_runner::system());
+        let schedule = PollSchedule::new(config.polling.clone(), Instant::now());
         
         Ok(Self {
             config,
-            image_processor,
+            queue,
+            awaiting_job: None,
+            awaiting_app: None,
+            awaiting_original: None,
+            transform_metadata: HashMap::new(),
+            screenshot_events: events.subscribe(),
+            attribution: ScreenshotAttribution::default(),
+            schedule,
+            events,
+            downloader,
+            runner: command_runner::system(),
             last_content: None,
+            sensitive_skipped: AtomicBool::new(false),
+            read_type: Mutex::new(None),
+            watch: None,
             running: false,
         })
     }
     
-    pub async fn run(&mut self) -> Result<()> {
-        if !self.config.intercept_methods.clipboard {
-            info!("Clipboard monitoring disabled in config");
-            return Ok(());
+    /// Share an event bus with other components so they see intercepted images
+    pub fn set_event_bus(&mut self, events: EventBus) {
+        self.screenshot_events = events.subscribe();
+        self.events = events;
+    }
+    
+    pub fn event_bus(&self) -> EventBus {
+        self.events.clone()
+    }
+    
+    /// Replace the runner used to invoke clipboard tools
+    pub fn set_command_runner(&mut self, runner: SharedRunner) {
+        self.downloader = UrlDownloader::new(self.config.url_downloads.clone(), runner.clone());
+        self.runner = runner;
+    }
+    
+    /// What the clipboard holds, if it's text
+    pub async fn text(&self) -> Result<Option<String>> {
+        Ok(self.get_clipboard_content().await?.and_then(ClipboardPayload::into_text))
+    }
+    
+    /// Put `content` on the clipboard as text
+    pub async fn set_text(&self, content: &str) -> Result<()> {
+        self.set_clipboard_content(content).await
+    }
+    
+    /// Put image `data` on the clipboard as a PNG, for GUI apps to paste;
+    /// the monitor leaves it there rather than turning it into a path
+    pub async fn set_clipboard_image(&self, data: &[u8]) -> Result<()> {
+        let home_dir = crate::get_home_dir()?;
+        paste_image::copy_data_to_clipboard(self.runner.as_ref(), self.config.get_display_server(), data, &home_dir, &self.config.decode_limits).await
+    }
+    
+    /// Replace the clipboard text with `new` if it still is `old`, such as the
+    /// path of an image that has since been renamed; true if it was replaced
+    pub async fn replace_text(&self, old: &str, new: &str) -> Result<bool> {
+        match self.get_clipboard_content().await? {
+            Some(ClipboardPayload::Text(content)) if content.trim_end() == old => {
+                self.set_clipboard_content(new).await?;
+                Ok(true)
+            }
+            _ => Ok(false),
         }
-        
+    }
+    
+    pub async fn run(&mut self) -> Result<()> {
         // Use faster polling for better responsiveness to screenshots
         let poll_interval = std::cmp::min(self.config.poll_interval, 250); // Max 250ms for good responsiveness
-        info!("Starting clipboard monitor with {}ms interval", poll_interval);
+        self.watch = ClipboardWatch::start(&self.config, self.runner.as_ref());
+        if self.watch.is_some() {
+            info!("Starting clipboard monitor on wl-paste --watch events");
+        } else {
+            info!(
+                "Starting clipboard monitor with {}ms interval{}",
+                poll_interval,
+                if self.config.polling.adaptive { ", slower while idle" } else { "" }
+            );
+        }
         self.running = true;
+        let mut consecutive_failures = 0;
+        let mut disabled = false;
         
         while self.running {
-            if let Err(e) = self.poll_clipboard().await {
-                if e.is_recoverable() {
-                    warn!("Recoverable clipboard error: {}", e);
-                    sleep(Duration::from_millis(poll_interval * 2)).await;
-                } else {
+            // `klipdot disable clipboard` can switch monitoring off and on while running
+            if !intercept_switches::enabled(InterceptSource::Clipboard, &self.config.intercept_methods) {
+                if !disabled {
+                    info!("Clipboard monitoring disabled");
+                    disabled = true;
+                }
+                sleep(Duration::from_millis(poll_interval)).await;
+                continue;
+            }
+            if disabled {
+                info!("Clipboard monitoring enabled");
+                disabled = false;
+                // What was copied meanwhile is left alone
+                self.last_content = self.get_clipboard_content().await.ok().flatten();
+            }
+            
+            match self.poll_clipboard().await {
+                Ok(()) => consecutive_failures = 0,
+                Err(e) if e.is_recoverable() => {
+                    consecutive_failures += 1;
+                    let backoff = self.config.retry.delay_for_attempt(consecutive_failures);
+                    warn!("Recoverable clipboard error: {} (backing off {:?})", e, backoff);
+                    error_history::record_error("clipboard", &e);
+                    sleep(backoff).await;
+                }
+                Err(e) => {
                     error!("Fatal clipboard error: {}", e);
+                    error_history::record_error("clipboard", &e);
                     return Err(e);
                 }
             }
             
-            sleep(Duration::from_millis(poll_interval)).await;
+            self.wait_for_next_poll(Duration::from_millis(poll_interval)).await;
         }
         
         Ok(())
@@ -56,11 +253,37 @@ impl ClipboardMonitor {
     }
     
     async fn poll_clipboard(&mut self) -> Result<()> {
-        let content = self.get_clipboard_content().await?;
+        // Nothing was copied since the last poll
+        #[cfg(all(target_os = "macos", feature = "native-clipboard"))]
+        let changed = crate::pasteboard::changed();
+        #[cfg(all(target_os = "windows", feature = "native-clipboard"))]
+        let changed = crate::windows_clipboard::changed();
+        #[cfg(all(any(target_os = "macos", target_os = "windows"), feature = "native-clipboard"))]
+        if !changed {
+            self.drain_screenshot_events();
+            return Ok(());
+        }
+        
+        let policy = self.config.retry.clone();
+        let this = &*self;
+        let content = policy.run("clipboard_read", move || this.get_clipboard_content()).await?;
+        self.drain_screenshot_events();
+        
+        // A secret replaced the clipboard; the awaited path mustn't overwrite it
+        if self.sensitive_skipped.swap(false, Ordering::Relaxed) {
+            self.stop_awaiting();
+        }
         
+        let read_type = self.read_type.lock().unwrap().take();
         if let Some(content) = content {
             if Some(&content) != self.last_content.as_ref() {
-                self.handle_clipboard_change(&content).await?;
+                let now = Instant::now();
+                self.schedule.activity(now);
+                if self.schedule.repeat(content.bytes(), now) {
+                    debug!("Clipboard content handled moments ago seen again, skipping it");
+                } else {
+                    self.handle_clipboard_change(&content, read_type.as_deref()).await?;
+                }
                 self.last_content = Some(content);
             }
         }
@@ -68,8 +291,101 @@ impl ClipboardMonitor {
         Ok(())
     }
     
-    async fn handle_clipboard_change(&mut self, content: &str) -> Result<()> {
-        debug!("Clipboard content changed, length: {} bytes", content.len());
+    /// Sleep until the next poll, waking up as soon as the clipboard changes or
+    /// the interceptor reports a screenshot tool, and re-reading quickly while
+    /// its capture is expected. Images finished by the processing queue are
+    /// handled as they complete.
+    async fn wait_for_next_poll(&mut self, poll_interval: Duration) {
+        let now = Instant::now();
+        let delay = if self.attribution.awaiting_capture(now) {
+            self.schedule.screenshot_interval()
+        } else if self.watch.is_some() {
+            WATCH_POLL_INTERVAL
+        } else {
+            self.schedule.interval(poll_interval, now)
+        };
+        
+        tokio::select! {
+            _ = sleep(delay) => {}
+            changed = Self::next_change(&mut self.watch) => {
+                if !changed {
+                    warn!("wl-paste --watch stopped, polling the clipboard every {:?}", poll_interval);
+                    self.watch = None;
+                }
+            }
+            Some(processed) = self.queue.next_completed() => self.finish_processing(processed).await,
+            event = self.screenshot_events.recv() => match event {
+                Ok(event) => {
+                    debug!("Screenshot tool event, re-reading clipboard: {:?}", event);
+                    self.observe_screenshot_event(&event);
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("Clipboard monitor lagged, {} events skipped", skipped);
+                }
+                // The monitor holds a sender itself, so the bus never closes while it runs
+                Err(broadcast::error::RecvError::Closed) => sleep(delay).await,
+            },
+        }
+    }
+    
+    /// Wait for the next clipboard change event; never finishes without a watch
+    async fn next_change(watch: &mut Option<ClipboardWatch>) -> bool {
+        match watch {
+            Some(watch) => watch.changed().await,
+            None => std::future::pending().await,
+        }
+    }
+    
+    fn observe_screenshot_event(&mut self, event: &InterceptEvent) {
+        let now = Instant::now();
+        self.attribution.observe(event, now);
+        if !matches!(event, InterceptEvent::ImageIntercepted { .. }) {
+            self.schedule.activity(now);
+        }
+    }
+    
+    /// Pick up screenshot tools the interceptor saw since the last poll
+    fn drain_screenshot_events(&mut self) {
+        loop {
+            match self.screenshot_events.try_recv() {
+                Ok(event) => self.observe_screenshot_event(&event),
+                Err(TryRecvError::Lagged(skipped)) => {
+                    debug!("Clipboard monitor lagged, {} events skipped", skipped);
+                }
+                Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => break,
+            }
+        }
+    }
+    
+    /// Handle new clipboard content, of `content_type` when it was read as a particular type
+    async fn handle_clipboard_change(&mut self, content: &ClipboardPayload, content_type: Option<&str>) -> Result<()> {
+        // Whatever replaced the image is the user's now; don't overwrite it with a path
+        self.stop_awaiting();
+        
+        if let ClipboardPayload::Oversized(limit) = content {
+            warn!("Clipboard content over {} bytes copied, leaving it alone (see clipboard_limits)", limit);
+            return Ok(());
+        }
+        debug!("Clipboard content changed, length: {} bytes", content.bytes().len());
+        
+        // The app focused while copying is the best available guess at the content's origin
+        let focused_app = focus::focused_app(self.runner.as_ref()).await;
+        if sensitive::from_app(&self.config.sensitive, focused_app.as_deref()) {
+            info!("Clipboard content copied in a sensitive app, leaving it alone");
+            return Ok(());
+        }
+        
+        if let Some(reason) = pause::check(&self.config.pause, focused_app.as_deref()) {
+            info!("Interception paused ({}), leaving clipboard content untouched", reason);
+            return Ok(());
+        }
+        
+        let content = match content {
+            ClipboardPayload::Text(text) => text,
+            ClipboardPayload::Binary(data) => return self.handle_clipboard_data(data, content_type, focused_app).await,
+            // Left alone above
+            ClipboardPayload::Oversized(_) => return Ok(()),
+        };
         
         // Log first few characters for debugging (safely handle Unicode)
         let preview = if content.len() > 50 {
@@ -80,36 +396,226 @@ impl ClipboardMonitor {
         };
         debug!("Clipboard preview: {}", preview);
         
-        // Check if content is image data
-        if self.is_image_data(content) {
-            info!("Detected image data in clipboard, processing...");
-            self.process_clipboard_image(content).await?;
-        } else {
-            debug!("Clipboard content is not image data");
+        let content_type = match content_type {
+            Some(content_type) => content_type.to_string(),
+            None => self.content_type(content),
+        };
+        let action = clipboard_handlers::action_for(&self.config.clipboard_types.handlers, &content_type);
+        debug!("Clipboard holds {}, handled as {:?}", content_type, action);
+        
+        let image = match action {
+            ClipboardAction::Process if self.is_image_data(content) => {
+                info!("Detected image data in clipboard, processing...");
+                Some((self.decode_clipboard_image(content)?, self.config.undo.enabled))
+            }
+            ClipboardAction::CopyFile => match self.copied_image_file(content) {
+                Some(file) => {
+                    info!("Detected copied image file {:?}, processing...", file);
+                    // Undo puts back image data, which isn't what a file manager copied
+                    Some((tokio::fs::read(&file).await?, false))
+                }
+                None => None,
+            },
+            ClipboardAction::ExtractImages => self.html_image(content).await.map(|image_data| (image_data, false)),
+            ClipboardAction::Ignore => return Ok(()),
+            _ => None,
+        };
+        
+        match image {
+            Some((image_data, undoable)) => self.submit_clipboard_image(image_data, focused_app, undoable).await?,
+            None => {
+                debug!("Clipboard content is not image data");
+                if action == ClipboardAction::Text && self.config.copied_paths.enabled {
+                    if let Some(file) = self.copied_image_path(content).await {
+                        return self.handle_copied_path(content, file, focused_app).await;
+                    }
+                }
+                if self.config.history.enabled && self.config.history.text {
+                    clipboard_history::record_text(&self.config.history, "clipboard", focused_app.as_deref(), content).await;
+                }
+            }
         }
         
         Ok(())
     }
     
-    async fn process_clipboard_image(&mut self, content: &str) -> Result<()> {
-        info!("Processing clipboard image");
+    /// Handle copied data that isn't text: images are stored, anything else is left alone
+    async fn handle_clipboard_data(&mut self, data: &[u8], content_type: Option<&str>, focused_app: Option<String>) -> Result<()> {
+        let content_type = match content_type {
+            Some(content_type) => content_type.to_string(),
+            None if self.has_image_signature(data) => "image/png".to_string(),
+            None => "application/octet-stream".to_string(),
+        };
+        let action = clipboard_handlers::action_for(&self.config.clipboard_types.handlers, &content_type);
+        debug!("Clipboard holds {} bytes of {}, handled as {:?}", data.len(), content_type, action);
         
-        // Convert clipboard content to image data
-        let image_data = self.decode_clipboard_image(content)?;
+        if action == ClipboardAction::Process && self.has_image_signature(data) {
+            info!("Detected image data in clipboard, processing...");
+            return self.submit_clipboard_image(data.to_vec(), focused_app, self.config.undo.enabled).await;
+        }
+        debug!("Clipboard data is not an image to process");
+        Ok(())
+    }
+    
+    /// The type of `content` read without one: the image pipeline's or a file manager's, or text
+    fn content_type(&self, content: &str) -> String {
+        if self.is_image_data(content) {
+            "image/png".to_string()
+        } else if clipboard_types::uri_list_files(content).is_some() {
+            "text/uri-list".to_string()
+        } else {
+            "text/plain".to_string()
+        }
+    }
+    
+    /// The first image in copied HTML that can be read: inline data, a local
+    /// file, or a download when `url_downloads` is enabled
+    async fn html_image(&self, html: &str) -> Option<Vec<u8>> {
+        for src in clipboard_handlers::html_image_sources(html) {
+            let image_data = if src.starts_with("data:image/") {
+                self.decode_clipboard_image(&src).ok()
+            } else if let Some(file) = crate::substitution::file_uri_path(&src) {
+                tokio::fs::read(&file).await.ok()
+            } else if self.config.url_downloads.enabled && url_download::host_of(&src).is_some() {
+                self.download_image(&src).await
+            } else {
+                None
+            };
+            match image_data {
+                Some(image_data) if self.has_image_signature(&image_data) => {
+                    info!("Detected an image in copied HTML, processing...");
+                    return Some(image_data);
+                }
+                _ => debug!("Skipping image {} in copied HTML", src),
+            }
+        }
+        None
+    }
+    
+    async fn download_image(&self, url: &str) -> Option<Vec<u8>> {
+        let file = match self.downloader.download(url).await {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("Failed to download {} from copied HTML: {}", url, e);
+                return None;
+            }
+        };
+        let image_data = tokio::fs::read(&file).await.ok();
+        let _ = tokio::fs::remove_file(&file).await;
+        image_data
+    }
+    
+    fn stop_awaiting(&mut self) {
+        self.awaiting_job = None;
+        self.awaiting_app = None;
+        self.awaiting_original = None;
+    }
+    
+    /// Queue `image_data` from the clipboard for storing, keeping it for undo if `undoable`
+    async fn submit_clipboard_image(&mut self, image_data: Vec<u8>, focused_app: Option<String>, undoable: bool) -> Result<()> {
+        // `klipdot paste-image` put it there for a GUI app; don't turn it back into a path
+        if let Ok(home_dir) = crate::get_home_dir() {
+            if paste_image::take_handed_back(&home_dir, &image_data).await {
+                info!("Leaving image handed back by paste-image on the clipboard");
+                return Ok(());
+            }
+        }
         
-        // Process the image
-        let file_path = self.image_processor.process_image_data(
-            &image_data,
-            "clipboard"
-        ).await?;
+        // Captures from tools like `grimshot copy` only ever reach the clipboard
+        let (source, app) = match self.attribution.take(Instant::now()) {
+            Some(capture) => {
+                info!("Attributing clipboard image to {} ({})", capture.source, capture.tool);
+                (capture.source, Some(capture.tool))
+            }
+            None => (CLIPBOARD_SOURCE.to_string(), focused_app),
+        };
+        
+        // Full-screen captures are cropped to the window focused now, not once processed
+        let window = window_crop::geometry_for(&self.config, self.runner.as_ref(), &source).await;
         
-        // Replace clipboard content with file path
-        self.set_clipboard_content(&file_path.to_string_lossy()).await?;
+        let original = undoable.then(|| image_data.clone());
+        let transformed = transforms::apply(&self.config.transforms, self.runner.as_ref(), image_data, &source, app.as_deref()).await;
         
-        info!("Clipboard image replaced with file path: {:?}", file_path);
+        // Decoding and saving happen on the processing queue so polling carries on
+        let job = self.queue.submit(transformed.data, &source, app.clone(), window)?;
+        if !transformed.metadata.is_empty() {
+            self.transform_metadata.insert(job, transformed.metadata);
+        }
+        self.awaiting_job = Some(job);
+        self.awaiting_app = app;
+        self.awaiting_original = original;
         Ok(())
     }
     
+    async fn finish_processing(&mut self, processed: ProcessedImage) {
+        let transformed = self.transform_metadata.remove(&processed.id);
+        let file_path = match processed.result {
+            Ok(file_path) => file_path,
+            Err(e) => {
+                warn!("Failed to process {} image: {}", processed.source, e);
+                error_history::record_error("clipboard", &e);
+                return;
+            }
+        };
+        
+        if let Some(transformed) = transformed {
+            if let Err(e) = transforms::record(&file_path, &transformed).await {
+                warn!("Failed to record what transforms said about {:?}: {}", file_path, e);
+            }
+        }
+        
+        if self.awaiting_job == Some(processed.id) {
+            self.awaiting_job = None;
+            
+            // Replace clipboard content with file path
+            let app = self.awaiting_app.take();
+            let original = self.awaiting_original.take();
+            let replacement = path_format::for_clipboard(&self.config, &file_path, app.as_deref()).await;
+            let policy = self.config.retry.clone();
+            let this = &*self;
+            match policy.run("clipboard_write", || this.set_clipboard_content(&replacement)).await {
+                Ok(()) => {
+                    info!("Clipboard image replaced with file path: {:?}", file_path);
+                    // Our own path isn't a change to handle, or to add to the history
+                    self.last_content = Some(ClipboardPayload::Text(replacement.clone()));
+                    if let Some(original) = original {
+                        self.remember_original(&original, &replacement, &file_path).await;
+                    }
+                    let record = audit::Record {
+                        source: Some(processed.source.clone()),
+                        app,
+                        clipboard: Some(replacement.clone()),
+                        ..audit::Record::new(audit::Event::ClipboardRewrite, &file_path)
+                    };
+                    audit::record(&self.config.audit, record).await;
+                }
+                Err(e) => {
+                    warn!("Failed to replace clipboard image with {:?}: {}", file_path, e);
+                    error_history::record_error("clipboard", &e);
+                }
+            }
+        } else {
+            debug!("Clipboard changed while processing, keeping it; image saved to {:?}", file_path);
+        }
+        
+        self.events.publish(InterceptEvent::ImageIntercepted {
+            path: file_path,
+            source: processed.source,
+        });
+    }
+    
+    /// Keep the image that was just replaced so `klipdot undo` can put it back
+    async fn remember_original(&self, original: &[u8], replacement: &str, stored: &Path) {
+        let result = match crate::get_home_dir() {
+            Ok(home_dir) => undo::remember(&home_dir, original, replacement, stored).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            warn!("Failed to keep the replaced clipboard image for undo: {}", e);
+            error_history::record_error("clipboard", &e);
+        }
+    }
+    
     fn is_image_data(&self, content: &str) -> bool {
         // Check for data URL format
         if content.starts_with("data:image/") {
@@ -138,47 +644,70 @@ impl ClipboardMonitor {
         false
     }
     
-    fn has_image_signature(&self, data: &[u8]) -> bool {
-        if data.len() < 4 {
-            return false;
-        }
-        
-        // PNG signature
-        if data.len() >= 8 && data.starts_with(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]) {
-            return true;
+    /// The image file a file manager copied, when `content` is a `text/uri-list`
+    /// of a single image outside the screenshot store. Several files, or other
+    /// kinds of file, are left for pasting as files.
+    fn copied_image_file(&self, content: &str) -> Option<PathBuf> {
+        let [file] = <[PathBuf; 1]>::try_from(clipboard_types::uri_list_files(content)?).ok()?;
+        sniff::file_image_extension(&file)?;
+        // Already stored, e.g. a path put on the clipboard as a URI
+        if self.config.storage_dirs().iter().any(|dir| file.starts_with(dir)) {
+            debug!("Copied file {:?} is already in the screenshot store", file);
+            return None;
         }
-        
-        // JPEG signatures (multiple variants)
-        if data.len() >= 3 && data.starts_with(&[0xFF, 0xD8, 0xFF]) {
-            return true;
-        }
-        
-        // GIF signatures
-        if data.len() >= 6 && (data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a")) {
-            return true;
-        }
-        
-        // BMP signature
-        if data.len() >= 2 && data.starts_with(b"BM") {
-            return true;
-        }
-        
-        // WEBP signature
-        if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
-            return true;
+        Some(file)
+    }
+    
+    /// The image file named by copied text: an absolute path, one starting
+    /// with `~/`, or one relative to the directory of the shell that last
+    /// showed a prompt, quoted or not. Stored images are left alone.
+    async fn copied_image_path(&self, content: &str) -> Option<PathBuf> {
+        let text = content.trim();
+        if text.is_empty() || text.contains('\n') {
+            return None;
         }
-        
-        // TIFF signatures (big and little endian)
-        if data.len() >= 4 && (data.starts_with(&[0x49, 0x49, 0x2A, 0x00]) || data.starts_with(&[0x4D, 0x4D, 0x00, 0x2A])) {
-            return true;
+        let words = substitution::split_words(text);
+        let unquoted = match words.as_slice() {
+            [word] if !Path::new(text).exists() => word.value.clone(),
+            _ => text.to_string(),
+        };
+        let path = match unquoted.strip_prefix("~/") {
+            Some(rest) => dirs::home_dir()?.join(rest),
+            None => PathBuf::from(&unquoted),
+        };
+        let path = if path.is_absolute() { path } else { path_format::noted_cwd().await?.join(path) };
+        sniff::file_image_extension(&path)?;
+        let path = path.canonicalize().ok()?;
+        if self.config.storage_dirs().iter().any(|dir| path.starts_with(dir)) {
+            debug!("Copied path {:?} is already in the screenshot store", path);
+            return None;
         }
-        
-        // ICO signature
-        if data.len() >= 4 && data.starts_with(&[0x00, 0x00, 0x01, 0x00]) {
-            return true;
+        Some(path)
+    }
+    
+    /// Turn copied text naming the image at `file` into what `copied_paths.mode` asks for
+    async fn handle_copied_path(&mut self, content: &str, file: PathBuf, focused_app: Option<String>) -> Result<()> {
+        match self.config.copied_paths.mode {
+            CopiedPathMode::Store => {
+                info!("Detected copied image path {:?}, processing...", file);
+                let image_data = tokio::fs::read(&file).await?;
+                self.submit_clipboard_image(image_data, focused_app, false).await
+            }
+            CopiedPathMode::Absolute => {
+                let absolute = path_format::render(&file, PathFormat::Absolute, self.config.path_quoting, None);
+                if absolute == content.trim() {
+                    return Ok(());
+                }
+                self.set_clipboard_content(&absolute).await?;
+                info!("Copied image path replaced with {:?}", file);
+                Ok(())
+            }
         }
-        
-        false
+    }
+    
+    /// Whether `data` starts like a raster image; copied SVG markup is text
+    fn has_image_signature(&self, data: &[u8]) -> bool {
+        sniff::image_extension(data).is_some_and(|ext| ext != "svg")
     }
     
     fn decode_clipboard_image(&self, content: &str) -> Result<Vec<u8>> {
@@ -198,51 +727,75 @@ impl ClipboardMonitor {
     
     // Platform-specific clipboard implementations
     
-    #[cfg(target_os = "macos")]
-    async fn get_clipboard_content(&self) -> Result<Option<String>> {
-        use std::process::Command;
+    async fn run_tool(&self, program: &str, args: &[&str], stdin: Option<&[u8]>) -> Result<CommandOutput> {
+        self.runner.run(program, args, stdin).await
+            .map_err(|e| Error::Clipboard(format!("Failed to run {}: {}", program, e)))
+    }
+    
+    /// Read clipboard content with `program`, giving up on it past `limit`
+    /// bytes; `None` when it failed or printed nothing
+    #[cfg(not(all(any(target_os = "macos", target_os = "windows"), feature = "native-clipboard")))]
+    async fn read_tool(&self, program: &str, args: &[&str], limit: usize) -> Result<Option<ClipboardPayload>> {
+        let output = self.runner.run_limited(program, args, limit).await
+            .map_err(|e| Error::Clipboard(format!("Failed to run {}: {}", program, e)))?;
+        Ok(match output {
+            Some(output) if output.success && !output.stdout.is_empty() => Some(ClipboardPayload::Binary(output.stdout)),
+            Some(_) => None,
+            None => Some(ClipboardPayload::Oversized(limit)),
+        })
+    }
+    
+    #[cfg(all(target_os = "macos", feature = "native-clipboard"))]
+    async fn get_clipboard_content(&self) -> Result<Option<ClipboardPayload>> {
+        *self.read_type.lock().unwrap() = None;
+        let offered = crate::pasteboard::types();
+        if let Some(mime) = sensitive::marked(&self.config.sensitive, &offered) {
+            debug!("Clipboard marked sensitive with {}, not reading it", mime);
+            self.sensitive_skipped.store(true, Ordering::Relaxed);
+            return Ok(None);
+        }
+        
+        for (uti, mime) in crate::pasteboard::IMAGE_TYPES {
+            if let Some(image_data) = crate::pasteboard::data(uti) {
+                debug!("Found {} in clipboard: {} bytes", uti, image_data.len());
+                *self.read_type.lock().unwrap() = Some(mime.to_string());
+                return Ok(Some(clipboard_payload::check(&self.config.clipboard_limits, ClipboardPayload::Binary(image_data))));
+            }
+        }
+        
+        let text = crate::pasteboard::text().filter(|text| !text.is_empty());
+        Ok(text.map(|text| clipboard_payload::check(&self.config.clipboard_limits, ClipboardPayload::Text(text))))
+    }
+    
+    #[cfg(all(target_os = "macos", not(feature = "native-clipboard")))]
+    async fn get_clipboard_content(&self) -> Result<Option<ClipboardPayload>> {
+        let limits = &self.config.clipboard_limits;
         
         // First check if there's image data in clipboard (from Cmd+Shift+3/4/5)
         if let Ok(image_data) = self.get_macos_clipboard_image().await {
             if !image_data.is_empty() {
                 debug!("Found image data in clipboard: {} bytes", image_data.len());
-                return Ok(Some(base64::encode(&image_data)));
+                return Ok(Some(clipboard_payload::check(limits, ClipboardPayload::Binary(image_data))));
             }
         }
         
         // Try to get text content
-        let output = Command::new("pbpaste")
-            .output()
-            .map_err(|e| Error::Clipboard(format!("Failed to run pbpaste: {}", e)))?;
-        
-        if output.status.success() {
-            let text = String::from_utf8_lossy(&output.stdout);
-            if !text.is_empty() {
-                return Ok(Some(text.to_string()));
-            }
-        }
-        
-        Ok(None)
+        let content = self.read_tool("pbpaste", &[], limits.max_text_size as usize).await?;
+        Ok(content.map(|content| clipboard_payload::check(limits, content.decoded())))
     }
     
-    #[cfg(target_os = "macos")]
+    #[cfg(all(target_os = "macos", not(feature = "native-clipboard")))]
     async fn get_macos_clipboard_image(&self) -> Result<Vec<u8>> {
-        use std::process::Command;
-        
         // Method 1: Try to get PNG data using osascript
-        let output = Command::new("osascript")
-            .arg("-e")
-            .arg(r#"
+        let output = self.run_tool("osascript", &["-e", r#"
                 try
                     set imageData to the clipboard as «class PNGf»
                     return imageData
                 end try
-            "#)
-            .output()
-            .map_err(|e| Error::Clipboard(format!("Failed to get PNG from clipboard: {}", e)))?;
+            "#], None).await?;
         
-        if output.status.success() && !output.stdout.is_empty() {
-            let hex_string = String::from_utf8_lossy(&output.stdout)
+        if output.success && !output.stdout.is_empty() {
+            let hex_string = output.stdout_lossy()
                 .trim()
                 .replace("«data PNGf", "")
                 .replace("»", "")
@@ -257,26 +810,19 @@ impl ClipboardMonitor {
         }
         
         // Method 2: Try using pngpaste if available
-        if crate::is_command_available("pngpaste") {
-            let output = Command::new("pngpaste")
-                .arg("-")
-                .output()
-                .map_err(|e| Error::Clipboard(format!("Failed to run pngpaste: {}", e)))?;
+        if self.runner.is_available("pngpaste") {
+            let output = self.run_tool("pngpaste", &["-"], None).await?;
             
-            if output.status.success() && !output.stdout.is_empty() {
+            if output.success && !output.stdout.is_empty() {
                 debug!("Successfully extracted PNG from clipboard via pngpaste");
                 return Ok(output.stdout);
             }
         }
         
         // Method 3: Try using pbpaste with specific type
-        let output = Command::new("pbpaste")
-            .arg("-pboard")
-            .arg("general")
-            .output()
-            .map_err(|e| Error::Clipboard(format!("Failed to run pbpaste for image: {}", e)))?;
+        let output = self.run_tool("pbpaste", &["-pboard", "general"], None).await?;
         
-        if output.status.success() && !output.stdout.is_empty() {
+        if output.success && !output.stdout.is_empty() {
             // Check if this looks like binary image data
             if self.has_image_signature(&output.stdout) {
                 debug!("Successfully extracted image from clipboard via pbpaste");
@@ -289,31 +835,17 @@ impl ClipboardMonitor {
     
     #[cfg(target_os = "macos")]
     async fn set_clipboard_content(&self, content: &str) -> Result<()> {
-        use std::process::{Command, Stdio};
-        use std::io::Write;
+        let output = self.run_tool("pbcopy", &[], Some(content.as_bytes())).await?;
         
-        let mut child = Command::new("pbcopy")
-            .stdin(Stdio::piped())
-            .spawn()
-            .map_err(|e| Error::Clipboard(format!("Failed to start pbcopy: {}", e)))?;
-        
-        if let Some(stdin) = child.stdin.as_mut() {
-            stdin.write_all(content.as_bytes())
-                .map_err(|e| Error::Clipboard(format!("Failed to write to pbcopy: {}", e)))?;
-        }
-        
-        let status = child.wait()
-            .map_err(|e| Error::Clipboard(format!("Failed to wait for pbcopy: {}", e)))?;
-        
-        if !status.success() {
+        if !output.success {
             return Err(Error::Clipboard("pbcopy failed".to_string()));
         }
         
         Ok(())
     }
     
-    #[cfg(target_os = "linux")]
-    async fn get_clipboard_content(&self) -> Result<Option<String>> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    async fn get_clipboard_content(&self) -> Result<Option<ClipboardPayload>> {
         let available_tools = self.config.get_available_clipboard_tools();
         
         if available_tools.is_empty() {
@@ -330,66 +862,58 @@ impl ClipboardMonitor {
         Ok(None)
     }
     
-    #[cfg(target_os = "linux")]
-    async fn get_clipboard_with_tool(&self, tool: &str) -> Result<Option<String>> {
-        use std::process::Command;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    async fn get_clipboard_with_tool(&self, tool: &str) -> Result<Option<ClipboardPayload>> {
+        *self.read_type.lock().unwrap() = None;
+        if let Some(offered) = clipboard_types::offered(self.runner.as_ref(), tool).await {
+            if let Some(mime) = sensitive::marked(&self.config.sensitive, &offered) {
+                debug!("Clipboard marked sensitive with {}, not reading it", mime);
+                self.sensitive_skipped.store(true, Ordering::Relaxed);
+                return Ok(None);
+            }
+            match clipboard_types::choose(&offered, &self.config.clipboard_types.priority) {
+                Some(mime) => {
+                    *self.read_type.lock().unwrap() = Some(mime.to_string());
+                    return self.read_clipboard_type(tool, mime).await;
+                }
+                None => debug!("No clipboard type in the priority list among {:?}", offered),
+            }
+        }
         
-        let output = match tool {
+        // Without a type it's only known to be text once it has been read
+        let limits = &self.config.clipboard_limits;
+        let any_size = clipboard_payload::largest(limits);
+        let content = match tool {
             "wl-paste" => {
-                // Try text first
-                let mut cmd = Command::new("wl-paste");
-                cmd.arg("--type").arg("text/plain");
-                let text_output = cmd.output().map_err(|e| Error::Clipboard(format!("Failed to run wl-paste: {}", e)))?;
-                
-                if text_output.status.success() {
-                    let content = String::from_utf8_lossy(&text_output.stdout);
-                    if !content.is_empty() {
-                        return Ok(Some(content.to_string()));
-                    }
+                // Try text first, then image data
+                match self.read_tool("wl-paste", &["--type", "text/plain"], limits.max_text_size as usize).await? {
+                    Some(content) => Some(content),
+                    None => self.read_tool("wl-paste", &["--type", "image/png"], limits.max_image_size as usize).await?,
                 }
-                
-                // Try image data
-                let mut cmd = Command::new("wl-paste");
-                cmd.arg("--type").arg("image/png");
-                cmd.output().map_err(|e| Error::Clipboard(format!("Failed to run wl-paste for image: {}", e)))?
-            }
-            "xclip" => {
-                Command::new("xclip")
-                    .arg("-selection")
-                    .arg("clipboard")
-                    .arg("-o")
-                    .output()
-                    .map_err(|e| Error::Clipboard(format!("Failed to run xclip: {}", e)))?
-            }
-            "xsel" => {
-                Command::new("xsel")
-                    .arg("--clipboard")
-                    .arg("--output")
-                    .output()
-                    .map_err(|e| Error::Clipboard(format!("Failed to run xsel: {}", e)))?
             }
+            "xclip" => self.read_tool("xclip", &["-selection", "clipboard", "-o"], any_size).await?,
+            "xsel" => self.read_tool("xsel", &["--clipboard", "--output"], any_size).await?,
+            crate::termux::CLIPBOARD_GET => self.read_tool(tool, &[], any_size).await?,
             _ => {
                 return Err(Error::Clipboard(format!("Unsupported clipboard tool: {}", tool)));
             }
         };
         
-        if output.status.success() {
-            let content = String::from_utf8_lossy(&output.stdout);
-            if !content.is_empty() {
-                // For image data, encode as base64
-                if tool == "wl-paste" && !content.starts_with("data:") && !content.chars().all(|c| c.is_ascii_graphic() || c.is_ascii_whitespace()) {
-                    // This might be binary image data
-                    let base64_content = base64::encode(output.stdout);
-                    return Ok(Some(base64_content));
-                }
-                return Ok(Some(content.to_string()));
-            }
-        }
-        
-        Ok(None)
+        Ok(content.map(|content| clipboard_payload::check(limits, content.decoded())))
     }
     
-    #[cfg(target_os = "linux")]
+    /// Read the clipboard as `mime`; raster images stay binary even if their bytes are UTF-8
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    async fn read_clipboard_type(&self, tool: &str, mime: &str) -> Result<Option<ClipboardPayload>> {
+        debug!("Reading the clipboard as {} with {}", mime, tool);
+        let limits = &self.config.clipboard_limits;
+        let raster = clipboard_types::is_raster(mime);
+        let limit = if raster { limits.max_image_size } else { limits.max_text_size };
+        let content = self.read_tool(tool, &clipboard_types::read_args(tool, mime), limit as usize).await?;
+        Ok(content.map(|content| if raster { content } else { content.decoded() }))
+    }
+    
+    #[cfg(any(target_os = "linux", target_os = "android"))]
     async fn set_clipboard_content(&self, content: &str) -> Result<()> {
         let available_tools = self.config.get_available_clipboard_tools();
         
@@ -407,95 +931,59 @@ impl ClipboardMonitor {
         Err(Error::Clipboard("Failed to set clipboard content with any available tool".to_string()))
     }
     
-    #[cfg(target_os = "linux")]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
     async fn set_clipboard_with_tool(&self, tool: &str, content: &str) -> Result<()> {
-        use std::process::{Command, Stdio};
-        use std::io::Write;
-        
-        let mut child = match tool {
-            "wl-copy" => {
-                Command::new("wl-copy")
-                    .arg("--type")
-                    .arg("text/plain")
-                    .stdin(Stdio::piped())
-                    .spawn()
-                    .map_err(|e| Error::Clipboard(format!("Failed to start wl-copy: {}", e)))?
-            }
-            "xclip" => {
-                Command::new("xclip")
-                    .arg("-selection")
-                    .arg("clipboard")
-                    .stdin(Stdio::piped())
-                    .spawn()
-                    .map_err(|e| Error::Clipboard(format!("Failed to start xclip: {}", e)))?
-            }
-            "xsel" => {
-                Command::new("xsel")
-                    .arg("--clipboard")
-                    .arg("--input")
-                    .stdin(Stdio::piped())
-                    .spawn()
-                    .map_err(|e| Error::Clipboard(format!("Failed to start xsel: {}", e)))?
-            }
+        let args: &[&str] = match tool {
+            "wl-copy" => &["--type", "text/plain"],
+            "xclip" => &["-selection", "clipboard"],
+            "xsel" => &["--clipboard", "--input"],
+            crate::termux::CLIPBOARD_SET => &[],
             _ => {
                 return Err(Error::Clipboard(format!("Unsupported clipboard tool: {}", tool)));
             }
         };
         
-        if let Some(stdin) = child.stdin.as_mut() {
-            stdin.write_all(content.as_bytes())
-                .map_err(|e| Error::Clipboard(format!("Failed to write to {}: {}", tool, e)))?;
-        }
-        
-        let status = child.wait()
-            .map_err(|e| Error::Clipboard(format!("Failed to wait for {}: {}", tool, e)))?;
+        let output = self.run_tool(tool, args, Some(content.as_bytes())).await?;
         
-        if !status.success() {
+        if !output.success {
             return Err(Error::Clipboard(format!("{} failed", tool)));
         }
         
         Ok(())
     }
     
-    #[cfg(target_os = "windows")]
-    async fn get_clipboard_content(&self) -> Result<Option<String>> {
-        use std::process::Command;
-        
-        let output = Command::new("powershell")
-            .arg("-Command")
-            .arg("Get-Clipboard")
-            .output()
-            .map_err(|e| Error::Clipboard(format!("Failed to run PowerShell: {}", e)))?;
-        
-        if output.status.success() {
-            let content = String::from_utf8_lossy(&output.stdout);
-            if !content.is_empty() {
-                return Ok(Some(content.to_string()));
-            }
+    #[cfg(all(target_os = "windows", feature = "native-clipboard"))]
+    async fn get_clipboard_content(&self) -> Result<Option<ClipboardPayload>> {
+        *self.read_type.lock().unwrap() = None;
+        let limits = &self.config.clipboard_limits;
+        if let Some(image_data) = crate::windows_clipboard::image()? {
+            debug!("Found image data in clipboard: {} bytes", image_data.len());
+            *self.read_type.lock().unwrap() = Some("image/png".to_string());
+            return Ok(Some(clipboard_payload::check(limits, ClipboardPayload::Binary(image_data))));
         }
         
-        Ok(None)
+        let text = crate::windows_clipboard::text()?.filter(|text| !text.is_empty());
+        Ok(text.map(|text| clipboard_payload::check(limits, ClipboardPayload::Text(text))))
     }
     
-    #[cfg(target_os = "windows")]
+    #[cfg(all(target_os = "windows", feature = "native-clipboard"))]
     async fn set_clipboard_content(&self, content: &str) -> Result<()> {
-        use std::process::{Command, Stdio};
-        use std::io::Write;
-        
-        let mut child = Command::new("clip")
-            .stdin(Stdio::piped())
-            .spawn()
-            .map_err(|e| Error::Clipboard(format!("Failed to start clip: {}", e)))?;
-        
-        if let Some(stdin) = child.stdin.as_mut() {
-            stdin.write_all(content.as_bytes())
-                .map_err(|e| Error::Clipboard(format!("Failed to write to clip: {}", e)))?;
-        }
-        
-        let status = child.wait()
-            .map_err(|e| Error::Clipboard(format!("Failed to wait for clip: {}", e)))?;
+        crate::windows_clipboard::set_text(content)
+    }
+    
+    #[cfg(all(target_os = "windows", not(feature = "native-clipboard")))]
+    async fn get_clipboard_content(&self) -> Result<Option<ClipboardPayload>> {
+        let limits = &self.config.clipboard_limits;
+        let content = self.read_tool("powershell", &["-Command", "Get-Clipboard"], limits.max_text_size as usize).await?;
+        // Get-Clipboard only ever prints text, in the console's code page
+        Ok(content.map(|content| clipboard_payload::check(limits, ClipboardPayload::Text(String::from_utf8_lossy(content.bytes()).into_owned()))))
+    }
+    
+    #[cfg(all(target_os = "windows", not(feature = "native-clipboard")))]
+    async fn set_clipboard_content(&self, content: &str) -> Result<()> {
+        let output = self.run_tool("clip", &[], Some(content.as_bytes())).await?;
         
-        if !status.success() {
+        if !output.success {
             return Err(Error::Clipboard("clip failed".to_string()));
         }
         
@@ -508,6 +996,7 @@ mod base64 {
     use base64::engine::general_purpose;
     use base64::Engine;
     
+    #[cfg(test)]
     pub fn encode(data: &[u8]) -> String {
         general_purpose::STANDARD.encode(data)
     }
@@ -518,6 +1007,7 @@ mod base64 {
 }
 
 // Add hex dependency to Cargo.toml
+#[cfg(all(target_os = "macos", not(feature = "native-clipboard")))]
 mod hex {
     pub fn decode(data: &str) -> Result<Vec<u8>, hex::FromHexError> {
         hex::decode(data)
@@ -532,8 +1022,10 @@ mod tests {
     #[tokio::test]
     async fn test_clipboard_monitor_creation() {
         let temp_dir = TempDir::new().unwrap();
-        let mut config = Config::default();
-        config.screenshot_dir = temp_dir.path().to_path_buf();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
         
         let monitor = ClipboardMonitor::new(config).await;
         assert!(monitor.is_ok());
@@ -543,10 +1035,24 @@ mod tests {
     async fn test_image_signature_detection() {
         let config = Config::default();
         let processor = ImageProcessor::new(config).await.unwrap();
+        let events = EventBus::new();
         let monitor = ClipboardMonitor {
             config: Config::default(),
-            image_processor: processor,
+            queue: ProcessingQueue::new(processor, &Default::default()),
+            awaiting_job: None,
+            awaiting_app: None,
+            awaiting_original: None,
+            transform_metadata: HashMap::new(),
+            screenshot_events: events.subscribe(),
+            attribution: ScreenshotAttribution::default(),
+            schedule: PollSchedule::new(Default::default(), Instant::now()),
+            events,
+            downloader: UrlDownloader::new(Default::default(), command_runner::system()),
+            runner: command_runner::system(),
             last_content: None,
+            sensitive_skipped: AtomicBool::new(false),
+            read_type: Mutex::new(None),
+            watch: None,
             running: false,
         };
         
@@ -567,10 +1073,24 @@ mod tests {
     async fn test_data_url_detection() {
         let config = Config::default();
         let processor = ImageProcessor::new(config).await.unwrap();
+        let events = EventBus::new();
         let monitor = ClipboardMonitor {
             config: Config::default(),
-            image_processor: processor,
+            queue: ProcessingQueue::new(processor, &Default::default()),
+            awaiting_job: None,
+            awaiting_app: None,
+            awaiting_original: None,
+            transform_metadata: HashMap::new(),
+            screenshot_events: events.subscribe(),
+            attribution: ScreenshotAttribution::default(),
+            schedule: PollSchedule::new(Default::default(), Instant::now()),
+            events,
+            downloader: UrlDownloader::new(Default::default(), command_runner::system()),
+            runner: command_runner::system(),
             last_content: None,
+            sensitive_skipped: AtomicBool::new(false),
+            read_type: Mutex::new(None),
+            watch: None,
             running: false,
         };
         
@@ -580,4 +1100,304 @@ mod tests {
         let text = "Hello, world!";
         assert!(!monitor.is_image_data(text));
     }
-}
\ No newline at end of file
+    
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[tokio::test]
+    async fn test_clipboard_tools_through_runner() {
+        use crate::command_runner::FakeRunner;
+        use std::sync::Arc;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        let runner = Arc::new(FakeRunner::new()
+            .with_output("xclip", CommandOutput::ok("some text"))
+            .with_output_for("xclip", &["-selection", "clipboard", "-t", "TARGETS", "-o"], CommandOutput::failed("no TARGETS"))
+            .with_output("xsel", CommandOutput::failed("Can't open display")));
+        
+        let mut monitor = ClipboardMonitor::new(config).await.unwrap();
+        monitor.set_command_runner(runner.clone());
+        
+        let content = monitor.get_clipboard_with_tool("xclip").await.unwrap();
+        assert_eq!(content, Some(ClipboardPayload::Text("some text".to_string())));
+        assert_eq!(monitor.get_clipboard_with_tool("xsel").await.unwrap(), None);
+        assert!(monitor.get_clipboard_with_tool("wl-paste").await.is_err());
+        
+        monitor.set_clipboard_with_tool("xclip", "/tmp/shot.png").await.unwrap();
+        assert!(monitor.set_clipboard_with_tool("xsel", "/tmp/shot.png").await.is_err());
+        
+        // Listing the types failed, so the clipboard was read as before
+        let calls = runner.calls_to("xclip");
+        assert_eq!(calls[1].args, ["-selection", "clipboard", "-o"]);
+        assert_eq!(calls[2].args, ["-selection", "clipboard"]);
+        assert_eq!(calls[2].stdin.as_deref(), Some(&b"/tmp/shot.png"[..]));
+        
+        runner.set_output("termux-clipboard-get", CommandOutput::ok("shared text"));
+        runner.set_output("termux-clipboard-set", CommandOutput::ok(""));
+        let content = monitor.get_clipboard_with_tool("termux-clipboard-get").await.unwrap();
+        assert_eq!(content, Some(ClipboardPayload::Text("shared text".to_string())));
+        monitor.set_clipboard_with_tool("termux-clipboard-set", "/tmp/shot.png").await.unwrap();
+        assert_eq!(runner.calls_to("termux-clipboard-set")[0].stdin.as_deref(), Some(&b"/tmp/shot.png"[..]));
+    }
+    
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[tokio::test]
+    async fn test_clipboard_type_negotiation() {
+        use crate::command_runner::FakeRunner;
+        use std::sync::Arc;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        let webp = b"RIFF\x10\0\0\0WEBPVP8 ".to_vec();
+        let runner = Arc::new(FakeRunner::new()
+            .with_output_for("wl-paste", &["--list-types"], CommandOutput::ok("text/html\nimage/webp\ntext/plain;charset=utf-8\n"))
+            .with_output_for("wl-paste", &["--no-newline", "--type", "image/webp"], CommandOutput::ok(webp.clone()))
+            .with_output_for("wl-paste", &["--no-newline", "--type", "text/plain;charset=utf-8"], CommandOutput::ok("caption")));
+        
+        let mut monitor = ClipboardMonitor::new(config).await.unwrap();
+        monitor.set_command_runner(runner.clone());
+        
+        // The WebP is read rather than the text, and handed on as binary data even though it's UTF-8
+        let content = monitor.get_clipboard_with_tool("wl-paste").await.unwrap().unwrap();
+        assert_eq!(content, ClipboardPayload::Binary(webp.clone()));
+        assert_eq!(monitor.read_type.lock().unwrap().as_deref(), Some("image/webp"));
+        
+        // Past its limit it isn't kept
+        monitor.config.clipboard_limits.max_image_size = 8;
+        let content = monitor.get_clipboard_with_tool("wl-paste").await.unwrap();
+        assert_eq!(content, Some(ClipboardPayload::Oversized(8)));
+        
+        monitor.config.clipboard_types.priority = vec!["text/plain".to_string(), "image/*".to_string()];
+        let content = monitor.get_clipboard_with_tool("wl-paste").await.unwrap();
+        assert_eq!(content, Some(ClipboardPayload::Text("caption".to_string())));
+    }
+    
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[tokio::test]
+    async fn test_sensitive_clipboard_left_unread() {
+        use crate::command_runner::FakeRunner;
+        use std::sync::Arc;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        let runner = Arc::new(FakeRunner::new()
+            .with_output_for("wl-paste", &["--list-types"], CommandOutput::ok("text/plain;charset=utf-8\nx-kde-passwordManagerHint\n"))
+            .with_output("wl-paste", CommandOutput::ok("hunter2")));
+        
+        let mut monitor = ClipboardMonitor::new(config).await.unwrap();
+        monitor.set_command_runner(runner.clone());
+        
+        // Only the types are listed, and the next poll stops awaiting a path to replace it with
+        assert_eq!(monitor.get_clipboard_with_tool("wl-paste").await.unwrap(), None);
+        assert_eq!(runner.calls_to("wl-paste").len(), 1);
+        assert!(monitor.sensitive_skipped.load(Ordering::Relaxed));
+        
+        monitor.config.sensitive.types.clear();
+        assert_eq!(monitor.get_clipboard_with_tool("wl-paste").await.unwrap(), Some(ClipboardPayload::Text("hunter2".to_string())));
+    }
+    
+    #[tokio::test]
+    async fn test_copied_image_file() {
+        use crate::command_runner::FakeRunner;
+        use crate::substitution::file_uri;
+        use std::sync::Arc;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().join("screenshots"),
+            ..Config::default()
+        };
+        let image = temp_dir.path().join("my shot.png");
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(4, 4)).save(&image).unwrap();
+        let notes = temp_dir.path().join("notes.txt");
+        std::fs::write(&notes, "not an image").unwrap();
+        
+        let mut monitor = ClipboardMonitor::new(config.clone()).await.unwrap();
+        monitor.set_command_runner(Arc::new(FakeRunner::new()));
+        
+        let copied = format!("{}\r\n", file_uri(&image));
+        assert_eq!(monitor.copied_image_file(&copied), Some(image.clone()));
+        assert_eq!(monitor.copied_image_file(&format!("{}\n{}", file_uri(&image), file_uri(&image))), None);
+        assert_eq!(monitor.copied_image_file(&file_uri(&notes)), None);
+        assert_eq!(monitor.copied_image_file(&image.to_string_lossy()), None);
+        
+        // The copied file is stored like a clipboard image
+        monitor.handle_clipboard_change(&ClipboardPayload::Text(copied.clone()), None).await.unwrap();
+        assert!(monitor.awaiting_job.is_some());
+        assert!(monitor.awaiting_original.is_none());
+        let processed = tokio::time::timeout(Duration::from_secs(10), monitor.queue.next_completed()).await.unwrap().unwrap();
+        let stored = processed.result.unwrap();
+        assert!(stored.starts_with(&config.screenshot_dir));
+        
+        // Stored images aren't taken in again
+        assert_eq!(monitor.copied_image_file(&file_uri(&stored)), None);
+    }
+    
+    #[tokio::test]
+    async fn test_clipboard_handlers() {
+        use crate::command_runner::FakeRunner;
+        use std::io::Cursor;
+        use std::sync::Arc;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().join("screenshots"),
+            ..Config::default()
+        };
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(4, 4)).write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        let html = format!(r#"<p>Chart</p><img src="https://example.com/logo.png"><img src="data:image/png;base64,{}">"#, base64::encode(&png));
+        
+        let mut monitor = ClipboardMonitor::new(config).await.unwrap();
+        monitor.set_command_runner(Arc::new(FakeRunner::new()));
+        
+        // Downloads are off, so the inline image is the first usable one
+        monitor.handle_clipboard_change(&ClipboardPayload::Text(html.clone()), Some("text/html")).await.unwrap();
+        assert!(monitor.awaiting_job.is_some());
+        let processed = tokio::time::timeout(Duration::from_secs(10), monitor.queue.next_completed()).await.unwrap().unwrap();
+        assert!(processed.result.unwrap().exists());
+        
+        monitor.config.clipboard_types.handlers.insert("text/html".to_string(), ClipboardAction::Ignore);
+        monitor.handle_clipboard_change(&ClipboardPayload::Text(html.clone()), Some("text/html; charset=utf-8")).await.unwrap();
+        assert!(monitor.awaiting_job.is_none());
+        
+        // Image data read as text isn't processed when text is handled as text
+        monitor.handle_clipboard_change(&ClipboardPayload::Text(base64::encode(&png)), Some("text/plain")).await.unwrap();
+        assert!(monitor.awaiting_job.is_none());
+        monitor.handle_clipboard_change(&ClipboardPayload::Text(base64::encode(&png)), None).await.unwrap();
+        assert!(monitor.awaiting_job.is_some());
+        
+        // Image data read as bytes is processed as is; oversized content is left alone
+        monitor.handle_clipboard_change(&ClipboardPayload::Oversized(1024), None).await.unwrap();
+        assert!(monitor.awaiting_job.is_none());
+        monitor.handle_clipboard_change(&ClipboardPayload::Binary(png.clone()), None).await.unwrap();
+        assert!(monitor.awaiting_job.is_some());
+        monitor.handle_clipboard_change(&ClipboardPayload::Binary(vec![0; 64]), None).await.unwrap();
+        assert!(monitor.awaiting_job.is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_copied_image_path() {
+        use crate::command_runner::FakeRunner;
+        use std::sync::Arc;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = Config {
+            screenshot_dir: temp_dir.path().join("screenshots"),
+            ..Config::default()
+        };
+        config.copied_paths.enabled = true;
+        let image = temp_dir.path().join("my shot.png");
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(4, 4)).save(&image).unwrap();
+        let image = image.canonicalize().unwrap();
+        
+        let mut monitor = ClipboardMonitor::new(config.clone()).await.unwrap();
+        monitor.set_command_runner(Arc::new(FakeRunner::new()));
+        
+        let raw = image.to_string_lossy().to_string();
+        assert_eq!(monitor.copied_image_path(&format!("{}\n", raw)).await, Some(image.clone()));
+        assert_eq!(monitor.copied_image_path(&format!("'{}'", raw)).await, Some(image.clone()));
+        assert_eq!(monitor.copied_image_path(&raw.replace(' ', "\\ ")).await, Some(image.clone()));
+        assert_eq!(monitor.copied_image_path(&format!("see {}", raw)).await, None);
+        assert_eq!(monitor.copied_image_path(&temp_dir.path().join("missing.png").to_string_lossy()).await, None);
+        
+        // Stored like a copied image file
+        monitor.handle_clipboard_change(&ClipboardPayload::Text(raw.clone()), Some("text/plain")).await.unwrap();
+        assert!(monitor.awaiting_job.is_some());
+        let processed = tokio::time::timeout(Duration::from_secs(10), monitor.queue.next_completed()).await.unwrap().unwrap();
+        let stored = processed.result.unwrap();
+        assert_eq!(monitor.copied_image_path(&stored.to_string_lossy()).await, None);
+        
+        monitor.config.copied_paths.enabled = false;
+        monitor.handle_clipboard_change(&ClipboardPayload::Text(raw.clone()), Some("text/plain")).await.unwrap();
+        assert!(monitor.awaiting_job.is_none());
+    }
+    
+    #[test]
+    fn test_screenshot_attribution_window() {
+        let tool = |source: &str| InterceptEvent::ScreenshotToolStarted {
+            tool: "grimshot".to_string(),
+            source: source.to_string(),
+        };
+        let finished = |source: &str| InterceptEvent::ScreenshotToolFinished {
+            tool: "grimshot".to_string(),
+            source: source.to_string(),
+        };
+        let start = Instant::now();
+        let mut attribution = ScreenshotAttribution::default();
+        
+        // Nothing announced: a plain clipboard image
+        assert_eq!(attribution.take(start), None);
+        
+        // Image arrives while the tool is still running, and is only attributed once
+        attribution.observe(&tool("wayland-screenshot"), start);
+        let capture = attribution.take(start + Duration::from_secs(20)).unwrap();
+        assert_eq!((capture.source.as_str(), capture.tool.as_str()), ("wayland-screenshot", "grimshot"));
+        assert_eq!(attribution.take(start + Duration::from_secs(21)), None);
+        
+        // Image arrives shortly after the tool exited
+        attribution.observe(&tool("x11-screenshot"), start);
+        attribution.observe(&finished("x11-screenshot"), start + Duration::from_secs(2));
+        assert_eq!(attribution.take(start + Duration::from_secs(4)).map(|capture| capture.source).as_deref(), Some("x11-screenshot"));
+        
+        // Too long after the tool exited
+        attribution.observe(&finished("wayland-screenshot"), start);
+        assert_eq!(attribution.take(start + SCREENSHOT_ATTRIBUTION_WINDOW + Duration::from_secs(1)), None);
+    }
+    
+    #[tokio::test]
+    async fn test_screenshot_events_from_shared_bus() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        let mut monitor = ClipboardMonitor::new(config).await.unwrap();
+        let events = EventBus::new();
+        monitor.set_event_bus(events.clone());
+        
+        events.publish(InterceptEvent::ScreenshotToolStarted {
+            tool: "grimshot".to_string(),
+            source: "wayland-screenshot".to_string(),
+        });
+        monitor.drain_screenshot_events();
+        
+        let source = monitor.attribution.take(Instant::now()).map(|capture| capture.source);
+        assert_eq!(source.as_deref(), Some("wayland-screenshot"));
+    }
+    
+    #[tokio::test]
+    async fn test_screenshot_tool_wakes_poll_loop() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        let mut monitor = ClipboardMonitor::new(config).await.unwrap();
+        let events = monitor.event_bus();
+        
+        events.publish(InterceptEvent::ScreenshotToolFinished {
+            tool: "grimshot".to_string(),
+            source: "wayland-screenshot".to_string(),
+        });
+        
+        // The event cuts a long poll interval short and switches to fast re-reads
+        let woke = tokio::time::timeout(Duration::from_secs(5), monitor.wait_for_next_poll(Duration::from_secs(60))).await;
+        assert!(woke.is_ok());
+        assert!(monitor.attribution.awaiting_capture(Instant::now()));
+        
+        let reread = tokio::time::timeout(Duration::from_secs(5), monitor.wait_for_next_poll(Duration::from_secs(60))).await;
+        assert!(reread.is_ok());
+        
+        monitor.attribution.take(Instant::now());
+        assert!(!monitor.attribution.awaiting_capture(Instant::now()));
+    }
+}
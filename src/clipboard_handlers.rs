@@ -0,0 +1,82 @@
+//! What the clipboard monitor does with each type of content.
+//!
+//! `clipboard_types.handlers` maps types to a [`ClipboardAction`]. The type is
+//! the one [`crate::clipboard_types`] chose to read or, where types can't be
+//! listed, what the content looks like. The most specific key wins: the
+//! exact type (without parameters), then `image/*`-style wildcards, then `*`.
+//! Content an action can't use, such as a `text/uri-list` of several files,
+//! is handled as text.
+
+use crate::config::ClipboardAction;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::HashMap;
+
+/// Default `clipboard_types.handlers`
+pub const DEFAULT_HANDLERS: &[(&str, ClipboardAction)] = &[
+    ("image/*", ClipboardAction::Process),
+    ("text/uri-list", ClipboardAction::CopyFile),
+    ("text/html", ClipboardAction::ExtractImages),
+    ("*", ClipboardAction::Text),
+];
+
+/// The action for content of type `mime`; text when no key matches
+pub fn action_for(handlers: &HashMap<String, ClipboardAction>, mime: &str) -> ClipboardAction {
+    let essence = mime.split(';').next().unwrap_or(mime).trim();
+    let wildcard = essence.split_once('/').map(|(kind, _)| format!("{}/*", kind));
+    let lookup = |key: &str| handlers.iter().find(|(pattern, _)| pattern.eq_ignore_ascii_case(key)).map(|(_, action)| *action);
+
+    lookup(essence)
+        .or_else(|| wildcard.as_deref().and_then(lookup))
+        .or_else(|| lookup("*"))
+        .unwrap_or(ClipboardAction::Text)
+}
+
+/// The `src` of each `<img>` in copied HTML, in document order
+pub fn html_image_sources(html: &str) -> Vec<String> {
+    static IMG_SRC: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r#"(?is)<img\b[^>]*?\bsrc\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#).unwrap()
+    });
+    IMG_SRC
+        .captures_iter(html)
+        .filter_map(|captures| captures.get(1).or_else(|| captures.get(2)).or_else(|| captures.get(3)))
+        .map(|src| src.as_str().trim().replace("&amp;", "&"))
+        .filter(|src| !src.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_action_for() {
+        let handlers: HashMap<String, ClipboardAction> =
+            DEFAULT_HANDLERS.iter().map(|(mime, action)| (mime.to_string(), *action)).collect();
+        assert_eq!(action_for(&handlers, "image/webp"), ClipboardAction::Process);
+        assert_eq!(action_for(&handlers, "text/uri-list"), ClipboardAction::CopyFile);
+        assert_eq!(action_for(&handlers, "TEXT/HTML;charset=utf-8"), ClipboardAction::ExtractImages);
+        assert_eq!(action_for(&handlers, "UTF8_STRING"), ClipboardAction::Text);
+
+        // Exact types beat wildcards
+        let handlers: HashMap<String, ClipboardAction> = [
+            ("image/*".to_string(), ClipboardAction::Ignore),
+            ("image/png".to_string(), ClipboardAction::Process),
+        ]
+        .into();
+        assert_eq!(action_for(&handlers, "image/png"), ClipboardAction::Process);
+        assert_eq!(action_for(&handlers, "image/gif"), ClipboardAction::Ignore);
+        assert_eq!(action_for(&handlers, "text/plain"), ClipboardAction::Text);
+    }
+
+    #[test]
+    fn test_html_image_sources() {
+        let html = r#"<p>Chart:</p><IMG alt="x" SRC="data:image/png;base64,iVBO">
+            <img src='file:///home/me/a%20b.png' /><img class=icon src=https://example.com/i.png?a=1&amp;b=2><img alt="none">"#;
+        assert_eq!(
+            html_image_sources(html),
+            ["data:image/png;base64,iVBO", "file:///home/me/a%20b.png", "https://example.com/i.png?a=1&b=2"]
+        );
+        assert!(html_image_sources("<p>no images</p>").is_empty());
+    }
+}
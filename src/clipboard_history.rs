@@ -0,0 +1,305 @@
+//! Clipboard history: every intercepted image, and optionally copied text,
+//! with when it arrived, where from and a SHA-256 hash of its content, so
+//! `klipdot history` can list entries and put one back on the clipboard.
+//!
+//! The history is a JSON Lines file in the application home directory,
+//! oldest entry first. It's trimmed to `max_entries` as entries are added;
+//! images stay in the screenshot directory however long their entry lasts.
+
+use crate::{
+    audit, clipboard::ClipboardMonitor, cold_storage, command_runner, config::{Config, HistoryConfig},
+    error::Result, error_history, paste_image, Error,
+};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{debug, warn};
+
+/// Longest text recorded, in bytes; larger copies are left out
+pub const MAX_TEXT_BYTES: usize = 64 * 1024;
+
+/// Serializes writes from this process; other processes are held off with a file lock
+static WRITE: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Image,
+    Text,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Assigned when the entry is added, counting up from 1
+    pub id: u64,
+    pub timestamp: DateTime<Utc>,
+    pub kind: Kind,
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    /// Stored image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    pub sha256: String,
+}
+
+impl Entry {
+    pub fn image(source: &str, app: Option<&str>, path: &Path, sha256: String) -> Self {
+        Self {
+            path: Some(path.to_path_buf()),
+            sha256,
+            ..Self::new(Kind::Image, source, app)
+        }
+    }
+
+    pub fn text(source: &str, app: Option<&str>, text: &str) -> Self {
+        Self {
+            text: Some(text.to_string()),
+            sha256: audit::sha256(text.as_bytes()),
+            ..Self::new(Kind::Text, source, app)
+        }
+    }
+
+    fn new(kind: Kind, source: &str, app: Option<&str>) -> Self {
+        Self {
+            id: 0,
+            timestamp: Utc::now(),
+            kind,
+            source: source.to_string(),
+            app: app.map(str::to_string),
+            path: None,
+            text: None,
+            sha256: String::new(),
+        }
+    }
+
+    /// What the entry holds, on one line of at most `width` characters
+    pub fn summary(&self, width: usize) -> String {
+        match (&self.path, &self.text) {
+            (Some(path), _) => path.display().to_string(),
+            (None, Some(text)) => {
+                let line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                match line.char_indices().nth(width.saturating_sub(3)) {
+                    Some((end, _)) if line.chars().count() > width => format!("{}...", &line[..end]),
+                    _ => line,
+                }
+            }
+            (None, None) => String::new(),
+        }
+    }
+}
+
+/// Where the history is kept
+pub fn history_path(settings: &HistoryConfig) -> Result<PathBuf> {
+    match &settings.file {
+        Some(file) => Ok(file.clone()),
+        None => Ok(crate::get_home_dir()?.join(crate::HISTORY_FILE)),
+    }
+}
+
+/// Add the image stored at `path` to the history when it's enabled
+pub async fn record_image(settings: &HistoryConfig, source: &str, app: Option<&str>, path: &Path) {
+    if !settings.enabled {
+        return;
+    }
+    match audit::sha256_file(path).await {
+        Ok(sha256) => record(settings, Entry::image(source, app, path, sha256)).await,
+        Err(e) => debug!("Not adding {:?} to the clipboard history: {}", path, e),
+    }
+}
+
+/// Add copied `text` to the history when text is recorded
+pub async fn record_text(settings: &HistoryConfig, source: &str, app: Option<&str>, text: &str) {
+    if !settings.enabled || !settings.text || text.trim().is_empty() {
+        return;
+    }
+    if text.len() > MAX_TEXT_BYTES {
+        debug!("Not adding {} bytes of text to the clipboard history", text.len());
+        return;
+    }
+    record(settings, Entry::text(source, app, text)).await;
+}
+
+async fn record(settings: &HistoryConfig, entry: Entry) {
+    // Tests storing images with the default config leave the real history alone
+    if cfg!(test) && settings.file.is_none() {
+        return;
+    }
+    let max_entries = settings.max_entries;
+    let result = match history_path(settings) {
+        Ok(path) => tokio::task::spawn_blocking(move || add(&path, entry, max_entries))
+            .await
+            .unwrap_or_else(|e| Err(Error::Internal(format!("Task join error: {}", e)))),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(Some(entry)) => debug!("Clipboard history entry {} recorded", entry.id),
+        Ok(None) => debug!("Clipboard history already ends with this content"),
+        Err(e) => {
+            warn!("Failed to write the clipboard history: {}", e);
+            error_history::record_error("history", &e);
+        }
+    }
+}
+
+/// Add `entry` to the history at `path`, keeping at most `max_entries`.
+/// Returns the entry with its id, or `None` when the newest entry already
+/// has the same content.
+pub fn add(path: &Path, entry: Entry, max_entries: usize) -> Result<Option<Entry>> {
+    let _guard = WRITE.lock().unwrap_or_else(|p| p.into_inner());
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    crate::lock_file(&file)?;
+
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    let mut entries = parse(&content, path);
+    if entries.last().is_some_and(|last| last.kind == entry.kind && last.sha256 == entry.sha256) {
+        return Ok(None);
+    }
+    let entry = Entry { id: entries.last().map_or(1, |last| last.id + 1), ..entry };
+    entries.push(entry.clone());
+
+    let excess = entries.len().saturating_sub(max_entries.max(1));
+    if excess > 0 {
+        entries.drain(..excess);
+        let mut lines = String::new();
+        for entry in &entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(lines.as_bytes())?;
+    } else {
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(line.as_bytes())?;
+    }
+    file.sync_data()?;
+    Ok(Some(entry))
+}
+
+/// Every entry in the history at `path`, oldest first
+pub fn entries(path: &Path) -> Result<Vec<Entry>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(parse(&content, path)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The entry numbered `id`
+pub fn find(path: &Path, id: u64) -> Result<Entry> {
+    entries(path)?
+        .into_iter()
+        .find(|entry| entry.id == id)
+        .ok_or_else(|| Error::NotFound(format!("No clipboard history entry {}", id)))
+}
+
+/// Forget every entry
+pub fn clear(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Put `entry` back on the clipboard: an image as image data, so GUI apps
+/// can paste it, and text as it was copied
+pub async fn restore(config: &Config, entry: &Entry) -> Result<()> {
+    match (&entry.path, &entry.text) {
+        (Some(path), _) => {
+            let runner = command_runner::system();
+            let path = cold_storage::thaw(runner.as_ref(), path)
+                .await?
+                .ok_or_else(|| Error::NotFound(format!("Image file not found: {:?}", path)))?;
+            paste_image::copy_to_clipboard(runner.as_ref(), config.get_display_server(), &path, &crate::get_home_dir()?, &config.decode_limits).await
+        }
+        (None, Some(text)) => ClipboardMonitor::new(config.clone()).await?.set_text(text).await,
+        (None, None) => Err(Error::InvalidInput(format!("Clipboard history entry {} is empty", entry.id))),
+    }
+}
+
+/// Entries in `content`, skipping lines that aren't entries
+fn parse(content: &str, path: &Path) -> Vec<Entry> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            serde_json::from_str(line)
+                .map_err(|e| debug!("Skipping invalid entry in {:?}: {}", path, e))
+                .ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_add_and_trim() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("history.jsonl");
+        let image = |n: u8| Entry::image("clipboard", Some("firefox"), Path::new("/tmp/shot.png"), audit::sha256(&[n]));
+
+        assert_eq!(add(&path, image(1), 3).unwrap().unwrap().id, 1);
+        // Copying the same thing again doesn't add an entry
+        assert!(add(&path, image(1), 3).unwrap().is_none());
+        add(&path, Entry::text("clipboard", None, "hello"), 3).unwrap();
+        add(&path, image(2), 3).unwrap();
+        add(&path, image(3), 3).unwrap();
+
+        let kept = entries(&path).unwrap();
+        assert_eq!(kept.iter().map(|entry| entry.id).collect::<Vec<_>>(), [2, 3, 4]);
+        assert_eq!(kept[0].text.as_deref(), Some("hello"));
+        assert_eq!(find(&path, 4).unwrap().app.as_deref(), Some("firefox"));
+        assert!(find(&path, 1).is_err());
+
+        // Ids keep counting after a trim
+        assert_eq!(add(&path, image(4), 3).unwrap().unwrap().id, 5);
+
+        clear(&path).unwrap();
+        assert!(entries(&path).unwrap().is_empty());
+        clear(&path).unwrap();
+    }
+
+    #[test]
+    fn test_summary() {
+        let text = Entry::text("clipboard", None, "first line\n  second\tline");
+        assert_eq!(text.summary(80), "first line second line");
+        assert_eq!(text.summary(10), "first l...");
+        let image = Entry::image("capture", None, Path::new("/tmp/shot.png"), String::new());
+        assert_eq!(image.summary(5), "/tmp/shot.png");
+    }
+
+    #[tokio::test]
+    async fn test_record_text_only_when_enabled() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("history.jsonl");
+        let settings = HistoryConfig { file: Some(path.clone()), ..HistoryConfig::default() };
+
+        record_text(&settings, "clipboard", None, "secret").await;
+        assert!(entries(&path).unwrap().is_empty());
+
+        let settings = HistoryConfig { text: true, ..settings };
+        record_text(&settings, "clipboard", None, "notes").await;
+        record_text(&settings, "clipboard", None, &"x".repeat(MAX_TEXT_BYTES + 1)).await;
+        assert_eq!(entries(&path).unwrap().len(), 1);
+    }
+}
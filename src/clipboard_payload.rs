@@ -0,0 +1,91 @@
+//! Clipboard content as read, kept as bytes until it is known to be text.
+//!
+//! Clipboard tools print whatever was copied, which for screenshots means
+//! megabytes of binary data. It is read as bytes and handed on as
+//! [`ClipboardPayload::Binary`] unless it is valid UTF-8, so image data never
+//! goes through a lossy text conversion or a base64 round trip. Reads stop
+//! at `clipboard_limits` (text is capped separately from binary data), and
+//! content past the cap becomes [`ClipboardPayload::Oversized`] and is left
+//! alone rather than held in memory.
+
+use crate::config::ClipboardLimitsConfig;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardPayload {
+    Text(String),
+    Binary(Vec<u8>),
+    /// More than this many bytes were copied; not read any further
+    Oversized(usize),
+}
+
+impl ClipboardPayload {
+    /// `data` as text when it is UTF-8, else as binary data
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        Self::Binary(data).decoded()
+    }
+
+    /// Binary data that is UTF-8 as text, without copying it
+    pub fn decoded(self) -> Self {
+        match self {
+            Self::Binary(data) => match String::from_utf8(data) {
+                Ok(text) => Self::Text(text),
+                Err(e) => Self::Binary(e.into_bytes()),
+            },
+            payload => payload,
+        }
+    }
+
+    /// The content's bytes; none when oversized
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            Self::Text(text) => text.as_bytes(),
+            Self::Binary(data) => data,
+            Self::Oversized(_) => &[],
+        }
+    }
+
+    pub fn into_text(self) -> Option<String> {
+        match self {
+            Self::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+/// The most a read is allowed to return before its kind is known
+pub fn largest(limits: &ClipboardLimitsConfig) -> usize {
+    limits.max_text_size.max(limits.max_image_size) as usize
+}
+
+/// `payload`, or [`ClipboardPayload::Oversized`] when it's over the limit for its kind
+pub fn check(limits: &ClipboardLimitsConfig, payload: ClipboardPayload) -> ClipboardPayload {
+    let limit = match &payload {
+        ClipboardPayload::Text(_) => limits.max_text_size,
+        ClipboardPayload::Binary(_) => limits.max_image_size,
+        ClipboardPayload::Oversized(_) => return payload,
+    } as usize;
+    if payload.bytes().len() > limit {
+        ClipboardPayload::Oversized(limit)
+    } else {
+        payload
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_kinds_and_limits() {
+        assert_eq!(ClipboardPayload::from_bytes(b"caf\xc3\xa9".to_vec()), ClipboardPayload::Text("café".to_string()));
+        let png = b"\x89PNG\r\n\x1a\n".to_vec();
+        assert_eq!(ClipboardPayload::from_bytes(png.clone()), ClipboardPayload::Binary(png.clone()));
+        assert_eq!(ClipboardPayload::Binary(png.clone()).into_text(), None);
+
+        let limits = ClipboardLimitsConfig { max_text_size: 4, max_image_size: 8 };
+        assert_eq!(largest(&limits), 8);
+        assert_eq!(check(&limits, ClipboardPayload::Text("hello".to_string())), ClipboardPayload::Oversized(4));
+        assert_eq!(check(&limits, ClipboardPayload::Binary(png.clone())), ClipboardPayload::Binary(png));
+        assert_eq!(check(&limits, ClipboardPayload::Binary(vec![0; 9])), ClipboardPayload::Oversized(8));
+    }
+}
@@ -0,0 +1,156 @@
+//! Choosing which of the clipboard's types to read.
+//!
+//! An app copying something usually offers it in several types at once: a
+//! browser copying an image offers `image/png` and `text/html`, a file
+//! manager `text/uri-list` and plain text. The types on offer are listed with
+//! `wl-paste --list-types` or `xclip -t TARGETS`, and the first entry of
+//! `clipboard_types.priority` that matches one of them is read. Raster images
+//! go to the image pipeline whatever their format; everything else is text.
+//! Tools that can't list types are read as before.
+
+use crate::command_runner::CommandRunner;
+use std::path::PathBuf;
+
+/// Default priority: images first, so WebP and JPEG copies are intercepted too
+pub const DEFAULT_PRIORITY: &[&str] = &[
+    "image/png",
+    "image/webp",
+    "image/jpeg",
+    "image/gif",
+    "image/bmp",
+    "image/tiff",
+    "image/avif",
+    "text/uri-list",
+    "text/plain",
+    "UTF8_STRING",
+    "STRING",
+];
+
+/// The types on the clipboard, when `tool` can list them
+pub async fn offered(runner: &dyn CommandRunner, tool: &str) -> Option<Vec<String>> {
+    let args: &[&str] = match tool {
+        "wl-paste" => &["--list-types"],
+        "xclip" => &["-selection", "clipboard", "-t", "TARGETS", "-o"],
+        _ => return None,
+    };
+    let output = runner.run(tool, args, None).await.ok().filter(|output| output.success)?;
+    let types: Vec<String> = output
+        .stdout_lossy()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+    (!types.is_empty()).then_some(types)
+}
+
+/// The first type in `offered` matched by an entry of `priority`, trying the
+/// entries in order. An entry without parameters matches an offered type with
+/// them (`text/plain` matches `text/plain;charset=utf-8`), and `image/*`
+/// matches every image type.
+pub fn choose<'a>(offered: &'a [String], priority: &[String]) -> Option<&'a str> {
+    priority
+        .iter()
+        .find_map(|pattern| offered.iter().find(|offered| matches(pattern, offered)))
+        .map(String::as_str)
+}
+
+fn matches(pattern: &str, offered: &str) -> bool {
+    if let Some(prefix) = pattern.strip_suffix("/*") {
+        return offered.split_once('/').is_some_and(|(kind, _)| kind.eq_ignore_ascii_case(prefix));
+    }
+    if pattern.contains(';') {
+        return pattern.eq_ignore_ascii_case(offered);
+    }
+    let essence = offered.split(';').next().unwrap_or(offered).trim();
+    pattern.eq_ignore_ascii_case(essence)
+}
+
+/// Arguments for `tool` to read the clipboard as `mime`
+pub fn read_args<'a>(tool: &str, mime: &'a str) -> Vec<&'a str> {
+    match tool {
+        "wl-paste" => vec!["--no-newline", "--type", mime],
+        _ => vec!["-selection", "clipboard", "-t", mime, "-o"],
+    }
+}
+
+/// The files in a `text/uri-list` (what file managers put on the clipboard
+/// for copied files), or `None` when `content` has anything but local file
+/// URIs and `#` comments
+pub fn uri_list_files(content: &str) -> Option<Vec<PathBuf>> {
+    let files = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(crate::substitution::file_uri_path)
+        .collect::<Option<Vec<_>>>()?;
+    (!files.is_empty()).then_some(files)
+}
+
+/// Whether data of type `mime` goes to the image pipeline; SVG is markup, read as text
+pub fn is_raster(mime: &str) -> bool {
+    let essence = mime.split(';').next().unwrap_or(mime).trim().to_ascii_lowercase();
+    essence.starts_with("image/") && essence != "image/svg+xml"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_choose() {
+        let priority = strings(DEFAULT_PRIORITY);
+        let browser = strings(&["text/html", "image/webp", "text/plain;charset=utf-8"]);
+        assert_eq!(choose(&browser, &priority), Some("image/webp"));
+
+        let files = strings(&["x-special/gnome-copied-files", "text/uri-list", "UTF8_STRING"]);
+        assert_eq!(choose(&files, &priority), Some("text/uri-list"));
+
+        let text = strings(&["TARGETS", "TIMESTAMP", "UTF8_STRING", "text/plain;charset=utf-8"]);
+        assert_eq!(choose(&text, &priority), Some("text/plain;charset=utf-8"));
+        assert_eq!(choose(&strings(&["application/x-thing"]), &priority), None);
+
+        // Text ahead of images, and wildcards
+        let text_first = strings(&["text/plain", "image/*"]);
+        assert_eq!(choose(&browser, &text_first), Some("text/plain;charset=utf-8"));
+        assert_eq!(choose(&strings(&["image/x-icon"]), &text_first), Some("image/x-icon"));
+        assert_eq!(choose(&browser, &strings(&["text/plain;charset=utf-16"])), None);
+    }
+
+    #[test]
+    fn test_is_raster() {
+        assert!(is_raster("image/png"));
+        assert!(is_raster("IMAGE/JPEG"));
+        assert!(!is_raster("image/svg+xml"));
+        assert!(!is_raster("text/uri-list"));
+        assert!(!is_raster("UTF8_STRING"));
+    }
+
+    #[test]
+    fn test_uri_list_files() {
+        let copied = "# copied from Files\r\nfile:///home/me/shot%201.png\r\nfile:///home/me/b.jpg\r\n";
+        assert_eq!(
+            uri_list_files(copied),
+            Some(vec![PathBuf::from("/home/me/shot 1.png"), PathBuf::from("/home/me/b.jpg")])
+        );
+        assert_eq!(uri_list_files("see file:///home/me/a.png"), None);
+        assert_eq!(uri_list_files("file:///a.png\nhttps://example.com/b.png"), None);
+        assert_eq!(uri_list_files("# nothing\n"), None);
+    }
+
+    #[tokio::test]
+    async fn test_offered() {
+        let runner = FakeRunner::new()
+            .with_output("wl-paste", CommandOutput::ok("image/png\ntext/html\n"))
+            .with_output("xclip", CommandOutput::failed("Error: target TARGETS not available"));
+        assert_eq!(offered(&runner, "wl-paste").await, Some(strings(&["image/png", "text/html"])));
+        assert_eq!(runner.calls_to("wl-paste")[0].args, ["--list-types"]);
+        assert_eq!(offered(&runner, "xclip").await, None);
+        assert_eq!(offered(&runner, "xsel").await, None);
+    }
+}
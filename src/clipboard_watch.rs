@@ -0,0 +1,97 @@
+//! Clipboard change events on Wayland, so the clipboard monitor reads the
+//! clipboard when it changes instead of polling it.
+//!
+//! `wl-paste --watch` runs a command each time the clipboard changes; ours
+//! discards the content and prints the `CLIPBOARD_STATE` wl-paste passes it,
+//! one line per change. Compositors without the data-control protocol make
+//! wl-paste exit straight away, and the monitor goes back to polling.
+
+use crate::{command_runner::CommandRunner, config::Config, DisplayServer};
+use std::process::Stdio;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader, Lines};
+use tokio::process::Child;
+use tracing::debug;
+
+/// Run for every change; older wl-paste versions leave `CLIPBOARD_STATE` unset
+const ON_CHANGE: &str = r#"cat >/dev/null; echo "$CLIPBOARD_STATE""#;
+
+pub struct ClipboardWatch {
+    /// Killed when the watch is dropped
+    _child: Option<Child>,
+    lines: Lines<Box<dyn AsyncBufRead + Send + Sync + Unpin>>,
+}
+
+impl ClipboardWatch {
+    /// Start `wl-paste --watch` when `config` allows clipboard events and
+    /// this is a Wayland session with wl-paste installed
+    pub fn start(config: &Config, runner: &dyn CommandRunner) -> Option<Self> {
+        if !config.intercept_methods.clipboard_events
+            || config.get_display_server() != DisplayServer::Wayland
+            || crate::termux::is_termux()
+            || !runner.is_available("wl-paste")
+        {
+            return None;
+        }
+        let mut child = tokio::process::Command::new("wl-paste")
+            .args(["--watch", "sh", "-c", ON_CHANGE])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| debug!("Can't run wl-paste --watch: {}", e))
+            .ok()?;
+        let stdout = child.stdout.take()?;
+        Some(Self {
+            _child: Some(child),
+            lines: (Box::new(BufReader::new(stdout)) as Box<dyn AsyncBufRead + Send + Sync + Unpin>).lines(),
+        })
+    }
+
+    /// Events read from `reader` instead of wl-paste
+    #[cfg(test)]
+    fn from_reader(reader: impl AsyncBufRead + Send + Sync + Unpin + 'static) -> Self {
+        Self {
+            _child: None,
+            lines: (Box::new(reader) as Box<dyn AsyncBufRead + Send + Sync + Unpin>).lines(),
+        }
+    }
+
+    /// Wait until the clipboard holds something new; false once wl-paste has
+    /// exited. Clearing it, and content marked sensitive by password managers,
+    /// don't count.
+    pub async fn changed(&mut self) -> bool {
+        loop {
+            match self.lines.next_line().await {
+                Ok(Some(state)) => match state.trim() {
+                    "data" | "" => return true,
+                    state => debug!("Clipboard changed to {}, not reading it", state),
+                },
+                Ok(None) | Err(_) => return false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::FakeRunner;
+
+    #[tokio::test]
+    async fn test_changes() {
+        let mut watch = ClipboardWatch::from_reader(&b"data\nsensitive\nclear\n\nnil\n"[..]);
+        assert!(watch.changed().await);
+        // Old wl-paste versions say nothing about the state
+        assert!(watch.changed().await);
+        assert!(!watch.changed().await);
+    }
+
+    #[test]
+    fn test_start_needs_wl_paste() {
+        let mut config = Config::default();
+        config.display_server.auto_detect = false;
+        config.display_server.preferred_server = Some("wayland".to_string());
+        assert!(ClipboardWatch::start(&config, &FakeRunner::new()).is_none());
+    }
+}
@@ -0,0 +1,337 @@
+//! The cold storage tier (`klipdot freeze`): images older than
+//! `cold_storage.after_days` are bundled into a zstd-compressed tar in the
+//! directory they were in, or converted to lossy WebP or AVIF with `cwebp`
+//! or `avifenc`.
+//!
+//! The index entry of a frozen image records where it went
+//! ([`ImageMetadata::cold`]), so its original path keeps working:
+//! [`thaw`] extracts it from its bundle back into place, or hands back the
+//! converted file. A conversion is only kept when it's smaller than the
+//! original, and originals are only removed once their bundle lists them.
+
+use crate::{
+    archive,
+    command_runner::CommandRunner,
+    config::{ColdFormat, ColdStorageConfig, Config, Screenshot},
+    error::Result,
+    metadata::{self, ImageMetadata},
+    Error,
+};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// Start of the names of zstd bundles
+const BUNDLE_PREFIX: &str = "cold-";
+
+/// Extensions `cwebp` and `avifenc` read
+const CONVERTIBLE: &[&str] = &["png", "jpg", "jpeg"];
+
+/// What a freeze did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Frozen {
+    pub count: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+/// Images older than `after_days` as of `now` that `format` can freeze, oldest first
+pub async fn candidates(config: &Config, format: ColdFormat, after_days: u32, now: DateTime<Utc>) -> Result<Vec<Screenshot>> {
+    let cutoff = now - Duration::days(after_days as i64);
+    let mut candidates: Vec<Screenshot> = config
+        .get_recent_screenshots(usize::MAX)
+        .await?
+        .into_iter()
+        .filter(|screenshot| screenshot.created_at < cutoff)
+        .filter(|screenshot| format == ColdFormat::Zstd || is_convertible(&screenshot.path))
+        .collect();
+    candidates.reverse();
+    Ok(candidates)
+}
+
+/// Move `screenshots` into cold storage as `settings` says
+pub async fn freeze(runner: &dyn CommandRunner, screenshots: &[Screenshot], settings: &ColdStorageConfig) -> Result<Frozen> {
+    let program = match settings.format {
+        ColdFormat::Zstd => "zstd",
+        ColdFormat::Webp => "cwebp",
+        ColdFormat::Avif => "avifenc",
+    };
+    if !runner.is_available(program) {
+        return Err(Error::Unsupported(format!("Freezing to {:?} needs {} installed", settings.format, program)));
+    }
+
+    // Each directory keeps its own index, and bundles stay beside their images
+    let mut by_dir: BTreeMap<PathBuf, Vec<&Screenshot>> = BTreeMap::new();
+    for screenshot in screenshots {
+        let dir = screenshot.path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        by_dir.entry(dir).or_default().push(screenshot);
+    }
+
+    let mut frozen = Frozen::default();
+    for (dir, screenshots) in by_dir {
+        let index = metadata::load(&dir).await?;
+        let done = match settings.format {
+            ColdFormat::Zstd => bundle(runner, &dir, &screenshots, &index).await?,
+            ColdFormat::Webp | ColdFormat::Avif => convert(runner, &dir, &screenshots, &index, settings).await,
+        };
+        frozen.count += done.count;
+        frozen.bytes_before += done.bytes_before;
+        frozen.bytes_after += done.bytes_after;
+    }
+    Ok(frozen)
+}
+
+/// Where the image stored at `path` can be read, extracting it back into
+/// place when it was bundled; `None` when it isn't there and wasn't frozen
+pub async fn thaw(runner: &dyn CommandRunner, path: &Path) -> Result<Option<PathBuf>> {
+    if path.is_file() {
+        return Ok(Some(path.to_path_buf()));
+    }
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Ok(None);
+    };
+    let Some(mut entry) = metadata::load(dir).await?.remove(name.to_string_lossy().as_ref()) else {
+        return Ok(None);
+    };
+    let Some(location) = entry.cold.take() else {
+        return Ok(None);
+    };
+
+    let Some((bundle, member)) = archive::split_member(&dir.join(&location).to_string_lossy()) else {
+        let converted = dir.join(&location);
+        if !converted.is_file() {
+            return Err(Error::NotFound(format!("{:?} was frozen to {:?}, which is gone", path, converted)));
+        }
+        return Ok(Some(converted));
+    };
+
+    let data = archive::read_member(runner, &bundle, &member).await?;
+    tokio::fs::write(path, data).await?;
+    metadata::record(dir, &entry).await?;
+    debug!("Thawed {:?} from {:?}", path, bundle);
+    Ok(Some(path.to_path_buf()))
+}
+
+fn is_convertible(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| CONVERTIBLE.iter().any(|convertible| ext.eq_ignore_ascii_case(convertible)))
+}
+
+/// The index entry of `screenshot`, or one made from what's known of it
+fn entry_for(screenshot: &Screenshot, index: &HashMap<String, ImageMetadata>) -> ImageMetadata {
+    index.get(&screenshot.filename).cloned().unwrap_or_else(|| ImageMetadata {
+        filename: screenshot.filename.clone(),
+        source: screenshot.source.clone(),
+        app: screenshot.app.clone(),
+        output: screenshot.output.clone(),
+        resized_from: screenshot.resized_from,
+        hash: None,
+        alt_text: screenshot.alt_text.clone(),
+        tags: screenshot.tags.clone(),
+        window: screenshot.window.clone(),
+        cold: None,
+    })
+}
+
+/// Bundle `screenshots` in `dir` into one new zstd-compressed tar
+async fn bundle(
+    runner: &dyn CommandRunner,
+    dir: &Path,
+    screenshots: &[&Screenshot],
+    index: &HashMap<String, ImageMetadata>,
+) -> Result<Frozen> {
+    let name = format!(
+        "{}{}-{}.tar.zst",
+        BUNDLE_PREFIX,
+        Utc::now().format("%Y%m%d-%H%M%S"),
+        &uuid::Uuid::new_v4().simple().to_string()[..8]
+    );
+    let bundle = dir.join(&name);
+    let bundle_arg = bundle.to_string_lossy();
+    let dir_arg = dir.to_string_lossy();
+    let mut args = vec!["--zstd", "-cf", &bundle_arg, "-C", &dir_arg, "--"];
+    args.extend(screenshots.iter().map(|screenshot| screenshot.filename.as_str()));
+
+    let output = runner
+        .run("tar", &args, None)
+        .await
+        .map_err(|e| Error::Process(format!("Failed to run tar: {}", e)))?;
+    if !output.success {
+        let _ = tokio::fs::remove_file(&bundle).await;
+        return Err(Error::Process(format!("tar failed bundling {:?}: {}", dir, output.stderr_lossy().trim())));
+    }
+    let listed = archive::list_images(runner, &bundle).await?;
+    if let Some(missing) = screenshots.iter().find(|screenshot| !listed.contains(&screenshot.filename)) {
+        let _ = tokio::fs::remove_file(&bundle).await;
+        return Err(Error::Process(format!("{:?} is missing {} after bundling", bundle, missing.filename)));
+    }
+
+    let mut frozen = Frozen {
+        bytes_after: tokio::fs::metadata(&bundle).await.map(|metadata| metadata.len()).unwrap_or_default(),
+        ..Frozen::default()
+    };
+    for screenshot in screenshots {
+        // Recorded first, so a crash leaves the image in place with a bundle to fall back on
+        let entry = ImageMetadata {
+            cold: Some(format!("{}{}{}", name, archive::MEMBER_SEPARATOR, screenshot.filename)),
+            ..entry_for(screenshot, index)
+        };
+        metadata::record(dir, &entry).await?;
+        tokio::fs::remove_file(&screenshot.path).await?;
+        frozen.count += 1;
+        frozen.bytes_before += screenshot.size;
+    }
+    Ok(frozen)
+}
+
+/// Convert `screenshots` in `dir` one by one, leaving any that fail or don't shrink
+async fn convert(
+    runner: &dyn CommandRunner,
+    dir: &Path,
+    screenshots: &[&Screenshot],
+    index: &HashMap<String, ImageMetadata>,
+    settings: &ColdStorageConfig,
+) -> Frozen {
+    let mut frozen = Frozen::default();
+    for screenshot in screenshots {
+        match convert_one(runner, dir, screenshot, index, settings).await {
+            Ok(Some(size)) => {
+                frozen.count += 1;
+                frozen.bytes_before += screenshot.size;
+                frozen.bytes_after += size;
+            }
+            Ok(None) => debug!("Converting {:?} doesn't save space, leaving it", screenshot.path),
+            Err(e) => warn!("Failed to freeze {:?}: {}", screenshot.path, e),
+        }
+    }
+    frozen
+}
+
+/// Size of the converted copy of `screenshot`, or `None` when it isn't smaller
+async fn convert_one(
+    runner: &dyn CommandRunner,
+    dir: &Path,
+    screenshot: &Screenshot,
+    index: &HashMap<String, ImageMetadata>,
+    settings: &ColdStorageConfig,
+) -> Result<Option<u64>> {
+    let extension = if settings.format == ColdFormat::Avif { "avif" } else { "webp" };
+    let converted = screenshot.path.with_extension(extension);
+    if converted.exists() {
+        return Err(Error::InvalidInput(format!("{:?} already exists", converted)));
+    }
+    let (input, output) = (screenshot.path.to_string_lossy(), converted.to_string_lossy());
+    let quality = settings.quality.min(100).to_string();
+    let (program, args) = match settings.format {
+        ColdFormat::Avif => ("avifenc", vec!["-q", &quality, &input, &output]),
+        _ => ("cwebp", vec!["-quiet", "-q", &quality, "-metadata", "none", &input, "-o", &output]),
+    };
+    let result = runner
+        .run(program, &args, None)
+        .await
+        .map_err(|e| Error::Process(format!("Failed to run {}: {}", program, e)))?;
+    let size = tokio::fs::metadata(&converted).await.map(|metadata| metadata.len()).ok();
+    let size = match size {
+        Some(size) if result.success && size > 0 => size,
+        _ => {
+            let _ = tokio::fs::remove_file(&converted).await;
+            return Err(Error::Process(format!("{} failed: {}", program, result.stderr_lossy().trim())));
+        }
+    };
+    if size >= screenshot.size {
+        tokio::fs::remove_file(&converted).await?;
+        return Ok(None);
+    }
+
+    // Keep the original's place in listings ordered by modification time
+    if let Ok(modified) = std::fs::metadata(&screenshot.path).and_then(|metadata| metadata.modified()) {
+        if let Err(e) = std::fs::File::options().write(true).open(&converted).and_then(|file| file.set_modified(modified)) {
+            debug!("Failed to keep the modification time of {:?}: {}", screenshot.path, e);
+        }
+    }
+
+    let converted_name = converted.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let entry = entry_for(screenshot, index);
+    // The hash was of the original's content
+    metadata::record(dir, &ImageMetadata { filename: converted_name.clone(), hash: None, ..entry.clone() }).await?;
+    metadata::record(dir, &ImageMetadata { cold: Some(converted_name), ..entry }).await?;
+    tokio::fs::remove_file(&screenshot.path).await?;
+    Ok(Some(size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+    use tempfile::TempDir;
+
+    fn old_screenshot(dir: &Path, name: &str) -> Screenshot {
+        let path = dir.join(name);
+        std::fs::write(&path, b"image data").unwrap();
+        Screenshot {
+            filename: name.to_string(),
+            path,
+            size: 10,
+            source: "clipboard".to_string(),
+            app: None,
+            output: None,
+            resized_from: None,
+            alt_text: None,
+            tags: vec!["bug".to_string()],
+            window: None,
+            created_at: Utc::now() - Duration::days(30),
+            mime_type: "image/png".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bundle_and_thaw() {
+        let temp_dir = TempDir::new().unwrap();
+        let screenshots = [old_screenshot(temp_dir.path(), "a.png"), old_screenshot(temp_dir.path(), "b.png")];
+        let runner = FakeRunner::new()
+            .with_output("zstd", CommandOutput::ok(""))
+            .with_output("tar", CommandOutput::ok("a.png\nb.png\n"));
+
+        let frozen = freeze(&runner, &screenshots, &ColdStorageConfig::default()).await.unwrap();
+        assert_eq!((frozen.count, frozen.bytes_before), (2, 20));
+        let create = &runner.calls_to("tar")[0].args;
+        assert_eq!(create[..2], ["--zstd", "-cf"]);
+        assert_eq!(create[create.len() - 2..], ["a.png", "b.png"]);
+        assert!(!screenshots[0].path.exists());
+
+        let entry = &metadata::load(temp_dir.path()).await.unwrap()["a.png"];
+        let (bundle, member) = entry.cold.as_deref().unwrap().split_once(archive::MEMBER_SEPARATOR).unwrap();
+        assert!(bundle.starts_with(BUNDLE_PREFIX) && bundle.ends_with(".tar.zst"));
+        assert_eq!((member, entry.tags.as_slice()), ("a.png", ["bug".to_string()].as_slice()));
+
+        // Thawing extracts the member back to where it was and clears the record
+        let thawed = thaw(&runner, &screenshots[0].path).await.unwrap();
+        assert_eq!(thawed.as_deref(), Some(screenshots[0].path.as_path()));
+        assert!(screenshots[0].path.is_file());
+        assert_eq!(runner.calls_to("tar").last().unwrap().args[..2], ["-xOf", &*temp_dir.path().join(bundle).to_string_lossy()]);
+        assert_eq!(metadata::load(temp_dir.path()).await.unwrap()["a.png"].cold, None);
+        assert_eq!(thaw(&runner, &temp_dir.path().join("never.png")).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_failed_conversion_keeps_original() {
+        let temp_dir = TempDir::new().unwrap();
+        let screenshots = [old_screenshot(temp_dir.path(), "a.png")];
+        let settings = ColdStorageConfig { format: ColdFormat::Webp, ..ColdStorageConfig::default() };
+
+        let runner = FakeRunner::new();
+        let err = freeze(&runner, &screenshots, &settings).await.unwrap_err();
+        assert_eq!(err.error_code(), "UNSUPPORTED");
+
+        // cwebp "succeeding" without writing anything isn't a conversion
+        runner.set_output("cwebp", CommandOutput::ok(""));
+        let frozen = freeze(&runner, &screenshots, &settings).await.unwrap();
+        assert_eq!(frozen, Frozen::default());
+        assert_eq!(runner.calls_to("cwebp")[0].args[..2], ["-quiet", "-q"]);
+        assert!(screenshots[0].path.is_file());
+        assert!(metadata::load(temp_dir.path()).await.unwrap().is_empty());
+        assert!(!is_convertible(Path::new("a.gif")));
+    }
+}
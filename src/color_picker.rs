@@ -0,0 +1,195 @@
+//! Picking a color off a stored image in the terminal (`klipdot pick-color`).
+//!
+//! The image is shown as in `annotate`, with a crosshair over it. The color is
+//! read from the full-size image under the crosshair rather than from the
+//! preview, and `f` switches to moving one image pixel per key, so thin lines
+//! and single pixels can be picked even when the preview blends them away.
+
+use crate::{
+    error::Result,
+    pixel_view::{self, Cursor, ScreenGuard, Viewport},
+    Error,
+};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::io::{self, IsTerminal};
+
+/// Preview pixels each crosshair arm reaches out from the cursor
+const CROSSHAIR_ARM: i64 = 2;
+
+/// What a keystroke asks the picker to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PickerAction {
+    Redraw,
+    Pick,
+    Quit,
+    Ignored,
+}
+
+/// A crosshair over an image
+#[derive(Debug, Clone)]
+pub struct Picker {
+    img: RgbaImage,
+    cursor: Cursor,
+    /// Image pixels per preview pixel, the step outside fine mode
+    step: u32,
+    fine: bool,
+}
+
+impl Picker {
+    /// A picker over `img`, the crosshair in its middle
+    pub fn new(img: RgbaImage) -> Self {
+        let cursor = Cursor::centered(img.width(), img.height());
+        Self { img, cursor, step: 1, fine: false }
+    }
+
+    pub fn set_step(&mut self, step: u32) {
+        self.step = step.max(1);
+        self.cursor.set_step(if self.fine { 1 } else { self.step });
+    }
+
+    pub fn position(&self) -> (u32, u32) {
+        self.cursor.position()
+    }
+
+    /// Color of the image pixel under the crosshair
+    pub fn color(&self) -> Rgba<u8> {
+        let (x, y) = self.position();
+        if self.img.width() == 0 || self.img.height() == 0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+        *self.img.get_pixel(x, y)
+    }
+
+    pub fn apply(&mut self, key: KeyEvent) -> PickerAction {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return PickerAction::Quit;
+        }
+        if self.cursor.handle(key, true) {
+            return PickerAction::Redraw;
+        }
+        match key.code {
+            KeyCode::Char('f') => {
+                self.fine = !self.fine;
+                self.set_step(self.step);
+                PickerAction::Redraw
+            }
+            KeyCode::Enter | KeyCode::Char(' ') | KeyCode::Char('y') => PickerAction::Pick,
+            KeyCode::Char('q') | KeyCode::Esc => PickerAction::Quit,
+            _ => PickerAction::Ignored,
+        }
+    }
+
+    /// Position and color under the crosshair with a swatch of it, for the status line
+    pub fn status(&self) -> String {
+        let (x, y) = self.position();
+        let color = self.color();
+        format!(
+            "{},{} \x1b[48;2;{};{};{}m    \x1b[0m {} · move: arrows/hjkl (shift: faster)  f {}  Enter pick  q quit",
+            x,
+            y,
+            color[0],
+            color[1],
+            color[2],
+            hex(color),
+            if self.fine { "coarse" } else { "fine" }
+        )
+    }
+}
+
+/// `color` as `#rrggbb`, or `#rrggbbaa` when it isn't opaque
+pub fn hex(color: Rgba<u8>) -> String {
+    let [r, g, b, a] = color.0;
+    if a == 255 {
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    } else {
+        format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+    }
+}
+
+fn frame(viewport: &Viewport, picker: &Picker) -> String {
+    let mut canvas = viewport.canvas();
+    let (x, y) = picker.position();
+    let center = ((x as f32 * viewport.scale()) as i64, (y as f32 * viewport.scale()) as i64);
+
+    // Arms in the inverse of what's under them, the center left to the viewport
+    for offset in (-CROSSHAIR_ARM..=CROSSHAIR_ARM).filter(|offset| *offset != 0) {
+        for (px, py) in [(center.0 + offset, center.1), (center.0, center.1 + offset)] {
+            if (0..canvas.width() as i64).contains(&px) && (0..canvas.height() as i64).contains(&py) {
+                let pixel = canvas.get_pixel_mut(px as u32, py as u32);
+                *pixel = Rgba([255 - pixel[0], 255 - pixel[1], 255 - pixel[2], 255]);
+            }
+        }
+    }
+    viewport.render(canvas, (x, y))
+}
+
+/// Let the user pick a color off `img`, or `None` when they quit
+pub fn run(img: &DynamicImage) -> Result<Option<Rgba<u8>>> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        return Err(Error::Unsupported("pick-color needs an interactive terminal".to_string()));
+    }
+
+    let _screen = ScreenGuard::enter()?;
+    let mut picker = Picker::new(img.to_rgba8());
+    let mut viewport = Viewport::fit(img, pixel_view::terminal_size()?);
+    picker.set_step(viewport.step());
+    pixel_view::draw(&frame(&viewport, &picker), &picker.status())?;
+
+    loop {
+        match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => match picker.apply(key) {
+                PickerAction::Redraw => pixel_view::draw(&frame(&viewport, &picker), &picker.status())?,
+                PickerAction::Pick => return Ok(Some(picker.color())),
+                PickerAction::Quit => return Ok(None),
+                PickerAction::Ignored => {}
+            },
+            Event::Resize(cols, rows) => {
+                viewport = Viewport::fit(img, (cols, rows));
+                picker.set_step(viewport.step());
+                pixel_view::draw(&frame(&viewport, &picker), &picker.status())?;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_picking() {
+        let mut img = RgbaImage::from_pixel(100, 100, Rgba([255, 255, 255, 255]));
+        // A one pixel line the preview would blend away
+        for y in 0..100 {
+            img.put_pixel(51, y, Rgba([0x1e, 0x90, 0xff, 255]));
+        }
+        let mut picker = Picker::new(img);
+        picker.set_step(10);
+        assert_eq!(picker.color(), Rgba([255, 255, 255, 255]));
+
+        // Coarse steps jump over it, fine ones land on it
+        picker.apply(key(KeyCode::Char('l')));
+        assert_eq!(picker.position(), (60, 50));
+        picker.apply(key(KeyCode::Char('f')));
+        for _ in 0..9 {
+            picker.apply(key(KeyCode::Left));
+        }
+        assert_eq!(picker.position(), (51, 50));
+        assert_eq!(hex(picker.color()), "#1e90ff");
+        assert!(picker.status().contains("51,50"));
+        assert_eq!(picker.apply(key(KeyCode::Enter)), PickerAction::Pick);
+        assert_eq!(picker.apply(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)), PickerAction::Quit);
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(hex(Rgba([0, 128, 255, 255])), "#0080ff");
+        assert_eq!(hex(Rgba([0, 128, 255, 64])), "#0080ff40");
+    }
+}
@@ -0,0 +1,328 @@
+//! Execution layer for external tools (ps, xclip, wl-paste, kitten, identify, ...).
+//!
+//! Monitors and preview code never spawn processes directly; they go through a
+//! shared [`CommandRunner`] so tests can swap in a [`FakeRunner`] and exercise
+//! the parsing and fallback logic without a display server or the tools installed.
+
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+
+/// Shared handle to a command runner
+pub type SharedRunner = Arc<dyn CommandRunner>;
+
+/// Captured result of running an external command
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandOutput {
+    pub success: bool,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl CommandOutput {
+    /// Successful output with the given stdout
+    pub fn ok(stdout: impl Into<Vec<u8>>) -> Self {
+        Self {
+            success: true,
+            stdout: stdout.into(),
+            stderr: Vec::new(),
+        }
+    }
+
+    /// Failed output with the given stderr
+    pub fn failed(stderr: impl Into<Vec<u8>>) -> Self {
+        Self {
+            success: false,
+            stdout: Vec::new(),
+            stderr: stderr.into(),
+        }
+    }
+
+    pub fn stdout_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.stdout).into_owned()
+    }
+
+    pub fn stderr_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.stderr).into_owned()
+    }
+}
+
+/// Runs external programs on behalf of the monitors and preview backends
+#[async_trait]
+pub trait CommandRunner: Send + Sync {
+    /// Run `program` to completion, feeding `stdin` if given, and capture its output
+    async fn run(&self, program: &str, args: &[&str], stdin: Option<&[u8]>) -> io::Result<CommandOutput>;
+
+    /// Run `program` like [`run`](Self::run) without stdin, but stop it once
+    /// it has printed more than `limit` bytes; `None` when it did
+    async fn run_limited(&self, program: &str, args: &[&str], limit: usize) -> io::Result<Option<CommandOutput>> {
+        let output = self.run(program, args, None).await?;
+        Ok((output.stdout.len() <= limit).then_some(output))
+    }
+
+    /// Start `program` without waiting for it to exit
+    async fn spawn_detached(&self, program: &str, args: &[&str]) -> io::Result<()>;
+
+    /// Whether `program` can be found on this system
+    fn is_available(&self, program: &str) -> bool;
+}
+
+/// Runner that executes real processes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemRunner;
+
+/// The default runner used outside of tests
+pub fn system() -> SharedRunner {
+    Arc::new(SystemRunner)
+}
+
+#[async_trait]
+impl CommandRunner for SystemRunner {
+    async fn run(&self, program: &str, args: &[&str], stdin: Option<&[u8]>) -> io::Result<CommandOutput> {
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        let mut child = command.spawn()?;
+
+        // Written while the output is read, so a filter that writes as it
+        // reads can't block on a full pipe; dropping the pipe closes its stdin
+        let pipe = child.stdin.take();
+        let write = async move {
+            if let (Some(input), Some(mut pipe)) = (stdin, pipe) {
+                pipe.write_all(input).await?;
+            }
+            io::Result::Ok(())
+        };
+        let (written, output) = tokio::join!(write, child.wait_with_output());
+        let output = output?;
+        // A program may exit without reading all of its input; its exit status says how it went
+        match written {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+            written => written?,
+        }
+        Ok(CommandOutput {
+            success: output.status.success(),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+
+    async fn run_limited(&self, program: &str, args: &[&str], limit: usize) -> io::Result<Option<CommandOutput>> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        // Read alongside stdout so a chatty program can't block on a full pipe
+        let stderr = tokio::spawn(read_all(child.stderr.take()));
+        let mut stdout = Vec::new();
+        if let Some(pipe) = child.stdout.take() {
+            pipe.take(limit as u64 + 1).read_to_end(&mut stdout).await?;
+        }
+        if stdout.len() > limit {
+            child.kill().await?;
+            return Ok(None);
+        }
+
+        let status = child.wait().await?;
+        let stderr = stderr.await.map_err(io::Error::other)??;
+        Ok(Some(CommandOutput {
+            success: status.success(),
+            stdout,
+            stderr,
+        }))
+    }
+
+    async fn spawn_detached(&self, program: &str, args: &[&str]) -> io::Result<()> {
+        Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        Ok(())
+    }
+
+    fn is_available(&self, program: &str) -> bool {
+        crate::is_command_available(program)
+    }
+}
+
+async fn read_all(pipe: Option<impl AsyncRead + Unpin>) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    if let Some(mut pipe) = pipe {
+        pipe.read_to_end(&mut data).await?;
+    }
+    Ok(data)
+}
+
+/// A recorded call made through a [`FakeRunner`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    pub program: String,
+    pub args: Vec<String>,
+    pub stdin: Option<Vec<u8>>,
+}
+
+/// Scripted runner for tests.
+///
+/// Programs without a scripted response fail with `NotFound`, as they would on
+/// a machine where the tool is not installed.
+#[derive(Debug, Default)]
+pub struct FakeRunner {
+    responses: Mutex<HashMap<String, CommandOutput>>,
+    /// Responses to particular arguments, ahead of `responses`
+    responses_for: Mutex<HashMap<(String, Vec<String>), CommandOutput>>,
+    available: Mutex<HashSet<String>>,
+    calls: Mutex<Vec<Invocation>>,
+}
+
+impl FakeRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Respond to every call of `program` with `output`; also marks it available
+    pub fn with_output(self, program: &str, output: CommandOutput) -> Self {
+        self.set_output(program, output);
+        self
+    }
+
+    pub fn set_output(&self, program: &str, output: CommandOutput) {
+        lock(&self.responses).insert(program.to_string(), output);
+        lock(&self.available).insert(program.to_string());
+    }
+
+    /// Respond to calls of `program` with exactly `args` with `output`; also marks it available
+    pub fn with_output_for(self, program: &str, args: &[&str], output: CommandOutput) -> Self {
+        let args = args.iter().map(|arg| arg.to_string()).collect();
+        lock(&self.responses_for).insert((program.to_string(), args), output);
+        lock(&self.available).insert(program.to_string());
+        self
+    }
+
+    /// Calls made so far, in order
+    pub fn calls(&self) -> Vec<Invocation> {
+        lock(&self.calls).clone()
+    }
+
+    /// Calls made to `program` so far
+    pub fn calls_to(&self, program: &str) -> Vec<Invocation> {
+        self.calls()
+            .into_iter()
+            .filter(|call| call.program == program)
+            .collect()
+    }
+
+    fn record(&self, program: &str, args: &[&str], stdin: Option<&[u8]>) {
+        lock(&self.calls).push(Invocation {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            stdin: stdin.map(<[u8]>::to_vec),
+        });
+    }
+}
+
+#[async_trait]
+impl CommandRunner for FakeRunner {
+    async fn run(&self, program: &str, args: &[&str], stdin: Option<&[u8]>) -> io::Result<CommandOutput> {
+        self.record(program, args, stdin);
+        let key = (program.to_string(), args.iter().map(|arg| arg.to_string()).collect());
+        if let Some(output) = lock(&self.responses_for).get(&key) {
+            return Ok(output.clone());
+        }
+        lock(&self.responses)
+            .get(program)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{}: command not found", program)))
+    }
+
+    async fn spawn_detached(&self, program: &str, args: &[&str]) -> io::Result<()> {
+        self.run(program, args, None).await.map(|_| ())
+    }
+
+    fn is_available(&self, program: &str) -> bool {
+        lock(&self.available).contains(program)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_system_runner_captures_output() {
+        let output = SystemRunner.run("sh", &["-c", "cat; echo err >&2"], Some(b"hello")).await.unwrap();
+        assert!(output.success);
+        assert_eq!(output.stdout_lossy(), "hello");
+        assert_eq!(output.stderr_lossy().trim(), "err");
+
+        let output = SystemRunner.run("sh", &["-c", "exit 3"], None).await.unwrap();
+        assert!(!output.success);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_system_runner_streams_large_input() {
+        let input = vec![b'x'; 1 << 20];
+        let output = SystemRunner.run("cat", &[], Some(&input)).await.unwrap();
+        assert!(output.success);
+        assert_eq!(output.stdout.len(), input.len());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_system_runner_limits_output() {
+        let output = SystemRunner.run_limited("sh", &["-c", "printf abc; echo err >&2"], 3).await.unwrap().unwrap();
+        assert_eq!(output.stdout_lossy(), "abc");
+        assert_eq!(output.stderr_lossy().trim(), "err");
+
+        // Stopped partway rather than read to the end
+        let output = SystemRunner.run_limited("sh", &["-c", "head -c 10000000 /dev/zero"], 1000).await.unwrap();
+        assert_eq!(output, None);
+    }
+
+    #[tokio::test]
+    async fn test_system_runner_missing_program() {
+        let result = SystemRunner.run("klipdot-definitely-missing-tool", &[], None).await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_fake_runner_records_calls() {
+        let runner = FakeRunner::new().with_output("xclip", CommandOutput::ok("content"));
+
+        let output = runner.run("xclip", &["-o"], Some(b"input")).await.unwrap();
+        assert_eq!(output.stdout_lossy(), "content");
+        assert!(runner.is_available("xclip"));
+        assert!(!runner.is_available("xsel"));
+        assert!(runner.run("xsel", &[], None).await.is_err());
+
+        let calls = runner.calls_to("xclip");
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].args, vec!["-o".to_string()]);
+        assert_eq!(calls[0].stdin.as_deref(), Some(&b"input"[..]));
+        assert_eq!(runner.calls().len(), 2);
+
+        let runner = runner.with_output_for("xclip", &["-t", "TARGETS"], CommandOutput::ok("image/png"));
+        assert_eq!(runner.run("xclip", &["-t", "TARGETS"], None).await.unwrap().stdout_lossy(), "image/png");
+        assert_eq!(runner.run("xclip", &["-o"], None).await.unwrap().stdout_lossy(), "content");
+    }
+}
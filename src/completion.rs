@@ -0,0 +1,117 @@
+//! Screenshot path completion for the shell hooks.
+//!
+//! `klipdot complete-paths <prefix>` lists intercepted screenshots that match
+//! the word being completed, best matches first and newest first within a
+//! match quality, so the hooks can offer them ahead of ordinary files.
+
+use crate::{config::Config, error::Result};
+use std::path::{Path, PathBuf};
+
+/// How well a screenshot matches the completion prefix; lower is better
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum MatchQuality {
+    PathPrefix,
+    NamePrefix,
+    NameContains,
+    Fuzzy,
+}
+
+/// Screenshots matching `prefix`, ranked by match quality and then recency
+pub async fn complete_paths(config: &Config, prefix: &str, limit: usize) -> Result<Vec<PathBuf>> {
+    let screenshots = config.get_recent_screenshots(usize::MAX).await?;
+    let newest_first = screenshots.into_iter().map(|screenshot| screenshot.path).collect();
+
+    Ok(rank(&expand_home(prefix), newest_first, limit))
+}
+
+/// Rank candidates (given newest first) against `prefix`
+fn rank(prefix: &str, newest_first: Vec<PathBuf>, limit: usize) -> Vec<PathBuf> {
+    let mut matches: Vec<(MatchQuality, usize, PathBuf)> = newest_first
+        .into_iter()
+        .enumerate()
+        .filter_map(|(age, path)| match_quality(prefix, &path).map(|quality| (quality, age, path)))
+        .collect();
+
+    matches.sort_by_key(|(quality, age, _)| (*quality, *age));
+    matches.into_iter().take(limit).map(|(_, _, path)| path).collect()
+}
+
+fn match_quality(prefix: &str, path: &Path) -> Option<MatchQuality> {
+    if path.to_string_lossy().starts_with(prefix) {
+        return Some(MatchQuality::PathPrefix);
+    }
+
+    // Match the last component of what was typed against the file name
+    let needle = prefix.rsplit('/').next().unwrap_or(prefix).to_lowercase();
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+
+    if name.starts_with(&needle) {
+        Some(MatchQuality::NamePrefix)
+    } else if name.contains(&needle) {
+        Some(MatchQuality::NameContains)
+    } else if is_subsequence(&needle, &name) {
+        Some(MatchQuality::Fuzzy)
+    } else {
+        None
+    }
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+    needle.chars().all(|c| haystack.any(|h| h == c))
+}
+
+fn expand_home(prefix: &str) -> String {
+    match (prefix.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest).to_string_lossy().into_owned(),
+        _ => prefix.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(|name| PathBuf::from("/shots").join(name)).collect()
+    }
+
+    #[test]
+    fn test_rank_orders_by_quality_then_recency() {
+        let newest_first = paths(&[
+            "terminal-3.png",
+            "clipboard-2.png",
+            "my-clipboard.png",
+            "clipboard-1.png",
+            "cat-lips.png",
+        ]);
+
+        let ranked = rank("clip", newest_first.clone(), 10);
+        assert_eq!(
+            ranked,
+            paths(&["clipboard-2.png", "clipboard-1.png", "my-clipboard.png", "cat-lips.png"])
+        );
+
+        // An empty prefix lists everything, newest first
+        assert_eq!(rank("", newest_first.clone(), 2), paths(&["terminal-3.png", "clipboard-2.png"]));
+
+        // A full path prefix matches before anything else
+        assert_eq!(rank("/shots/my", newest_first, 1), paths(&["my-clipboard.png"]));
+    }
+
+    #[tokio::test]
+    async fn test_complete_paths_lists_screenshot_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        std::fs::write(temp_dir.path().join("clipboard-1.png"), b"png").unwrap();
+        std::fs::write(temp_dir.path().join("notes.txt"), b"text").unwrap();
+
+        let completions = complete_paths(&config, "clip", 10).await.unwrap();
+        assert_eq!(completions, vec![temp_dir.path().join("clipboard-1.png")]);
+        assert!(complete_paths(&config, "zzz", 10).await.unwrap().is_empty());
+    }
+}
@@ -1,7 +1,10 @@
-use crate::{error::Result, Error};
+use crate::{error::Result, metadata::ImageMetadata, retry::RetryPolicy, Error};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use tracing::{debug, info};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -13,6 +16,9 @@ pub struct Config {
     pub poll_interval: u64,
     pub image_formats: Vec<String>,
     pub max_file_size: u64,
+    /// Per-source overrides of `max_file_size`, keyed by source ("clipboard", "file", ...)
+    #[serde(default)]
+    pub source_max_file_size: HashMap<String, u64>,
     pub compression_quality: u8,
     pub cleanup_days: u32,
     pub enable_logging: bool,
@@ -20,10 +26,1044 @@ pub struct Config {
     pub intercept_methods: InterceptMethods,
     pub shell_integration: ShellIntegration,
     pub display_server: DisplayServerConfig,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default)]
+    pub preview: PreviewConfig,
+    #[serde(default)]
+    pub processing: ProcessingConfig,
+    /// Directories tried, in order, when the screenshot directory can't be written
+    #[serde(default)]
+    pub fallback_dirs: Vec<PathBuf>,
+    #[serde(default)]
+    pub pause: PauseConfig,
+    /// Timestamp filenames in local time instead of UTC
+    #[serde(default)]
+    pub local_time_filenames: bool,
+    /// Record the focused window and workspace with each image, on sway, Hyprland and X11
+    #[serde(default = "default_window_context")]
+    pub window_context: bool,
+    /// Longest side images are scaled down to, or "unlimited"
+    #[serde(default)]
+    pub max_dimension: MaxDimension,
+    /// Format images are stored in; `compression_quality` applies to it
+    #[serde(default)]
+    pub output_format: OutputFormat,
+    /// Whether 16-bit and tone-mapped HDR images keep 16 bits per channel
+    #[serde(default)]
+    pub bit_depth: BitDepth,
+    #[serde(default)]
+    pub downloads: DownloadsConfig,
+    /// Where stored images get a descriptive name from
+    #[serde(default)]
+    pub auto_slug: AutoSlug,
+    /// What another capture of an already stored image becomes
+    #[serde(default)]
+    pub duplicates: DuplicateMode,
+    #[serde(default)]
+    pub alt_text: AltTextConfig,
+    #[serde(default)]
+    pub url_downloads: UrlDownloadConfig,
+    #[serde(default)]
+    pub uploads: UploadConfig,
+    #[serde(default)]
+    pub source_subdirs: SourceSubdirs,
+    /// How image paths put on the clipboard or into command lines are quoted
+    #[serde(default)]
+    pub path_quoting: PathQuoting,
+    #[serde(default)]
+    pub path_format: PathFormatConfig,
+    #[serde(default)]
+    pub mirror: MirrorConfig,
+    #[serde(default)]
+    pub crop_to_window: CropToWindowConfig,
+    #[serde(default)]
+    pub cold_storage: ColdStorageConfig,
+    #[serde(default)]
+    pub filters: FiltersConfig,
+    #[serde(default)]
+    pub scripts: ScriptsConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    #[serde(default)]
+    pub svg: SvgConfig,
+    #[serde(default)]
+    pub undo: UndoConfig,
+    #[serde(default)]
+    pub decode_limits: DecodeLimits,
+    #[serde(default)]
+    pub store_permissions: StorePermissions,
+    #[serde(default)]
+    pub clipboard_types: ClipboardTypesConfig,
+    #[serde(default)]
+    pub sensitive: SensitiveConfig,
+    #[serde(default)]
+    pub copied_paths: CopiedPathsConfig,
+    #[serde(default)]
+    pub pairing: PairingConfig,
+    #[serde(default)]
+    pub share: ShareConfig,
+    #[serde(default)]
+    pub polling: PollingConfig,
+    #[serde(default)]
+    pub clipboard_limits: ClipboardLimitsConfig,
+    #[serde(default)]
+    pub transforms: TransformsConfig,
+    /// Settings the system policy enforces, see [`crate::policy`]
+    #[serde(skip)]
+    pub policy: crate::policy::Policy,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreviewConfig {
+    /// Preview backend by name ("kitty", "sixel", "chafa", ...); auto-detected when unset
+    pub backend: Option<String>,
+    /// Size of previews shown automatically, in terminal cells
+    pub width: u32,
+    pub height: u32,
+    /// Largest preview size; used for overlays and caps `--width`/`--height`
+    pub max_width: u32,
+    pub max_height: u32,
+    /// How automatic previews are shown
+    pub method: PreviewMethod,
+    /// Preview images the daemon intercepts, such as clipboard images
+    /// replaced with a path
+    pub on_intercept: bool,
+    /// Preview images found in the output of wrapped commands
+    pub auto_preview: bool,
+    /// When non-empty, only these commands are auto-previewed
+    pub allow_commands: Vec<String>,
+    /// Commands never auto-previewed, such as builds and test runners
+    pub deny_commands: Vec<String>,
+    /// Smaller previews over slow SSH links
+    pub slow_link: SlowLinkConfig,
+}
+
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        Self {
+            backend: None,
+            width: 40,
+            height: 20,
+            max_width: 80,
+            max_height: 40,
+            method: PreviewMethod::Auto,
+            on_intercept: false,
+            auto_preview: true,
+            allow_commands: Vec::new(),
+            deny_commands: Vec::new(),
+            slow_link: SlowLinkConfig::default(),
+        }
+    }
+}
+
+impl PreviewConfig {
+    /// Size of automatic previews
+    pub fn size(&self) -> (Option<u32>, Option<u32>) {
+        (Some(self.width.min(self.max_width)), Some(self.height.min(self.max_height)))
+    }
+    
+    /// Size of overlay previews, which have a window of their own
+    pub fn max_size(&self) -> (Option<u32>, Option<u32>) {
+        (Some(self.max_width), Some(self.max_height))
+    }
+    
+    /// A requested size capped at the maximum; unset dimensions are left to
+    /// the backend
+    pub fn clamp(&self, width: Option<u32>, height: Option<u32>) -> (Option<u32>, Option<u32>) {
+        (width.map(|width| width.min(self.max_width)), height.map(|height| height.min(self.max_height)))
+    }
+    
+    /// Whether images in the output of `command` should be previewed. List
+    /// entries are a program name, optionally followed by leading arguments
+    /// ("cargo test"); the deny list wins over the allow list.
+    pub fn auto_preview_for(&self, command: &[String]) -> bool {
+        let Some(program) = command.first() else {
+            return self.auto_preview;
+        };
+        let program = Path::new(program)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| program.clone());
+        let words: Vec<&str> = std::iter::once(program.as_str())
+            .chain(command[1..].iter().map(String::as_str))
+            .collect();
+        let listed = |entries: &[String]| {
+            entries.iter().any(|entry| {
+                let entry: Vec<&str> = entry.split_whitespace().collect();
+                !entry.is_empty() && words.starts_with(&entry)
+            })
+        };
+
+        self.auto_preview
+            && !listed(&self.deny_commands)
+            && (self.allow_commands.is_empty() || listed(&self.allow_commands))
+    }
+}
+
+/// How automatic previews are shown
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewMethod {
+    /// Inline, except for full-screen programs that need an overlay or a
+    /// one-line summary
+    #[default]
+    Auto,
+    /// Drawn in the terminal
+    Inline,
+    /// A kitty overlay window, inline outside kitty
+    Overlay,
+    /// A one-line summary with the file name, dimensions and size
+    Compact,
+}
+
+/// When previews are reduced for a slow link, and how far
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SlowLinkConfig {
+    pub mode: SlowLinkMode,
+    /// Round-trip time above which an SSH link is slow, in milliseconds
+    pub max_rtt_ms: u32,
+    /// Delivery rate below which an SSH link is slow, in kilobits per second
+    pub min_rate_kbps: u32,
+    /// Longest side of reduced previews, in pixels
+    pub max_pixels: u32,
+    /// JPEG quality of reduced previews of opaque images
+    pub quality: u8,
+    /// Palette size of reduced sixel previews
+    pub sixel_colors: u16,
+}
+
+impl Default for SlowLinkConfig {
+    fn default() -> Self {
+        Self {
+            mode: SlowLinkMode::Auto,
+            max_rtt_ms: 150,
+            min_rate_kbps: 2000,
+            max_pixels: 640,
+            quality: 60,
+            sixel_colors: 64,
+        }
+    }
+}
+
+/// Whether previews are reduced for a slow link
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlowLinkMode {
+    /// When the SSH connection measures slow
+    #[default]
+    Auto,
+    /// Always
+    On,
+    /// Never
+    Off,
+}
+
+/// Limits for the background image processing queue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProcessingConfig {
+    /// Images that may wait for a worker before new ones are turned away
+    pub queue_capacity: usize,
+    /// Images processed concurrently
+    pub workers: usize,
+}
+
+impl Default for ProcessingConfig {
+    fn default() -> Self {
+        Self {
+            queue_capacity: 16,
+            workers: 2,
+        }
+    }
+}
+
+/// Interception of images saved to the downloads directory, see [`crate::downloads`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DownloadsConfig {
+    /// Requires `intercept_methods.file_watch`
+    pub enabled: bool,
+    /// Directory to watch; the platform downloads directory when unset
+    pub dir: Option<PathBuf>,
+    /// Show a desktop notification with the stored path
+    pub notify: bool,
+}
+
+impl Default for DownloadsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: None,
+            notify: true,
+        }
+    }
+}
+
+/// Descriptions of stored images for alt text, see [`crate::alt_text`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AltTextConfig {
+    /// Describe every stored image in the background
+    pub enabled: bool,
+    /// Command printing a description; `{path}` is replaced by the image
+    /// path, which is appended when there is no placeholder
+    pub command: Vec<String>,
+    /// Longest a description may take before it is abandoned
+    pub timeout_secs: u64,
+}
+
+impl Default for AltTextConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            command: vec![
+                "ollama".to_string(),
+                "run".to_string(),
+                "llava".to_string(),
+                "Describe this screenshot in one short sentence, for use as alt text: {path}".to_string(),
+            ],
+            timeout_secs: 60,
+        }
+    }
+}
+
+/// Limits on downloading image URLs found in monitored output, see [`crate::url_download`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UrlDownloadConfig {
+    pub enabled: bool,
+    /// When non-empty, only these domains and their subdomains are downloaded from
+    pub allow_domains: Vec<String>,
+    /// Domains never downloaded from; wins over the allow list
+    pub deny_domains: Vec<String>,
+    /// Largest image downloaded, in bytes
+    pub max_file_size: u64,
+    pub timeout_secs: u64,
+    /// Downloads running at once
+    pub max_concurrent: usize,
+    /// Downloads started in any one minute
+    pub max_per_minute: usize,
+}
+
+impl Default for UrlDownloadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            allow_domains: Vec::new(),
+            deny_domains: Vec::new(),
+            max_file_size: 10 * 1024 * 1024,
+            timeout_secs: 15,
+            max_concurrent: 2,
+            max_per_minute: 10,
+        }
+    }
+}
+
+/// How images copied to other hosts with scp and rsync are prepared, see [`crate::upload`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UploadConfig {
+    pub enabled: bool,
+    /// When non-empty, only copies to these hosts are rewritten
+    pub hosts: Vec<String>,
+    /// Longest side of the copy that is sent
+    pub max_dimension: MaxDimension,
+    /// Re-encode images so EXIF and other metadata stay local
+    pub strip_metadata: bool,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            hosts: Vec::new(),
+            max_dimension: MaxDimension::Pixels(2048),
+            strip_metadata: true,
+        }
+    }
+}
+
+/// A second directory stored images are copied into, see [`crate::mirror`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MirrorConfig {
+    /// Directory copies go to; mirroring is off without one
+    pub dir: Option<PathBuf>,
+    /// Name of each copy, relative to `dir`
+    pub name_template: String,
+    /// When non-empty, only images from these sources are copied
+    pub sources: Vec<String>,
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            name_template: "{name}.{ext}".to_string(),
+            sources: Vec::new(),
+        }
+    }
+}
+
+/// Cropping full-screen captures to the focused window, see [`crate::window_crop`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CropToWindowConfig {
+    pub enabled: bool,
+    /// Sources cropped; a source matches if it contains one of these
+    pub sources: Vec<String>,
+}
+
+impl Default for CropToWindowConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sources: vec!["screenshot".to_string()],
+        }
+    }
+}
+
+impl CropToWindowConfig {
+    /// Whether images from `source` are cropped
+    pub fn applies_to(&self, source: &str) -> bool {
+        self.enabled && self.sources.iter().any(|wanted| source.contains(wanted.as_str()))
+    }
+}
+
+/// Subdirectories of the screenshot directory images are stored in by source
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SourceSubdirs {
+    pub enabled: bool,
+    /// Subdirectory for each source; other sources stay at the top level
+    pub dirs: HashMap<String, String>,
+}
+
+impl Default for SourceSubdirs {
+    fn default() -> Self {
+        let dirs = [
+            ("clipboard", "clipboard"),
+            ("screenshot", "screenshots"),
+            ("stdin", "stdin"),
+            ("download", "downloads"),
+            ("sink", "sinks"),
+        ];
+        Self {
+            enabled: false,
+            dirs: dirs.into_iter().map(|(source, dir)| (source.to_string(), dir.to_string())).collect(),
+        }
+    }
+}
+
+/// Whether `subdir` is a relative path that stays inside the directory it's joined to
+fn is_plain_subdir(subdir: &str) -> bool {
+    !subdir.is_empty()
+        && Path::new(subdir)
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)))
+}
+
+/// Source of the slug added to stored image filenames
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoSlug {
+    /// Timestamped names only
+    #[default]
+    Off,
+    /// Title of the window focused when the image is stored
+    Window,
+    /// First line of text recognized in the image by `tesseract`
+    Ocr,
+}
+
+/// Handling of images whose content is already stored, see [`crate::dedup`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateMode {
+    /// Hand back the path of the stored copy
+    #[default]
+    Reuse,
+    /// Store a new entry as a reflink or hard link to the stored copy
+    Link,
+}
+
+/// Moving old images to a smaller format with `klipdot freeze`, see [`crate::cold_storage`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColdStorageConfig {
+    /// Age in days after which images are frozen
+    pub after_days: u32,
+    pub format: ColdFormat,
+    /// Quality of lossy conversions, from 0 to 100
+    pub quality: u8,
+}
+
+impl Default for ColdStorageConfig {
+    fn default() -> Self {
+        Self {
+            after_days: 14,
+            format: ColdFormat::default(),
+            quality: 75,
+        }
+    }
+}
+
+/// WebAssembly filters run on each image before it's stored, see `crate::wasm_filter`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FiltersConfig {
+    pub enabled: bool,
+    /// Directory of `.wasm` filter modules, run in name order; `~/.klipdot/filters` when unset
+    pub dir: Option<PathBuf>,
+    /// Instructions, roughly, each filter may run per image before it's stopped
+    pub fuel: u64,
+    /// Most memory each filter may use, in megabytes
+    pub max_memory_mb: u32,
+}
+
+impl Default for FiltersConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dir: None,
+            fuel: 5_000_000_000,
+            max_memory_mb: 512,
+        }
+    }
+}
+
+/// Lua event handler scripts, see `crate::lua_hooks`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScriptsConfig {
+    pub enabled: bool,
+    /// Directory of `.lua` scripts, run in name order; `~/.klipdot/scripts` when unset
+    pub dir: Option<PathBuf>,
+    /// Most memory each script may use, in megabytes
+    pub max_memory_mb: u32,
+    /// Lua instructions each handler call may run before it's stopped
+    pub max_instructions: u64,
+}
+
+impl Default for ScriptsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dir: None,
+            max_memory_mb: 32,
+            max_instructions: 50_000_000,
+        }
+    }
+}
+
+/// Hash-chained log of interceptions, see [`crate::audit`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    /// Log file; `~/.klipdot/audit.jsonl` when unset
+    pub file: Option<PathBuf>,
+}
+
+/// Record of what went through the clipboard, see [`crate::clipboard_history`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    /// Also record text copied to the clipboard
+    pub text: bool,
+    /// Entries kept; the oldest are dropped first
+    pub max_entries: usize,
+    /// History file; `~/.klipdot/history.jsonl` when unset
+    pub file: Option<PathBuf>,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            text: false,
+            max_entries: 1000,
+            file: None,
+        }
+    }
+}
+
+/// Keeping the image a clipboard rewrite replaced, see [`crate::undo`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UndoConfig {
+    pub enabled: bool,
+}
+
+impl Default for UndoConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+/// Largest images decoded, so decompression bombs are refused, see [`crate::decode_guard`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DecodeLimits {
+    /// Width times height, read from the image's header before decoding
+    pub max_pixels: u64,
+    /// Memory a decoder may allocate
+    pub max_memory_mb: u64,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_pixels: 250_000_000,
+            max_memory_mb: 1024,
+        }
+    }
+}
+
+/// Which of the clipboard's types is read, see [`crate::clipboard_types`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClipboardTypesConfig {
+    /// MIME types or X11 targets, most wanted first; `image/*` matches any image
+    pub priority: Vec<String>,
+    /// What is done with content of each type, see [`crate::clipboard_handlers`]
+    pub handlers: HashMap<String, ClipboardAction>,
+}
+
+impl Default for ClipboardTypesConfig {
+    fn default() -> Self {
+        Self {
+            priority: crate::clipboard_types::DEFAULT_PRIORITY.iter().map(|mime| mime.to_string()).collect(),
+            handlers: crate::clipboard_handlers::DEFAULT_HANDLERS
+                .iter()
+                .map(|(mime, action)| (mime.to_string(), *action))
+                .collect(),
+        }
+    }
+}
+
+/// Copied text naming an image file outside the store
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CopiedPathsConfig {
+    pub enabled: bool,
+    pub mode: CopiedPathMode,
+}
+
+/// What a copied image path is turned into
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CopiedPathMode {
+    /// The path of a stored copy, made like a copied image's
+    #[default]
+    Store,
+    /// The same file's absolute path
+    Absolute,
+}
+
+/// Before/after pairs of screenshots taken close together, see [`crate::pairing`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PairingConfig {
+    pub enabled: bool,
+    /// Longest gap between two images from the same source that pairs them
+    pub window_secs: u64,
+    /// Largest difference in any channel that doesn't count as a change
+    pub tolerance: u8,
+}
+
+impl Default for PairingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window_secs: 10,
+            tolerance: crate::image_diff::DEFAULT_TOLERANCE,
+        }
+    }
+}
+
+/// How the clipboard is polled where it can't be watched, see [`crate::poll_schedule`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PollingConfig {
+    /// Poll less often while the clipboard is idle
+    pub adaptive: bool,
+    /// Idle time after which the interval starts doubling
+    pub idle_after_secs: u64,
+    /// Longest interval while idle
+    pub max_interval_ms: u64,
+    /// Interval while a screenshot tool's capture is expected
+    pub screenshot_interval_ms: u64,
+    /// Content handled again within this long counts as the same change
+    pub debounce_ms: u64,
+}
+
+impl Default for PollingConfig {
+    fn default() -> Self {
+        Self {
+            adaptive: true,
+            idle_after_secs: 10,
+            max_interval_ms: 3000,
+            screenshot_interval_ms: 100,
+            debounce_ms: 500,
+        }
+    }
+}
+
+/// The most clipboard content that is read, see [`crate::clipboard_payload`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClipboardLimitsConfig {
+    /// Largest copied text handled, in bytes
+    pub max_text_size: u64,
+    /// Largest copied image or other binary data handled, in bytes
+    pub max_image_size: u64,
+}
+
+impl Default for ClipboardLimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_text_size: 16 * 1024 * 1024,
+            max_image_size: 256 * 1024 * 1024,
+        }
+    }
+}
+
+/// Commands clipboard images are piped through before they're stored, see [`crate::transforms`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransformsConfig {
+    /// Commands run in order, each reading the previous one's image on stdin;
+    /// `{source}` and `{app}` in their arguments are replaced
+    pub commands: Vec<Vec<String>>,
+    /// Longest a command may take before it's skipped
+    pub timeout_secs: u64,
+}
+
+impl Default for TransformsConfig {
+    fn default() -> Self {
+        Self {
+            commands: Vec::new(),
+            timeout_secs: 30,
+        }
+    }
+}
+
+/// Images shared for a limited time, see [`crate::share`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShareConfig {
+    /// Directory shared copies go to, such as a synced folder
+    pub dir: PathBuf,
+}
+
+impl Default for ShareConfig {
+    fn default() -> Self {
+        let home_dir = crate::get_home_dir().unwrap_or_else(|_| std::env::temp_dir().join(".klipdot"));
+        Self { dir: home_dir.join(crate::SHARE_DIR) }
+    }
+}
+
+/// What the clipboard monitor does with content of a type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClipboardAction {
+    /// Store image data and replace it with the stored path
+    Process,
+    /// Store a single copied image file, given as a `text/uri-list`
+    CopyFile,
+    /// Store the first image in copied HTML
+    ExtractImages,
+    /// Keep the text in the clipboard history, when it records text
+    Text,
+    /// Leave the content alone and out of the history
+    Ignore,
+}
+
+/// Clipboard content that is never read, stored or replaced, see [`crate::sensitive`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SensitiveConfig {
+    /// Clipboard types password managers add to mark a secret
+    pub types: Vec<String>,
+    /// Apps whose copies are left alone, matched against the focused app id or window class
+    pub apps: Vec<String>,
+}
+
+impl Default for SensitiveConfig {
+    fn default() -> Self {
+        Self {
+            types: crate::sensitive::DEFAULT_TYPES.iter().map(|mime| mime.to_string()).collect(),
+            apps: crate::sensitive::DEFAULT_APPS.iter().map(|app| app.to_string()).collect(),
+        }
+    }
+}
+
+/// Permissions of the screenshot store, see [`crate::store_permissions`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorePermissions {
+    /// Screenshot directory and the directories made inside it
+    pub dir_mode: FileMode,
+    /// Stored images
+    pub file_mode: FileMode,
+}
+
+impl Default for StorePermissions {
+    fn default() -> Self {
+        Self {
+            dir_mode: FileMode(0o700),
+            file_mode: FileMode(0o600),
+        }
+    }
+}
+
+/// Unix permission bits.
+///
+/// Serialized as an octal string such as `"0640"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct FileMode(pub u32);
+
+impl TryFrom<String> for FileMode {
+    type Error = String;
+    
+    fn try_from(raw: String) -> std::result::Result<Self, Self::Error> {
+        match u32::from_str_radix(raw.trim_start_matches("0o"), 8) {
+            Ok(mode) if mode <= 0o777 => Ok(FileMode(mode)),
+            _ => Err(format!("invalid mode {:?} (expected octal permissions such as \"0600\")", raw)),
+        }
+    }
+}
+
+impl From<FileMode> for String {
+    fn from(mode: FileMode) -> Self {
+        format!("{:04o}", mode.0)
+    }
+}
+
+/// How SVGs are cleaned before they're stored, previewed or uploaded, see [`crate::svg_sanitize`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SvgConfig {
+    pub sanitize: SvgSanitize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SvgSanitize {
+    /// Remove scripts and references outside the document, keeping styles,
+    /// animations and embedded raster images
+    #[default]
+    Standard,
+    /// Also remove styles, animations and every data URI
+    Strict,
+}
+
+/// What frozen images become
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ColdFormat {
+    /// Bundled losslessly into a zstd-compressed tar
+    #[default]
+    Zstd,
+    /// Converted to lossy WebP with `cwebp`
+    Webp,
+    /// Converted to lossy AVIF with `avifenc`
+    Avif,
+}
+
+/// Quoting of image paths put on the clipboard or into command lines, so
+/// ones with spaces or shell metacharacters paste intact
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathQuoting {
+    /// Single quotes, only around paths that need them
+    #[default]
+    Single,
+    /// Backslashes before spaces and shell metacharacters
+    Backslash,
+    /// A percent-encoded `file://` URI
+    Uri,
+    /// The path as is
+    Raw,
+}
+
+/// Form of image paths put on the clipboard or into command lines, see
+/// [`crate::path_format`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathFormat {
+    #[default]
+    Absolute,
+    /// Relative to the working directory
+    Relative,
+    /// Relative to the project (git, Mercurial or Jujutsu) root above the
+    /// working directory
+    Project,
+    /// Starting with `~/` inside the home directory
+    Home,
+    /// A percent-encoded `file://` URI
+    Uri,
+}
+
+/// Which [`PathFormat`] is used where
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PathFormatConfig {
+    pub format: PathFormat,
+    /// Formats for particular tools. Keys are matched case-insensitively
+    /// against the focused app id or window class for clipboard images, and
+    /// against the command name for payloads substituted in a command line.
+    pub profiles: HashMap<String, PathFormat>,
+}
+
+impl PathFormatConfig {
+    /// The format for the app or command `name`; the longest matching
+    /// profile wins
+    pub fn for_name(&self, name: Option<&str>) -> PathFormat {
+        let Some(name) = name.map(str::to_lowercase) else {
+            return self.format;
+        };
+        self.profiles
+            .iter()
+            .filter(|(key, _)| !key.is_empty() && name.contains(&key.to_lowercase()))
+            .max_by_key(|(key, _)| key.len())
+            .map(|(_, format)| *format)
+            .unwrap_or(self.format)
+    }
+}
+
+/// Encoding used for stored images
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Lossless; quality selects the compression effort and, below 50, palette quantization
+    #[default]
+    Png,
+    /// Lossy at the configured quality, without transparency
+    Jpeg,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+        }
+    }
+}
+
+/// Bits per channel of stored images; HDR images are always tone mapped first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BitDepth {
+    /// Keep 16 bits per channel in PNGs; JPEG and palette PNGs are always 8-bit
+    #[default]
+    Preserve,
+    /// Store 8 bits per channel
+    Reduce,
+}
+
+/// Limit on the longest side of stored images.
+///
+/// Serialized as a pixel count or the string `"unlimited"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawMaxDimension", into = "RawMaxDimension")]
+pub enum MaxDimension {
+    Pixels(u32),
+    Unlimited,
+}
+
+impl MaxDimension {
+    /// The limit in pixels, `None` when unlimited
+    pub fn pixels(self) -> Option<u32> {
+        match self {
+            MaxDimension::Pixels(pixels) => Some(pixels),
+            MaxDimension::Unlimited => None,
+        }
+    }
+}
+
+impl Default for MaxDimension {
+    fn default() -> Self {
+        MaxDimension::Pixels(crate::MAX_IMAGE_DIMENSION)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RawMaxDimension {
+    Pixels(u32),
+    Keyword(String),
+}
+
+impl TryFrom<RawMaxDimension> for MaxDimension {
+    type Error = String;
+    
+    fn try_from(raw: RawMaxDimension) -> std::result::Result<Self, Self::Error> {
+        match raw {
+            RawMaxDimension::Pixels(pixels) => Ok(MaxDimension::Pixels(pixels)),
+            RawMaxDimension::Keyword(keyword) if keyword.eq_ignore_ascii_case("unlimited") => Ok(MaxDimension::Unlimited),
+            RawMaxDimension::Keyword(keyword) => Err(format!("invalid max_dimension {:?} (expected pixels or \"unlimited\")", keyword)),
+        }
+    }
+}
+
+impl From<MaxDimension> for RawMaxDimension {
+    fn from(limit: MaxDimension) -> Self {
+        match limit {
+            MaxDimension::Pixels(pixels) => RawMaxDimension::Pixels(pixels),
+            MaxDimension::Unlimited => RawMaxDimension::Keyword("unlimited".to_string()),
+        }
+    }
+}
+
+/// When clipboard interception pauses by itself, see [`crate::pause`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PauseConfig {
+    /// Local-time windows during which interception is paused
+    pub schedules: Vec<QuietWindow>,
+    /// Pause while the focused app id or window class contains one of these
+    pub deny_apps: Vec<String>,
+    /// When non-empty, only intercept while one of these apps is focused
+    pub allow_apps: Vec<String>,
+}
+
+impl Default for PauseConfig {
+    fn default() -> Self {
+        Self {
+            schedules: Vec::new(),
+            // Screen sharing and presentation tools
+            deny_apps: ["zoom", "teams", "webex", "obs", "impress", "keynote", "powerpoint"]
+                .iter()
+                .map(|app| app.to_string())
+                .collect(),
+            allow_apps: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuietWindow {
+    /// Start time, "HH:MM"
+    pub start: String,
+    /// End time, "HH:MM"; earlier than `start` for windows past midnight
+    pub end: String,
+    /// Weekdays ("mon", "tue", ...) the window starts on; every day when empty
+    #[serde(default)]
+    pub days: Vec<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InterceptMethods {
     pub clipboard: bool,
@@ -32,6 +1072,18 @@ pub struct InterceptMethods {
     pub stdin: bool,
     pub file_watch: bool,
     pub process_monitor: bool,
+    /// React to clipboard change events (`wl-paste --watch` on Wayland)
+    /// instead of polling, where they're available
+    #[serde(default = "default_clipboard_events")]
+    pub clipboard_events: bool,
+}
+
+fn default_clipboard_events() -> bool {
+    true
+}
+
+fn default_window_context() -> bool {
+    true
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -40,6 +1092,24 @@ pub struct ShellIntegration {
     pub shells: Vec<String>,
     pub hook_commands: Vec<String>,
     pub aliases: Vec<String>,
+    /// zsh `bindkey` sequence for the preview-under-cursor widget
+    #[serde(default = "default_preview_key")]
+    pub preview_key: String,
+    /// Commands the hooks run under `klipdot monitor-output` when their
+    /// output goes to a terminal, so images they print are previewed. Each
+    /// entry is a program name, optionally followed by leading arguments
+    /// ("cargo test").
+    #[serde(default)]
+    pub monitor_commands: Vec<String>,
+    /// Directories the hooks scan for new images at each prompt, in one
+    /// background `klipdot scan-new`; "." is the current directory. Empty
+    /// turns scanning off.
+    #[serde(default)]
+    pub scan_dirs: Vec<String>,
+}
+
+fn default_preview_key() -> String {
+    "^[i".to_string()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -64,7 +1134,6 @@ pub struct ScreenshotToolsConfig {
     pub wayland_tools: Vec<String>,
     pub x11_tools: Vec<String>,
     pub preferred_tool: Option<String>,
-    pub default_args: std::collections::HashMap<String, Vec<String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -73,10 +1142,38 @@ pub struct Screenshot {
     pub path: PathBuf,
     pub size: u64,
     pub source: String,
+    /// Application the image came from, when it was recorded
+    #[serde(default)]
+    pub app: Option<String>,
+    /// Monitor the screenshot was captured from, when it was recorded
+    #[serde(default)]
+    pub output: Option<String>,
+    /// Original width and height when the image was scaled down to fit `max_dimension`
+    #[serde(default)]
+    pub resized_from: Option<(u32, u32)>,
+    /// Description of the image, when one was generated
+    #[serde(default)]
+    pub alt_text: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Focused window and workspace when the image was intercepted, when recorded
+    #[serde(default)]
+    pub window: Option<crate::focus::WindowContext>,
     pub created_at: DateTime<Utc>,
     pub mime_type: String,
 }
 
+impl Screenshot {
+    /// Whether the source and app contain the given filters, ignoring case
+    pub fn matches(&self, source: Option<&str>, app: Option<&str>) -> bool {
+        let contains = |value: Option<&str>, filter: Option<&str>| match filter {
+            Some(filter) => value.is_some_and(|value| value.to_lowercase().contains(&filter.to_lowercase())),
+            None => true,
+        };
+        contains(Some(&self.source), source) && contains(self.app.as_deref(), app)
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         let home_dir = crate::get_home_dir().unwrap_or_else(|_| {
@@ -93,6 +1190,7 @@ impl Default for Config {
             poll_interval: crate::DEFAULT_POLL_INTERVAL,
             image_formats: crate::SUPPORTED_FORMATS.iter().map(|s| s.to_string()).collect(),
             max_file_size: crate::MAX_FILE_SIZE,
+            source_max_file_size: HashMap::new(),
             compression_quality: crate::IMAGE_QUALITY,
             cleanup_days: crate::DEFAULT_CLEANUP_DAYS,
             enable_logging: true,
@@ -100,6 +1198,45 @@ impl Default for Config {
             intercept_methods: InterceptMethods::default(),
             shell_integration: ShellIntegration::default(),
             display_server: DisplayServerConfig::default(),
+            retry: RetryPolicy::default(),
+            preview: PreviewConfig::default(),
+            processing: ProcessingConfig::default(),
+            fallback_dirs: Vec::new(),
+            pause: PauseConfig::default(),
+            local_time_filenames: false,
+            window_context: true,
+            max_dimension: MaxDimension::default(),
+            output_format: OutputFormat::default(),
+            bit_depth: BitDepth::default(),
+            downloads: DownloadsConfig::default(),
+            auto_slug: AutoSlug::default(),
+            duplicates: DuplicateMode::default(),
+            alt_text: AltTextConfig::default(),
+            url_downloads: UrlDownloadConfig::default(),
+            uploads: UploadConfig::default(),
+            source_subdirs: SourceSubdirs::default(),
+            path_quoting: PathQuoting::default(),
+            path_format: PathFormatConfig::default(),
+            mirror: MirrorConfig::default(),
+            crop_to_window: CropToWindowConfig::default(),
+            cold_storage: ColdStorageConfig::default(),
+            filters: FiltersConfig::default(),
+            scripts: ScriptsConfig::default(),
+            audit: AuditConfig::default(),
+            history: HistoryConfig::default(),
+            svg: SvgConfig::default(),
+            undo: UndoConfig::default(),
+            decode_limits: DecodeLimits::default(),
+            store_permissions: StorePermissions::default(),
+            clipboard_types: ClipboardTypesConfig::default(),
+            sensitive: SensitiveConfig::default(),
+            copied_paths: CopiedPathsConfig::default(),
+            pairing: PairingConfig::default(),
+            share: ShareConfig::default(),
+            polling: PollingConfig::default(),
+            clipboard_limits: ClipboardLimitsConfig::default(),
+            transforms: TransformsConfig::default(),
+            policy: crate::policy::Policy::default(),
             created_at: now,
             updated_at: now,
         }
@@ -115,6 +1252,7 @@ impl Default for InterceptMethods {
             stdin: true,
             file_watch: true,
             process_monitor: true,
+            clipboard_events: true,
         }
     }
 }
@@ -135,6 +1273,9 @@ impl Default for ShellIntegration {
                 "mv".to_string(),
                 "scp".to_string(),
             ],
+            preview_key: default_preview_key(),
+            monitor_commands: Vec::new(),
+            scan_dirs: Vec::new(),
         }
     }
 }
@@ -154,7 +1295,9 @@ impl Default for DisplayServerConfig {
 
 impl Default for ClipboardToolsConfig {
     fn default() -> Self {
+        #[allow(unused_mut)]
         let mut wayland_tools = crate::WAYLAND_CLIPBOARD_TOOLS.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        #[allow(unused_mut)]
         let mut x11_tools = crate::X11_CLIPBOARD_TOOLS.iter().map(|s| s.to_string()).collect::<Vec<_>>();
         
         // Add macOS tools to both lists for compatibility
@@ -178,26 +1321,9 @@ impl Default for ClipboardToolsConfig {
 
 impl Default for ScreenshotToolsConfig {
     fn default() -> Self {
-        let mut default_args = std::collections::HashMap::new();
-        
-        // Wayland tools
-        default_args.insert("grim".to_string(), vec!["-".to_string()]);
-        default_args.insert("wayshot".to_string(), vec!["--stdout".to_string()]);
-        default_args.insert("grimshot".to_string(), vec!["copy".to_string(), "screen".to_string()]);
-        default_args.insert("spectacle".to_string(), vec!["-b".to_string(), "-n".to_string()]);
-        default_args.insert("flameshot".to_string(), vec!["gui".to_string()]);
-        
-        // X11 tools
-        default_args.insert("scrot".to_string(), vec!["-".to_string()]);
-        default_args.insert("gnome-screenshot".to_string(), vec!["-f".to_string(), "-".to_string()]);
-        default_args.insert("import".to_string(), vec!["-window".to_string(), "root".to_string(), "-".to_string()]);
-        default_args.insert("xfce4-screenshooter".to_string(), vec!["-f".to_string(), "-s".to_string()]);
-        
-        // macOS tools
-        default_args.insert("screencapture".to_string(), vec!["-c".to_string()]);
-        default_args.insert("screenshot".to_string(), vec![]);
-        
+        #[allow(unused_mut)]
         let mut wayland_tools = crate::WAYLAND_SCREENSHOT_TOOLS.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        #[allow(unused_mut)]
         let mut x11_tools = crate::X11_SCREENSHOT_TOOLS.iter().map(|s| s.to_string()).collect::<Vec<_>>();
         
         // Add macOS tools to both lists for compatibility
@@ -215,7 +1341,6 @@ impl Default for ScreenshotToolsConfig {
             } else {
                 "grim".to_string()
             }),
-            default_args
         }
     }
 }
@@ -229,27 +1354,42 @@ impl Config {
         } else {
             let config = Self::default();
             config.save()?;
-            Ok(config)
+            crate::policy::Policy::system()?.apply(config)
         }
     }
     
-    pub fn load_from_path(path: &PathBuf) -> Result<Self> {
+    pub fn load_from_path(path: &Path) -> Result<Self> {
+        // The system policy wins over whatever the user set
+        let config = crate::policy::Policy::system()?.apply(Self::load_user_config(path)?)?;
+        
+        // Ensure directories exist
+        crate::store_permissions::create_dir_blocking(&config.screenshot_dir, &config.store_permissions)?;
+        
+        info!("Config loaded successfully");
+        Ok(config)
+    }
+    
+    /// The user's own settings in the config file at `path`, without the
+    /// system policy; what changes to the config are made to and saved from
+    pub fn load_user_config(path: &Path) -> Result<Self> {
         debug!("Loading config from: {:?}", path);
         
         let content = std::fs::read_to_string(path)?;
         let mut config: Config = serde_json::from_str(&content)?;
         
         // Update the config file path to the one we loaded from
-        config.config_file = path.clone();
-        
-        // Ensure directories exist
-        std::fs::create_dir_all(&config.screenshot_dir)?;
-        
-        info!("Config loaded successfully");
+        config.config_file = path.to_path_buf();
         Ok(config)
     }
     
+    /// Write the config to its file. A config the system policy was applied
+    /// to is refused, so enforced settings never replace the user's own.
     pub fn save(&self) -> Result<()> {
+        if !self.policy.is_empty() {
+            return Err(Error::Config(
+                "Refusing to save a config with the system policy applied; save the user's own settings instead".to_string(),
+            ));
+        }
         debug!("Saving config to: {:?}", self.config_file);
         
         // Ensure parent directory exists
@@ -295,42 +1435,115 @@ impl Config {
         self.image_formats.contains(&extension.to_lowercase())
     }
     
-    pub fn get_screenshot_path(&self, filename: &str) -> PathBuf {
-        self.screenshot_dir.join(filename)
+    /// Where an image from `source` named `filename` is stored
+    pub fn get_screenshot_path(&self, source: &str, filename: &str) -> PathBuf {
+        match self.source_subdir(source) {
+            Some(subdir) => self.screenshot_dir.join(subdir).join(filename),
+            None => self.screenshot_dir.join(filename),
+        }
     }
     
-    pub async fn get_recent_screenshots(&self, limit: usize) -> Result<Vec<Screenshot>> {
-        let mut screenshots = Vec::new();
+    /// Subdirectory images from `source` are stored in, when enabled. Names
+    /// that would leave the screenshot directory are ignored.
+    pub fn source_subdir(&self, source: &str) -> Option<&str> {
+        if !self.source_subdirs.enabled {
+            return None;
+        }
+        self.source_subdirs.dirs.get(source).map(String::as_str).filter(|subdir| is_plain_subdir(subdir))
+    }
+    
+    /// `base` and the source subdirectories in it that exist. Subdirectories
+    /// are included even while disabled, so images stored earlier are found.
+    pub fn dirs_in(&self, base: &Path) -> Vec<PathBuf> {
+        let mut subdirs: Vec<&String> = self.source_subdirs.dirs.values().filter(|subdir| is_plain_subdir(subdir)).collect();
+        subdirs.sort();
+        subdirs.dedup();
         
-        if !self.screenshot_dir.exists() {
-            return Ok(screenshots);
+        let mut dirs = vec![base.to_path_buf()];
+        dirs.extend(subdirs.into_iter().map(|subdir| base.join(subdir)).filter(|dir| dir.is_dir()));
+        dirs
+    }
+    
+    /// The screenshot directory and its source subdirectories
+    pub fn screenshot_dirs(&self) -> Vec<PathBuf> {
+        self.dirs_in(&self.screenshot_dir)
+    }
+    
+    /// Directories intercepted images may be written to, most preferred first:
+    /// the screenshot directory, the configured fallbacks, then the runtime
+    /// directory (usually tmpfs) and the system temp directory
+    pub fn storage_dirs(&self) -> Vec<PathBuf> {
+        let mut candidates = vec![self.screenshot_dir.clone()];
+        candidates.extend(self.fallback_dirs.iter().cloned());
+        if let Some(runtime_dir) = dirs::runtime_dir() {
+            candidates.push(runtime_dir.join(crate::APP_NAME).join(crate::SCREENSHOT_DIR));
+        }
+        candidates.push(std::env::temp_dir().join(crate::APP_NAME).join(crate::SCREENSHOT_DIR));
+        
+        let mut seen = std::collections::HashSet::new();
+        candidates.retain(|dir| seen.insert(dir.clone()));
+        candidates
+    }
+    
+    /// The `limit` most recently modified screenshots, newest first, from the
+    /// screenshot directory and its source subdirectories.
+    ///
+    /// Only the newest `limit` entries are kept while scanning, so large
+    /// directories cost one metadata call per file. Files that vanish or
+    /// become unreadable mid-scan are skipped.
+    pub async fn get_recent_screenshots(&self, limit: usize) -> Result<Vec<Screenshot>> {
+        if limit == 0 || !self.screenshot_dir.exists() {
+            return Ok(Vec::new());
         }
         
-        let mut entries = tokio::fs::read_dir(&self.screenshot_dir).await?;
-        let mut files = Vec::new();
+        // Min-heap on modification time: the oldest kept entry is evicted first
+        let mut newest: BinaryHeap<Reverse<(SystemTime, PathBuf)>> = BinaryHeap::new();
         
-        while let Some(entry) = entries.next_entry().await? {
-            let path = entry.path();
-            if path.is_file() {
-                if let Some(ext) = path.extension() {
-                    if let Some(ext_str) = ext.to_str() {
-                        if self.is_image_format_supported(ext_str) {
-                            files.push(path);
-                        }
-                    }
+        for dir in self.screenshot_dirs() {
+            let mut entries = tokio::fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                let supported = path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| self.is_image_format_supported(ext));
+                if !supported {
+                    continue;
+                }
+                
+                let Ok(metadata) = tokio::fs::metadata(&path).await else {
+                    continue;
+                };
+                if !metadata.is_file() {
+                    continue;
+                }
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                
+                if newest.len() < limit {
+                    newest.push(Reverse((modified, path)));
+                } else if newest.peek().is_some_and(|Reverse((oldest, _))| modified > *oldest) {
+                    newest.pop();
+                    newest.push(Reverse((modified, path)));
                 }
             }
         }
         
-        // Sort by modification time (newest first)
-        files.sort_by(|a, b| {
-            let a_meta = std::fs::metadata(a).unwrap();
-            let b_meta = std::fs::metadata(b).unwrap();
-            b_meta.modified().unwrap().cmp(&a_meta.modified().unwrap())
-        });
+        // Each directory keeps its own index
+        let mut indexes: HashMap<PathBuf, HashMap<String, ImageMetadata>> = HashMap::new();
         
-        for file in files.iter().take(limit) {
-            if let Ok(screenshot) = self.create_screenshot_info(file).await {
+        // Ascending order of Reverse is newest first
+        let mut screenshots = Vec::with_capacity(newest.len());
+        for Reverse((_, path)) in newest.into_sorted_vec() {
+            let dir = path.parent().unwrap_or(&self.screenshot_dir).to_path_buf();
+            if !indexes.contains_key(&dir) {
+                let index = crate::metadata::load(&dir).await.unwrap_or_else(|e| {
+                    debug!("Failed to read metadata index: {}", e);
+                    Default::default()
+                });
+                indexes.insert(dir.clone(), index);
+            }
+            let recorded = path.file_name().and_then(|name| indexes[&dir].get(name.to_string_lossy().as_ref()));
+            if let Ok(screenshot) = self.create_screenshot_info(&path, recorded).await {
                 screenshots.push(screenshot);
             }
         }
@@ -338,28 +1551,52 @@ impl Config {
         Ok(screenshots)
     }
     
+    /// Delete images older than `days`, or the policy's retention maximum when that's shorter
     pub async fn cleanup_old_screenshots(&self, days: u32) -> Result<usize> {
-        let cutoff = Utc::now() - chrono::Duration::days(days as i64);
+        let cutoff = Utc::now() - chrono::Duration::days(self.policy.retention(days) as i64);
+        // Past the policy's maximum, scripts can't keep an image
+        #[cfg(feature = "lua-hooks")]
+        let enforced = self.policy.max_retention_days.map(|max| Utc::now() - chrono::Duration::days(max as i64));
         let mut count = 0;
         
         if !self.screenshot_dir.exists() {
             return Ok(count);
         }
         
-        let mut entries = tokio::fs::read_dir(&self.screenshot_dir).await?;
-        
-        while let Some(entry) = entries.next_entry().await? {
-            let path = entry.path();
-            if path.is_file() {
-                if let Ok(metadata) = std::fs::metadata(&path) {
-                    if let Ok(modified) = metadata.modified() {
-                        let modified_utc = DateTime::<Utc>::from(modified);
-                        if modified_utc < cutoff {
-                            if let Err(e) = tokio::fs::remove_file(&path).await {
-                                tracing::warn!("Failed to remove old screenshot {:?}: {}", path, e);
-                            } else {
-                                count += 1;
-                                debug!("Removed old screenshot: {:?}", path);
+        #[cfg(feature = "lua-hooks")]
+        let scripts = crate::lua_hooks::Scripts::load(self, crate::command_runner::system());
+        
+        for dir in self.screenshot_dirs() {
+            let mut entries = tokio::fs::read_dir(&dir).await?;
+            #[cfg(feature = "lua-hooks")]
+            let index = match scripts.is_empty() {
+                true => HashMap::new(),
+                false => crate::metadata::load(&dir).await.unwrap_or_default(),
+            };
+            
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if path.is_file() {
+                    if let Ok(metadata) = std::fs::metadata(&path) {
+                        if let Ok(modified) = metadata.modified() {
+                            let modified_utc = DateTime::<Utc>::from(modified);
+                            #[cfg(feature = "lua-hooks")]
+                            if modified_utc < cutoff && !scripts.is_empty() && enforced.is_none_or(|enforced| modified_utc >= enforced) {
+                                let recorded = index.get(entry.file_name().to_string_lossy().as_ref());
+                                if let Ok(screenshot) = self.create_screenshot_info(&path, recorded).await {
+                                    if !scripts.allows_cleanup(&crate::lua_hooks::ImageInfo::from(&screenshot)).await {
+                                        debug!("A script keeps {:?}", path);
+                                        continue;
+                                    }
+                                }
+                            }
+                            if modified_utc < cutoff {
+                                if let Err(e) = tokio::fs::remove_file(&path).await {
+                                    tracing::warn!("Failed to remove old screenshot {:?}: {}", path, e);
+                                } else {
+                                    count += 1;
+                                    debug!("Removed old screenshot: {:?}", path);
+                                }
                             }
                         }
                     }
@@ -371,8 +1608,8 @@ impl Config {
         Ok(count)
     }
     
-    async fn create_screenshot_info(&self, path: &PathBuf) -> Result<Screenshot> {
-        let metadata = std::fs::metadata(path)?;
+    async fn create_screenshot_info(&self, path: &Path, recorded: Option<&ImageMetadata>) -> Result<Screenshot> {
+        let metadata = tokio::fs::metadata(path).await?;
         let filename = path.file_name()
             .ok_or_else(|| Error::Format("Invalid filename".to_string()))?
             .to_string_lossy()
@@ -382,7 +1619,9 @@ impl Config {
             std::time::SystemTime::now()
         }));
         
-        let source = if filename.contains("clipboard") {
+        let source = if let Some(recorded) = recorded {
+            recorded.source.as_str()
+        } else if filename.contains("clipboard") {
             "clipboard"
         } else if filename.contains("terminal") {
             "terminal"
@@ -410,14 +1649,25 @@ impl Config {
         
         Ok(Screenshot {
             filename,
-            path: path.clone(),
+            path: path.to_path_buf(),
             size: metadata.len(),
             source,
+            app: recorded.and_then(|recorded| recorded.app.clone()),
+            output: recorded.and_then(|recorded| recorded.output.clone()),
+            resized_from: recorded.and_then(|recorded| recorded.resized_from),
+            alt_text: recorded.and_then(|recorded| recorded.alt_text.clone()),
+            tags: recorded.map(|recorded| recorded.tags.clone()).unwrap_or_default(),
+            window: recorded.and_then(|recorded| recorded.window.clone()),
             created_at,
             mime_type,
         })
     }
     
+    /// Size above which images from `source` are downscaled while decoding
+    pub fn max_file_size_for(&self, source: &str) -> u64 {
+        self.source_max_file_size.get(source).copied().unwrap_or(self.max_file_size)
+    }
+    
     pub fn validate(&self) -> Result<()> {
         if self.poll_interval < 100 {
             return Err(Error::Validation("Poll interval must be at least 100ms".to_string()));
@@ -427,14 +1677,54 @@ impl Config {
             return Err(Error::Validation("Max file size must be at least 1KB".to_string()));
         }
         
+        if let Some(source) = self.source_max_file_size.iter().find(|(_, size)| **size < 1024).map(|(source, _)| source) {
+            return Err(Error::Validation(format!("Max file size for {} must be at least 1KB", source)));
+        }
+        
         if self.compression_quality > 100 {
             return Err(Error::Validation("Compression quality must be between 0-100".to_string()));
         }
         
+        if self.output_format == OutputFormat::Jpeg && !cfg!(feature = "codecs") {
+            return Err(Error::Validation("JPEG output requires a build with the codecs feature".to_string()));
+        }
+        
+        if self.max_dimension == MaxDimension::Pixels(0) {
+            return Err(Error::Validation("Max dimension must be greater than 0 (or \"unlimited\")".to_string()));
+        }
+        
         if self.cleanup_days == 0 {
             return Err(Error::Validation("Cleanup days must be greater than 0".to_string()));
         }
         
+        if self.polling.screenshot_interval_ms == 0 {
+            return Err(Error::Validation("polling.screenshot_interval_ms must be greater than 0".to_string()));
+        }
+        
+        if self.clipboard_limits.max_text_size < 1024 || self.clipboard_limits.max_image_size < 1024 {
+            return Err(Error::Validation("clipboard_limits sizes must be at least 1KB".to_string()));
+        }
+        
+        if !self.transforms.commands.is_empty() && self.transforms.timeout_secs == 0 {
+            return Err(Error::Validation("transforms.timeout_secs must be greater than 0".to_string()));
+        }
+        
+        if self.pairing.enabled && self.pairing.window_secs == 0 {
+            return Err(Error::Validation("pairing.window_secs must be greater than 0".to_string()));
+        }
+        
+        for window in &self.pause.schedules {
+            crate::pause::validate_window(window)?;
+        }
+        
+        // KlipDot itself has to be able to use the store
+        if self.store_permissions.dir_mode.0 & 0o700 != 0o700 {
+            return Err(Error::Validation("store_permissions.dir_mode must give the owner rwx (0700)".to_string()));
+        }
+        if self.store_permissions.file_mode.0 & 0o600 != 0o600 {
+            return Err(Error::Validation("store_permissions.file_mode must give the owner rw (0600)".to_string()));
+        }
+        
         Ok(())
     }
     
@@ -483,6 +1773,16 @@ impl Config {
             }
         }
         
+        // Termux has no display server; Termux:API reaches Android's clipboard
+        if crate::termux::is_termux() {
+            for tool in [crate::termux::CLIPBOARD_GET, crate::termux::CLIPBOARD_SET] {
+                if crate::is_command_available(tool) {
+                    tools.push(tool.to_string());
+                }
+            }
+            return tools;
+        }
+        
         // Get tools based on display server
         match self.get_display_server() {
             crate::DisplayServer::Wayland => {
@@ -585,13 +1885,6 @@ impl Config {
         
         tools
     }
-    
-    pub fn get_screenshot_tool_args(&self, tool: &str) -> Vec<String> {
-        self.display_server.screenshot_tools.default_args
-            .get(tool)
-            .cloned()
-            .unwrap_or_default()
-    }
 }
 
 #[cfg(test)]
@@ -629,6 +1922,23 @@ mod tests {
         // Invalid cleanup days
         config.cleanup_days = 0;
         assert!(config.validate().is_err());
+        config.cleanup_days = 30;
+        
+        // Store permissions KlipDot couldn't use itself
+        config.store_permissions.file_mode = FileMode(0o400);
+        assert!(config.validate().is_err());
+        config.store_permissions.file_mode = FileMode(0o640);
+        assert!(config.validate().is_ok());
+    }
+    
+    #[test]
+    fn test_file_mode_serialization() {
+        let permissions: StorePermissions = serde_json::from_str(r#"{"file_mode": "0640"}"#).unwrap();
+        assert_eq!(permissions.file_mode, FileMode(0o640));
+        assert_eq!(permissions.dir_mode, FileMode(0o700));
+        assert_eq!(serde_json::to_string(&FileMode(0o600)).unwrap(), r#""0600""#);
+        assert!(serde_json::from_str::<FileMode>(r#""0999""#).is_err());
+        assert!(serde_json::from_str::<FileMode>(r#""10000""#).is_err());
     }
     
     #[tokio::test]
@@ -636,9 +1946,11 @@ mod tests {
         let temp_dir = TempDir::new().unwrap();
         let config_path = temp_dir.path().join("config.json");
         
-        let mut config = Config::default();
-        config.config_file = config_path.clone();
-        config.enabled = false;
+        let config = Config {
+            config_file: config_path.clone(),
+            enabled: false,
+            ..Config::default()
+        };
         
         assert!(config.save().is_ok());
         assert!(config_path.exists());
@@ -648,6 +1960,30 @@ mod tests {
         assert_eq!(loaded_config.config_file, config_path);
     }
     
+    #[test]
+    fn test_policy_stays_out_of_saved_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.json");
+        let uploads = UploadConfig { strip_metadata: false, ..UploadConfig::default() };
+        Config { config_file: config_path.clone(), cleanup_days: 30, uploads, ..Config::default() }.save().unwrap();
+        let policy_path = temp_dir.path().join("policy.json");
+        std::fs::write(&policy_path, r#"{"require_strip_metadata": true, "max_retention_days": 7}"#).unwrap();
+        let policy = crate::policy::Policy::load(&policy_path).unwrap();
+        
+        let enforced = policy.apply(Config::load_user_config(&config_path).unwrap()).unwrap();
+        assert_eq!(enforced.cleanup_days, 7);
+        assert!(enforced.save().is_err());
+        
+        // Load, change and save the user's settings: the policy's don't end up in them
+        let mut user = Config::load_user_config(&config_path).unwrap();
+        user.enabled = false;
+        user.save().unwrap();
+        let reloaded = Config::load_user_config(&config_path).unwrap();
+        assert_eq!(reloaded.cleanup_days, 30);
+        assert!(!reloaded.uploads.strip_metadata);
+        assert!(!reloaded.enabled);
+    }
+    
     #[test]
     fn test_image_format_support() {
         let config = Config::default();
@@ -657,4 +1993,169 @@ mod tests {
         assert!(!config.is_image_format_supported("txt"));
         assert!(!config.is_image_format_supported("exe"));
     }
-}
\ No newline at end of file
+    
+    #[tokio::test]
+    async fn test_recent_screenshots_newest_first() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        
+        let base = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        for (i, name) in ["b.png", "a.png", "d.jpg", "c.png"].iter().enumerate() {
+            let file = std::fs::File::create(temp_dir.path().join(name)).unwrap();
+            file.set_modified(base + std::time::Duration::from_secs(i as u64 * 60)).unwrap();
+        }
+        std::fs::write(temp_dir.path().join("notes.txt"), b"text").unwrap();
+        std::fs::create_dir(temp_dir.path().join("folder.png")).unwrap();
+        
+        let names = |screenshots: Vec<Screenshot>| -> Vec<String> {
+            screenshots.into_iter().map(|s| s.filename).collect()
+        };
+        assert_eq!(names(config.get_recent_screenshots(2).await.unwrap()), ["c.png", "d.jpg"]);
+        assert_eq!(names(config.get_recent_screenshots(10).await.unwrap()), ["c.png", "d.jpg", "a.png", "b.png"]);
+        assert!(config.get_recent_screenshots(0).await.unwrap().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_source_subdirs_are_traversed() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        std::fs::create_dir(temp_dir.path().join("clipboard")).unwrap();
+        std::fs::create_dir(temp_dir.path().join("temp")).unwrap();
+        
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(10 * 24 * 3600);
+        for name in ["top.png", "clipboard/copied.png", "temp/scratch.png"] {
+            let file = std::fs::File::create(temp_dir.path().join(name)).unwrap();
+            file.set_modified(old).unwrap();
+        }
+        crate::metadata::record(&temp_dir.path().join("clipboard"), &ImageMetadata {
+            filename: "copied.png".to_string(),
+            source: "clipboard".to_string(),
+            app: Some("firefox".to_string()),
+            output: None,
+            resized_from: None,
+            hash: None,
+            alt_text: None,
+            tags: Vec::new(),
+            window: None,
+            cold: None,
+        }).await.unwrap();
+        
+        // Subdirectories are read even while storing into them is disabled
+        let screenshots = config.get_recent_screenshots(10).await.unwrap();
+        let mut names: Vec<_> = screenshots.iter().map(|s| s.filename.as_str()).collect();
+        names.sort();
+        assert_eq!(names, ["copied.png", "top.png"]);
+        let copied = screenshots.iter().find(|s| s.filename == "copied.png").unwrap();
+        assert_eq!(copied.app.as_deref(), Some("firefox"));
+        
+        assert_eq!(config.cleanup_old_screenshots(5).await.unwrap(), 2);
+        assert!(temp_dir.path().join("temp/scratch.png").exists());
+    }
+    
+    #[tokio::test]
+    async fn test_recent_screenshots_use_metadata_index() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        std::fs::write(temp_dir.path().join("wayland-screenshot-1.png"), b"png").unwrap();
+        std::fs::write(temp_dir.path().join("clipboard-2.png"), b"png").unwrap();
+        crate::metadata::record(temp_dir.path(), &ImageMetadata {
+            filename: "wayland-screenshot-1.png".to_string(),
+            source: "wayland-screenshot".to_string(),
+            app: Some("grimshot".to_string()),
+            output: Some("DP-1".to_string()),
+            resized_from: None,
+            hash: None,
+            alt_text: None,
+            tags: Vec::new(),
+            window: None,
+            cold: None,
+        }).await.unwrap();
+        
+        let screenshots = config.get_recent_screenshots(10).await.unwrap();
+        assert_eq!(screenshots.len(), 2);
+        let shot = screenshots.iter().find(|s| s.filename == "wayland-screenshot-1.png").unwrap();
+        assert_eq!(shot.source, "wayland-screenshot");
+        assert_eq!(shot.app.as_deref(), Some("grimshot"));
+        assert_eq!(shot.output.as_deref(), Some("DP-1"));
+        assert!(shot.matches(Some("wayland"), Some("GRIM")));
+        assert!(!shot.matches(None, Some("firefox")));
+        
+        // Without an index entry the source comes from the filename
+        let copied = screenshots.iter().find(|s| s.filename == "clipboard-2.png").unwrap();
+        assert_eq!(copied.source, "clipboard");
+        assert!(copied.matches(Some("clip"), None));
+        assert!(!copied.matches(None, Some("grim")));
+    }
+    
+    #[test]
+    fn test_max_dimension_serialization() {
+        assert_eq!(serde_json::to_string(&MaxDimension::Pixels(3840)).unwrap(), "3840");
+        assert_eq!(serde_json::to_string(&MaxDimension::Unlimited).unwrap(), r#""unlimited""#);
+        assert_eq!(serde_json::from_str::<MaxDimension>("8000").unwrap(), MaxDimension::Pixels(8000));
+        assert_eq!(serde_json::from_str::<MaxDimension>(r#""Unlimited""#).unwrap(), MaxDimension::Unlimited);
+        assert!(serde_json::from_str::<MaxDimension>(r#""huge""#).is_err());
+        
+        let config = Config {
+            max_dimension: MaxDimension::Pixels(0),
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+    }
+    
+    #[test]
+    fn test_auto_preview_lists() {
+        let command = |line: &str| -> Vec<String> { line.split_whitespace().map(String::from).collect() };
+        let mut preview = PreviewConfig {
+            deny_commands: vec!["cargo test".to_string(), "make".to_string()],
+            ..PreviewConfig::default()
+        };
+        assert!(preview.auto_preview_for(&command("ls -la")));
+        assert!(preview.auto_preview_for(&command("cargo run")));
+        assert!(!preview.auto_preview_for(&command("cargo test --release")));
+        assert!(!preview.auto_preview_for(&command("/usr/bin/make all")));
+        
+        preview.allow_commands = vec!["ranger".to_string()];
+        assert!(preview.auto_preview_for(&command("ranger ~/Pictures")));
+        assert!(!preview.auto_preview_for(&command("ls")));
+        
+        preview.auto_preview = false;
+        assert!(!preview.auto_preview_for(&command("ranger")));
+    }
+    
+    #[test]
+    fn test_preview_sizes() {
+        let mut preview = PreviewConfig::default();
+        assert_eq!(preview.size(), (Some(40), Some(20)));
+        assert_eq!(preview.max_size(), (Some(80), Some(40)));
+        assert_eq!(preview.clamp(Some(200), None), (Some(80), None));
+        
+        // The automatic size never exceeds the maximum
+        preview.width = 120;
+        assert_eq!(preview.size(), (Some(80), Some(20)));
+        
+        let parsed: PreviewConfig = serde_json::from_str(r#"{"method": "compact", "on_intercept": true}"#).unwrap();
+        assert_eq!(parsed.method, PreviewMethod::Compact);
+        assert!(parsed.on_intercept);
+        assert_eq!(parsed.width, 40);
+    }
+    
+    #[test]
+    fn test_path_format_profiles() {
+        let parsed: PathFormatConfig =
+            serde_json::from_str(r#"{"format": "home", "profiles": {"code": "relative", "Code-Insiders": "uri"}}"#).unwrap();
+        assert_eq!(parsed.for_name(None), PathFormat::Home);
+        assert_eq!(parsed.for_name(Some("firefox")), PathFormat::Home);
+        assert_eq!(parsed.for_name(Some("code")), PathFormat::Relative);
+        // The longest matching profile wins
+        assert_eq!(parsed.for_name(Some("code-insiders")), PathFormat::Uri);
+    }
+}
@@ -0,0 +1,143 @@
+//! Refusing decompression bombs: images whose few compressed bytes decode to
+//! billions of pixels, enough to exhaust the daemon's memory.
+//!
+//! The dimensions in an image's header are checked against
+//! `decode_limits.max_pixels` before anything is decoded, and decoders are
+//! held to `decode_limits.max_memory_mb` as they allocate. Rejections are
+//! [`Error::Validation`] errors naming the limit, so `klipdot status` shows
+//! why an image was skipped.
+
+use crate::{config::DecodeLimits, error::Result, Error};
+use image::{io::Reader, DynamicImage, ImageError};
+use std::io::{BufRead, Cursor, Seek};
+use std::path::Path;
+
+/// Refuse an image of `width` by `height` if it has more pixels than `limits` allow
+pub fn check_dimensions(width: u32, height: u32, limits: &DecodeLimits) -> Result<()> {
+    let pixels = width as u64 * height as u64;
+    if pixels > limits.max_pixels {
+        return Err(Error::Validation(format!(
+            "Image is {}x{} ({} pixels), more than decode_limits.max_pixels ({})",
+            width, height, pixels, limits.max_pixels
+        )));
+    }
+    Ok(())
+}
+
+/// Refuse the image in `data` if its header declares too many pixels. Data
+/// whose dimensions can't be read is left for the decoder to reject.
+pub fn check(data: &[u8], limits: &DecodeLimits) -> Result<()> {
+    check_reader(Reader::new(Cursor::new(data)), limits)
+}
+
+/// [`check`] for the image file at `path`, reading only its header
+pub fn check_file(path: &Path, limits: &DecodeLimits) -> Result<()> {
+    check_reader(Reader::open(path)?, limits)
+}
+
+/// Decode `data` within `limits`
+pub fn load(data: &[u8], limits: &DecodeLimits) -> Result<DynamicImage> {
+    check(data, limits)?;
+    decode(Reader::new(Cursor::new(data)), limits)
+}
+
+/// Decode the image file at `path` within `limits`
+pub fn open(path: &Path, limits: &DecodeLimits) -> Result<DynamicImage> {
+    check_file(path, limits)?;
+    decode(Reader::open(path)?, limits)
+}
+
+fn check_reader<R: BufRead + Seek>(reader: Reader<R>, limits: &DecodeLimits) -> Result<()> {
+    match reader.with_guessed_format()?.into_dimensions() {
+        Ok((width, height)) => check_dimensions(width, height, limits),
+        Err(_) => Ok(()),
+    }
+}
+
+fn decode<R: BufRead + Seek>(reader: Reader<R>, limits: &DecodeLimits) -> Result<DynamicImage> {
+    let mut reader = reader.with_guessed_format()?;
+    let mut decoder_limits = image::io::Limits::default();
+    decoder_limits.max_alloc = Some(limits.max_memory_mb.saturating_mul(1024 * 1024));
+    reader.limits(decoder_limits);
+    reader.decode().map_err(|e| match e {
+        ImageError::Limits(_) => Error::Validation(format!(
+            "Decoding the image needs more than decode_limits.max_memory_mb ({} MB)",
+            limits.max_memory_mb
+        )),
+        e => Error::Image(e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, RgbaImage};
+
+    /// A PNG declaring `width` by `height`, with only the start of its pixel data
+    fn png_bomb(width: u32, height: u32) -> Vec<u8> {
+        let mut ihdr = width.to_be_bytes().to_vec();
+        ihdr.extend(height.to_be_bytes());
+        ihdr.extend([8, 6, 0, 0, 0]);
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (kind, data) in [(b"IHDR", &ihdr[..]), (b"IDAT", &[0x78, 0x9c][..]), (b"IEND", &[][..])] {
+            png.extend((data.len() as u32).to_be_bytes());
+            let chunk = [&kind[..], data].concat();
+            png.extend(&chunk);
+            png.extend(crc32(&chunk).to_be_bytes());
+        }
+        png
+    }
+
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in data {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            }
+        }
+        !crc
+    }
+
+    fn encode(width: u32, height: u32) -> Vec<u8> {
+        let mut png = Vec::new();
+        DynamicImage::ImageRgba8(RgbaImage::new(width, height))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn test_refuses_too_many_pixels() {
+        let limits = DecodeLimits::default();
+        let bomb = png_bomb(100_000, 100_000);
+        let error = load(&bomb, &limits).unwrap_err();
+        assert!(matches!(error, Error::Validation(_)));
+        assert!(error.to_string().contains("100000x100000"), "{}", error);
+
+        let small = encode(4, 3);
+        assert!(check(&small, &limits).is_ok());
+        assert_eq!(load(&small, &limits).unwrap().width(), 4);
+        assert!(load(&small, &DecodeLimits { max_pixels: 11, ..limits.clone() }).is_err());
+        // Not an image: left for the decoder to reject
+        assert!(check(b"not an image", &limits).is_ok());
+    }
+
+    #[test]
+    fn test_refuses_too_much_memory() {
+        let limits = DecodeLimits { max_memory_mb: 1, ..DecodeLimits::default() };
+        // 600x600 RGBA is about 1.4 MB once decoded
+        let error = load(&encode(600, 600), &limits).unwrap_err();
+        assert!(error.to_string().contains("max_memory_mb"), "{}", error);
+        assert!(load(&encode(400, 400), &limits).is_ok());
+    }
+
+    #[test]
+    fn test_open_checks_header() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let bomb = temp_dir.path().join("bomb.png");
+        std::fs::write(&bomb, png_bomb(100_000, 100_000)).unwrap();
+        assert!(matches!(open(&bomb, &DecodeLimits::default()), Err(Error::Validation(_))));
+        assert!(matches!(check_file(&bomb, &DecodeLimits::default()), Err(Error::Validation(_))));
+    }
+}
@@ -0,0 +1,217 @@
+//! Content-hash gate so every image is stored once.
+//!
+//! The clipboard monitor, file watcher, process monitor and shell hooks can
+//! each notice the same new image. Every ingestion path hashes the incoming
+//! bytes, holds [`lock`] for that hash while it checks the metadata index for
+//! an earlier copy, and only stores the image when there is none. The index
+//! records the hash, so the check also holds across processes and restarts;
+//! it's SHA-256, which unlike std's hashers stays the same across Rust
+//! releases.
+//!
+//! With `"duplicates": "link"`, content captured again later, as in a loop
+//! taking the same screenshot, gets an index entry and filename of its own
+//! that shares the stored data: a copy-on-write clone where the filesystem
+//! supports one (Btrfs, XFS, APFS), a hard link otherwise. Detections within
+//! [`SAME_CAPTURE_WINDOW`] of the newest copy are still taken as the same
+//! capture seen by several detectors.
+
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncReadExt;
+use tokio::sync::OwnedMutexGuard;
+use tracing::debug;
+
+/// How long after an image is stored its content counts as the same capture
+pub const SAME_CAPTURE_WINDOW: Duration = Duration::from_secs(10);
+
+/// How a duplicate shares the stored data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    /// Copy-on-write clone: a separate file until either is written
+    Reflink,
+    /// Another name for the same file
+    Hardlink,
+}
+
+/// Incremental content hash: hex SHA-256, stable across processes,
+/// platforms and toolchains
+pub struct ContentHasher {
+    digest: Sha256,
+}
+
+impl ContentHasher {
+    pub fn new() -> Self {
+        Self { digest: Sha256::new() }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.digest.update(data);
+    }
+
+    pub fn finish(&self) -> String {
+        hex::encode(self.digest.clone().finalize())
+    }
+}
+
+impl Default for ContentHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Hash of `data`
+pub fn content_hash(data: &[u8]) -> String {
+    let mut hasher = ContentHasher::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// Hash of the file at `path`, read in chunks; equal to [`content_hash`] of its contents
+pub async fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = ContentHasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(hasher.finish());
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
+
+static IN_FLIGHT: Lazy<Mutex<HashMap<String, Weak<tokio::sync::Mutex<()>>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Wait until no other task in this process is storing content with `hash`
+pub async fn lock(hash: &str) -> OwnedMutexGuard<()> {
+    let gate = {
+        let mut in_flight = IN_FLIGHT.lock().unwrap_or_else(|p| p.into_inner());
+        in_flight.retain(|_, gate| gate.strong_count() > 0);
+        match in_flight.get(hash).and_then(Weak::upgrade) {
+            Some(gate) => gate,
+            None => {
+                let gate = Arc::new(tokio::sync::Mutex::new(()));
+                in_flight.insert(hash.to_string(), Arc::downgrade(&gate));
+                gate
+            }
+        }
+    };
+    gate.lock_owned().await
+}
+
+/// Create `target` sharing the data of `existing`, as a clone or else a hard
+/// link; never a full copy
+pub async fn link_duplicate(existing: &Path, target: &Path) -> std::io::Result<LinkKind> {
+    let (existing, target) = (existing.to_path_buf(), target.to_path_buf());
+    tokio::task::spawn_blocking(move || {
+        match reflink(&existing, &target) {
+            Ok(()) => {
+                // A clone is a new file; it keeps the stored image's permissions
+                std::fs::set_permissions(&target, std::fs::metadata(&existing)?.permissions())?;
+                return Ok(LinkKind::Reflink);
+            }
+            Err(e) => debug!("Can't clone {:?}, hard linking: {}", existing, e),
+        }
+        std::fs::hard_link(&existing, &target)?;
+        // Both names share the file's times; the new one is the newest capture
+        std::fs::File::options().write(true).open(&target)?.set_modified(SystemTime::now())?;
+        Ok(LinkKind::Hardlink)
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn reflink(existing: &Path, target: &Path) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let source = std::fs::File::open(existing)?;
+    let clone = std::fs::File::options().write(true).create_new(true).open(target)?;
+    // SAFETY: FICLONE only reads the two descriptors, which stay open for the call
+    if unsafe { libc::ioctl(clone.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) } == 0 {
+        return Ok(());
+    }
+    let e = std::io::Error::last_os_error();
+    drop(clone);
+    let _ = std::fs::remove_file(target);
+    Err(e)
+}
+
+#[cfg(target_os = "macos")]
+fn reflink(existing: &Path, target: &Path) -> std::io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let to_c = |path: &Path| CString::new(path.as_os_str().as_bytes()).map_err(std::io::Error::other);
+    let (existing, target) = (to_c(existing)?, to_c(target)?);
+    // SAFETY: both are valid NUL-terminated paths
+    if unsafe { libc::clonefile(existing.as_ptr(), target.as_ptr(), 0) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+fn reflink(_existing: &Path, _target: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "no copy-on-write clones on this platform"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_content_hash() {
+        assert_eq!(content_hash(b"image"), content_hash(b"image"));
+        assert_ne!(content_hash(b"image"), content_hash(b"imagf"));
+        assert_ne!(content_hash(b""), content_hash(b"\0"));
+        // Stored in the index, so it must never change
+        assert_eq!(content_hash(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+
+        let mut hasher = ContentHasher::new();
+        hasher.update(b"ima");
+        hasher.update(b"ge");
+        assert_eq!(hasher.finish(), content_hash(b"image"));
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("shot.png");
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(hash_file(&path).await.unwrap(), content_hash(&data));
+    }
+
+    #[tokio::test]
+    async fn test_lock_serializes_same_hash() {
+        let first = lock("same").await;
+        // Other content is unaffected
+        drop(lock("other").await);
+        assert!(tokio::time::timeout(Duration::from_millis(20), lock("same")).await.is_err());
+        drop(first);
+        drop(lock("same").await);
+    }
+
+    #[tokio::test]
+    async fn test_link_duplicate() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let existing = temp_dir.path().join("first.png");
+        std::fs::write(&existing, b"png").unwrap();
+        let old = SystemTime::now() - Duration::from_secs(3600);
+        std::fs::File::options().write(true).open(&existing).unwrap().set_modified(old).unwrap();
+
+        let target = temp_dir.path().join("second.png");
+        let kind = link_duplicate(&existing, &target).await.unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"png");
+        // Either way the new name counts as just stored
+        let modified = std::fs::metadata(&target).unwrap().modified().unwrap();
+        assert!(modified > old + Duration::from_secs(60), "{:?}", kind);
+
+        assert!(link_duplicate(&existing, &target).await.is_err());
+        assert!(link_duplicate(&temp_dir.path().join("missing.png"), &temp_dir.path().join("third.png")).await.is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Interception of images downloaded by a browser.
+//!
+//! Many "paste a screenshot" flows start with an image saved from a browser.
+//! The downloads directory is watched with `notify`; browsers write to a
+//! partial file (`.crdownload`, `.part`, ...) and rename it when done, so an
+//! image is picked up once it has its final name and its size stops changing.
+
+use crate::{
+    config::Config,
+    error::Result,
+    events::{EventBus, InterceptEvent},
+    image_processor::ImageProcessor,
+    intercept_switches::{self, InterceptSource},
+    Error,
+};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+/// Source recorded for downloaded images
+pub const DOWNLOAD_SOURCE: &str = "download";
+
+/// Suffixes browsers give downloads still in progress
+const PARTIAL_SUFFIXES: &[&str] = &["crdownload", "part", "partial", "download", "tmp"];
+
+/// How often a new file's size is checked while it is still being written
+const SETTLE_POLL: Duration = Duration::from_millis(250);
+
+/// Longest a download may keep growing before it is processed anyway
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A file reported again within this window is the same download
+const REPEAT_WINDOW: Duration = Duration::from_secs(10);
+
+pub struct DownloadWatcher {
+    config: Config,
+    dir: PathBuf,
+    events: EventBus,
+    processor: ImageProcessor,
+    /// Recently handled downloads, so repeated filesystem events process each once
+    seen: HashMap<PathBuf, Instant>,
+}
+
+impl DownloadWatcher {
+    pub async fn new(config: Config) -> Result<Self> {
+        let dir = config
+            .downloads
+            .dir
+            .clone()
+            .or_else(dirs::download_dir)
+            .or_else(crate::termux::downloads_dir)
+            .ok_or_else(|| Error::Config("No downloads directory configured or found".to_string()))?;
+
+        Ok(Self {
+            processor: ImageProcessor::new(config.clone()).await?,
+            config,
+            dir,
+            events: EventBus::new(),
+            seen: HashMap::new(),
+        })
+    }
+
+    /// Publish intercepted downloads on a shared event bus
+    pub fn set_event_bus(&mut self, events: EventBus) {
+        self.events = events;
+    }
+
+    pub async fn run(&mut self) -> Result<()> {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = sender.send(event);
+        })?;
+        watcher.watch(&self.dir, RecursiveMode::NonRecursive)?;
+        info!("Watching {:?} for downloaded images", self.dir);
+
+        while let Some(event) = receiver.recv().await {
+            let event: notify::Event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    warn!("Downloads watcher error: {}", e);
+                    continue;
+                }
+            };
+
+            // A rename lists the old name first; the last path is the file's name now
+            let finished = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(notify::event::ModifyKind::Name(_)));
+            if let Some(path) = event.paths.last().filter(|path| finished && is_finished_image(path)) {
+                // Watching goes on while `klipdot disable filewatch` has it off, so it can resume
+                if !intercept_switches::enabled(InterceptSource::Filewatch, &self.config.intercept_methods) {
+                    debug!("File watching disabled, leaving download {:?}", path);
+                    continue;
+                }
+                self.handle_download(path.clone()).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_download(&mut self, path: PathBuf) {
+        let now = Instant::now();
+        self.seen.retain(|_, handled| now.duration_since(*handled) < REPEAT_WINDOW);
+        if self.seen.insert(path.clone(), now).is_some() {
+            debug!("Already handled download {:?}", path);
+            return;
+        }
+
+        if !wait_until_complete(&path, SETTLE_POLL, SETTLE_TIMEOUT).await {
+            debug!("Download {:?} disappeared before it finished", path);
+            return;
+        }
+
+        match self.processor.process_image_file(&path, DOWNLOAD_SOURCE).await {
+            Ok(stored) => {
+                info!("Downloaded image {:?} stored as {:?}", path, stored);
+                if self.config.downloads.notify {
+                    self.processor.notify(&format!("Downloaded image saved to {}", stored.display()));
+                }
+                self.events.publish(InterceptEvent::ImageIntercepted {
+                    path: stored,
+                    source: DOWNLOAD_SOURCE.to_string(),
+                });
+            }
+            Err(e) => {
+                warn!("Failed to process downloaded image {:?}: {}", path, e);
+                crate::error_history::record_error("downloads", &e);
+            }
+        }
+    }
+}
+
+/// Whether `path` is an image with its final name, rather than a hidden or in-progress file
+pub fn is_finished_image(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    let partial = path
+        .extension()
+        .is_some_and(|ext| PARTIAL_SUFFIXES.iter().any(|suffix| ext.eq_ignore_ascii_case(suffix)));
+
+    !hidden && !partial && crate::is_image_file(path)
+}
+
+/// Wait until the file at `path` has the same non-zero size on two checks
+/// `poll` apart, or `timeout` passes; false if the file went away
+pub async fn wait_until_complete(path: &Path, poll: Duration, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    let mut last_size = None;
+
+    loop {
+        let Ok(metadata) = tokio::fs::metadata(path).await else {
+            return false;
+        };
+        let size = metadata.len();
+        if (size > 0 && last_size == Some(size)) || Instant::now() >= deadline {
+            return true;
+        }
+        last_size = Some(size);
+        tokio::time::sleep(poll).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_finished_image_filter() {
+        let temp_dir = TempDir::new().unwrap();
+        let download = |name: &str, content: &[u8]| {
+            let path = temp_dir.path().join(name);
+            std::fs::write(&path, content).unwrap();
+            path
+        };
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        let jpeg = [0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, 0x4A, 0x46];
+
+        assert!(is_finished_image(&download("diagram.png", png)));
+        assert!(is_finished_image(&download("photo.JPG", &jpeg)));
+        // Content decides, not the name
+        assert!(is_finished_image(&download("image", png)));
+        assert!(!is_finished_image(&download("page.png", b"<html></html>")));
+        assert!(!is_finished_image(&download("diagram.png.crdownload", png)));
+        assert!(!is_finished_image(&download("diagram.png.part", png)));
+        assert!(!is_finished_image(&download(".diagram.png", png)));
+        assert!(!is_finished_image(&download("report.pdf", b"%PDF-1.7\n")));
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_complete() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("shot.png");
+        assert!(!wait_until_complete(&path, Duration::from_millis(5), Duration::from_secs(1)).await);
+
+        std::fs::write(&path, b"png").unwrap();
+        assert!(wait_until_complete(&path, Duration::from_millis(5), Duration::from_secs(1)).await);
+
+        // An empty file is given up on at the timeout rather than waited for forever
+        std::fs::write(&path, b"").unwrap();
+        assert!(wait_until_complete(&path, Duration::from_millis(5), Duration::from_millis(20)).await);
+    }
+}
@@ -0,0 +1,183 @@
+//! Decoding oversized images straight to a smaller size.
+//!
+//! Images above the configured size limit are scaled down while they are
+//! decoded rather than refused. PNGs are read row by row and box-filtered, so
+//! only the reduced image is ever held in memory; JPEGs use the decoder's
+//! DCT scaling. Other formats are not streamable and are skipped.
+
+use crate::{error::Result, Error};
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use std::io::Read;
+
+/// Whether images in `format` can be downscaled while decoding
+pub fn is_streamable(format: ImageFormat) -> bool {
+    format == ImageFormat::Png || (format == ImageFormat::Jpeg && cfg!(feature = "codecs"))
+}
+
+/// Decode `reader` so that neither side exceeds `max_dimension`, returning the
+/// image and its original width and height
+pub fn decode_downscaled<R: Read>(reader: R, format: ImageFormat, max_dimension: u32) -> Result<(DynamicImage, (u32, u32))> {
+    let max_dimension = max_dimension.max(1);
+    match format {
+        ImageFormat::Png => decode_png(reader, max_dimension),
+        #[cfg(feature = "codecs")]
+        ImageFormat::Jpeg => decode_jpeg(reader, max_dimension),
+        other => Err(Error::Unsupported(format!("{:?} images can't be downscaled while decoding", other))),
+    }
+}
+
+fn decode_png<R: Read>(reader: R, max_dimension: u32) -> Result<(DynamicImage, (u32, u32))> {
+    let png_error = |e: png::DecodingError| Error::Format(format!("Failed to decode PNG: {}", e));
+
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(png_error)?;
+    let (width, height) = reader.info().size();
+
+    // Interlaced rows arrive in passes; decode those whole and resize afterwards
+    if reader.info().interlaced {
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buffer).map_err(png_error)?;
+        let img = rgba_from_rows(&buffer, reader.output_color_type().0, width, height)?;
+        return Ok((fit(DynamicImage::ImageRgba8(img), max_dimension), (width, height)));
+    }
+
+    let channels = channel_count(reader.output_color_type().0)?;
+    let factor = width.max(height).div_ceil(max_dimension).max(1);
+    let (out_width, out_height) = (width.div_ceil(factor), height.div_ceil(factor));
+
+    // Running sums for one band of `factor` source rows
+    let mut sums = vec![0u64; out_width as usize * 4];
+    let mut out = Vec::with_capacity(out_width as usize * out_height as usize * 4);
+    let mut band_rows = 0;
+
+    while let Some(row) = reader.next_row().map_err(png_error)? {
+        for (x, pixel) in row.data().chunks_exact(channels).enumerate() {
+            let cell = (x / factor as usize) * 4;
+            for (sum, value) in sums[cell..cell + 4].iter_mut().zip(to_rgba(pixel)) {
+                *sum += value as u64;
+            }
+        }
+        band_rows += 1;
+
+        if band_rows == factor {
+            flush_band(&mut sums, &mut out, width, factor, band_rows);
+            band_rows = 0;
+        }
+    }
+    // The last band may cover fewer than `factor` rows
+    if band_rows > 0 {
+        flush_band(&mut sums, &mut out, width, factor, band_rows);
+    }
+
+    let img = RgbaImage::from_raw(out_width, out_height, out)
+        .ok_or_else(|| Error::Format("PNG ended before its last row".to_string()))?;
+    Ok((DynamicImage::ImageRgba8(img), (width, height)))
+}
+
+/// Average the band in `sums` into one output row and reset the sums
+fn flush_band(sums: &mut [u64], out: &mut Vec<u8>, width: u32, factor: u32, band_rows: u32) {
+    for (cell, chunk) in sums.chunks_exact_mut(4).enumerate() {
+        // The last column of cells may cover fewer than `factor` source columns
+        let columns = (width - cell as u32 * factor).min(factor);
+        let count = (columns * band_rows) as u64;
+        for sum in chunk.iter_mut() {
+            out.push((*sum / count) as u8);
+            *sum = 0;
+        }
+    }
+}
+
+fn channel_count(color: png::ColorType) -> Result<usize> {
+    match color {
+        png::ColorType::Grayscale => Ok(1),
+        png::ColorType::GrayscaleAlpha => Ok(2),
+        png::ColorType::Rgb => Ok(3),
+        png::ColorType::Rgba => Ok(4),
+        png::ColorType::Indexed => Err(Error::Format("PNG palette was not expanded".to_string())),
+    }
+}
+
+fn to_rgba(pixel: &[u8]) -> [u8; 4] {
+    match *pixel {
+        [gray] => [gray, gray, gray, 255],
+        [gray, alpha] => [gray, gray, gray, alpha],
+        [r, g, b] => [r, g, b, 255],
+        [r, g, b, a] => [r, g, b, a],
+        _ => [0, 0, 0, 0],
+    }
+}
+
+fn rgba_from_rows(buffer: &[u8], color: png::ColorType, width: u32, height: u32) -> Result<RgbaImage> {
+    let channels = channel_count(color)?;
+    let pixels = buffer.chunks_exact(channels).flat_map(to_rgba).collect();
+    RgbaImage::from_raw(width, height, pixels).ok_or_else(|| Error::Format("PNG ended before its last row".to_string()))
+}
+
+#[cfg(feature = "codecs")]
+fn decode_jpeg<R: Read>(reader: R, max_dimension: u32) -> Result<(DynamicImage, (u32, u32))> {
+    use image::ImageDecoder;
+
+    let mut decoder = image::codecs::jpeg::JpegDecoder::new(reader)?;
+    let (width, height) = decoder.dimensions();
+    // The decoder only scales by 1/2, 1/4 or 1/8, so finish with a resize
+    let requested = max_dimension.min(u16::MAX as u32) as u16;
+    decoder.scale(requested, requested)?;
+    let img = DynamicImage::from_decoder(decoder)?;
+    Ok((fit(img, max_dimension), (width, height)))
+}
+
+fn fit(img: DynamicImage, max_dimension: u32) -> DynamicImage {
+    if img.width() > max_dimension || img.height() > max_dimension {
+        img.resize(max_dimension, max_dimension, image::imageops::FilterType::Triangle)
+    } else {
+        img
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(img: &DynamicImage, format: image::ImageOutputFormat) -> Vec<u8> {
+        let mut data = Vec::new();
+        img.write_to(&mut std::io::Cursor::new(&mut data), format).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_png_downscaled_while_decoding() {
+        // Left half black, right half white, 10x5 with an odd last column
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(10, 5, |x, _| {
+            if x < 4 { image::Rgb([0, 0, 0]) } else { image::Rgb([255, 255, 255]) }
+        }));
+        let data = encode(&img, image::ImageOutputFormat::Png);
+
+        let (scaled, original) = decode_downscaled(data.as_slice(), ImageFormat::Png, 3).unwrap();
+        assert_eq!(original, (10, 5));
+        assert_eq!((scaled.width(), scaled.height()), (3, 2));
+
+        let scaled = scaled.to_rgba8();
+        assert_eq!(scaled.get_pixel(0, 0).0, [0, 0, 0, 255]);
+        assert_eq!(scaled.get_pixel(2, 1).0, [255, 255, 255, 255]);
+        // Columns 4..7 are white; the first cell of columns 0..3 stays black
+        assert_eq!(scaled.get_pixel(1, 0).0, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_small_png_unchanged() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, image::Rgba([10, 20, 30, 40])));
+        let data = encode(&img, image::ImageOutputFormat::Png);
+
+        let (decoded, original) = decode_downscaled(data.as_slice(), ImageFormat::Png, 100).unwrap();
+        assert_eq!(original, (4, 4));
+        assert_eq!(decoded.to_rgba8(), img.to_rgba8());
+    }
+
+    #[test]
+    fn test_unstreamable_format() {
+        assert!(is_streamable(ImageFormat::Png));
+        assert!(!is_streamable(ImageFormat::Gif));
+        assert!(decode_downscaled(&b"GIF89a"[..], ImageFormat::Gif, 100).is_err());
+    }
+}
@@ -11,6 +11,7 @@ pub enum Error {
     #[error("Image processing error: {0}")]
     Image(#[from] image::ImageError),
     
+    #[cfg(feature = "file-watch")]
     #[error("File watcher error: {0}")]
     FileWatcher(#[from] notify::Error),
     
@@ -82,28 +83,28 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 impl Error {
     pub fn is_recoverable(&self) -> bool {
-        match self {
-            Error::Io(_) => true,
-            Error::Clipboard(_) => true,
-            Error::Network(_) => true,
-            Error::Timeout(_) => true,
-            Error::Process(_) => true,
-            Error::Wayland(_) => true,
-            Error::DisplayServer(_) => true,
-            Error::Cancelled => true,
-            _ => false,
-        }
+        matches!(
+            self,
+            Error::Io(_)
+                | Error::Clipboard(_)
+                | Error::Network(_)
+                | Error::Timeout(_)
+                | Error::Process(_)
+                | Error::Wayland(_)
+                | Error::DisplayServer(_)
+                | Error::Cancelled
+        )
     }
     
     pub fn is_fatal(&self) -> bool {
-        match self {
-            Error::Config(_) => true,
-            Error::Permission(_) => true,
-            Error::Unsupported(_) => true,
-            Error::Internal(_) => true,
-            Error::Compositor(_) => true,
-            _ => false,
-        }
+        matches!(
+            self,
+            Error::Config(_)
+                | Error::Permission(_)
+                | Error::Unsupported(_)
+                | Error::Internal(_)
+                | Error::Compositor(_)
+        )
     }
     
     pub fn error_code(&self) -> &'static str {
@@ -111,6 +112,7 @@ impl Error {
             Error::Io(_) => "IO",
             Error::Serialization(_) => "SERIALIZATION",
             Error::Image(_) => "IMAGE",
+            #[cfg(feature = "file-watch")]
             Error::FileWatcher(_) => "FILE_WATCHER",
             Error::Config(_) => "CONFIG",
             Error::Clipboard(_) => "CLIPBOARD",
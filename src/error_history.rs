@@ -0,0 +1,167 @@
+use crate::{error::Result, Error};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::debug;
+
+/// A single error observed by the running service
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorRecord {
+    pub code: String,
+    pub subsystem: String,
+    pub timestamp: DateTime<Utc>,
+    pub message: String,
+}
+
+/// Bounded history of the most recent errors, optionally mirrored to disk so
+/// that `klipdot status` can read what the daemon saw
+#[derive(Debug)]
+pub struct ErrorHistory {
+    capacity: usize,
+    records: VecDeque<ErrorRecord>,
+    file: Option<PathBuf>,
+}
+
+static HISTORY: Lazy<Mutex<ErrorHistory>> =
+    Lazy::new(|| Mutex::new(ErrorHistory::new(crate::ERROR_HISTORY_SIZE)));
+
+impl ErrorHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            records: VecDeque::new(),
+            file: None,
+        }
+    }
+
+    /// Create a history backed by `path`, keeping any records already stored there
+    pub fn with_file(path: PathBuf, capacity: usize) -> Self {
+        let mut history = Self::new(capacity);
+        if let Ok(records) = Self::load(&path) {
+            for record in records {
+                history.push(record);
+            }
+        }
+        history.file = Some(path);
+        history
+    }
+
+    /// Read the records persisted at `path`, oldest first
+    pub fn load(path: &Path) -> Result<Vec<ErrorRecord>> {
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn record(&mut self, subsystem: &str, error: &Error) {
+        self.push(ErrorRecord {
+            code: error.error_code().to_string(),
+            subsystem: subsystem.to_string(),
+            timestamp: Utc::now(),
+            message: error.to_string(),
+        });
+
+        if let Err(e) = self.save() {
+            debug!("Failed to persist error history: {}", e);
+        }
+    }
+
+    fn push(&mut self, record: ErrorRecord) {
+        while self.records.len() >= self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(ref file) = self.file {
+            let records: Vec<&ErrorRecord> = self.records.iter().collect();
+            std::fs::write(file, serde_json::to_string_pretty(&records)?)?;
+        }
+        Ok(())
+    }
+
+    /// Records currently held, oldest first
+    pub fn records(&self) -> Vec<ErrorRecord> {
+        self.records.iter().cloned().collect()
+    }
+
+    pub fn clear(&mut self) -> Result<()> {
+        self.records.clear();
+        self.save()
+    }
+}
+
+fn global() -> std::sync::MutexGuard<'static, ErrorHistory> {
+    HISTORY.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Mirror the process-wide history to `path` (used by the service)
+pub fn persist_to(path: PathBuf) {
+    *global() = ErrorHistory::with_file(path, crate::ERROR_HISTORY_SIZE);
+}
+
+/// Record an error in the process-wide history
+pub fn record_error(subsystem: &str, error: &Error) {
+    global().record(subsystem, error);
+}
+
+/// Records in the process-wide history, oldest first
+pub fn recent_errors() -> Vec<ErrorRecord> {
+    global().records()
+}
+
+/// Default location of the persisted history
+pub fn default_history_path() -> Result<PathBuf> {
+    Ok(crate::get_home_dir()?.join(crate::ERROR_HISTORY_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_ring_buffer_capacity() {
+        let mut history = ErrorHistory::new(2);
+        history.record("clipboard", &Error::Clipboard("one".to_string()));
+        history.record("clipboard", &Error::Clipboard("two".to_string()));
+        history.record("interceptor", &Error::Process("three".to_string()));
+
+        let records = history.records();
+        assert_eq!(records.len(), 2);
+        assert!(records[0].message.contains("two"));
+        assert_eq!(records[1].code, "PROCESS");
+        assert_eq!(records[1].subsystem, "interceptor");
+    }
+
+    #[test]
+    fn test_persisted_history() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("errors.json");
+
+        let mut history = ErrorHistory::with_file(path.clone(), 10);
+        history.record("clipboard", &Error::Timeout("wl-paste".to_string()));
+
+        let loaded = ErrorHistory::load(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].code, "TIMEOUT");
+
+        // A new history picks up where the previous process left off
+        let reopened = ErrorHistory::with_file(path.clone(), 10);
+        assert_eq!(reopened.records(), loaded);
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let records = ErrorHistory::load(&temp_dir.path().join("missing.json")).unwrap();
+        assert!(records.is_empty());
+    }
+}
@@ -0,0 +1,94 @@
+use std::path::PathBuf;
+use tokio::sync::broadcast;
+use tracing::debug;
+
+/// Number of events a slow subscriber can fall behind before it starts losing them
+const EVENT_BUS_CAPACITY: usize = 64;
+
+/// Events emitted by the interception core
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterceptEvent {
+    /// An image was intercepted and stored at `path`
+    ImageIntercepted {
+        path: PathBuf,
+        source: String,
+    },
+    /// A screenshot tool was seen running; its capture may land on the clipboard
+    ScreenshotToolStarted {
+        tool: String,
+        source: String,
+    },
+    /// A screenshot tool exited
+    ScreenshotToolFinished {
+        tool: String,
+        source: String,
+    },
+}
+
+impl InterceptEvent {
+    /// Path of the stored image this event refers to, if any
+    pub fn path(&self) -> Option<&PathBuf> {
+        match self {
+            InterceptEvent::ImageIntercepted { path, .. } => Some(path),
+            InterceptEvent::ScreenshotToolStarted { .. } | InterceptEvent::ScreenshotToolFinished { .. } => None,
+        }
+    }
+}
+
+/// Fan-out channel shared between the monitors and their consumers
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<InterceptEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+        Self { sender }
+    }
+
+    /// Publish an event; it is dropped silently when nobody is subscribed
+    pub fn publish(&self, event: InterceptEvent) {
+        if self.sender.send(event).is_err() {
+            debug!("No event subscribers, event dropped");
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<InterceptEvent> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_publish_subscribe() {
+        let bus = EventBus::new();
+        let mut receiver = bus.subscribe();
+
+        bus.publish(InterceptEvent::ImageIntercepted {
+            path: PathBuf::from("/tmp/test.png"),
+            source: "clipboard".to_string(),
+        });
+
+        let event = receiver.recv().await.unwrap();
+        assert_eq!(event.path(), Some(&PathBuf::from("/tmp/test.png")));
+    }
+
+    #[test]
+    fn test_publish_without_subscribers() {
+        let bus = EventBus::new();
+        bus.publish(InterceptEvent::ImageIntercepted {
+            path: PathBuf::from("/tmp/test.png"),
+            source: "clipboard".to_string(),
+        });
+    }
+}
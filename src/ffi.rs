@@ -0,0 +1,228 @@
+//! C ABI for embedding the interception core in non-Rust hosts.
+//!
+//! The API is deliberately small so it can be driven from LuaJIT FFI, Zig or
+//! plain C: start the core once, poll for events from the host's own loop and
+//! read the path of the most recent interception. See `include/klipdot.h`.
+
+use crate::{
+    clipboard::ClipboardMonitor, config::Config, events::InterceptEvent,
+    interceptor::TerminalInterceptor,
+};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+use tracing::{error, warn};
+
+/// Call succeeded
+pub const KLIPDOT_OK: c_int = 0;
+/// No event is pending
+pub const KLIPDOT_EVENT_NONE: c_int = 0;
+/// An image was intercepted; its path is available from `klipdot_last_path`
+pub const KLIPDOT_EVENT_IMAGE: c_int = 1;
+/// `klipdot_start` has not been called
+pub const KLIPDOT_ERR_NOT_STARTED: c_int = -1;
+/// `klipdot_start` was called twice without `klipdot_stop`
+pub const KLIPDOT_ERR_ALREADY_STARTED: c_int = -2;
+/// An argument was not valid UTF-8
+pub const KLIPDOT_ERR_INVALID_ARG: c_int = -3;
+/// Configuration or runtime initialisation failed
+pub const KLIPDOT_ERR_INIT: c_int = -4;
+
+struct FfiState {
+    runtime: tokio::runtime::Runtime,
+    receiver: broadcast::Receiver<InterceptEvent>,
+    last_path: Option<CString>,
+}
+
+static STATE: Mutex<Option<FfiState>> = Mutex::new(None);
+
+fn lock_state() -> std::sync::MutexGuard<'static, Option<FfiState>> {
+    STATE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Start clipboard and process monitoring on a background runtime.
+///
+/// `config_path` may be NULL to use the default configuration file.
+///
+/// # Safety
+///
+/// `config_path` must be NULL or point to a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn klipdot_start(config_path: *const c_char) -> c_int {
+    let mut state = lock_state();
+    if state.is_some() {
+        return KLIPDOT_ERR_ALREADY_STARTED;
+    }
+
+    let config = if config_path.is_null() {
+        Config::load_or_create_default()
+    } else {
+        match CStr::from_ptr(config_path).to_str() {
+            Ok(path) => Config::load_from_path(&PathBuf::from(path)),
+            Err(_) => return KLIPDOT_ERR_INVALID_ARG,
+        }
+    };
+
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Failed to load config for FFI start: {}", e);
+            return KLIPDOT_ERR_INIT;
+        }
+    };
+
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            error!("Failed to build runtime for FFI start: {}", e);
+            return KLIPDOT_ERR_INIT;
+        }
+    };
+
+    let monitors = runtime.block_on(async {
+        let clipboard_monitor = ClipboardMonitor::new(config.clone()).await?;
+        let mut interceptor = TerminalInterceptor::new(config).await?;
+        interceptor.set_event_bus(clipboard_monitor.event_bus());
+        crate::Result::Ok((clipboard_monitor, interceptor))
+    });
+
+    let (mut clipboard_monitor, mut interceptor) = match monitors {
+        Ok(monitors) => monitors,
+        Err(e) => {
+            error!("Failed to create monitors for FFI start: {}", e);
+            return KLIPDOT_ERR_INIT;
+        }
+    };
+
+    let receiver = clipboard_monitor.event_bus().subscribe();
+
+    runtime.spawn(async move {
+        if let Err(e) = clipboard_monitor.run().await {
+            error!("Clipboard monitor error: {}", e);
+        }
+    });
+    runtime.spawn(async move {
+        if let Err(e) = interceptor.run().await {
+            error!("Terminal interceptor error: {}", e);
+        }
+    });
+
+    *state = Some(FfiState {
+        runtime,
+        receiver,
+        last_path: None,
+    });
+
+    KLIPDOT_OK
+}
+
+/// Poll for the next interception event without blocking.
+///
+/// Returns `KLIPDOT_EVENT_IMAGE` when an image was intercepted (the path is
+/// then available from `klipdot_last_path`), `KLIPDOT_EVENT_NONE` when nothing
+/// is pending, or a negative error code.
+#[no_mangle]
+pub extern "C" fn klipdot_poll_event() -> c_int {
+    let mut guard = lock_state();
+    let Some(state) = guard.as_mut() else {
+        return KLIPDOT_ERR_NOT_STARTED;
+    };
+
+    loop {
+        match state.receiver.try_recv() {
+            Ok(event) => {
+                // Tool lifecycle events only matter to the monitors
+                let Some(path) = event.path() else {
+                    continue;
+                };
+                let path = path.to_string_lossy().into_owned();
+                state.last_path = CString::new(path).ok();
+                return KLIPDOT_EVENT_IMAGE;
+            }
+            Err(TryRecvError::Lagged(skipped)) => {
+                warn!("FFI consumer lagged, {} events skipped", skipped);
+            }
+            Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => {
+                return KLIPDOT_EVENT_NONE;
+            }
+        }
+    }
+}
+
+/// Path of the most recently polled interception, or NULL if there is none.
+///
+/// The returned string is owned by KlipDot and stays valid until the next call
+/// to `klipdot_poll_event` or `klipdot_stop`.
+#[no_mangle]
+pub extern "C" fn klipdot_last_path() -> *const c_char {
+    lock_state()
+        .as_ref()
+        .and_then(|state| state.last_path.as_ref())
+        .map_or(std::ptr::null(), |path| path.as_ptr())
+}
+
+/// Stop monitoring and release all resources held by `klipdot_start`.
+#[no_mangle]
+pub extern "C" fn klipdot_stop() -> c_int {
+    match lock_state().take() {
+        Some(state) => {
+            state.runtime.shutdown_background();
+            KLIPDOT_OK
+        }
+        None => KLIPDOT_ERR_NOT_STARTED,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[test]
+    #[serial]
+    fn test_calls_before_start() {
+        assert_eq!(klipdot_poll_event(), KLIPDOT_ERR_NOT_STARTED);
+        assert!(klipdot_last_path().is_null());
+        assert_eq!(klipdot_stop(), KLIPDOT_ERR_NOT_STARTED);
+    }
+
+    #[test]
+    #[serial]
+    fn test_start_with_missing_config() {
+        let path = CString::new("/nonexistent/klipdot/config.json").unwrap();
+        assert_eq!(unsafe { klipdot_start(path.as_ptr()) }, KLIPDOT_ERR_INIT);
+        assert_eq!(klipdot_poll_event(), KLIPDOT_ERR_NOT_STARTED);
+    }
+
+    #[test]
+    #[serial]
+    fn test_start_poll_stop() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.json");
+
+        let mut config = Config {
+            config_file: config_path.clone(),
+            screenshot_dir: temp_dir.path().join("screenshots"),
+            ..Config::default()
+        };
+        config.intercept_methods.clipboard = false;
+        config.intercept_methods.process_monitor = false;
+        config.save().unwrap();
+
+        let path = CString::new(config_path.to_string_lossy().into_owned()).unwrap();
+        assert_eq!(unsafe { klipdot_start(path.as_ptr()) }, KLIPDOT_OK);
+        assert_eq!(unsafe { klipdot_start(path.as_ptr()) }, KLIPDOT_ERR_ALREADY_STARTED);
+
+        assert_eq!(klipdot_poll_event(), KLIPDOT_EVENT_NONE);
+        assert!(klipdot_last_path().is_null());
+
+        assert_eq!(klipdot_stop(), KLIPDOT_OK);
+        assert_eq!(klipdot_poll_event(), KLIPDOT_ERR_NOT_STARTED);
+    }
+}
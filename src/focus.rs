@@ -0,0 +1,334 @@
+//! Which application and window have keyboard focus.
+//!
+//! Queried through the desktop's own command-line tools: `swaymsg` and
+//! `hyprctl` on Wayland compositors that expose the focused window,
+//! `xdotool` on X11 and System Events on macOS. Other desktops report no
+//! focused application.
+//!
+//! sway and Hyprland also report the active workspace, as do X11 window
+//! managers through the EWMH root window properties `xprop` reads, so with
+//! `window_context` on, [`window_context`] is recorded with each stored image
+//! for `search window:` and `search workspace:`.
+
+use crate::command_runner::CommandRunner;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use tracing::debug;
+
+/// The focused window when an image was intercepted, as the compositor reported it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowContext {
+    /// Application id, or window class for XWayland windows
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Name of the workspace the window is on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace: Option<String>,
+}
+
+/// Application id or window class of the focused window, if it can be determined
+pub async fn focused_app(runner: &dyn CommandRunner) -> Option<String> {
+    let app = match crate::detect_display_server() {
+        crate::DisplayServer::Wayland => match crate::detect_wayland_compositor().as_deref() {
+            Some("sway") => query(runner, "swaymsg", &["-t", "get_tree", "-r"]).await.and_then(|tree| parse_sway_tree(&tree)),
+            Some("hyprland") => query(runner, "hyprctl", &["activewindow", "-j"]).await.and_then(|window| parse_hyprland_window(&window)),
+            // XWayland windows are still visible to X11 tools
+            _ => query(runner, "xdotool", &["getactivewindow", "getwindowclassname"]).await,
+        },
+        crate::DisplayServer::X11 => match query(runner, "xdotool", &["getactivewindow", "getwindowclassname"]).await {
+            Some(app) => Some(app),
+            None => x11_context(runner).await.and_then(|context| context.app_id),
+        },
+        crate::DisplayServer::MacOS => {
+            let script = r#"tell application "System Events" to get name of first application process whose frontmost is true"#;
+            query(runner, "osascript", &["-e", script]).await
+        }
+        crate::DisplayServer::Unknown => None,
+    };
+
+    debug!("Focused application: {:?}", app);
+    app.filter(|app| !app.is_empty())
+}
+
+/// Title of the focused window, if it can be determined
+pub async fn focused_title(runner: &dyn CommandRunner) -> Option<String> {
+    let title = match crate::detect_display_server() {
+        crate::DisplayServer::Wayland => match crate::detect_wayland_compositor().as_deref() {
+            Some("sway") => query(runner, "swaymsg", &["-t", "get_tree", "-r"]).await.and_then(|tree| parse_sway_title(&tree)),
+            Some("hyprland") => query(runner, "hyprctl", &["activewindow", "-j"]).await.and_then(|window| parse_hyprland_title(&window)),
+            _ => query(runner, "xdotool", &["getactivewindow", "getwindowname"]).await,
+        },
+        crate::DisplayServer::X11 => match query(runner, "xdotool", &["getactivewindow", "getwindowname"]).await {
+            Some(title) => Some(title),
+            None => x11_context(runner).await.and_then(|context| context.title),
+        },
+        crate::DisplayServer::MacOS => {
+            let script = r#"tell application "System Events" to get name of front window of (first application process whose frontmost is true)"#;
+            query(runner, "osascript", &["-e", script]).await
+        }
+        crate::DisplayServer::Unknown => None,
+    };
+
+    debug!("Focused window title: {:?}", title);
+    title.filter(|title| !title.is_empty())
+}
+
+/// The focused window and its workspace from sway's or Hyprland's IPC, or
+/// the EWMH properties on X11; `None` on other desktops
+pub async fn window_context(runner: &dyn CommandRunner) -> Option<WindowContext> {
+    let context = match crate::detect_display_server() {
+        crate::DisplayServer::Wayland => match crate::detect_wayland_compositor().as_deref() {
+            Some("sway") => query(runner, "swaymsg", &["-t", "get_tree", "-r"]).await.and_then(|tree| parse_sway_context(&tree)),
+            Some("hyprland") => query(runner, "hyprctl", &["activewindow", "-j"]).await.and_then(|window| parse_hyprland_context(&window)),
+            _ => None,
+        },
+        crate::DisplayServer::X11 => x11_context(runner).await,
+        _ => None,
+    };
+
+    debug!("Focused window context: {:?}", context);
+    context.filter(|context| *context != WindowContext::default())
+}
+
+/// The active window's class and title and the current desktop, from the
+/// root window's `_NET_ACTIVE_WINDOW` and the window's own properties
+async fn x11_context(runner: &dyn CommandRunner) -> Option<WindowContext> {
+    let root = query(runner, "xprop", &["-root", "_NET_ACTIVE_WINDOW", "_NET_CURRENT_DESKTOP", "_NET_DESKTOP_NAMES"]).await?;
+    let window = match x11_active_window(&root) {
+        Some(id) => query(runner, "xprop", &["-id", id, "WM_CLASS", "_NET_WM_NAME", "WM_NAME"]).await,
+        None => None,
+    };
+    Some(parse_x11_context(&root, window.as_deref()))
+}
+
+async fn query(runner: &dyn CommandRunner, program: &str, args: &[&str]) -> Option<String> {
+    if !runner.is_available(program) {
+        return None;
+    }
+
+    let output = runner.run(program, args, None).await.ok()?;
+    output.success.then(|| output.stdout_lossy().trim().to_string())
+}
+
+/// The focused node of `swaymsg -t get_tree` output
+fn find_sway_focused(node: &serde_json::Value) -> Option<&serde_json::Value> {
+    if node["focused"].as_bool() == Some(true) {
+        return Some(node);
+    }
+
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node[*key].as_array())
+        .flatten()
+        .find_map(find_sway_focused)
+}
+
+/// The focused node of `swaymsg -t get_tree` output and the workspace it's on
+fn find_sway_focused_on<'a>(node: &'a Value, workspace: Option<&'a str>) -> Option<(&'a Value, Option<&'a str>)> {
+    let workspace = match node["type"].as_str() {
+        Some("workspace") => node["name"].as_str(),
+        _ => workspace,
+    };
+    if node["focused"].as_bool() == Some(true) {
+        return Some((node, workspace));
+    }
+
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node[*key].as_array())
+        .flatten()
+        .find_map(|child| find_sway_focused_on(child, workspace))
+}
+
+/// Focused window and workspace from `swaymsg -t get_tree` output
+fn parse_sway_context(tree: &str) -> Option<WindowContext> {
+    let tree = serde_json::from_str(tree).ok()?;
+    let (node, workspace) = find_sway_focused_on(&tree, None)?;
+    let string = |value: &Value| value.as_str().map(str::to_string);
+    // An empty workspace has focus itself
+    if node["type"].as_str() == Some("workspace") {
+        return Some(WindowContext { workspace: workspace.map(str::to_string), ..Default::default() });
+    }
+    Some(WindowContext {
+        app_id: string(&node["app_id"]).or_else(|| string(&node["window_properties"]["class"])),
+        title: string(&node["name"]),
+        workspace: workspace.map(str::to_string),
+    })
+}
+
+/// Application of the focused node of `swaymsg -t get_tree` output
+fn parse_sway_tree(tree: &str) -> Option<String> {
+    let tree = serde_json::from_str(tree).ok()?;
+    let node = find_sway_focused(&tree)?;
+    node["app_id"]
+        .as_str()
+        .or_else(|| node["window_properties"]["class"].as_str())
+        .map(str::to_string)
+}
+
+/// Title of the focused node of `swaymsg -t get_tree` output
+fn parse_sway_title(tree: &str) -> Option<String> {
+    let tree = serde_json::from_str(tree).ok()?;
+    find_sway_focused(&tree)?["name"].as_str().map(str::to_string)
+}
+
+/// Window class from `hyprctl activewindow -j`
+fn parse_hyprland_window(window: &str) -> Option<String> {
+    let window: serde_json::Value = serde_json::from_str(window).ok()?;
+    window["class"].as_str().map(str::to_string)
+}
+
+/// Window title from `hyprctl activewindow -j`
+fn parse_hyprland_title(window: &str) -> Option<String> {
+    let window: serde_json::Value = serde_json::from_str(window).ok()?;
+    window["title"].as_str().map(str::to_string)
+}
+
+/// Window class, title and workspace from `hyprctl activewindow -j`
+fn parse_hyprland_context(window: &str) -> Option<WindowContext> {
+    let window: Value = serde_json::from_str(window).ok()?;
+    let string = |value: &Value| value.as_str().filter(|value| !value.is_empty()).map(str::to_string);
+    Some(WindowContext {
+        app_id: string(&window["class"]),
+        title: string(&window["title"]),
+        workspace: string(&window["workspace"]["name"]),
+    })
+}
+
+/// Property values from `xprop` output lines like `WM_CLASS(STRING) = "a", "b"`,
+/// by property name; properties that aren't set are left out
+fn parse_xprop(output: &str) -> HashMap<&str, &str> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, rest) = line.split_once('(')?;
+            let (_, value) = rest.split_once(')')?;
+            Some((name, value.trim_start_matches([' ', '=', ':']).trim()))
+        })
+        .collect()
+}
+
+/// The quoted strings of an `xprop` value, unescaped
+fn xprop_strings(value: &str) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '"' {
+            continue;
+        }
+        let mut string = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => string.extend(chars.next()),
+                '"' => break,
+                c => string.push(c),
+            }
+        }
+        strings.push(string);
+    }
+    strings
+}
+
+/// The id of the active window in `xprop -root` output, like `0x3a00007`
+fn x11_active_window(root: &str) -> Option<&str> {
+    let (_, id) = parse_xprop(root).get("_NET_ACTIVE_WINDOW")?.split_once('#')?;
+    let id = id.split(',').next()?.trim();
+    (id.starts_with("0x") && id != "0x0").then_some(id)
+}
+
+/// Window class, title and desktop from `xprop` output for the root and,
+/// when there is one, the active window. Unnamed desktops are numbered from 1.
+fn parse_x11_context(root: &str, window: Option<&str>) -> WindowContext {
+    let root = parse_xprop(root);
+    let workspace = root.get("_NET_CURRENT_DESKTOP").and_then(|desktop| desktop.parse::<usize>().ok()).map(|desktop| {
+        let names = root.get("_NET_DESKTOP_NAMES").map(|names| xprop_strings(names)).unwrap_or_default();
+        names.get(desktop).filter(|name| !name.is_empty()).cloned().unwrap_or_else(|| (desktop + 1).to_string())
+    });
+
+    let window = window.map(parse_xprop).unwrap_or_default();
+    // The class is the second string, after the instance name
+    let app_id = window.get("WM_CLASS").and_then(|class| xprop_strings(class).pop());
+    let title = ["_NET_WM_NAME", "WM_NAME"]
+        .iter()
+        .find_map(|property| window.get(property).and_then(|value| xprop_strings(value).into_iter().next()));
+    let string = |value: Option<String>| value.filter(|value| !value.is_empty());
+    WindowContext { app_id: string(app_id), title: string(title), workspace }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sway_tree() {
+        let tree = r#"{"focused": false, "nodes": [
+            {"focused": false, "nodes": [{"focused": false, "app_id": "foot", "nodes": []}]},
+            {"focused": false, "nodes": [], "floating_nodes": [
+                {"focused": true, "app_id": null, "window_properties": {"class": "zoom"}}
+            ]}
+        ]}"#;
+        assert_eq!(parse_sway_tree(tree).as_deref(), Some("zoom"));
+        assert_eq!(parse_sway_title(tree), None);
+        assert_eq!(parse_sway_tree(r#"{"focused": false, "nodes": []}"#), None);
+        assert_eq!(parse_sway_tree("not json"), None);
+    }
+
+    #[test]
+    fn test_parse_hyprland_window() {
+        let window = r#"{"address": "0x1", "class": "com.obsproject.Studio", "title": "OBS"}"#;
+        assert_eq!(parse_hyprland_window(window).as_deref(), Some("com.obsproject.Studio"));
+        assert_eq!(parse_hyprland_window("{}"), None);
+        assert_eq!(parse_hyprland_title(window).as_deref(), Some("OBS"));
+    }
+
+    #[test]
+    fn test_parse_window_context() {
+        let tree = r#"{"type": "root", "focused": false, "nodes": [
+            {"type": "output", "name": "eDP-1", "focused": false, "nodes": [
+                {"type": "workspace", "name": "2:web", "focused": false, "nodes": [
+                    {"type": "con", "focused": true, "app_id": "firefox", "name": "Issue 12 - Mozilla Firefox"}
+                ]},
+                {"type": "workspace", "name": "3", "focused": false, "nodes": []}
+            ]}
+        ]}"#;
+        let context = WindowContext {
+            app_id: Some("firefox".to_string()),
+            title: Some("Issue 12 - Mozilla Firefox".to_string()),
+            workspace: Some("2:web".to_string()),
+        };
+        assert_eq!(parse_sway_context(tree), Some(context));
+        let empty = r#"{"type": "root", "focused": false, "nodes": [{"type": "workspace", "name": "3", "focused": true, "nodes": []}]}"#;
+        assert_eq!(parse_sway_context(empty), Some(WindowContext { workspace: Some("3".to_string()), ..Default::default() }));
+
+        let window = r#"{"class": "kitty", "title": "vim", "workspace": {"id": 4, "name": "4"}}"#;
+        assert_eq!(parse_hyprland_context(window).unwrap().workspace.as_deref(), Some("4"));
+        assert_eq!(parse_hyprland_context("{}"), Some(WindowContext::default()));
+    }
+
+    #[test]
+    fn test_parse_x11_context() {
+        let root = "_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007\n\
+                    _NET_CURRENT_DESKTOP(CARDINAL) = 1\n\
+                    _NET_DESKTOP_NAMES(UTF8_STRING) = \"term\", \"web\"\n";
+        let window = "WM_CLASS(STRING) = \"Navigator\", \"firefox\"\n\
+                      _NET_WM_NAME(UTF8_STRING) = \"Issue \\\"12\\\" - Mozilla Firefox\"\n\
+                      WM_NAME(STRING) = \"Issue 12\"\n";
+        assert_eq!(x11_active_window(root), Some("0x3a00007"));
+        assert_eq!(
+            parse_x11_context(root, Some(window)),
+            WindowContext {
+                app_id: Some("firefox".to_string()),
+                title: Some("Issue \"12\" - Mozilla Firefox".to_string()),
+                workspace: Some("web".to_string()),
+            }
+        );
+
+        // No active window, and a desktop without a name
+        let root = "_NET_ACTIVE_WINDOW(WINDOW): window id # 0x0\n_NET_CURRENT_DESKTOP(CARDINAL) = 2\n_NET_DESKTOP_NAMES:  not found.\n";
+        assert_eq!(x11_active_window(root), None);
+        assert_eq!(parse_x11_context(root, None), WindowContext { workspace: Some("3".to_string()), ..Default::default() });
+    }
+}
@@ -0,0 +1,139 @@
+//! Batched scans for new images in the directories the shell hooks watch
+//! (`shell_integration.scan_dirs`).
+//!
+//! Instead of inspecting every image in a directory at each prompt, the
+//! hooks start one `klipdot scan-new` in the background. It hands the scan to
+//! the daemon over IPC when one is running and scans itself otherwise. The
+//! time of each directory's last scan is kept in
+//! `~/.klipdot/scan-state.json`, so only images modified since then are
+//! stored; a directory scanned for the first time contributes the images
+//! from the last [`FIRST_SCAN_WINDOW`].
+
+use crate::{config::Config, error::Result, image_processor::ImageProcessor};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, warn};
+
+/// How far back the first scan of a directory looks
+pub const FIRST_SCAN_WINDOW: Duration = Duration::from_secs(30);
+
+/// Source recorded for images found by a scan
+pub const SCAN_SOURCE: &str = "directory";
+
+/// Images directly in `dir` modified after `since`
+pub fn new_images(dir: &Path, since: SystemTime) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut images: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified().map(|modified| metadata.is_file() && modified > since))
+                .unwrap_or(false)
+        })
+        // Sniffed last so only new files are read
+        .filter(|path| crate::is_image_file(path))
+        .collect();
+    images.sort();
+    images
+}
+
+/// Store the images modified in `dirs` since each was last scanned, returning
+/// the stored paths. `state_dir` is the KlipDot home directory.
+pub async fn scan_new(config: &Config, state_dir: &Path, dirs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let state_path = state_dir.join(crate::SCAN_STATE_FILE);
+    let mut state: HashMap<PathBuf, DateTime<Utc>> = match tokio::fs::read(&state_path).await {
+        Ok(content) => serde_json::from_slice(&content).unwrap_or_default(),
+        Err(_) => HashMap::new(),
+    };
+
+    let now = SystemTime::now();
+    let own_dirs: Vec<PathBuf> = config.screenshot_dirs().iter().filter_map(|dir| dir.canonicalize().ok()).collect();
+    let mut processor = None;
+    let mut stored = Vec::new();
+
+    for dir in dirs {
+        let Ok(dir) = dir.canonicalize() else {
+            continue;
+        };
+        // Images klipdot stored itself would be stored again
+        if own_dirs.contains(&dir) {
+            continue;
+        }
+
+        let since = state
+            .get(&dir)
+            .map(|&scanned| SystemTime::from(scanned))
+            .unwrap_or_else(|| now - FIRST_SCAN_WINDOW);
+        state.insert(dir.clone(), DateTime::<Utc>::from(now));
+
+        for image in new_images(&dir, since) {
+            if processor.is_none() {
+                processor = Some(ImageProcessor::new(config.clone()).await?);
+            }
+            let Some(processor) = &processor else { continue };
+            match processor.process_image_file(&image, SCAN_SOURCE).await {
+                Ok(path) => stored.push(path),
+                Err(e) => warn!("Failed to store {:?}: {}", image, e),
+            }
+        }
+    }
+
+    // Written through a temporary file, since shells may scan at the same time
+    let temp = state_dir.join(format!("{}.{}.tmp", crate::SCAN_STATE_FILE, std::process::id()));
+    tokio::fs::write(&temp, serde_json::to_vec(&state)?).await?;
+    tokio::fs::rename(&temp, &state_path).await?;
+
+    debug!("Scanned {} directories, stored {} images", dirs.len(), stored.len());
+    Ok(stored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write_png(path: &Path) {
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(4, 4)).save(path).unwrap();
+    }
+
+    #[test]
+    fn test_new_images() {
+        let temp_dir = TempDir::new().unwrap();
+        write_png(&temp_dir.path().join("plot.png"));
+        std::fs::write(temp_dir.path().join("notes.txt"), "x").unwrap();
+        std::fs::create_dir(temp_dir.path().join("dir.png")).unwrap();
+
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        assert_eq!(new_images(temp_dir.path(), an_hour_ago), [temp_dir.path().join("plot.png")]);
+        assert!(new_images(temp_dir.path(), SystemTime::now() + Duration::from_secs(60)).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_scan_new() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().join("shots"),
+            ..Config::default()
+        };
+        let desktop = temp_dir.path().join("Desktop");
+        std::fs::create_dir(&desktop).unwrap();
+        write_png(&desktop.join("plot.png"));
+        let watched = vec![desktop];
+
+        let stored = scan_new(&config, temp_dir.path(), &watched).await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert!(stored[0].starts_with(temp_dir.path().join("shots")));
+
+        // Already scanned: nothing new until an image changes
+        assert!(scan_new(&config, temp_dir.path(), &watched).await.unwrap().is_empty());
+
+        // The screenshot directory itself is never scanned
+        let shots = vec![temp_dir.path().join("shots")];
+        assert!(scan_new(&config, temp_dir.path(), &shots).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,156 @@
+//! Visual differences between two renderings of an image, for
+//! `klipdot watch-diff`.
+//!
+//! Pixels are compared channel by channel, ignoring differences up to a
+//! tolerance so antialiasing and compression noise don't count as changes.
+//! The diff image is the new rendering with unchanged pixels dimmed to gray,
+//! so only what changed stands out; pixels one rendering has and the other
+//! doesn't, when the size changed, are marked in `REMOVED`.
+
+use crate::annotate::{Point, Rect};
+use image::{imageops, Rgba, RgbaImage};
+
+/// Largest difference in any channel that doesn't count as a change
+pub const DEFAULT_TOLERANCE: u8 = 8;
+
+/// Color of pixels only the previous rendering had
+const REMOVED: Rgba<u8> = Rgba([255, 0, 255, 255]);
+
+/// Share of their brightness unchanged pixels keep
+const DIM: f32 = 0.25;
+
+#[derive(Debug, Clone)]
+pub struct ImageDiff {
+    /// Pixels that changed, were added or were removed
+    pub changed: u64,
+    /// Pixels in either rendering
+    pub total: u64,
+    /// Smallest rectangle holding every change
+    pub bounds: Option<Rect>,
+    /// The new rendering with unchanged pixels dimmed
+    pub image: RgbaImage,
+}
+
+impl ImageDiff {
+    /// Share of the pixels that changed, from 0 to 1
+    pub fn ratio(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.changed as f64 / self.total as f64
+        }
+    }
+
+    /// The diff image cut down to the changes and `margin` pixels around
+    /// them, so small changes fill the preview; `None` when nothing changed
+    pub fn changed_area(&self, margin: u32) -> Option<RgbaImage> {
+        let bounds = self.bounds?;
+        let x = bounds.x.saturating_sub(margin);
+        let y = bounds.y.saturating_sub(margin);
+        let right = (bounds.x + bounds.width + margin).min(self.image.width());
+        let bottom = (bounds.y + bounds.height + margin).min(self.image.height());
+        Some(imageops::crop_imm(&self.image, x, y, right - x, bottom - y).to_image())
+    }
+
+    /// One line describing the change, like `1.2% changed in 40x12 at 100,80`
+    pub fn summary(&self) -> String {
+        match self.bounds {
+            None => "No visual change".to_string(),
+            Some(bounds) => format!(
+                "{:.1}% changed in {}x{} at {},{}",
+                self.ratio() * 100.0,
+                bounds.width,
+                bounds.height,
+                bounds.x,
+                bounds.y
+            ),
+        }
+    }
+}
+
+/// Compare `previous` with `current`, ignoring channel differences up to `tolerance`
+pub fn diff(previous: &RgbaImage, current: &RgbaImage, tolerance: u8) -> ImageDiff {
+    let width = previous.width().max(current.width());
+    let height = previous.height().max(current.height());
+    let mut image = RgbaImage::new(width, height);
+    let (mut changed, mut min, mut max) = (0, Point { x: u32::MAX, y: u32::MAX }, Point { x: 0, y: 0 });
+
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let before = (x < previous.width() && y < previous.height()).then(|| *previous.get_pixel(x, y));
+        let after = (x < current.width() && y < current.height()).then(|| *current.get_pixel(x, y));
+        let (is_change, shown) = match (before, after) {
+            (Some(before), Some(after)) => {
+                let differs = before.0.iter().zip(after.0).any(|(a, b)| a.abs_diff(b) > tolerance);
+                (differs, if differs { after } else { dimmed(after) })
+            }
+            (None, Some(after)) => (true, after),
+            (Some(_), None) => (true, REMOVED),
+            (None, None) => (false, Rgba([0, 0, 0, 0])),
+        };
+        *pixel = shown;
+        if is_change {
+            changed += 1;
+            min = Point { x: min.x.min(x), y: min.y.min(y) };
+            max = Point { x: max.x.max(x), y: max.y.max(y) };
+        }
+    }
+
+    ImageDiff {
+        changed,
+        total: width as u64 * height as u64,
+        bounds: (changed > 0).then(|| Rect::spanning(min, max)),
+        image,
+    }
+}
+
+/// `pixel` in gray at `DIM` of its brightness
+fn dimmed(pixel: Rgba<u8>) -> Rgba<u8> {
+    let [r, g, b, a] = pixel.0;
+    let luma = (0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32) * DIM;
+    let gray = luma.round() as u8;
+    Rgba([gray, gray, gray, a])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let white = Rgba([255, 255, 255, 255]);
+        let previous = RgbaImage::from_pixel(100, 50, white);
+        let mut current = previous.clone();
+        // Noise within the tolerance, and a real change
+        current.put_pixel(0, 0, Rgba([250, 250, 250, 255]));
+        for x in 40..50 {
+            for y in 10..14 {
+                current.put_pixel(x, y, Rgba([200, 0, 0, 255]));
+            }
+        }
+
+        let diff = diff(&previous, &current, DEFAULT_TOLERANCE);
+        assert_eq!(diff.changed, 40);
+        assert_eq!(diff.bounds, Some(Rect { x: 40, y: 10, width: 10, height: 4 }));
+        assert_eq!(diff.summary(), "0.8% changed in 10x4 at 40,10");
+        assert_eq!(*diff.image.get_pixel(45, 12), Rgba([200, 0, 0, 255]));
+        assert_eq!(*diff.image.get_pixel(0, 0), Rgba([63, 63, 63, 255]));
+        assert_eq!(diff.changed_area(5).unwrap().dimensions(), (20, 14));
+
+        let same = super::diff(&previous, &previous, DEFAULT_TOLERANCE);
+        assert_eq!((same.changed, same.bounds), (0, None));
+        assert!(same.changed_area(5).is_none());
+        assert_eq!(same.summary(), "No visual change");
+    }
+
+    #[test]
+    fn test_diff_resized() {
+        let previous = RgbaImage::from_pixel(10, 10, Rgba([0, 0, 0, 255]));
+        let current = RgbaImage::from_pixel(12, 8, Rgba([0, 0, 0, 255]));
+        let diff = diff(&previous, &current, DEFAULT_TOLERANCE);
+        // Two new columns of 8 and two lost rows of 10, none in the corner neither has
+        assert_eq!(diff.changed, 16 + 20);
+        assert_eq!(diff.total, 120);
+        assert_eq!(*diff.image.get_pixel(5, 9), REMOVED);
+        assert_eq!(diff.image.get_pixel(11, 9)[3], 0);
+    }
+}
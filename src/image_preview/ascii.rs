@@ -0,0 +1,79 @@
+use super::{arg_refs, PreviewBackend};
+use crate::{command_runner::CommandRunner, error::Result, Error};
+use async_trait::async_trait;
+use std::path::Path;
+
+/// ASCII art via `jp2a`, falling back to `img2txt`
+pub struct Ascii;
+
+#[async_trait]
+impl PreviewBackend for Ascii {
+    fn name(&self) -> &str {
+        "ascii"
+    }
+
+    async fn detect(&self, runner: &dyn CommandRunner) -> bool {
+        runner.is_available("jp2a") || runner.is_available("img2txt")
+    }
+
+    async fn render(&self, runner: &dyn CommandRunner, image_path: &Path, max_width: Option<u32>, max_height: Option<u32>) -> Result<Vec<u8>> {
+        let path = image_path.to_string_lossy().into_owned();
+
+        // Try jp2a first (usually better quality)
+        if runner.is_available("jp2a") {
+            let mut args = vec!["--colors".to_string()];
+
+            if let Some(width) = max_width {
+                args.push("--width".to_string());
+                args.push(width.to_string());
+            }
+
+            if let Some(height) = max_height {
+                args.push("--height".to_string());
+                args.push(height.to_string());
+            }
+
+            args.push(path.clone());
+
+            if let Ok(output) = runner.run("jp2a", &arg_refs(&args), None).await {
+                if output.success {
+                    return Ok(output.stdout);
+                }
+            }
+        }
+
+        // Fallback to img2txt
+        if runner.is_available("img2txt") {
+            let mut args = Vec::new();
+
+            if let Some(width) = max_width {
+                args.push("-W".to_string());
+                args.push(width.to_string());
+            }
+
+            if let Some(height) = max_height {
+                args.push("-H".to_string());
+                args.push(height.to_string());
+            }
+
+            args.push(path);
+
+            let output = runner.run("img2txt", &arg_refs(&args), None).await
+                .map_err(|e| Error::Process(format!("Failed to run img2txt: {}", e)))?;
+
+            if output.success {
+                return Ok(output.stdout);
+            }
+        }
+
+        Err(Error::Unsupported("No ASCII art tools available".to_string()))
+    }
+
+    fn preview_command(&self, runner: &dyn CommandRunner, image_path: &Path) -> String {
+        if runner.is_available("jp2a") {
+            format!("jp2a --colors '{}'", image_path.display())
+        } else {
+            format!("img2txt '{}'", image_path.display())
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use super::{CellSize, PreviewRegistry};
+use crate::{command_runner::SharedRunner, config::DecodeLimits, error::Result, Error};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tracing::debug;
+
+/// Identifies one rendering; a changed file gets a new key through its mtime and size
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CacheKey {
+    backend: String,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    len: u64,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+}
+
+/// Renders previews with any registered backend and keeps the most recent
+/// outputs, so repeated previews skip decoding and encoding.
+///
+/// Used by the daemon to answer preview requests from short-lived clients.
+pub struct CachedRenderer {
+    registry: PreviewRegistry,
+    runner: SharedRunner,
+    capacity: usize,
+    decode_limits: DecodeLimits,
+    /// Most recently used last
+    entries: Mutex<VecDeque<(CacheKey, Arc<Vec<u8>>)>>,
+}
+
+impl CachedRenderer {
+    pub fn new(registry: PreviewRegistry, runner: SharedRunner, capacity: usize) -> Self {
+        Self {
+            registry,
+            runner,
+            capacity,
+            decode_limits: DecodeLimits::default(),
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Decode images within `limits` rather than the defaults
+    pub fn with_decode_limits(mut self, limits: DecodeLimits) -> Self {
+        self.decode_limits = limits;
+        self
+    }
+
+    /// Render `image_path` with the named backend at a size in cells of
+    /// `cell_size`, returning the output and whether it came from the cache
+    pub async fn render(
+        &self,
+        backend: &str,
+        image_path: &Path,
+        max_width: Option<u32>,
+        max_height: Option<u32>,
+        cell_size: Option<CellSize>,
+    ) -> Result<(Arc<Vec<u8>>, bool)> {
+        let renderer = self
+            .registry
+            .get(backend)
+            .ok_or_else(|| Error::NotFound(format!("Unknown preview backend: {}", backend)))?;
+        if !renderer.cacheable() {
+            return Err(Error::Unsupported(format!("{} previews cannot be rendered remotely", backend)));
+        }
+
+        let (max_width, max_height) = super::backend_size(renderer.as_ref(), cell_size, max_width, max_height);
+        let metadata = std::fs::metadata(image_path)
+            .map_err(|e| Error::NotFound(format!("Image file not found: {:?}: {}", image_path, e)))?;
+        let key = CacheKey {
+            backend: backend.to_string(),
+            path: image_path.to_path_buf(),
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            max_width,
+            max_height,
+        };
+
+        if let Some(output) = self.lookup(&key) {
+            debug!("Preview cache hit for {:?} ({})", image_path, backend);
+            return Ok((output, true));
+        }
+
+        let output = Arc::new(super::render_with(renderer.as_ref(), self.runner.as_ref(), image_path, max_width, max_height, None, &self.decode_limits).await?);
+        self.insert(key, output.clone());
+        Ok((output, false))
+    }
+
+    fn lookup(&self, key: &CacheKey) -> Option<Arc<Vec<u8>>> {
+        let mut entries = self.entries.lock().unwrap_or_else(|p| p.into_inner());
+        let index = entries.iter().position(|(k, _)| k == key)?;
+        let entry = entries.remove(index)?;
+        let output = entry.1.clone();
+        entries.push_back(entry);
+        Some(output)
+    }
+
+    fn insert(&self, key: CacheKey, output: Arc<Vec<u8>>) {
+        let mut entries = self.entries.lock().unwrap_or_else(|p| p.into_inner());
+        entries.retain(|(k, _)| k != &key);
+        entries.push_back((key, output));
+        while entries.len() > self.capacity {
+            entries.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+    use crate::image_preview::{ExternalViewer, Kitty};
+
+    #[tokio::test]
+    async fn test_cached_renderer() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let first = temp_dir.path().join("first.png");
+        let second = temp_dir.path().join("second.png");
+        std::fs::write(&first, b"png").unwrap();
+        std::fs::write(&second, b"png").unwrap();
+
+        let runner = Arc::new(FakeRunner::new().with_output("kitten", CommandOutput::ok("\x1b_Gimage\x1b\\")));
+        let mut registry = PreviewRegistry::new();
+        registry.register(Arc::new(Kitty));
+        registry.register(Arc::new(ExternalViewer::new("open")));
+        let renderer = CachedRenderer::new(registry, runner.clone(), 1);
+
+        let (output, cached) = renderer.render("kitty", &first, Some(40), None, None).await.unwrap();
+        assert_eq!(output.as_slice(), b"\x1b_Gimage\x1b\\");
+        assert!(!cached);
+        assert!(renderer.render("kitty", &first, Some(40), None, None).await.unwrap().1);
+        assert_eq!(runner.calls_to("kitten").len(), 1);
+
+        // Different geometry renders again, and capacity 1 evicts the older entry
+        assert!(!renderer.render("kitty", &first, Some(80), None, None).await.unwrap().1);
+        assert!(!renderer.render("kitty", &second, Some(80), None, None).await.unwrap().1);
+        assert!(!renderer.render("kitty", &first, Some(80), None, None).await.unwrap().1);
+
+        assert!(matches!(renderer.render("open", &first, None, None, None).await, Err(Error::Unsupported(_))));
+        assert!(matches!(renderer.render("sixel", &first, None, None, None).await, Err(Error::NotFound(_))));
+    }
+}
@@ -0,0 +1,157 @@
+//! The size of a terminal cell in pixels, for sizing previews drawn by
+//! backends that take pixels.
+//!
+//! Previews are sized in cells, so a 40-column preview covers the same part
+//! of the screen whatever the display's density: on a HiDPI display each
+//! cell has more pixels, and the preview is given more of them. The cell
+//! size comes from the window size the kernel keeps for the terminal
+//! (`TIOCGWINSZ`), kitty's `kitten icat --print-window-size`, or the
+//! terminal's answer to an XTWINOPS query (`CSI 16 t`), in that order.
+
+use crate::command_runner::CommandRunner;
+use tracing::debug;
+
+/// Cell size assumed when the terminal doesn't report one, typical of a
+/// 96 DPI display
+pub const DEFAULT_CELL_SIZE: CellSize = CellSize { width: 10, height: 20 };
+
+/// How long to wait for the terminal to answer a query
+#[cfg(unix)]
+const QUERY_TIMEOUT_MS: i32 = 150;
+
+/// Pixels per terminal cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CellSize {
+    /// Cell size of a window `pixels` large holding `cells` columns and rows
+    pub fn of_window(pixels: (u32, u32), cells: (u16, u16)) -> Option<Self> {
+        let (columns, rows) = (cells.0 as u32, cells.1 as u32);
+        if pixels.0 == 0 || pixels.1 == 0 || columns == 0 || rows == 0 {
+            return None;
+        }
+        Some(Self {
+            width: (pixels.0 / columns).max(1),
+            height: (pixels.1 / rows).max(1),
+        })
+    }
+
+    /// A size in cells as pixels
+    pub fn to_pixels(self, width: Option<u32>, height: Option<u32>) -> (Option<u32>, Option<u32>) {
+        (width.map(|width| width * self.width), height.map(|height| height * self.height))
+    }
+}
+
+/// The cell size of the controlling terminal, if it can be found out
+pub async fn detect(runner: &dyn CommandRunner) -> Option<CellSize> {
+    let cells = crossterm::terminal::size().ok()?;
+    if let Ok(window) = crossterm::terminal::window_size() {
+        if let Some(cell_size) = CellSize::of_window((window.width as u32, window.height as u32), cells) {
+            debug!("Cell size from the window size: {:?}", cell_size);
+            return Some(cell_size);
+        }
+    }
+
+    if std::env::var("TERM").is_ok_and(|term| term.contains("kitty")) && runner.is_available("kitten") {
+        if let Ok(output) = runner.run("kitten", &["icat", "--print-window-size"], None).await {
+            if let Some(cell_size) = parse_window_size(&output.stdout_lossy()).and_then(|pixels| CellSize::of_window(pixels, cells)) {
+                debug!("Cell size from kitty: {:?}", cell_size);
+                return Some(cell_size);
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    {
+        let reply = tokio::task::spawn_blocking(query_cell_size).await.ok().flatten();
+        if let Some(cell_size) = reply.as_deref().and_then(parse_xtwinops_reply) {
+            debug!("Cell size from XTWINOPS: {:?}", cell_size);
+            return Some(cell_size);
+        }
+    }
+    None
+}
+
+/// `1280x800`, as `kitten icat --print-window-size` prints it
+fn parse_window_size(output: &str) -> Option<(u32, u32)> {
+    let (width, height) = output.trim().split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+/// The cell size in a `CSI 6 ; height ; width t` reply
+fn parse_xtwinops_reply(reply: &[u8]) -> Option<CellSize> {
+    let reply = std::str::from_utf8(reply).ok()?;
+    let start = reply.find("\x1b[6;")?;
+    let body = reply[start + 4..].split('t').next()?;
+    let (height, width) = body.split_once(';')?;
+    let cell_size = CellSize { width: width.parse().ok()?, height: height.parse().ok()? };
+    (cell_size.width > 0 && cell_size.height > 0).then_some(cell_size)
+}
+
+/// Ask the terminal on `/dev/tty` for its cell size, returning what it
+/// answered within `QUERY_TIMEOUT_MS`
+#[cfg(unix)]
+fn query_cell_size() -> Option<Vec<u8>> {
+    use std::io::{Read, Write};
+    use std::os::fd::AsRawFd;
+
+    let mut tty = std::fs::OpenOptions::new().read(true).write(true).open("/dev/tty").ok()?;
+    // Raw mode so the reply isn't echoed and arrives without a newline
+    let was_raw = crossterm::terminal::is_raw_mode_enabled().unwrap_or(false);
+    if !was_raw {
+        crossterm::terminal::enable_raw_mode().ok()?;
+    }
+
+    let mut reply = Vec::new();
+    if tty.write_all(b"\x1b[16t").and_then(|()| tty.flush()).is_ok() {
+        let mut poll = libc::pollfd { fd: tty.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        let mut buffer = [0u8; 64];
+        // Terminals without XTWINOPS stay silent, so each read waits at most the timeout
+        while !reply.ends_with(b"t") && reply.len() < 256 {
+            // SAFETY: `poll` points to one valid pollfd for the duration of the call
+            if unsafe { libc::poll(&mut poll, 1, QUERY_TIMEOUT_MS) } <= 0 {
+                break;
+            }
+            match tty.read(&mut buffer) {
+                Ok(read) if read > 0 => reply.extend_from_slice(&buffer[..read]),
+                _ => break,
+            }
+        }
+    }
+
+    if !was_raw {
+        let _ = crossterm::terminal::disable_raw_mode();
+    }
+    (!reply.is_empty()).then_some(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cell_size() {
+        // The same 40 columns take twice the pixels on a display twice as dense
+        let normal = CellSize::of_window((1600, 900), (160, 45)).unwrap();
+        let hidpi = CellSize::of_window((3200, 1800), (160, 45)).unwrap();
+        assert_eq!(normal, CellSize { width: 10, height: 20 });
+        assert_eq!(normal.to_pixels(Some(40), None), (Some(400), None));
+        assert_eq!(hidpi.to_pixels(Some(40), Some(10)), (Some(800), Some(400)));
+        // Terminals that don't fill in the pixel size report zero
+        assert_eq!(CellSize::of_window((0, 0), (160, 45)), None);
+    }
+
+    #[test]
+    fn test_parse_replies() {
+        assert_eq!(parse_window_size("2560x1600\n"), Some((2560, 1600)));
+        assert_eq!(parse_window_size("error"), None);
+        assert_eq!(parse_xtwinops_reply(b"\x1b[6;34;17t"), Some(CellSize { width: 17, height: 34 }));
+        // Anything the user typed meanwhile may come first
+        assert_eq!(parse_xtwinops_reply(b"ls\x1b[6;20;10t"), Some(CellSize { width: 10, height: 20 }));
+        assert_eq!(parse_xtwinops_reply(b"\x1b[6;0;0t"), None);
+        assert_eq!(parse_xtwinops_reply(b"\x1b[?62;4c"), None);
+    }
+}
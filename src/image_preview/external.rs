@@ -0,0 +1,116 @@
+use super::{arg_refs, PreviewBackend};
+use crate::{command_runner::CommandRunner, error::Result, Error};
+use async_trait::async_trait;
+use std::path::Path;
+
+/// A standalone viewer program (chafa, catimg, timg, imgcat, qlmanage, open, ...)
+pub struct ExternalViewer {
+    viewer: String,
+    /// Only detect inside this terminal (`$TERM_PROGRAM`)
+    term_program: Option<&'static str>,
+}
+
+impl ExternalViewer {
+    pub fn new(viewer: &str) -> Self {
+        Self {
+            viewer: viewer.to_string(),
+            term_program: None,
+        }
+    }
+
+    /// A viewer that is only auto-detected inside the given terminal
+    pub fn for_terminal(viewer: &str, term_program: &'static str) -> Self {
+        Self {
+            viewer: viewer.to_string(),
+            term_program: Some(term_program),
+        }
+    }
+
+    fn args(&self, path: String, max_width: Option<u32>, max_height: Option<u32>) -> Vec<String> {
+        let mut args = Vec::new();
+
+        match self.viewer.as_str() {
+            "catimg" => {
+                if let Some(width) = max_width {
+                    args.push("-w".to_string());
+                    args.push(width.to_string());
+                }
+            }
+            "timg" => {
+                if let Some(width) = max_width {
+                    args.push("-g".to_string());
+                    args.push(format!("{}x{}", width, max_height.unwrap_or(width)));
+                }
+            }
+            "chafa" => {
+                // chafa tool - modern ASCII art generator
+                args.push("--size".to_string());
+                if let Some(width) = max_width {
+                    args.push(format!("{}x{}", width, max_height.unwrap_or(width / 2)));
+                } else {
+                    args.push("80x40".to_string());
+                }
+                args.push("--format".to_string());
+                args.push("symbols".to_string());
+            }
+            "qlmanage" => {
+                args.push("-p".to_string());
+            }
+            _ => {}
+        }
+
+        args.push(path);
+        args
+    }
+}
+
+#[async_trait]
+impl PreviewBackend for ExternalViewer {
+    fn name(&self) -> &str {
+        &self.viewer
+    }
+
+    async fn detect(&self, runner: &dyn CommandRunner) -> bool {
+        if let Some(term_program) = self.term_program {
+            if std::env::var("TERM_PROGRAM").ok().as_deref() != Some(term_program) {
+                return false;
+            }
+        }
+        runner.is_available(&self.viewer)
+    }
+
+    async fn render(&self, runner: &dyn CommandRunner, image_path: &Path, max_width: Option<u32>, max_height: Option<u32>) -> Result<Vec<u8>> {
+        let args = self.args(image_path.to_string_lossy().into_owned(), max_width, max_height);
+        let file_name = image_path.file_name().unwrap_or_default().to_string_lossy();
+
+        // GUI viewers are launched in the background and return immediately
+        match self.viewer.as_str() {
+            "qlmanage" => {
+                let _ = runner.spawn_detached(&self.viewer, &arg_refs(&args)).await;
+                return Ok(format!("🖼️  Opening with QuickLook: {}\n", file_name).into_bytes());
+            }
+            "open" => {
+                let _ = runner.spawn_detached(&self.viewer, &arg_refs(&args)).await;
+                return Ok(format!("🖼️  Opening with default app: {}\n", file_name).into_bytes());
+            }
+            _ => {}
+        }
+
+        let output = runner.run(&self.viewer, &arg_refs(&args), None).await
+            .map_err(|e| Error::Process(format!("Failed to run {}: {}", self.viewer, e)))?;
+
+        if output.success {
+            Ok(output.stdout)
+        } else {
+            Err(Error::Process(format!("{} preview failed: {}", self.viewer, output.stderr_lossy())))
+        }
+    }
+
+    fn cacheable(&self) -> bool {
+        !matches!(self.viewer.as_str(), "qlmanage" | "open")
+    }
+
+    fn preview_command(&self, _runner: &dyn CommandRunner, image_path: &Path) -> String {
+        format!("{} '{}'", self.viewer, image_path.display())
+    }
+}
@@ -0,0 +1,43 @@
+use super::PreviewBackend;
+use crate::{command_runner::CommandRunner, error::Result};
+use async_trait::async_trait;
+use base64::engine::general_purpose;
+use base64::Engine;
+use std::path::Path;
+
+/// iTerm2 inline images protocol
+pub struct ITerm2;
+
+#[async_trait]
+impl PreviewBackend for ITerm2 {
+    fn name(&self) -> &str {
+        "iterm2"
+    }
+
+    fn sizes_in_pixels(&self) -> bool {
+        true
+    }
+
+    async fn detect(&self, _runner: &dyn CommandRunner) -> bool {
+        std::env::var("TERM_PROGRAM").is_ok_and(|term_program| term_program == "iTerm.app")
+    }
+
+    async fn render(&self, _runner: &dyn CommandRunner, image_path: &Path, max_width: Option<u32>, max_height: Option<u32>) -> Result<Vec<u8>> {
+        let image_data = std::fs::read(image_path)?;
+        let base64_data = general_purpose::STANDARD.encode(&image_data);
+
+        let width_param = max_width.map(|w| format!(";width={}px", w)).unwrap_or_default();
+        let height_param = max_height.map(|h| format!(";height={}px", h)).unwrap_or_default();
+
+        // iTerm2 inline image sequence
+        let escape_sequence = format!(
+            "\x1b]1337;File=inline=1;preserveAspectRatio=1{}{};size={}:{}\x07",
+            width_param,
+            height_param,
+            image_data.len(),
+            base64_data
+        );
+
+        Ok(escape_sequence.into_bytes())
+    }
+}
@@ -0,0 +1,74 @@
+use super::{run_preview_tool, PreviewBackend};
+use crate::{command_runner::CommandRunner, error::Result, temp_files, Error};
+use async_trait::async_trait;
+use std::path::Path;
+use tracing::debug;
+
+/// Kitty graphics protocol via `kitten icat`
+pub struct Kitty;
+
+#[async_trait]
+impl PreviewBackend for Kitty {
+    fn name(&self) -> &str {
+        "kitty"
+    }
+
+    async fn detect(&self, _runner: &dyn CommandRunner) -> bool {
+        std::env::var("TERM").is_ok_and(|term| term.contains("kitty"))
+    }
+
+    async fn render(&self, runner: &dyn CommandRunner, image_path: &Path, max_width: Option<u32>, max_height: Option<u32>) -> Result<Vec<u8>> {
+        let mut args = vec!["icat".to_string()];
+
+        if let Some(width) = max_width {
+            args.push("--cols".to_string());
+            args.push(width.to_string());
+        }
+
+        if let Some(height) = max_height {
+            args.push("--rows".to_string());
+            args.push(height.to_string());
+        }
+
+        args.push(image_path.to_string_lossy().into_owned());
+
+        run_preview_tool(runner, "kitten", &args, "Kitty").await
+    }
+}
+
+/// Whether this process runs in a kitty window that `kitten @` can address
+pub fn in_kitty_window() -> bool {
+    std::env::var_os("KITTY_WINDOW_ID").is_some()
+}
+
+/// Show `image_path` in an overlay window over the current kitty window,
+/// closed with any key. Needs `allow_remote_control` in kitty.conf.
+///
+/// The overlay gets its own copy of the image, which it removes on close, so
+/// the caller can delete the original straight away.
+pub async fn launch_overlay(runner: &dyn CommandRunner, image_path: &Path) -> Result<()> {
+    let file_name = image_path.file_name().unwrap_or_default().to_string_lossy();
+    let extension = image_path.extension().map(|ext| ext.to_string_lossy().to_string()).unwrap_or_default();
+    // Written rather than copied, which would give it the original's permissions
+    let copy = temp_files::create("overlay-", &extension)?;
+    tokio::fs::write(&copy, tokio::fs::read(image_path).await?).await?;
+
+    let title = format!("klipdot: {}", file_name);
+    let copy_arg = copy.to_string_lossy();
+    let args: &[&str] = &[
+        "@", "launch", "--type=overlay", "--title", &title, "--",
+        "sh", "-c", r#"kitten icat --hold -- "$1"; rm -f -- "$1""#, "klipdot-overlay", &copy_arg,
+    ];
+
+    let result = runner.run("kitten", args, None).await;
+    let error = match result {
+        Ok(output) if output.success => {
+            debug!("Opened kitty overlay for {:?}", image_path);
+            copy.keep();
+            return Ok(());
+        }
+        Ok(output) => Error::Unsupported(format!("kitty remote control failed: {}", output.stderr_lossy().trim())),
+        Err(e) => Error::Process(format!("Failed to run kitten: {}", e)),
+    };
+    Err(error)
+}
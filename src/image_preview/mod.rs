@@ -0,0 +1,695 @@
+use crate::{
+    command_runner::{self, CommandRunner, SharedRunner},
+    config::{BitDepth, Config, DecodeLimits, PreviewMethod, SlowLinkConfig}, decode_guard, error::Result, image_stats::{self, ImageStats}, output, svg_sanitize, temp_files::{self, TempFile}, tone_map, Error,
+};
+use async_trait::async_trait;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+
+mod ascii;
+mod cache;
+mod cell_size;
+mod external;
+mod iterm2;
+mod kitty;
+mod sixel;
+mod slow_link;
+
+pub use ascii::Ascii;
+pub use cache::CachedRenderer;
+pub use cell_size::CellSize;
+pub use external::ExternalViewer;
+pub use iterm2::ITerm2;
+pub use kitty::Kitty;
+pub use sixel::Sixel;
+
+/// A terminal image protocol or viewer that can render previews
+#[async_trait]
+pub trait PreviewBackend: Send + Sync {
+    /// Name used to select the backend in config (`preview.backend`)
+    fn name(&self) -> &str;
+
+    /// Whether the backend can render in the current terminal
+    async fn detect(&self, runner: &dyn CommandRunner) -> bool;
+
+    /// Render `image_path`, returning the bytes to write to the terminal.
+    /// The size is in cells, or pixels for backends that [size in pixels].
+    ///
+    /// [size in pixels]: PreviewBackend::sizes_in_pixels
+    async fn render(&self, runner: &dyn CommandRunner, image_path: &Path, max_width: Option<u32>, max_height: Option<u32>) -> Result<Vec<u8>>;
+
+    /// Whether `render` takes its size in pixels; previews are sized in
+    /// cells, which are converted with the terminal's cell size
+    fn sizes_in_pixels(&self) -> bool {
+        false
+    }
+
+    /// Whether the rendered output can be cached and replayed elsewhere;
+    /// false for viewers that open their own window
+    fn cacheable(&self) -> bool {
+        true
+    }
+
+    /// Render a copy of an image reduced for a slow link; backends whose
+    /// output depends on more than the image, like sixel's palette, shrink
+    /// it further
+    async fn render_reduced(
+        &self,
+        runner: &dyn CommandRunner,
+        image_path: &Path,
+        max_width: Option<u32>,
+        max_height: Option<u32>,
+        _slow_link: &SlowLinkConfig,
+    ) -> Result<Vec<u8>> {
+        self.render(runner, image_path, max_width, max_height).await
+    }
+
+    /// Shell command that shows the same preview
+    fn preview_command(&self, _runner: &dyn CommandRunner, image_path: &Path) -> String {
+        format!("klipdot preview '{}'", image_path.display())
+    }
+}
+
+/// Ordered set of preview backends; detection picks the first one that reports support
+#[derive(Clone, Default)]
+pub struct PreviewRegistry {
+    backends: Vec<Arc<dyn PreviewBackend>>,
+}
+
+impl PreviewRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registry with the built-in backends in order of preference
+    pub fn with_builtin() -> Self {
+        let mut registry = Self::new();
+        registry.register(Arc::new(ITerm2));
+        // Apple Terminal has no inline protocol, so hand off to QuickLook
+        registry.register(Arc::new(ExternalViewer::for_terminal("qlmanage", "Apple_Terminal")));
+        registry.register(Arc::new(Kitty));
+        registry.register(Arc::new(Sixel));
+        for viewer in ["imgcat", "chafa", "catimg", "timg", "qlmanage", "open"] {
+            registry.register(Arc::new(ExternalViewer::new(viewer)));
+        }
+        registry.register(Arc::new(Ascii));
+        registry
+    }
+
+    /// Add a backend after the existing ones
+    pub fn register(&mut self, backend: Arc<dyn PreviewBackend>) {
+        self.backends.push(backend);
+    }
+
+    /// Look up a backend by name
+    pub fn get(&self, name: &str) -> Option<Arc<dyn PreviewBackend>> {
+        self.backends.iter().find(|backend| backend.name() == name).cloned()
+    }
+
+    /// Names of all registered backends, without duplicates
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for backend in &self.backends {
+            if !names.iter().any(|name| name == backend.name()) {
+                names.push(backend.name().to_string());
+            }
+        }
+        names
+    }
+
+    /// First backend that can render in the current terminal
+    pub async fn detect(&self, runner: &dyn CommandRunner) -> Option<Arc<dyn PreviewBackend>> {
+        for backend in &self.backends {
+            if backend.detect(runner).await {
+                return Some(backend.clone());
+            }
+        }
+        None
+    }
+
+    /// Backend named in the config, or the detected one when unset or unknown
+    pub async fn select(&self, config: &Config, runner: &dyn CommandRunner) -> Option<Arc<dyn PreviewBackend>> {
+        if let Some(ref name) = config.preview.backend {
+            if let Some(backend) = self.get(name) {
+                return Some(backend);
+            }
+            warn!("Unknown preview backend '{}', available: {}", name, self.names().join(", "));
+        }
+        self.detect(runner).await
+    }
+}
+
+/// Terminal image preview system supporting multiple protocols
+#[derive(Clone)]
+pub struct ImagePreviewManager {
+    #[allow(dead_code)]
+    config: Config,
+    backend: Option<Arc<dyn PreviewBackend>>,
+    runner: SharedRunner,
+    /// Settings previews are reduced with, when the link is slow
+    slow_link: Option<SlowLinkConfig>,
+    /// Pixels per cell, found out for backends that size in pixels
+    cell_size: Option<CellSize>,
+}
+
+impl ImagePreviewManager {
+    pub async fn new(config: Config) -> Result<Self> {
+        Self::with_registry(config, &PreviewRegistry::with_builtin()).await
+    }
+
+    /// Create a manager choosing from a custom set of backends
+    pub async fn with_registry(config: Config, registry: &PreviewRegistry) -> Result<Self> {
+        let runner = command_runner::system();
+        let backend = registry.select(&config, runner.as_ref()).await;
+        info!("Image preview backend: {}", backend.as_ref().map_or("none", |backend| backend.name()));
+        let slow_link = slow_link::is_slow(&config.preview.slow_link, runner.as_ref())
+            .await
+            .then(|| config.preview.slow_link.clone());
+        if slow_link.is_some() {
+            info!("Slow link, reducing previews");
+        }
+        let cell_size = match &backend {
+            Some(backend) if backend.sizes_in_pixels() => cell_size::detect(runner.as_ref()).await,
+            _ => None,
+        };
+
+        Ok(Self {
+            config,
+            backend,
+            runner,
+            slow_link,
+            cell_size,
+        })
+    }
+
+    /// Replace the runner used to invoke preview tools
+    pub fn set_command_runner(&mut self, runner: SharedRunner) {
+        self.runner = runner;
+    }
+
+    /// Whether previews are reduced for a slow link
+    pub fn reduces_previews(&self) -> bool {
+        self.slow_link.is_some()
+    }
+
+    /// Pixels per cell of the terminal, when the backend needed them and
+    /// the terminal reported them
+    pub fn cell_size(&self) -> Option<CellSize> {
+        self.cell_size
+    }
+
+    /// Name of the backend in use, if any
+    pub fn backend_name(&self) -> Option<&str> {
+        self.backend.as_ref().map(|backend| backend.name())
+    }
+
+    /// Preview image data from stdin
+    pub async fn preview_stdin_data(&self, data: Vec<u8>) -> Result<()> {
+        // Removed when dropped, even if previewing fails
+        let temp_file = temp_files::write("stdin-", "png", &data)?;
+        self.show_preview(&temp_file, None, None).await
+    }
+
+    /// Create a compact preview for LSP-style display, with the image's
+    /// dominant colors, brightness and whether it's mostly text when it can be decoded
+    pub async fn show_compact_preview(&self, image_path: &Path) -> Result<String> {
+        if !image_path.exists() {
+            return Err(Error::NotFound(format!("Image file not found: {:?}", image_path)));
+        }
+
+        let metadata = std::fs::metadata(image_path)?;
+        let file_name = image_path.file_name().unwrap_or_default().to_string_lossy();
+        let file_size = Self::format_file_size(metadata.len());
+        let dimensions = self.get_image_dimensions(image_path).await.unwrap_or_default();
+
+        let mut info = format!("🖼️ {}", file_name);
+        if !dimensions.is_empty() {
+            info.push_str(&format!(" ({})", dimensions));
+        }
+        info.push_str(&format!(" - {}", file_size));
+        if let Some(stats) = self.image_stats(image_path).await {
+            info.push_str(&format!(" · {}", stats.summary()));
+        }
+
+        Ok(info)
+    }
+
+    async fn image_stats(&self, image_path: &Path) -> Option<ImageStats> {
+        let (path, limits) = (image_path.to_path_buf(), self.config.decode_limits.clone());
+        let decoded = tokio::task::spawn_blocking(move || {
+            decode_guard::open(&path, &limits).map(|img| image_stats::analyze(&tone_map::prepare(img, BitDepth::Reduce)))
+        })
+        .await
+        .ok()?;
+        match decoded {
+            Ok(stats) => Some(stats),
+            Err(e) => {
+                debug!("No statistics for {:?}: {}", image_path, e);
+                None
+            }
+        }
+    }
+
+    /// Show an image preview in the terminal
+    pub async fn show_preview(&self, image_path: &Path, max_width: Option<u32>, max_height: Option<u32>) -> Result<()> {
+        let rendered = self.render_preview(image_path, max_width, max_height).await?;
+
+        let mut stdout = std::io::stdout();
+        stdout.write_all(&rendered)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    /// Show an image in a dismissible kitty overlay window, so full-screen
+    /// programs keep their screen intact. Outside kitty, or with remote
+    /// control disabled, the preview is shown inline instead.
+    pub async fn show_overlay_preview(&self, image_path: &Path, max_width: Option<u32>, max_height: Option<u32>) -> Result<()> {
+        if !image_path.exists() {
+            return Err(Error::NotFound(format!("Image file not found: {:?}", image_path)));
+        }
+
+        if kitty::in_kitty_window() && self.runner.is_available("kitten") {
+            let sanitized = self.sanitized_svg(image_path).await?;
+            match kitty::launch_overlay(self.runner.as_ref(), sanitized.as_deref().unwrap_or(image_path)).await {
+                Ok(()) => return Ok(()),
+                Err(e) => debug!("No kitty overlay, previewing inline: {}", e),
+            }
+        }
+        self.show_preview(image_path, max_width, max_height).await
+    }
+
+    /// Show an image the way `preview.method` asks, at the configured size;
+    /// `auto` previews inline
+    pub async fn show_automatic_preview(&self, image_path: &Path) -> Result<()> {
+        let preview = &self.config.preview;
+        match preview.method {
+            PreviewMethod::Auto | PreviewMethod::Inline => {
+                let (width, height) = preview.size();
+                self.show_preview(image_path, width, height).await
+            }
+            PreviewMethod::Overlay => {
+                let (width, height) = preview.max_size();
+                self.show_overlay_preview(image_path, width, height).await
+            }
+            PreviewMethod::Compact => {
+                output::status("", self.show_compact_preview(image_path).await?);
+                Ok(())
+            }
+        }
+    }
+
+    /// Render an image preview without writing it, for callers that place the output themselves
+    pub async fn render_preview(&self, image_path: &Path, max_width: Option<u32>, max_height: Option<u32>) -> Result<Vec<u8>> {
+        if !image_path.exists() {
+            return Err(Error::NotFound(format!("Image file not found: {:?}", image_path)));
+        }
+
+        match &self.backend {
+            Some(backend) => {
+                debug!("Showing preview for: {:?} using backend: {}", image_path, backend.name());
+                let (max_width, max_height) = backend_size(backend.as_ref(), self.cell_size, max_width, max_height);
+                let sanitized = self.sanitized_svg(image_path).await?;
+                let path = sanitized.as_deref().unwrap_or(image_path);
+                render_with(backend.as_ref(), self.runner.as_ref(), path, max_width, max_height, self.slow_link.as_ref(), &self.config.decode_limits).await
+            }
+            None => {
+                warn!("No preview method available for image: {:?}", image_path);
+                Ok(self.text_info(image_path).await?.into_bytes())
+            }
+        }
+    }
+
+    /// A temporary sanitized copy of `image_path` to hand to viewers when it's
+    /// an SVG
+    async fn sanitized_svg(&self, image_path: &Path) -> Result<Option<TempFile>> {
+        let copy = temp_files::create("preview-", "svg")?;
+        let sanitized = svg_sanitize::sanitized_copy(image_path, copy.to_path_buf(), self.config.svg.sanitize).await?;
+        Ok(sanitized.map(|_| copy))
+    }
+
+    /// Text information about the image (fallback)
+    async fn text_info(&self, image_path: &Path) -> Result<String> {
+        let metadata = std::fs::metadata(image_path)?;
+        let file_name = image_path.file_name().unwrap_or_default().to_string_lossy();
+        let file_size = Self::format_file_size(metadata.len());
+
+        // Try to get image dimensions if possible
+        let dimensions = self.get_image_dimensions(image_path).await.unwrap_or_default();
+
+        let mut info = format!("📸 Image: {}\n📏 Size: {}\n", file_name, file_size);
+        if !dimensions.is_empty() {
+            info.push_str(&format!("🖼️  Dimensions: {}\n", dimensions));
+        }
+        info.push_str(&format!("📁 Path: {}\n", image_path.display()));
+
+        // On macOS, offer to open with QuickLook
+        if cfg!(target_os = "macos") {
+            info.push_str(&format!("💡 Tip: Run 'qlmanage -p \"{}\"' to preview with QuickLook\n", image_path.display()));
+            info.push_str(&format!("💡 Or: 'open \"{}\"' to open with default app\n", image_path.display()));
+        }
+
+        Ok(info)
+    }
+
+    async fn get_image_dimensions(&self, image_path: &Path) -> Option<String> {
+        let path = image_path.to_string_lossy();
+
+        // Try using ImageMagick identify command
+        if self.runner.is_available("identify") {
+            if let Ok(output) = self.runner.run("identify", &["-format", "%wx%h", &path], None).await {
+                if output.success {
+                    let dimensions = output.stdout_lossy().trim().to_string();
+                    if !dimensions.is_empty() {
+                        return Some(dimensions);
+                    }
+                }
+            }
+        }
+
+        // Try using file command
+        if let Ok(output) = self.runner.run("file", &[&path], None).await {
+            if output.success {
+                // Parse dimensions from file output (format varies)
+                if let Some(dims) = Self::parse_file_dimensions(&output.stdout_lossy()) {
+                    return Some(dims);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn parse_file_dimensions(file_output: &str) -> Option<String> {
+        // Look for patterns like "1920 x 1080" or "1920x1080"
+        let re = regex::Regex::new(r"(\d+)\s*[x×]\s*(\d+)").ok()?;
+        if let Some(caps) = re.captures(file_output) {
+            let width = caps.get(1)?.as_str();
+            let height = caps.get(2)?.as_str();
+            return Some(format!("{}x{}", width, height));
+        }
+        None
+    }
+
+    fn format_file_size(size: u64) -> String {
+        const UNITS: &[&str] = &["B", "KB", "MB", "GB"];
+        let mut size = size as f64;
+        let mut unit_index = 0;
+
+        while size >= 1024.0 && unit_index < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit_index += 1;
+        }
+
+        if unit_index == 0 {
+            format!("{} {}", size as u64, UNITS[unit_index])
+        } else {
+            format!("{:.1} {}", size, UNITS[unit_index])
+        }
+    }
+
+    /// Create a quick preview command for a given image path
+    pub fn create_preview_command(&self, image_path: &Path) -> String {
+        match &self.backend {
+            Some(backend) => backend.preview_command(self.runner.as_ref(), image_path),
+            None => format!("file '{}'", image_path.display()),
+        }
+    }
+}
+
+/// The existing image file named by the shell word under `cursor` (a byte offset)
+pub fn image_path_at(line: &str, cursor: usize) -> Option<PathBuf> {
+    let word = crate::substitution::word_at(line, cursor)?;
+
+    let path = match word.value.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir()?.join(rest),
+        None => PathBuf::from(&word.value),
+    };
+
+    let is_image = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            matches!(
+                ext.to_lowercase().as_str(),
+                "png" | "jpg" | "jpeg" | "gif" | "bmp" | "webp" | "svg" | "tiff" | "tif" | "ico"
+            )
+        });
+
+    (is_image && path.is_file()).then_some(path)
+}
+
+/// A preview size in cells in the units `backend` takes, assuming
+/// [`cell_size::DEFAULT_CELL_SIZE`] when the terminal's is unknown
+fn backend_size(backend: &dyn PreviewBackend, cell_size: Option<CellSize>, max_width: Option<u32>, max_height: Option<u32>) -> (Option<u32>, Option<u32>) {
+    if !backend.sizes_in_pixels() {
+        return (max_width, max_height);
+    }
+    cell_size.unwrap_or(cell_size::DEFAULT_CELL_SIZE).to_pixels(max_width, max_height)
+}
+
+/// Render `image_path` with `backend`. Terminal renderers get HDR and 16-bit
+/// images as a tone-mapped 8-bit sRGB copy, since most preview tools clip them
+/// or drop their transfer curve, and a reduced copy over a `slow_link`;
+/// viewer windows are given the original.
+async fn render_with(
+    backend: &dyn PreviewBackend,
+    runner: &dyn CommandRunner,
+    image_path: &Path,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+    slow_link: Option<&SlowLinkConfig>,
+    limits: &DecodeLimits,
+) -> Result<Vec<u8>> {
+    if let Some(settings) = slow_link.filter(|_| backend.cacheable()) {
+        let (source, owned, limits) = (image_path.to_path_buf(), settings.clone(), limits.clone());
+        let reduced = tokio::task::spawn_blocking(move || slow_link::write_reduced_copy(&source, &owned, &limits))
+            .await
+            .map_err(|e| Error::Internal(format!("Task join error: {}", e)))?;
+        match reduced {
+            Ok(reduced) => return backend.render_reduced(runner, &reduced, max_width, max_height, settings).await,
+            // Formats that don't decode, like SVG, are left to the backend
+            Err(e) => debug!("Previewing {:?} unreduced: {}", image_path, e),
+        }
+    }
+
+    if !backend.cacheable() || !tone_map::needs_display_conversion(image_path) {
+        return backend.render(runner, image_path, max_width, max_height).await;
+    }
+
+    let temp_file = temp_files::create("preview-", "png")?;
+    let (source, target, limits) = (image_path.to_path_buf(), temp_file.to_path_buf(), limits.clone());
+    tokio::task::spawn_blocking(move || tone_map::write_display_copy(&source, &target, &limits))
+        .await
+        .map_err(|e| Error::Internal(format!("Task join error: {}", e)))??;
+
+    backend.render(runner, &temp_file, max_width, max_height).await
+}
+
+/// Run a preview tool and capture its output
+async fn run_preview_tool(runner: &dyn CommandRunner, program: &str, args: &[String], label: &str) -> Result<Vec<u8>> {
+    let output = runner.run(program, &arg_refs(args), None).await
+        .map_err(|e| Error::Process(format!("Failed to run {}: {}", program, e)))?;
+
+    if output.success {
+        Ok(output.stdout)
+    } else {
+        Err(Error::Process(format!("{} preview failed: {}", label, output.stderr_lossy())))
+    }
+}
+
+fn arg_refs(args: &[String]) -> Vec<&str> {
+    args.iter().map(String::as_str).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+
+    #[tokio::test]
+    async fn test_preview_manager_creation() {
+        let config = Config::default();
+        let manager = ImagePreviewManager::new(config).await;
+        assert!(manager.is_ok());
+    }
+
+    #[test]
+    fn test_file_size_formatting() {
+        assert_eq!(ImagePreviewManager::format_file_size(500), "500 B");
+        assert_eq!(ImagePreviewManager::format_file_size(1500), "1.5 KB");
+        assert_eq!(ImagePreviewManager::format_file_size(1500000), "1.4 MB");
+    }
+
+    #[tokio::test]
+    async fn test_compact_preview_stats() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let image_path = temp_dir.path().join("shot.png");
+        image::RgbaImage::from_pixel(8, 4, image::Rgba([0, 0, 0, 255])).save(&image_path).unwrap();
+        let manager = ImagePreviewManager {
+            config: Config::default(),
+            backend: None,
+            runner: Arc::new(FakeRunner::new().with_output("identify", CommandOutput::ok("8x4"))),
+            slow_link: None,
+            cell_size: None,
+        };
+
+        let info = manager.show_compact_preview(&image_path).await.unwrap();
+        assert!(info.starts_with("🖼️ shot.png (8x4) - "), "{}", info);
+        assert!(info.ends_with(" · 🎨 #000000 100% · ☀️ 0%"), "{}", info);
+
+        // Files that don't decode still get the basic line
+        std::fs::write(&image_path, b"png").unwrap();
+        assert!(!manager.show_compact_preview(&image_path).await.unwrap().contains('🎨'));
+    }
+
+    #[test]
+    fn test_parse_file_dimensions() {
+        let file_output = "test.png: PNG image data, 1920 x 1080, 8-bit/color RGBA";
+        let dims = ImagePreviewManager::parse_file_dimensions(file_output);
+        assert_eq!(dims, Some("1920x1080".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_kitty_preview_through_runner() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let image_path = temp_dir.path().join("shot.png");
+        std::fs::write(&image_path, b"png").unwrap();
+
+        let runner = Arc::new(FakeRunner::new()
+            .with_output("kitten", CommandOutput::ok(""))
+            .with_output("identify", CommandOutput::ok("640x480")));
+        let manager = ImagePreviewManager {
+            config: Config::default(),
+            backend: Some(Arc::new(Kitty)),
+            runner: runner.clone(),
+            slow_link: None,
+            cell_size: None,
+        };
+
+        manager.show_preview(&image_path, Some(40), None).await.unwrap();
+        let calls = runner.calls_to("kitten");
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].args, ["icat", "--cols", "40", &image_path.to_string_lossy()]);
+
+        assert_eq!(manager.get_image_dimensions(&image_path).await, Some("640x480".to_string()));
+
+        manager.show_automatic_preview(&image_path).await.unwrap();
+        assert_eq!(runner.calls_to("kitten")[1].args[1..5], ["--cols", "40", "--rows", "20"]);
+
+        runner.set_output("kitten", CommandOutput::failed("not a kitty terminal"));
+        let err = manager.show_preview(&image_path, None, None).await.unwrap_err();
+        assert!(err.to_string().contains("not a kitty terminal"));
+    }
+
+    #[tokio::test]
+    async fn test_slow_link_previews_are_reduced() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let image_path = temp_dir.path().join("shot.png");
+        image::RgbaImage::from_pixel(1600, 900, image::Rgba([0, 0, 0, 255])).save(&image_path).unwrap();
+
+        let runner = Arc::new(FakeRunner::new().with_output("img2sixel", CommandOutput::ok("sixel")));
+        let manager = ImagePreviewManager {
+            config: Config::default(),
+            backend: Some(Arc::new(Sixel)),
+            runner: runner.clone(),
+            slow_link: Some(SlowLinkConfig::default()),
+            cell_size: Some(CellSize { width: 8, height: 16 }),
+        };
+        assert!(manager.reduces_previews());
+
+        assert_eq!(manager.render_preview(&image_path, Some(40), None).await.unwrap(), b"sixel");
+        let args = &runner.calls_to("img2sixel")[0].args;
+        // Sized in pixels from the cell size
+        assert_eq!(args[..4], ["-p", "64", "-w", "320"]);
+        // Rendered from a reduced copy, which is gone afterwards
+        let reduced = PathBuf::from(args.last().unwrap());
+        assert_ne!(reduced, image_path);
+        assert!(!reduced.exists());
+
+        // Images that don't decode are rendered as they are
+        std::fs::write(&image_path, b"png").unwrap();
+        manager.render_preview(&image_path, None, None).await.unwrap();
+        assert_eq!(runner.calls_to("img2sixel")[1].args, [image_path.to_string_lossy()]);
+    }
+
+    #[tokio::test]
+    async fn test_kitty_overlay() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let image_path = temp_dir.path().join("shot.png");
+        std::fs::write(&image_path, b"png").unwrap();
+        let runner = FakeRunner::new().with_output("kitten", CommandOutput::ok("42"));
+
+        kitty::launch_overlay(&runner, &image_path).await.unwrap();
+        let args = &runner.calls_to("kitten")[0].args;
+        assert_eq!(args[..6], ["@", "launch", "--type=overlay", "--title", "klipdot: shot.png", "--"]);
+        // The overlay reads and removes its own copy
+        let copy = std::path::PathBuf::from(args.last().unwrap());
+        assert_ne!(copy, image_path);
+        assert_eq!(std::fs::read(&copy).unwrap(), b"png");
+        std::fs::remove_file(&copy).unwrap();
+
+        runner.set_output("kitten", CommandOutput::failed("remote control is disabled"));
+        let err = kitty::launch_overlay(&runner, &image_path).await.unwrap_err();
+        assert_eq!(err.error_code(), "UNSUPPORTED");
+        let copy = std::path::PathBuf::from(runner.calls_to("kitten")[1].args.last().unwrap());
+        assert!(!copy.exists());
+    }
+
+    struct Recorder;
+
+    #[async_trait]
+    impl PreviewBackend for Recorder {
+        fn name(&self) -> &str {
+            "recorder"
+        }
+
+        async fn detect(&self, runner: &dyn CommandRunner) -> bool {
+            runner.is_available("recorder")
+        }
+
+        async fn render(&self, _runner: &dyn CommandRunner, _image_path: &Path, _max_width: Option<u32>, _max_height: Option<u32>) -> Result<Vec<u8>> {
+            Ok(b"recorded".to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registry_selection() {
+        let mut registry = PreviewRegistry::new();
+        registry.register(Arc::new(ExternalViewer::new("chafa")));
+        registry.register(Arc::new(Recorder));
+
+        let runner = FakeRunner::new().with_output("recorder", CommandOutput::ok(""));
+        assert_eq!(registry.detect(&runner).await.unwrap().name(), "recorder");
+        assert!(registry.detect(&FakeRunner::new()).await.is_none());
+
+        // Config selection wins over detection and falls back when unknown
+        let mut config = Config::default();
+        config.preview.backend = Some("chafa".to_string());
+        assert_eq!(registry.select(&config, &runner).await.unwrap().name(), "chafa");
+        config.preview.backend = Some("ueberzugpp".to_string());
+        assert_eq!(registry.select(&config, &runner).await.unwrap().name(), "recorder");
+    }
+
+    #[test]
+    fn test_image_path_at() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let image = temp_dir.path().join("my shot.png");
+        std::fs::write(&image, b"fake image data").unwrap();
+
+        let line = format!("feh '{}' notes.txt", image.display());
+        assert_eq!(image_path_at(&line, 6), Some(image));
+        assert_eq!(image_path_at(&line, 0), None);
+        assert_eq!(image_path_at(&line, line.len()), None);
+    }
+
+    #[test]
+    fn test_builtin_registry_names() {
+        let names = PreviewRegistry::with_builtin().names();
+        assert_eq!(names.iter().filter(|name| *name == "qlmanage").count(), 1);
+        for name in ["iterm2", "kitty", "sixel", "chafa", "ascii"] {
+            assert!(names.contains(&name.to_string()), "missing {}", name);
+        }
+    }
+}
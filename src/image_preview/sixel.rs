@@ -0,0 +1,61 @@
+use super::{run_preview_tool, PreviewBackend};
+use crate::{command_runner::CommandRunner, config::SlowLinkConfig, error::Result};
+use async_trait::async_trait;
+use std::path::Path;
+
+/// Sixel graphics via `img2sixel`
+pub struct Sixel;
+
+#[async_trait]
+impl PreviewBackend for Sixel {
+    fn name(&self) -> &str {
+        "sixel"
+    }
+
+    fn sizes_in_pixels(&self) -> bool {
+        true
+    }
+
+    async fn detect(&self, runner: &dyn CommandRunner) -> bool {
+        // Ask the terminal for its device attributes; sixel support is attribute 4
+        runner
+            .run("sh", &["-c", "echo -e '\\e[c' && read -t 1 -s -r response && echo $response | grep -q '4;'"], None)
+            .await
+            .is_ok_and(|output| output.success)
+    }
+
+    async fn render(&self, runner: &dyn CommandRunner, image_path: &Path, max_width: Option<u32>, max_height: Option<u32>) -> Result<Vec<u8>> {
+        run_preview_tool(runner, "img2sixel", &tool_args(image_path, max_width, max_height), "Sixel").await
+    }
+
+    async fn render_reduced(
+        &self,
+        runner: &dyn CommandRunner,
+        image_path: &Path,
+        max_width: Option<u32>,
+        max_height: Option<u32>,
+        slow_link: &SlowLinkConfig,
+    ) -> Result<Vec<u8>> {
+        // Each palette entry is defined in the output, and fewer colors give longer runs
+        let mut args = vec!["-p".to_string(), slow_link.sixel_colors.clamp(2, 256).to_string()];
+        args.extend(tool_args(image_path, max_width, max_height));
+        run_preview_tool(runner, "img2sixel", &args, "Sixel").await
+    }
+}
+
+fn tool_args(image_path: &Path, max_width: Option<u32>, max_height: Option<u32>) -> Vec<String> {
+    let mut args = Vec::new();
+
+    if let Some(width) = max_width {
+        args.push("-w".to_string());
+        args.push(width.to_string());
+    }
+
+    if let Some(height) = max_height {
+        args.push("-h".to_string());
+        args.push(height.to_string());
+    }
+
+    args.push(image_path.to_string_lossy().into_owned());
+    args
+}
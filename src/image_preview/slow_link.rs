@@ -0,0 +1,197 @@
+//! Smaller previews over slow SSH links.
+//!
+//! In an SSH session, `SSH_CONNECTION` names the connection's socket, and
+//! `ss` reads its round-trip time and delivery rate from the kernel's TCP
+//! statistics. The rate is only trusted when the connection wasn't idle
+//! while it was sampled (`app_limited`), which it mostly is for interactive
+//! shells, so round-trip time is what usually decides.
+//!
+//! Over a slow link, previews are rendered from a copy that fits
+//! `max_pixels` and, when opaque, is a JPEG at `quality`, and sixel output
+//! uses a smaller palette.
+
+use crate::{
+    config::{BitDepth, DecodeLimits, SlowLinkConfig, SlowLinkMode},
+    command_runner::CommandRunner,
+    decode_guard,
+    error::Result,
+    temp_files::{self, TempFile},
+    tone_map,
+};
+use image::{imageops::FilterType, DynamicImage, GenericImageView, ImageFormat};
+use std::path::Path;
+use tracing::debug;
+
+/// Measured state of a TCP connection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkStats {
+    pub rtt_ms: f64,
+    /// Delivery rate in kilobits per second, when the sample wasn't app-limited
+    pub rate_kbps: Option<f64>,
+}
+
+impl LinkStats {
+    pub fn is_slow(&self, settings: &SlowLinkConfig) -> bool {
+        self.rtt_ms > settings.max_rtt_ms as f64 || self.rate_kbps.is_some_and(|rate| rate < settings.min_rate_kbps as f64)
+    }
+}
+
+/// Whether previews should be reduced; `auto` measures the SSH connection, if any
+pub async fn is_slow(settings: &SlowLinkConfig, runner: &dyn CommandRunner) -> bool {
+    match settings.mode {
+        SlowLinkMode::On => true,
+        SlowLinkMode::Off => false,
+        SlowLinkMode::Auto => {
+            let Ok(connection) = std::env::var("SSH_CONNECTION") else {
+                return false;
+            };
+            let stats = measure(runner, &connection).await;
+            debug!("SSH link: {:?}", stats);
+            stats.is_some_and(|stats| stats.is_slow(settings))
+        }
+    }
+}
+
+/// Statistics of the connection `SSH_CONNECTION` describes
+/// ("client_ip client_port server_ip server_port")
+pub async fn measure(runner: &dyn CommandRunner, ssh_connection: &str) -> Option<LinkStats> {
+    let mut fields = ssh_connection.split_whitespace();
+    let (client_ip, client_port) = (fields.next()?, fields.next()?);
+    let client = if client_ip.contains(':') {
+        format!("[{}]:{}", client_ip, client_port)
+    } else {
+        format!("{}:{}", client_ip, client_port)
+    };
+    if !runner.is_available("ss") {
+        return None;
+    }
+    let output = runner.run("ss", &["-tinH", "dst", &client], None).await.ok()?;
+    if !output.success {
+        return None;
+    }
+    parse_ss(&output.stdout_lossy())
+}
+
+/// The first connection's statistics in `ss -ti` output
+fn parse_ss(output: &str) -> Option<LinkStats> {
+    let tokens: Vec<&str> = output.split_whitespace().collect();
+    let rtt_ms = tokens.iter().find_map(|token| token.strip_prefix("rtt:")?.split('/').next()?.parse().ok())?;
+    let rate_kbps = if tokens.contains(&"app_limited") {
+        None
+    } else {
+        tokens
+            .iter()
+            .position(|token| *token == "delivery_rate")
+            .and_then(|index| tokens.get(index + 1))
+            .and_then(|rate| parse_rate(rate))
+    };
+    Some(LinkStats { rtt_ms, rate_kbps })
+}
+
+/// `87310bps`, `1.5Mbps` and the like, in kilobits per second
+fn parse_rate(rate: &str) -> Option<f64> {
+    let number = rate.strip_suffix("bps")?;
+    let (number, scale) = match number.chars().last()? {
+        'K' | 'k' => (&number[..number.len() - 1], 1.0),
+        'M' => (&number[..number.len() - 1], 1e3),
+        'G' => (&number[..number.len() - 1], 1e6),
+        _ => (number, 1e-3),
+    };
+    Some(number.parse::<f64>().ok()? * scale)
+}
+
+/// Write a reduced copy of `source` to a temporary file: fit within
+/// `max_pixels`, and a JPEG when it's opaque and JPEG can be written. The
+/// source is decoded within `limits`.
+pub fn write_reduced_copy(source: &Path, settings: &SlowLinkConfig, limits: &DecodeLimits) -> Result<TempFile> {
+    let img = tone_map::prepare(decode_guard::open(source, limits)?, BitDepth::Reduce);
+    let max_pixels = settings.max_pixels.max(1);
+    let img = if img.width().max(img.height()) > max_pixels {
+        img.resize(max_pixels, max_pixels, FilterType::Triangle)
+    } else {
+        img
+    };
+
+    let opaque = !img.color().has_alpha() || img.pixels().all(|(_, _, pixel)| pixel[3] == 255);
+    let format = if opaque && cfg!(feature = "codecs") { ImageFormat::Jpeg } else { ImageFormat::Png };
+    let target = temp_files::create("preview-", format.extensions_str()[0])?;
+    write(&img, &target, format, settings.quality)?;
+    Ok(target)
+}
+
+#[cfg(feature = "codecs")]
+fn write(img: &DynamicImage, target: &Path, format: ImageFormat, quality: u8) -> Result<()> {
+    if format == ImageFormat::Jpeg {
+        let file = std::io::BufWriter::new(std::fs::File::create(target)?);
+        let rgb = img.to_rgb8();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(file, quality.clamp(1, 100))
+            .encode(rgb.as_raw(), rgb.width(), rgb.height(), image::ColorType::Rgb8)?;
+        return Ok(());
+    }
+    img.save_with_format(target, format)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "codecs"))]
+fn write(img: &DynamicImage, target: &Path, format: ImageFormat, _quality: u8) -> Result<()> {
+    img.save_with_format(target, format)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+
+    const SS_OUTPUT: &str = "ESTAB 0 0 10.0.0.2:22 203.0.113.7:51234\n\t cubic wscale:7,7 rto:604 rtt:210.5/12.25 ato:40 \
+        mss:1448 cwnd:10 send 550Kbps pacing_rate 1.1Mbps delivery_rate 480Kbps delivered:90 minrtt:198.1\n";
+
+    #[tokio::test]
+    async fn test_measure() {
+        let runner = FakeRunner::new().with_output("ss", CommandOutput::ok(SS_OUTPUT));
+        let stats = measure(&runner, "203.0.113.7 51234 10.0.0.2 22").await.unwrap();
+        assert_eq!(stats, LinkStats { rtt_ms: 210.5, rate_kbps: Some(480.0) });
+        assert_eq!(runner.calls_to("ss")[0].args, ["-tinH", "dst", "203.0.113.7:51234"]);
+
+        measure(&runner, "2001:db8::7 51234 2001:db8::2 22").await.unwrap();
+        assert_eq!(runner.calls_to("ss")[1].args[2], "[2001:db8::7]:51234");
+        assert_eq!(measure(&FakeRunner::new(), "203.0.113.7 51234 10.0.0.2 22").await, None);
+
+        let settings = SlowLinkConfig::default();
+        assert!(stats.is_slow(&settings));
+        assert!(LinkStats { rtt_ms: 20.0, rate_kbps: Some(480.0) }.is_slow(&settings));
+        assert!(!LinkStats { rtt_ms: 20.0, rate_kbps: None }.is_slow(&settings));
+    }
+
+    #[test]
+    fn test_parse_ss() {
+        // Idle interactive sessions sample an app-limited rate, which says nothing of the link
+        let idle = SS_OUTPUT.replace("delivered:90", "delivered:90 app_limited");
+        assert_eq!(parse_ss(&idle), Some(LinkStats { rtt_ms: 210.5, rate_kbps: None }));
+        assert_eq!(parse_ss(""), None);
+        assert_eq!(parse_rate("87310666664bps"), Some(87310666.664));
+        assert_eq!(parse_rate("1.5Mbps"), Some(1500.0));
+        assert_eq!(parse_rate("fast"), None);
+    }
+
+    #[test]
+    fn test_reduced_copy() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let source = temp_dir.path().join("shot.png");
+        image::RgbaImage::from_pixel(2000, 1000, image::Rgba([10, 20, 30, 255])).save(&source).unwrap();
+
+        let reduced = write_reduced_copy(&source, &SlowLinkConfig::default(), &DecodeLimits::default()).unwrap();
+        let img = image::open(&reduced).unwrap();
+        assert_eq!(img.dimensions(), (640, 320));
+        let expected = if cfg!(feature = "codecs") { "jpg" } else { "png" };
+        assert_eq!(reduced.extension().unwrap(), expected);
+        let path = reduced.to_path_buf();
+        drop(reduced);
+        assert!(!path.exists());
+
+        // Transparency needs PNG
+        image::RgbaImage::from_pixel(20, 10, image::Rgba([10, 20, 30, 0])).save(&source).unwrap();
+        let reduced = write_reduced_copy(&source, &SlowLinkConfig::default(), &DecodeLimits::default()).unwrap();
+        assert_eq!(reduced.extension().unwrap(), "png");
+    }
+}
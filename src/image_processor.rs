@@ -1,52 +1,184 @@
-use crate::{config::Config, error::Result, Error};
-use image::{DynamicImage, ImageFormat};
-use std::path::PathBuf;
-use tracing::{debug, info};
+use crate::{
+    alt_text, audit, clipboard_history,
+    command_runner::{self, SharedRunner},
+    config::{Config, DuplicateMode, OutputFormat}, error::Result, error_history,
+    decode_guard, dedup, downscale, focus::{self, WindowContext}, metadata::{self, ImageMetadata}, mirror::{self, MirrorName}, pairing, rename, sniff, store_permissions,
+    svg_sanitize, tone_map,
+    window_crop::{self, WindowGeometry}, Error,
+};
+use image::codecs::png::{CompressionType, FilterType as PngFilterType, PngEncoder};
+use image::{DynamicImage, GenericImageView, ImageEncoder, ImageFormat};
+use once_cell::sync::Lazy;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{debug, info, warn};
+
+/// What [`ImageProcessor::store`] writes
+enum Content {
+    /// A decoded image, encoded in the output format
+    Raster { img: DynamicImage, original: (u32, u32) },
+    /// A sanitized SVG, stored as it is
+    Svg(Vec<u8>),
+}
+
+/// A stored image encoded again by [`ImageProcessor::reencode`]
+pub struct Reencoded {
+    pub data: Vec<u8>,
+    /// Extension for the output format
+    pub extension: &'static str,
+    /// Size before it was scaled down, if it was
+    pub resized_from: Option<(u32, u32)>,
+}
+
+/// Qualities below this store PNGs as 256-colour palette images
+const PALETTE_QUALITY: u8 = 50;
+
+/// Qualities from this up favour encoding speed over PNG size
+const FAST_PNG_QUALITY: u8 = 90;
+
+/// NeuQuant samples every 10th pixel, its recommended speed/quality trade-off
+const QUANTIZER_SAMPLE_FACTOR: i32 = 10;
+
+/// Error history subsystem for oversized images that could not be stored
+pub const SKIPPED_SUBSYSTEM: &str = "skipped";
+
+/// Directory images are being written to because the screenshot directory failed, if any
+static STORAGE_FALLBACK: Lazy<Mutex<Option<PathBuf>>> = Lazy::new(|| Mutex::new(None));
+
+/// The fallback directory currently in use, or `None` while the screenshot directory is writable
+pub fn storage_fallback() -> Option<PathBuf> {
+    STORAGE_FALLBACK.lock().unwrap_or_else(|p| p.into_inner()).clone()
+}
 
 pub struct ImageProcessor {
     config: Config,
+    runner: SharedRunner,
 }
 
 impl ImageProcessor {
     pub async fn new(config: Config) -> Result<Self> {
-        // Ensure screenshot directory exists
-        tokio::fs::create_dir_all(&config.screenshot_dir).await?;
+        // Ensure screenshot directory exists; if it can't be created, saving falls back elsewhere
+        if let Err(e) = store_permissions::create_dir(&config.screenshot_dir, &config.store_permissions).await {
+            warn!("Cannot create screenshot directory {:?}: {}", config.screenshot_dir, e);
+        }
         
-        Ok(Self { config })
+        Ok(Self {
+            config,
+            runner: command_runner::system(),
+        })
+    }
+    
+    /// Replace the runner used for desktop notifications
+    pub fn set_command_runner(&mut self, runner: SharedRunner) {
+        self.runner = runner;
     }
     
     pub async fn process_image_data(&self, data: &[u8], source: &str) -> Result<PathBuf> {
-        debug!("Processing image data from source: {}", source);
+        self.process_image_data_from(data, source, None, None).await
+    }
+    
+    /// Process `data`, recording the application it came from in the metadata index;
+    /// `window` is the focused window when it was captured, to crop to
+    pub async fn process_image_data_from(
+        &self,
+        data: &[u8],
+        source: &str,
+        app: Option<&str>,
+        window: Option<&WindowGeometry>,
+    ) -> Result<PathBuf> {
+        let stored = self.process_data(data, source, app, None, window).await?;
+        clipboard_history::record_image(&self.config.history, source, app, &stored).await;
+        if self.config.audit.enabled {
+            let record = audit::Record {
+                source: Some(source.to_string()),
+                app: app.map(str::to_string),
+                original_sha256: Some(audit::sha256(data)),
+                ..audit::Record::new(audit::Event::Intercept, &stored)
+            };
+            self.audit(record).await;
+        }
+        Ok(stored)
+    }
+    
+    async fn process_data(
+        &self,
+        data: &[u8],
+        source: &str,
+        app: Option<&str>,
+        output: Option<&str>,
+        window: Option<&WindowGeometry>,
+    ) -> Result<PathBuf> {
+        debug!("Processing image data from source: {} (app: {:?}, output: {:?})", source, app, output);
         
         // Validate image data
         if data.is_empty() {
             return Err(Error::InvalidInput("Empty image data".to_string()));
         }
         
-        if data.len() > self.config.max_file_size as usize {
-            return Err(Error::InvalidInput(format!(
-                "Image size {} exceeds maximum allowed size {}",
-                data.len(),
-                self.config.max_file_size
-            )));
+        let hash = dedup::content_hash(data);
+        let _gate = dedup::lock(&hash).await;
+        if let Some(stored) = self.find_stored(&hash).await {
+            return self.store_duplicate(stored, source, app, output).await;
         }
         
-        // Load image
-        let img = image::load_from_memory(data)
-            .map_err(|e| Error::Image(e))?;
-        
-        // Generate filename
-        let filename = crate::generate_screenshot_filename(source);
-        let output_path = self.config.get_screenshot_path(&filename);
-        
-        // Process and save image
-        self.save_processed_image(&img, &output_path).await?;
+        // SVGs are documents, kept as they are once anything unsafe is removed
+        if sniff::image_extension(data) == Some("svg") {
+            let svg = svg_sanitize::sanitize(data, self.config.svg.sanitize)?;
+            return self.store(Content::Svg(svg), source, app, output, hash).await;
+        }
         
-        info!("Processed image saved to: {:?}", output_path);
-        Ok(output_path)
+        let (img, original) = self.decode(data, source)?;
+        let (img, original) = match window {
+            Some(window) => window_crop::crop(img, original, window),
+            None => (img, original),
+        };
+        self.store(Content::Raster { img, original }, source, app, output, hash).await
     }
     
     pub async fn process_image_file(&self, input_path: &PathBuf, source: &str) -> Result<PathBuf> {
+        self.process_image_file_from(input_path, source, None, None, None).await
+    }
+    
+    /// Process a file, recording the application and monitor it came from in the
+    /// metadata index; `window` is the focused window when it was captured, to crop to
+    pub async fn process_image_file_from(
+        &self,
+        input_path: &PathBuf,
+        source: &str,
+        app: Option<&str>,
+        output: Option<&str>,
+        window: Option<&WindowGeometry>,
+    ) -> Result<PathBuf> {
+        // Hashed first, as the file may change once it's been stored
+        let original = match self.config.audit.enabled {
+            true => audit::sha256_file(input_path).await.ok(),
+            false => None,
+        };
+        let stored = self.process_file(input_path, source, app, output, window).await?;
+        if stored != *input_path {
+            clipboard_history::record_image(&self.config.history, source, app, &stored).await;
+        }
+        if self.config.audit.enabled && stored != *input_path {
+            let record = audit::Record {
+                source: Some(source.to_string()),
+                app: app.map(str::to_string),
+                input: Some(input_path.clone()),
+                original_sha256: original,
+                ..audit::Record::new(audit::Event::Intercept, &stored)
+            };
+            self.audit(record).await;
+        }
+        Ok(stored)
+    }
+    
+    async fn process_file(
+        &self,
+        input_path: &PathBuf,
+        source: &str,
+        app: Option<&str>,
+        output: Option<&str>,
+        window: Option<&WindowGeometry>,
+    ) -> Result<PathBuf> {
         debug!("Processing image file: {:?}", input_path);
         
         // Validate input file
@@ -54,75 +186,458 @@ impl ImageProcessor {
             return Err(Error::NotFound(format!("Input file not found: {:?}", input_path)));
         }
         
+        if self.is_stored(input_path).await {
+            debug!("{:?} is already a stored image", input_path);
+            return Ok(input_path.clone());
+        }
+        
         let metadata = tokio::fs::metadata(input_path).await?;
-        if metadata.len() > self.config.max_file_size {
-            return Err(Error::InvalidInput(format!(
-                "File size {} exceeds maximum allowed size {}",
-                metadata.len(),
-                self.config.max_file_size
-            )));
+        let limit = self.config.max_file_size_for(source);
+        if metadata.len() <= limit {
+            let data = tokio::fs::read(input_path).await?;
+            return self.process_data(&data, source, app, output, window).await;
+        }
+        
+        // Oversized files are decoded from disk so the whole file is never in memory
+        let hash = dedup::hash_file(input_path).await?;
+        let _gate = dedup::lock(&hash).await;
+        if let Some(stored) = self.find_stored(&hash).await {
+            return self.store_duplicate(stored, source, app, output).await;
         }
         
-        // Read and process image
-        let data = tokio::fs::read(input_path).await?;
-        self.process_image_data(&data, source).await
+        let mut header = [0u8; 32];
+        let read = std::io::Read::read(&mut std::fs::File::open(input_path)?, &mut header)?;
+        let format = self.streamable_format(&header[..read], metadata.len(), source, limit)?;
+        self.refuse_bombs(decode_guard::check_file(input_path, &self.config.decode_limits), source)?;
+        
+        let path = input_path.clone();
+        let target = self.downscale_target();
+        let (img, original) = tokio::task::spawn_blocking(move || {
+            let file = std::io::BufReader::new(std::fs::File::open(path)?);
+            downscale::decode_downscaled(file, format, target)
+        }).await.map_err(|e| Error::Internal(format!("Task join error: {}", e)))??;
+        let (img, original) = match window {
+            Some(window) => window_crop::crop(img, original, window),
+            None => (img, original),
+        };
+        
+        self.store(Content::Raster { img, original }, source, app, output, hash).await
+    }
+    
+    /// Add `record` to the audit log with the hash of the stored file
+    async fn audit(&self, record: audit::Record) {
+        let stored_sha256 = audit::sha256_file(&record.path).await.ok();
+        audit::record(&self.config.audit, audit::Record { stored_sha256, ..record }).await;
+    }
+    
+    /// The newest image with content `hash` already in one of the storage
+    /// directories, with its index entry
+    async fn find_stored(&self, hash: &str) -> Option<(PathBuf, ImageMetadata)> {
+        let mut newest: Option<(std::time::SystemTime, PathBuf, ImageMetadata)> = None;
+        for dir in self.indexed_dirs() {
+            let Ok(index) = metadata::load(&dir).await else {
+                continue;
+            };
+            for entry in index.into_values().filter(|entry| entry.hash.as_deref() == Some(hash)) {
+                let path = dir.join(&entry.filename);
+                let Ok(modified) = std::fs::metadata(&path).and_then(|metadata| metadata.modified()) else {
+                    continue;
+                };
+                if newest.as_ref().is_none_or(|(newest, ..)| modified > *newest) {
+                    newest = Some((modified, path, entry));
+                }
+            }
+        }
+        newest.map(|(_, path, entry)| (path, entry))
     }
     
-    async fn save_processed_image(&self, img: &DynamicImage, output_path: &PathBuf) -> Result<()> {
-        debug!("Saving processed image to: {:?}", output_path);
+    /// Another capture of the `stored` image: its path, or with `duplicates:
+    /// link` a new entry sharing its data once it's older than the same capture
+    async fn store_duplicate(
+        &self,
+        (existing, stored): (PathBuf, ImageMetadata),
+        source: &str,
+        app: Option<&str>,
+        output: Option<&str>,
+    ) -> Result<PathBuf> {
+        let age = std::fs::metadata(&existing)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .unwrap_or_default();
+        if self.config.duplicates == DuplicateMode::Reuse || age < dedup::SAME_CAPTURE_WINDOW {
+            info!("Image from {} is already stored as {:?}", source, existing);
+            return Ok(existing);
+        }
         
-        // Ensure parent directory exists
-        if let Some(parent) = output_path.parent() {
-            tokio::fs::create_dir_all(parent).await?;
+        let extension = existing.extension().and_then(|ext| ext.to_str()).unwrap_or(self.config.output_format.extension());
+        let filename = crate::generate_screenshot_filename(source, self.config.local_time_filenames, extension);
+        let dir = match self.config.source_subdir(source) {
+            Some(subdir) => self.config.screenshot_dir.join(subdir),
+            None => self.config.screenshot_dir.clone(),
+        };
+        let linked = dir.join(&filename);
+        let kind = match store_permissions::create_dir(&dir, &self.config.store_permissions).await {
+            Ok(()) => dedup::link_duplicate(&existing, &linked).await,
+            Err(e) => Err(e),
+        };
+        let kind = match kind {
+            Ok(kind) => kind,
+            Err(e) => {
+                warn!("Can't link {:?} for another capture, reusing it: {}", existing, e);
+                return Ok(existing);
+            }
+        };
+        
+        let entry = ImageMetadata {
+            filename,
+            source: source.to_string(),
+            app: app.map(str::to_string),
+            output: output.map(str::to_string),
+            tags: Vec::new(),
+            window: self.window_context().await,
+            ..stored
+        };
+        if let Err(e) = metadata::record(&dir, &entry).await {
+            warn!("Failed to record metadata for {:?}: {}", linked, e);
+        }
+        info!("Stored another capture of {:?} as {:?} ({:?}, no new data)", existing, linked, kind);
+        Ok(linked)
+    }
+    
+    /// Storage directories and their source subdirectories, each with its own index
+    fn indexed_dirs(&self) -> Vec<PathBuf> {
+        self.config
+            .storage_dirs()
+            .iter()
+            .flat_map(|dir| self.config.dirs_in(dir))
+            .collect()
+    }
+    
+    /// Whether `path` is itself an image this processor stored
+    async fn is_stored(&self, path: &Path) -> bool {
+        let (Some(dir), Some(filename)) = (path.parent(), path.file_name()) else {
+            return false;
+        };
+        if !self.indexed_dirs().iter().any(|indexed| indexed == dir) {
+            return false;
+        }
+        metadata::load(dir)
+            .await
+            .is_ok_and(|index| index.contains_key(filename.to_string_lossy().as_ref()))
+    }
+    
+    /// Decode `data`, downscaling while decoding if it exceeds the size limit for `source`
+    fn decode(&self, data: &[u8], source: &str) -> Result<(DynamicImage, (u32, u32))> {
+        let limit = self.config.max_file_size_for(source);
+        if data.len() as u64 <= limit {
+            let img = self.refuse_bombs(decode_guard::load(data, &self.config.decode_limits), source)?;
+            let dimensions = img.dimensions();
+            return Ok((img, dimensions));
         }
         
-        // Convert image to PNG with compression
-        let processed_img = self.apply_image_processing(img)?;
+        self.refuse_bombs(decode_guard::check(data, &self.config.decode_limits), source)?;
+        let format = self.streamable_format(data, data.len() as u64, source, limit)?;
+        downscale::decode_downscaled(data, format, self.downscale_target())
+    }
+    
+    /// Images over the decode limits are skipped, and recorded so `klipdot status` can report it
+    fn refuse_bombs<T>(&self, result: Result<T>, source: &str) -> Result<T> {
+        result.map_err(|e| match e {
+            Error::Validation(reason) => {
+                let e = Error::Validation(format!("Skipped {} image: {}", source, reason));
+                warn!("{}", e);
+                error_history::record_error(SKIPPED_SUBSYSTEM, &e);
+                e
+            }
+            e => e,
+        })
+    }
+    
+    /// Format of an oversized image if it can be downscaled while decoding; otherwise
+    /// the image is skipped, and recorded so `klipdot status` can report it
+    fn streamable_format(&self, header: &[u8], size: u64, source: &str, limit: u64) -> Result<ImageFormat> {
+        let format = image::guess_format(header).ok();
+        if let Some(format) = format.filter(|format| downscale::is_streamable(*format)) {
+            info!(
+                "{} image of {} exceeds the {} limit, downscaling while decoding",
+                source, crate::format_file_size(size), crate::format_file_size(limit)
+            );
+            return Ok(format);
+        }
+        
+        let kind = format.map_or_else(|| "unrecognized".to_string(), |format| format!("{:?}", format));
+        let e = Error::InvalidInput(format!(
+            "Skipped {} {} image of {}: larger than the {} limit and can't be downscaled while decoding",
+            source, kind, crate::format_file_size(size), crate::format_file_size(limit)
+        ));
+        warn!("{}", e);
+        error_history::record_error(SKIPPED_SUBSYSTEM, &e);
+        Err(e)
+    }
+    
+    /// Longest side oversized images are decoded to
+    /// The focused window and workspace, when they're recorded
+    async fn window_context(&self) -> Option<WindowContext> {
+        if !self.config.window_context {
+            return None;
+        }
+        focus::window_context(self.runner.as_ref()).await
+    }
+    
+    fn downscale_target(&self) -> u32 {
+        self.config.max_dimension.pixels().unwrap_or(crate::MAX_IMAGE_DIMENSION)
+    }
+    
+    /// Apply processing to a decoded image (originally `original` in size) and save
+    /// it, indexed under the `hash` of the content it was decoded from
+    async fn store(
+        &self,
+        content: Content,
+        source: &str,
+        app: Option<&str>,
+        output: Option<&str>,
+        hash: String,
+    ) -> Result<PathBuf> {
+        let window = self.window_context().await;
         
-        // Save image
-        tokio::task::spawn_blocking({
-            let output_path = output_path.clone();
-            let processed_img = processed_img.clone();
-            move || {
-                processed_img.save_with_format(&output_path, ImageFormat::Png)
+        // Process and save image
+        let (content, resized_from, dimensions) = match content {
+            Content::Raster { img, original } => {
+                let processed = self.apply_image_processing(&img)?;
+                let resized_from = (processed.dimensions() != original).then_some(original);
+                if let Some((width, height)) = resized_from {
+                    info!(
+                        "Scaled {}x{} image down to {}x{}",
+                        width, height, processed.width(), processed.height()
+                    );
+                }
+                let dimensions = processed.dimensions();
+                (Content::Raster { img: processed, original }, resized_from, Some(dimensions))
             }
-        }).await.map_err(|e| Error::Internal(format!("Task join error: {}", e)))??;
+            svg => (svg, None, None),
+        };
         
-        Ok(())
+        #[cfg(feature = "lua-hooks")]
+        let (scripts, script_image) = (
+            crate::lua_hooks::Scripts::load(&self.config, self.runner.clone()),
+            crate::lua_hooks::ImageInfo {
+                source: source.to_string(),
+                app: app.map(str::to_string),
+                output: output.map(str::to_string),
+                width: dimensions.map(|(width, _)| width),
+                height: dimensions.map(|(_, height)| height),
+                ..Default::default()
+            },
+        );
+        #[cfg(feature = "lua-hooks")]
+        let routed = match scripts.route(&script_image).await {
+            crate::lua_hooks::Route::Keep => None,
+            crate::lua_hooks::Route::Drop(script) => {
+                return Err(Error::InvalidInput(format!("Script {} dropped the image from {}", script, source)));
+            }
+            crate::lua_hooks::Route::Source(routed) => Some(routed),
+        };
+        #[cfg(feature = "lua-hooks")]
+        let source = routed.as_deref().unwrap_or(source);
+        
+        let (encoded, extension) = match content {
+            Content::Raster { img, .. } => {
+                let encoded = encode_image(img, self.config.output_format, self.config.compression_quality).await?;
+                #[cfg(feature = "wasm-filters")]
+                let encoded = match dimensions {
+                    Some(dimensions) => self.filter(encoded, source, app, output, dimensions).await?,
+                    None => encoded,
+                };
+                // Filters may change the format
+                let extension = image::guess_format(&encoded)
+                    .ok()
+                    .and_then(|format| format.extensions_str().first().copied())
+                    .unwrap_or(self.config.output_format.extension());
+                (encoded, extension)
+            }
+            Content::Svg(svg) => (svg, "svg"),
+        };
+        
+        // A slug goes after the source so names still start with where the image came from
+        #[cfg(feature = "lua-hooks")]
+        let named = scripts.rename(&crate::lua_hooks::ImageInfo { source: source.to_string(), ..script_image.clone() }).await;
+        #[cfg(not(feature = "lua-hooks"))]
+        let named = None;
+        let slug = match named {
+            Some(slug) => Some(slug),
+            None => rename::auto_slug(self.runner.as_ref(), self.config.auto_slug, &encoded).await,
+        };
+        let prefix = match &slug {
+            Some(slug) => format!("{}-{}", source, slug),
+            None => source.to_string(),
+        };
+        let filename = crate::generate_screenshot_filename(&prefix, self.config.local_time_filenames, extension);
+        let output_path = self.write_with_fallback(source, &filename, &encoded).await?;
+        
+        let entry = ImageMetadata {
+            filename,
+            source: source.to_string(),
+            app: app.map(str::to_string),
+            output: output.map(str::to_string),
+            resized_from,
+            hash: Some(hash),
+            alt_text: None,
+            tags: Vec::new(),
+            window,
+            cold: None,
+        };
+        let dir = output_path.parent().unwrap_or(&self.config.screenshot_dir);
+        if let Err(e) = metadata::record(dir, &entry).await {
+            warn!("Failed to record metadata for {:?}: {}", output_path, e);
+        } else if self.config.alt_text.enabled {
+            alt_text::spawn_generation(self.runner.clone(), self.config.alt_text.clone(), output_path.clone());
+        }
+        
+        if dimensions.is_some() {
+            match pairing::pair(&self.config, &output_path, source, app).await {
+                Ok(Some(diff)) => info!("Stored the difference from the previous {} image: {:?}", source, diff),
+                Ok(None) => {}
+                Err(e) => warn!("Failed to pair {:?} with the previous image: {}", output_path, e),
+            }
+        }
+        
+        let name = MirrorName {
+            stored: &output_path,
+            source,
+            slug: slug.as_deref(),
+            app,
+            time: chrono::Local::now(),
+        };
+        if let Err(e) = mirror::mirror_image(&self.config.mirror, &name).await {
+            warn!("Failed to mirror {:?}: {}", output_path, e);
+            error_history::record_error("mirror", &e);
+        }
+        
+        #[cfg(feature = "lua-hooks")]
+        if !scripts.is_empty() {
+            let image = crate::lua_hooks::ImageInfo {
+                path: Some(output_path.clone()),
+                source: source.to_string(),
+                size: Some(encoded.len() as u64),
+                ..script_image
+            };
+            tokio::spawn(async move { scripts.on_intercept(&image).await });
+        }
+        
+        info!("Processed image saved to: {:?}", output_path);
+        Ok(output_path)
     }
     
-    fn apply_image_processing(&self, img: &DynamicImage) -> Result<DynamicImage> {
-        let mut processed = img.clone();
+    /// Decode the stored image `data` from `source` and encode it again with
+    /// the current `max_dimension`, `bit_depth`, `output_format` and
+    /// `compression_quality`, as a new capture would be
+    pub async fn reencode(&self, data: &[u8], source: &str) -> Result<Reencoded> {
+        let (img, original) = self.decode(data, source)?;
+        let processed = self.apply_image_processing(&img)?;
+        let resized_from = (processed.dimensions() != original).then_some(original);
+        let data = encode_image(processed, self.config.output_format, self.config.compression_quality).await?;
+        Ok(Reencoded { data, extension: self.config.output_format.extension(), resized_from })
+    }
+    
+    /// Run the configured WebAssembly filters on the encoded image
+    #[cfg(feature = "wasm-filters")]
+    async fn filter(
+        &self,
+        encoded: Vec<u8>,
+        source: &str,
+        app: Option<&str>,
+        output: Option<&str>,
+        (width, height): (u32, u32),
+    ) -> Result<Vec<u8>> {
+        let metadata = crate::wasm_filter::FilterMetadata {
+            source: source.to_string(),
+            app: app.map(str::to_string),
+            output: output.map(str::to_string),
+            format: self.config.output_format.extension().to_string(),
+            width,
+            height,
+        };
+        match crate::wasm_filter::apply(&self.config.filters, encoded, &metadata).await? {
+            crate::wasm_filter::Filtered::Kept(data) => Ok(data),
+            crate::wasm_filter::Filtered::Rejected(filter) => {
+                Err(Error::InvalidInput(format!("Filter {} rejected the image from {}", filter, source)))
+            }
+        }
+    }
+    
+    /// Write `data` to the first storage directory that accepts it, so a full
+    /// disk or unwritable screenshot directory doesn't lose the image. Each
+    /// directory gets the same subdirectory for `source`.
+    async fn write_with_fallback(&self, source: &str, filename: &str, data: &[u8]) -> Result<PathBuf> {
+        let mut last_error = None;
+        let subdir = self.config.source_subdir(source);
         
-        // Apply compression if needed
-        if self.config.compression_quality < 100 {
-            processed = self.apply_compression(&processed)?;
+        for (attempt, dir) in self.config.storage_dirs().into_iter().enumerate() {
+            let output_path = match subdir {
+                Some(subdir) => dir.join(subdir).join(filename),
+                None => dir.join(filename),
+            };
+            match store_permissions::write(&output_path, data, &self.config.store_permissions).await {
+                Ok(()) => {
+                    self.update_fallback_state((attempt > 0).then_some(dir));
+                    return Ok(output_path);
+                }
+                Err(e) => {
+                    warn!("Failed to save image to {:?}: {}", output_path, e);
+                    last_error = Some(e);
+                }
+            }
         }
         
-        // Ensure reasonable dimensions (max 4K)
-        const MAX_DIMENSION: u32 = 3840;
-        if processed.width() > MAX_DIMENSION || processed.height() > MAX_DIMENSION {
-            let ratio = (MAX_DIMENSION as f32 / processed.width().max(processed.height()) as f32).min(1.0);
-            let new_width = (processed.width() as f32 * ratio) as u32;
-            let new_height = (processed.height() as f32 * ratio) as u32;
-            
-            processed = processed.resize(new_width, new_height, image::imageops::FilterType::Lanczos3);
-            debug!("Resized image to {}x{}", new_width, new_height);
+        let e = Error::Io(last_error.unwrap_or_else(|| std::io::Error::other("no storage directories configured")));
+        error_history::record_error("storage", &e);
+        Err(e)
+    }
+    
+    fn update_fallback_state(&self, fallback: Option<PathBuf>) {
+        let mut current = STORAGE_FALLBACK.lock().unwrap_or_else(|p| p.into_inner());
+        if *current == fallback {
+            return;
         }
         
-        Ok(processed)
+        match &fallback {
+            Some(dir) => {
+                let e = Error::Permission(format!(
+                    "Cannot write to {:?}, saving screenshots to {:?}",
+                    self.config.screenshot_dir, dir
+                ));
+                warn!("{}", e);
+                error_history::record_error("storage", &e);
+                self.notify(&e.to_string());
+            }
+            None => info!("Screenshot directory {:?} is writable again", self.config.screenshot_dir),
+        }
+        *current = fallback;
     }
     
-    fn apply_compression(&self, img: &DynamicImage) -> Result<DynamicImage> {
-        // For PNG, we can't directly control compression quality, but we can
-        // reduce color depth or apply other optimizations
-        if self.config.compression_quality < 50 {
-            // Apply more aggressive compression by reducing color depth
-            let img_rgb8 = img.to_rgb8();
-            Ok(DynamicImage::ImageRgb8(img_rgb8))
-        } else {
-            Ok(img.clone())
+    /// Best-effort desktop notification
+    pub fn notify(&self, message: &str) {
+        notify(&self.runner, message);
+    }
+    
+    fn apply_image_processing(&self, img: &DynamicImage) -> Result<DynamicImage> {
+        let mut processed = img.clone();
+        
+        // Keep dimensions within the configured limit, if any
+        if let Some(max_dimension) = self.config.max_dimension.pixels() {
+            if processed.width() > max_dimension || processed.height() > max_dimension {
+                // resize keeps the aspect ratio, fitting the image within the square
+                processed = processed.resize(max_dimension, max_dimension, image::imageops::FilterType::Lanczos3);
+                debug!("Resized image to {}x{}", processed.width(), processed.height());
+            }
         }
+        
+        // Tone map HDR and settle the bit depth before encoding
+        if tone_map::is_hdr(&processed) {
+            debug!("Tone mapping HDR image");
+        }
+        Ok(tone_map::prepare(processed, self.config.bit_depth))
     }
     
     pub fn is_supported_format(&self, data: &[u8]) -> bool {
@@ -131,7 +646,7 @@ impl ImageProcessor {
     }
     
     pub fn get_image_info(&self, data: &[u8]) -> Result<ImageInfo> {
-        let img = image::load_from_memory(data)?;
+        let img = decode_guard::load(data, &self.config.decode_limits)?;
         let format = image::guess_format(data)?;
         
         Ok(ImageInfo {
@@ -152,6 +667,101 @@ impl ImageProcessor {
     }
 }
 
+/// Encode `img` in `format` at `quality` off the async runtime
+/// Best-effort desktop notification, shown in the background
+pub fn notify(runner: &SharedRunner, message: &str) {
+    let (program, args): (&str, Vec<String>) = if cfg!(target_os = "macos") {
+        let script = format!("display notification {:?} with title \"KlipDot\"", message);
+        ("osascript", vec!["-e".to_string(), script])
+    } else if crate::termux::is_termux() {
+        let args = ["--title", "KlipDot", "--content", message];
+        ("termux-notification", args.iter().map(|arg| arg.to_string()).collect())
+    } else {
+        ("notify-send", vec!["KlipDot".to_string(), message.to_string()])
+    };
+
+    if !runner.is_available(program) {
+        return;
+    }
+
+    let runner = runner.clone();
+    tokio::spawn(async move {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        if let Err(e) = runner.spawn_detached(program, &args).await {
+            debug!("Failed to show notification: {}", e);
+        }
+    });
+}
+
+async fn encode_image(img: DynamicImage, format: OutputFormat, quality: u8) -> Result<Vec<u8>> {
+    tokio::task::spawn_blocking(move || match format {
+        OutputFormat::Png => encode_png(&img, quality),
+        OutputFormat::Jpeg => encode_jpeg(&img, quality),
+    }).await.map_err(|e| Error::Internal(format!("Task join error: {}", e)))?
+}
+
+/// PNG is lossless, so quality selects how hard the encoder works; low
+/// qualities give up colour accuracy for a palette image a fraction the size
+fn encode_png(img: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+    if quality < PALETTE_QUALITY {
+        return encode_palette_png(img);
+    }
+    
+    let compression = if quality >= FAST_PNG_QUALITY {
+        CompressionType::Fast
+    } else {
+        CompressionType::Best
+    };
+    let mut encoded = Vec::new();
+    PngEncoder::new_with_quality(&mut encoded, compression, PngFilterType::Adaptive)
+        .write_image(img.as_bytes(), img.width(), img.height(), img.color())?;
+    Ok(encoded)
+}
+
+/// Quantize `img` to 256 colours (keeping alpha) and write it as an indexed PNG
+fn encode_palette_png(img: &DynamicImage) -> Result<Vec<u8>> {
+    let rgba = img.to_rgba8();
+    let quantizer = color_quant::NeuQuant::new(QUANTIZER_SAMPLE_FACTOR, 256, rgba.as_raw());
+    let indices: Vec<u8> = rgba
+        .as_raw()
+        .chunks_exact(4)
+        .map(|pixel| quantizer.index_of(pixel) as u8)
+        .collect();
+    
+    let colors = quantizer.color_map_rgba();
+    let palette: Vec<u8> = colors.chunks_exact(4).flat_map(|color| color[..3].to_vec()).collect();
+    let alpha: Vec<u8> = colors.chunks_exact(4).map(|color| color[3]).collect();
+    
+    let png_error = |e: png::EncodingError| Error::Format(format!("Failed to encode palette PNG: {}", e));
+    let mut encoded = Vec::new();
+    let mut encoder = png::Encoder::new(&mut encoded, img.width(), img.height());
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette);
+    encoder.set_trns(alpha);
+    encoder.set_compression(png::Compression::Best);
+    
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    writer.write_image_data(&indices).map_err(png_error)?;
+    writer.finish().map_err(png_error)?;
+    Ok(encoded)
+}
+
+#[cfg(feature = "codecs")]
+fn encode_jpeg(img: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+    // JPEG has no alpha channel
+    let rgb = img.to_rgb8();
+    let mut encoded = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut encoded, quality.clamp(1, 100))
+        .write_image(rgb.as_raw(), rgb.width(), rgb.height(), image::ColorType::Rgb8)?;
+    Ok(encoded)
+}
+
+#[cfg(not(feature = "codecs"))]
+fn encode_jpeg(_img: &DynamicImage, _quality: u8) -> Result<Vec<u8>> {
+    Err(Error::Unsupported("JPEG output requires a build with the codecs feature".to_string()))
+}
+
 #[derive(Debug, Clone)]
 pub struct ImageInfo {
     pub width: u32,
@@ -168,6 +778,8 @@ fn format_to_string(format: ImageFormat) -> String {
         ImageFormat::WebP => "WebP".to_string(),
         ImageFormat::Bmp => "BMP".to_string(),
         ImageFormat::Tiff => "TIFF".to_string(),
+        ImageFormat::Hdr => "HDR".to_string(),
+        ImageFormat::OpenExr => "OpenEXR".to_string(),
         _ => "Unknown".to_string(),
     }
 }
@@ -191,8 +803,10 @@ mod tests {
     #[tokio::test]
     async fn test_image_processor_creation() {
         let temp_dir = TempDir::new().unwrap();
-        let mut config = Config::default();
-        config.screenshot_dir = temp_dir.path().to_path_buf();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
         
         let processor = ImageProcessor::new(config).await;
         assert!(processor.is_ok());
@@ -201,8 +815,10 @@ mod tests {
     #[tokio::test]
     async fn test_process_image_data() {
         let temp_dir = TempDir::new().unwrap();
-        let mut config = Config::default();
-        config.screenshot_dir = temp_dir.path().to_path_buf();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
         
         let processor = ImageProcessor::new(config).await.unwrap();
         let image_data = create_test_image_data();
@@ -215,11 +831,32 @@ mod tests {
         assert!(output_path.to_string_lossy().contains("test"));
     }
     
+    #[tokio::test]
+    async fn test_svgs_are_stored_sanitized() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        
+        let processor = ImageProcessor::new(config).await.unwrap();
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg"><script>steal()</script><circle r="4"/></svg>"#;
+        let stored = processor.process_image_data(svg, "clipboard").await.unwrap();
+        assert_eq!(stored.extension().unwrap(), "svg");
+        assert_eq!(
+            std::fs::read_to_string(&stored).unwrap(),
+            r#"<svg xmlns="http://www.w3.org/2000/svg"><circle r="4"/></svg>"#
+        );
+        assert!(processor.process_image_data(b"<svg><g></svg>", "clipboard").await.is_err());
+    }
+    
     #[tokio::test]
     async fn test_image_format_detection() {
         let temp_dir = TempDir::new().unwrap();
-        let mut config = Config::default();
-        config.screenshot_dir = temp_dir.path().to_path_buf();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
         
         let processor = ImageProcessor::new(config).await.unwrap();
         let image_data = create_test_image_data();
@@ -233,8 +870,10 @@ mod tests {
     #[tokio::test]
     async fn test_image_info() {
         let temp_dir = TempDir::new().unwrap();
-        let mut config = Config::default();
-        config.screenshot_dir = temp_dir.path().to_path_buf();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
         
         let processor = ImageProcessor::new(config).await.unwrap();
         let image_data = create_test_image_data();
@@ -249,8 +888,10 @@ mod tests {
     #[tokio::test]
     async fn test_invalid_image_data() {
         let temp_dir = TempDir::new().unwrap();
-        let mut config = Config::default();
-        config.screenshot_dir = temp_dir.path().to_path_buf();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
         
         let processor = ImageProcessor::new(config).await.unwrap();
         
@@ -267,14 +908,299 @@ mod tests {
     #[tokio::test]
     async fn test_file_size_limit() {
         let temp_dir = TempDir::new().unwrap();
-        let mut config = Config::default();
-        config.screenshot_dir = temp_dir.path().to_path_buf();
-        config.max_file_size = 10; // Very small limit - smaller than any image
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            max_file_size: 10, // Very small limit - smaller than any image
+            ..Config::default()
+        };
         
         let processor = ImageProcessor::new(config).await.unwrap();
-        let image_data = create_test_image_data();
         
-        let result = processor.process_image_data(&image_data, "test").await;
+        // Oversized images that can't be downscaled while decoding are skipped
+        let result = processor.process_image_data(b"GIF89a\x01\x00\x01\x00\x00\x00\x00", "test").await;
         assert!(result.is_err());
+        assert!(error_history::recent_errors().iter().any(|record| record.subsystem == SKIPPED_SUBSYSTEM));
+        
+        // PNGs are decoded to at most max_dimension instead
+        let image_data = create_test_image_data();
+        assert!(processor.process_image_data(&image_data, "test").await.unwrap().exists());
+    }
+
+    #[tokio::test]
+    async fn test_decode_limits() {
+        let temp_dir = TempDir::new().unwrap();
+        let input = temp_dir.path().join("wide.png");
+        DynamicImage::ImageRgb8(image::RgbImage::new(400, 100)).save(&input).unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().join("out"),
+            decode_limits: crate::config::DecodeLimits { max_pixels: 10_000, ..Default::default() },
+            ..Config::default()
+        };
+        let processor = ImageProcessor::new(config).await.unwrap();
+
+        let result = processor.process_image_data(&std::fs::read(&input).unwrap(), "bomb-test").await;
+        assert!(matches!(result, Err(Error::Validation(_))));
+        assert!(error_history::recent_errors()
+            .iter()
+            .any(|record| record.subsystem == SKIPPED_SUBSYSTEM && record.message.contains("Skipped bomb-test image: Image is 400x100")));
+
+        // Files too large to decode whole are checked before they're streamed
+        let mut config = processor.config.clone();
+        config.source_max_file_size.insert("file".to_string(), 10);
+        let processor = ImageProcessor::new(config).await.unwrap();
+        assert!(matches!(processor.process_image_file(&input, "file").await, Err(Error::Validation(_))));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_file_downscaled() {
+        let temp_dir = TempDir::new().unwrap();
+        let input = temp_dir.path().join("wide.png");
+        DynamicImage::ImageRgb8(image::RgbImage::new(400, 100)).save(&input).unwrap();
+        
+        let config = Config {
+            screenshot_dir: temp_dir.path().join("out"),
+            source_max_file_size: [("file".to_string(), 10)].into_iter().collect(),
+            max_dimension: crate::config::MaxDimension::Pixels(100),
+            ..Config::default()
+        };
+        assert_eq!(config.max_file_size_for("file"), 10);
+        assert_eq!(config.max_file_size_for("clipboard"), crate::MAX_FILE_SIZE);
+        
+        let processor = ImageProcessor::new(config).await.unwrap();
+        let output_path = processor.process_image_file(&input, "file").await.unwrap();
+        assert_eq!(image::image_dimensions(&output_path).unwrap(), (100, 25));
+        
+        let index = metadata::load(&temp_dir.path().join("out")).await.unwrap();
+        let filename = output_path.file_name().unwrap().to_string_lossy();
+        assert_eq!(index[filename.as_ref()].resized_from, Some((400, 100)));
+    }
+    
+    #[tokio::test]
+    async fn test_unwritable_screenshot_dir_falls_back() {
+        use crate::command_runner::FakeRunner;
+        use std::sync::Arc;
+        
+        let temp_dir = TempDir::new().unwrap();
+        // A directory below a regular file can never be created
+        let blocker = temp_dir.path().join("blocker");
+        std::fs::write(&blocker, b"").unwrap();
+        let config = Config {
+            screenshot_dir: blocker.join("screenshots"),
+            fallback_dirs: vec![temp_dir.path().join("fallback")],
+            ..Config::default()
+        };
+        
+        let mut processor = ImageProcessor::new(config).await.unwrap();
+        processor.set_command_runner(Arc::new(FakeRunner::new()));
+        
+        let output_path = processor.process_image_data(&create_test_image_data(), "test").await.unwrap();
+        assert!(output_path.starts_with(temp_dir.path().join("fallback")));
+        assert!(output_path.exists());
+    }
+    
+    #[tokio::test]
+    async fn test_max_dimension() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut tall = Vec::new();
+        DynamicImage::ImageRgb8(image::RgbImage::new(10, 300))
+            .write_to(&mut std::io::Cursor::new(&mut tall), ImageFormat::Png)
+            .unwrap();
+        
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            max_dimension: crate::config::MaxDimension::Pixels(100),
+            ..Config::default()
+        };
+        let processor = ImageProcessor::new(config.clone()).await.unwrap();
+        let scaled = processor.process_image_data(&tall, "test").await.unwrap();
+        assert_eq!(image::image_dimensions(&scaled).unwrap().1, 100);
+        
+        let index = metadata::load(temp_dir.path()).await.unwrap();
+        let filename = scaled.file_name().unwrap().to_string_lossy();
+        assert_eq!(index[filename.as_ref()].resized_from, Some((10, 300)));
+        
+        // A separate directory, as identical content is only stored once
+        let config = Config {
+            screenshot_dir: temp_dir.path().join("unlimited"),
+            max_dimension: crate::config::MaxDimension::Unlimited,
+            ..config
+        };
+        let processor = ImageProcessor::new(config).await.unwrap();
+        let original = processor.process_image_data(&tall, "test").await.unwrap();
+        assert_eq!(image::image_dimensions(&original).unwrap(), (10, 300));
+        
+        let index = metadata::load(&temp_dir.path().join("unlimited")).await.unwrap();
+        let filename = original.file_name().unwrap().to_string_lossy();
+        assert_eq!(index[filename.as_ref()].resized_from, None);
+    }
+    
+    #[test]
+    fn test_png_quality() {
+        // Noise compresses poorly unless its colours are reduced
+        let mut rng = fastrand::Rng::with_seed(7);
+        let noise = image::RgbaImage::from_fn(64, 64, |x, _| {
+            image::Rgba([rng.u8(..), rng.u8(..), rng.u8(..), if x < 8 { 0 } else { 255 }])
+        });
+        let img = DynamicImage::ImageRgba8(noise);
+        
+        let fast = encode_png(&img, 95).unwrap();
+        let best = encode_png(&img, 70).unwrap();
+        let palette = encode_png(&img, 30).unwrap();
+        assert!(best.len() <= fast.len());
+        assert!(palette.len() < best.len());
+        
+        let decoded = image::load_from_memory(&palette).unwrap().to_rgba8();
+        assert_eq!(decoded.dimensions(), (64, 64));
+        assert_eq!(decoded.get_pixel(0, 0)[3], 0);
+        assert_eq!(decoded.get_pixel(63, 63)[3], 255);
+    }
+    
+    #[cfg(feature = "codecs")]
+    #[tokio::test]
+    async fn test_jpeg_output() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            output_format: OutputFormat::Jpeg,
+            compression_quality: 60,
+            ..Config::default()
+        };
+        
+        let processor = ImageProcessor::new(config).await.unwrap();
+        let output_path = processor.process_image_data(&create_test_image_data(), "test").await.unwrap();
+        assert_eq!(output_path.extension().unwrap(), "jpg");
+        assert_eq!(image::guess_format(&std::fs::read(&output_path).unwrap()).unwrap(), ImageFormat::Jpeg);
+    }
+    
+    #[tokio::test]
+    async fn test_source_subdirs() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        config.source_subdirs.enabled = true;
+        config.source_subdirs.dirs.insert("escape".to_string(), "../elsewhere".to_string());
+        let processor = ImageProcessor::new(config.clone()).await.unwrap();
+        let image_data = create_test_image_data();
+        
+        let copied = processor.process_image_data(&image_data, "clipboard").await.unwrap();
+        assert_eq!(copied.parent().unwrap(), temp_dir.path().join("clipboard"));
+        // Duplicates are found in other sources' subdirectories
+        assert_eq!(processor.process_image_data(&image_data, "screenshot").await.unwrap(), copied);
+        assert_eq!(processor.process_image_file(&copied, "file").await.unwrap(), copied);
+        
+        assert_eq!(config.get_screenshot_path("download", "a.png"), temp_dir.path().join("downloads/a.png"));
+        assert_eq!(config.get_screenshot_path("escape", "a.png"), temp_dir.path().join("a.png"));
+    }
+    
+    #[tokio::test]
+    async fn test_duplicates_stored_once() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().join("out"),
+            ..Config::default()
+        };
+        let processor = ImageProcessor::new(config).await.unwrap();
+        let image_data = create_test_image_data();
+        
+        // Concurrent detectors firing for the same image
+        let (first, second) = tokio::join!(
+            processor.process_image_data(&image_data, "clipboard"),
+            processor.process_image_data(&image_data, "screenshot"),
+        );
+        assert_eq!(first.unwrap(), second.unwrap());
+        
+        // A file with the same content, and a stored image handed back in
+        let input = temp_dir.path().join("copy.png");
+        std::fs::write(&input, &image_data).unwrap();
+        let from_file = processor.process_image_file(&input, "file").await.unwrap();
+        assert_eq!(processor.process_image_file(&from_file, "file").await.unwrap(), from_file);
+        
+        let stored: Vec<_> = std::fs::read_dir(temp_dir.path().join("out"))
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| crate::is_image_file(&entry.path()))
+            .collect();
+        assert_eq!(stored.len(), 1);
+    }
+    
+    #[tokio::test]
+    async fn test_duplicates_linked() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().join("out"),
+            duplicates: DuplicateMode::Link,
+            ..Config::default()
+        };
+        let processor = ImageProcessor::new(config).await.unwrap();
+        let image_data = create_test_image_data();
+        
+        // Detectors seeing the same capture still get the one copy
+        let first = processor.process_image_data(&image_data, "screenshot").await.unwrap();
+        assert_eq!(processor.process_image_data(&image_data, "clipboard").await.unwrap(), first);
+        
+        // The same content captured again later gets its own entry
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(60);
+        std::fs::File::options().write(true).open(&first).unwrap().set_modified(old).unwrap();
+        let second = processor.process_image_data(&image_data, "clipboard").await.unwrap();
+        assert_ne!(second, first);
+        assert_eq!(std::fs::read(&second).unwrap(), std::fs::read(&first).unwrap());
+        
+        let index = metadata::load(&temp_dir.path().join("out")).await.unwrap();
+        assert_eq!(index.len(), 2);
+        let linked = &index[second.file_name().unwrap().to_string_lossy().as_ref()];
+        assert_eq!(linked.source, "clipboard");
+        assert_eq!(linked.hash, index[first.file_name().unwrap().to_string_lossy().as_ref()].hash);
+        
+        // The new copy counts as just stored, so detections of it aren't linked again
+        let third = processor.process_image_data(&image_data, "screenshot").await.unwrap();
+        assert!(third == second || third == first, "{:?}", third);
+        assert_eq!(metadata::load(&temp_dir.path().join("out")).await.unwrap().len(), 2);
     }
-}
\ No newline at end of file
+    
+    #[tokio::test]
+    async fn test_bit_depth() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut deep = Vec::new();
+        DynamicImage::ImageRgb16(image::ImageBuffer::from_pixel(2, 2, image::Rgb([1000, 30000, 65535])))
+            .write_to(&mut std::io::Cursor::new(&mut deep), ImageFormat::Png)
+            .unwrap();
+        
+        let config = Config {
+            screenshot_dir: temp_dir.path().join("preserve"),
+            ..Config::default()
+        };
+        let processor = ImageProcessor::new(config.clone()).await.unwrap();
+        let preserved = processor.process_image_data(&deep, "test").await.unwrap();
+        assert!(matches!(image::open(&preserved).unwrap(), DynamicImage::ImageRgb16(_)));
+        
+        let config = Config {
+            screenshot_dir: temp_dir.path().join("reduce"),
+            bit_depth: crate::config::BitDepth::Reduce,
+            ..config
+        };
+        let processor = ImageProcessor::new(config).await.unwrap();
+        let reduced = processor.process_image_data(&deep, "test").await.unwrap();
+        assert!(matches!(image::open(&reduced).unwrap(), DynamicImage::ImageRgb8(_)));
+    }
+    
+    #[tokio::test]
+    async fn test_mirror() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().join("out"),
+            mirror: crate::config::MirrorConfig {
+                dir: Some(temp_dir.path().join("vault")),
+                name_template: "{source}.{ext}".to_string(),
+                sources: Vec::new(),
+            },
+            ..Config::default()
+        };
+        let processor = ImageProcessor::new(config).await.unwrap();
+        let stored = processor.process_image_data(&create_test_image_data(), "clipboard").await.unwrap();
+        
+        let copy = temp_dir.path().join("vault").join("clipboard.png");
+        assert_eq!(std::fs::read(copy).unwrap(), std::fs::read(stored).unwrap());
+    }
+}
@@ -0,0 +1,221 @@
+//! Color and content statistics for an image, shown in compact previews:
+//! its dominant colors, average brightness and whether it's mostly text.
+//!
+//! Everything is computed in-process from a sample of at most
+//! `SAMPLE_SIZE` pixels on the long side, taken without smoothing so text
+//! keeps its hard edges. Colors are bucketed into a 4-bit-per-channel
+//! histogram; a bucket's color is the mean of the pixels in it.
+//!
+//! Text is told apart by its shape in that histogram: a background taking up
+//! much of the image, a few ink colors, and frequent sharp changes in
+//! brightness along each row. Photos spread over far more buckets, and flat
+//! UI has few edges. It's a hint for whether OCR is worth running, not a
+//! classifier.
+
+use image::{imageops::FilterType, DynamicImage, GenericImageView, Rgba};
+use std::fmt::Write;
+
+/// Longest side of the sample the statistics are computed on
+const SAMPLE_SIZE: u32 = 800;
+
+/// Bits kept per channel when bucketing colors
+const BUCKET_BITS: u32 = 4;
+
+/// Alpha below which a pixel is left out
+const ALPHA_THRESHOLD: u8 = 128;
+
+/// Most dominant colors reported
+const MAX_DOMINANT: usize = 3;
+
+/// Smallest share of the image a reported dominant color covers
+const MIN_DOMINANT_SHARE: f32 = 0.03;
+
+/// Brightness change between neighbours counted as an edge, out of 255
+const EDGE_CONTRAST: f32 = 48.0;
+
+/// Smallest share of the image the background covers in text
+const TEXT_MIN_BACKGROUND: f32 = 0.45;
+
+/// Most buckets needed to cover `TEXT_COVERAGE` of text
+const TEXT_MAX_BUCKETS: usize = 32;
+const TEXT_COVERAGE: f32 = 0.9;
+
+/// Range of edge density (edges per horizontal neighbour pair) in text
+const TEXT_EDGE_DENSITY: (f32, f32) = (0.02, 0.4);
+
+/// A color and the share of the image it covers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DominantColor {
+    pub color: [u8; 3],
+    pub share: f32,
+}
+
+impl DominantColor {
+    pub fn hex(&self) -> String {
+        let [r, g, b] = self.color;
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageStats {
+    /// Most common colors first
+    pub dominant: Vec<DominantColor>,
+    /// Mean luma of the visible pixels, from 0 to 1
+    pub brightness: f32,
+    pub mostly_text: bool,
+}
+
+impl ImageStats {
+    /// One line for compact previews, like `🎨 #1e1e1e 71% #d4d4d4 12% · ☀️ 20% · 📝 mostly text`
+    pub fn summary(&self) -> String {
+        let mut summary = String::new();
+        if !self.dominant.is_empty() {
+            summary.push('🎨');
+            for dominant in &self.dominant {
+                let _ = write!(summary, " {} {:.0}%", dominant.hex(), dominant.share * 100.0);
+            }
+            summary.push_str(" · ");
+        }
+        let _ = write!(summary, "☀️ {:.0}%", self.brightness * 100.0);
+        if self.mostly_text {
+            summary.push_str(" · 📝 mostly text");
+        }
+        summary
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Bucket {
+    count: u32,
+    sums: [u64; 3],
+}
+
+/// Statistics for `img`
+pub fn analyze(img: &DynamicImage) -> ImageStats {
+    let (width, height) = img.dimensions();
+    let sample = if width.max(height) > SAMPLE_SIZE {
+        img.resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Nearest).to_rgba8()
+    } else {
+        img.to_rgba8()
+    };
+
+    let mut buckets = vec![Bucket::default(); 1 << (BUCKET_BITS * 3)];
+    let (mut visible, mut luma_sum) = (0u32, 0f64);
+    let (mut pairs, mut edges) = (0u32, 0u32);
+    for row in sample.rows() {
+        let mut previous: Option<f32> = None;
+        for pixel in row {
+            if pixel[3] < ALPHA_THRESHOLD {
+                previous = None;
+                continue;
+            }
+            let bucket = &mut buckets[bucket_index(pixel)];
+            bucket.count += 1;
+            for channel in 0..3 {
+                bucket.sums[channel] += pixel[channel] as u64;
+            }
+
+            let luma = luma(pixel);
+            visible += 1;
+            luma_sum += luma as f64;
+            if let Some(previous) = previous {
+                pairs += 1;
+                if (luma - previous).abs() > EDGE_CONTRAST {
+                    edges += 1;
+                }
+            }
+            previous = Some(luma);
+        }
+    }
+
+    if visible == 0 {
+        return ImageStats { dominant: Vec::new(), brightness: 0.0, mostly_text: false };
+    }
+
+    buckets.retain(|bucket| bucket.count > 0);
+    buckets.sort_by_key(|bucket| std::cmp::Reverse(bucket.count));
+    let share = |bucket: &Bucket| bucket.count as f32 / visible as f32;
+
+    let dominant = buckets
+        .iter()
+        .take(MAX_DOMINANT)
+        .filter(|bucket| share(bucket) >= MIN_DOMINANT_SHARE)
+        .map(|bucket| DominantColor {
+            color: bucket.sums.map(|sum| (sum / bucket.count as u64) as u8),
+            share: share(bucket),
+        })
+        .collect();
+
+    let mut covered = 0.0;
+    let coverage_buckets = buckets
+        .iter()
+        .take_while(|bucket| {
+            let below = covered < TEXT_COVERAGE;
+            covered += share(bucket);
+            below
+        })
+        .count();
+    let edge_density = if pairs == 0 { 0.0 } else { edges as f32 / pairs as f32 };
+    let mostly_text = share(&buckets[0]) >= TEXT_MIN_BACKGROUND
+        && coverage_buckets <= TEXT_MAX_BUCKETS
+        && (TEXT_EDGE_DENSITY.0..=TEXT_EDGE_DENSITY.1).contains(&edge_density);
+
+    ImageStats {
+        dominant,
+        brightness: (luma_sum / visible as f64 / 255.0) as f32,
+        mostly_text,
+    }
+}
+
+fn bucket_index(pixel: &Rgba<u8>) -> usize {
+    let shift = 8 - BUCKET_BITS;
+    ((pixel[0] as usize >> shift) << (BUCKET_BITS * 2)) | ((pixel[1] as usize >> shift) << BUCKET_BITS) | (pixel[2] as usize >> shift)
+}
+
+/// Rec. 709 luma, out of 255
+fn luma(pixel: &Rgba<u8>) -> f32 {
+    0.2126 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.0722 * pixel[2] as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    #[test]
+    fn test_text_and_dominant_colors() {
+        // Dark lines of "text" on white: short strokes with gaps, every other row band
+        let mut img = RgbaImage::from_pixel(400, 200, Rgba([255, 255, 255, 255]));
+        for y in (10..190).filter(|y| y % 20 < 10) {
+            for x in (10..390).filter(|x| x % 6 < 2) {
+                img.put_pixel(x, y, Rgba([20, 20, 20, 255]));
+            }
+        }
+        let stats = analyze(&DynamicImage::ImageRgba8(img));
+        assert!(stats.mostly_text);
+        assert_eq!(stats.dominant[0].hex(), "#ffffff");
+        assert_eq!(stats.dominant[1].hex(), "#141414");
+        assert!(stats.dominant[0].share > 0.8);
+        assert!(stats.brightness > 0.8);
+        assert!(stats.summary().starts_with("🎨 #ffffff 8"));
+        assert!(stats.summary().ends_with("📝 mostly text"));
+    }
+
+    #[test]
+    fn test_photo_and_flat_images() {
+        // A smooth gradient with noise spreads over many buckets
+        let photo = RgbaImage::from_fn(300, 300, |x, y| {
+            let noise = ((x * 7919 + y * 104729) % 37) as u8;
+            Rgba([(x % 256) as u8, (y % 256) as u8, 128u8.wrapping_add(noise), 255])
+        });
+        assert!(!analyze(&DynamicImage::ImageRgba8(photo)).mostly_text);
+
+        // Flat blocks have a background and few colors but hardly any edges
+        let flat = RgbaImage::from_fn(300, 300, |x, _| if x < 100 { Rgba([30, 60, 200, 255]) } else { Rgba([240, 240, 240, 255]) });
+        let stats = analyze(&DynamicImage::ImageRgba8(flat));
+        assert!(!stats.mostly_text);
+        assert_eq!(stats.dominant.len(), 2);
+        assert_eq!(stats.summary(), "🎨 #f0f0f0 67% #1e3cc8 33% · ☀️ 71%");
+    }
+}
@@ -0,0 +1,130 @@
+//! `klipdot inject`: feeding an image into the pipeline as if a monitor had
+//! intercepted it, for integration tests and demo scripts on machines
+//! without a GUI.
+//!
+//! Each source applies the same checks its monitor does before storing, so a
+//! clipboard image is still skipped during quiet hours or when `klipdot
+//! paste-image` handed it back, and the stored image is recorded under the
+//! source's name.
+
+use crate::{config::Config, error::Result, image_processor::ImageProcessor, paste_image, pause, Error};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::info;
+
+/// Monitor an injected image pretends to come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InjectSource {
+    #[default]
+    Clipboard,
+    Screenshot,
+    Stdin,
+}
+
+impl InjectSource {
+    /// Source recorded for the stored image
+    pub fn as_str(self) -> &'static str {
+        match self {
+            InjectSource::Clipboard => "clipboard",
+            InjectSource::Screenshot => "screenshot",
+            InjectSource::Stdin => "stdin",
+        }
+    }
+}
+
+impl fmt::Display for InjectSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for InjectSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "clipboard" => Ok(InjectSource::Clipboard),
+            "screenshot" => Ok(InjectSource::Screenshot),
+            "stdin" => Ok(InjectSource::Stdin),
+            other => Err(Error::InvalidInput(format!(
+                "Invalid source '{}', expected 'clipboard', 'screenshot' or 'stdin'",
+                other
+            ))),
+        }
+    }
+}
+
+/// What happened to an injected image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Injected {
+    Stored(PathBuf),
+    /// The source's monitor would have left the image alone, for this reason
+    Skipped(String),
+}
+
+/// Store `data` the way the monitor for `source` would. `state_dir` is the
+/// KlipDot home directory, where `paste-image` marks images it handed back.
+pub async fn inject(config: &Config, state_dir: &Path, data: &[u8], source: InjectSource) -> Result<Injected> {
+    if source == InjectSource::Clipboard {
+        if let Some(reason) = pause::check(&config.pause, None) {
+            return Ok(Injected::Skipped(format!("interception is paused ({})", reason)));
+        }
+        if paste_image::take_handed_back(state_dir, data).await {
+            return Ok(Injected::Skipped("it was handed back by paste-image".to_string()));
+        }
+    }
+
+    let processor = ImageProcessor::new(config.clone()).await?;
+    let stored = processor.process_image_data(data, source.as_str()).await?;
+    info!("Injected {} image stored at {:?}", source, stored);
+    Ok(Injected::Stored(stored))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn png_bytes() -> Vec<u8> {
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(4, 4))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn test_parse_source() {
+        assert_eq!("Screenshot".parse::<InjectSource>().unwrap(), InjectSource::Screenshot);
+        assert_eq!("stdin".parse::<InjectSource>().unwrap().as_str(), "stdin");
+        assert!("printer".parse::<InjectSource>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_inject_as_sources() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().join("shots"),
+            ..Config::default()
+        };
+        let data = png_bytes();
+
+        let Injected::Stored(stored) = inject(&config, temp_dir.path(), &data, InjectSource::Screenshot).await.unwrap() else {
+            panic!("screenshot image was not stored");
+        };
+        let index = crate::metadata::load(stored.parent().unwrap()).await.unwrap();
+        assert_eq!(index[stored.file_name().unwrap().to_string_lossy().as_ref()].source, "screenshot");
+
+        // Handed back by paste-image: the clipboard monitor leaves it alone once
+        paste_image::mark_handed_back(temp_dir.path(), &data).await.unwrap();
+        let skipped = inject(&config, temp_dir.path(), &data, InjectSource::Clipboard).await.unwrap();
+        assert!(matches!(skipped, Injected::Skipped(_)));
+        assert!(matches!(
+            inject(&config, temp_dir.path(), &data, InjectSource::Clipboard).await.unwrap(),
+            Injected::Stored(_)
+        ));
+
+        assert!(inject(&config, temp_dir.path(), b"not an image", InjectSource::Stdin).await.is_err());
+    }
+}
@@ -1,5 +1,7 @@
+use crate::config::ShellIntegration;
 use crate::error::Result;
-use std::path::PathBuf;
+use crate::substitution::shell_quote;
+use std::path::{Path, PathBuf};
 use tracing::{debug, info, warn};
 
 pub struct ShellInstaller {
@@ -7,27 +9,42 @@ pub struct ShellInstaller {
     home_dir: PathBuf,
     shell_rc_path: PathBuf,
     hooks_dir: PathBuf,
+    preview_key: String,
+    monitor_commands: Vec<String>,
+    scan_dirs: Vec<String>,
 }
 
 impl ShellInstaller {
     pub fn new(shell_type: &str) -> Self {
         let home_dir = dirs::home_dir().unwrap_or_else(|| "/tmp".into());
         let shell_rc_path = Self::get_shell_rc_path(&home_dir, shell_type);
-        let hooks_dir = crate::get_home_dir().unwrap_or_else(|_| home_dir.clone().join(".klipdot")).join(crate::HOOKS_DIR);
+        let klipdot_dir = crate::get_home_dir().unwrap_or_else(|_| home_dir.clone().join(".klipdot"));
+        let hooks_dir = klipdot_dir.join(crate::HOOKS_DIR);
         
         Self {
             shell_type: shell_type.to_string(),
             home_dir,
             shell_rc_path,
             hooks_dir,
+            preview_key: ShellIntegration::default().preview_key,
+            monitor_commands: Vec::new(),
+            scan_dirs: Vec::new(),
         }
     }
     
+    /// Take hook settings such as the preview key binding from the config
+    pub fn with_config(mut self, shell_integration: &ShellIntegration) -> Self {
+        self.preview_key = shell_integration.preview_key.clone();
+        self.monitor_commands = shell_integration.monitor_commands.clone();
+        self.scan_dirs = shell_integration.scan_dirs.clone();
+        self
+    }
+    
     pub fn detect_shell() -> Self {
         let shell = std::env::var("SHELL")
             .unwrap_or_else(|_| "/bin/bash".to_string())
             .split('/')
-            .last()
+            .next_back()
             .unwrap_or("bash")
             .to_string();
         
@@ -53,6 +70,10 @@ impl ShellInstaller {
         // Add source line to shell RC file
         self.add_source_line().await?;
         
+        if crate::termux::is_termux() {
+            self.install_termux_share_receiver().await;
+        }
+        
         info!("Shell hooks installed successfully");
         Ok(())
     }
@@ -72,6 +93,16 @@ impl ShellInstaller {
         Ok(())
     }
     
+    /// Receive images shared to Termux from other Android apps
+    async fn install_termux_share_receiver(&self) {
+        if let Err(e) = crate::termux::install_share_receiver(&self.home_dir, &Self::get_klipdot_binary_path()).await {
+            warn!("Images shared to Termux won't be stored: {}", e);
+        }
+        if crate::termux::storage_dir().is_none() {
+            warn!("Run termux-setup-storage so KlipDot can reach shared storage such as Download");
+        }
+    }
+    
     async fn install_zsh_hooks(&self) -> Result<()> {
         let hook_content = self.generate_zsh_hook_content();
         let hook_path = self.hooks_dir.join("zsh-hooks.zsh");
@@ -89,6 +120,12 @@ impl ShellInstaller {
         tokio::fs::write(&hook_path, hook_content).await?;
         debug!("Created Bash hook file: {:?}", hook_path);
         
+        if cfg!(feature = "preview") {
+            let plugin_path = self.hooks_dir.join("klipdot.blesh");
+            tokio::fs::write(&plugin_path, self.generate_blesh_plugin_content()).await?;
+            debug!("Created ble.sh plugin: {:?}", plugin_path);
+        }
+        
         Ok(())
     }
     
@@ -133,13 +170,11 @@ preexec_klipdot() {{
 
 # Hook into command completion
 precmd_klipdot() {{
-    # Check for new files in current directory
-    for file in *.{{png,jpg,jpeg,gif,bmp,webp,svg}}(N); do
-        if [[ -f "$file" ]]; then
-            klipdot_handle_image "$file"
-        fi
-    done
-}}
+    # Note this terminal so intercepted images can be previewed in it, and
+    # the directory relative clipboard paths start from
+    [[ "$TTY" == /dev/* ]] && print -rl -- "$TTY" "$PWD" >| "$KLIPDOT_DIR/{active_terminal}" 2>/dev/null
+    
+{scan}}}
 
 # Add hooks to ZSH
 if [[ -n "$ZSH_VERSION" ]]; then
@@ -148,10 +183,45 @@ if [[ -n "$ZSH_VERSION" ]]; then
     add-zsh-hook precmd precmd_klipdot
 fi
 
+# Replace pasted image payloads with KlipDot paths before the line runs
+klipdot_substitute_accept_line() {{
+    if [[ "$BUFFER" == *data:image/* || "$BUFFER" =~ [A-Za-z0-9+/=]{{100,}} ]]; then
+        local rewritten
+        rewritten=$("$KLIPDOT_BIN" substitute -- "$BUFFER" 2>/dev/null) && BUFFER="$rewritten"
+    fi
+    zle .accept-line
+}}
+
+if [[ -o interactive ]]; then
+    zle -N accept-line klipdot_substitute_accept_line
+fi
+
+# Offer intercepted screenshots first when completing image arguments
+klipdot_image_flags=(--image --img --attach --file --screenshot -i)
+
+_klipdot_screenshots() {{
+    (( ${{klipdot_image_flags[(Ie)${{words[CURRENT-1]}}]}} )) || return 1
+    local -a shots
+    shots=("${{(@f)$("$KLIPDOT_BIN" complete-paths -- "$PREFIX" 2>/dev/null)}}")
+    shots=(${{shots:#}})
+    (( ${{#shots}} )) && compadd -U -V klipdot-screenshots -X 'KlipDot screenshots' -a shots
+    # Let the remaining completers add ordinary files after the screenshots
+    return 1
+}}
+
+if [[ -o interactive ]]; then
+    zstyle -a ':completion:*' completer klipdot_completers || klipdot_completers=(_complete _ignored)
+    if (( ! ${{klipdot_completers[(Ie)_klipdot_screenshots]}} )); then
+        zstyle ':completion:*' completer _klipdot_screenshots "${{klipdot_completers[@]}}"
+    fi
+    unset klipdot_completers
+fi
+{}
 # Enhanced aliases
 alias cp='klipdot_cp'
 alias mv='klipdot_mv'
 alias scp='klipdot_scp'
+alias rsync='klipdot_rsync'
 
 klipdot_cp() {{
     local result
@@ -182,11 +252,22 @@ klipdot_mv() {{
 }}
 
 klipdot_scp() {{
-    local result
+    local result prepared
+    local -a sources
+    sources=("$@")
+    # Images sent to another host go as resized, metadata-free copies, and
+    # copies the system policy forbids aren't made at all
+    prepared=$("$KLIPDOT_BIN" prepare-upload scp -- "$@" 2>/dev/null)
+    if [[ $? -eq {forbidden} ]]; then
+        echo "scp: copies to this host are forbidden by the klipdot policy" >&2
+        return {forbidden}
+    elif [[ -n "$prepared" ]]; then
+        eval "set -- $prepared"
+    fi
     command scp "$@"
     result=$?
     
-    for arg in "$@"; do
+    for arg in "${{sources[@]}}"; do
         if [[ -f "$arg" ]]; then
             klipdot_handle_image "$arg"
         fi
@@ -194,7 +275,136 @@ klipdot_scp() {{
     
     return $result
 }}
-"#, klipdot_dir.display(), klipdot_bin)
+
+klipdot_rsync() {{
+    local result prepared
+    local -a sources
+    sources=("$@")
+    # Images sent to another host go as resized, metadata-free copies, and
+    # copies the system policy forbids aren't made at all
+    prepared=$("$KLIPDOT_BIN" prepare-upload rsync -- "$@" 2>/dev/null)
+    if [[ $? -eq {forbidden} ]]; then
+        echo "rsync: copies to this host are forbidden by the klipdot policy" >&2
+        return {forbidden}
+    elif [[ -n "$prepared" ]]; then
+        eval "set -- $prepared"
+    fi
+    command rsync "$@"
+    result=$?
+    
+    for arg in "${{sources[@]}}"; do
+        if [[ -f "$arg" ]]; then
+            klipdot_handle_image "$arg"
+        fi
+    done
+    
+    return $result
+}}
+{}"#, klipdot_dir.display(), klipdot_bin, self.zsh_preview_widget(), self.monitor_wrappers(), active_terminal = crate::ACTIVE_TERMINAL_FILE, scan = self.scan_dirs_command("", " &!"), forbidden = crate::policy::FORBIDDEN_EXIT_CODE)
+    }
+    
+    /// ZLE widget previewing the image path under the cursor
+    fn zsh_preview_widget(&self) -> String {
+        if !cfg!(feature = "preview") {
+            return String::new();
+        }
+        
+        format!(r#"
+# Preview the image path under the cursor
+klipdot_preview_widget() {{
+    zle -I
+    if ! "$KLIPDOT_BIN" preview --line "$BUFFER" --cursor "$CURSOR" 2>/dev/null; then
+        zle -M "[KlipDot] No image path under cursor"
+    fi
+}}
+
+if [[ -o interactive ]]; then
+    zle -N klipdot-preview klipdot_preview_widget
+    bindkey {} klipdot-preview
+fi
+"#, shell_quote(&self.preview_key))
+    }
+    
+    /// Prompt hook lines starting one background scan of the configured
+    /// directories, wrapped in `open` and `close` to detach it; empty when
+    /// scanning is off
+    fn scan_dirs_command(&self, open: &str, close: &str) -> String {
+        if self.scan_dirs.is_empty() {
+            return String::new();
+        }
+        
+        let dirs: Vec<String> = self
+            .scan_dirs
+            .iter()
+            .map(|dir| match dir.strip_prefix('~') {
+                Some("") => "\"$HOME\"".to_string(),
+                Some(rest) if rest.starts_with('/') => format!("\"$HOME\"{}", shell_quote(rest)),
+                _ => shell_quote(dir),
+            })
+            .collect();
+        format!(
+            "    # Store new images from the watched directories, in one background call\n    {}\"$KLIPDOT_BIN\" --quiet scan-new -- {} >/dev/null 2>&1{}\n",
+            open,
+            dirs.join(" "),
+            close
+        )
+    }
+    
+    /// Functions running the configured commands under `klipdot
+    /// monitor-output` when their output goes to a terminal. Shared by the
+    /// zsh and bash hooks.
+    fn monitor_wrappers(&self) -> String {
+        if !cfg!(feature = "preview") || self.monitor_commands.is_empty() {
+            return String::new();
+        }
+        
+        // Leading arguments to match, per program; an empty list matches any
+        let mut programs: Vec<(&str, Vec<Vec<&str>>)> = Vec::new();
+        for entry in &self.monitor_commands {
+            let mut words = entry.split_whitespace();
+            let Some(program) = words.next() else { continue };
+            let valid = !program.starts_with('-')
+                && program.chars().all(|c| c.is_ascii_alphanumeric() || "_.+-".contains(c));
+            if !valid {
+                warn!("Not wrapping {:?}: not a plain command name", entry);
+                continue;
+            }
+            match programs.iter_mut().find(|(name, _)| *name == program) {
+                Some((_, leading)) => leading.push(words.collect()),
+                None => programs.push((program, vec![words.collect()])),
+            }
+        }
+        
+        let mut wrappers = String::from("\n# Preview images printed by these commands (shell_integration.monitor_commands)\n");
+        for (program, leading) in programs {
+            let matches: Vec<String> = leading
+                .iter()
+                .map(|words| {
+                    let tests: Vec<String> = words
+                        .iter()
+                        .enumerate()
+                        .map(|(index, word)| format!("\"${}\" == {}", index + 1, shell_quote(word)))
+                        .collect();
+                    tests.join(" && ")
+                })
+                .collect();
+            let condition = if matches.iter().any(String::is_empty) {
+                "[[ -t 1 ]]".to_string()
+            } else {
+                format!("[[ -t 1 ]] && [[ ( {} ) ]]", matches.join(" ) || ( "))
+            };
+            wrappers.push_str(&format!(
+                r#"function {program} {{
+    if {condition}; then
+        "$KLIPDOT_BIN" --quiet monitor-output -- {program} "$@"
+    else
+        command {program} "$@"
+    fi
+}}
+"#
+            ));
+        }
+        wrappers
     }
     
     fn generate_bash_hook_content(&self) -> String {
@@ -218,9 +428,9 @@ klipdot_handle_image() {{
     return 1
 }}
 
-# Hook into command execution
+# Hook into command execution, given the command about to run
 klipdot_preexec() {{
-    local cmd="$BASH_COMMAND"
+    local cmd="$1"
     
     # Check for image-related commands
     if [[ "$cmd" =~ (cp|mv|scp|rsync).*\.(png|jpg|jpeg|gif|bmp|webp|svg) ]]; then
@@ -235,28 +445,140 @@ klipdot_preexec() {{
     done
 }}
 
-# Hook into prompt
+# Hook into prompt, keeping $? for the prompt commands that follow
+KLIPDOT_TTY=$(tty 2>/dev/null)
 klipdot_precmd() {{
-    # Check for new files in current directory
-    for file in *.{{png,jpg,jpeg,gif,bmp,webp,svg}}; do
-        if [[ -f "$file" ]] 2>/dev/null; then
-            klipdot_handle_image "$file"
+    local status=$?
+    KLIPDOT_COMMAND_LINE=
+    
+    # Note this terminal so intercepted images can be previewed in it, and
+    # the directory relative clipboard paths start from
+    [[ "$KLIPDOT_TTY" == /dev/* ]] && printf '%s\n' "$KLIPDOT_TTY" "$PWD" >| "$KLIPDOT_DIR/{active_terminal}" 2>/dev/null
+    
+{scan}    return $status
+}}
+
+# Last in PROMPT_COMMAND: from here until the next prompt, commands come
+# from the line the user typed
+klipdot_prompt_ready() {{
+    local status=$?
+    KLIPDOT_COMMAND_LINE=1
+    return $status
+}}
+
+klipdot_return() {{
+    return "$1"
+}}
+
+# DEBUG trap: runs klipdot_preexec for the user's commands only (not prompt
+# commands or completions), then whatever trap was set before KlipDot's
+klipdot_debug_trap() {{
+    local status=$?
+    if [[ -n "$KLIPDOT_COMMAND_LINE" && -z "${{COMP_LINE-}}" && "$BASH_COMMAND" != klipdot_precmd ]]; then
+        klipdot_preexec "$BASH_COMMAND"
+    fi
+    if [[ -n "$KLIPDOT_PREVIOUS_DEBUG_TRAP" ]]; then
+        klipdot_return "$status"
+        eval "$KLIPDOT_PREVIOUS_DEBUG_TRAP"
+    fi
+}}
+
+# Takes over the DEBUG trap, chaining any trap set before. Run from
+# PROMPT_COMMAND at the first prompt, since neither the sourced hooks nor a
+# function can see a DEBUG trap set outside them.
+KLIPDOT_INSTALL_TRAP='KLIPDOT_PREVIOUS_DEBUG_TRAP=$(trap -p DEBUG); klipdot_install_debug_trap'
+klipdot_install_debug_trap() {{
+    local previous=${{KLIPDOT_PREVIOUS_DEBUG_TRAP#trap -- }} entry
+    previous=${{previous% DEBUG}}
+    KLIPDOT_PREVIOUS_DEBUG_TRAP=
+    # Sourcing the hooks twice mustn't chain the trap to itself
+    if [[ -n "$previous" && "$previous" != "'klipdot_debug_trap'" ]]; then
+        eval "KLIPDOT_PREVIOUS_DEBUG_TRAP=$previous"
+    fi
+    
+    if [[ "$(declare -p PROMPT_COMMAND 2>/dev/null)" == "declare -a"* ]]; then
+        local -a commands=()
+        for entry in "${{PROMPT_COMMAND[@]}}"; do
+            [[ "$entry" == "$KLIPDOT_INSTALL_TRAP" ]] || commands+=("$entry")
+        done
+        PROMPT_COMMAND=("${{commands[@]}}")
+    else
+        PROMPT_COMMAND=${{PROMPT_COMMAND//$'\n'"$KLIPDOT_INSTALL_TRAP"/}}
+    fi
+    trap 'klipdot_debug_trap' DEBUG
+}}
+
+# Set up command hooks. With bash-preexec (loaded before this file) the hooks
+# join its function arrays, sharing its DEBUG trap with starship, atuin and
+# the like instead of chaining another.
+if [[ -n "${{bash_preexec_imported-}}${{__bp_imported-}}" ]]; then
+    [[ " ${{preexec_functions[*]}} " == *" klipdot_preexec "* ]] || preexec_functions+=(klipdot_preexec)
+    [[ " ${{precmd_functions[*]}} " == *" klipdot_precmd "* ]] || precmd_functions+=(klipdot_precmd)
+elif [[ "$(declare -p PROMPT_COMMAND 2>/dev/null)" == "declare -a"* ]]; then
+    # An array since bash 5.1
+    if [[ " ${{PROMPT_COMMAND[*]}} " != *" klipdot_precmd "* ]]; then
+        PROMPT_COMMAND=(klipdot_precmd "${{PROMPT_COMMAND[@]}}" klipdot_prompt_ready "$KLIPDOT_INSTALL_TRAP")
+    fi
+elif [[ "$PROMPT_COMMAND" != *klipdot_precmd* ]]; then
+    # Joined with newlines, so a command ending in ';' stays valid
+    PROMPT_COMMAND="klipdot_precmd${{PROMPT_COMMAND:+$'\n'$PROMPT_COMMAND}}"$'\n'"klipdot_prompt_ready"$'\n'"$KLIPDOT_INSTALL_TRAP"
+fi
+
+# Replace pasted image payloads with KlipDot paths before the line runs
+klipdot_substitute_line() {{
+    if [[ "$READLINE_LINE" == *data:image/* || "$READLINE_LINE" =~ [A-Za-z0-9+/=]{{100,}} ]]; then
+        local rewritten
+        if rewritten=$("$KLIPDOT_BIN" substitute -- "$READLINE_LINE" 2>/dev/null); then
+            READLINE_LINE="$rewritten"
+            READLINE_POINT=${{#READLINE_LINE}}
         fi
-    done 2>/dev/null
+    fi
 }}
 
-# Set up command hooks
-trap 'klipdot_preexec' DEBUG
-if [[ -z "$PROMPT_COMMAND" ]]; then
-    PROMPT_COMMAND="klipdot_precmd"
-else
-    PROMPT_COMMAND="klipdot_precmd;$PROMPT_COMMAND"
+if [[ $- == *i* ]]; then
+    bind -x '"\C-x\C-k": klipdot_substitute_line'
+    bind '"\C-m": "\C-x\C-k\C-j"'
 fi
 
-# Enhanced aliases
-alias cp='klipdot_cp'
-alias mv='klipdot_mv'
-alias scp='klipdot_scp'
+# Offer intercepted screenshots when completing image arguments of commands
+# without their own completion, deferring to any previous default completion
+klipdot_complete_default() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}" prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    case "$prev" in
+        --image|--img|--attach|--file|--screenshot|-i)
+            local IFS=$'\n'
+            COMPREPLY=($("$KLIPDOT_BIN" complete-paths -- "$cur" 2>/dev/null))
+            if [[ ${{#COMPREPLY[@]}} -gt 0 ]]; then
+                compopt -o nosort 2>/dev/null
+                return 0
+            fi
+            ;;
+    esac
+    if [[ -n "$KLIPDOT_PREVIOUS_DEFAULT_COMPLETION" ]]; then
+        "$KLIPDOT_PREVIOUS_DEFAULT_COMPLETION" "$@"
+        return $?
+    fi
+}}
+
+if [[ $- == *i* ]]; then
+    klipdot_previous=$(complete -p -D 2>/dev/null | sed -n 's/.*-F \([^ ]*\).*/\1/p')
+    if [[ "$klipdot_previous" != klipdot_complete_default ]]; then
+        KLIPDOT_PREVIOUS_DEFAULT_COMPLETION="$klipdot_previous"
+    fi
+    unset klipdot_previous
+    complete -D -o default -o bashdefault -F klipdot_complete_default
+fi
+
+# Load the ble.sh plugin when running under ble.sh
+if [[ -n "${{BLE_VERSION-}}" && -f "$KLIPDOT_DIR/{}/klipdot.blesh" ]]; then
+    ble-import "$KLIPDOT_DIR/{}/klipdot.blesh"
+fi
+
+# Enhanced aliases, leaving the user's own aliases for these commands alone
+for klipdot_command in cp mv scp rsync; do
+    alias "$klipdot_command" >/dev/null 2>&1 || alias "$klipdot_command=klipdot_$klipdot_command"
+done
+unset klipdot_command
 
 klipdot_cp() {{
     local result
@@ -287,11 +609,47 @@ klipdot_mv() {{
 }}
 
 klipdot_scp() {{
-    local result
+    local result prepared
+    local -a sources
+    sources=("$@")
+    # Images sent to another host go as resized, metadata-free copies, and
+    # copies the system policy forbids aren't made at all
+    prepared=$("$KLIPDOT_BIN" prepare-upload scp -- "$@" 2>/dev/null)
+    if [[ $? -eq {forbidden} ]]; then
+        echo "scp: copies to this host are forbidden by the klipdot policy" >&2
+        return {forbidden}
+    elif [[ -n "$prepared" ]]; then
+        eval "set -- $prepared"
+    fi
     command scp "$@"
     result=$?
     
-    for arg in "$@"; do
+    for arg in "${{sources[@]}}"; do
+        if [[ -f "$arg" ]]; then
+            klipdot_handle_image "$arg"
+        fi
+    done
+    
+    return $result
+}}
+
+klipdot_rsync() {{
+    local result prepared
+    local -a sources
+    sources=("$@")
+    # Images sent to another host go as resized, metadata-free copies, and
+    # copies the system policy forbids aren't made at all
+    prepared=$("$KLIPDOT_BIN" prepare-upload rsync -- "$@" 2>/dev/null)
+    if [[ $? -eq {forbidden} ]]; then
+        echo "rsync: copies to this host are forbidden by the klipdot policy" >&2
+        return {forbidden}
+    elif [[ -n "$prepared" ]]; then
+        eval "set -- $prepared"
+    fi
+    command rsync "$@"
+    result=$?
+    
+    for arg in "${{sources[@]}}"; do
         if [[ -f "$arg" ]]; then
             klipdot_handle_image "$arg"
         fi
@@ -299,7 +657,43 @@ klipdot_scp() {{
     
     return $result
 }}
-"#, klipdot_dir.display(), klipdot_bin)
+{}"#, klipdot_dir.display(), klipdot_bin, crate::HOOKS_DIR, crate::HOOKS_DIR, self.monitor_wrappers(), active_terminal = crate::ACTIVE_TERMINAL_FILE, scan = self.scan_dirs_command("( ", " & )"), forbidden = crate::policy::FORBIDDEN_EXIT_CODE)
+    }
+    
+    /// Optional ble.sh plugin giving bash the cursor-aware preview of the zsh widget
+    fn generate_blesh_plugin_content(&self) -> String {
+        r#"# KlipDot ble.sh Plugin
+# Loaded by the bash hooks via ble-import; expects KLIPDOT_DIR and KLIPDOT_BIN
+
+KLIPDOT_BLE_PREVIEW_KEY="${KLIPDOT_BLE_PREVIEW_KEY:-M-i}"
+KLIPDOT_BLE_INSERT_KEY="${KLIPDOT_BLE_INSERT_KEY:-M-p}"
+
+# Preview the image path under the cursor
+function ble/widget/klipdot-preview {
+    ble/edit/enter-command-layout
+    if ! "$KLIPDOT_BIN" preview --line "$_ble_edit_str" --cursor "$_ble_edit_ind" 2>/dev/null; then
+        echo "[KlipDot] No image path under cursor"
+    fi
+    ble/edit/leave-command-layout
+}
+
+# Insert the path of the most recent screenshot at the cursor, asking klipdot
+# so only stored images are offered
+function ble/widget/klipdot-insert-screenshot {
+    local latest
+    latest=$("$KLIPDOT_BIN" complete-paths --limit 1 2>/dev/null)
+    if [[ -z "$latest" ]]; then
+        ble/widget/.bell "[KlipDot] No screenshots yet"
+        return 1
+    fi
+    local path
+    printf -v path '%q' "$latest"
+    ble/widget/insert-string "$path"
+}
+
+ble-bind -f "$KLIPDOT_BLE_PREVIEW_KEY" klipdot-preview
+ble-bind -f "$KLIPDOT_BLE_INSERT_KEY" klipdot-insert-screenshot
+"#.to_string()
     }
     
     async fn add_source_line(&self) -> Result<()> {
@@ -367,7 +761,7 @@ klipdot_scp() {{
         Ok(())
     }
     
-    fn get_shell_rc_path(home_dir: &PathBuf, shell_type: &str) -> PathBuf {
+    fn get_shell_rc_path(home_dir: &Path, shell_type: &str) -> PathBuf {
         match shell_type {
             "zsh" => home_dir.join(".zshrc"),
             "bash" => home_dir.join(".bashrc"),
@@ -493,6 +887,9 @@ mod tests {
             home_dir: temp_dir.path().to_path_buf(),
             shell_rc_path: temp_dir.path().join(".bashrc"),
             hooks_dir: temp_dir.path().join("hooks"),
+            preview_key: "^[i".to_string(),
+            monitor_commands: Vec::new(),
+            scan_dirs: Vec::new(),
         };
         
         let bash_content = installer.generate_bash_hook_content();
@@ -504,6 +901,97 @@ mod tests {
         assert!(zsh_content.contains("KlipDot ZSH Integration"));
         assert!(zsh_content.contains("klipdot_handle_image"));
         assert!(zsh_content.contains("add-zsh-hook"));
+        assert!(zsh_content.contains("substitute -- \"$BUFFER\""));
+        assert!(bash_content.contains("substitute -- \"$READLINE_LINE\""));
+        assert!(zsh_content.contains("complete-paths -- \"$PREFIX\""));
+        assert!(bash_content.contains("complete-paths -- \"$cur\""));
+        assert!(zsh_content.contains("prepare-upload scp -- \"$@\""));
+        assert!(bash_content.contains("prepare-upload rsync -- \"$@\""));
+        assert!(bash_content.contains("for arg in \"${sources[@]}\"; do"));
+        assert!(zsh_content.contains("print -rl -- \"$TTY\" \"$PWD\" >| \"$KLIPDOT_DIR/active-terminal\""));
+        assert!(bash_content.contains("\"$KLIPDOT_TTY\" \"$PWD\" >| \"$KLIPDOT_DIR/active-terminal\""));
+        assert!(bash_content.contains("preexec_functions+=(klipdot_preexec)"));
+        assert!(!bash_content.contains("alias cp='klipdot_cp'"));
+        
+        if cfg!(feature = "preview") {
+            assert!(zsh_content.contains("bindkey '^[i' klipdot-preview"));
+        }
+        
+        assert!(bash_content.contains("ble-import \"$KLIPDOT_DIR/hooks/klipdot.blesh\""));
+        let blesh_content = installer.generate_blesh_plugin_content();
+        assert!(blesh_content.contains("function ble/widget/klipdot-preview"));
+        assert!(blesh_content.contains("--cursor \"$_ble_edit_ind\""));
+        assert!(blesh_content.contains("latest=$(\"$KLIPDOT_BIN\" complete-paths --limit 1 2>/dev/null)"));
+    }
+    
+    #[test]
+    fn test_monitor_wrappers() {
+        let config = ShellIntegration {
+            monitor_commands: vec!["pytest".to_string(), "cargo test".to_string(), "cargo bench".to_string(), "$(reboot) now".to_string()],
+            ..ShellIntegration::default()
+        };
+        let installer = ShellInstaller::new("bash").with_config(&config);
+        let wrappers = installer.monitor_wrappers();
+        
+        if !cfg!(feature = "preview") {
+            assert!(wrappers.is_empty());
+            return;
+        }
+        assert!(wrappers.contains("function pytest {\n    if [[ -t 1 ]]; then\n"));
+        assert!(wrappers.contains("if [[ -t 1 ]] && [[ ( \"$1\" == test ) || ( \"$1\" == bench ) ]]; then"));
+        assert!(wrappers.contains("monitor-output -- cargo \"$@\""));
+        assert!(wrappers.contains("command cargo \"$@\""));
+        assert!(!wrappers.contains("reboot"));
+        assert!(installer.generate_zsh_hook_content().contains("function pytest {"));
+    }
+    
+    #[test]
+    fn test_bash_hooks_chain_existing_hooks() {
+        if which::which("bash").is_err() {
+            return;
+        }
+        let temp_dir = TempDir::new().unwrap();
+        let installer = ShellInstaller::new("bash");
+        let hooks = temp_dir.path().join("bash-hooks.bash");
+        std::fs::write(&hooks, installer.generate_bash_hook_content()).unwrap();
+        
+        // A user trap and prompt command, then two prompts as bash runs them
+        let script = format!(
+            r#"trap 'echo "user trap: $BASH_COMMAND"' DEBUG
+PROMPT_COMMAND='echo "status $?";'
+source {hooks}
+source {hooks}
+klipdot_preexec() {{ echo "klipdot: $1"; }}
+false
+eval "$PROMPT_COMMAND"
+echo typed
+eval "$PROMPT_COMMAND"
+trap -p DEBUG
+"#,
+            hooks = shell_quote(&hooks.to_string_lossy())
+        );
+        let output = std::process::Command::new("bash").arg("-c").arg(script).output().unwrap();
+        let output = String::from_utf8_lossy(&output.stdout);
+        
+        assert!(output.contains("status 1\n"));
+        assert!(output.contains("klipdot: echo typed\nuser trap: echo typed\ntyped\n"));
+        assert!(!output.contains("klipdot: klipdot_precmd"));
+        assert!(output.ends_with("trap -- 'klipdot_debug_trap' DEBUG\n"));
+    }
+    
+    #[test]
+    fn test_scan_dirs_hook() {
+        let installer = ShellInstaller::new("bash");
+        assert!(!installer.generate_bash_hook_content().contains("scan-new"));
+        
+        let config = ShellIntegration {
+            scan_dirs: vec![".".to_string(), "~/Desktop".to_string(), "/mnt/my shots".to_string()],
+            ..ShellIntegration::default()
+        };
+        let installer = installer.with_config(&config);
+        let call = r#""$KLIPDOT_BIN" --quiet scan-new -- . "$HOME"/Desktop '/mnt/my shots' >/dev/null 2>&1"#;
+        assert!(installer.generate_bash_hook_content().contains(&format!("( {} & )", call)));
+        assert!(installer.generate_zsh_hook_content().contains(&format!("{} &!", call)));
     }
     
     #[tokio::test]
@@ -514,6 +1002,9 @@ mod tests {
             home_dir: temp_dir.path().to_path_buf(),
             shell_rc_path: temp_dir.path().join(".bashrc"),
             hooks_dir: temp_dir.path().join("hooks"),
+            preview_key: "^[i".to_string(),
+            monitor_commands: Vec::new(),
+            scan_dirs: Vec::new(),
         };
         
         // Create hooks directory and file
@@ -0,0 +1,99 @@
+//! One running KlipDot per user.
+//!
+//! Shell hooks start `klipdot monitor-clipboard` in every new shell and
+//! nothing stops `klipdot start` being run in two terminals, so each
+//! long-running entry point first takes an exclusive lock on
+//! [`crate::LOCK_FILE`] in the run-state directory, through
+//! [`crate::run_state::claim`]. The holder writes its
+//! PID into the file for others to report. The operating system releases the
+//! lock when the process exits, however it exits, so a crash never leaves a
+//! stale lock behind.
+
+use crate::error::Result;
+use std::fs::{File, TryLockError};
+use std::io::{Seek, Write};
+use std::path::Path;
+use tracing::debug;
+
+/// Held while this process is the running instance
+#[derive(Debug)]
+pub struct InstanceLock {
+    _file: File,
+}
+
+/// What [`acquire`] found
+#[derive(Debug)]
+pub enum Acquired {
+    /// This process is now the running instance
+    Locked(InstanceLock),
+    /// Another instance holds the lock, with its PID when it could be read
+    Running(Option<u32>),
+}
+
+/// Become the running instance, unless another process already is
+pub fn acquire(dir: &Path) -> Result<Acquired> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(crate::LOCK_FILE);
+    // Not truncated on open: until the lock is taken the PID is the holder's
+    let mut file = File::options().read(true).write(true).create(true).truncate(false).open(&path)?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => return Ok(Acquired::Running(holder_pid(dir))),
+        Err(TryLockError::Error(e)) => return Err(e.into()),
+    }
+
+    file.set_len(0)?;
+    file.rewind()?;
+    write!(file, "{}", std::process::id())?;
+    debug!("Took the instance lock {:?}", path);
+    Ok(Acquired::Locked(InstanceLock { _file: file }))
+}
+
+/// Whether another process is the running instance
+pub fn is_held(dir: &Path) -> bool {
+    let Ok(file) = File::options().read(true).write(true).open(dir.join(crate::LOCK_FILE)) else {
+        return false;
+    };
+    // When free, the lock is released again as `file` is dropped
+    matches!(file.try_lock(), Err(TryLockError::WouldBlock))
+}
+
+/// The PID the running instance wrote into the lock file
+pub fn holder_pid(dir: &Path) -> Option<u32> {
+    std::fs::read_to_string(dir.join(crate::LOCK_FILE)).ok()?.trim().parse().ok()
+}
+
+/// The message shown when an entry point finds another instance running
+pub fn already_running(pid: Option<u32>) -> String {
+    match pid {
+        Some(pid) => format!("KlipDot is already running (PID {})", pid),
+        None => "KlipDot is already running".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_single_instance() {
+        let temp_dir = TempDir::new().unwrap();
+        assert!(!is_held(temp_dir.path()));
+
+        let lock = match acquire(temp_dir.path()).unwrap() {
+            Acquired::Locked(lock) => lock,
+            Acquired::Running(pid) => panic!("lock held by {:?}", pid),
+        };
+        let pid = std::process::id();
+        assert!(is_held(temp_dir.path()));
+        assert_eq!(holder_pid(temp_dir.path()), Some(pid));
+        assert!(matches!(acquire(temp_dir.path()).unwrap(), Acquired::Running(Some(running)) if running == pid));
+
+        // Released with the lock, though the file and its PID stay behind
+        drop(lock);
+        assert!(!is_held(temp_dir.path()));
+        assert_eq!(holder_pid(temp_dir.path()), Some(pid));
+        assert!(matches!(acquire(temp_dir.path()).unwrap(), Acquired::Locked(_)));
+    }
+}
@@ -0,0 +1,210 @@
+//! Previews of images the daemon intercepts, shown where the user is working
+//! rather than where the daemon runs (`preview.on_intercept`).
+//!
+//! Inside tmux the preview opens in a short-lived popup on the attached
+//! client. Otherwise the shell hooks note their terminal at every prompt in
+//! `~/.klipdot/active-terminal`, and a one-line summary is written there;
+//! image escape sequences aren't, since another program owns that screen.
+//! A daemon running in the foreground of a terminal previews there.
+
+use crate::{
+    command_runner::{CommandRunner, SharedRunner},
+    config::{Config, PreviewMethod},
+    error::Result,
+    events::{EventBus, InterceptEvent},
+    image_preview::ImagePreviewManager,
+    substitution::shell_quote,
+    Error,
+};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, error};
+
+/// Seconds a tmux popup preview stays open
+const POPUP_SECONDS: u32 = 3;
+
+/// Where a preview of an intercepted image is shown
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreviewTarget {
+    /// A popup on the tmux client last used
+    Tmux,
+    /// The terminal device of the shell that last showed a prompt
+    Terminal(PathBuf),
+    /// The terminal running klipdot itself
+    Own,
+}
+
+/// The terminal to preview intercepted images in, if any
+pub async fn find_target(runner: &dyn CommandRunner, state_dir: &Path) -> Option<PreviewTarget> {
+    if runner.is_available("tmux") {
+        let client = runner.run("tmux", &["display-message", "-p", "#{client_tty}"], None).await;
+        if client.is_ok_and(|output| output.success && !output.stdout_lossy().trim().is_empty()) {
+            return Some(PreviewTarget::Tmux);
+        }
+    }
+
+    let noted = tokio::fs::read_to_string(state_dir.join(crate::ACTIVE_TERMINAL_FILE)).await.unwrap_or_default();
+    if let Some(tty) = noted.lines().next().map(str::trim).filter(|tty| tty.starts_with("/dev/")) {
+        if Path::new(tty).exists() {
+            return Some(PreviewTarget::Terminal(PathBuf::from(tty)));
+        }
+    }
+
+    std::io::stdout().is_terminal().then_some(PreviewTarget::Own)
+}
+
+/// Preview intercepted images
+pub struct InterceptPreviewer {
+    config: Config,
+    preview_manager: ImagePreviewManager,
+    runner: SharedRunner,
+    state_dir: PathBuf,
+    klipdot_bin: String,
+}
+
+impl InterceptPreviewer {
+    pub async fn new(config: Config, runner: SharedRunner) -> Result<Self> {
+        let preview_manager = ImagePreviewManager::new(config.clone()).await?;
+        let klipdot_bin = std::env::current_exe()
+            .map(|exe| exe.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| crate::APP_NAME.to_string());
+        Ok(Self {
+            config,
+            preview_manager,
+            runner,
+            state_dir: crate::get_home_dir()?,
+            klipdot_bin,
+        })
+    }
+
+    /// Preview every image published on `events` until the bus closes
+    pub async fn run(&self, events: EventBus) {
+        let mut events = events.subscribe();
+        loop {
+            match events.recv().await {
+                Ok(InterceptEvent::ImageIntercepted { path, .. }) => {
+                    if let Err(e) = self.preview(&path).await {
+                        debug!("Failed to preview {:?}: {}", path, e);
+                    }
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+
+    /// Preview one image in the user's terminal
+    pub async fn preview(&self, image_path: &Path) -> Result<()> {
+        let Some(target) = find_target(self.runner.as_ref(), &self.state_dir).await else {
+            debug!("No terminal to preview {:?} in", image_path);
+            return Ok(());
+        };
+        let compact = self.config.preview.method == PreviewMethod::Compact;
+
+        match target {
+            PreviewTarget::Tmux if compact => {
+                let info = self.preview_manager.show_compact_preview(image_path).await?;
+                self.tmux(&["display-message", &format!("klipdot: {}", info)]).await
+            }
+            PreviewTarget::Tmux => {
+                let (width, height) = self.config.preview.size();
+                let (width, height) = (width.unwrap_or_default(), height.unwrap_or_default());
+                let command = format!(
+                    "{} --quiet preview --width {} --height {} -- {}; sleep {}",
+                    shell_quote(&self.klipdot_bin),
+                    width,
+                    height,
+                    shell_quote(&image_path.to_string_lossy()),
+                    POPUP_SECONDS
+                );
+                // Room for the border and the line after the image
+                let popup_width = (width + 2).to_string();
+                let popup_height = (height + 3).to_string();
+                let title = format!("klipdot: {}", image_path.file_name().unwrap_or_default().to_string_lossy());
+                self.runner
+                    .spawn_detached(
+                        "tmux",
+                        &["display-popup", "-E", "-w", &popup_width, "-h", &popup_height, "-T", &title, &command],
+                    )
+                    .await
+                    .map_err(|e| Error::Process(format!("Failed to open tmux popup: {}", e)))
+            }
+            PreviewTarget::Terminal(tty) => {
+                let info = self.preview_manager.show_compact_preview(image_path).await?;
+                let mut terminal = std::fs::OpenOptions::new().append(true).open(&tty)?;
+                write!(terminal, "\r\n[klipdot] {}\r\n", info)?;
+                Ok(())
+            }
+            PreviewTarget::Own => self.preview_manager.show_automatic_preview(image_path).await,
+        }
+    }
+
+    async fn tmux(&self, args: &[&str]) -> Result<()> {
+        let output = self.runner.run("tmux", args, None).await?;
+        if output.success {
+            Ok(())
+        } else {
+            Err(Error::Process(format!("tmux failed: {}", output.stderr_lossy().trim())))
+        }
+    }
+}
+
+/// Preview images published on `events` as they're intercepted
+pub async fn preview_intercepted(config: Config, runner: SharedRunner, events: EventBus) {
+    match InterceptPreviewer::new(config, runner).await {
+        Ok(previewer) => previewer.run(events).await,
+        Err(e) => error!("Intercepted images won't be previewed: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_find_target() {
+        let temp_dir = TempDir::new().unwrap();
+        let runner = FakeRunner::new().with_output("tmux", CommandOutput::ok("/dev/pts/3\n"));
+        assert_eq!(find_target(&runner, temp_dir.path()).await, Some(PreviewTarget::Tmux));
+
+        // No tmux client: the terminal the hooks noted, if it still exists
+        runner.set_output("tmux", CommandOutput::failed("no server running"));
+        let tty = temp_dir.path().join("dev-pts-4");
+        std::fs::write(temp_dir.path().join(crate::ACTIVE_TERMINAL_FILE), "/dev/null\n").unwrap();
+        assert_eq!(
+            find_target(&runner, temp_dir.path()).await,
+            Some(PreviewTarget::Terminal(PathBuf::from("/dev/null")))
+        );
+        std::fs::write(temp_dir.path().join(crate::ACTIVE_TERMINAL_FILE), tty.to_string_lossy().as_ref()).unwrap();
+        assert_ne!(find_target(&runner, temp_dir.path()).await, Some(PreviewTarget::Terminal(tty)));
+    }
+
+    #[tokio::test]
+    async fn test_tmux_popup() {
+        let temp_dir = TempDir::new().unwrap();
+        let image_path = temp_dir.path().join("shot.png");
+        std::fs::write(&image_path, b"png").unwrap();
+        let runner = Arc::new(FakeRunner::new().with_output("tmux", CommandOutput::ok("/dev/pts/3")));
+        let config = Config::default();
+        let previewer = InterceptPreviewer {
+            preview_manager: ImagePreviewManager::new(config.clone()).await.unwrap(),
+            config,
+            runner: runner.clone(),
+            state_dir: temp_dir.path().to_path_buf(),
+            klipdot_bin: "/usr/bin/klipdot".to_string(),
+        };
+
+        previewer.preview(&image_path).await.unwrap();
+        let calls = runner.calls_to("tmux");
+        let popup = &calls.last().unwrap().args;
+        assert_eq!(popup[..6], ["display-popup", "-E", "-w", "42", "-h", "23"]);
+        assert_eq!(
+            popup.last().unwrap(),
+            &format!("/usr/bin/klipdot --quiet preview --width 40 --height 20 -- {}; sleep 3", image_path.display())
+        );
+    }
+}
@@ -0,0 +1,107 @@
+//! Turning interception sources on and off while KlipDot runs.
+//!
+//! `klipdot enable <source>` and `klipdot disable <source>` save the change
+//! to `intercept_methods` and send it to the running instance over
+//! [`crate::ipc`]. There the switches take over from the configuration the
+//! instance started with: the clipboard monitor, process monitor and
+//! downloads watcher check them as they go instead of only at startup.
+//! Elsewhere, as in short-lived commands, the configuration is used as is.
+
+use crate::config::InterceptMethods;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+
+/// The running instance's switches; `None` until [`start`]
+static LIVE: Lazy<RwLock<Option<InterceptMethods>>> = Lazy::new(|| RwLock::new(None));
+
+/// A source of images that can be switched on and off
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum InterceptSource {
+    /// Clipboard monitoring (`intercept_methods.clipboard`)
+    Clipboard,
+    /// Watching processes for screenshot tools (`intercept_methods.process_monitor`)
+    Process,
+    /// Watching directories, such as downloads (`intercept_methods.file_watch`)
+    Filewatch,
+    /// Images piped to `klipdot sink` (`intercept_methods.stdin`)
+    Stdin,
+}
+
+impl InterceptSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            InterceptSource::Clipboard => "clipboard",
+            InterceptSource::Process => "process",
+            InterceptSource::Filewatch => "filewatch",
+            InterceptSource::Stdin => "stdin",
+        }
+    }
+
+    /// Whether `methods` has this source on
+    pub fn get(self, methods: &InterceptMethods) -> bool {
+        match self {
+            InterceptSource::Clipboard => methods.clipboard,
+            InterceptSource::Process => methods.process_monitor,
+            InterceptSource::Filewatch => methods.file_watch,
+            InterceptSource::Stdin => methods.stdin,
+        }
+    }
+
+    /// Turn this source on or off in `methods`
+    pub fn set(self, methods: &mut InterceptMethods, enabled: bool) {
+        let field = match self {
+            InterceptSource::Clipboard => &mut methods.clipboard,
+            InterceptSource::Process => &mut methods.process_monitor,
+            InterceptSource::Filewatch => &mut methods.file_watch,
+            InterceptSource::Stdin => &mut methods.stdin,
+        };
+        *field = enabled;
+    }
+}
+
+/// Make this process's switches live, starting from `configured`
+pub fn start(configured: &InterceptMethods) {
+    *LIVE.write().unwrap() = Some(configured.clone());
+}
+
+/// Whether `source` is on: the live switch once [`start`]ed, else `configured`
+pub fn enabled(source: InterceptSource, configured: &InterceptMethods) -> bool {
+    match &*LIVE.read().unwrap() {
+        Some(live) => source.get(live),
+        None => source.get(configured),
+    }
+}
+
+/// Flip a live switch; false when this process hasn't [`start`]ed them
+pub fn set(source: InterceptSource, enabled: bool) -> bool {
+    match &mut *LIVE.write().unwrap() {
+        Some(live) => {
+            source.set(live, enabled);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sources_map_to_methods() {
+        let mut methods = InterceptMethods::default();
+        for source in [InterceptSource::Clipboard, InterceptSource::Process, InterceptSource::Filewatch, InterceptSource::Stdin] {
+            assert!(source.get(&methods));
+            source.set(&mut methods, false);
+            assert!(!source.get(&methods));
+        }
+        assert!(!methods.clipboard && !methods.process_monitor && !methods.file_watch && !methods.stdin);
+        // Sources without a switch are untouched
+        assert!(methods.terminal && methods.drag_drop && methods.clipboard_events);
+
+        let encoded = serde_json::to_string(&InterceptSource::Filewatch).unwrap();
+        assert_eq!(encoded, format!("\"{}\"", InterceptSource::Filewatch.as_str()));
+    }
+}
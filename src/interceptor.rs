@@ -1,12 +1,17 @@
-use crate::{config::Config, error::Result, Error};
+use crate::{
+    command_runner::{self, SharedRunner},
+    config::Config, error::Result, error_history, events::{EventBus, InterceptEvent},
+    intercept_switches::{self, InterceptSource}, Error,
+};
 use std::collections::HashMap;
 use std::time::Duration;
-use tokio::process::Command;
 use tokio::time::sleep;
 use tracing::{debug, info, warn};
 
 pub struct TerminalInterceptor {
     config: Config,
+    runner: SharedRunner,
+    events: EventBus,
     running: bool,
     process_monitors: HashMap<String, ProcessMonitor>,
 }
@@ -24,29 +29,59 @@ impl TerminalInterceptor {
     pub async fn new(config: Config) -> Result<Self> {
         Ok(Self {
             config,
+            runner: command_runner::system(),
+            events: EventBus::new(),
             running: false,
             process_monitors: HashMap::new(),
         })
     }
     
+    /// Share an event bus with the clipboard monitor so it can attribute
+    /// clipboard-only captures to the screenshot tool that made them
+    pub fn set_event_bus(&mut self, events: EventBus) {
+        self.events = events;
+    }
+    
+    /// Replace the runner used to list and inspect processes
+    pub fn set_command_runner(&mut self, runner: SharedRunner) {
+        self.runner = runner;
+    }
+    
     pub async fn run(&mut self) -> Result<()> {
-        if !self.config.intercept_methods.process_monitor {
-            info!("Process monitoring disabled in config");
-            return Ok(());
-        }
-        
         info!("Starting terminal interceptor");
         self.running = true;
         
         let mut interval = tokio::time::interval(Duration::from_millis(self.config.poll_interval));
+        let mut consecutive_failures = 0;
+        let mut disabled = false;
         
         while self.running {
             interval.tick().await;
             
-            if let Err(e) = self.monitor_processes().await {
-                if e.is_recoverable() {
-                    warn!("Recoverable process monitoring error: {}", e);
-                } else {
+            // `klipdot disable process` can switch monitoring off and on while running
+            if !intercept_switches::enabled(InterceptSource::Process, &self.config.intercept_methods) {
+                if !disabled {
+                    info!("Process monitoring disabled");
+                    disabled = true;
+                }
+                continue;
+            }
+            if disabled {
+                info!("Process monitoring enabled");
+                disabled = false;
+            }
+            
+            match self.monitor_processes().await {
+                Ok(()) => consecutive_failures = 0,
+                Err(e) if e.is_recoverable() => {
+                    consecutive_failures += 1;
+                    let backoff = self.config.retry.delay_for_attempt(consecutive_failures);
+                    warn!("Recoverable process monitoring error: {} (backing off {:?})", e, backoff);
+                    error_history::record_error("interceptor", &e);
+                    sleep(backoff).await;
+                }
+                Err(e) => {
+                    error_history::record_error("interceptor", &e);
                     return Err(e);
                 }
             }
@@ -63,7 +98,9 @@ impl TerminalInterceptor {
     async fn monitor_processes(&mut self) -> Result<()> {
         debug!("Monitoring processes for image operations");
         
-        let processes = self.get_running_processes().await?;
+        let policy = self.config.retry.clone();
+        let this = &*self;
+        let processes = policy.run("process_list", move || this.get_running_processes()).await?;
         
         for process in processes {
             if self.is_image_process(&process.name) {
@@ -144,7 +181,7 @@ impl TerminalInterceptor {
         info!("Detected Wayland screenshot tool: {} (PID: {})", process.name, process.pid);
         
         // Wait for the process to complete
-        self.wait_for_process_completion(process.pid).await?;
+        self.track_screenshot_tool(process, "wayland-screenshot").await?;
         
         // Look for recently created images in common directories
         let screenshot_dirs = vec![
@@ -154,10 +191,8 @@ impl TerminalInterceptor {
             Some(std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from("/tmp")))
         ];
         
-        for dir_opt in screenshot_dirs {
-            if let Some(dir) = dir_opt {
-                self.scan_directory_for_new_images(&dir, "wayland-screenshot").await?;
-            }
+        for dir in screenshot_dirs.into_iter().flatten() {
+            self.scan_directory_for_new_images(&dir, "wayland-screenshot").await?;
         }
         
         Ok(())
@@ -167,7 +202,7 @@ impl TerminalInterceptor {
         info!("Detected X11 screenshot tool: {} (PID: {})", process.name, process.pid);
         
         // Wait for the process to complete
-        self.wait_for_process_completion(process.pid).await?;
+        self.track_screenshot_tool(process, "x11-screenshot").await?;
         
         // Look for recently created images in common directories
         let screenshot_dirs = vec![
@@ -177,10 +212,8 @@ impl TerminalInterceptor {
             Some(std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from("/tmp")))
         ];
         
-        for dir_opt in screenshot_dirs {
-            if let Some(dir) = dir_opt {
-                self.scan_directory_for_new_images(&dir, "x11-screenshot").await?;
-            }
+        for dir in screenshot_dirs.into_iter().flatten() {
+            self.scan_directory_for_new_images(&dir, "x11-screenshot").await?;
         }
         
         Ok(())
@@ -190,10 +223,7 @@ impl TerminalInterceptor {
         info!("Detected macOS screenshot tool: {} (PID: {})", process.name, process.pid);
         
         // Wait for the process to complete
-        self.wait_for_process_completion(process.pid).await?;
-        
-        // Check clipboard for screenshot data (screencapture -c puts it in clipboard)
-        self.check_clipboard_after_screenshot().await?;
+        self.track_screenshot_tool(process, "macos-screenshot").await?;
         
         // Look for recently created images in macOS screenshot directories
         let screenshot_dirs = vec![
@@ -203,10 +233,8 @@ impl TerminalInterceptor {
             Some(std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from("/tmp")))
         ];
         
-        for dir_opt in screenshot_dirs {
-            if let Some(dir) = dir_opt {
-                self.scan_directory_for_new_images(&dir, "macos-screenshot").await?;
-            }
+        for dir in screenshot_dirs.into_iter().flatten() {
+            self.scan_directory_for_new_images(&dir, "macos-screenshot").await?;
         }
         
         Ok(())
@@ -225,6 +253,24 @@ impl TerminalInterceptor {
         Ok(processes)
     }
     
+    /// Wait for a screenshot tool to exit, announcing it on the event bus so a
+    /// capture copied only to the clipboard (e.g. `grimshot copy`) is named
+    /// after `source` rather than treated as a plain clipboard image
+    async fn track_screenshot_tool(&self, process: &Process, source: &str) -> Result<()> {
+        self.events.publish(InterceptEvent::ScreenshotToolStarted {
+            tool: process.name.clone(),
+            source: source.to_string(),
+        });
+        
+        let result = self.wait_for_process_completion(process.pid).await;
+        
+        self.events.publish(InterceptEvent::ScreenshotToolFinished {
+            tool: process.name.clone(),
+            source: source.to_string(),
+        });
+        result
+    }
+    
     async fn wait_for_process_completion(&self, pid: u32) -> Result<()> {
         let max_wait = Duration::from_secs(30); // Maximum wait time
         let check_interval = Duration::from_millis(100);
@@ -269,15 +315,12 @@ impl TerminalInterceptor {
         
         #[cfg(windows)]
         {
-            let output = Command::new("tasklist")
-                .arg("/FI")
-                .arg(&format!("PID eq {}", pid))
-                .output()
+            let filter = format!("PID eq {}", pid);
+            let output = self.runner.run("tasklist", &["/FI", &filter], None)
                 .await
                 .map_err(|e| Error::Process(format!("Failed to check process: {}", e)))?;
             
-            let output_str = String::from_utf8_lossy(&output.stdout);
-            Ok(output_str.contains(&pid.to_string()))
+            Ok(output.stdout_lossy().contains(&pid.to_string()))
         }
     }
     
@@ -287,22 +330,20 @@ impl TerminalInterceptor {
         }
         
         let mut entries = tokio::fs::read_dir(dir).await
-            .map_err(|e| Error::Io(std::io::Error::new(std::io::ErrorKind::Other, e)))?;
+            .map_err(|e| Error::Io(std::io::Error::other(e)))?;
         
         let recent_threshold = std::time::SystemTime::now() - Duration::from_secs(30);
         
         while let Some(entry) = entries.next_entry().await
-            .map_err(|e| Error::Io(std::io::Error::new(std::io::ErrorKind::Other, e)))? {
+            .map_err(|e| Error::Io(std::io::Error::other(e)))? {
             
             let path = entry.path();
             
-            if crate::is_image_file(&path) {
-                if let Ok(metadata) = entry.metadata().await {
-                    if let Ok(modified) = metadata.modified() {
-                        if modified > recent_threshold {
-                            info!("Found new image: {:?}", path);
-                            self.process_image_file(&path, source).await?;
-                        }
+            if let Ok(metadata) = entry.metadata().await {
+                if let Ok(modified) = metadata.modified() {
+                    if modified > recent_threshold && crate::is_image_file(&path) {
+                        info!("Found new image: {:?}", path);
+                        self.process_image_file(&path, source).await?;
                     }
                 }
             }
@@ -326,15 +367,12 @@ impl TerminalInterceptor {
         
         #[cfg(unix)]
         {
-            let output = Command::new("ps")
-                .arg("-eo")
-                .arg("pid,comm,args")
-                .output()
+            let output = self.runner.run("ps", &["-eo", "pid,comm,args"], None)
                 .await
                 .map_err(|e| Error::Process(format!("Failed to run ps: {}", e)))?;
             
-            if output.status.success() {
-                let output_str = String::from_utf8_lossy(&output.stdout);
+            if output.success {
+                let output_str = output.stdout_lossy();
                 for line in output_str.lines().skip(1) {
                     if let Some(process) = self.parse_ps_line(line) {
                         processes.push(process);
@@ -345,17 +383,12 @@ impl TerminalInterceptor {
         
         #[cfg(windows)]
         {
-            let output = Command::new("wmic")
-                .arg("process")
-                .arg("get")
-                .arg("ProcessId,Name,CommandLine")
-                .arg("/format:csv")
-                .output()
+            let output = self.runner.run("wmic", &["process", "get", "ProcessId,Name,CommandLine", "/format:csv"], None)
                 .await
                 .map_err(|e| Error::Process(format!("Failed to run wmic: {}", e)))?;
             
-            if output.status.success() {
-                let output_str = String::from_utf8_lossy(&output.stdout);
+            if output.success {
+                let output_str = output.stdout_lossy();
                 for line in output_str.lines().skip(1) {
                     if let Some(process) = self.parse_wmic_line(line) {
                         processes.push(process);
@@ -369,7 +402,7 @@ impl TerminalInterceptor {
     
     #[cfg(unix)]
     fn parse_ps_line(&self, line: &str) -> Option<Process> {
-        let parts: Vec<&str> = line.trim().split_whitespace().collect();
+        let parts: Vec<&str> = line.split_whitespace().collect();
         if parts.len() >= 3 {
             if let Ok(pid) = parts[0].parse::<u32>() {
                 let name = parts[1].to_string();
@@ -500,10 +533,7 @@ impl TerminalInterceptor {
         info!("Wayland screenshot process detected: {} (PID: {})", process.name, process.pid);
         
         // Wait for the process to complete
-        self.wait_for_process_completion(process.pid).await?;
-        
-        // Check for clipboard changes (many Wayland tools copy to clipboard)
-        self.check_clipboard_after_screenshot().await?;
+        self.track_screenshot_tool(process, "wayland-screenshot").await?;
         
         // Look for recently created image files
         self.scan_for_new_images().await?;
@@ -515,7 +545,7 @@ impl TerminalInterceptor {
         info!("Traditional screenshot process detected: {} (PID: {})", process.name, process.pid);
         
         // Wait for the process to complete
-        self.wait_for_process_completion(process.pid).await?;
+        self.track_screenshot_tool(process, screenshot_source()).await?;
         
         // Look for recently created image files
         self.scan_for_new_images().await?;
@@ -523,17 +553,6 @@ impl TerminalInterceptor {
         Ok(())
     }
     
-    async fn check_clipboard_after_screenshot(&self) -> Result<()> {
-        // Give the screenshot tool time to update clipboard
-        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
-        
-        // Check if there's new image data in clipboard
-        // This would integrate with the clipboard monitor
-        debug!("Checking clipboard for screenshot data");
-        
-        Ok(())
-    }
-    
     async fn scan_for_new_images(&self) -> Result<()> {
         let mut scan_dirs = vec![
             dirs::desktop_dir(),
@@ -553,11 +572,9 @@ impl TerminalInterceptor {
             _ => {}
         }
         
-        for dir_option in &scan_dirs {
-            if let Some(dir) = dir_option {
-                if dir.exists() {
-                    self.scan_directory_for_images(dir).await?;
-                }
+        for dir in scan_dirs.iter().flatten() {
+            if dir.exists() {
+                self.scan_directory_for_images(dir).await?;
             }
         }
         
@@ -625,14 +642,12 @@ impl TerminalInterceptor {
         while let Some(entry) = entries.next_entry().await? {
             let path = entry.path();
             
-            if path.is_file() && crate::is_image_file(&path) {
-                if let Ok(metadata) = entry.metadata().await {
-                    if let Ok(created) = metadata.created() {
-                        // Check if file was created in the last 30 seconds
-                        if let Ok(elapsed) = now.duration_since(created) {
-                            if elapsed.as_secs() < 30 {
-                                self.process_new_image(&path).await?;
-                            }
+            if let Ok(metadata) = entry.metadata().await {
+                if let Ok(created) = metadata.created() {
+                    // Check if file was created in the last 30 seconds
+                    if let Ok(elapsed) = now.duration_since(created) {
+                        if elapsed.as_secs() < 30 && crate::is_image_file(&path) {
+                            self.process_new_image(&path).await?;
                         }
                     }
                 }
@@ -647,7 +662,10 @@ impl TerminalInterceptor {
         
         // Use the image processor to handle the file
         let image_processor = crate::image_processor::ImageProcessor::new(self.config.clone()).await?;
-        let processed_path = image_processor.process_image_file(&path.to_path_buf(), "screenshot").await?;
+        let window = crate::window_crop::geometry_for(&self.config, self.runner.as_ref(), "screenshot").await;
+        let processed_path = image_processor
+            .process_image_file_from(&path.to_path_buf(), "screenshot", None, None, window.as_ref())
+            .await?;
         
         // Replace the original file reference with the processed path
         // This would typically involve shell integration
@@ -684,6 +702,16 @@ struct Process {
     command: String,
 }
 
+/// Source name for captures from a screenshot tool on the current display server
+fn screenshot_source() -> &'static str {
+    match crate::detect_display_server() {
+        crate::DisplayServer::Wayland => "wayland-screenshot",
+        crate::DisplayServer::X11 => "x11-screenshot",
+        crate::DisplayServer::MacOS => "macos-screenshot",
+        crate::DisplayServer::Unknown => "screenshot",
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -692,8 +720,10 @@ mod tests {
     #[tokio::test]
     async fn test_terminal_interceptor_creation() {
         let temp_dir = TempDir::new().unwrap();
-        let mut config = Config::default();
-        config.screenshot_dir = temp_dir.path().to_path_buf();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
         
         let interceptor = TerminalInterceptor::new(config).await;
         assert!(interceptor.is_ok());
@@ -704,6 +734,8 @@ mod tests {
         let config = Config::default();
         let interceptor = TerminalInterceptor {
             config,
+            runner: command_runner::system(),
+            events: EventBus::new(),
             running: false,
             process_monitors: HashMap::new(),
         };
@@ -721,6 +753,8 @@ mod tests {
         let config = Config::default();
         let interceptor = TerminalInterceptor {
             config,
+            runner: command_runner::system(),
+            events: EventBus::new(),
             running: false,
             process_monitors: HashMap::new(),
         };
@@ -735,8 +769,10 @@ mod tests {
     #[tokio::test]
     async fn test_cleanup_old_monitors() {
         let temp_dir = TempDir::new().unwrap();
-        let mut config = Config::default();
-        config.screenshot_dir = temp_dir.path().to_path_buf();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
         
         let mut interceptor = TerminalInterceptor::new(config).await.unwrap();
         
@@ -769,4 +805,68 @@ mod tests {
         assert!(interceptor.process_monitors.contains_key("recent_process"));
         assert!(!interceptor.process_monitors.contains_key("old_process"));
     }
-}
\ No newline at end of file
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_process_listing_through_runner() {
+        use crate::command_runner::{CommandOutput, FakeRunner};
+        use std::sync::Arc;
+        
+        let ps_output = "  PID COMMAND         COMMAND\n\
+                         101 bash            -bash\n\
+                         202 grim            grim -g 0,0 1920x1080 /tmp/shot.png\n\
+                         303 flameshot       flameshot gui\n";
+        let runner = Arc::new(FakeRunner::new().with_output("ps", CommandOutput::ok(ps_output)));
+        
+        let mut interceptor = TerminalInterceptor::new(Config::default()).await.unwrap();
+        interceptor.set_command_runner(runner.clone());
+        
+        let processes = interceptor.get_running_processes().await.unwrap();
+        assert_eq!(processes.len(), 3);
+        assert_eq!(processes[1].pid, 202);
+        assert_eq!(processes[1].command, "grim -g 0,0 1920x1080 /tmp/shot.png");
+        
+        let grim = interceptor.get_processes_by_name("grim").await.unwrap();
+        assert_eq!(grim.len(), 1);
+        assert_eq!(runner.calls_to("ps")[0].args, ["-eo", "pid,comm,args"]);
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_process_listing_without_ps() {
+        use crate::command_runner::FakeRunner;
+        use std::sync::Arc;
+        
+        let mut interceptor = TerminalInterceptor::new(Config::default()).await.unwrap();
+        interceptor.set_command_runner(Arc::new(FakeRunner::new()));
+        
+        let err = interceptor.get_running_processes().await.unwrap_err();
+        assert_eq!(err.error_code(), "PROCESS");
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_screenshot_tool_is_announced() {
+        let mut interceptor = TerminalInterceptor::new(Config::default()).await.unwrap();
+        let events = EventBus::new();
+        let mut receiver = events.subscribe();
+        interceptor.set_event_bus(events);
+        
+        // A PID that cannot exist, so the tool counts as already finished
+        let process = Process {
+            pid: i32::MAX as u32,
+            name: "grimshot".to_string(),
+            command: "grimshot copy area".to_string(),
+        };
+        interceptor.track_screenshot_tool(&process, "wayland-screenshot").await.unwrap();
+        
+        assert_eq!(receiver.recv().await.unwrap(), InterceptEvent::ScreenshotToolStarted {
+            tool: "grimshot".to_string(),
+            source: "wayland-screenshot".to_string(),
+        });
+        assert_eq!(receiver.recv().await.unwrap(), InterceptEvent::ScreenshotToolFinished {
+            tool: "grimshot".to_string(),
+            source: "wayland-screenshot".to_string(),
+        });
+    }
+}
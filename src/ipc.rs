@@ -0,0 +1,334 @@
+//! Local IPC between the daemon and short-lived clients (hooks, editor
+//! plugins, fzf helpers).
+//!
+//! Clients connect to a Unix socket in the run-state directory and
+//! exchange newline-delimited JSON: one [`Request`] line, one [`Response`]
+//! line. Preview rendering goes through the daemon's [`CachedRenderer`] so
+//! repeated previews of the same image return pre-rendered escape sequences.
+//!
+//! [`CachedRenderer`]: crate::image_preview::CachedRenderer
+
+use crate::{error::Result, error_history::ErrorRecord, intercept_switches::InterceptSource, processing_queue::QueueMetrics, retry::RetryCounters, Error};
+use base64::engine::general_purpose;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Requests a client can send to the daemon
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    Ping,
+    /// Render `path` with the named preview backend within the given geometry
+    RenderPreview {
+        path: PathBuf,
+        backend: String,
+        width: Option<u32>,
+        height: Option<u32>,
+        /// Pixels per cell of the client's terminal, for backends that size in pixels
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cell_size: Option<(u32, u32)>,
+    },
+    /// Counters describing the daemon's processing queue and retries
+    Stats,
+    /// Store the images modified in `dirs` since their last scan
+    ScanNew { dirs: Vec<PathBuf> },
+    /// Turn an interception source on or off, see [`crate::intercept_switches`]
+    SetSource { source: InterceptSource, enabled: bool },
+    /// The errors the daemon has recorded, see [`crate::error_history`]
+    Errors,
+}
+
+/// Daemon replies, one per request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    Pong,
+    /// Rendered terminal output, base64 encoded
+    Preview { data: String, cached: bool },
+    Stats {
+        processing: QueueMetrics,
+        /// Where images are saved while the screenshot directory is unwritable
+        #[serde(default)]
+        storage_fallback: Option<PathBuf>,
+        /// Retry counters of each operation run through a retry policy, by name
+        #[serde(default)]
+        retries: BTreeMap<String, RetryCounters>,
+    },
+    /// Images stored by a `ScanNew` request
+    Scanned { stored: Vec<PathBuf> },
+    /// The switch a `SetSource` request flipped
+    SourceSet { source: InterceptSource, enabled: bool },
+    /// Recent errors, oldest first
+    Errors { records: Vec<ErrorRecord> },
+    Error { code: String, message: String },
+}
+
+impl Response {
+    pub fn preview(output: &[u8], cached: bool) -> Self {
+        Response::Preview {
+            data: general_purpose::STANDARD.encode(output),
+            cached,
+        }
+    }
+
+    pub fn error(err: &Error) -> Self {
+        Response::Error {
+            code: err.error_code().to_string(),
+            message: err.to_string(),
+        }
+    }
+
+    /// Decoded output of a `Preview` response
+    pub fn preview_output(&self) -> Result<Vec<u8>> {
+        match self {
+            Response::Preview { data, .. } => general_purpose::STANDARD
+                .decode(data)
+                .map_err(|e| Error::Format(format!("Invalid preview data: {}", e))),
+            Response::Error { message, .. } => Err(Error::Service(message.clone())),
+            other => Err(Error::Service(format!("Unexpected response: {:?}", other))),
+        }
+    }
+}
+
+/// Location of the daemon's socket
+pub fn default_socket_path() -> Result<PathBuf> {
+    crate::run_state::socket_path()
+}
+
+#[cfg(unix)]
+pub use unix::{request, IpcServer};
+
+#[cfg(unix)]
+mod unix {
+    use super::{Request, Response};
+    use crate::{config::Config, error::Result, Error};
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{UnixListener, UnixStream};
+    use tracing::{debug, info};
+
+    #[cfg(feature = "preview")]
+    use crate::image_preview::CachedRenderer;
+    #[cfg(feature = "preview")]
+    use tracing::warn;
+
+    /// How long a client waits for the daemon before falling back to doing the work itself
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Serves IPC requests on behalf of the daemon
+    pub struct IpcServer {
+        socket_path: PathBuf,
+        config: Option<Config>,
+        #[cfg(feature = "preview")]
+        renderer: Arc<CachedRenderer>,
+    }
+
+    #[cfg(feature = "preview")]
+    fn renderer(limits: crate::config::DecodeLimits) -> Arc<CachedRenderer> {
+        Arc::new(
+            CachedRenderer::new(
+                crate::image_preview::PreviewRegistry::with_builtin(),
+                crate::command_runner::system(),
+                crate::PREVIEW_CACHE_SIZE,
+            )
+            .with_decode_limits(limits),
+        )
+    }
+
+    impl IpcServer {
+        pub fn new(socket_path: PathBuf) -> Self {
+            Self {
+                socket_path,
+                config: None,
+                #[cfg(feature = "preview")]
+                renderer: renderer(Default::default()),
+            }
+        }
+
+        /// Let clients hand work that stores images, such as scans, to the daemon
+        pub fn with_config(mut self, config: Config) -> Self {
+            #[cfg(feature = "preview")]
+            {
+                self.renderer = renderer(config.decode_limits.clone());
+            }
+            self.config = Some(config);
+            self
+        }
+
+        /// Accept connections until the listener fails
+        pub async fn run(self) -> Result<()> {
+            // A socket left behind by a previous run would make bind fail
+            if self.socket_path.exists() {
+                std::fs::remove_file(&self.socket_path)?;
+            }
+
+            let listener = UnixListener::bind(&self.socket_path)
+                .map_err(|e| Error::Service(format!("Failed to bind {:?}: {}", self.socket_path, e)))?;
+            std::fs::set_permissions(&self.socket_path, std::fs::Permissions::from_mode(0o600))?;
+            info!("IPC server listening on {:?}", self.socket_path);
+
+            let server = Arc::new(self);
+            loop {
+                let (stream, _) = listener.accept().await?;
+                let server = server.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = server.serve_connection(stream).await {
+                        debug!("IPC connection closed with error: {}", e);
+                    }
+                });
+            }
+        }
+
+        async fn serve_connection(&self, stream: UnixStream) -> Result<()> {
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+
+            while let Some(line) = lines.next_line().await? {
+                let response = match serde_json::from_str::<Request>(&line) {
+                    Ok(request) => self.handle(request).await,
+                    Err(e) => Response::error(&Error::InvalidInput(format!("Malformed request: {}", e))),
+                };
+
+                let mut encoded = serde_json::to_vec(&response)?;
+                encoded.push(b'\n');
+                writer.write_all(&encoded).await?;
+            }
+
+            Ok(())
+        }
+
+        pub async fn handle(&self, request: Request) -> Response {
+            match request {
+                Request::Ping => Response::Pong,
+                Request::Stats => Response::Stats {
+                    processing: crate::processing_queue::metrics(),
+                    storage_fallback: crate::image_processor::storage_fallback(),
+                    retries: crate::retry::metrics().into_iter().collect(),
+                },
+                #[cfg(feature = "preview")]
+                Request::RenderPreview { path, backend, width, height, cell_size } => {
+                    let cell_size = cell_size.map(|(width, height)| crate::image_preview::CellSize { width, height });
+                    match self.renderer.render(&backend, &path, width, height, cell_size).await {
+                        Ok((output, cached)) => Response::preview(&output, cached),
+                        Err(e) => {
+                            warn!("Preview request for {:?} failed: {}", path, e);
+                            Response::error(&e)
+                        }
+                    }
+                }
+                #[cfg(not(feature = "preview"))]
+                Request::RenderPreview { .. } => Response::error(&Error::Unsupported(
+                    "Daemon was built without preview support".to_string(),
+                )),
+                Request::ScanNew { dirs } => {
+                    let Some(config) = &self.config else {
+                        return Response::error(&Error::Unsupported("Daemon doesn't store images".to_string()));
+                    };
+                    let scanned = match crate::get_home_dir() {
+                        Ok(state_dir) => crate::hook_scan::scan_new(config, &state_dir, &dirs).await,
+                        Err(e) => Err(e),
+                    };
+                    match scanned {
+                        Ok(stored) => Response::Scanned { stored },
+                        Err(e) => Response::error(&e),
+                    }
+                }
+                Request::SetSource { source, enabled } => {
+                    if !crate::intercept_switches::set(source, enabled) {
+                        return Response::error(&Error::Unsupported("Daemon has no interception switches".to_string()));
+                    }
+                    info!("{} interception {} by request", source.as_str(), if enabled { "enabled" } else { "disabled" });
+                    Response::SourceSet { source, enabled }
+                }
+                Request::Errors => Response::Errors { records: crate::error_history::recent_errors() },
+            }
+        }
+    }
+
+    /// Send one request to the daemon listening on `socket_path`
+    pub async fn request(socket_path: &Path, request: &Request) -> Result<Response> {
+        let exchange = async {
+            let stream = UnixStream::connect(socket_path).await?;
+            let (reader, mut writer) = stream.into_split();
+
+            let mut encoded = serde_json::to_vec(request)?;
+            encoded.push(b'\n');
+            writer.write_all(&encoded).await?;
+
+            let line = BufReader::new(reader)
+                .lines()
+                .next_line()
+                .await?
+                .ok_or_else(|| Error::Service("Daemon closed the connection".to_string()))?;
+            Ok(serde_json::from_str(&line)?)
+        };
+
+        tokio::time::timeout(REQUEST_TIMEOUT, exchange)
+            .await
+            .map_err(|_| Error::Timeout("No response from daemon".to_string()))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol_encoding() {
+        let request = Request::RenderPreview {
+            path: PathBuf::from("/tmp/shot.png"),
+            backend: "kitty".to_string(),
+            width: Some(40),
+            height: None,
+            cell_size: Some((10, 20)),
+        };
+        let encoded = serde_json::to_string(&request).unwrap();
+        assert!(encoded.contains(r#""type":"render_preview""#));
+        assert_eq!(serde_json::from_str::<Request>(&encoded).unwrap(), request);
+
+        let response = Response::preview(b"\x1b_Gimage\x1b\\", true);
+        assert_eq!(response.preview_output().unwrap(), b"\x1b_Gimage\x1b\\");
+        assert!(Response::error(&Error::NotFound("x".to_string())).preview_output().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_server_round_trip() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let socket_path = temp_dir.path().join("klipdot.sock");
+
+        tokio::spawn(IpcServer::new(socket_path.clone()).run());
+        for _ in 0..50 {
+            if socket_path.exists() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(request(&socket_path, &Request::Ping).await.unwrap(), Response::Pong);
+        assert!(matches!(request(&socket_path, &Request::Stats).await.unwrap(), Response::Stats { .. }));
+        crate::error_history::record_error("ipc-test", &Error::Timeout("slow".to_string()));
+        let Response::Errors { records } = request(&socket_path, &Request::Errors).await.unwrap() else {
+            panic!("expected errors");
+        };
+        assert!(records.iter().any(|record| record.subsystem == "ipc-test" && record.code == "TIMEOUT"));
+
+        let missing = Request::RenderPreview {
+            path: temp_dir.path().join("missing.png"),
+            backend: "iterm2".to_string(),
+            width: None,
+            height: None,
+            cell_size: None,
+        };
+        assert!(matches!(request(&socket_path, &missing).await.unwrap(), Response::Error { .. }));
+
+        // Switches are only live in an instance that started them
+        let switch = Request::SetSource { source: InterceptSource::Clipboard, enabled: false };
+        assert!(matches!(request(&socket_path, &switch).await.unwrap(), Response::Error { .. }));
+    }
+}
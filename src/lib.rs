@@ -1,13 +1,103 @@
+pub mod alt_text;
+pub mod annotate;
+pub mod ansi_export;
+pub mod archive;
+pub mod attach;
+pub mod audit;
+pub mod chat_upload;
 pub mod clipboard;
+pub mod clipboard_handlers;
+pub mod clipboard_history;
+pub mod clipboard_payload;
+pub mod clipboard_types;
+pub mod clipboard_watch;
+pub mod cold_storage;
+pub mod command_runner;
+pub mod completion;
 pub mod config;
+pub mod decode_guard;
+pub mod dedup;
+#[cfg(feature = "file-watch")]
+pub mod downloads;
+pub mod downscale;
 pub mod error;
+pub mod error_history;
+pub mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod focus;
+pub mod hook_scan;
 pub mod interceptor;
+pub mod ipc;
+pub mod metadata;
+pub mod mirror;
+pub mod output;
+pub mod pairing;
+pub mod monitors;
+pub mod paste_image;
+#[cfg(all(target_os = "macos", feature = "native-clipboard"))]
+pub mod pasteboard;
+pub mod path_format;
+pub mod pause;
+pub mod poll_schedule;
+pub mod policy;
+pub mod processing_queue;
+pub mod remote;
+pub mod rename;
+pub mod report;
+pub mod reprocess;
+pub mod retry;
+pub mod run_state;
+pub mod screenshot;
+pub mod search;
+pub mod secrets;
+pub mod sensitive;
 pub mod service;
+pub mod share;
+pub mod store_permissions;
+pub mod store_stats;
 pub mod installer;
 pub mod image_processor;
+pub mod image_diff;
+pub mod image_stats;
+pub mod inject;
+pub mod intercept_switches;
+pub mod instance_lock;
+#[cfg(feature = "lua-hooks")]
+pub mod lua_hooks;
+pub mod man;
+#[cfg(feature = "preview")]
+pub mod color_picker;
+#[cfg(feature = "preview")]
 pub mod image_preview;
+#[cfg(feature = "preview")]
+pub mod intercept_preview;
+#[cfg(feature = "preview")]
+pub mod live_preview;
+#[cfg(feature = "preview")]
+pub mod pixel_view;
+#[cfg(feature = "preview")]
 pub mod stdout_monitor;
 pub mod shell_hooks;
+pub mod sniff;
+pub mod sink;
+pub mod substitution;
+pub mod svg_sanitize;
+pub mod termux;
+pub mod tone_map;
+pub mod temp_files;
+pub mod tool_cache;
+pub mod transforms;
+pub mod undo;
+pub mod upload;
+pub mod url_download;
+#[cfg(feature = "wasm-filters")]
+pub mod wasm_filter;
+#[cfg(all(feature = "preview", feature = "file-watch"))]
+pub mod watch_diff;
+pub mod window_crop;
+pub mod window_target;
+pub mod windows_clipboard;
 
 pub use error::{Error, Result};
 
@@ -23,16 +113,25 @@ pub const SCREENSHOT_DIR: &str = "screenshots";
 /// Configuration file name
 pub const CONFIG_FILE: &str = "config.json";
 
-/// Service PID file name
+/// Directory for run state when there's no `$XDG_RUNTIME_DIR`, see [`run_state`]
+pub const RUN_DIR: &str = "run";
+
+/// Service PID file name, in the run-state directory
 pub const PID_FILE: &str = "klipdot.pid";
 
+/// Lock held by the running instance in the run-state directory, see [`instance_lock`]
+pub const LOCK_FILE: &str = "klipdot.lock";
+
 /// Service log file name
 pub const LOG_FILE: &str = "klipdot.log";
 
+/// Directory images are shared from for a limited time, see [`share`]
+pub const SHARE_DIR: &str = "share";
+
 /// Shell hooks directory name
 pub const HOOKS_DIR: &str = "hooks";
 
-/// Temporary files directory name
+/// Private directory for temporary files in the run-state directory, see [`temp_files`]
 pub const TEMP_DIR: &str = "temp";
 
 /// Default polling interval in milliseconds
@@ -41,8 +140,8 @@ pub const DEFAULT_POLL_INTERVAL: u64 = 1000;
 /// Default cleanup age in days
 pub const DEFAULT_CLEANUP_DAYS: u32 = 30;
 
-/// Maximum file size for image processing (10MB)
-pub const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+/// Size above which images are downscaled while decoding (50MB)
+pub const MAX_FILE_SIZE: u64 = 50 * 1024 * 1024;
 
 /// Supported image formats
 pub const SUPPORTED_FORMATS: &[&str] = &["png", "jpg", "jpeg", "gif", "bmp", "webp", "svg"];
@@ -50,12 +149,59 @@ pub const SUPPORTED_FORMATS: &[&str] = &["png", "jpg", "jpeg", "gif", "bmp", "we
 /// Image quality for compression
 pub const IMAGE_QUALITY: u8 = 90;
 
+/// Default longest side, in pixels, that stored images are scaled down to (4K)
+pub const MAX_IMAGE_DIMENSION: u32 = 3840;
+
 /// Maximum number of recent screenshots to display
 pub const MAX_RECENT_SCREENSHOTS: usize = 10;
 
 /// Service status check interval in milliseconds
 pub const SERVICE_CHECK_INTERVAL: u64 = 5000;
 
+/// Error history file name
+pub const ERROR_HISTORY_FILE: &str = "errors.json";
+
+/// Number of recent errors kept in the error history
+pub const ERROR_HISTORY_SIZE: usize = 50;
+
+/// Hash of the image `klipdot paste-image` last put on the clipboard
+pub const HANDED_BACK_FILE: &str = "handed-back";
+
+/// Daemon IPC socket file name, in the run-state directory
+pub const IPC_SOCKET: &str = "klipdot.sock";
+
+/// Directory holding the named pipes of `klipdot sink`
+pub const SINKS_DIR: &str = "sinks";
+
+/// Directory of WebAssembly filter modules, in the application home directory
+pub const FILTERS_DIR: &str = "filters";
+
+/// Directory of Lua event handler scripts, in the application home directory
+pub const SCRIPTS_DIR: &str = "scripts";
+
+/// Hash-chained audit log of interceptions, in the application home directory
+pub const AUDIT_LOG_FILE: &str = "audit.jsonl";
+
+/// Clipboard history, in the application home directory
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+/// Image the latest clipboard rewrite replaced, kept for `klipdot undo`, in
+/// the application home directory
+pub const UNDO_DIR: &str = "undo";
+
+/// Terminal and working directory of the shell that last showed a prompt,
+/// noted by the shell hooks
+pub const ACTIVE_TERMINAL_FILE: &str = "active-terminal";
+
+/// When each directory the shell hooks watch was last scanned
+pub const SCAN_STATE_FILE: &str = "scan-state.json";
+
+/// Number of rendered previews the daemon keeps for IPC clients
+pub const PREVIEW_CACHE_SIZE: usize = 32;
+
+/// Image metadata index kept in each screenshot directory
+pub const METADATA_INDEX: &str = ".index.jsonl";
+
 /// Shell hook patterns to detect image operations
 pub const IMAGE_COMMAND_PATTERNS: &[&str] = &[
     r"cp.*\.(png|jpg|jpeg|gif|bmp|webp|svg)",
@@ -179,8 +325,13 @@ pub fn get_home_dir() -> Result<std::path::PathBuf> {
     Ok(home_dir)
 }
 
-/// Check if a file is an image based on extension
+/// Check if a file is an image based on its content, whatever it's named
 pub fn is_image_file(path: &std::path::Path) -> bool {
+    sniff::file_image_extension(path).is_some()
+}
+
+/// Check if a name has an image extension, for names with no file to read
+pub fn has_image_extension(path: &std::path::Path) -> bool {
     if let Some(ext) = path.extension() {
         if let Some(ext_str) = ext.to_str() {
             return SUPPORTED_FORMATS.contains(&ext_str.to_lowercase().as_str());
@@ -189,11 +340,40 @@ pub fn is_image_file(path: &std::path::Path) -> bool {
     false
 }
 
-/// Generate a unique filename for a screenshot
-pub fn generate_screenshot_filename(source: &str) -> String {
-    let timestamp = chrono::Utc::now().format("%Y-%m-%dT%H-%M-%S%.3fZ");
+/// Whether `host` is `domain` or one of its subdomains
+pub(crate) fn matches_domain(host: &str, domain: &str) -> bool {
+    let domain = domain.trim_start_matches("*.").trim_end_matches('.').to_ascii_lowercase();
+    host == domain || host.strip_suffix(&domain).is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+/// Hold an exclusive lock on `file` until it's closed, holding off other
+/// processes writing the same store
+#[cfg(unix)]
+pub(crate) fn lock_file(file: &std::fs::File) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: the descriptor stays open for as long as `file` lives
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn lock_file(_file: &std::fs::File) -> Result<()> {
+    Ok(())
+}
+
+/// Generate a unique filename for a screenshot, timestamped in UTC or in
+/// local time with its offset (`2024-01-01T09-30-00.000+0100`)
+pub fn generate_screenshot_filename(source: &str, local_time: bool, extension: &str) -> String {
+    let timestamp = if local_time {
+        chrono::Local::now().format("%Y-%m-%dT%H-%M-%S%.3f%z").to_string()
+    } else {
+        chrono::Utc::now().format("%Y-%m-%dT%H-%M-%S%.3fZ").to_string()
+    };
     let id = uuid::Uuid::new_v4().to_string()[..8].to_string();
-    format!("{}-{}-{}.png", source, timestamp, id)
+    format!("{}-{}-{}.{}", source, timestamp, id, extension)
 }
 
 /// Format file size for display
@@ -210,6 +390,33 @@ pub fn format_file_size(size: u64) -> String {
     format!("{:.1} {}", size, UNITS[unit_index])
 }
 
+/// Format how long ago `time` was relative to `now` ("3 minutes ago")
+pub fn format_relative_time(time: chrono::DateTime<chrono::Utc>, now: chrono::DateTime<chrono::Utc>) -> String {
+    let seconds = (now - time).num_seconds();
+    let plural = |count: i64, unit: &str| {
+        format!("{} {}{} ago", count, unit, if count == 1 { "" } else { "s" })
+    };
+    
+    match seconds {
+        // Clock skew between machines can put a file slightly in the future
+        i64::MIN..=59 => "just now".to_string(),
+        60..=3599 => plural(seconds / 60, "minute"),
+        3600..=86_399 => plural(seconds / 3600, "hour"),
+        86_400..=172_799 => "yesterday".to_string(),
+        172_800..=2_591_999 => plural(seconds / 86_400, "day"),
+        _ => time.with_timezone(&chrono::Local).format("%Y-%m-%d").to_string(),
+    }
+}
+
+/// Format a timestamp in local time, followed by how long ago it was
+pub fn format_local_time(time: chrono::DateTime<chrono::Utc>) -> String {
+    format!(
+        "{} ({})",
+        time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S"),
+        format_relative_time(time, chrono::Utc::now())
+    )
+}
+
 /// Format duration for display
 pub fn format_duration(duration: std::time::Duration) -> String {
     let total_seconds = duration.as_secs();
@@ -296,9 +503,9 @@ pub fn detect_wayland_compositor() -> Option<String> {
     None
 }
 
-/// Check if a command is available in PATH
+/// Check if a command is available in PATH; results are cached, see [`tool_cache`]
 pub fn is_command_available(command: &str) -> bool {
-    which::which(command).is_ok()
+    tool_cache::is_available(command)
 }
 
 /// Get available clipboard tools for the current display server
@@ -391,19 +598,36 @@ mod tests {
     
     #[test]
     fn test_is_image_file() {
-        assert!(is_image_file(&std::path::Path::new("test.png")));
-        assert!(is_image_file(&std::path::Path::new("test.jpg")));
-        assert!(is_image_file(&std::path::Path::new("test.PNG")));
-        assert!(!is_image_file(&std::path::Path::new("test.txt")));
-        assert!(!is_image_file(&std::path::Path::new("test")));
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let png = temp_dir.path().join("screenshot");
+        std::fs::write(&png, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+        let renamed = temp_dir.path().join("test.png");
+        std::fs::write(&renamed, "not an image").unwrap();
+        assert!(is_image_file(&png));
+        assert!(!is_image_file(&renamed));
+        assert!(!is_image_file(std::path::Path::new("missing.png")));
+    }
+    
+    #[test]
+    fn test_has_image_extension() {
+        assert!(has_image_extension(std::path::Path::new("test.png")));
+        assert!(has_image_extension(std::path::Path::new("test.jpg")));
+        assert!(has_image_extension(std::path::Path::new("test.PNG")));
+        assert!(!has_image_extension(std::path::Path::new("test.txt")));
+        assert!(!has_image_extension(std::path::Path::new("test")));
     }
     
     #[test]
     fn test_generate_screenshot_filename() {
-        let filename = generate_screenshot_filename("clipboard");
+        let filename = generate_screenshot_filename("clipboard", false, "png");
         assert!(filename.starts_with("clipboard-"));
         assert!(filename.ends_with(".png"));
         assert!(filename.len() > 20);
+        
+        let local = generate_screenshot_filename("clipboard", true, "jpg");
+        assert!(local.starts_with("clipboard-"));
+        assert!(local.ends_with(".jpg"));
+        assert!(!local.contains('Z'));
     }
     
     #[test]
@@ -414,6 +638,21 @@ mod tests {
         assert_eq!(format_file_size(1024 * 1024), "1.0 MB");
     }
     
+    #[test]
+    fn test_format_relative_time() {
+        let now = chrono::Utc::now();
+        let ago = |seconds: i64| format_relative_time(now - chrono::Duration::seconds(seconds), now);
+        assert_eq!(ago(-5), "just now");
+        assert_eq!(ago(30), "just now");
+        assert_eq!(ago(60), "1 minute ago");
+        assert_eq!(ago(180), "3 minutes ago");
+        assert_eq!(ago(2 * 3600), "2 hours ago");
+        assert_eq!(ago(30 * 3600), "yesterday");
+        assert_eq!(ago(5 * 86_400), "5 days ago");
+        // Older times are shown as a date
+        assert_eq!(ago(90 * 86_400).len(), "2024-01-01".len());
+    }
+    
     #[test]
     fn test_format_duration() {
         assert_eq!(format_duration(std::time::Duration::from_secs(30)), "30s");
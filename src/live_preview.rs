@@ -0,0 +1,450 @@
+//! Interactive live preview: a raw-mode line editor that previews the image
+//! path under the cursor as you type.
+//!
+//! Keystrokes are read one at a time with crossterm, previews are debounced
+//! so fast typing doesn't spawn a renderer per key, and the preview is drawn
+//! in a region reserved below the input line so it can be erased and redrawn
+//! without disturbing the rest of the screen. When stdin is not a terminal the
+//! system falls back to previewing whole lines.
+
+use crate::{config::Config, error::Result, image_preview::{self, ImagePreviewManager}};
+use crossterm::{
+    cursor::{self, MoveTo, MoveToColumn},
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    terminal::{self, Clear, ClearType},
+};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+const PROMPT: &str = "> ";
+const DEBOUNCE: Duration = Duration::from_millis(150);
+const PREVIEW_WIDTH: u32 = 40;
+const PREVIEW_HEIGHT: u16 = 10;
+
+/// Single-line editing buffer; the cursor is a char index
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LineBuffer {
+    text: String,
+    cursor: usize,
+}
+
+/// What a keystroke asks the editor to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditAction {
+    Edited,
+    Moved,
+    Submit(String),
+    PreviewNow,
+    Quit,
+    Ignored,
+}
+
+impl LineBuffer {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Cursor as a byte offset into `text`
+    pub fn byte_cursor(&self) -> usize {
+        self.byte_index(self.cursor)
+    }
+
+    fn byte_index(&self, chars: usize) -> usize {
+        self.text.char_indices().nth(chars).map_or(self.text.len(), |(i, _)| i)
+    }
+
+    fn len(&self) -> usize {
+        self.text.chars().count()
+    }
+
+    pub fn apply(&mut self, key: KeyEvent) -> EditAction {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        match key.code {
+            KeyCode::Char('c') | KeyCode::Char('d') if ctrl => EditAction::Quit,
+            KeyCode::Char('p') if ctrl => EditAction::PreviewNow,
+            KeyCode::Char('a') if ctrl => self.move_to(0),
+            KeyCode::Char('e') if ctrl => self.move_to(self.len()),
+            KeyCode::Char('u') if ctrl => {
+                let end = self.byte_cursor();
+                self.text.replace_range(..end, "");
+                self.cursor = 0;
+                EditAction::Edited
+            }
+            KeyCode::Char('w') if ctrl => {
+                let end = self.byte_cursor();
+                let trimmed = self.text[..end].trim_end();
+                let start = trimmed
+                    .char_indices()
+                    .rev()
+                    .find(|(_, c)| c.is_whitespace())
+                    .map_or(0, |(i, c)| i + c.len_utf8());
+                self.cursor -= self.text[start..end].chars().count();
+                self.text.replace_range(start..end, "");
+                EditAction::Edited
+            }
+            KeyCode::Char(c) if !ctrl => {
+                let at = self.byte_cursor();
+                self.text.insert(at, c);
+                self.cursor += 1;
+                EditAction::Edited
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                let at = self.byte_cursor();
+                self.text.remove(at);
+                EditAction::Edited
+            }
+            KeyCode::Delete if self.cursor < self.len() => {
+                let at = self.byte_cursor();
+                self.text.remove(at);
+                EditAction::Edited
+            }
+            KeyCode::Left if self.cursor > 0 => self.move_to(self.cursor - 1),
+            KeyCode::Right if self.cursor < self.len() => self.move_to(self.cursor + 1),
+            KeyCode::Home => self.move_to(0),
+            KeyCode::End => self.move_to(self.len()),
+            KeyCode::Enter => {
+                self.cursor = 0;
+                EditAction::Submit(std::mem::take(&mut self.text))
+            }
+            KeyCode::Esc => EditAction::Quit,
+            _ => EditAction::Ignored,
+        }
+    }
+
+    fn move_to(&mut self, cursor: usize) -> EditAction {
+        self.cursor = cursor;
+        EditAction::Moved
+    }
+
+    /// The slice of text that fits in `width` columns around the cursor, and
+    /// the cursor column within it
+    pub fn visible(&self, width: usize) -> (String, usize) {
+        let width = width.max(1);
+        let start = (self.cursor + 1).saturating_sub(width);
+        let visible: String = self.text.chars().skip(start).take(width).collect();
+        (visible, self.cursor - start)
+    }
+}
+
+/// Delays preview updates until typing pauses
+#[derive(Debug, Clone)]
+pub struct Debouncer {
+    delay: Duration,
+    pending_since: Option<Instant>,
+}
+
+impl Debouncer {
+    pub fn new(delay: Duration) -> Self {
+        Self { delay, pending_since: None }
+    }
+
+    /// Note input at `now`, restarting the delay
+    pub fn touch(&mut self, now: Instant) {
+        self.pending_since = Some(now);
+    }
+
+    /// How long to wait for the next key before the pending update is due
+    pub fn timeout(&self, now: Instant) -> Option<Duration> {
+        self.pending_since.map(|since| (since + self.delay).saturating_duration_since(now))
+    }
+
+    /// Whether the pending update is due; clears it if so
+    pub fn fire(&mut self, now: Instant) -> bool {
+        match self.pending_since {
+            Some(since) if now.duration_since(since) >= self.delay => {
+                self.pending_since = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Change to the preview region after an edit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreviewChange {
+    Show(PathBuf),
+    Hide,
+    Unchanged,
+}
+
+/// Restores cooked mode even if the editor loop bails out early
+struct RawModeGuard;
+
+impl RawModeGuard {
+    fn enable() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// LSP-style live preview system for real-time image detection
+pub struct LivePreviewSystem {
+    preview_manager: ImagePreviewManager,
+    current_preview: Option<PathBuf>,
+    /// Terminal row of the input line
+    input_row: u16,
+}
+
+impl LivePreviewSystem {
+    pub async fn new(config: Config) -> Result<Self> {
+        let preview_manager = ImagePreviewManager::new(config).await?;
+
+        Ok(Self {
+            preview_manager,
+            current_preview: None,
+            input_row: 0,
+        })
+    }
+
+    /// Decide how the preview should change for `text` with the cursor at byte `cursor`
+    pub fn preview_change(&self, text: &str, cursor: usize) -> PreviewChange {
+        match image_preview::image_path_at(text, cursor) {
+            Some(path) if Some(&path) != self.current_preview.as_ref() => PreviewChange::Show(path),
+            None if self.current_preview.is_some() => PreviewChange::Hide,
+            _ => PreviewChange::Unchanged,
+        }
+    }
+
+    /// Run the editor, echoing each submitted line; previews follow the cursor
+    /// when `auto_preview` is set and are otherwise shown on Ctrl+P
+    pub async fn run(&mut self, auto_preview: bool) -> Result<()> {
+        if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            return self.run_lines().await;
+        }
+
+        let _raw = RawModeGuard::enable()?;
+        self.reserve_region()?;
+        self.redraw_input(&LineBuffer::default())?;
+
+        let mut line = LineBuffer::default();
+        let mut debouncer = Debouncer::new(DEBOUNCE);
+
+        loop {
+            let timeout = debouncer.timeout(Instant::now()).unwrap_or(Duration::from_secs(3600));
+
+            if event::poll(timeout)? {
+                let Event::Key(key) = event::read()? else {
+                    continue;
+                };
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+
+                match line.apply(key) {
+                    EditAction::Edited | EditAction::Moved => {
+                        self.redraw_input(&line)?;
+                        if auto_preview {
+                            debouncer.touch(Instant::now());
+                        }
+                    }
+                    EditAction::PreviewNow => self.update_preview(&line).await?,
+                    EditAction::Submit(text) => {
+                        self.erase_preview()?;
+                        let mut stdout = io::stdout();
+                        queue!(stdout, MoveTo(0, self.input_row), Clear(ClearType::CurrentLine))?;
+                        write!(stdout, "Input: {}\r\n", text)?;
+                        stdout.flush()?;
+                        self.reserve_region()?;
+                        self.redraw_input(&line)?;
+                    }
+                    EditAction::Quit => break,
+                    EditAction::Ignored => {}
+                }
+            } else if debouncer.fire(Instant::now()) {
+                self.update_preview(&line).await?;
+            }
+        }
+
+        self.erase_preview()?;
+        execute!(io::stdout(), MoveTo(0, self.input_row), Clear(ClearType::CurrentLine))?;
+        Ok(())
+    }
+
+    /// Non-interactive fallback: preview the last image path of each line
+    async fn run_lines(&mut self) -> Result<()> {
+        for line in io::stdin().lock().lines() {
+            let line = line?;
+
+            if let PreviewChange::Show(path) = self.preview_change(&line, line.len()) {
+                if let Err(e) = self.preview_manager.show_preview(&path, Some(PREVIEW_WIDTH), Some(PREVIEW_HEIGHT as u32)).await {
+                    warn!("Failed to show live preview: {}", e);
+                }
+                self.current_preview = Some(path);
+            }
+
+            println!("Input: {}", line);
+        }
+
+        Ok(())
+    }
+
+    /// Make room below the input line for the preview, scrolling if needed
+    fn reserve_region(&mut self) -> Result<()> {
+        let mut stdout = io::stdout();
+        for _ in 0..=PREVIEW_HEIGHT {
+            write!(stdout, "\r\n")?;
+        }
+        execute!(stdout, cursor::MoveUp(PREVIEW_HEIGHT + 1))?;
+
+        // Terminals that don't answer the position query have just scrolled,
+        // which leaves the input line a region's height above the bottom
+        self.input_row = match cursor::position() {
+            Ok((_, row)) => row,
+            Err(_) => terminal::size()?.1.saturating_sub(PREVIEW_HEIGHT + 2),
+        };
+        Ok(())
+    }
+
+    fn redraw_input(&self, line: &LineBuffer) -> Result<()> {
+        // Some ptys report a zero size; assume a classic 80 columns there
+        let columns = match terminal::size()?.0 {
+            0 => 80,
+            columns => columns as usize,
+        };
+        let (visible, cursor_column) = line.visible(columns.saturating_sub(PROMPT.len() + 1));
+
+        let mut stdout = io::stdout();
+        queue!(stdout, MoveTo(0, self.input_row), Clear(ClearType::CurrentLine))?;
+        write!(stdout, "{}{}", PROMPT, visible)?;
+        queue!(stdout, MoveToColumn((PROMPT.len() + cursor_column) as u16))?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    async fn update_preview(&mut self, line: &LineBuffer) -> Result<()> {
+        match self.preview_change(line.text(), line.byte_cursor()) {
+            PreviewChange::Show(path) => {
+                self.erase_preview()?;
+                execute!(io::stdout(), MoveTo(0, self.input_row + 1))?;
+
+                // Renderers write plain newlines, which need output processing
+                terminal::disable_raw_mode()?;
+                let rendered = self
+                    .preview_manager
+                    .show_preview(&path, Some(PREVIEW_WIDTH), Some(PREVIEW_HEIGHT as u32))
+                    .await;
+                io::stdout().flush()?;
+                terminal::enable_raw_mode()?;
+
+                if let Err(e) = rendered {
+                    warn!("Failed to show live preview: {}", e);
+                }
+                self.current_preview = Some(path);
+            }
+            PreviewChange::Hide => {
+                self.erase_preview()?;
+                self.current_preview = None;
+            }
+            PreviewChange::Unchanged => return Ok(()),
+        }
+
+        self.redraw_input(line)
+    }
+
+    fn erase_preview(&self) -> Result<()> {
+        execute!(io::stdout(), MoveTo(0, self.input_row + 1), Clear(ClearType::FromCursorDown))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn ctrl(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    fn typed(text: &str) -> LineBuffer {
+        let mut line = LineBuffer::default();
+        for c in text.chars() {
+            line.apply(key(KeyCode::Char(c)));
+        }
+        line
+    }
+
+    #[test]
+    fn test_line_editing() {
+        let mut line = typed("vim a.png");
+        assert_eq!(line.byte_cursor(), 9);
+
+        line.apply(key(KeyCode::Home));
+        line.apply(key(KeyCode::Delete));
+        assert_eq!(line.text(), "im a.png");
+
+        line.apply(ctrl('e'));
+        line.apply(ctrl('w'));
+        assert_eq!(line.text(), "im ");
+
+        line.apply(key(KeyCode::Backspace));
+        line.apply(key(KeyCode::Left));
+        line.apply(key(KeyCode::Char('é')));
+        assert_eq!(line.text(), "iém");
+        assert_eq!(line.byte_cursor(), 3);
+
+        assert_eq!(line.apply(key(KeyCode::Enter)), EditAction::Submit("iém".to_string()));
+        assert_eq!(line.text(), "");
+        assert_eq!(line.apply(ctrl('c')), EditAction::Quit);
+
+        // Words separated by multi-byte whitespace
+        let mut line = typed("open\u{3000}a.png");
+        line.apply(ctrl('w'));
+        assert_eq!(line.text(), "open\u{3000}");
+        assert_eq!(line.byte_cursor(), line.text().len());
+    }
+
+    #[test]
+    fn test_visible_window_follows_cursor() {
+        let line = typed("0123456789");
+        assert_eq!(line.visible(4), ("789".to_string(), 3));
+        assert_eq!(line.visible(20), ("0123456789".to_string(), 10));
+    }
+
+    #[test]
+    fn test_debouncer() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(Duration::from_millis(100));
+        assert_eq!(debouncer.timeout(start), None);
+
+        debouncer.touch(start);
+        assert_eq!(debouncer.timeout(start + Duration::from_millis(40)), Some(Duration::from_millis(60)));
+        assert!(!debouncer.fire(start + Duration::from_millis(50)));
+        assert!(debouncer.fire(start + Duration::from_millis(100)));
+        assert!(!debouncer.fire(start + Duration::from_millis(200)));
+    }
+
+    #[tokio::test]
+    async fn test_preview_change_follows_cursor() {
+        let mut system = LivePreviewSystem::new(Config::default()).await.unwrap();
+
+        let temp_dir = tempdir().unwrap();
+        let image_path = temp_dir.path().join("test.png");
+        fs::write(&image_path, b"fake image data").unwrap();
+
+        let text = format!("vim {}", image_path.display());
+        let cursor = text.len() - 4; // Position in the middle of the filename
+        assert_eq!(system.preview_change(&text, cursor), PreviewChange::Show(image_path.clone()));
+
+        system.current_preview = Some(image_path);
+        assert_eq!(system.preview_change(&text, cursor), PreviewChange::Unchanged);
+        assert_eq!(system.preview_change(&text, 1), PreviewChange::Hide);
+    }
+}
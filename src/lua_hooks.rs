@@ -0,0 +1,427 @@
+//! Event handlers scripted in Lua: every `.lua` file in the scripts
+//! directory can define any of
+//!
+//! - `on_intercept(image)`, after an image is stored
+//! - `on_cleanup(image)`, before `klipdot cleanup` deletes an image; returning
+//!   `false` keeps it
+//! - `rename(image)`, before an image is stored; a string returned becomes
+//!   the descriptive part of its filename
+//! - `route(image)`, before an image is stored; returning `false` drops it and
+//!   a string files it under that source, with its source subdirectory
+//!
+//! Scripts run in name order, each in a fresh Lua state, and for `rename`
+//! and `route` the first answer wins. They run sandboxed: only the `string`,
+//! `table`, `math` and `utf8` libraries, nothing that reaches files or
+//! processes and no way to load bytecode (`load` and `string.dump` are
+//! gone, and scripts must be source), with limits on memory and
+//! instructions. What they can do goes through the `klipdot` table:
+//!
+//! - `klipdot.recent(n)` and `klipdot.search(query)` list images from the index
+//! - `klipdot.copy(text)` puts text on the clipboard
+//! - `klipdot.notify(message)` shows a desktop notification
+//! - `klipdot.log(message)` (and `print`) write to the log
+//!
+//! A script that fails is logged and skipped; it never stops an image from
+//! being stored.
+
+use crate::{
+    clipboard::ClipboardMonitor,
+    command_runner::SharedRunner,
+    config::{Config, Screenshot, ScriptsConfig},
+    error::Result,
+    error_history, image_processor, search, Error,
+};
+use mlua::{ChunkMode, HookTriggers, Lua, LuaOptions, StdLib, Table, Value};
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use tracing::{debug, info, warn};
+
+/// Instructions between checks of the instruction limit
+const INSTRUCTION_CHECK: u32 = 10_000;
+
+/// Base library functions that read files, or load chunks that may be
+/// bytecode, which Lua doesn't verify
+const UNSAFE_GLOBALS: &[&str] = &["dofile", "loadfile", "load"];
+
+/// What scripts are told about an image; fields not known yet are `nil`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub path: Option<PathBuf>,
+    pub source: String,
+    pub app: Option<String>,
+    pub output: Option<String>,
+    pub tags: Vec<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub size: Option<u64>,
+}
+
+impl From<&Screenshot> for ImageInfo {
+    fn from(screenshot: &Screenshot) -> Self {
+        Self {
+            path: Some(screenshot.path.clone()),
+            source: screenshot.source.clone(),
+            app: screenshot.app.clone(),
+            output: screenshot.output.clone(),
+            tags: screenshot.tags.clone(),
+            size: Some(screenshot.size),
+            ..Self::default()
+        }
+    }
+}
+
+/// Where a `route` handler sent an image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    /// Store it as usual
+    Keep,
+    /// Don't store it, as the named script said
+    Drop(String),
+    /// Store it as coming from this source
+    Source(String),
+}
+
+/// The scripts in the configured directory
+pub struct Scripts {
+    config: Config,
+    runner: SharedRunner,
+    files: Vec<PathBuf>,
+}
+
+impl Scripts {
+    /// The scripts `config` names; none when scripting is disabled
+    pub fn load(config: &Config, runner: SharedRunner) -> Self {
+        let files = match script_dir(&config.scripts) {
+            Some(dir) if config.scripts.enabled => scripts_in(&dir),
+            _ => Vec::new(),
+        };
+        Self { config: config.clone(), runner, files }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Run every `on_intercept` handler
+    pub async fn on_intercept(&self, image: &ImageInfo) {
+        self.call("on_intercept", image).await;
+    }
+
+    /// Whether every `on_cleanup` handler lets `image` be deleted
+    pub async fn allows_cleanup(&self, image: &ImageInfo) -> bool {
+        let answers = self.call("on_cleanup", image).await;
+        !answers.iter().any(|(_, answer)| *answer == Answer::False)
+    }
+
+    /// The name the first `rename` handler gives `image`, as a filename slug
+    pub async fn rename(&self, image: &ImageInfo) -> Option<String> {
+        self.call("rename", image).await.into_iter().find_map(|(_, answer)| match answer {
+            Answer::Text(name) => Some(crate::rename::slugify(&name)).filter(|slug| !slug.is_empty()),
+            _ => None,
+        })
+    }
+
+    /// Where the first `route` handler with an answer sends `image`
+    pub async fn route(&self, image: &ImageInfo) -> Route {
+        for (script, answer) in self.call("route", image).await {
+            match answer {
+                Answer::False => return Route::Drop(script),
+                Answer::Text(source) if !source.trim().is_empty() => return Route::Source(source.trim().to_string()),
+                _ => {}
+            }
+        }
+        Route::Keep
+    }
+
+    /// Call `handler` in each script that defines it, in order, with what each returned
+    async fn call(&self, handler: &'static str, image: &ImageInfo) -> Vec<(String, Answer)> {
+        if self.files.is_empty() {
+            return Vec::new();
+        }
+        let (config, runner, files, image) = (self.config.clone(), self.runner.clone(), self.files.clone(), image.clone());
+        let runtime = tokio::runtime::Handle::current();
+        let answers = tokio::task::spawn_blocking(move || {
+            let mut answers = Vec::new();
+            for file in &files {
+                let script = file.file_name().unwrap_or_default().to_string_lossy().to_string();
+                match run_handler(&config, &runner, &runtime, file, handler, &image) {
+                    Ok(Some(answer)) => answers.push((script, answer)),
+                    Ok(None) => {}
+                    Err(e) => {
+                        warn!("Script {} failed in {}: {}", script, handler, e);
+                        error_history::record_error("script", &e);
+                    }
+                }
+            }
+            answers
+        })
+        .await;
+        answers.unwrap_or_default()
+    }
+}
+
+/// What a handler returned
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Answer {
+    Nothing,
+    False,
+    Text(String),
+}
+
+fn script_dir(settings: &ScriptsConfig) -> Option<PathBuf> {
+    match &settings.dir {
+        Some(dir) => Some(dir.clone()),
+        None => crate::get_home_dir().ok().map(|home| home.join(crate::SCRIPTS_DIR)),
+    }
+}
+
+/// Lua scripts in `dir`, in name order
+fn scripts_in(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut scripts: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "lua"))
+        .collect();
+    scripts.sort();
+    scripts
+}
+
+/// Run `file` in a fresh sandbox and call its `handler`, if it defines one
+fn run_handler(
+    config: &Config,
+    runner: &SharedRunner,
+    runtime: &tokio::runtime::Handle,
+    file: &Path,
+    handler: &str,
+    image: &ImageInfo,
+) -> Result<Option<Answer>> {
+    let source = std::fs::read_to_string(file)?;
+    let lua = sandbox(config, runner, runtime).map_err(script_error)?;
+    let name = file.file_name().unwrap_or_default().to_string_lossy().to_string();
+    lua.load(source.as_str()).set_name(name).set_mode(ChunkMode::Text).exec().map_err(script_error)?;
+
+    let Value::Function(function) = lua.globals().get::<_, Value>(handler).map_err(script_error)? else {
+        return Ok(None);
+    };
+    let answer = match function.call::<_, Value>(image_table(&lua, image).map_err(script_error)?).map_err(script_error)? {
+        Value::Boolean(false) => Answer::False,
+        Value::String(text) => Answer::Text(text.to_str().map_err(script_error)?.to_string()),
+        _ => Answer::Nothing,
+    };
+    debug!("{} in {:?} returned {:?}", handler, file, answer);
+    Ok(Some(answer))
+}
+
+fn script_error(e: mlua::Error) -> Error {
+    Error::Process(e.to_string())
+}
+
+/// A Lua state with the safe libraries, the limits, and the `klipdot` API
+fn sandbox(config: &Config, runner: &SharedRunner, runtime: &tokio::runtime::Handle) -> mlua::Result<Lua> {
+    let lua = Lua::new_with(StdLib::STRING | StdLib::TABLE | StdLib::MATH | StdLib::UTF8, LuaOptions::default())?;
+    lua.set_memory_limit(config.scripts.max_memory_mb as usize * 1024 * 1024)?;
+    let budget = Rc::new(Cell::new(config.scripts.max_instructions / INSTRUCTION_CHECK as u64));
+    lua.set_hook(HookTriggers::new().every_nth_instruction(INSTRUCTION_CHECK), move |_, _| {
+        if budget.get() == 0 {
+            return Err(mlua::Error::RuntimeError("script ran too long".to_string()));
+        }
+        budget.set(budget.get() - 1);
+        Ok(())
+    });
+    install_api(&lua, config, runner, runtime)?;
+    Ok(lua)
+}
+
+/// Remove what reads files or makes bytecode and add the `klipdot` table
+fn install_api(lua: &Lua, config: &Config, runner: &SharedRunner, runtime: &tokio::runtime::Handle) -> mlua::Result<()> {
+    let globals = lua.globals();
+    for name in UNSAFE_GLOBALS {
+        globals.set(*name, Value::Nil)?;
+    }
+    globals.get::<_, Table>("string")?.set("dump", Value::Nil)?;
+    let api = lua.create_table()?;
+
+    let handle = runtime.clone();
+    let listed = config.clone();
+    api.set(
+        "recent",
+        lua.create_function(move |lua, count: Option<usize>| {
+            let screenshots = handle.block_on(listed.get_recent_screenshots(count.unwrap_or(10))).map_err(mlua::Error::external)?;
+            image_list(lua, &screenshots)
+        })?,
+    )?;
+
+    let handle = runtime.clone();
+    let searched = config.clone();
+    api.set(
+        "search",
+        lua.create_function(move |lua, query: String| {
+            let query = search::Query::parse(&query).map_err(mlua::Error::external)?;
+            let screenshots = handle.block_on(searched.get_recent_screenshots(usize::MAX)).map_err(mlua::Error::external)?;
+            let found: Vec<Screenshot> = screenshots.into_iter().filter(|screenshot| query.matches(screenshot)).collect();
+            image_list(lua, &found)
+        })?,
+    )?;
+
+    let handle = runtime.clone();
+    let copied = config.clone();
+    api.set(
+        "copy",
+        lua.create_function(move |_, text: String| {
+            handle
+                .block_on(async {
+                    let clipboard = ClipboardMonitor::new(copied.clone()).await?;
+                    clipboard.set_text(&text).await
+                })
+                .map_err(mlua::Error::external)
+        })?,
+    )?;
+
+    let handle = runtime.clone();
+    let notifier = runner.clone();
+    api.set(
+        "notify",
+        lua.create_function(move |_, message: String| {
+            let _guard = handle.enter();
+            image_processor::notify(&notifier, &message);
+            Ok(())
+        })?,
+    )?;
+
+    let log = lua.create_function(|_, message: String| {
+        info!("script: {}", message);
+        Ok(())
+    })?;
+    api.set("log", log.clone())?;
+    globals.set("print", log)?;
+    globals.set("klipdot", api)
+}
+
+fn image_table<'lua>(lua: &'lua Lua, image: &ImageInfo) -> mlua::Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    if let Some(path) = &image.path {
+        table.set("path", path.to_string_lossy().to_string())?;
+        table.set("filename", path.file_name().map(|name| name.to_string_lossy().to_string()))?;
+    }
+    table.set("source", image.source.as_str())?;
+    table.set("app", image.app.as_deref())?;
+    table.set("output", image.output.as_deref())?;
+    table.set("tags", image.tags.clone())?;
+    table.set("width", image.width)?;
+    table.set("height", image.height)?;
+    table.set("size", image.size)?;
+    Ok(table)
+}
+
+fn image_list<'lua>(lua: &'lua Lua, screenshots: &[Screenshot]) -> mlua::Result<Table<'lua>> {
+    let list = lua.create_table()?;
+    for (index, screenshot) in screenshots.iter().enumerate() {
+        list.set(index + 1, image_table(lua, &ImageInfo::from(screenshot))?)?;
+    }
+    Ok(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::FakeRunner;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn scripts(temp_dir: &TempDir, files: &[(&str, &str)]) -> Scripts {
+        let dir = temp_dir.path().join("scripts");
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, source) in files {
+            std::fs::write(dir.join(name), source).unwrap();
+        }
+        let config = Config {
+            screenshot_dir: temp_dir.path().join("shots"),
+            scripts: ScriptsConfig { dir: Some(dir), ..ScriptsConfig::default() },
+            ..Config::default()
+        };
+        Scripts::load(&config, Arc::new(FakeRunner::new()))
+    }
+
+    fn image(app: &str) -> ImageInfo {
+        ImageInfo {
+            source: "clipboard".to_string(),
+            app: Some(app.to_string()),
+            width: Some(800),
+            height: Some(600),
+            ..ImageInfo::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handlers() {
+        let temp_dir = TempDir::new().unwrap();
+        let scripts = scripts(
+            &temp_dir,
+            &[
+                ("10-route.lua", r#"function route(image) if image.app == "1password" then return false end end"#),
+                (
+                    "20-name.lua",
+                    r#"
+                    function rename(image) return image.app .. " " .. image.width .. "x" .. image.height end
+                    function route(image) if image.app == "slack" then return "work" end end
+                    function on_cleanup(image) return not image.tags[1] end
+                    "#,
+                ),
+                ("30-broken.lua", "function rename(image) error('oops') end"),
+                ("notes.txt", "function route() return false end"),
+            ],
+        );
+
+        assert_eq!(scripts.rename(&image("firefox")).await.as_deref(), Some("firefox-800x600"));
+        assert_eq!(scripts.route(&image("1password")).await, Route::Drop("10-route.lua".to_string()));
+        assert_eq!(scripts.route(&image("slack")).await, Route::Source("work".to_string()));
+        assert_eq!(scripts.route(&image("firefox")).await, Route::Keep);
+
+        let tagged = ImageInfo { tags: vec!["keep".to_string()], ..image("firefox") };
+        assert!(!scripts.allows_cleanup(&tagged).await);
+        assert!(scripts.allows_cleanup(&image("firefox")).await);
+    }
+
+    #[tokio::test]
+    async fn test_sandbox() {
+        let temp_dir = TempDir::new().unwrap();
+        let scripts = scripts(
+            &temp_dir,
+            &[
+                ("a.lua", "function rename() return tostring(io) .. tostring(os) .. tostring(dofile) end"),
+                ("b.lua", "function route() while true do end end"),
+                ("c.lua", "function on_cleanup() load(string.dump(function() end))() return false end"),
+            ],
+        );
+        // Nothing reaches files or processes, and runaway scripts are stopped
+        assert_eq!(scripts.rename(&image("firefox")).await.as_deref(), Some("nilnilnil"));
+        assert_eq!(scripts.route(&image("firefox")).await, Route::Keep);
+        // No bytecode can be made or loaded, so the script fails before answering
+        assert!(scripts.allows_cleanup(&image("firefox")).await);
+    }
+
+    #[tokio::test]
+    async fn test_api() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("shots")).unwrap();
+        std::fs::write(temp_dir.path().join("shots/clipboard-1.png"), b"png").unwrap();
+        let scripts = scripts(
+            &temp_dir,
+            &[(
+                "api.lua",
+                r#"function rename()
+                    klipdot.notify("hello")
+                    return "found " .. #klipdot.recent(5) .. " " .. #klipdot.search("source:screenshot")
+                end"#,
+            )],
+        );
+        assert_eq!(scripts.rename(&image("firefox")).await.as_deref(), Some("found-1-0"));
+
+        let config = Config { scripts: ScriptsConfig { enabled: false, ..scripts.config.scripts.clone() }, ..scripts.config.clone() };
+        assert!(Scripts::load(&config, Arc::new(FakeRunner::new())).is_empty());
+    }
+}
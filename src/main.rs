@@ -1,15 +1,58 @@
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
 use klipdot::{
+    alt_text,
+    ansi_export::{self, AnsiFormat},
+    archive,
+    attach,
+    audit,
+    chat_upload,
     clipboard::ClipboardMonitor,
-    config::Config,
+    clipboard_history,
+    cold_storage,
+    command_runner,
+    completion,
+    config::{ColdFormat, ColdStorageConfig, Config},
+    error_history::{self, ErrorHistory},
+    image_processor::ImageProcessor,
+    inject::{self, InjectSource, Injected},
+    instance_lock,
+    intercept_switches::{self, InterceptSource},
     interceptor::TerminalInterceptor,
+    ipc,
+    man,
+    monitors::{self, Monitor},
+    output,
+    paste_image,
+    path_format,
+    remote,
+    rename,
+    report,
+    reprocess,
+    run_state::{self, Claimed},
+    screenshot::{self, CaptureMode},
+    search, secrets,
     service::ServiceManager,
+    sink,
+    substitution::{self, SubstitutionEngine},
+    temp_files,
+    undo,
+    upload,
+    window_target::{self, WindowSelection, WindowTarget},
+};
+#[cfg(feature = "preview")]
+use klipdot::{
+    annotate,
+    color_picker,
     image_preview::ImagePreviewManager,
-    stdout_monitor::{StdoutMonitor, LivePreviewSystem},
+    live_preview::LivePreviewSystem,
+    stdout_monitor::StdoutMonitor,
 };
-use std::path::PathBuf;
-use tracing::{info, error, warn};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use tracing::{info, error};
+#[cfg(all(unix, feature = "preview"))]
+use tracing::debug;
 use tracing_subscriber::EnvFilter;
 
 #[derive(Parser)]
@@ -23,9 +66,19 @@ struct Args {
     #[command(subcommand)]
     command: Commands,
     
+    /// Log debug output
     #[arg(short, long, global = true)]
     verbose: bool,
     
+    /// Print only a command's results, no confirmations or banners
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    
+    /// Leave out emoji and colors (also set by NO_COLOR)
+    #[arg(long, global = true)]
+    no_color: bool,
+    
+    /// Use this configuration file instead of ~/.klipdot/config.json
     #[arg(short, long, global = true)]
     config: Option<PathBuf>,
 }
@@ -37,12 +90,262 @@ enum Commands {
         #[arg(short, long)]
         daemon: bool,
     },
+    /// Run only the clipboard monitor, as shell hooks do; exits when KlipDot is already running
+    MonitorClipboard,
     /// Stop the running service
     Stop,
     /// Restart the service
     Restart,
     /// Show service status and statistics
     Status,
+    /// Turn an interception source on, in the running instance and the configuration
+    Enable {
+        #[arg(value_enum)]
+        source: InterceptSource,
+    },
+    /// Turn an interception source off, in the running instance and the configuration
+    Disable {
+        #[arg(value_enum)]
+        source: InterceptSource,
+    },
+    /// List recent screenshots, newest first
+    List {
+        /// Number of screenshots to show
+        #[arg(short, long, default_value_t = klipdot::MAX_RECENT_SCREENSHOTS)]
+        recent: usize,
+        /// Only screenshots whose source contains this ("clipboard", "wayland-screenshot", ...)
+        #[arg(long)]
+        source: Option<String>,
+        /// Only screenshots from an application whose name contains this
+        #[arg(long)]
+        app: Option<String>,
+    },
+    /// Search stored images, newest first, with a query like
+    /// 'source:clipboard size:>2MB before:2024-06-01 tag:bug text:"panic"'
+    Search {
+        /// Terms that must all hold: source:, app:, output:, tag:, text:,
+        /// size:>2MB, before:/after: YYYY-MM-DD or an age like 7d, or bare
+        /// words to find in names and descriptions; -term negates
+        #[arg(required = true, num_args = 1.., allow_hyphen_values = true)]
+        query: Vec<String>,
+        /// Show at most this many matches
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+    },
+    /// Show captures per day, storage growth, top sources and the biggest files in the store
+    Stats {
+        /// Days of history to chart
+        #[arg(long, default_value_t = 14)]
+        days: u32,
+        /// Number of sources and files to list
+        #[arg(long, default_value_t = 5)]
+        top: usize,
+    },
+    /// Add or remove tags on a stored image, for `search tag:...`
+    Tag {
+        /// Image to tag, or "last" for the newest screenshot
+        target: String,
+        /// Tags to add
+        tags: Vec<String>,
+        /// Tags to remove
+        #[arg(long, short)]
+        remove: Vec<String>,
+    },
+    /// Take a screenshot and store it in the screenshot directory
+    Capture {
+        /// Capture a region given as "X,Y WxH"; select interactively when no value is given
+        #[arg(long, num_args = 0..=1, default_missing_value = "", conflicts_with_all = ["window", "output"])]
+        region: Option<String>,
+        /// Capture the focused window ("active", the default) or one picked by clicking ("pick")
+        #[arg(long, num_args = 0..=1, default_missing_value = "active", conflicts_with = "output")]
+        window: Option<WindowSelection>,
+        /// Capture one monitor by name ("DP-1"); pick from a list when no value is given
+        #[arg(long, num_args = 0..=1, default_missing_value = "")]
+        output: Option<String>,
+        /// Screenshot tool to use instead of the configured one
+        #[arg(long)]
+        tool: Option<String>,
+        /// Wait this many seconds, counting down, before capturing (menus, hover states)
+        #[arg(long, value_name = "SECONDS")]
+        delay: Option<u64>,
+        /// Ring the terminal bell when the delay is over
+        #[arg(long, requires = "delay")]
+        beep: bool,
+    },
+    /// Put a stored image back on the clipboard as image data, for pasting into GUI apps
+    PasteImage {
+        /// Image to copy, or "last" for the newest screenshot
+        #[arg(default_value = "last")]
+        target: String,
+    },
+    /// Put an image file on the clipboard as image data, for pasting into GUI apps
+    Copy {
+        /// Image file, or "-" to read it from stdin
+        #[arg(long, value_name = "PATH")]
+        image: PathBuf,
+    },
+    /// Put back the image the clipboard held before KlipDot replaced it with a path
+    Undo {
+        /// Restore it even if something else has been copied since
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print a markdown image link for a stored image, described in its alt text
+    Snippet {
+        /// Image to link, or "last" for the newest screenshot
+        #[arg(default_value = "last")]
+        target: String,
+        /// Alt text to use instead of the image's description
+        #[arg(long)]
+        alt: Option<String>,
+    },
+    #[cfg(feature = "preview")]
+    /// Mark up an image with boxes, arrows, text and a crop in the terminal,
+    /// storing the result as a new image and copying its path
+    Annotate {
+        /// Image to annotate, or "last" for the newest screenshot
+        #[arg(default_value = "last")]
+        target: String,
+    },
+    #[cfg(feature = "preview")]
+    /// Pick a color off an image with a crosshair in the terminal, copying its hex code
+    PickColor {
+        /// Image to pick from, or "last" for the newest screenshot
+        #[arg(default_value = "last")]
+        target: String,
+    },
+    /// Upload a stored image as a GitHub or GitLab attachment and print markdown linking to it
+    GhAttach {
+        /// Image to upload, or "last" for the newest screenshot
+        #[arg(default_value = "last")]
+        target: String,
+        /// Repository as owner/name, host/owner/name or a remote URL; the
+        /// origin remote of the current directory by default
+        #[arg(long)]
+        repo: Option<String>,
+        /// Where the repository is hosted, when the host name doesn't say
+        #[arg(long, value_enum)]
+        forge: Option<attach::Forge>,
+        /// GitHub branch images are committed to
+        #[arg(long, default_value = attach::DEFAULT_BRANCH)]
+        branch: String,
+        /// Alt text to use instead of the image's description
+        #[arg(long)]
+        alt: Option<String>,
+        /// Print the URL alone instead of markdown
+        #[arg(long)]
+        url: bool,
+    },
+    /// Write an image as a text file of terminal graphics, to `cat` over plain SSH
+    ExportAnsi {
+        /// Image to export, or "last" for the newest screenshot
+        #[arg(default_value = "last")]
+        target: String,
+        /// Width in terminal columns
+        #[arg(long, default_value_t = ansi_export::DEFAULT_COLUMNS)]
+        cols: u32,
+        /// Truecolor half blocks, or sixel graphics (needs img2sixel)
+        #[arg(long, value_enum, default_value_t)]
+        format: AnsiFormat,
+        /// File to write, or "-" for stdout; `<image name>.ans` (or `.six`) in
+        /// the current directory by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Share an image in a Slack channel or through a Discord webhook
+    Upload {
+        /// Where to post: slack:#channel, slack:<channel id>, discord or discord:<name>
+        #[arg(long)]
+        to: String,
+        /// Image to upload, or "last" for the newest screenshot
+        #[arg(default_value = "last")]
+        target: String,
+        /// Message posted with the image
+        #[arg(long)]
+        comment: Option<String>,
+    },
+    /// Manage tokens for upload targets in the system keyring
+    Secret {
+        #[command(subcommand)]
+        action: SecretAction,
+    },
+    /// Rename a stored image, keeping its metadata
+    Rename {
+        /// Image to rename, or "last" for the newest screenshot
+        target: String,
+        /// New name; named after the text in the image (OCR) when omitted
+        name: Option<String>,
+        /// Also update the clipboard if it still holds the old path
+        #[arg(long)]
+        fix_clipboard: bool,
+    },
+    /// Feed an image through interception as if a monitor had seen it, for tests and demos
+    Inject {
+        /// Image file, or "-" to read it from stdin
+        #[arg(long)]
+        image: PathBuf,
+        /// Source to act as: "clipboard", "screenshot" or "stdin"
+        #[arg(long = "as", default_value = "clipboard")]
+        source: InjectSource,
+    },
+    /// Store and preview each image written to a named pipe, such as matplotlib figures
+    Sink {
+        /// Name of the sink; its pipe is ~/.klipdot/sinks/<name>
+        #[arg(short, long, default_value = "default")]
+        name: String,
+        /// Read images from stdin instead of a named pipe
+        #[arg(long)]
+        stdin: bool,
+        /// Only store images, without previewing them
+        #[arg(long)]
+        no_preview: bool,
+    },
+    /// Store an image file (used by the shell hooks); prints the stored path
+    ProcessFile {
+        /// Image to store
+        path: PathBuf,
+        /// Source recorded for the image
+        #[arg(long, default_value = "file")]
+        source: String,
+    },
+    /// Store images produced on an SSH host locally, for remote workflows
+    Remote {
+        #[command(subcommand)]
+        action: RemoteAction,
+    },
+    /// Store an image from inside a .zip or .tar archive; prints the stored path
+    Extract {
+        /// Image in an archive, as `artifacts.zip::screenshots/fail.png`
+        member: String,
+    },
+    /// Replace image payloads in a command line with KlipDot paths
+    Substitute {
+        /// Command line as one argument, or already split words
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Print scp or rsync arguments with images prepared for the remote host
+    PrepareUpload {
+        /// Copy command the arguments are for ("scp" or "rsync")
+        program: String,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Store new images from directories the shell hooks watch (run by the hooks)
+    ScanNew {
+        /// Directories to scan; "." is the current directory
+        #[arg(env = "KLIPDOT_SCAN_DIRS", value_delimiter = ':')]
+        dirs: Vec<PathBuf>,
+    },
+    /// List screenshot paths matching a prefix, for shell completion
+    CompletePaths {
+        /// Word being completed
+        #[arg(default_value = "", allow_hyphen_values = true)]
+        prefix: String,
+        /// Maximum number of paths to print
+        #[arg(short, long, default_value = "20")]
+        limit: usize,
+    },
     /// Install shell hooks and system integration
     Install {
         #[arg(short, long)]
@@ -50,20 +353,82 @@ enum Commands {
     },
     /// Uninstall shell hooks and system integration
     Uninstall,
+    /// Print a command's manual page, or write them all with --dir
+    Man {
+        /// Command to show, such as `remote connect`
+        command: Vec<String>,
+        /// Write klipdot.1 and a klipdot-<command>.1 page per command here
+        #[arg(long, value_name = "DIR", conflicts_with = "command")]
+        dir: Option<PathBuf>,
+    },
+    /// Copy an image into the share directory, deleted once the TTL is over
+    Share {
+        /// Image to share, or "last" for the newest screenshot
+        #[arg(default_value = "last")]
+        target: String,
+        /// How long the copy is kept, like 30m, 1h or 2d
+        #[arg(long, default_value = "1h")]
+        ttl: String,
+    },
+    /// Write a diagnostic report to attach to bug reports; nothing is sent anywhere
+    Report {
+        /// File to write [default: klipdot-report-<time>.txt in the current directory]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
     /// Clean up old screenshots
     Cleanup {
         #[arg(short, long, default_value = "30")]
         days: u32,
     },
+    /// List clipboard history entries or put one back on the clipboard
+    History {
+        #[command(subcommand)]
+        action: Option<HistoryAction>,
+    },
+    /// Verify or export the audit log of interceptions
+    Audit {
+        #[command(subcommand)]
+        action: AuditAction,
+    },
+    /// Move old images to cold storage: a zstd bundle, WebP or AVIF
+    Freeze {
+        /// Freeze images older than this many days [default: cold_storage.after_days]
+        #[arg(short, long)]
+        days: Option<u32>,
+        /// What frozen images become [default: cold_storage.format]
+        #[arg(short, long, value_enum)]
+        format: Option<ColdFormat>,
+        /// List the images that would be frozen without touching them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Run stored images through the current format, quality and size settings again
+    Reprocess {
+        /// Only images matching a search query, like 'source:clipboard after:7d'
+        #[arg(long)]
+        filter: Option<String>,
+        /// List the images that would be reprocessed without touching them
+        #[arg(long)]
+        dry_run: bool,
+    },
     /// Show configuration
     Config {
         #[command(subcommand)]
         action: Option<ConfigAction>,
     },
+    #[cfg(feature = "preview")]
     /// Preview an image in the terminal
     Preview {
         /// Path to the image file
-        image_path: PathBuf,
+        #[arg(required_unless_present = "line")]
+        image_path: Option<PathBuf>,
+        /// Preview the image path under the cursor in this command line instead
+        #[arg(long, conflicts_with = "image_path")]
+        line: Option<String>,
+        /// Cursor position in --line, in characters
+        #[arg(long, requires = "line")]
+        cursor: Option<usize>,
         /// Maximum width in characters/pixels
         #[arg(short, long)]
         width: Option<u32>,
@@ -71,20 +436,37 @@ enum Commands {
         #[arg(short = 'H', long)]
         height: Option<u32>,
     },
+    #[cfg(feature = "preview")]
     /// Monitor command output for image paths and auto-preview
     MonitorOutput {
         /// Command to monitor (optional, if not provided reads from stdin)
         #[arg(trailing_var_arg = true)]
         command: Vec<String>,
     },
+    #[cfg(all(feature = "preview", feature = "file-watch"))]
+    /// Re-run a command that renders an image whenever files change, and preview what changed
+    WatchDiff {
+        /// Image the command writes; found from its output when not given
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Files or directories to watch [default: the working directory]
+        #[arg(short, long)]
+        watch: Vec<PathBuf>,
+        /// Command to run, such as `python plot.py`
+        #[arg(trailing_var_arg = true, required = true)]
+        command: Vec<String>,
+    },
+    #[cfg(feature = "preview")]
     /// Preview image data from stdin
     PreviewStdin,
+    #[cfg(feature = "preview")]
     /// Enable LSP-style live preview mode
     LivePreview {
         /// Enable auto-preview as you type
         #[arg(long)]
         auto_preview: bool,
     },
+    #[cfg(feature = "preview")]
     /// Run a TUI application with image monitoring
     Tui {
         /// TUI application to run with monitoring
@@ -93,6 +475,72 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum SecretAction {
+    /// Store a secret, read from stdin ("slack", "discord", "discord-<name>")
+    Set {
+        name: String,
+    },
+    /// Remove a stored secret
+    Delete {
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum AuditAction {
+    /// Check that no entry was changed, removed or reordered
+    Verify,
+    /// Write every entry as JSON or CSV
+    Export {
+        #[arg(long, value_enum, default_value = "json")]
+        format: audit::ExportFormat,
+        /// File to write; stdout when omitted
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum HistoryAction {
+    /// Show the newest entries, newest last
+    List {
+        #[arg(short = 'n', long, default_value = "20")]
+        limit: usize,
+    },
+    /// Put an entry back on the clipboard
+    Restore {
+        /// Entry number, as `klipdot history list` shows it
+        id: u64,
+    },
+    /// Forget every entry; stored images are kept
+    Clear,
+}
+
+#[derive(Subcommand)]
+enum RemoteAction {
+    /// Connect to an SSH host running klipdot and store the images it produces
+    Connect {
+        /// SSH destination, such as user@host or a Host from ~/.ssh/config
+        destination: String,
+        /// Remote directory to watch; repeatable (default: its screenshot directory and home)
+        #[arg(long = "watch", value_name = "DIR")]
+        dirs: Vec<String>,
+        /// Put the local path of each image on the clipboard
+        #[arg(long)]
+        copy_path: bool,
+    },
+    /// Stream new images to stdout (run by `remote connect` over SSH)
+    Serve {
+        /// Directory to watch; repeatable
+        #[arg(long = "watch", value_name = "DIR")]
+        dirs: Vec<PathBuf>,
+        /// How often to look for new images, in milliseconds
+        #[arg(long, default_value_t = 500)]
+        interval: u64,
+    },
+}
+
 #[derive(Subcommand)]
 enum ConfigAction {
     /// Show current configuration
@@ -107,17 +555,27 @@ enum ConfigAction {
 async fn main() -> Result<()> {
     let args = Args::parse();
     
+    output::init(args.quiet, args.no_color);
+    
     // Initialize tracing
     let filter = if args.verbose {
         EnvFilter::new("klipdot=debug")
+    } else if args.quiet {
+        EnvFilter::new("klipdot=warn")
     } else {
         EnvFilter::new("klipdot=info")
     };
     
+    // Log to stderr so stdout stays usable by hooks that capture it
     tracing_subscriber::fmt()
         .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(!output::is_plain())
         .init();
     
+    // Release builds abort on panic, which would leave temporary files behind
+    temp_files::install_panic_hook();
+    
     // Load configuration
     let config = if let Some(config_path) = args.config {
         Config::load_from_path(&config_path)?
@@ -135,6 +593,9 @@ async fn main() -> Result<()> {
                 start_foreground(&config).await?;
             }
         }
+        Commands::MonitorClipboard => {
+            monitor_clipboard(&config).await?;
+        }
         Commands::Stop => {
             ServiceManager::stop().await?;
         }
@@ -144,30 +605,200 @@ async fn main() -> Result<()> {
         Commands::Status => {
             show_status(&config).await?;
         }
+        Commands::Enable { source } => {
+            switch_source(&config, source, true).await?;
+        }
+        Commands::Disable { source } => {
+            switch_source(&config, source, false).await?;
+        }
+        Commands::List { recent, source, app } => {
+            list_screenshots(&config, recent, source.as_deref(), app.as_deref()).await?;
+        }
+        Commands::Search { query, limit } => {
+            search_screenshots(&config, &query.join(" "), limit).await?;
+        }
+        Commands::Stats { days, top } => {
+            let screenshots = config.get_recent_screenshots(usize::MAX).await?;
+            let stats = klipdot::store_stats::StoreStats::compute(&screenshots, chrono::Utc::now(), days, top, config.cleanup_days);
+            print!("{}", stats.render());
+        }
+        Commands::Tag { target, tags, remove } => {
+            tag_screenshot(&config, &target, &tags, &remove).await?;
+        }
+        Commands::Capture { region, window, output, tool, delay, beep } => {
+            let delay = delay.map(|seconds| (std::time::Duration::from_secs(seconds), beep));
+            capture_screenshot(&config, region, window, output, tool, delay).await?;
+        }
+        Commands::PasteImage { target } => {
+            let path = paste_image::resolve(&config, &target).await?;
+            let runner = command_runner::system();
+            paste_image::copy_to_clipboard(runner.as_ref(), config.get_display_server(), &path, &klipdot::get_home_dir()?, &config.decode_limits).await?;
+            output::status("✅", format!("Copied {} to the clipboard", path.display()));
+        }
+        Commands::Copy { image } => {
+            let data = if image.as_os_str() == "-" {
+                let mut data = Vec::new();
+                tokio::io::AsyncReadExt::read_to_end(&mut tokio::io::stdin(), &mut data).await?;
+                data
+            } else {
+                tokio::fs::read(&image).await?
+            };
+            ClipboardMonitor::new(config.clone()).await?.set_clipboard_image(&data).await?;
+            match image.as_os_str() == "-" {
+                true => output::status("✅", "Copied the image from stdin to the clipboard"),
+                false => output::status("✅", format!("Copied {} to the clipboard", image.display())),
+            }
+        }
+        Commands::Undo { force } => {
+            let rewrite = undo::undo(&config, &klipdot::get_home_dir()?, force).await?;
+            output::status("↩️", format!("Put back the image replaced with {}", rewrite.replacement.trim_end()));
+        }
+        Commands::Inject { image, source } => {
+            let data = if image.as_os_str() == "-" {
+                let mut data = Vec::new();
+                tokio::io::AsyncReadExt::read_to_end(&mut tokio::io::stdin(), &mut data).await?;
+                data
+            } else {
+                tokio::fs::read(&image).await?
+            };
+            match inject::inject(&config, &klipdot::get_home_dir()?, &data, source).await? {
+                Injected::Stored(path) => println!("{}", path.display()),
+                Injected::Skipped(reason) => anyhow::bail!("The {} monitor would skip this image: {}", source, reason),
+            }
+        }
+        Commands::Sink { name, stdin, no_preview } => {
+            run_sink(&config, &name, stdin, !no_preview).await?;
+        }
+        Commands::Snippet { target, alt } => {
+            print_snippet(&config, &target, alt).await?;
+        }
+        Commands::GhAttach { target, repo, forge, branch, alt, url } => {
+            attach_image(&config, &target, repo, forge, &branch, alt, url).await?;
+        }
+        #[cfg(feature = "preview")]
+        Commands::Annotate { target } => {
+            annotate_image(&config, &target).await?;
+        }
+        #[cfg(feature = "preview")]
+        Commands::PickColor { target } => {
+            pick_color(&config, &target).await?;
+        }
+        Commands::ExportAnsi { target, cols, format, output } => {
+            export_ansi(&config, &target, cols, format, output).await?;
+        }
+        Commands::Upload { to, target, comment } => {
+            upload_image(&config, &to, &target, comment).await?;
+        }
+        Commands::Secret { action } => {
+            handle_secret_command(action).await?;
+        }
+        Commands::Rename { target, name, fix_clipboard } => {
+            rename_screenshot(&config, &target, name, fix_clipboard).await?;
+        }
+        Commands::ProcessFile { path, source } => {
+            // Detectors that already stored the same image get that copy back
+            let processor = ImageProcessor::new(config.clone()).await?;
+            println!("{}", processor.process_image_file(&path, &source).await?.display());
+        }
+        Commands::Remote { action } => {
+            handle_remote_command(&config, action).await?;
+        }
+        Commands::Extract { member } => {
+            let (archive_path, member) = archive::split_member(&member)
+                .ok_or_else(|| anyhow::anyhow!("Expected an archive member such as artifacts.zip::screenshots/fail.png"))?;
+            let runner = command_runner::system();
+            let extracted = archive::extract(runner.as_ref(), &archive_path, &member).await?;
+            let processor = ImageProcessor::new(config.clone()).await?;
+            println!("{}", processor.process_image_file(&extracted.to_path_buf(), "archive").await?.display());
+        }
+        Commands::Substitute { command } => {
+            substitute_command(&config, command).await?;
+        }
+        Commands::PrepareUpload { program, args } => {
+            // Nothing is printed when the arguments can be used as they are
+            match upload::prepare_args(&config, &program, &args).await {
+                Ok(Some(args)) => {
+                    let quoted: Vec<_> = args.iter().map(|arg| substitution::shell_quote(arg)).collect();
+                    println!("{}", quoted.join(" "));
+                }
+                Ok(None) => {}
+                Err(e @ klipdot::Error::Permission(_)) => {
+                    eprintln!("{}", output::decorate("❌", e.to_string()));
+                    std::process::exit(klipdot::policy::FORBIDDEN_EXIT_CODE);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Commands::ScanNew { dirs } => {
+            for path in scan_new_images(&config, dirs).await? {
+                println!("{}", path.display());
+            }
+        }
+        Commands::CompletePaths { prefix, limit } => {
+            for path in completion::complete_paths(&config, &prefix, limit).await? {
+                println!("{}", path.display());
+            }
+        }
         Commands::Install { shell } => {
-            install_hooks(shell).await?;
+            install_hooks(&config, shell).await?;
         }
         Commands::Uninstall => {
             uninstall_hooks().await?;
         }
+        Commands::Man { command, dir } => {
+            handle_man_command(command, dir).await?;
+        }
+        Commands::Share { target, ttl } => {
+            share_image(&config, &target, &ttl).await?;
+        }
+        Commands::Report { output } => {
+            write_report(&config, output).await?;
+        }
         Commands::Cleanup { days } => {
             cleanup_screenshots(&config, days).await?;
         }
+        Commands::History { action } => {
+            handle_history_command(&config, action.unwrap_or(HistoryAction::List { limit: 20 })).await?;
+        }
+        Commands::Audit { action } => {
+            handle_audit_command(&config, action)?;
+        }
+        Commands::Freeze { days, format, dry_run } => {
+            freeze_screenshots(&config, days, format, dry_run).await?;
+        }
+        Commands::Reprocess { filter, dry_run } => {
+            reprocess_screenshots(&config, filter.as_deref(), dry_run).await?;
+        }
         Commands::Config { action } => {
             handle_config_command(action, &config).await?;
         }
-        Commands::Preview { image_path, width, height } => {
+        #[cfg(feature = "preview")]
+        Commands::Preview { image_path, line, cursor, width, height } => {
+            let image_path = match (image_path, line) {
+                (Some(image_path), _) => image_path,
+                (None, Some(line)) => image_path_at_cursor(&line, cursor)?,
+                (None, None) => unreachable!("clap requires an image path or --line"),
+            };
             handle_preview_command(&config, &image_path, width, height).await?;
         }
+        #[cfg(feature = "preview")]
         Commands::MonitorOutput { command } => {
             handle_monitor_output_command(&config, command).await?;
         }
+        #[cfg(all(feature = "preview", feature = "file-watch"))]
+        Commands::WatchDiff { output, watch, command } => {
+            let watch = if watch.is_empty() { vec![std::env::current_dir()?] } else { watch };
+            klipdot::watch_diff::WatchDiff::new(command, output)?.run(&config, &watch).await?;
+        }
+        #[cfg(feature = "preview")]
         Commands::PreviewStdin => {
             handle_preview_stdin_command(&config).await?;
         }
+        #[cfg(feature = "preview")]
         Commands::LivePreview { auto_preview } => {
             handle_live_preview_command(&config, auto_preview).await?;
         }
+        #[cfg(feature = "preview")]
         Commands::Tui { command } => {
             handle_tui_command(&config, command).await?;
         }
@@ -176,18 +807,357 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+async fn substitute_command(config: &Config, command: Vec<String>) -> Result<()> {
+    let engine = SubstitutionEngine::new(config.clone()).await?;
+
+    // A single argument is a full command line from a hook; otherwise the
+    // shell has already split the words for us
+    let line = if let [line] = command.as_slice() {
+        engine.rewrite_command_line(line).await?.command_line
+    } else {
+        engine
+            .rewrite_args(&command)
+            .await?
+            .iter()
+            .map(|arg| substitution::shell_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    println!("{}", line);
+    Ok(())
+}
+
+async fn scan_new_images(config: &Config, dirs: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+    // Relative directories belong to the hook's shell, not to the daemon
+    let cwd = std::env::current_dir()?;
+    let dirs: Vec<PathBuf> = dirs.iter().map(|dir| cwd.join(dir)).collect();
+    
+    // The daemon already has a processor running, so let it do the scan
+    #[cfg(unix)]
+    if let Some(socket_path) = ipc::default_socket_path().ok().filter(|path| path.exists()) {
+        match ipc::request(&socket_path, &ipc::Request::ScanNew { dirs: dirs.clone() }).await {
+            Ok(ipc::Response::Scanned { stored }) => return Ok(stored),
+            Ok(response) => tracing::debug!("Daemon can't scan, scanning here: {:?}", response),
+            Err(e) => tracing::debug!("Daemon unavailable, scanning here: {}", e),
+        }
+    }
+    
+    Ok(klipdot::hook_scan::scan_new(config, &klipdot::get_home_dir()?, &dirs).await?)
+}
+
+async fn handle_remote_command(config: &Config, action: RemoteAction) -> Result<()> {
+    match action {
+        RemoteAction::Serve { dirs: mut watched, interval } => {
+            if watched.is_empty() {
+                watched.extend(config.screenshot_dirs());
+                watched.extend(dirs::home_dir());
+            }
+            remote::serve(watched, std::time::Duration::from_millis(interval), &mut tokio::io::stdout()).await?;
+        }
+        RemoteAction::Connect { destination, dirs, copy_path } => {
+            let clipboard = if copy_path {
+                Some(ClipboardMonitor::new(config.clone()).await?)
+            } else {
+                None
+            };
+            let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+            let connection = remote::connect(config, &destination, &dirs, |remote_path, stored| {
+                println!("{}:{} -> {}", destination, remote_path, stored.display());
+                let _ = sender.send(stored.to_path_buf());
+            });
+            
+            // Clipboard writes happen here so they don't hold up the stream
+            let copy_paths = async {
+                while let Some(stored) = receiver.recv().await {
+                    if let Some(clipboard) = &clipboard {
+                        if let Err(e) = clipboard.set_text(&path_format::for_clipboard(config, &stored, None).await).await {
+                            error!("Failed to copy {} to the clipboard: {}", stored.display(), e);
+                        }
+                    }
+                }
+            };
+            tokio::select! {
+                result = connection => result?,
+                _ = copy_paths => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn run_sink(config: &Config, name: &str, stdin: bool, preview: bool) -> Result<()> {
+    if !config.intercept_methods.stdin {
+        return Err(anyhow::anyhow!("Sinks are disabled; turn them on with `klipdot enable stdin`"));
+    }
+    let sink = sink::Sink::new(config, name, preview).await?;
+    if stdin {
+        let stored = sink.run(sink::Input::Stdin).await?;
+        output::status("✅", format!("Stored {} image{}", stored, if stored == 1 { "" } else { "s" }));
+        return Ok(());
+    }
+
+    let path = sink::fifo_path(&klipdot::get_home_dir()?, name)?;
+    sink::create_fifo(&path)?;
+    output::status("🚰", format!("Sink {} is open; write images to {}", name, path.display()));
+    tokio::select! {
+        result = sink.run(sink::Input::Fifo(path.clone())) => { result?; }
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    let _ = std::fs::remove_file(&path);
+    Ok(())
+}
+
+async fn print_snippet(config: &Config, target: &str, alt: Option<String>) -> Result<()> {
+    let path = paste_image::resolve(config, target).await?;
+    let alt = image_alt(config, &path, alt).await?;
+    println!("{}", alt_text::markdown(&alt, &path));
+    Ok(())
+}
+
+/// `alt`, or else the alt text recorded or generated for the stored image at `path`
+async fn image_alt(config: &Config, path: &Path, alt: Option<String>) -> Result<String> {
+    let recorded = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(filename)) => klipdot::metadata::load(dir).await?.remove(filename.to_string_lossy().as_ref()),
+        _ => None,
+    };
+    
+    let alt = match alt.or_else(|| recorded.as_ref().and_then(|entry| entry.alt_text.clone())) {
+        Some(alt) => alt,
+        // Not described yet, e.g. stored before alt text was enabled
+        None if config.alt_text.enabled => {
+            let runner = command_runner::system();
+            alt_text::generate_and_record(runner.as_ref(), &config.alt_text, path).await?
+        }
+        None => match recorded.and_then(|entry| entry.app) {
+            Some(app) => format!("Screenshot of {}", app),
+            None => "Screenshot".to_string(),
+        },
+    };
+    Ok(alt)
+}
+
+async fn attach_image(
+    config: &Config,
+    target: &str,
+    repo: Option<String>,
+    forge: Option<attach::Forge>,
+    branch: &str,
+    alt: Option<String>,
+    url_only: bool,
+) -> Result<()> {
+    let path = paste_image::resolve(config, target).await?;
+    let runner = command_runner::system();
+    let repo = match repo {
+        Some(spec) => attach::Repo::parse(&spec, forge).ok_or_else(|| anyhow::anyhow!("Not a repository: {:?}", spec))?,
+        None => attach::Repo::detect(runner.as_ref(), forge).await?,
+    };
+    let token = match repo.forge {
+        attach::Forge::Gitlab => attach::gitlab_token(runner.as_ref(), &repo.host).await,
+        attach::Forge::Github => None,
+    };
+    
+    let service = match repo.forge {
+        attach::Forge::Github => "github",
+        attach::Forge::Gitlab => "gitlab",
+    };
+    config.policy.check_upload(service, &repo.host)?;
+    let copy = upload::policy_copy(config, &path).await?;
+    let result = attach::upload(runner.as_ref(), &config.retry, &repo, copy.as_deref().unwrap_or(&path), branch, token.as_deref()).await;
+    remove_policy_copy(copy).await;
+    let attachment = result?;
+    output::status("✅", format!("Uploaded {} to {}/{}", path.display(), repo.host, repo.path));
+    if url_only {
+        println!("{}", attachment.url);
+    } else {
+        let alt = image_alt(config, &path, alt).await?;
+        println!("{}", alt_text::markdown_link(&alt, &attachment.link));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "preview")]
+async fn annotate_image(config: &Config, target: &str) -> Result<()> {
+    let path = paste_image::resolve(config, target).await?;
+    let img = klipdot::tone_map::prepare(klipdot::decode_guard::open(&path, &config.decode_limits)?, klipdot::config::BitDepth::Reduce);
+
+    let Some(markup) = annotate::run(&img)? else {
+        output::status("↩️", "Nothing saved");
+        return Ok(());
+    };
+    let mut png = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgba8(markup.apply(&img)).write_to(&mut png, image::ImageOutputFormat::Png)?;
+
+    let processor = ImageProcessor::new(config.clone()).await?;
+    let annotated = processor.process_image_data(png.get_ref(), "annotation").await?;
+    output::status("✅", format!("Saved the annotated image as {}", annotated.display()));
+
+    let monitor = ClipboardMonitor::new(config.clone()).await?;
+    match monitor.set_text(&path_format::for_clipboard(config, &annotated, None).await).await {
+        Ok(()) => output::status("📋", "Copied its path to the clipboard"),
+        Err(e) => error!("Failed to copy {} to the clipboard: {}", annotated.display(), e),
+    }
+    println!("{}", annotated.display());
+    Ok(())
+}
+
+#[cfg(feature = "preview")]
+async fn pick_color(config: &Config, target: &str) -> Result<()> {
+    let path = paste_image::resolve(config, target).await?;
+    let img = klipdot::tone_map::prepare(klipdot::decode_guard::open(&path, &config.decode_limits)?, klipdot::config::BitDepth::Reduce);
+
+    let Some(color) = color_picker::run(&img)? else {
+        output::status("↩️", "No color picked");
+        return Ok(());
+    };
+    let hex = color_picker::hex(color);
+    let monitor = ClipboardMonitor::new(config.clone()).await?;
+    match monitor.set_text(&hex).await {
+        Ok(()) => output::status("📋", format!("Copied {} to the clipboard", hex)),
+        Err(e) => error!("Failed to copy {} to the clipboard: {}", hex, e),
+    }
+    println!("{}", hex);
+    Ok(())
+}
+
+async fn export_ansi(config: &Config, target: &str, cols: u32, format: AnsiFormat, output: Option<PathBuf>) -> Result<()> {
+    let path = paste_image::resolve(config, target).await?;
+    let runner = command_runner::system();
+    let exported = ansi_export::export(runner.as_ref(), &path, format, cols, &config.decode_limits).await?;
+
+    let output = output.unwrap_or_else(|| {
+        let stem = path.file_stem().unwrap_or_default();
+        PathBuf::from(stem).with_extension(format.extension())
+    });
+    if output == Path::new("-") {
+        use std::io::Write;
+        std::io::stdout().write_all(&exported)?;
+        return Ok(());
+    }
+    tokio::fs::write(&output, &exported).await?;
+    output::status("✅", format!("Exported {} to {} (show it with `cat`)", path.display(), output.display()));
+    println!("{}", output.display());
+    Ok(())
+}
+
+/// Remove the directory of a copy made by [`upload::policy_copy`]
+async fn remove_policy_copy(copy: Option<PathBuf>) {
+    if let Some(dir) = copy.as_deref().and_then(Path::parent) {
+        let _ = tokio::fs::remove_dir_all(dir).await;
+    }
+}
+
+async fn upload_image(config: &Config, to: &str, target: &str, comment: Option<String>) -> Result<()> {
+    let destination = chat_upload::Destination::parse(to)
+        .ok_or_else(|| anyhow::anyhow!("Unknown upload target {:?}; expected slack:#channel or discord[:name]", to))?;
+    config.policy.check_upload(destination.service(), destination.host())?;
+    let path = paste_image::resolve(config, target).await?;
+    let runner = command_runner::system();
+
+    let copy = upload::policy_copy(config, &path).await?;
+    let result = chat_upload::upload(runner.as_ref(), &config.retry, &destination, copy.as_deref().unwrap_or(&path), comment.as_deref()).await;
+    remove_policy_copy(copy).await;
+    let link = result?;
+    output::status("✅", format!("Uploaded {} to {}", path.display(), to));
+    println!("{}", link);
+    Ok(())
+}
+
+async fn handle_secret_command(action: SecretAction) -> Result<()> {
+    let runner = command_runner::system();
+    match action {
+        SecretAction::Set { name } => {
+            if std::io::stdin().is_terminal() {
+                eprint!("Value for {}: ", name);
+            }
+            let mut value = String::new();
+            std::io::stdin().read_line(&mut value)?;
+            let value = value.trim();
+            if value.is_empty() {
+                anyhow::bail!("No value given for secret {}", name);
+            }
+            secrets::set(runner.as_ref(), &name, value).await?;
+            output::status("🔑", format!("Stored secret {}", name));
+        }
+        SecretAction::Delete { name } => {
+            secrets::delete(runner.as_ref(), &name).await?;
+            output::status("🗑️", format!("Removed secret {}", name));
+        }
+    }
+    Ok(())
+}
+
+async fn rename_screenshot(config: &Config, target: &str, name: Option<String>, fix_clipboard: bool) -> Result<()> {
+    let path = paste_image::resolve(config, target).await?;
+    let name = match name {
+        Some(name) => name,
+        None => {
+            let data = tokio::fs::read(&path).await?;
+            let runner = command_runner::system();
+            rename::auto_slug(runner.as_ref(), klipdot::config::AutoSlug::Ocr, &data)
+                .await
+                .ok_or_else(|| anyhow::anyhow!("No text recognized in {} (is tesseract installed?); give a name", path.display()))?
+        }
+    };
+    
+    let renamed = rename::rename_image(&path, &name).await?;
+    output::status("✅", format!("Renamed {} to {}", path.display(), renamed.display()));
+    
+    if fix_clipboard {
+        let monitor = ClipboardMonitor::new(config.clone()).await?;
+        let old = path_format::for_clipboard(config, &path, None).await;
+        let new = path_format::for_clipboard(config, &renamed, None).await;
+        if monitor.replace_text(&old, &new).await? {
+            output::status("📋", "Clipboard updated to the new path");
+        }
+    }
+    Ok(())
+}
+
 async fn start_foreground(config: &Config) -> Result<()> {
+    // Dropped on the way out, removing the socket and PID file
+    let _run_state = match run_state::claim()? {
+        Claimed::Owned(state) => state,
+        Claimed::Running(pid) => {
+            output::status("ℹ️", format!("{}, showing its status", instance_lock::already_running(pid)));
+            return show_status(config).await;
+        }
+    };
     info!("Starting KlipDot in foreground mode");
+    error_history::persist_to(error_history::default_history_path()?);
+    intercept_switches::start(&config.intercept_methods);
     
     let mut interceptor = TerminalInterceptor::new(config.clone()).await?;
     let mut clipboard_monitor = ClipboardMonitor::new(config.clone()).await?;
     
-    // Handle shutdown gracefully
-    let shutdown_signal = async {
-        tokio::signal::ctrl_c()
-            .await
-            .expect("Failed to install CTRL+C signal handler");
-    };
+    // Let the clipboard monitor attribute clipboard-only captures to screenshot tools
+    interceptor.set_event_bus(clipboard_monitor.event_bus());
+    
+    // Serve preview and other requests from hooks and editor plugins
+    #[cfg(unix)]
+    {
+        let server = ipc::IpcServer::new(ipc::default_socket_path()?).with_config(config.clone());
+        tokio::spawn(async move {
+            if let Err(e) = server.run().await {
+                error!("IPC server error: {}", e);
+                error_history::record_error("ipc", &e);
+            }
+        });
+    }
+    
+    #[cfg(feature = "preview")]
+    if config.preview.on_intercept {
+        tokio::spawn(klipdot::intercept_preview::preview_intercepted(
+            config.clone(),
+            command_runner::system(),
+            clipboard_monitor.event_bus(),
+        ));
+    }
+    
+    let downloads = watch_downloads(config, clipboard_monitor.event_bus());
+    
+    tokio::spawn(klipdot::policy::enforce_retention(config.clone()));
+    tokio::spawn(klipdot::share::expire_shares(config.clone()));
     
     tokio::select! {
         result = interceptor.run() => {
@@ -195,12 +1165,17 @@ async fn start_foreground(config: &Config) -> Result<()> {
                 error!("Terminal interceptor error: {}", e);
             }
         }
+        result = downloads => {
+            if let Err(e) = result {
+                error!("Downloads watcher error: {}", e);
+            }
+        }
         result = clipboard_monitor.run() => {
             if let Err(e) = result {
                 error!("Clipboard monitor error: {}", e);
             }
         }
-        _ = shutdown_signal => {
+        _ = shutdown_signal() => {
             info!("Received shutdown signal, stopping KlipDot");
         }
     }
@@ -208,12 +1183,91 @@ async fn start_foreground(config: &Config) -> Result<()> {
     Ok(())
 }
 
+/// The clipboard monitor on its own, for shells started without the service
+async fn monitor_clipboard(config: &Config) -> Result<()> {
+    // The service runs a clipboard monitor too, and each shell starts one of these
+    let _run_state = match run_state::claim()? {
+        Claimed::Owned(state) => state,
+        Claimed::Running(pid) => {
+            output::status("ℹ️", instance_lock::already_running(pid));
+            return Ok(());
+        }
+    };
+    info!("Starting the clipboard monitor");
+    error_history::persist_to(error_history::default_history_path()?);
+    
+    let mut clipboard_monitor = ClipboardMonitor::new(config.clone()).await?;
+    tokio::select! {
+        result = clipboard_monitor.run() => result?,
+        _ = shutdown_signal() => info!("Received shutdown signal, stopping the clipboard monitor"),
+    }
+    Ok(())
+}
+
+/// Ctrl+C, or the SIGTERM `klipdot stop` sends; the run state is only cleaned
+/// up if these are handled rather than ending the process
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.expect("Failed to install CTRL+C signal handler"),
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to install CTRL+C signal handler");
+}
+
+/// Intercept browser downloads when enabled; never finishes otherwise
+async fn watch_downloads(config: &Config, events: klipdot::events::EventBus) -> klipdot::error::Result<()> {
+    #[cfg(feature = "file-watch")]
+    {
+        // `intercept_methods.file_watch` is checked as downloads arrive, see `klipdot enable`
+        if config.downloads.enabled {
+            let mut watcher = klipdot::downloads::DownloadWatcher::new(config.clone()).await?;
+            watcher.set_event_bus(events);
+            return watcher.run().await;
+        }
+    }
+    #[cfg(not(feature = "file-watch"))]
+    let _ = (config, events);
+    
+    std::future::pending().await
+}
+
 async fn start_daemon(config: &Config) -> Result<()> {
     info!("Starting KlipDot in daemon mode");
     ServiceManager::start_daemon(config.clone()).await
         .map_err(|e| anyhow::anyhow!("Failed to start daemon: {}", e))
 }
 
+/// Save a source's switch to the configuration and flip it in the running instance
+async fn switch_source(config: &Config, source: InterceptSource, enabled: bool) -> Result<()> {
+    // Changed in the user's own settings, so the policy's aren't saved with them
+    let mut updated = Config::load_user_config(&config.config_file)?;
+    source.set(&mut updated.intercept_methods, enabled);
+    if source.get(&config.policy.apply(updated.clone())?.intercept_methods) != enabled {
+        return Err(anyhow::anyhow!("The system policy sets {} interception", source.as_str()));
+    }
+    updated.update()?;
+    
+    let state = if enabled { "enabled" } else { "disabled" };
+    #[cfg(unix)]
+    {
+        let request = ipc::Request::SetSource { source, enabled };
+        if let Ok(ipc::Response::SourceSet { .. }) = ipc::request(&ipc::default_socket_path()?, &request).await {
+            output::status("✅", format!("{} interception {}", source.as_str(), state));
+            return Ok(());
+        }
+    }
+    output::status("✅", format!("{} interception {} from the next start", source.as_str(), state));
+    Ok(())
+}
+
 async fn show_status(config: &Config) -> Result<()> {
     let service_manager = ServiceManager::new();
     let status = service_manager.status().await?;
@@ -231,34 +1285,285 @@ async fn show_status(config: &Config) -> Result<()> {
     
     println!("Configuration: {:?}", config.screenshot_dir);
     
+    let disabled: Vec<_> = [InterceptSource::Clipboard, InterceptSource::Process, InterceptSource::Filewatch, InterceptSource::Stdin]
+        .into_iter()
+        .filter(|source| !source.get(&config.intercept_methods))
+        .map(InterceptSource::as_str)
+        .collect();
+    if !disabled.is_empty() {
+        println!("Disabled: {}", disabled.join(", "));
+    }
+    
+    if let Some(reason) = klipdot::pause::scheduled_pause(&config.pause, chrono::Local::now().naive_local()) {
+        println!("Interception: paused ({})", reason);
+    }
+    
+    if let Some(path) = &config.policy.path {
+        println!("Policy: {}", path.display());
+        if let Some(days) = config.policy.max_retention_days {
+            println!("  retention: at most {} days", days);
+        }
+        if !config.policy.forbidden_upload_targets.is_empty() {
+            println!("  forbidden uploads: {}", config.policy.forbidden_upload_targets.join(", "));
+        }
+    }
+    
+    // Queue counters live in the daemon, so they are only available while it runs
+    #[cfg(unix)]
+    if let Ok(ipc::Response::Stats { processing, storage_fallback, retries }) = ipc::request(&ipc::default_socket_path()?, &ipc::Request::Stats).await {
+        if let Some(dir) = storage_fallback {
+            println!("Storage: screenshot directory unwritable, saving to {:?}", dir);
+        }
+        println!(
+            "Processing queue: {} pending, {} done, {} failed, {} rejected",
+            processing.depth, processing.completed, processing.failed, processing.rejected
+        );
+        if let Some(average) = processing.average_latency() {
+            println!("  latency: {:?} average, {}ms max", average, processing.max_latency_ms);
+        }
+        if !retries.is_empty() {
+            println!("Retries:");
+            for (name, counters) in &retries {
+                println!("  {}: {} calls, {} retries, {} gave up", name, counters.calls, counters.retries, counters.exhausted);
+            }
+        }
+    }
+    
     // Show recent screenshots
     let screenshots = config.get_recent_screenshots(5).await?;
     println!("Recent screenshots: {}", screenshots.len());
     
     for (i, screenshot) in screenshots.iter().enumerate() {
-        println!("  {}. {} ({})", i + 1, screenshot.filename, screenshot.size);
+        println!(
+            "  {}. {} ({}, {})",
+            i + 1,
+            screenshot.filename,
+            klipdot::format_file_size(screenshot.size),
+            klipdot::format_relative_time(screenshot.created_at, chrono::Utc::now())
+        );
+    }
+    
+    // Show recent errors so missed interceptions can be diagnosed without the logs
+    // A damaged history file shouldn't stop the rest of the status from showing
+    let errors = ErrorHistory::load(&error_history::default_history_path()?).unwrap_or_else(|e| {
+        output::status("⚠️", format!("Couldn't read the error history: {}", e));
+        Vec::new()
+    });
+    let skipped: Vec<_> = errors
+        .iter()
+        .filter(|record| record.subsystem == klipdot::image_processor::SKIPPED_SUBSYSTEM)
+        .collect();
+    if !skipped.is_empty() {
+        println!("Skipped images: {}", skipped.len());
+        for record in skipped.iter().rev().take(5) {
+            println!("  {} {}", klipdot::format_local_time(record.timestamp), record.message);
+        }
+    }
+    
+    println!("Recent errors: {}", errors.len());
+    
+    for record in errors.iter().rev().take(5) {
+        println!(
+            "  {} [{}] {}: {}",
+            klipdot::format_local_time(record.timestamp),
+            record.code,
+            record.subsystem,
+            record.message
+        );
+    }
+    
+    Ok(())
+}
+
+async fn list_screenshots(config: &Config, recent: usize, source: Option<&str>, app: Option<&str>) -> Result<()> {
+    // Filtering needs the whole directory; otherwise only the newest entries are read
+    let candidates = if source.is_some() || app.is_some() { usize::MAX } else { recent };
+    let screenshots = config.get_recent_screenshots(candidates).await?;
+    
+    for screenshot in screenshots.iter().filter(|s| s.matches(source, app)).take(recent) {
+        print_screenshot(screenshot);
+    }
+    
+    Ok(())
+}
+
+fn print_screenshot(screenshot: &klipdot::config::Screenshot) {
+    let mut origin = match &screenshot.app {
+        Some(app) => format!("{} ({})", screenshot.source, app),
+        None => screenshot.source.clone(),
+    };
+    if let Some(output) = &screenshot.output {
+        origin.push_str(&format!(" on {}", output));
+    }
+    if let Some(window) = &screenshot.window {
+        if let Some(app_id) = &window.app_id {
+            origin.push_str(&format!(", {} focused", app_id));
+        }
+        if let Some(workspace) = &window.workspace {
+            origin.push_str(&format!(", workspace {}", workspace));
+        }
+    }
+    if let Some((width, height)) = screenshot.resized_from {
+        origin.push_str(&format!(", resized from {}x{}", width, height));
+    }
+    if !screenshot.tags.is_empty() {
+        origin.push_str(&format!(" [{}]", screenshot.tags.join(", ")));
+    }
+    println!(
+        "{}  {}  {}  {}",
+        klipdot::format_local_time(screenshot.created_at),
+        screenshot.path.display(),
+        origin,
+        klipdot::format_file_size(screenshot.size)
+    );
+}
+
+async fn search_screenshots(config: &Config, query: &str, limit: Option<usize>) -> Result<()> {
+    let query = search::Query::parse(query)?;
+    let screenshots = config.get_recent_screenshots(usize::MAX).await?;
+    
+    let matches: Vec<_> = screenshots.iter().filter(|s| query.matches(s)).take(limit.unwrap_or(usize::MAX)).collect();
+    if matches.is_empty() {
+        output::status("🔍", "No matching images");
+    }
+    for screenshot in matches {
+        print_screenshot(screenshot);
+    }
+    Ok(())
+}
+
+async fn tag_screenshot(config: &Config, target: &str, add: &[String], remove: &[String]) -> Result<()> {
+    let path = paste_image::resolve(config, target).await?;
+    let (Some(dir), Some(filename)) = (path.parent(), path.file_name()) else {
+        return Err(anyhow::anyhow!("Not an image file: {}", path.display()));
+    };
+    let tags = klipdot::metadata::retag(dir, &filename.to_string_lossy(), add, remove)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("{} isn't a stored image with metadata, so it can't be tagged", path.display()))?;
+    
+    if tags.is_empty() {
+        output::status("🏷️", format!("{} has no tags", path.display()));
+    } else {
+        output::status("🏷️", format!("Tagged {}", path.display()));
+        println!("{}", tags.join(" "));
+    }
+    Ok(())
+}
+
+async fn capture_screenshot(
+    config: &Config,
+    region: Option<String>,
+    window: Option<WindowSelection>,
+    output: Option<String>,
+    tool: Option<String>,
+    delay: Option<(std::time::Duration, bool)>,
+) -> Result<()> {
+    let runner = command_runner::system();
+    
+    let mode = match (window, region, output) {
+        (Some(selection), _, _) => match window_target::locate(runner.as_ref(), config.get_display_server(), selection).await? {
+            Some(WindowTarget::Region(region)) => CaptureMode::Region(Some(region)),
+            Some(WindowTarget::Id(id)) => CaptureMode::WindowId(id),
+            // The screenshot tool finds the window itself
+            None => CaptureMode::Window,
+        },
+        (_, _, Some(name)) => {
+            let monitors = monitors::list_monitors(runner.as_ref(), config.get_display_server()).await?;
+            let monitor = if name.is_empty() {
+                pick_monitor(&monitors)?
+            } else {
+                monitors::find_monitor(&monitors, &name)?
+            };
+            CaptureMode::Output(monitor.clone())
+        }
+        (_, Some(region), _) if region.is_empty() => CaptureMode::Region(None),
+        (_, Some(region), _) => CaptureMode::Region(Some(region.parse()?)),
+        (None, None, None) => CaptureMode::Full,
+    };
+    
+    let tool = match tool {
+        Some(name) => screenshot::find_tool(&name)
+            .ok_or_else(|| anyhow::anyhow!("Unsupported screenshot tool: {}", name))?,
+        None => screenshot::select_tool_for(config, runner.as_ref(), &mode)?,
+    };
+    
+    if let Some((delay, beep)) = delay {
+        // Only draw the countdown for a person watching
+        let mut stderr = std::io::stderr();
+        let mut sink = std::io::sink();
+        let out: &mut dyn std::io::Write = if stderr.is_terminal() { &mut stderr } else { &mut sink };
+        screenshot::countdown(delay, beep, out).await?;
     }
     
+    info!("Capturing {} with {}", mode, tool.name());
+    
+    let temp_dir = temp_files::dir()?;
+    let temp_path = temp_dir.join(format!("capture-{}.png", uuid::Uuid::new_v4()));
+    
+    screenshot::capture(tool.as_ref(), runner.as_ref(), &mode, &temp_path).await?;
+    
+    let processor = ImageProcessor::new(config.clone()).await?;
+    let output = match &mode {
+        CaptureMode::Output(monitor) => Some(monitor.name.as_str()),
+        _ => None,
+    };
+    let result = processor.process_image_file_from(&temp_path, "capture", Some(tool.name()), output, None).await;
+    let _ = tokio::fs::remove_file(&temp_path).await;
+    
+    println!("{}", result?.display());
+    
     Ok(())
 }
 
-async fn install_hooks(shell: Option<String>) -> Result<()> {
+/// Ask on the terminal which monitor to capture; a single monitor is used as is
+fn pick_monitor(monitors: &[Monitor]) -> Result<&Monitor> {
+    use std::io::{BufRead, Write};
+    
+    if let [monitor] = monitors {
+        return Ok(monitor);
+    }
+    
+    for (number, monitor) in monitors.iter().enumerate() {
+        eprintln!("{:>3}  {}", number + 1, monitor);
+    }
+    eprint!("Monitor to capture [1-{}]: ", monitors.len());
+    std::io::stderr().flush()?;
+    
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    let answer = answer.trim();
+    
+    // Accept the number from the list or an output name
+    match answer.parse::<usize>().ok().and_then(|number| monitors.get(number.checked_sub(1)?)) {
+        Some(monitor) => Ok(monitor),
+        None => Ok(monitors::find_monitor(monitors, answer)?),
+    }
+}
+
+async fn install_hooks(config: &Config, shell: Option<String>) -> Result<()> {
     info!("Installing KlipDot shell hooks");
     
     let shell = shell.unwrap_or_else(|| {
         std::env::var("SHELL")
             .unwrap_or_else(|_| "/bin/bash".to_string())
             .split('/')
-            .last()
+            .next_back()
             .unwrap_or("bash")
             .to_string()
     });
     
-    let installer = klipdot::installer::ShellInstaller::new(&shell);
+    let installer = klipdot::installer::ShellInstaller::new(&shell)
+        .with_config(&config.shell_integration);
     installer.install().await?;
     
-    println!("✅ Shell hooks installed for {}", shell);
-    println!("Please restart your shell or run: source ~/.{}rc", shell);
+    output::status("✅", format!("Shell hooks installed for {}", shell));
+    if let Some(man_dir) = man::user_man_dir() {
+        match man::write_pages(&Args::command(), &man_dir).await {
+            Ok(_) => output::status("📖", format!("Manual pages installed in {}", man_dir.display())),
+            Err(e) => error!("Failed to install manual pages: {}", e),
+        }
+    }
+    output::status("", format!("Please restart your shell or run: source ~/.{}rc", shell));
     
     Ok(())
 }
@@ -268,22 +1573,207 @@ async fn uninstall_hooks() -> Result<()> {
     
     let installer = klipdot::installer::ShellInstaller::detect_shell();
     installer.uninstall().await?;
+    if let Some(man_dir) = man::user_man_dir() {
+        man::remove_pages(&Args::command(), &man_dir).await?;
+    }
+    
+    output::status("✅", "Shell hooks uninstalled");
+    output::status("", "Please restart your shell to complete removal");
+    
+    Ok(())
+}
+
+async fn handle_man_command(command: Vec<String>, dir: Option<PathBuf>) -> Result<()> {
+    if let Some(dir) = dir {
+        let written = man::write_pages(&Args::command(), &dir).await?;
+        output::status("📖", format!("Wrote {} manual pages to {}", written.len(), dir.display()));
+        return Ok(());
+    }
     
-    println!("✅ Shell hooks uninstalled");
-    println!("Please restart your shell to complete removal");
+    let words: Vec<_> = std::iter::once("klipdot".to_string()).chain(command).collect();
+    let page = words.join("-");
+    let (page, cmd) = man::pages(&Args::command())
+        .into_iter()
+        .find(|(name, _)| *name == page)
+        .ok_or_else(|| anyhow::anyhow!("No manual page for {}", words.join(" ")))?;
+    print!("{}", man::render(&page, &cmd));
     
     Ok(())
 }
 
+async fn share_image(config: &Config, target: &str, ttl: &str) -> Result<()> {
+    let ttl = klipdot::share::parse_ttl(ttl)?;
+    let path = paste_image::resolve(config, target).await?;
+    let now = chrono::Utc::now();
+    klipdot::share::expire(config, now).await?;
+    let (copy, share) = klipdot::share::add(config, &path, ttl, now).await?;
+    output::status("🔗", format!(
+        "Shared {} until {}",
+        path.display(),
+        share.expires_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S")
+    ));
+    if !ServiceManager::new().status().await?.running {
+        output::status("ℹ️", "KlipDot isn't running; the copy is deleted once it runs after the TTL is over");
+    }
+    println!("{}", copy.display());
+    Ok(())
+}
+
+async fn write_report(config: &Config, file: Option<PathBuf>) -> Result<()> {
+    let report = report::generate(config).await?;
+    let path = file.unwrap_or_else(|| PathBuf::from(report::file_name(chrono::Local::now())));
+    std::fs::write(&path, report)?;
+    output::status("📋", format!("Wrote {}; read it before attaching it to a bug report", path.display()));
+    println!("{}", path.display());
+    Ok(())
+}
+
 async fn cleanup_screenshots(config: &Config, days: u32) -> Result<()> {
     info!("Cleaning up screenshots older than {} days", days);
     
     let count = config.cleanup_old_screenshots(days).await?;
-    println!("✅ Cleaned up {} old screenshots", count);
+    output::status("✅", format!("Cleaned up {} old screenshots", count));
     
     Ok(())
 }
 
+async fn handle_history_command(config: &Config, action: HistoryAction) -> Result<()> {
+    let history = clipboard_history::history_path(&config.history)?;
+    match action {
+        HistoryAction::List { limit } => {
+            let entries = clipboard_history::entries(&history)?;
+            if entries.is_empty() {
+                output::status("📋", "The clipboard history is empty");
+            }
+            for entry in &entries[entries.len().saturating_sub(limit)..] {
+                let origin = match &entry.app {
+                    Some(app) => format!("{} ({})", entry.source, app),
+                    None => entry.source.clone(),
+                };
+                println!(
+                    "{:>5}  {}  {}  {}",
+                    entry.id,
+                    klipdot::format_local_time(entry.timestamp),
+                    origin,
+                    entry.summary(60)
+                );
+            }
+        }
+        HistoryAction::Restore { id } => {
+            let entry = clipboard_history::find(&history, id)?;
+            clipboard_history::restore(config, &entry).await?;
+            output::status("✅", format!("Copied entry {} to the clipboard: {}", id, entry.summary(60)));
+        }
+        HistoryAction::Clear => {
+            clipboard_history::clear(&history)?;
+            output::status("🧹", "Cleared the clipboard history");
+        }
+    }
+    Ok(())
+}
+
+fn handle_audit_command(config: &Config, action: AuditAction) -> Result<()> {
+    let log = audit::log_path(&config.audit)?;
+    match action {
+        AuditAction::Verify => {
+            let verification = audit::verify(&log)?;
+            if let Some(problem) = verification.problem {
+                return Err(anyhow::anyhow!("Audit log {} is broken after {} entries. {}", log.display(), verification.valid, problem));
+            }
+            output::status("✅", format!("{} audit entries, chain intact", verification.valid));
+            if let Some(head) = verification.head {
+                println!("{}", head);
+            }
+        }
+        AuditAction::Export { format, output } => {
+            let exported = audit::export(&audit::entries(&log)?, format)?;
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, exported)?;
+                    output::status("✅", format!("Exported the audit log to {}", path.display()));
+                }
+                None => print!("{}", exported),
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn freeze_screenshots(config: &Config, days: Option<u32>, format: Option<ColdFormat>, dry_run: bool) -> Result<()> {
+    let settings = ColdStorageConfig {
+        after_days: days.unwrap_or(config.cold_storage.after_days),
+        format: format.unwrap_or(config.cold_storage.format),
+        ..config.cold_storage.clone()
+    };
+    let candidates = cold_storage::candidates(config, settings.format, settings.after_days, chrono::Utc::now()).await?;
+    if candidates.is_empty() {
+        output::status("✅", format!("No images older than {} days to freeze", settings.after_days));
+        return Ok(());
+    }
+
+    if dry_run {
+        for screenshot in &candidates {
+            println!("{}", screenshot.path.display());
+        }
+        let bytes = candidates.iter().map(|screenshot| screenshot.size).sum();
+        output::status("🧊", format!("Would freeze {} images ({})", candidates.len(), klipdot::format_file_size(bytes)));
+        return Ok(());
+    }
+
+    let runner = command_runner::system();
+    let frozen = cold_storage::freeze(&*runner, &candidates, &settings).await?;
+    output::status(
+        "🧊",
+        format!(
+            "Froze {} of {} images: {} now take {}",
+            frozen.count,
+            candidates.len(),
+            klipdot::format_file_size(frozen.bytes_before),
+            klipdot::format_file_size(frozen.bytes_after)
+        ),
+    );
+    Ok(())
+}
+
+async fn reprocess_screenshots(config: &Config, filter: Option<&str>, dry_run: bool) -> Result<()> {
+    let query = filter.map(search::Query::parse).transpose()?;
+    let mut screenshots = config.get_recent_screenshots(usize::MAX).await?;
+    screenshots.retain(|screenshot| query.as_ref().is_none_or(|query| query.matches(screenshot)));
+    let candidates = reprocess::candidates(screenshots).await?;
+    if candidates.is_empty() {
+        output::status("✅", "No images to reprocess");
+        return Ok(());
+    }
+
+    if dry_run {
+        for screenshot in &candidates {
+            println!("{}", screenshot.path.display());
+        }
+        output::status("🔄", format!("Would reprocess {} images", candidates.len()));
+        return Ok(());
+    }
+
+    let total = candidates.len();
+    let reprocessed = reprocess::reprocess(config, &candidates, |i, screenshot| {
+        output::status("🔄", format!("[{}/{}] {}", i + 1, total, screenshot.filename));
+    })
+    .await?;
+    output::status(
+        "✅",
+        format!(
+            "Reprocessed {} of {} images: {} now take {}",
+            reprocessed.count,
+            total,
+            klipdot::format_file_size(reprocessed.bytes_before),
+            klipdot::format_file_size(reprocessed.bytes_after)
+        ),
+    );
+    if reprocessed.failed > 0 {
+        output::status("⚠️", format!("{} images couldn't be reprocessed; see the log for why", reprocessed.failed));
+    }
+    Ok(())
+}
+
 async fn handle_config_command(action: Option<ConfigAction>, config: &Config) -> Result<()> {
     match action.unwrap_or(ConfigAction::Show) {
         ConfigAction::Show => {
@@ -295,32 +1785,102 @@ async fn handle_config_command(action: Option<ConfigAction>, config: &Config) ->
             let config_path = config.get_config_path();
             
             std::process::Command::new(editor)
-                .arg(&config_path)
+                .arg(config_path)
                 .status()?;
                 
-            println!("Configuration edited: {:?}", config_path);
+            output::status("", format!("Configuration edited: {:?}", config_path));
         }
         ConfigAction::Reset => {
             Config::reset_to_default()?;
-            println!("✅ Configuration reset to default");
+            output::status("✅", "Configuration reset to default");
         }
     }
     
     Ok(())
 }
 
-async fn handle_preview_command(config: &Config, image_path: &PathBuf, width: Option<u32>, height: Option<u32>) -> Result<()> {
+#[cfg(feature = "preview")]
+async fn handle_preview_command(config: &Config, image_path: &std::path::Path, width: Option<u32>, height: Option<u32>) -> Result<()> {
+    // `artifacts.zip::screenshots/fail.png` previews one image from an archive
+    if let Some((archive_path, member)) = archive::split_member(&image_path.to_string_lossy()) {
+        let runner = command_runner::system();
+        let extracted = archive::extract(runner.as_ref(), &archive_path, &member).await?;
+        return preview_image_file(config, &extracted, width, height).await;
+    }
+    if archive::ArchiveKind::of(image_path).is_some() {
+        let runner = command_runner::system();
+        let images = archive::list_images(runner.as_ref(), image_path).await?;
+        if images.is_empty() {
+            output::status("", format!("No images in {}", image_path.display()));
+        }
+        for image in images {
+            println!("{}{}{}", image_path.display(), archive::MEMBER_SEPARATOR, image);
+        }
+        return Ok(());
+    }
+    
+    preview_image_file(config, image_path, width, height).await
+}
+
+#[cfg(feature = "preview")]
+async fn preview_image_file(config: &Config, image_path: &std::path::Path, width: Option<u32>, height: Option<u32>) -> Result<()> {
     info!("Showing preview for image: {:?}", image_path);
+    let (width, height) = config.preview.clamp(width, height);
     
     let preview_manager = ImagePreviewManager::new(config.clone()).await
         .map_err(|e| anyhow::anyhow!("Failed to create preview manager: {}", e))?;
     
+    // Reuse the daemon's rendering cache when it is running
+    #[cfg(unix)]
+    if let Some(output) = render_via_daemon(&preview_manager, image_path, width, height).await {
+        use std::io::Write;
+        let mut stdout = std::io::stdout();
+        stdout.write_all(&output)?;
+        stdout.flush()?;
+        return Ok(());
+    }
+    
     preview_manager.show_preview(image_path, width, height).await
         .map_err(|e| anyhow::anyhow!("Failed to show preview: {}", e))?;
     
     Ok(())
 }
 
+/// Ask the daemon to render the preview; `None` means render locally instead
+#[cfg(all(unix, feature = "preview"))]
+async fn render_via_daemon(preview_manager: &ImagePreviewManager, image_path: &std::path::Path, width: Option<u32>, height: Option<u32>) -> Option<Vec<u8>> {
+    // The daemon's previews aren't reduced for a slow link
+    if preview_manager.reduces_previews() {
+        return None;
+    }
+    let backend = preview_manager.backend_name()?.to_string();
+    let socket_path = ipc::default_socket_path().ok().filter(|path| path.exists())?;
+    // The daemon runs elsewhere, so relative paths must be resolved here
+    let path = std::fs::canonicalize(image_path).ok()?;
+    
+    let cell_size = preview_manager.cell_size().map(|cell_size| (cell_size.width, cell_size.height));
+    let request = ipc::Request::RenderPreview { path, backend, width, height, cell_size };
+    match ipc::request(&socket_path, &request).await.and_then(|response| response.preview_output()) {
+        Ok(output) => Some(output),
+        Err(e) => {
+            debug!("Daemon preview unavailable, rendering locally: {}", e);
+            None
+        }
+    }
+}
+
+/// Resolve the image path under a character cursor position (end of line by default)
+#[cfg(feature = "preview")]
+fn image_path_at_cursor(line: &str, cursor: Option<usize>) -> Result<PathBuf> {
+    let cursor = cursor
+        .and_then(|chars| line.char_indices().nth(chars).map(|(i, _)| i))
+        .unwrap_or(line.len());
+    
+    klipdot::image_preview::image_path_at(line, cursor)
+        .ok_or_else(|| anyhow::anyhow!("No image path under cursor"))
+}
+
+#[cfg(feature = "preview")]
 async fn handle_monitor_output_command(config: &Config, command: Vec<String>) -> Result<()> {
     let monitor = StdoutMonitor::new(config.clone()).await
         .map_err(|e| anyhow::anyhow!("Failed to create stdout monitor: {}", e))?;
@@ -336,24 +1896,33 @@ async fn handle_monitor_output_command(config: &Config, command: Vec<String>) ->
         for (line_num, line) in reader.lines().enumerate() {
             let line = line.map_err(|e| anyhow::anyhow!("Failed to read line: {}", e))?;
             println!("{}", line); // Echo the line
+            if !config.preview.auto_preview {
+                continue;
+            }
             
             // Detect images in this line
             let detected = monitor.detect_images_in_line(&line, line_num + 1);
             for image in detected {
-                println!("🖼️  Detected image: {}", image.path.display());
-                // Optionally show preview here
+                // stderr, so piped output passes through unchanged
+                output::status("🖼️ ", format!("Detected image: {}", image.path.display()));
             }
         }
     } else {
         // Monitor command output
         info!("Monitoring command: {:?}", command);
-        monitor.monitor_command(command).await
+        let status = monitor.monitor_command(command).await
             .map_err(|e| anyhow::anyhow!("Failed to monitor command: {}", e))?;
+        // Hooks wrap commands like pytest in this, so their exit status must
+        // come through for `&&` and CI
+        if !status.success() {
+            std::process::exit(status.code().unwrap_or(1));
+        }
     }
     
     Ok(())
 }
 
+#[cfg(feature = "preview")]
 async fn handle_preview_stdin_command(config: &Config) -> Result<()> {
     info!("Reading image data from stdin...");
     
@@ -376,38 +1945,28 @@ async fn handle_preview_stdin_command(config: &Config) -> Result<()> {
     Ok(())
 }
 
+#[cfg(feature = "preview")]
 async fn handle_live_preview_command(config: &Config, auto_preview: bool) -> Result<()> {
     info!("Starting LSP-style live preview mode (auto_preview: {})", auto_preview);
     
     let mut live_system = LivePreviewSystem::new(config.clone()).await
         .map_err(|e| anyhow::anyhow!("Failed to create live preview system: {}", e))?;
     
-    println!("🔍 Live Preview Mode Enabled");
-    println!("Type image paths and see previews in real-time!");
-    println!("Press Ctrl+C to exit");
-    
-    use std::io::{self, BufRead, BufReader};
-    
-    let stdin = io::stdin();
-    let reader = BufReader::new(stdin.lock());
-    
-    for line in reader.lines() {
-        let line = line.map_err(|e| anyhow::anyhow!("Failed to read line: {}", e))?;
-        
-        if auto_preview {
-            // Show live preview for the entire line
-            let cursor_pos = line.len(); // Assume cursor is at end
-            if let Err(e) = live_system.show_live_preview(&line, cursor_pos).await {
-                warn!("Failed to show live preview: {}", e);
-            }
-        }
-        
-        println!("Input: {}", line);
+    output::status("🔍", "Live Preview Mode Enabled");
+    if auto_preview {
+        output::status("", "Type image paths and see previews as you type!");
+    } else {
+        output::status("", "Type image paths and press Ctrl+P to preview the one under the cursor");
     }
+    output::status("", "Press Ctrl+C or Esc to exit");
+    
+    live_system.run(auto_preview).await
+        .map_err(|e| anyhow::anyhow!("Live preview failed: {}", e))?;
     
     Ok(())
 }
 
+#[cfg(feature = "preview")]
 async fn handle_tui_command(config: &Config, command: Vec<String>) -> Result<()> {
     if command.is_empty() {
         return Err(anyhow::anyhow!("No TUI command provided"));
@@ -0,0 +1,249 @@
+//! Manual pages rendered from the command-line definition.
+//!
+//! `klipdot man` prints the page for a command, or with `--dir` writes
+//! `klipdot.1` and a `klipdot-<command>.1` page for every subcommand, the
+//! way git names its pages. `klipdot install` writes them into the user's
+//! manpath so `man klipdot-capture` works without a package manager.
+
+use crate::error::Result;
+use clap::{Arg, ArgAction, Command};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// `~/.local/share/man/man1`, which man finds next to `~/.local/bin`
+pub fn user_man_dir() -> Option<PathBuf> {
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| dirs::home_dir().map(|home| home.join(".local").join("share")))?;
+    Some(data_home.join("man").join("man1"))
+}
+
+/// Every command as `(page name, command)`, the top-level one first
+pub fn pages(root: &Command) -> Vec<(String, Command)> {
+    // Building propagates global flags and the version, and fills in each
+    // subcommand's full name for its synopsis
+    let mut root = root.clone().propagate_version(true);
+    root.build();
+    let mut found = Vec::new();
+    collect(&root, root.get_name().to_string(), &mut found);
+    found
+}
+
+fn collect(cmd: &Command, page: String, found: &mut Vec<(String, Command)>) {
+    found.push((page.clone(), cmd.clone()));
+    for sub in documented(cmd) {
+        collect(sub, format!("{}-{}", page, sub.get_name()), found);
+    }
+}
+
+/// Subcommands that get a page, leaving out clap's `help`
+fn documented(cmd: &Command) -> impl Iterator<Item = &Command> {
+    cmd.get_subcommands().filter(|sub| !sub.is_hide_set() && sub.get_name() != "help")
+}
+
+/// roff source of the page for `cmd`
+pub fn render(page: &str, cmd: &Command) -> String {
+    let mut cmd = cmd.clone();
+    let mut out = String::new();
+    let version = cmd.get_version().map(|version| format!("klipdot {}", version)).unwrap_or_default();
+    let _ = writeln!(out, ".TH {} 1 \"\" \"{}\" \"KlipDot Manual\"", escape(&page.to_uppercase()), escape(&version));
+
+    let _ = writeln!(out, ".SH NAME");
+    let about = cmd.get_about().map(|about| about.to_string()).unwrap_or_default();
+    let _ = writeln!(out, "{} \\- {}", escape(page), escape(&about));
+
+    let _ = writeln!(out, ".SH SYNOPSIS");
+    let usage = cmd.render_usage().to_string();
+    let usage = usage.trim_start_matches("Usage:").trim();
+    let _ = writeln!(out, ".nf\n{}\n.fi", escape(usage));
+
+    if let Some(long_about) = cmd.get_long_about() {
+        let _ = writeln!(out, ".SH DESCRIPTION");
+        paragraphs(&mut out, &long_about.to_string());
+    }
+
+    let (positionals, options): (Vec<&Arg>, Vec<&Arg>) = cmd
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set())
+        .partition(|arg| arg.is_positional());
+    if !positionals.is_empty() {
+        let _ = writeln!(out, ".SH ARGUMENTS");
+        for arg in positionals {
+            let _ = writeln!(out, ".TP\n{}", escape(&value_name(arg)));
+            describe(&mut out, arg);
+        }
+    }
+    if !options.is_empty() {
+        let _ = writeln!(out, ".SH OPTIONS");
+        for arg in options {
+            let _ = writeln!(out, ".TP\n{}", option_label(arg));
+            describe(&mut out, arg);
+        }
+    }
+
+    let subcommands: Vec<&Command> = documented(&cmd).collect();
+    if !subcommands.is_empty() {
+        let _ = writeln!(out, ".SH COMMANDS");
+        for sub in &subcommands {
+            let _ = writeln!(out, ".TP\n\\fB{}\\-{}\\fR(1)", escape(page), escape(sub.get_name()));
+            if let Some(about) = sub.get_about() {
+                let _ = writeln!(out, "{}", escape(&about.to_string()));
+            }
+        }
+    }
+
+    if let Some((parent, _)) = page.rsplit_once('-') {
+        let _ = writeln!(out, ".SH SEE ALSO\n\\fB{}\\fR(1)", escape(parent));
+    }
+    out
+}
+
+/// Write a page for every command into `dir`, returning the files written
+pub async fn write_pages(root: &Command, dir: &Path) -> Result<Vec<PathBuf>> {
+    tokio::fs::create_dir_all(dir).await?;
+    let mut written = Vec::new();
+    for (page, cmd) in pages(root) {
+        let path = dir.join(format!("{}.1", page));
+        tokio::fs::write(&path, render(&page, &cmd)).await?;
+        debug!("Wrote manual page {:?}", path);
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Remove the pages `write_pages` left in `dir`
+pub async fn remove_pages(root: &Command, dir: &Path) -> Result<()> {
+    for (page, _) in pages(root) {
+        match tokio::fs::remove_file(dir.join(format!("{}.1", page))).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn describe(out: &mut String, arg: &Arg) {
+    let help = arg.get_long_help().or(arg.get_help()).map(|help| help.to_string()).unwrap_or_default();
+    let defaults: Vec<_> = arg.get_default_values().iter().map(|value| value.to_string_lossy()).collect();
+    let mut text = help;
+    if !defaults.is_empty() && arg.get_action().takes_values() {
+        if !text.is_empty() {
+            text.push(' ');
+        }
+        let _ = write!(text, "[default: {}]", defaults.join(", "));
+    }
+    paragraphs(out, &text);
+}
+
+fn option_label(arg: &Arg) -> String {
+    let mut names = Vec::new();
+    if let Some(short) = arg.get_short() {
+        names.push(format!("\\fB\\-{}\\fR", escape(&short.to_string())));
+    }
+    if let Some(long) = arg.get_long() {
+        names.push(format!("\\fB\\-\\-{}\\fR", escape(long)));
+    }
+    let mut label = names.join(", ");
+    if arg.get_action().takes_values() && !matches!(arg.get_action(), ArgAction::SetTrue | ArgAction::SetFalse) {
+        let _ = write!(label, " \\fI{}\\fR", escape(&value_name(arg)));
+    }
+    label
+}
+
+fn value_name(arg: &Arg) -> String {
+    let name = arg
+        .get_value_names()
+        .and_then(|names| names.first())
+        .map(|name| name.to_string())
+        .unwrap_or_else(|| arg.get_id().as_str().to_uppercase());
+    if arg.get_num_args().is_some_and(|range| range.max_values() > 1) {
+        format!("<{}>...", name)
+    } else {
+        format!("<{}>", name)
+    }
+}
+
+fn paragraphs(out: &mut String, text: &str) {
+    for (index, paragraph) in text.split("\n\n").enumerate() {
+        if index > 0 {
+            let _ = writeln!(out, ".PP");
+        }
+        let _ = writeln!(out, "{}", escape(paragraph.trim()));
+    }
+}
+
+/// Escape text for roff: backslashes and dashes, and lines that would
+/// otherwise start a request
+fn escape(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let line = line.replace('\\', "\\e").replace('-', "\\-");
+            if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{}", line)
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn command() -> Command {
+        Command::new("klipdot")
+            .version("1.2.3")
+            .about("Universal terminal image interceptor")
+            .arg(Arg::new("quiet").short('q').long("quiet").global(true).action(ArgAction::SetTrue).help("Print only results"))
+            .subcommand(
+                Command::new("remote")
+                    .about("Images from SSH hosts")
+                    .subcommand(
+                        Command::new("serve")
+                            .about("Stream new images")
+                            .arg(Arg::new("interval").long("interval").default_value("500").help("Polling interval")),
+                    ),
+            )
+            .subcommand(Command::new("capture").about("Take a screenshot").arg(Arg::new("output").help("Where to save")))
+    }
+
+    #[test]
+    fn test_pages() {
+        let names: Vec<_> = pages(&command()).into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["klipdot", "klipdot-remote", "klipdot-remote-serve", "klipdot-capture"]);
+    }
+
+    #[test]
+    fn test_render() {
+        let pages = pages(&command());
+        let (_, root) = &pages[0];
+        let page = render("klipdot", root);
+        assert!(page.starts_with(".TH KLIPDOT 1 \"\" \"klipdot 1.2.3\""));
+        assert!(page.contains("klipdot \\- Universal terminal image interceptor"));
+        assert!(page.contains("\\fBklipdot\\-capture\\fR(1)\nTake a screenshot"));
+
+        let (name, serve) = &pages[2];
+        let page = render(name, serve);
+        assert!(page.contains("klipdot remote serve [OPTIONS]"));
+        assert!(page.contains("\\fB\\-\\-interval\\fR \\fI<INTERVAL>\\fR\nPolling interval [default: 500]"));
+        // Global flags are documented on every page
+        assert!(page.contains("\\fB\\-q\\fR, \\fB\\-\\-quiet\\fR\nPrint only results"));
+        assert!(page.contains(".SH SEE ALSO\n\\fBklipdot\\-remote\\fR(1)"));
+    }
+
+    #[tokio::test]
+    async fn test_write_and_remove_pages() {
+        let temp_dir = TempDir::new().unwrap();
+        let written = write_pages(&command(), temp_dir.path()).await.unwrap();
+        assert_eq!(written.len(), 4);
+        assert!(temp_dir.path().join("klipdot-remote-serve.1").exists());
+
+        remove_pages(&command(), temp_dir.path()).await.unwrap();
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+    }
+}
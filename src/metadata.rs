@@ -0,0 +1,180 @@
+//! Metadata for stored images that the files themselves don't carry.
+//!
+//! Each directory images are written to keeps an append-only JSON-lines index
+//! ([`crate::METADATA_INDEX`]) recording where every image came from. Images
+//! without an entry fall back to what their filename suggests.
+
+use crate::{error::Result, focus::WindowContext};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+use tracing::debug;
+
+/// Origin of one stored image
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageMetadata {
+    pub filename: String,
+    /// Interception source ("clipboard", "wayland-screenshot", ...)
+    pub source: String,
+    /// Application the image came from: the screenshot tool, or the app focused when it was copied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    /// Monitor a capture was taken of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// Original width and height when the image was scaled down before saving
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resized_from: Option<(u32, u32)>,
+    /// [`crate::dedup::content_hash`] of the image as it was received
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Short description of the image, see [`crate::alt_text`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alt_text: Option<String>,
+    /// Labels given with `klipdot tag`, lowercase
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Focused window and workspace when the image was intercepted, see [`crate::focus::window_context`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window: Option<WindowContext>,
+    /// Where the image went in cold storage, in the same directory: a
+    /// `bundle.tar.zst::member` or the converted file, see [`crate::cold_storage`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cold: Option<String>,
+}
+
+/// Append `entry` to the index in `dir`
+pub async fn record(dir: &Path, entry: &ImageMetadata) -> Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+
+    let mut index = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(crate::METADATA_INDEX))
+        .await?;
+    index.write_all(&line).await?;
+    // tokio finishes file writes in the background; flush so the entry is on disk on return
+    index.flush().await?;
+    Ok(())
+}
+
+/// Index entries in `dir` by filename; later entries win
+pub async fn load(dir: &Path) -> Result<HashMap<String, ImageMetadata>> {
+    let content = match tokio::fs::read_to_string(dir.join(crate::METADATA_INDEX)).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut entries = HashMap::new();
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        match serde_json::from_str::<ImageMetadata>(line) {
+            Ok(entry) => {
+                entries.insert(entry.filename.clone(), entry);
+            }
+            // A line cut short by a crash shouldn't hide the rest of the index
+            Err(e) => debug!("Skipping malformed metadata entry: {}", e),
+        }
+    }
+    Ok(entries)
+}
+
+/// Add `add` to and remove `remove` from the tags of `filename` in `dir`,
+/// returning its tags afterwards; `None` when it has no index entry
+pub async fn retag(dir: &Path, filename: &str, add: &[String], remove: &[String]) -> Result<Option<Vec<String>>> {
+    // The index is append-only and later entries win, so the updated entry replaces the original
+    let Some(mut entry) = load(dir).await?.remove(filename) else {
+        return Ok(None);
+    };
+    for tag in add.iter().map(|tag| tag.to_lowercase()) {
+        if !entry.tags.contains(&tag) {
+            entry.tags.push(tag);
+        }
+    }
+    entry.tags.retain(|tag| !remove.iter().any(|removed| removed.eq_ignore_ascii_case(tag)));
+    record(dir, &entry).await?;
+    Ok(Some(entry.tags))
+}
+
+/// Point the index entries for `from` in `dir` at the renamed file `to`.
+///
+/// The index is rewritten through a temporary file so a crash leaves either
+/// the old or the new index in place.
+pub async fn rename(dir: &Path, from: &str, to: &str) -> Result<()> {
+    let index = dir.join(crate::METADATA_INDEX);
+    let content = match tokio::fs::read_to_string(&index).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut rewritten = String::with_capacity(content.len());
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        match serde_json::from_str::<ImageMetadata>(line) {
+            Ok(mut entry) if entry.filename == from || entry.filename == to => {
+                // An entry left over from an earlier file called `to` no longer applies
+                if entry.filename == to {
+                    continue;
+                }
+                entry.filename = to.to_string();
+                rewritten.push_str(&serde_json::to_string(&entry)?);
+            }
+            _ => rewritten.push_str(line),
+        }
+        rewritten.push('\n');
+    }
+
+    let temp = dir.join(format!("{}.tmp", crate::METADATA_INDEX));
+    tokio::fs::write(&temp, rewritten).await?;
+    tokio::fs::rename(&temp, &index).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_record_and_load() {
+        let temp_dir = TempDir::new().unwrap();
+        assert!(load(temp_dir.path()).await.unwrap().is_empty());
+
+        let entry = ImageMetadata {
+            filename: "clipboard-1.png".to_string(),
+            source: "clipboard".to_string(),
+            app: Some("firefox".to_string()),
+            output: None,
+            resized_from: Some((5000, 1200)),
+            hash: Some("0123-4".to_string()),
+            alt_text: None,
+            tags: Vec::new(),
+            window: None,
+            cold: None,
+        };
+        record(temp_dir.path(), &entry).await.unwrap();
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(temp_dir.path().join(crate::METADATA_INDEX))
+            .and_then(|mut index| std::io::Write::write_all(&mut index, b"{\"filename\": \"trunc"))
+            .unwrap();
+
+        let entries = load(temp_dir.path()).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries["clipboard-1.png"], entry);
+
+        rename(temp_dir.path(), "clipboard-1.png", "login-page.png").await.unwrap();
+        let entries = load(temp_dir.path()).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries["login-page.png"].app.as_deref(), Some("firefox"));
+
+        let tags = retag(temp_dir.path(), "login-page.png", &["Bug".to_string(), "ui".to_string()], &[]).await.unwrap();
+        assert_eq!(tags, Some(vec!["bug".to_string(), "ui".to_string()]));
+        let tags = retag(temp_dir.path(), "login-page.png", &["bug".to_string()], &["UI".to_string()]).await.unwrap();
+        assert_eq!(tags, Some(vec!["bug".to_string()]));
+        assert_eq!(load(temp_dir.path()).await.unwrap()["login-page.png"].tags, ["bug"]);
+        assert_eq!(retag(temp_dir.path(), "other.png", &["bug".to_string()], &[]).await.unwrap(), None);
+    }
+}
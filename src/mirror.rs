@@ -0,0 +1,168 @@
+//! Copies of stored images in a second directory (`mirror`), such as a notes
+//! vault's attachments folder or a synced folder.
+//!
+//! Each copy is named from `mirror.name_template`, whose placeholders are
+//! filled in when the image is stored:
+//!
+//! | Placeholder | Value |
+//! |-------------|-------|
+//! | `{name}` | Stored filename without its extension |
+//! | `{source}` | Where the image came from ("clipboard", "screenshot", ...) |
+//! | `{slug}` | Slug of the window title or image text, if `auto_slug` made one |
+//! | `{app}` | Focused app the image came from, if known |
+//! | `{date}`, `{time}` | Local date and time, `2024-01-01` and `09-30-00` |
+//! | `{timestamp}` | Local date and time, `20240101093000` |
+//! | `{ext}` | Extension of the stored image |
+//!
+//! A template may put copies in subdirectories ("{date}/{name}.{ext}"). A
+//! copy never replaces a file already in the mirror; a counter is added to
+//! its name instead.
+
+use crate::{config::MirrorConfig, error::Result, Error};
+use chrono::{DateTime, Local};
+use std::path::{Component, Path, PathBuf};
+use tracing::info;
+
+/// Most names tried with a counter before giving up
+const MAX_COUNTER: u32 = 1000;
+
+/// What a stored image's mirror name is made of
+#[derive(Debug, Clone)]
+pub struct MirrorName<'a> {
+    pub stored: &'a Path,
+    pub source: &'a str,
+    pub slug: Option<&'a str>,
+    pub app: Option<&'a str>,
+    pub time: DateTime<Local>,
+}
+
+/// `template` with its placeholders filled in; `None` when the result isn't
+/// a relative path staying inside the mirror directory
+pub fn render_name(template: &str, name: &MirrorName) -> Option<PathBuf> {
+    let stem = name.stored.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let ext = name.stored.extension().map(|ext| ext.to_string_lossy().into_owned()).unwrap_or_default();
+    let values = [
+        ("{name}", stem),
+        ("{source}", name.source.to_string()),
+        ("{slug}", name.slug.unwrap_or_default().to_string()),
+        ("{app}", name.app.unwrap_or_default().to_string()),
+        ("{date}", name.time.format("%Y-%m-%d").to_string()),
+        ("{time}", name.time.format("%H-%M-%S").to_string()),
+        ("{timestamp}", name.time.format("%Y%m%d%H%M%S").to_string()),
+        ("{ext}", ext),
+    ];
+
+    let mut rendered = template.to_string();
+    for (placeholder, value) in values {
+        // Values never add directories of their own
+        rendered = rendered.replace(placeholder, &value.replace(['/', '\\'], "-"));
+    }
+
+    let path = PathBuf::from(rendered);
+    let plain = path.file_name().is_some() && path.components().all(|component| matches!(component, Component::Normal(_)));
+    plain.then_some(path)
+}
+
+/// Copy the stored image into the mirror directory, returning the copy's
+/// path; `None` when mirroring is off or not wanted for `name.source`
+pub async fn mirror_image(config: &MirrorConfig, name: &MirrorName<'_>) -> Result<Option<PathBuf>> {
+    let Some(dir) = config.dir.as_ref() else {
+        return Ok(None);
+    };
+    if !config.sources.is_empty() && !config.sources.iter().any(|source| source == name.source) {
+        return Ok(None);
+    }
+
+    let relative = render_name(&config.name_template, name)
+        .ok_or_else(|| Error::Config(format!("Invalid mirror name template {:?}", config.name_template)))?;
+    let target = dir.join(relative);
+    if let Some(parent) = target.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let target = free_path(&target).await?;
+    tokio::fs::copy(name.stored, &target).await?;
+    info!("Mirrored {:?} to {:?}", name.stored, target);
+    Ok(Some(target))
+}
+
+/// `path`, or `path` with "-1", "-2", ... before its extension if taken
+async fn free_path(path: &Path) -> Result<PathBuf> {
+    if !tokio::fs::try_exists(path).await? {
+        return Ok(path.to_path_buf());
+    }
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let ext = path.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+    for counter in 1..=MAX_COUNTER {
+        let candidate = path.with_file_name(format!("{}-{}{}", stem, counter, ext));
+        if !tokio::fs::try_exists(&candidate).await? {
+            return Ok(candidate);
+        }
+    }
+    Err(Error::Io(std::io::Error::new(
+        std::io::ErrorKind::AlreadyExists,
+        format!("No free name for {:?} in the mirror", path),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use tempfile::TempDir;
+
+    fn name(stored: &Path) -> MirrorName<'_> {
+        MirrorName {
+            stored,
+            source: "clipboard",
+            slug: Some("login-page"),
+            app: Some("org/mozilla.firefox"),
+            time: Local.with_ymd_and_hms(2024, 1, 2, 9, 30, 5).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_render_name() {
+        let stored = Path::new("/shots/clipboard-2024-01-02T08-30-05.000Z-1a2b3c4d.png");
+        let name = name(stored);
+        assert_eq!(
+            render_name("{name}.{ext}", &name).unwrap(),
+            Path::new("clipboard-2024-01-02T08-30-05.000Z-1a2b3c4d.png")
+        );
+        assert_eq!(
+            render_name("Pasted image {timestamp}.{ext}", &name).unwrap(),
+            Path::new("Pasted image 20240102093005.png")
+        );
+        assert_eq!(
+            render_name("{date}/{app}-{slug}.{ext}", &name).unwrap(),
+            Path::new("2024-01-02/org-mozilla.firefox-login-page.png")
+        );
+        assert!(render_name("../{name}.{ext}", &name).is_none());
+        assert!(render_name("/tmp/{name}", &name).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mirror_image() {
+        let temp_dir = TempDir::new().unwrap();
+        let stored = temp_dir.path().join("clipboard-1.png");
+        std::fs::write(&stored, b"png").unwrap();
+        let mut config = MirrorConfig {
+            dir: Some(temp_dir.path().join("vault").join("attachments")),
+            name_template: "shot-{date}.{ext}".to_string(),
+            sources: Vec::new(),
+        };
+
+        let first = mirror_image(&config, &name(&stored)).await.unwrap().unwrap();
+        assert_eq!(first, temp_dir.path().join("vault/attachments/shot-2024-01-02.png"));
+        assert_eq!(std::fs::read(&first).unwrap(), b"png");
+        // Existing copies are kept
+        let second = mirror_image(&config, &name(&stored)).await.unwrap().unwrap();
+        assert_eq!(second, temp_dir.path().join("vault/attachments/shot-2024-01-02-1.png"));
+
+        config.sources = vec!["screenshot".to_string()];
+        assert!(mirror_image(&config, &name(&stored)).await.unwrap().is_none());
+        config.dir = None;
+        config.sources.clear();
+        assert!(mirror_image(&config, &name(&stored)).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,250 @@
+//! Connected monitors, for capturing one output of a multi-monitor setup.
+//!
+//! Outputs are read from the compositor on sway (`swaymsg`) and Hyprland
+//! (`hyprctl`), from `wlr-randr` on other wlroots compositors and from
+//! `xrandr` on X11. Geometry is in the logical coordinates screenshot tools
+//! take regions in, so scaled and rotated outputs line up.
+
+use crate::{command_runner::CommandRunner, error::Result, screenshot::Region, DisplayServer, Error};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
+use std::fmt;
+use tracing::debug;
+
+/// `HDMI-1 connected primary 1920x1080+0+0 ...` lines of `xrandr --query`
+static XRANDR_OUTPUT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(\S+) connected (primary )?(\d+)x(\d+)\+(-?\d+)\+(-?\d+)").unwrap()
+});
+
+/// One enabled output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Monitor {
+    /// Output name ("DP-1", "eDP-1", ...)
+    pub name: String,
+    pub region: Region,
+    /// Focused output on Wayland, the primary output on X11
+    pub focused: bool,
+}
+
+impl fmt::Display for Monitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.region)?;
+        if self.focused {
+            write!(f, " *")?;
+        }
+        Ok(())
+    }
+}
+
+/// Enabled monitors on `display_server`
+pub async fn list_monitors(runner: &dyn CommandRunner, display_server: DisplayServer) -> Result<Vec<Monitor>> {
+    let monitors = match display_server {
+        DisplayServer::Wayland => match crate::detect_wayland_compositor().as_deref() {
+            Some("sway") => parse_sway_outputs(&query(runner, "swaymsg", &["-t", "get_outputs", "-r"]).await?),
+            Some("hyprland") => parse_hyprland_monitors(&query(runner, "hyprctl", &["monitors", "-j"]).await?),
+            _ => parse_wlr_randr(&query(runner, "wlr-randr", &["--json"]).await?),
+        },
+        DisplayServer::X11 => parse_xrandr(&query(runner, "xrandr", &["--query"]).await?),
+        DisplayServer::MacOS | DisplayServer::Unknown => {
+            return Err(Error::Unsupported(format!("Listing monitors is not supported on {:?}", display_server)));
+        }
+    }?;
+
+    debug!("Monitors: {:?}", monitors);
+    if monitors.is_empty() {
+        return Err(Error::NotFound("No enabled monitors found".to_string()));
+    }
+    Ok(monitors)
+}
+
+/// The monitor called `name`, ignoring case
+pub fn find_monitor<'a>(monitors: &'a [Monitor], name: &str) -> Result<&'a Monitor> {
+    monitors
+        .iter()
+        .find(|monitor| monitor.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            let names: Vec<&str> = monitors.iter().map(|monitor| monitor.name.as_str()).collect();
+            Error::NotFound(format!("No monitor named '{}' (available: {})", name, names.join(", ")))
+        })
+}
+
+async fn query(runner: &dyn CommandRunner, program: &str, args: &[&str]) -> Result<String> {
+    if !runner.is_available(program) {
+        return Err(Error::Unsupported(format!("{} is needed to list monitors", program)));
+    }
+
+    let output = runner
+        .run(program, args, None)
+        .await
+        .map_err(|e| Error::Process(format!("Failed to run {}: {}", program, e)))?;
+    if !output.success {
+        return Err(Error::Process(format!("{} failed: {}", program, output.stderr_lossy().trim())));
+    }
+    Ok(output.stdout_lossy())
+}
+
+fn parse_json(output: &str, program: &str) -> Result<Vec<Value>> {
+    match serde_json::from_str(output) {
+        Ok(Value::Array(entries)) => Ok(entries),
+        _ => Err(Error::Format(format!("Unexpected {} output", program))),
+    }
+}
+
+/// `swaymsg -t get_outputs -r`; `rect` is already in logical coordinates
+fn parse_sway_outputs(output: &str) -> Result<Vec<Monitor>> {
+    Ok(parse_json(output, "swaymsg")?
+        .iter()
+        .filter(|entry| entry["active"].as_bool() != Some(false))
+        .filter_map(|entry| {
+            let rect = &entry["rect"];
+            Some(Monitor {
+                name: entry["name"].as_str()?.to_string(),
+                region: region(rect["x"].as_i64()?, rect["y"].as_i64()?, rect["width"].as_u64()?, rect["height"].as_u64()?)?,
+                focused: entry["focused"].as_bool() == Some(true),
+            })
+        })
+        .collect())
+}
+
+/// `hyprctl monitors -j`; `width` and `height` are the mode in physical pixels
+fn parse_hyprland_monitors(output: &str) -> Result<Vec<Monitor>> {
+    Ok(parse_json(output, "hyprctl")?
+        .iter()
+        .filter(|entry| entry["disabled"].as_bool() != Some(true))
+        .filter_map(|entry| {
+            let (width, height) = logical_size(
+                entry["width"].as_u64()?,
+                entry["height"].as_u64()?,
+                entry["scale"].as_f64().unwrap_or(1.0),
+                entry["transform"].as_u64().is_some_and(|transform| transform % 2 == 1),
+            );
+            Some(Monitor {
+                name: entry["name"].as_str()?.to_string(),
+                region: region(entry["x"].as_i64()?, entry["y"].as_i64()?, width, height)?,
+                focused: entry["focused"].as_bool() == Some(true),
+            })
+        })
+        .collect())
+}
+
+/// `wlr-randr --json`; the size comes from the current mode
+fn parse_wlr_randr(output: &str) -> Result<Vec<Monitor>> {
+    Ok(parse_json(output, "wlr-randr")?
+        .iter()
+        .filter(|entry| entry["enabled"].as_bool() == Some(true))
+        .filter_map(|entry| {
+            let mode = entry["modes"]
+                .as_array()?
+                .iter()
+                .find(|mode| mode["current"].as_bool() == Some(true))?;
+            let rotated = matches!(entry["transform"].as_str(), Some("90" | "270" | "flipped-90" | "flipped-270"));
+            let (width, height) = logical_size(
+                mode["width"].as_u64()?,
+                mode["height"].as_u64()?,
+                entry["scale"].as_f64().unwrap_or(1.0),
+                rotated,
+            );
+            let position = &entry["position"];
+            Some(Monitor {
+                name: entry["name"].as_str()?.to_string(),
+                region: region(position["x"].as_i64()?, position["y"].as_i64()?, width, height)?,
+                // wlr-randr doesn't know which output has focus
+                focused: false,
+            })
+        })
+        .collect())
+}
+
+/// `xrandr --query`; connected outputs without a mode are disabled
+fn parse_xrandr(output: &str) -> Result<Vec<Monitor>> {
+    Ok(output
+        .lines()
+        .filter_map(|line| XRANDR_OUTPUT.captures(line))
+        .filter_map(|captures| {
+            Some(Monitor {
+                name: captures[1].to_string(),
+                region: region(captures[5].parse().ok()?, captures[6].parse().ok()?, captures[3].parse().ok()?, captures[4].parse().ok()?)?,
+                focused: captures.get(2).is_some(),
+            })
+        })
+        .collect())
+}
+
+fn logical_size(width: u64, height: u64, scale: f64, rotated: bool) -> (u64, u64) {
+    let scale = if scale > 0.0 { scale } else { 1.0 };
+    let (width, height) = if rotated { (height, width) } else { (width, height) };
+    ((width as f64 / scale).round() as u64, (height as f64 / scale).round() as u64)
+}
+
+fn region(x: i64, y: i64, width: u64, height: u64) -> Option<Region> {
+    let region = Region {
+        x: x.try_into().ok()?,
+        y: y.try_into().ok()?,
+        width: width.try_into().ok()?,
+        height: height.try_into().ok()?,
+    };
+    (region.width > 0 && region.height > 0).then_some(region)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sway_outputs() {
+        let output = r#"[
+            {"name": "eDP-1", "active": true, "focused": false, "rect": {"x": 0, "y": 0, "width": 1280, "height": 800}},
+            {"name": "DP-2", "active": true, "focused": true, "rect": {"x": 1280, "y": 0, "width": 2560, "height": 1440}},
+            {"name": "HDMI-A-1", "active": false, "focused": false, "rect": {"x": 0, "y": 0, "width": 0, "height": 0}}
+        ]"#;
+        let monitors = parse_sway_outputs(output).unwrap();
+        assert_eq!(monitors.len(), 2);
+        assert_eq!(monitors[1].name, "DP-2");
+        assert_eq!(monitors[1].region.to_string(), "1280,0 2560x1440");
+        assert!(monitors[1].focused);
+    }
+
+    #[test]
+    fn test_parse_hyprland_monitors() {
+        let output = r#"[
+            {"name": "eDP-1", "x": 0, "y": 0, "width": 2880, "height": 1800, "scale": 2.0, "transform": 0, "focused": true},
+            {"name": "DP-1", "x": 1440, "y": 0, "width": 1920, "height": 1080, "scale": 1.0, "transform": 1, "focused": false}
+        ]"#;
+        let monitors = parse_hyprland_monitors(output).unwrap();
+        assert_eq!(monitors[0].region.to_string(), "0,0 1440x900");
+        assert!(monitors[0].focused);
+        // Rotated outputs swap width and height
+        assert_eq!(monitors[1].region.to_string(), "1440,0 1080x1920");
+    }
+
+    #[test]
+    fn test_parse_wlr_randr() {
+        let output = r#"[
+            {"name": "DP-1", "enabled": true, "position": {"x": 0, "y": 0}, "scale": 1.5, "transform": "normal",
+             "modes": [{"width": 1920, "height": 1080, "current": false}, {"width": 3840, "height": 2160, "current": true}]},
+            {"name": "HDMI-A-1", "enabled": false, "position": {"x": 0, "y": 0}, "modes": []}
+        ]"#;
+        let monitors = parse_wlr_randr(output).unwrap();
+        assert_eq!(monitors.len(), 1);
+        assert_eq!(monitors[0].region.to_string(), "0,0 2560x1440");
+        assert!(parse_wlr_randr("not json").is_err());
+    }
+
+    #[test]
+    fn test_parse_xrandr() {
+        let output = "Screen 0: minimum 8 x 8, current 3840 x 1080, maximum 32767 x 32767\n\
+            eDP-1 connected primary 1920x1080+0+0 (normal left inverted right x axis y axis) 309mm x 174mm\n   \
+            1920x1080     60.00*+\n\
+            HDMI-1 connected 1920x1080+1920+0 (normal left inverted right x axis y axis) 527mm x 296mm\n\
+            DP-1 connected (normal left inverted right x axis y axis)\n\
+            DP-2 disconnected (normal left inverted right x axis y axis)\n";
+        let monitors = parse_xrandr(output).unwrap();
+        assert_eq!(monitors.len(), 2);
+        assert!(monitors[0].focused);
+        assert_eq!(monitors[1].region.to_string(), "1920,0 1920x1080");
+
+        assert_eq!(find_monitor(&monitors, "hdmi-1").unwrap().name, "HDMI-1");
+        assert!(find_monitor(&monitors, "DP-1").unwrap_err().to_string().contains("eDP-1, HDMI-1"));
+    }
+}
@@ -0,0 +1,59 @@
+//! Human-facing command-line output.
+//!
+//! stdout is kept for what a command produces (paths, listings, snippets) so
+//! scripts can capture it. Confirmations and banners go to stderr through
+//! [`status`], are dropped with `--quiet`, and lose their emoji with
+//! `--no-color`, `NO_COLOR` or when stderr isn't a terminal.
+
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+/// Apply the global `--quiet` and `--no-color` flags
+pub fn init(quiet: bool, no_color: bool) {
+    let plain = no_color || std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) || !std::io::stderr().is_terminal();
+    QUIET.store(quiet, Ordering::Relaxed);
+    PLAIN.store(plain, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Whether emoji and colors should be left out
+pub fn is_plain() -> bool {
+    PLAIN.load(Ordering::Relaxed)
+}
+
+/// `message` with its `emoji` prefix unless output is plain
+pub fn decorate(emoji: &str, message: impl Display) -> String {
+    if is_plain() || emoji.is_empty() {
+        message.to_string()
+    } else {
+        format!("{} {}", emoji, message)
+    }
+}
+
+/// Print a confirmation or banner line to stderr, unless quiet
+pub fn status(emoji: &str, message: impl Display) {
+    if !is_quiet() {
+        eprintln!("{}", decorate(emoji, message));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decorate() {
+        PLAIN.store(false, Ordering::Relaxed);
+        assert_eq!(decorate("✅", "Done"), "✅ Done");
+        assert_eq!(decorate("", "Done"), "Done");
+        PLAIN.store(true, Ordering::Relaxed);
+        assert_eq!(decorate("✅", "Done"), "Done");
+    }
+}
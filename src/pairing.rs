@@ -0,0 +1,140 @@
+//! Before/after pairs of screenshots taken close together.
+//!
+//! With `pairing.enabled`, an image stored within `pairing.window_secs` of the
+//! previous one from the same source is compared with it using
+//! [`crate::image_diff`]. When they differ, the diff image is stored next to
+//! the newer one as `<name>-diff.png`, and the three are tagged `before`,
+//! `after` and `diff` plus a shared `pair-<id>` tag, so `search tag:pair-<id>`
+//! finds them together. Pairs chain: the newer image is the `before` of the
+//! next capture that follows closely enough.
+
+use crate::{
+    config::Config,
+    decode_guard,
+    error::Result,
+    image_diff,
+    metadata::{self, ImageMetadata},
+    store_permissions, Error,
+};
+use image::{DynamicImage, ImageFormat};
+use once_cell::sync::Lazy;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// The image most recently stored while pairing is on
+struct Stored {
+    path: PathBuf,
+    source: String,
+    at: Instant,
+}
+
+static LAST: Lazy<Mutex<Option<Stored>>> = Lazy::new(|| Mutex::new(None));
+
+/// Pair `path`, just stored from `source`, with the image stored before it
+/// when that is recent enough, returning the stored diff
+pub async fn pair(config: &Config, path: &Path, source: &str, app: Option<&str>) -> Result<Option<PathBuf>> {
+    if !config.pairing.enabled {
+        return Ok(None);
+    }
+    let now = Instant::now();
+    let previous = LAST.lock().unwrap().replace(Stored { path: path.to_path_buf(), source: source.to_string(), at: now });
+    let Some(previous) = previous.filter(|previous| {
+        previous.source == source
+            && now.duration_since(previous.at) <= Duration::from_secs(config.pairing.window_secs)
+            && previous.path != path
+    }) else {
+        return Ok(None);
+    };
+
+    let (before, after, tolerance) = (previous.path.clone(), path.to_path_buf(), config.pairing.tolerance);
+    let limits = config.decode_limits.clone();
+    let encoded = tokio::task::spawn_blocking(move || -> Result<Option<Vec<u8>>> {
+        let (before, after) = (decode_guard::open(&before, &limits)?, decode_guard::open(&after, &limits)?);
+        let diff = image_diff::diff(&before.to_rgba8(), &after.to_rgba8(), tolerance);
+        if diff.bounds.is_none() {
+            return Ok(None);
+        }
+        let mut encoded = Vec::new();
+        DynamicImage::ImageRgba8(diff.image).write_to(&mut std::io::Cursor::new(&mut encoded), ImageFormat::Png)?;
+        Ok(Some(encoded))
+    })
+    .await
+    .map_err(|e| Error::Internal(format!("Task join error: {}", e)))??;
+    let Some(encoded) = encoded else {
+        debug!("{:?} looks the same as {:?}, not pairing them", path, previous.path);
+        return Ok(None);
+    };
+
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let diff_path = path.with_file_name(format!("{}-diff.png", stem));
+    store_permissions::write(&diff_path, &encoded, &config.store_permissions).await?;
+
+    let tag = format!("pair-{}", &uuid::Uuid::new_v4().simple().to_string()[..8]);
+    let entry = ImageMetadata {
+        filename: file_name(&diff_path),
+        source: source.to_string(),
+        app: app.map(str::to_string),
+        output: None,
+        resized_from: None,
+        hash: None,
+        alt_text: None,
+        tags: vec!["diff".to_string(), tag.clone()],
+        window: None,
+        cold: None,
+    };
+    metadata::record(parent(&diff_path), &entry).await?;
+    metadata::retag(parent(&previous.path), &file_name(&previous.path), &["before".to_string(), tag.clone()], &[]).await?;
+    metadata::retag(parent(path), &file_name(path), &["after".to_string(), tag], &[]).await?;
+
+    Ok(Some(diff_path))
+}
+
+fn parent(path: &Path) -> &Path {
+    path.parent().unwrap_or(Path::new("."))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_processor::ImageProcessor;
+    use tempfile::TempDir;
+
+    fn png(shade: u8) -> Vec<u8> {
+        let img = image::RgbImage::from_pixel(4, 4, image::Rgb([shade, shade, shade]));
+        let mut buffer = Vec::new();
+        DynamicImage::ImageRgb8(img).write_to(&mut std::io::Cursor::new(&mut buffer), ImageFormat::Png).unwrap();
+        buffer
+    }
+
+    #[tokio::test]
+    async fn test_screenshots_paired() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = Config { screenshot_dir: temp_dir.path().to_path_buf(), ..Config::default() };
+        config.pairing.enabled = true;
+        let processor = ImageProcessor::new(config).await.unwrap();
+
+        let before = processor.process_image_data(&png(0), "pairing-test").await.unwrap();
+        let after = processor.process_image_data(&png(200), "pairing-test").await.unwrap();
+        // A different source doesn't pair with either
+        let other = processor.process_image_data(&png(100), "pairing-other").await.unwrap();
+
+        let diff = after.with_file_name(format!("{}-diff.png", after.file_stem().unwrap().to_string_lossy()));
+        assert!(diff.exists());
+        assert!(!other.with_file_name(format!("{}-diff.png", other.file_stem().unwrap().to_string_lossy())).exists());
+
+        let index = metadata::load(temp_dir.path()).await.unwrap();
+        let tags = |path: &Path| index[&file_name(path)].tags.clone();
+        let pair = tags(&diff)[1].clone();
+        assert!(pair.starts_with("pair-"));
+        assert_eq!(tags(&before), ["before".to_string(), pair.clone()]);
+        assert_eq!(tags(&after), ["after".to_string(), pair.clone()]);
+        assert_eq!(tags(&diff), ["diff".to_string(), pair]);
+        assert!(tags(&other).is_empty());
+    }
+}
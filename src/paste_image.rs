@@ -0,0 +1,235 @@
+//! `klipdot paste-image`: putting a stored image back on the clipboard.
+//!
+//! The image goes on the clipboard as PNG data in each platform's native form
+//! (`wl-copy`, `xclip`, the macOS pasteboard via `osascript`, a Windows Forms
+//! bitmap via PowerShell, which other apps see as `CF_DIB`), so GUI apps can
+//! paste it. `klipdot copy --image` does the same for any image. The clipboard monitor would
+//! otherwise intercept it straight back into a path, so its hash is left in
+//! [`crate::HANDED_BACK_FILE`] for the monitor to skip it once.
+
+use crate::{
+    command_runner::CommandRunner,
+    config::{Config, DecodeLimits},
+    decode_guard, dedup,
+    error::Result,
+    temp_files, DisplayServer, Error,
+};
+use image::ImageFormat;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// The stored image `target` names: a path, or "last" for the newest
+/// screenshot. A path to an image in cold storage is thawed first.
+pub async fn resolve(config: &Config, target: &str) -> Result<PathBuf> {
+    if target == "last" {
+        return config
+            .get_recent_screenshots(1)
+            .await?
+            .into_iter()
+            .next()
+            .map(|screenshot| screenshot.path)
+            .ok_or_else(|| Error::NotFound(format!("No screenshots in {:?}", config.screenshot_dir)));
+    }
+
+    let path = PathBuf::from(target);
+    crate::cold_storage::thaw(&*crate::command_runner::system(), &path)
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("Image file not found: {:?}", path)))
+}
+
+/// Place the image at `path` on the clipboard as image data, marking it as
+/// handed back in `state_dir` (the KlipDot home directory). Images that
+/// aren't PNGs are decoded within `limits` to convert them.
+pub async fn copy_to_clipboard(runner: &dyn CommandRunner, display_server: DisplayServer, path: &Path, state_dir: &Path, limits: &DecodeLimits) -> Result<()> {
+    let data = tokio::fs::read(path).await?;
+    place(runner, display_server, data, Some(path), state_dir, limits).await
+}
+
+/// [`copy_to_clipboard`] for image data that isn't in a file
+pub async fn copy_data_to_clipboard(runner: &dyn CommandRunner, display_server: DisplayServer, data: &[u8], state_dir: &Path, limits: &DecodeLimits) -> Result<()> {
+    place(runner, display_server, data.to_vec(), None, state_dir, limits).await
+}
+
+/// Place `data`, read from `path` if it came from a file, on the clipboard as PNG
+async fn place(runner: &dyn CommandRunner, display_server: DisplayServer, data: Vec<u8>, path: Option<&Path>, state_dir: &Path, limits: &DecodeLimits) -> Result<()> {
+    let png = if image::guess_format(&data).ok() == Some(ImageFormat::Png) {
+        data
+    } else {
+        let img = decode_guard::load(&data, limits)?;
+        let mut png = Vec::new();
+        img.write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)?;
+        png
+    };
+
+    mark_handed_back(state_dir, &png).await?;
+
+    match display_server {
+        DisplayServer::Wayland => run(runner, "wl-copy", &["--type", "image/png"], &png).await,
+        DisplayServer::X11 => run(runner, "xclip", &["-selection", "clipboard", "-t", "image/png", "-i"], &png).await,
+        DisplayServer::MacOS => {
+            with_png_file(path, &png, |file| async move {
+                // The path is passed as an argument so it needs no AppleScript quoting
+                let script = ["-e", "on run argv", "-e", "set the clipboard to (read (POSIX file (item 1 of argv)) as «class PNGf»)", "-e", "end run"];
+                let file = file.to_string_lossy();
+                let args: Vec<&str> = script.iter().copied().chain([file.as_ref()]).collect();
+                run(runner, "osascript", &args, &[]).await
+            })
+            .await
+        }
+        DisplayServer::Unknown if cfg!(target_os = "windows") => place_windows(runner, path, &png).await,
+        DisplayServer::Unknown => Err(Error::Unsupported("Cannot place images on the clipboard without a display server".to_string())),
+    }
+}
+
+#[cfg(all(target_os = "windows", feature = "native-clipboard"))]
+async fn place_windows(_runner: &dyn CommandRunner, _path: Option<&Path>, png: &[u8]) -> Result<()> {
+    crate::windows_clipboard::set_png(png)
+}
+
+#[cfg(not(all(target_os = "windows", feature = "native-clipboard")))]
+async fn place_windows(runner: &dyn CommandRunner, path: Option<&Path>, png: &[u8]) -> Result<()> {
+    with_png_file(path, png, |file| async move {
+        let command = format!(
+            "Add-Type -AssemblyName System.Windows.Forms, System.Drawing; \
+             [System.Windows.Forms.Clipboard]::SetImage([System.Drawing.Image]::FromFile('{}'))",
+            file.to_string_lossy().replace('\'', "''")
+        );
+        run(runner, "powershell", &["-NoProfile", "-STA", "-Command", &command], &[]).await
+    })
+    .await
+}
+
+/// Run `copy` with a PNG file of the image: `path` itself when it is a PNG,
+/// otherwise a temporary copy that is removed afterwards
+async fn with_png_file<F, Fut>(path: Option<&Path>, png: &[u8], copy: F) -> Result<()>
+where
+    F: FnOnce(PathBuf) -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    if let Some(path) = path.filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png"))) {
+        return copy(path.to_path_buf()).await;
+    }
+
+    let temp_file = temp_files::write("paste-", "png", png)?;
+    copy(temp_file.to_path_buf()).await
+}
+
+async fn run(runner: &dyn CommandRunner, program: &str, args: &[&str], stdin: &[u8]) -> Result<()> {
+    if !runner.is_available(program) {
+        return Err(Error::Unsupported(format!("{} is needed to place images on the clipboard", program)));
+    }
+
+    let stdin = (!stdin.is_empty()).then_some(stdin);
+    let output = runner
+        .run(program, args, stdin)
+        .await
+        .map_err(|e| Error::Clipboard(format!("Failed to run {}: {}", program, e)))?;
+    if !output.success {
+        return Err(Error::Clipboard(format!("{} failed: {}", program, output.stderr_lossy().trim())));
+    }
+    debug!("Image placed on the clipboard with {}", program);
+    Ok(())
+}
+
+/// Record that `data` is being put on the clipboard by KlipDot itself
+pub async fn mark_handed_back(dir: &Path, data: &[u8]) -> Result<()> {
+    tokio::fs::write(dir.join(crate::HANDED_BACK_FILE), dedup::content_hash(data)).await?;
+    Ok(())
+}
+
+/// Whether `data` was handed back with `paste-image`; the mark is cleared so a
+/// later copy of the same image is intercepted again
+pub async fn take_handed_back(dir: &Path, data: &[u8]) -> bool {
+    let marker = dir.join(crate::HANDED_BACK_FILE);
+    let Ok(hash) = tokio::fs::read_to_string(&marker).await else {
+        return false;
+    };
+    if hash.trim() != dedup::content_hash(data) {
+        return false;
+    }
+
+    let _ = tokio::fs::remove_file(&marker).await;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_handed_back_marker() {
+        let temp_dir = TempDir::new().unwrap();
+        assert!(!take_handed_back(temp_dir.path(), b"image").await);
+
+        mark_handed_back(temp_dir.path(), b"image").await.unwrap();
+        assert!(!take_handed_back(temp_dir.path(), b"other image").await);
+        assert!(take_handed_back(temp_dir.path(), b"image").await);
+        // Only skipped once
+        assert!(!take_handed_back(temp_dir.path(), b"image").await);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_last() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        assert!(resolve(&config, "last").await.is_err());
+
+        let shot = temp_dir.path().join("clipboard-1.png");
+        std::fs::write(&shot, b"png").unwrap();
+        assert_eq!(resolve(&config, "last").await.unwrap(), shot);
+        assert_eq!(resolve(&config, shot.to_str().unwrap()).await.unwrap(), shot);
+        assert!(resolve(&config, "/nonexistent/shot.png").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_copy_data() {
+        use crate::command_runner::{CommandOutput, FakeRunner};
+
+        let temp_dir = TempDir::new().unwrap();
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(2, 2))
+            .write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let runner = FakeRunner::new()
+            .with_output("wl-copy", CommandOutput::ok(""))
+            .with_output("osascript", CommandOutput::ok(""));
+
+        copy_data_to_clipboard(&runner, DisplayServer::Wayland, &png, temp_dir.path(), &DecodeLimits::default()).await.unwrap();
+        let call = &runner.calls_to("wl-copy")[0];
+        assert_eq!(call.args, ["--type", "image/png"]);
+        assert_eq!(call.stdin.as_deref(), Some(&png[..]));
+
+        // The pasteboard reads a file, written for the data and removed afterwards
+        copy_data_to_clipboard(&runner, DisplayServer::MacOS, &png, temp_dir.path(), &DecodeLimits::default()).await.unwrap();
+        let file = PathBuf::from(runner.calls_to("osascript")[0].args.last().unwrap());
+        assert_eq!(file.extension().unwrap(), "png");
+        assert!(!file.exists());
+
+        assert!(copy_data_to_clipboard(&runner, DisplayServer::Wayland, b"not an image", temp_dir.path(), &DecodeLimits::default()).await.is_err());
+    }
+
+    #[cfg(feature = "codecs")]
+    #[tokio::test]
+    async fn test_copy_converts_to_png() {
+        use crate::command_runner::{CommandOutput, FakeRunner};
+
+        let temp_dir = TempDir::new().unwrap();
+        let bmp = temp_dir.path().join("shot.bmp");
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(2, 2)).save_with_format(&bmp, ImageFormat::Bmp).unwrap();
+
+        let runner = FakeRunner::new().with_output("xclip", CommandOutput::ok(""));
+        copy_to_clipboard(&runner, DisplayServer::X11, &bmp, temp_dir.path(), &DecodeLimits::default()).await.unwrap();
+
+        let call = &runner.calls_to("xclip")[0];
+        assert_eq!(call.args, ["-selection", "clipboard", "-t", "image/png", "-i"]);
+        let png = call.stdin.as_ref().unwrap();
+        assert_eq!(image::guess_format(png).unwrap(), ImageFormat::Png);
+        assert!(take_handed_back(temp_dir.path(), png).await);
+
+        assert!(copy_to_clipboard(&runner, DisplayServer::Wayland, &bmp, temp_dir.path(), &DecodeLimits::default()).await.is_err());
+    }
+}
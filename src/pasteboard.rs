@@ -0,0 +1,97 @@
+//! Native access to the macOS general pasteboard.
+//!
+//! Reading through AppKit saves the clipboard monitor an `osascript` or
+//! `pbpaste` process per poll and the hex round trip of `«data PNGf…»`.
+//! Polls start with the pasteboard's change count, so an unchanged clipboard
+//! isn't read at all. Used automatically on macOS when built with the
+//! `native-clipboard` feature; without it the shell tools are used.
+
+use cocoa::appkit::NSPasteboard;
+use cocoa::base::{id, nil};
+use cocoa::foundation::{NSArray, NSAutoreleasePool, NSData, NSString};
+use std::ffi::CStr;
+use std::sync::atomic::{AtomicIsize, Ordering};
+
+/// Image types read, most preferred first, with the MIME type each is handled as
+pub const IMAGE_TYPES: &[(&str, &str)] = &[("public.png", "image/png"), ("public.tiff", "image/tiff")];
+
+/// Plain text
+const TEXT_TYPE: &str = "public.utf8-plain-text";
+
+/// The change count [`changed`] last saw
+static SEEN: AtomicIsize = AtomicIsize::new(-1);
+
+/// The pasteboard's change count, bumped by every copy
+pub fn change_count() -> isize {
+    unsafe {
+        let pool = NSAutoreleasePool::new(nil);
+        let count = NSPasteboard::generalPasteboard(nil).changeCount() as isize;
+        pool.drain();
+        count
+    }
+}
+
+/// Whether anything was copied since the last call; true the first time
+pub fn changed() -> bool {
+    let count = change_count();
+    SEEN.swap(count, Ordering::Relaxed) != count
+}
+
+/// The types on offer, as uniform type identifiers like `public.png`
+pub fn types() -> Vec<String> {
+    unsafe {
+        let pool = NSAutoreleasePool::new(nil);
+        let types = NSPasteboard::generalPasteboard(nil).types();
+        let mut offered = Vec::new();
+        if types != nil {
+            for i in 0..types.count() {
+                if let Some(name) = to_string(types.objectAtIndex(i)) {
+                    offered.push(name);
+                }
+            }
+        }
+        pool.drain();
+        offered
+    }
+}
+
+/// The data of type `uti`, if the pasteboard has any
+pub fn data(uti: &str) -> Option<Vec<u8>> {
+    unsafe {
+        let pool = NSAutoreleasePool::new(nil);
+        let data = NSPasteboard::generalPasteboard(nil).dataForType(ns_string(uti));
+        let bytes = if data == nil || data.length() == 0 {
+            None
+        } else {
+            Some(std::slice::from_raw_parts(data.bytes() as *const u8, data.length() as usize).to_vec())
+        };
+        pool.drain();
+        bytes
+    }
+}
+
+/// The pasteboard's plain text, if any
+pub fn text() -> Option<String> {
+    unsafe {
+        let pool = NSAutoreleasePool::new(nil);
+        let text = to_string(NSPasteboard::generalPasteboard(nil).stringForType(ns_string(TEXT_TYPE)));
+        pool.drain();
+        text
+    }
+}
+
+/// An autoreleased `NSString`; call inside a pool
+unsafe fn ns_string(value: &str) -> id {
+    NSString::alloc(nil).init_str(value).autorelease()
+}
+
+unsafe fn to_string(string: id) -> Option<String> {
+    if string == nil {
+        return None;
+    }
+    let utf8 = string.UTF8String();
+    if utf8.is_null() {
+        return None;
+    }
+    Some(CStr::from_ptr(utf8).to_string_lossy().into_owned())
+}
@@ -0,0 +1,131 @@
+//! The form image paths take when they replace a clipboard image or a pasted
+//! payload (`path_format`).
+//!
+//! Paths are absolute unless a format says otherwise. Relative forms need a
+//! working directory: command-line substitution uses its own, and clipboard
+//! replacement uses the one the shell hooks noted at the last prompt, next to
+//! the terminal in `~/.klipdot/active-terminal`. Without one the path stays
+//! absolute. The chosen form is then quoted per `path_quoting`.
+
+use crate::{
+    config::{Config, PathFormat, PathQuoting},
+    substitution::{file_uri, quote_path},
+};
+use std::path::{Component, Path, PathBuf};
+
+/// Files or directories marking the root of a project
+const PROJECT_MARKERS: &[&str] = &[".git", ".hg", ".jj"];
+
+/// `path` in `format`, relative to `cwd` where the format asks for it, and
+/// quoted per `quoting`
+pub fn render(path: &Path, format: PathFormat, quoting: PathQuoting, cwd: Option<&Path>) -> String {
+    if format == PathFormat::Uri || quoting == PathQuoting::Uri {
+        return file_uri(path);
+    }
+
+    let base = match format {
+        PathFormat::Relative => cwd.map(Path::to_path_buf),
+        PathFormat::Project => cwd.and_then(project_root),
+        _ => None,
+    };
+    if let Some(relative) = base.and_then(|base| relative_to(path, &base)) {
+        return quote_path(&relative, quoting);
+    }
+
+    if format == PathFormat::Home {
+        if let Some(rest) = dirs::home_dir().and_then(|home| path.strip_prefix(home).ok().map(Path::to_path_buf)) {
+            // The tilde has to stay unquoted to be expanded
+            if rest.as_os_str().is_empty() {
+                return "~".to_string();
+            }
+            return format!("~/{}", quote_path(&rest, quoting));
+        }
+    }
+    quote_path(path, quoting)
+}
+
+/// `path` as it should replace a clipboard image copied in `app`
+pub async fn for_clipboard(config: &Config, path: &Path, app: Option<&str>) -> String {
+    let format = config.path_format.for_name(app);
+    let cwd = match format {
+        PathFormat::Relative | PathFormat::Project => noted_cwd().await,
+        _ => None,
+    };
+    render(path, format, config.path_quoting, cwd.as_deref())
+}
+
+/// Working directory of the shell that last showed a prompt
+pub async fn noted_cwd() -> Option<PathBuf> {
+    let home_dir = crate::get_home_dir().ok()?;
+    let noted = tokio::fs::read_to_string(home_dir.join(crate::ACTIVE_TERMINAL_FILE)).await.ok()?;
+    noted.lines().nth(1).map(PathBuf::from).filter(|cwd| cwd.is_absolute() && cwd.is_dir())
+}
+
+/// The closest directory at or above `cwd` holding a project marker
+pub fn project_root(cwd: &Path) -> Option<PathBuf> {
+    cwd.ancestors()
+        .find(|dir| PROJECT_MARKERS.iter().any(|marker| dir.join(marker).exists()))
+        .map(Path::to_path_buf)
+}
+
+/// `path` relative to the directory `base`, with `..` where needed. Both must
+/// be absolute.
+pub fn relative_to(path: &Path, base: &Path) -> Option<PathBuf> {
+    if !path.is_absolute() || !base.is_absolute() {
+        return None;
+    }
+    let path: Vec<Component> = path.components().collect();
+    let base: Vec<Component> = base.components().collect();
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..base.len() {
+        relative.push("..");
+    }
+    relative.extend(&path[common..]);
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    Some(relative)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_relative_to() {
+        let shot = Path::new("/home/me/.klipdot/screenshots/a.png");
+        assert_eq!(relative_to(shot, Path::new("/home/me")).unwrap(), Path::new(".klipdot/screenshots/a.png"));
+        assert_eq!(
+            relative_to(shot, Path::new("/home/me/code/app")).unwrap(),
+            Path::new("../../.klipdot/screenshots/a.png")
+        );
+        assert!(relative_to(Path::new("a.png"), Path::new("/home/me")).is_none());
+    }
+
+    #[test]
+    fn test_render() {
+        let temp_dir = TempDir::new().unwrap();
+        let project = temp_dir.path().join("app");
+        std::fs::create_dir_all(project.join(".git")).unwrap();
+        std::fs::create_dir_all(project.join("src")).unwrap();
+        let shot = temp_dir.path().join("shots").join("my shot.png");
+        let single = PathQuoting::Single;
+
+        assert_eq!(render(&shot, PathFormat::Absolute, single, None), quote_path(&shot, single));
+        assert_eq!(render(&shot, PathFormat::Relative, single, Some(&project.join("src"))), "'../../shots/my shot.png'");
+        assert_eq!(render(&shot, PathFormat::Project, single, Some(&project.join("src"))), "'../shots/my shot.png'");
+        assert_eq!(render(&shot, PathFormat::Relative, PathQuoting::Backslash, Some(temp_dir.path())), r"shots/my\ shot.png");
+        assert!(render(&shot, PathFormat::Uri, single, None).ends_with("/shots/my%20shot.png"));
+
+        // No working directory: absolute
+        assert_eq!(render(&shot, PathFormat::Relative, single, None), quote_path(&shot, single));
+
+        if let Some(home) = dirs::home_dir() {
+            let shot = home.join("Pictures").join("it's.png");
+            assert_eq!(render(&shot, PathFormat::Home, single, None), r"~/'Pictures/it'\''s.png'");
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Automatic pauses of clipboard interception.
+//!
+//! Interception stops during configured quiet hours and while a denied
+//! application (screen sharing, presentations) has focus, so images copied
+//! there are left on the clipboard untouched.
+
+use crate::{
+    config::{PauseConfig, QuietWindow},
+    error::Result,
+    Error,
+};
+use chrono::{Datelike, Duration, NaiveDateTime, NaiveTime, Weekday};
+use std::fmt;
+
+/// Why interception is currently paused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PauseReason {
+    /// Inside a quiet-hours window, given as "start-end"
+    Schedule(String),
+    /// The focused application is denied, or not on the allow list
+    FocusedApp(String),
+}
+
+impl fmt::Display for PauseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PauseReason::Schedule(window) => write!(f, "quiet hours {}", window),
+            PauseReason::FocusedApp(app) => write!(f, "{} is focused", app),
+        }
+    }
+}
+
+/// Check the schedule and the focused application (see [`crate::focus`])
+pub fn check(config: &PauseConfig, focused_app: Option<&str>) -> Option<PauseReason> {
+    scheduled_pause(config, chrono::Local::now().naive_local()).or_else(|| app_pause(config, focused_app))
+}
+
+/// The quiet-hours window containing `now`, if any
+pub fn scheduled_pause(config: &PauseConfig, now: NaiveDateTime) -> Option<PauseReason> {
+    config
+        .schedules
+        .iter()
+        .find(|window| window_contains(window, now).unwrap_or(false))
+        .map(|window| PauseReason::Schedule(format!("{}-{}", window.start, window.end)))
+}
+
+/// Whether the focused `app` pauses interception.
+///
+/// Matching is a case-insensitive substring test against the app id or
+/// window class. An unknown focused app never pauses.
+pub fn app_pause(config: &PauseConfig, app: Option<&str>) -> Option<PauseReason> {
+    let app = app?;
+    let lower = app.to_lowercase();
+    let matches = |patterns: &[String]| patterns.iter().any(|pattern| lower.contains(&pattern.to_lowercase()));
+
+    let denied = matches(&config.deny_apps);
+    let not_allowed = !config.allow_apps.is_empty() && !matches(&config.allow_apps);
+    (denied || not_allowed).then(|| PauseReason::FocusedApp(app.to_string()))
+}
+
+/// Parse a window's times and days, reporting the first invalid field
+pub fn validate_window(window: &QuietWindow) -> Result<()> {
+    parse_time(&window.start)?;
+    parse_time(&window.end)?;
+    for day in &window.days {
+        parse_day(day)?;
+    }
+    Ok(())
+}
+
+/// Whether `now` falls in `window`; windows ending before they start run past midnight
+/// and belong to the day they start on
+fn window_contains(window: &QuietWindow, now: NaiveDateTime) -> Result<bool> {
+    let start = parse_time(&window.start)?;
+    let end = parse_time(&window.end)?;
+    let time = now.time();
+
+    let started_on = if start <= end {
+        if time < start || time >= end {
+            return Ok(false);
+        }
+        now.date()
+    } else if time >= start {
+        now.date()
+    } else if time < end {
+        now.date() - Duration::days(1)
+    } else {
+        return Ok(false);
+    };
+
+    if window.days.is_empty() {
+        return Ok(true);
+    }
+    for day in &window.days {
+        if parse_day(day)? == started_on.weekday() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn parse_time(value: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(value, "%H:%M")
+        .map_err(|e| Error::Config(format!("Invalid quiet hours time {:?} (expected HH:MM): {}", value, e)))
+}
+
+fn parse_day(value: &str) -> Result<Weekday> {
+    value
+        .parse()
+        .map_err(|_| Error::Config(format!("Invalid quiet hours day {:?} (expected mon, tue, ...)", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str, time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(&format!("{} {}", date, time), "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn window(start: &str, end: &str, days: &[&str]) -> QuietWindow {
+        QuietWindow {
+            start: start.to_string(),
+            end: end.to_string(),
+            days: days.iter().map(|day| day.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_scheduled_pause() {
+        let config = PauseConfig {
+            schedules: vec![window("09:00", "10:00", &["mon"]), window("22:00", "07:00", &["fri"])],
+            ..PauseConfig::default()
+        };
+
+        // 2024-01-01 is a Monday, 2024-01-05 a Friday
+        assert_eq!(
+            scheduled_pause(&config, at("2024-01-01", "09:30")),
+            Some(PauseReason::Schedule("09:00-10:00".to_string()))
+        );
+        assert_eq!(scheduled_pause(&config, at("2024-01-01", "10:00")), None);
+        assert_eq!(scheduled_pause(&config, at("2024-01-02", "09:30")), None);
+
+        // The overnight window belongs to Friday, including the early hours of Saturday
+        assert!(scheduled_pause(&config, at("2024-01-05", "23:00")).is_some());
+        assert!(scheduled_pause(&config, at("2024-01-06", "06:59")).is_some());
+        assert_eq!(scheduled_pause(&config, at("2024-01-05", "06:00")), None);
+    }
+
+    #[test]
+    fn test_app_pause() {
+        let config = PauseConfig {
+            deny_apps: vec!["zoom".to_string(), "OBS".to_string()],
+            ..PauseConfig::default()
+        };
+        assert_eq!(app_pause(&config, Some("zoom.us")), Some(PauseReason::FocusedApp("zoom.us".to_string())));
+        assert!(app_pause(&config, Some("com.obsproject.Studio")).is_some());
+        assert_eq!(app_pause(&config, Some("foot")), None);
+        assert_eq!(app_pause(&config, None), None);
+
+        let config = PauseConfig {
+            allow_apps: vec!["kitty".to_string()],
+            deny_apps: Vec::new(),
+            ..PauseConfig::default()
+        };
+        assert_eq!(app_pause(&config, Some("kitty")), None);
+        assert!(app_pause(&config, Some("firefox")).is_some());
+    }
+
+    #[test]
+    fn test_validate_window() {
+        assert!(validate_window(&window("22:00", "07:00", &["Fri", "sat"])).is_ok());
+        assert!(validate_window(&window("25:00", "07:00", &[])).is_err());
+        assert!(validate_window(&window("22:00", "07:00", &["someday"])).is_err());
+    }
+}
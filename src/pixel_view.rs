@@ -0,0 +1,209 @@
+//! An image filling the terminal with a cursor moved over it by keys, for the
+//! interactive commands (`annotate`, `pick-color`).
+//!
+//! The image is scaled down once to fit above the status line and shown in
+//! truecolor half blocks on the alternate screen, so it works in any terminal
+//! with 24-bit color, over SSH too. The cursor is kept in image pixels, a
+//! preview pixel at a time unless asked to go finer.
+
+use crate::{ansi_export, error::Result};
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{KeyCode, KeyEvent, KeyModifiers},
+    execute, queue,
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use image::{imageops::FilterType, DynamicImage, Rgba, RgbaImage};
+use std::io::{self, Write};
+
+/// Terminal rows kept below the image for the status line
+pub const STATUS_ROWS: u16 = 2;
+
+/// Cursor steps taken by one shifted movement key
+const FAST_STEPS: i64 = 10;
+
+/// A position in an image, moved by arrow keys and `hjkl`
+#[derive(Debug, Clone)]
+pub struct Cursor {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    /// Image pixels moved per key
+    step: u32,
+}
+
+impl Cursor {
+    /// A cursor in the middle of a `width` × `height` image
+    pub fn centered(width: u32, height: u32) -> Self {
+        Self {
+            x: width / 2,
+            y: height / 2,
+            width: width.max(1),
+            height: height.max(1),
+            step: 1,
+        }
+    }
+
+    pub fn position(&self) -> (u32, u32) {
+        (self.x, self.y)
+    }
+
+    pub fn step(&self) -> u32 {
+        self.step
+    }
+
+    pub fn set_step(&mut self, step: u32) {
+        self.step = step.max(1);
+    }
+
+    /// Move for `key` if it's a movement key, shifted or `HJKL` going ten
+    /// times as far; `hjkl` only count when `letters` is set
+    pub fn handle(&mut self, key: KeyEvent, letters: bool) -> bool {
+        let shift = key.modifiers.contains(KeyModifiers::SHIFT);
+        let (dx, dy, fast) = match key.code {
+            KeyCode::Left => (-1, 0, shift),
+            KeyCode::Right => (1, 0, shift),
+            KeyCode::Up => (0, -1, shift),
+            KeyCode::Down => (0, 1, shift),
+            KeyCode::Char(c) if letters => {
+                let (dx, dy) = match c.to_ascii_lowercase() {
+                    'h' => (-1, 0),
+                    'l' => (1, 0),
+                    'k' => (0, -1),
+                    'j' => (0, 1),
+                    _ => return false,
+                };
+                (dx, dy, c.is_ascii_uppercase())
+            }
+            _ => return false,
+        };
+        let distance = self.step as i64 * if fast { FAST_STEPS } else { 1 };
+        self.x = (self.x as i64 + dx * distance).clamp(0, self.width as i64 - 1) as u32;
+        self.y = (self.y as i64 + dy * distance).clamp(0, self.height as i64 - 1) as u32;
+        true
+    }
+}
+
+/// An image scaled to fit the terminal
+pub struct Viewport {
+    base: RgbaImage,
+    cols: u32,
+    /// Preview pixels per image pixel
+    scale: f32,
+}
+
+impl Viewport {
+    /// `img` fitted into a terminal of `cols` × `rows`, keeping the status rows free
+    pub fn fit(img: &DynamicImage, (cols, rows): (u16, u16)) -> Self {
+        let (width, height) = (img.width().max(1), img.height().max(1));
+        let rows = rows.saturating_sub(STATUS_ROWS).max(1) as u32;
+        let mut cols = (cols.max(1) as u32).min(width);
+        // Two image rows per terminal row
+        if height as u64 * cols as u64 > rows as u64 * 2 * width as u64 {
+            cols = ((rows * 2) as u64 * width as u64 / height as u64).max(1) as u32;
+        }
+        let pixel_rows = (((height as f64 * cols as f64 / width as f64) / 2.0).round().max(1.0) as u32) * 2;
+        Self {
+            base: img.resize_exact(cols, pixel_rows, FilterType::Triangle).to_rgba8(),
+            cols,
+            scale: cols as f32 / width as f32,
+        }
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Image pixels per preview pixel, the cursor's natural step
+    pub fn step(&self) -> u32 {
+        (1.0 / self.scale).ceil() as u32
+    }
+
+    /// A copy of the scaled image to draw on
+    pub fn canvas(&self) -> RgbaImage {
+        self.base.clone()
+    }
+
+    /// `canvas` as terminal text, with the preview pixel under `cursor` (in
+    /// image pixels) inverted
+    pub fn render(&self, mut canvas: RgbaImage, (x, y): (u32, u32)) -> String {
+        let x = ((x as f32 * self.scale) as u32).min(canvas.width() - 1);
+        let y = ((y as f32 * self.scale) as u32).min(canvas.height() - 1);
+        let under = *canvas.get_pixel(x, y);
+        canvas.put_pixel(x, y, Rgba([255 - under[0], 255 - under[1], 255 - under[2], 255]));
+        ansi_export::render_blocks(&DynamicImage::ImageRgba8(canvas), self.cols)
+    }
+}
+
+/// Raw mode on the alternate screen, restored even if the command bails out
+pub struct ScreenGuard;
+
+impl ScreenGuard {
+    pub fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        Ok(Self)
+    }
+}
+
+impl Drop for ScreenGuard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Size of the terminal; some ptys report zero, where a classic 80×24 is assumed
+pub fn terminal_size() -> io::Result<(u16, u16)> {
+    Ok(match terminal::size()? {
+        (0, _) | (_, 0) => (80, 24),
+        size => size,
+    })
+}
+
+/// Replace the screen with `frame` and a `status` line cut to the terminal's width
+pub fn draw(frame: &str, status: &str) -> Result<()> {
+    let columns = terminal_size()?.0 as usize;
+    let mut stdout = io::stdout();
+    queue!(stdout, MoveTo(0, 0), Clear(ClearType::All))?;
+    // Raw mode doesn't turn newlines into line starts
+    write!(stdout, "{}", frame.replace('\n', "\r\n"))?;
+    let status: String = status.chars().take(columns.max(1)).collect();
+    write!(stdout, "{}", status)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor() {
+        let mut cursor = Cursor::centered(100, 50);
+        cursor.set_step(5);
+        assert!(cursor.handle(KeyEvent::new(KeyCode::Char('l'), KeyModifiers::NONE), true));
+        assert!(cursor.handle(KeyEvent::new(KeyCode::Down, KeyModifiers::NONE), true));
+        assert_eq!(cursor.position(), (55, 30));
+
+        // Shifted keys go further, and the cursor stays in the image
+        cursor.handle(KeyEvent::new(KeyCode::Right, KeyModifiers::SHIFT), true);
+        cursor.handle(KeyEvent::new(KeyCode::Char('K'), KeyModifiers::SHIFT), true);
+        assert_eq!(cursor.position(), (99, 0));
+
+        assert!(!cursor.handle(KeyEvent::new(KeyCode::Char('h'), KeyModifiers::NONE), false));
+        assert!(!cursor.handle(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE), true));
+        assert_eq!(cursor.position(), (99, 0));
+    }
+
+    #[test]
+    fn test_viewport_fits_terminal() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::new(1000, 1000));
+        let viewport = Viewport::fit(&img, (200, 52));
+        // 50 rows for the image, two pixels each
+        assert_eq!(viewport.canvas().dimensions(), (100, 100));
+        assert_eq!(viewport.step(), 10);
+        assert_eq!(viewport.render(viewport.canvas(), (500, 500)).lines().count(), 50);
+    }
+}
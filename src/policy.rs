@@ -0,0 +1,240 @@
+//! Admin-managed policy: a read-only file outside the user's reach
+//! (`/etc/klipdot/policy.json` on Unix) whose settings the user's config
+//! can't override.
+//!
+//! ```json
+//! {
+//!   "enforce": { "uploads": { "hosts": ["files.example.com"] } },
+//!   "require_strip_metadata": true,
+//!   "forbidden_upload_targets": ["discord", "github", "*.example.org"],
+//!   "max_retention_days": 14
+//! }
+//! ```
+//!
+//! `enforce` holds settings in the config file's format, merged over the
+//! user's config each time it's loaded. Forbidden upload targets name a
+//! service (`slack`, `discord`, `github`, `gitlab`, `scp`, `rsync`) or a host
+//! and its subdomains. The retention maximum caps `cleanup_days` and
+//! `klipdot cleanup --days`, wins over scripts that keep images, and is
+//! enforced by the daemon.
+//!
+//! A policy file that can't be read or parsed stops klipdot from starting
+//! rather than being ignored.
+
+use crate::{config::Config, error::Result, Error};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Exit status of `klipdot prepare-upload` when the policy forbids the
+/// copy, so the shell wrappers don't run it
+pub const FORBIDDEN_EXIT_CODE: i32 = 77;
+
+/// How often the daemon deletes images past the retention maximum
+const RETENTION_CHECK: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Settings, in the config file's format, that replace the user's
+    pub enforce: Map<String, Value>,
+    /// Re-encode uploaded images so EXIF and other metadata stay local
+    pub require_strip_metadata: bool,
+    /// Services and hosts images may not be uploaded to
+    pub forbidden_upload_targets: Vec<String>,
+    /// Longest images may be kept, in days
+    pub max_retention_days: Option<u32>,
+    /// File the policy was loaded from; `None` when there is no policy
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+/// Where administrators put the policy
+pub fn system_policy_path() -> PathBuf {
+    #[cfg(windows)]
+    {
+        let program_data = std::env::var_os("ProgramData").unwrap_or_else(|| r"C:\ProgramData".into());
+        PathBuf::from(program_data).join(crate::APP_NAME).join("policy.json")
+    }
+    #[cfg(not(windows))]
+    {
+        PathBuf::from("/etc").join(crate::APP_NAME).join("policy.json")
+    }
+}
+
+impl Policy {
+    /// The system policy, or an empty one when there is none
+    pub fn system() -> Result<Self> {
+        Self::load(&system_policy_path())
+    }
+
+    /// The policy at `path`, or an empty one when the file doesn't exist
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(Error::Config(format!("Can't read policy {:?}: {}", path, e))),
+        };
+        let mut policy: Policy =
+            serde_json::from_str(&content).map_err(|e| Error::Config(format!("Invalid policy {:?}: {}", path, e)))?;
+        if policy.max_retention_days == Some(0) {
+            return Err(Error::Config(format!("Invalid policy {:?}: max_retention_days must be greater than 0", path)));
+        }
+        policy.path = Some(path.to_path_buf());
+        Ok(policy)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.path.is_none()
+    }
+
+    /// `config` with the policy's settings in force
+    pub fn apply(&self, config: Config) -> Result<Config> {
+        if self.is_empty() {
+            return Ok(config);
+        }
+        let config_file = config.config_file.clone();
+        let mut merged = serde_json::to_value(&config)?;
+        merge(&mut merged, &Value::Object(self.enforce.clone()));
+        let mut config: Config = serde_json::from_value(merged)
+            .map_err(|e| Error::Config(format!("Policy {:?} enforces an invalid setting: {}", self.path, e)))?;
+
+        config.config_file = config_file;
+        if self.require_strip_metadata {
+            config.uploads.strip_metadata = true;
+        }
+        if let Some(max) = self.max_retention_days {
+            config.cleanup_days = config.cleanup_days.min(max);
+        }
+        config.policy = self.clone();
+        Ok(config)
+    }
+
+    /// Refuse an upload to `service` (such as `slack` or `scp`) at `host`
+    /// when the policy forbids it
+    pub fn check_upload(&self, service: &str, host: &str) -> Result<()> {
+        let host = host.to_ascii_lowercase();
+        let forbidden = self
+            .forbidden_upload_targets
+            .iter()
+            .find(|target| target.eq_ignore_ascii_case(service) || crate::matches_domain(&host, target));
+        match forbidden {
+            Some(target) => Err(Error::Permission(format!(
+                "Uploads to {} ({}) are forbidden by policy ({})",
+                host, service, target
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Retention in days, capped at the policy's maximum
+    pub fn retention(&self, days: u32) -> u32 {
+        self.max_retention_days.map_or(days, |max| days.min(max))
+    }
+}
+
+/// Merge `overlay` into `base`, object by object; anything else in `overlay` replaces what's in `base`
+fn merge(base: &mut Value, overlay: &Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay.clone(),
+    }
+}
+
+/// Delete images past the policy's retention maximum now and every hour;
+/// never finishes, even without a maximum
+pub async fn enforce_retention(config: Config) {
+    let Some(days) = config.policy.max_retention_days else {
+        return std::future::pending().await;
+    };
+    let mut interval = tokio::time::interval(RETENTION_CHECK);
+    loop {
+        interval.tick().await;
+        match config.cleanup_old_screenshots(days).await {
+            Ok(0) => {}
+            Ok(count) => info!("Deleted {} images past the {}-day retention policy", count, days),
+            Err(e) => {
+                warn!("Failed to enforce the retention policy: {}", e);
+                crate::error_history::record_error("policy", &e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn policy(temp_dir: &TempDir, content: &str) -> Result<Policy> {
+        let path = temp_dir.path().join("policy.json");
+        std::fs::write(&path, content).unwrap();
+        Policy::load(&path)
+    }
+
+    #[test]
+    fn test_apply() {
+        let temp_dir = TempDir::new().unwrap();
+        let policy = policy(
+            &temp_dir,
+            r#"{
+                "enforce": { "uploads": { "hosts": ["files.example.com"] }, "compression_quality": 70 },
+                "require_strip_metadata": true,
+                "max_retention_days": 14
+            }"#,
+        )
+        .unwrap();
+
+        let mut user = Config { cleanup_days: 90, compression_quality: 100, ..Config::default() };
+        user.uploads.strip_metadata = false;
+        user.uploads.enabled = true;
+        let config = policy.apply(user.clone()).unwrap();
+        assert_eq!((config.cleanup_days, config.compression_quality), (14, 70));
+        assert!(config.uploads.strip_metadata);
+        // Only what the policy names is replaced
+        assert_eq!(config.uploads.hosts, ["files.example.com"]);
+        assert!(config.uploads.enabled);
+        assert_eq!(config.config_file, user.config_file);
+        assert_eq!(config.policy.retention(30), 14);
+
+        let short = Config { cleanup_days: 7, ..user };
+        assert_eq!(policy.apply(short).unwrap().cleanup_days, 7);
+    }
+
+    #[test]
+    fn test_load() {
+        let temp_dir = TempDir::new().unwrap();
+        let missing = Policy::load(&temp_dir.path().join("missing.json")).unwrap();
+        assert!(missing.is_empty());
+        assert_eq!(missing.retention(30), 30);
+
+        // Mistakes stop klipdot rather than leaving settings unenforced
+        assert!(policy(&temp_dir, r#"{"forbiden_upload_targets": ["slack"]}"#).is_err());
+        assert!(policy(&temp_dir, r#"{"max_retention_days": 0}"#).is_err());
+        let bad_setting = policy(&temp_dir, r#"{"enforce": {"cleanup_days": "never"}}"#).unwrap();
+        assert!(bad_setting.apply(Config::default()).is_err());
+    }
+
+    #[test]
+    fn test_check_upload() {
+        let temp_dir = TempDir::new().unwrap();
+        let policy = policy(&temp_dir, r#"{"forbidden_upload_targets": ["Discord", "*.example.org"]}"#).unwrap();
+        assert!(policy.check_upload("discord", "discord.com").is_err());
+        assert!(policy.check_upload("scp", "Files.Example.org").is_err());
+        assert!(policy.check_upload("github", "example.org").is_err());
+        assert!(policy.check_upload("slack", "slack.com").is_ok());
+        assert!(policy.check_upload("scp", "example.org.evil.com").is_ok());
+        assert!(Policy::default().check_upload("discord", "discord.com").is_ok());
+    }
+}
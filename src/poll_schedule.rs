@@ -0,0 +1,108 @@
+//! How often the clipboard monitor polls, when polling is all there is.
+//!
+//! Right after a change or a screenshot tool event the clipboard is polled at
+//! the configured interval. Once it has been idle for `polling.idle_after_secs`
+//! the interval doubles for every further such period, up to
+//! `polling.max_interval_ms`, so an idle session isn't spawning clipboard
+//! tools several times a second. While a screenshot tool's capture is
+//! expected it is re-read every `polling.screenshot_interval_ms`.
+//!
+//! Polling that quickly can see one change more than once, such as a tool
+//! writing the clipboard twice or reads alternating between clipboard tools.
+//! Content that was already handled within `polling.debounce_ms` is taken as
+//! the same change seen again rather than a new one.
+
+use crate::config::PollingConfig;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub struct PollSchedule {
+    polling: PollingConfig,
+    last_activity: Instant,
+    /// Hashes of content handled within the debounce window, oldest first
+    handled: VecDeque<(u64, Instant)>,
+}
+
+impl PollSchedule {
+    pub fn new(polling: PollingConfig, now: Instant) -> Self {
+        Self { polling, last_activity: now, handled: VecDeque::new() }
+    }
+
+    /// The clipboard changed or a screenshot tool ran at `now`
+    pub fn activity(&mut self, now: Instant) {
+        self.last_activity = now;
+    }
+
+    /// How long to wait before the next poll, `base` while recently active
+    pub fn interval(&self, base: Duration, now: Instant) -> Duration {
+        let idle_after = Duration::from_secs(self.polling.idle_after_secs);
+        let max = Duration::from_millis(self.polling.max_interval_ms).max(base);
+        if !self.polling.adaptive || idle_after.is_zero() {
+            return base;
+        }
+        let idle_periods = (now.duration_since(self.last_activity).as_secs_f64() / idle_after.as_secs_f64()) as u32;
+        match idle_periods {
+            0 => base,
+            periods => base.saturating_mul(1 << periods.min(16)).min(max),
+        }
+    }
+
+    /// How long to wait while a screenshot tool's capture is expected
+    pub fn screenshot_interval(&self) -> Duration {
+        Duration::from_millis(self.polling.screenshot_interval_ms)
+    }
+
+    /// Whether `content` was already handled within the debounce window;
+    /// if not, it is remembered as handled at `now`
+    pub fn repeat(&mut self, content: &[u8], now: Instant) -> bool {
+        let window = Duration::from_millis(self.polling.debounce_ms);
+        while self.handled.front().is_some_and(|(_, at)| now.duration_since(*at) > window) {
+            self.handled.pop_front();
+        }
+
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        let hash = hasher.finish();
+        if self.handled.iter().any(|(handled, _)| *handled == hash) {
+            return true;
+        }
+        if !window.is_zero() {
+            self.handled.push_back((hash, now));
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poll_schedule() {
+        let start = Instant::now();
+        let mut schedule = PollSchedule::new(PollingConfig::default(), start);
+        let base = Duration::from_millis(250);
+        let after = |secs: u64| start + Duration::from_secs(secs);
+
+        // Defaults: idle after 10s, doubling up to 3s
+        assert_eq!(schedule.interval(base, after(5)), base);
+        assert_eq!(schedule.interval(base, after(10)), base * 2);
+        assert_eq!(schedule.interval(base, after(35)), base * 8);
+        assert_eq!(schedule.interval(base, after(3600)), Duration::from_secs(3));
+        schedule.activity(after(3600));
+        assert_eq!(schedule.interval(base, after(3601)), base);
+        assert_eq!(schedule.screenshot_interval(), Duration::from_millis(100));
+
+        // The same content within the debounce window is a repeat
+        assert!(!schedule.repeat(b"a", start));
+        assert!(!schedule.repeat(b"b", start + Duration::from_millis(100)));
+        assert!(schedule.repeat(b"a", start + Duration::from_millis(200)));
+        assert!(!schedule.repeat(b"a", start + Duration::from_secs(2)));
+
+        let fixed = PollSchedule::new(PollingConfig { adaptive: false, debounce_ms: 0, ..PollingConfig::default() }, start);
+        assert_eq!(fixed.interval(base, after(3600)), base);
+    }
+}
@@ -0,0 +1,242 @@
+//! Bounded worker queue for image processing.
+//!
+//! Decoding and re-encoding a large image takes long enough to delay the next
+//! clipboard read, so the clipboard monitor submits images here and picks up
+//! the stored paths as they complete. At most `workers` images are processed
+//! at once and at most `queue_capacity` wait; a full queue rejects new work
+//! with a recoverable error instead of buffering a burst without bound.
+
+use crate::{config::ProcessingConfig, error::Result, image_processor::ImageProcessor, window_crop::WindowGeometry, Error};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::Semaphore;
+use tracing::debug;
+
+/// Queue counters, shared by every queue in the process
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueMetrics {
+    /// Jobs waiting for or undergoing processing
+    pub depth: u64,
+    pub submitted: u64,
+    pub completed: u64,
+    pub failed: u64,
+    /// Jobs turned away because the queue was full
+    pub rejected: u64,
+    /// Time from submission to completion, summed over finished jobs
+    pub total_latency_ms: u64,
+    pub max_latency_ms: u64,
+}
+
+impl QueueMetrics {
+    /// Mean time from submission to completion
+    pub fn average_latency(&self) -> Option<Duration> {
+        let finished = self.completed + self.failed;
+        (finished > 0).then(|| Duration::from_millis(self.total_latency_ms / finished))
+    }
+}
+
+static METRICS: Lazy<Mutex<QueueMetrics>> = Lazy::new(|| Mutex::new(QueueMetrics::default()));
+
+fn record(update: impl FnOnce(&mut QueueMetrics)) {
+    update(&mut METRICS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+}
+
+/// Snapshot of the processing queue counters
+pub fn metrics() -> QueueMetrics {
+    *METRICS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Outcome of one submitted image
+#[derive(Debug)]
+pub struct ProcessedImage {
+    /// Identifier returned by [`ProcessingQueue::submit`]
+    pub id: u64,
+    pub source: String,
+    pub result: Result<PathBuf>,
+    pub latency: Duration,
+}
+
+struct Job {
+    id: u64,
+    data: Vec<u8>,
+    source: String,
+    app: Option<String>,
+    window: Option<WindowGeometry>,
+    submitted: Instant,
+}
+
+pub struct ProcessingQueue {
+    jobs: mpsc::Sender<Job>,
+    completed: mpsc::UnboundedReceiver<ProcessedImage>,
+    next_id: u64,
+}
+
+impl ProcessingQueue {
+    /// Start the workers; must be called from within a Tokio runtime
+    pub fn new(processor: ImageProcessor, settings: &ProcessingConfig) -> Self {
+        let (jobs, receiver) = mpsc::channel(settings.queue_capacity.max(1));
+        let (results, completed) = mpsc::unbounded_channel();
+        tokio::spawn(dispatch(Arc::new(processor), receiver, results, settings.workers.max(1)));
+
+        Self {
+            jobs,
+            completed,
+            next_id: 0,
+        }
+    }
+
+    /// Queue `data` from `source` (and `app` and the focused `window`, if
+    /// known) for processing, returning the job's id
+    pub fn submit(&mut self, data: Vec<u8>, source: &str, app: Option<String>, window: Option<WindowGeometry>) -> Result<u64> {
+        let id = self.next_id;
+        let job = Job {
+            id,
+            data,
+            source: source.to_string(),
+            app,
+            window,
+            submitted: Instant::now(),
+        };
+
+        match self.jobs.try_send(job) {
+            Ok(()) => {
+                self.next_id += 1;
+                record(|metrics| {
+                    metrics.submitted += 1;
+                    metrics.depth += 1;
+                });
+                Ok(id)
+            }
+            Err(TrySendError::Full(_)) => {
+                record(|metrics| metrics.rejected += 1);
+                Err(Error::Timeout("Image processing queue is full".to_string()))
+            }
+            Err(TrySendError::Closed(_)) => Err(Error::Internal("Image processing workers have stopped".to_string())),
+        }
+    }
+
+    /// Wait for the next finished job
+    pub async fn next_completed(&mut self) -> Option<ProcessedImage> {
+        self.completed.recv().await
+    }
+}
+
+async fn dispatch(
+    processor: Arc<ImageProcessor>,
+    mut jobs: mpsc::Receiver<Job>,
+    results: mpsc::UnboundedSender<ProcessedImage>,
+    workers: usize,
+) {
+    let permits = Arc::new(Semaphore::new(workers));
+
+    // A job is only taken off the channel once a worker is free, so the
+    // channel's capacity is all the backlog there is
+    loop {
+        let Ok(permit) = permits.clone().acquire_owned().await else {
+            break;
+        };
+        let Some(job) = jobs.recv().await else {
+            break;
+        };
+        let processor = processor.clone();
+        let results = results.clone();
+
+        tokio::spawn(async move {
+            let result = processor
+                .process_image_data_from(&job.data, &job.source, job.app.as_deref(), job.window.as_ref())
+                .await;
+            let latency = job.submitted.elapsed();
+            drop(permit);
+
+            let latency_ms = latency.as_millis() as u64;
+            record(|metrics| {
+                metrics.depth = metrics.depth.saturating_sub(1);
+                if result.is_ok() {
+                    metrics.completed += 1;
+                } else {
+                    metrics.failed += 1;
+                }
+                metrics.total_latency_ms += latency_ms;
+                metrics.max_latency_ms = metrics.max_latency_ms.max(latency_ms);
+            });
+            debug!("Processed {} image job {} in {:?}", job.source, job.id, latency);
+
+            // The queue owner may be gone during shutdown; the image is stored either way
+            let _ = results.send(ProcessedImage {
+                id: job.id,
+                source: job.source,
+                result,
+                latency,
+            });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use tempfile::TempDir;
+
+    fn png_bytes() -> Vec<u8> {
+        let img = image::RgbaImage::from_pixel(4, 4, image::Rgba([0, 0, 255, 255]));
+        let mut data = Vec::new();
+        image::DynamicImage::ImageRgba8(img)
+            .write_to(&mut std::io::Cursor::new(&mut data), image::ImageOutputFormat::Png)
+            .unwrap();
+        data
+    }
+
+    async fn queue(temp_dir: &TempDir, settings: ProcessingConfig) -> ProcessingQueue {
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        ProcessingQueue::new(ImageProcessor::new(config).await.unwrap(), &settings)
+    }
+
+    #[tokio::test]
+    async fn test_jobs_complete_with_results() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut queue = queue(&temp_dir, ProcessingConfig::default()).await;
+
+        let good = queue.submit(png_bytes(), "clipboard", None, None).unwrap();
+        let bad = queue.submit(b"not an image".to_vec(), "clipboard", None, None).unwrap();
+
+        let mut results = [queue.next_completed().await.unwrap(), queue.next_completed().await.unwrap()];
+        results.sort_by_key(|processed| processed.id);
+
+        assert_eq!(results[0].id, good);
+        assert!(results[0].result.as_ref().unwrap().exists());
+        assert_eq!(results[1].id, bad);
+        assert!(results[1].result.is_err());
+
+        let metrics = metrics();
+        assert!(metrics.completed >= 1);
+        assert!(metrics.failed >= 1);
+        assert!(metrics.average_latency().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_rejects_jobs() {
+        let temp_dir = TempDir::new().unwrap();
+        let settings = ProcessingConfig {
+            queue_capacity: 1,
+            workers: 1,
+        };
+        let mut queue = queue(&temp_dir, settings).await;
+
+        // The single-threaded test runtime does not run the workers until we yield
+        queue.submit(png_bytes(), "clipboard", None, None).unwrap();
+        let err = queue.submit(png_bytes(), "clipboard", None, None).unwrap_err();
+        assert!(err.is_recoverable());
+        assert!(metrics().rejected >= 1);
+
+        assert!(queue.next_completed().await.unwrap().result.is_ok());
+        queue.submit(png_bytes(), "clipboard", None, None).unwrap();
+    }
+}
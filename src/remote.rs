@@ -0,0 +1,272 @@
+//! Remote-agent mode: images produced on an SSH host, stored locally.
+//!
+//! `klipdot remote serve` runs on the server and writes every new image in
+//! the watched directories to stdout as one JSON line. `klipdot remote
+//! connect` runs it over `ssh` and stores what arrives like any other
+//! interception, so previews, completion and clipboard paths all work on the
+//! local copy. The SSH connection is the only transport; nothing listens on
+//! a port.
+
+use crate::{
+    config::Config,
+    error::Result,
+    image_processor::ImageProcessor,
+    substitution::shell_quote,
+    Error,
+};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tracing::{debug, info, warn};
+
+/// Source recorded for images streamed from a remote host
+pub const REMOTE_SOURCE: &str = "remote";
+
+/// Files modified more recently than this may still be being written
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// One line of the `remote serve` stream
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RemoteMessage {
+    /// Sent first, so a mismatched or missing remote klipdot is noticed
+    Hello { version: String, host: String },
+    /// A new image; `data` is base64
+    Image { path: String, data: String },
+    /// Something the local side should report
+    Error { message: String },
+}
+
+impl RemoteMessage {
+    pub fn image(path: &Path, data: &[u8]) -> Self {
+        RemoteMessage::Image {
+            path: path.to_string_lossy().to_string(),
+            data: BASE64.encode(data),
+        }
+    }
+}
+
+/// Images in `dirs` that are new or changed since `seen` was last updated.
+/// Files still being written are left for a later scan.
+pub fn scan(dirs: &[PathBuf], seen: &mut HashMap<PathBuf, SystemTime>) -> Vec<PathBuf> {
+    let now = SystemTime::now();
+    let mut found = Vec::new();
+
+    for dir in dirs {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(modified) = entry.metadata().and_then(|metadata| metadata.modified()) else {
+                continue;
+            };
+            if now.duration_since(modified).is_ok_and(|age| age < SETTLE_TIME) {
+                continue;
+            }
+            // Only files that changed since the last scan are read to sniff them
+            if seen.insert(path.clone(), modified) != Some(modified) && crate::is_image_file(&path) {
+                found.push(path);
+            }
+        }
+    }
+
+    found.sort();
+    found
+}
+
+/// Stream new images in `dirs` to `out` until it is closed. Images already
+/// there when serving starts are not sent.
+pub async fn serve<W: AsyncWrite + Unpin>(dirs: Vec<PathBuf>, interval: Duration, out: &mut W) -> Result<()> {
+    let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "remote".to_string());
+    send(out, &RemoteMessage::Hello { version: crate::VERSION.to_string(), host }).await?;
+
+    let mut seen = HashMap::new();
+    scan(&dirs, &mut seen);
+    info!("Streaming new images in {:?}", dirs);
+
+    loop {
+        tokio::time::sleep(interval).await;
+        for path in scan(&dirs, &mut seen) {
+            let message = match tokio::fs::read(&path).await {
+                Ok(data) if data.len() as u64 > crate::MAX_FILE_SIZE => RemoteMessage::Error {
+                    message: format!("{} is larger than {}", path.display(), crate::format_file_size(crate::MAX_FILE_SIZE)),
+                },
+                Ok(data) => RemoteMessage::image(&path, &data),
+                Err(e) => {
+                    debug!("Failed to read {:?}: {}", path, e);
+                    continue;
+                }
+            };
+            // The local side hanging up ends the session
+            send(out, &message).await?;
+        }
+    }
+}
+
+async fn send<W: AsyncWrite + Unpin>(out: &mut W, message: &RemoteMessage) -> Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    out.write_all(&line).await?;
+    out.flush().await?;
+    Ok(())
+}
+
+/// Store an image sent by the remote side; `None` for other messages
+pub async fn handle_message(processor: &ImageProcessor, message: RemoteMessage) -> Result<Option<(String, PathBuf)>> {
+    match message {
+        RemoteMessage::Hello { version, host } => {
+            if version != crate::VERSION {
+                warn!("{} runs klipdot {}, this is {}", host, version, crate::VERSION);
+            }
+            info!("Connected to {}", host);
+            Ok(None)
+        }
+        RemoteMessage::Image { path, data } => {
+            let data = BASE64
+                .decode(data)
+                .map_err(|e| Error::Format(format!("Invalid image data for {}: {}", path, e)))?;
+            let stored = processor.process_image_data(&data, REMOTE_SOURCE).await?;
+            Ok(Some((path, stored)))
+        }
+        RemoteMessage::Error { message } => {
+            warn!("Remote: {}", message);
+            Ok(None)
+        }
+    }
+}
+
+/// Run `klipdot remote serve` on `destination` over ssh and store the images it
+/// sends, calling `on_image` with each remote path and its local copy
+pub async fn connect<F>(config: &Config, destination: &str, remote_dirs: &[String], mut on_image: F) -> Result<()>
+where
+    F: FnMut(&str, &Path),
+{
+    let mut remote_command = vec!["klipdot".to_string(), "remote".to_string(), "serve".to_string()];
+    for dir in remote_dirs {
+        remote_command.push("--watch".to_string());
+        remote_command.push(shell_quote(dir));
+    }
+
+    // ssh joins the words into a command line for the remote shell
+    let mut child = tokio::process::Command::new("ssh")
+        .args(["-T", "-o", "BatchMode=yes", "--", destination])
+        .arg(remote_command.join(" "))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| Error::Process(format!("Failed to run ssh: {}", e)))?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| Error::Internal("ssh stdout was not captured".to_string()))?;
+
+    let processor = ImageProcessor::new(config.clone()).await?;
+    let mut lines = BufReader::new(stdout).lines();
+    let mut greeted = false;
+    while let Some(line) = lines.next_line().await? {
+        let message = match serde_json::from_str::<RemoteMessage>(&line) {
+            Ok(message) => message,
+            // Login banners and shell startup output can precede the stream
+            Err(_) if !greeted => {
+                debug!("Remote output before the stream: {}", line);
+                continue;
+            }
+            Err(e) => {
+                warn!("Malformed message from {}: {}", destination, e);
+                continue;
+            }
+        };
+        greeted = true;
+
+        match handle_message(&processor, message).await {
+            Ok(Some((remote_path, stored))) => on_image(&remote_path, &stored),
+            Ok(None) => {}
+            Err(e) => {
+                warn!("Failed to store image from {}: {}", destination, e);
+                crate::error_history::record_error("remote", &e);
+            }
+        }
+    }
+
+    let status = child.wait().await?;
+    if !greeted {
+        return Err(Error::Process(format!(
+            "No klipdot stream from {} ({}); is klipdot installed there?",
+            destination, status
+        )));
+    }
+    info!("Disconnected from {}", destination);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    #[test]
+    fn test_message_format() {
+        let message = RemoteMessage::image(Path::new("/srv/out/plot.png"), b"png");
+        let line = serde_json::to_string(&message).unwrap();
+        assert_eq!(line, r#"{"type":"image","path":"/srv/out/plot.png","data":"cG5n"}"#);
+        assert_eq!(serde_json::from_str::<RemoteMessage>(&line).unwrap(), message);
+    }
+
+    #[test]
+    fn test_scan_reports_new_images_once() {
+        let temp_dir = TempDir::new().unwrap();
+        let dirs = [temp_dir.path().to_path_buf()];
+        let settled = SystemTime::now() - Duration::from_secs(5);
+        let write = |name: &str, content: &[u8]| {
+            let path = temp_dir.path().join(name);
+            std::fs::write(&path, content).unwrap();
+            std::fs::File::options().write(true).open(&path).unwrap().set_modified(settled).unwrap();
+            path
+        };
+
+        let mut seen = HashMap::new();
+        let plot = write("plot.png", PNG);
+        write("notes.txt", b"text");
+        // Images are found by their content
+        let unnamed = write("capture", PNG);
+        write("fake.png", b"text");
+        let mut found = scan(&dirs, &mut seen);
+        found.sort();
+        assert_eq!(found, [unnamed, plot]);
+        assert!(scan(&dirs, &mut seen).is_empty());
+
+        // Still being written
+        std::fs::write(temp_dir.path().join("fresh.png"), PNG).unwrap();
+        assert!(scan(&dirs, &mut seen).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_handle_image_message() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        let processor = ImageProcessor::new(config).await.unwrap();
+
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(2, 2))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let message = RemoteMessage::image(Path::new("/srv/out/plot.png"), &png);
+
+        let (remote_path, stored) = handle_message(&processor, message).await.unwrap().unwrap();
+        assert_eq!(remote_path, "/srv/out/plot.png");
+        assert!(stored.starts_with(temp_dir.path()));
+        assert!(handle_message(&processor, RemoteMessage::Error { message: "x".to_string() }).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,150 @@
+//! Renaming stored images, by hand with `klipdot rename` or automatically with
+//! a slug of the focused window's title or the image's own text.
+//!
+//! A stored image's filename is its key in the metadata index, so renames go
+//! through [`rename_image`] to keep the index pointing at the file.
+
+use crate::{command_runner::CommandRunner, config::AutoSlug, error::Result, metadata, Error};
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// Longest slug kept, in bytes
+const MAX_SLUG_LEN: usize = 48;
+
+/// Lowercase ASCII words of `text` joined by dashes:
+/// "Login Page — Mozilla Firefox" becomes "login-page-mozilla-firefox"
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for word in text.split(|c: char| !c.is_ascii_alphanumeric()).filter(|word| !word.is_empty()) {
+        if !slug.is_empty() {
+            if slug.len() + 1 + word.len() > MAX_SLUG_LEN {
+                break;
+            }
+            slug.push('-');
+        }
+        slug.push_str(&word.to_ascii_lowercase());
+    }
+    slug.truncate(MAX_SLUG_LEN);
+    slug
+}
+
+/// Slug for the image `data` from the source `mode` selects; `None` when
+/// slugs are off or no usable text was found
+pub async fn auto_slug(runner: &dyn CommandRunner, mode: AutoSlug, data: &[u8]) -> Option<String> {
+    let text = match mode {
+        AutoSlug::Off => return None,
+        AutoSlug::Window => crate::focus::focused_title(runner).await?,
+        AutoSlug::Ocr => ocr_title(runner, data).await?,
+    };
+    Some(slugify(&text)).filter(|slug| !slug.is_empty())
+}
+
+/// First line of text `tesseract` recognizes in the image `data`
+pub async fn ocr_title(runner: &dyn CommandRunner, data: &[u8]) -> Option<String> {
+    if !runner.is_available("tesseract") {
+        debug!("tesseract is not installed, no OCR slug");
+        return None;
+    }
+
+    let output = runner.run("tesseract", &["stdin", "stdout"], Some(data)).await.ok()?;
+    if !output.success {
+        debug!("tesseract failed: {}", output.stderr_lossy().trim());
+        return None;
+    }
+    title_line(&output.stdout_lossy())
+}
+
+/// The first line with a few letters or digits, skipping OCR noise like "|" or "—"
+fn title_line(text: &str) -> Option<String> {
+    text.lines()
+        .map(str::trim)
+        .find(|line| line.chars().filter(|c| c.is_alphanumeric()).count() >= 3)
+        .map(str::to_string)
+}
+
+/// Rename the stored image at `path` to `name` in the same directory and
+/// update the metadata index. The extension is kept when `name` has none.
+pub async fn rename_image(path: &Path, name: &str) -> Result<PathBuf> {
+    let name = name.trim();
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(Error::InvalidInput(format!("Invalid image name '{}'", name)));
+    }
+    let (Some(dir), Some(from)) = (path.parent(), path.file_name()) else {
+        return Err(Error::InvalidInput(format!("Not an image file: {:?}", path)));
+    };
+    let from = from.to_string_lossy();
+
+    let to = match path.extension() {
+        Some(ext) if !crate::has_image_extension(Path::new(name)) => format!("{}.{}", name, ext.to_string_lossy()),
+        _ => name.to_string(),
+    };
+    let renamed = dir.join(&to);
+    if to == from {
+        return Ok(renamed);
+    }
+    if tokio::fs::try_exists(&renamed).await? {
+        return Err(Error::InvalidInput(format!("{:?} already exists", renamed)));
+    }
+
+    tokio::fs::rename(path, &renamed).await?;
+    if let Err(e) = metadata::rename(dir, &from, &to).await {
+        warn!("Failed to update metadata for {:?}: {}", renamed, e);
+    }
+    debug!("Renamed {:?} to {:?}", path, renamed);
+    Ok(renamed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+    use crate::metadata::ImageMetadata;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Login Page — Mozilla Firefox"), "login-page-mozilla-firefox");
+        assert_eq!(slugify("  ~/src/klipdot: vim  "), "src-klipdot-vim");
+        assert_eq!(slugify("日本語"), "");
+        let long = slugify(&"word ".repeat(20));
+        assert!(long.len() <= MAX_SLUG_LEN && long.ends_with("word"));
+    }
+
+    #[tokio::test]
+    async fn test_ocr_slug() {
+        let runner = FakeRunner::new().with_output("tesseract", CommandOutput::ok("|\n\nBuild Failed: 3 errors\nsrc/main.rs\n"));
+        assert_eq!(auto_slug(&runner, AutoSlug::Ocr, b"png").await.as_deref(), Some("build-failed-3-errors"));
+        assert_eq!(runner.calls_to("tesseract")[0].stdin.as_deref(), Some(&b"png"[..]));
+        assert_eq!(auto_slug(&runner, AutoSlug::Off, b"png").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_rename_image() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("clipboard-1.png");
+        std::fs::write(&path, b"png").unwrap();
+        std::fs::write(temp_dir.path().join("taken.png"), b"png").unwrap();
+        metadata::record(temp_dir.path(), &ImageMetadata {
+            filename: "clipboard-1.png".to_string(),
+            source: "clipboard".to_string(),
+            app: None,
+            output: None,
+            resized_from: None,
+            hash: Some("0123-3".to_string()),
+            alt_text: None,
+            tags: Vec::new(),
+            window: None,
+            cold: None,
+        }).await.unwrap();
+
+        assert!(rename_image(&path, "../escape").await.is_err());
+        assert!(rename_image(&path, "taken").await.is_err());
+
+        let renamed = rename_image(&path, "login-page").await.unwrap();
+        assert_eq!(renamed, temp_dir.path().join("login-page.png"));
+        assert!(renamed.exists() && !path.exists());
+        let index = metadata::load(temp_dir.path()).await.unwrap();
+        assert_eq!(index["login-page.png"].hash.as_deref(), Some("0123-3"));
+        assert!(!index.contains_key("clipboard-1.png"));
+    }
+}
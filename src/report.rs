@@ -0,0 +1,224 @@
+//! `klipdot report`: a diagnostic bundle to attach to bug reports.
+//!
+//! Everything goes into one plain-text file on this machine; nothing is sent
+//! anywhere. It holds the environment KlipDot detected, a few checks of what
+//! interception needs, the most recent errors and the configuration. The
+//! home directory, user name and upload hosts are replaced throughout, so the file can be shared as it is, though it's meant to be
+//! read before attaching.
+
+use crate::{
+    config::Config,
+    error::Result,
+    error_history::{self, ErrorHistory},
+    service::ServiceManager,
+};
+use serde_json::Value;
+use std::fmt::Write;
+use std::path::Path;
+
+/// How many of the most recent errors go in
+const RECENT_ERRORS: usize = 20;
+
+/// Environment variables that say how the session and terminal were detected
+const ENVIRONMENT_VARS: &[&str] = &[
+    "XDG_SESSION_TYPE",
+    "XDG_CURRENT_DESKTOP",
+    "WAYLAND_DISPLAY",
+    "DISPLAY",
+    "TERM",
+    "TERM_PROGRAM",
+    "SHELL",
+];
+
+/// One check of what interception needs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+/// The report, sanitized
+pub async fn generate(config: &Config) -> Result<String> {
+    let mut report = String::new();
+    let _ = writeln!(report, "KlipDot diagnostic report");
+    let _ = writeln!(report, "Generated {}", crate::format_local_time(chrono::Utc::now()));
+
+    section(&mut report, "Environment");
+    for (name, value) in environment() {
+        let _ = writeln!(report, "{}: {}", name, value);
+    }
+
+    section(&mut report, "Checks");
+    for check in checks(config).await {
+        let _ = writeln!(report, "[{}] {}: {}", if check.ok { "ok" } else { "FAIL" }, check.name, check.detail);
+    }
+
+    section(&mut report, "Recent errors");
+    let errors = ErrorHistory::load(&error_history::default_history_path()?).unwrap_or_else(|e| {
+        let _ = writeln!(report, "Unreadable: {}", e);
+        Vec::new()
+    });
+    if errors.is_empty() {
+        let _ = writeln!(report, "None");
+    }
+    for record in errors.iter().rev().take(RECENT_ERRORS) {
+        let _ = writeln!(
+            report,
+            "{} [{}] {}: {}",
+            crate::format_local_time(record.timestamp),
+            record.code,
+            record.subsystem,
+            record.message
+        );
+    }
+
+    section(&mut report, "Configuration");
+    let _ = writeln!(report, "{}", serde_json::to_string_pretty(&sanitized_config(config)?)?);
+
+    let home = dirs::home_dir();
+    let user = std::env::var("USER").or_else(|_| std::env::var("USERNAME")).ok();
+    Ok(sanitize(&report, home.as_deref(), user.as_deref()))
+}
+
+/// Default file name, e.g. `klipdot-report-20240101-120000.txt`
+pub fn file_name(now: chrono::DateTime<chrono::Local>) -> String {
+    format!("klipdot-report-{}.txt", now.format("%Y%m%d-%H%M%S"))
+}
+
+fn section(report: &mut String, title: &str) {
+    let _ = write!(report, "\n== {} ==\n", title);
+}
+
+fn environment() -> Vec<(&'static str, String)> {
+    let mut environment = vec![
+        ("Version", crate::VERSION.to_string()),
+        ("OS", format!("{} ({})", std::env::consts::OS, std::env::consts::ARCH)),
+        ("Display server", format!("{:?}", crate::detect_display_server())),
+        ("Compositor", crate::detect_wayland_compositor().unwrap_or_else(|| "unknown".to_string())),
+        ("Termux", crate::termux::is_termux().to_string()),
+    ];
+    for var in ENVIRONMENT_VARS {
+        environment.push((var, std::env::var(var).unwrap_or_else(|_| "unset".to_string())));
+    }
+    environment
+}
+
+/// What `klipdot report` checks
+pub async fn checks(config: &Config) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    checks.push(match config.validate() {
+        Ok(()) => Check { name: "configuration", ok: true, detail: format!("{} is valid", config.get_config_path().display()) },
+        Err(e) => Check { name: "configuration", ok: false, detail: e.to_string() },
+    });
+
+    checks.push(match writable(&config.screenshot_dir) {
+        Ok(()) => Check { name: "screenshot directory", ok: true, detail: format!("{} is writable", config.screenshot_dir.display()) },
+        Err(e) => Check { name: "screenshot directory", ok: false, detail: format!("{}: {}", config.screenshot_dir.display(), e) },
+    });
+
+    let tools = config.get_available_clipboard_tools();
+    checks.push(Check {
+        name: "clipboard tools",
+        ok: !tools.is_empty(),
+        detail: if tools.is_empty() { "none found".to_string() } else { tools.join(", ") },
+    });
+
+    let tools = crate::get_available_screenshot_tools();
+    checks.push(Check {
+        name: "screenshot tools",
+        ok: !tools.is_empty(),
+        detail: if tools.is_empty() { "none found".to_string() } else { tools.join(", ") },
+    });
+
+    checks.push(match ServiceManager::new().status().await {
+        Ok(status) if status.running => Check {
+            name: "service",
+            ok: true,
+            detail: match status.pid {
+                Some(pid) => format!("running (PID {})", pid),
+                None => "running".to_string(),
+            },
+        },
+        Ok(_) => Check { name: "service", ok: false, detail: "not running".to_string() },
+        Err(e) => Check { name: "service", ok: false, detail: e.to_string() },
+    });
+
+    let hooks = crate::get_home_dir().map(|home| home.join(crate::HOOKS_DIR));
+    checks.push(Check {
+        name: "shell hooks",
+        ok: hooks.as_ref().is_ok_and(|hooks| hooks.exists()),
+        detail: match hooks {
+            Ok(hooks) if hooks.exists() => format!("installed in {}", hooks.display()),
+            _ => "not installed, see `klipdot install`".to_string(),
+        },
+    });
+
+    checks
+}
+
+/// Whether a file can be created in `dir`
+fn writable(dir: &Path) -> std::io::Result<()> {
+    if !dir.is_dir() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "does not exist"));
+    }
+    tempfile::NamedTempFile::new_in(dir).map(drop)
+}
+
+/// The configuration as JSON with upload hosts replaced
+pub fn sanitized_config(config: &Config) -> Result<Value> {
+    let mut value = serde_json::to_value(config)?;
+    if let Some(hosts) = value.pointer_mut("/uploads/hosts").and_then(Value::as_array_mut) {
+        for host in hosts {
+            *host = Value::String("<host>".to_string());
+        }
+    }
+    Ok(value)
+}
+
+/// `text` with paths under `home` made relative to `~` and `user` replaced
+pub fn sanitize(text: &str, home: Option<&Path>, user: Option<&str>) -> String {
+    let mut text = text.to_string();
+    if let Some(home) = home.map(|home| home.to_string_lossy()).filter(|home| home.len() > 1) {
+        text = text.replace(home.as_ref(), "~");
+    }
+    // Too short a name would mangle unrelated words
+    if let Some(user) = user.filter(|user| user.len() >= 3) {
+        text = text.replace(user, "<user>");
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        let home = Path::new("/home/alice");
+        let text = "screenshot_dir: /home/alice/.klipdot/screenshots\nSocket owned by alice";
+        assert_eq!(
+            sanitize(text, Some(home), Some("alice")),
+            "screenshot_dir: ~/.klipdot/screenshots\nSocket owned by <user>"
+        );
+        assert_eq!(sanitize("bob and bobcat", None, Some("bo")), "bob and bobcat");
+        assert_eq!(sanitize("/x", Some(Path::new("/")), None), "/x");
+    }
+
+    #[test]
+    fn test_sanitized_config() {
+        let mut config = Config::default();
+        config.uploads.hosts = vec!["build.internal.example.com".to_string()];
+        let value = sanitized_config(&config).unwrap();
+        assert_eq!(value["uploads"]["hosts"][0], "<host>");
+        assert!(!value.to_string().contains("internal.example.com"));
+        assert_eq!(value["compression_quality"], config.compression_quality);
+    }
+
+    #[test]
+    fn test_file_name() {
+        let now = chrono::TimeZone::with_ymd_and_hms(&chrono::Local, 2024, 1, 2, 3, 4, 5).unwrap();
+        assert_eq!(file_name(now), "klipdot-report-20240102-030405.txt");
+    }
+}
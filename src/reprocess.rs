@@ -0,0 +1,152 @@
+//! Running stored images through the current settings again (`klipdot reprocess`).
+//!
+//! After `output_format`, `compression_quality`, `max_dimension` or
+//! `bit_depth` change, stored images can be brought in line with new
+//! captures: each is decoded and encoded again with
+//! [`ImageProcessor::reencode`]. An image whose format changes gets the new
+//! extension, and its index entry follows it with its tags, alt text and the
+//! rest of its metadata. Images are only ever scaled down, since the original
+//! pixels are gone once they were. SVGs and images in cold storage are left
+//! alone.
+
+use crate::{
+    config::{Config, Screenshot},
+    error::Result,
+    image_processor::ImageProcessor,
+    metadata::{self, ImageMetadata},
+    store_permissions, Error,
+};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// What a reprocess did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Reprocessed {
+    pub count: usize,
+    pub failed: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+/// The images among `screenshots` that can be reprocessed: raster images
+/// still in the store and not a frozen image's converted copy
+pub async fn candidates(screenshots: Vec<Screenshot>) -> Result<Vec<Screenshot>> {
+    let mut frozen: HashMap<PathBuf, HashSet<String>> = HashMap::new();
+    let mut candidates = Vec::new();
+    for screenshot in screenshots {
+        let is_svg = screenshot.path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("svg"));
+        if is_svg || !screenshot.path.is_file() {
+            continue;
+        }
+        let dir = parent(&screenshot.path).to_path_buf();
+        if !frozen.contains_key(&dir) {
+            let converted = metadata::load(&dir).await?.into_values().filter_map(|entry| entry.cold).collect();
+            frozen.insert(dir.clone(), converted);
+        }
+        if !frozen[&dir].contains(&screenshot.filename) {
+            candidates.push(screenshot);
+        }
+    }
+    Ok(candidates)
+}
+
+/// Reprocess `screenshots` with `config`'s settings, calling `progress` with
+/// the index of each image before it's done
+pub async fn reprocess(config: &Config, screenshots: &[Screenshot], mut progress: impl FnMut(usize, &Screenshot)) -> Result<Reprocessed> {
+    let processor = ImageProcessor::new(config.clone()).await?;
+    let mut reprocessed = Reprocessed::default();
+    for (i, screenshot) in screenshots.iter().enumerate() {
+        progress(i, screenshot);
+        match reprocess_one(config, &processor, screenshot).await {
+            Ok(size) => {
+                reprocessed.count += 1;
+                reprocessed.bytes_before += screenshot.size;
+                reprocessed.bytes_after += size;
+            }
+            Err(e) => {
+                warn!("Failed to reprocess {:?}: {}", screenshot.path, e);
+                reprocessed.failed += 1;
+            }
+        }
+    }
+    Ok(reprocessed)
+}
+
+/// Replace `screenshot` with its reprocessed form, returning its new size
+async fn reprocess_one(config: &Config, processor: &ImageProcessor, screenshot: &Screenshot) -> Result<u64> {
+    let data = tokio::fs::read(&screenshot.path).await?;
+    let reencoded = processor.reencode(&data, &screenshot.source).await?;
+    let target = screenshot.path.with_extension(reencoded.extension);
+    if target != screenshot.path && target.exists() {
+        return Err(Error::AlreadyExists(format!("{:?} already exists", target)));
+    }
+
+    // Written beside it first, so a failure leaves the image as it was
+    let dir = parent(&screenshot.path);
+    let temp = dir.join(format!(".{}.reprocess", screenshot.filename));
+    let modified = std::fs::metadata(&screenshot.path).and_then(|metadata| metadata.modified());
+    store_permissions::write(&temp, &reencoded.data, &config.store_permissions).await?;
+    tokio::fs::rename(&temp, &target).await?;
+
+    // Keep its place in listings ordered by modification time
+    if let Ok(modified) = modified {
+        if let Err(e) = std::fs::File::options().write(true).open(&target).and_then(|file| file.set_modified(modified)) {
+            debug!("Failed to keep the modification time of {:?}: {}", screenshot.path, e);
+        }
+    }
+
+    let name = target.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    if target != screenshot.path {
+        metadata::rename(dir, &screenshot.filename, &name).await?;
+        tokio::fs::remove_file(&screenshot.path).await?;
+    }
+    // The first size it was scaled down from is still the original's
+    if let (Some(resized_from), Some(entry)) = (reencoded.resized_from, metadata::load(dir).await?.remove(&name)) {
+        if entry.resized_from.is_none() {
+            metadata::record(dir, &ImageMetadata { resized_from: Some(resized_from), ..entry }).await?;
+        }
+    }
+    Ok(reencoded.data.len() as u64)
+}
+
+fn parent(path: &Path) -> &Path {
+    path.parent().unwrap_or(Path::new("."))
+}
+
+#[cfg(all(test, feature = "codecs"))]
+mod tests {
+    use super::*;
+    use crate::config::{MaxDimension, OutputFormat};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_reprocess() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = Config { screenshot_dir: temp_dir.path().to_path_buf(), ..Config::default() };
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(64, 32, image::Rgb([200, 40, 40])));
+        let mut png = Vec::new();
+        img.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        let stored = ImageProcessor::new(config.clone()).await.unwrap().process_image_data(&png, "clipboard").await.unwrap();
+        let filename = stored.file_name().unwrap().to_string_lossy().into_owned();
+        metadata::retag(temp_dir.path(), &filename, &["bug".to_string()], &[]).await.unwrap();
+        std::fs::write(temp_dir.path().join("logo.svg"), "<svg xmlns=\"http://www.w3.org/2000/svg\"/>").unwrap();
+
+        config.output_format = OutputFormat::Jpeg;
+        config.max_dimension = MaxDimension::Pixels(16);
+        let screenshots = candidates(config.get_recent_screenshots(usize::MAX).await.unwrap()).await.unwrap();
+        assert_eq!(screenshots.len(), 1);
+        let mut seen = Vec::new();
+        let reprocessed = reprocess(&config, &screenshots, |i, screenshot| seen.push((i, screenshot.filename.clone()))).await.unwrap();
+        assert_eq!((reprocessed.count, reprocessed.failed), (1, 0));
+        assert_eq!(seen, [(0, filename.clone())]);
+
+        // Renamed for the new format, scaled down, and still tagged
+        let jpeg = stored.with_extension("jpg");
+        assert!(!stored.exists());
+        assert_eq!(image::image_dimensions(&jpeg).unwrap(), (16, 8));
+        let entry = &metadata::load(temp_dir.path()).await.unwrap()[jpeg.file_name().unwrap().to_str().unwrap()];
+        assert_eq!(entry.tags, ["bug"]);
+        assert_eq!(entry.resized_from, Some((64, 32)));
+    }
+}
@@ -0,0 +1,217 @@
+use crate::error::Result;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Retry settings for operations that fail with recoverable errors
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Total attempts including the first one
+    pub max_attempts: u32,
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// Factor applied to the delay after each failed attempt
+    pub multiplier: f64,
+    /// Fraction of the delay (0.0-1.0) randomised to avoid synchronised retries
+    pub jitter: f64,
+}
+
+/// Retry counters for a single named operation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryCounters {
+    /// Calls made through the policy
+    pub calls: u64,
+    /// Extra attempts made after a recoverable failure
+    pub retries: u64,
+    /// Calls that still failed after the last attempt
+    pub exhausted: u64,
+}
+
+static METRICS: Lazy<Mutex<HashMap<String, RetryCounters>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_delay_ms: 100,
+            max_delay_ms: 5000,
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that runs the operation exactly once
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Backoff delay before retry number `attempt` (1-based), without jitter
+    pub fn base_delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(32) as i32;
+        let delay = self.initial_delay_ms as f64 * self.multiplier.max(1.0).powi(exponent);
+        Duration::from_millis(delay.min(self.max_delay_ms as f64) as u64)
+    }
+
+    /// Backoff delay before retry number `attempt` (1-based), with jitter applied
+    pub fn delay_for_attempt(&self, attempt: u32) -> Duration {
+        let base = self.base_delay(attempt).as_millis() as f64;
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = 1.0 + jitter * (fastrand::f64() * 2.0 - 1.0);
+        Duration::from_millis((base * factor).max(0.0) as u64)
+    }
+
+    /// Run `operation`, retrying recoverable errors with exponential backoff.
+    ///
+    /// `name` identifies the operation in logs and in [`metrics`].
+    pub async fn run<T, F, Fut>(&self, name: &str, mut operation: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let max_attempts = self.max_attempts.max(1);
+        record(name, |counters| counters.calls += 1);
+
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err(e) if e.is_recoverable() && attempt < max_attempts => {
+                    let delay = self.delay_for_attempt(attempt);
+                    debug!(
+                        "{} failed (attempt {}/{}): {}, retrying in {:?}",
+                        name, attempt, max_attempts, e, delay
+                    );
+                    record(name, |counters| counters.retries += 1);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    if e.is_recoverable() {
+                        warn!("{} failed after {} attempts: {}", name, attempt, e);
+                        record(name, |counters| counters.exhausted += 1);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+fn record(name: &str, update: impl FnOnce(&mut RetryCounters)) {
+    let mut metrics = METRICS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    update(metrics.entry(name.to_string()).or_default());
+}
+
+/// Snapshot of retry counters for every operation run through a [`RetryPolicy`]
+pub fn metrics() -> HashMap<String, RetryCounters> {
+    METRICS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn fast_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_delay_ms: 1,
+            max_delay_ms: 5,
+            multiplier: 2.0,
+            jitter: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_backoff_delays() {
+        let policy = RetryPolicy {
+            initial_delay_ms: 100,
+            max_delay_ms: 1000,
+            multiplier: 2.0,
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        };
+
+        assert_eq!(policy.base_delay(1), Duration::from_millis(100));
+        assert_eq!(policy.base_delay(2), Duration::from_millis(200));
+        assert_eq!(policy.base_delay(3), Duration::from_millis(400));
+        assert_eq!(policy.base_delay(10), Duration::from_millis(1000));
+        assert_eq!(policy.delay_for_attempt(2), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_jitter_bounds() {
+        let policy = RetryPolicy {
+            initial_delay_ms: 1000,
+            jitter: 0.5,
+            ..RetryPolicy::default()
+        };
+
+        for _ in 0..100 {
+            let delay = policy.delay_for_attempt(1).as_millis();
+            assert!((500..=1500).contains(&delay));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_recoverable_errors() {
+        let calls = AtomicU32::new(0);
+        let result = fast_policy(3)
+            .run("test_recoverable", || async {
+                if calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Err(Error::Clipboard("busy".to_string()))
+                } else {
+                    Ok(42)
+                }
+            })
+            .await;
+
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let counters = metrics()["test_recoverable"];
+        assert_eq!(counters.calls, 1);
+        assert_eq!(counters.retries, 2);
+        assert_eq!(counters.exhausted, 0);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let calls = AtomicU32::new(0);
+        let result: Result<()> = fast_policy(2)
+            .run("test_exhausted", || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(Error::Timeout("slow".to_string()))
+            })
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(metrics()["test_exhausted"].exhausted, 1);
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_fatal_errors() {
+        let calls = AtomicU32::new(0);
+        let result: Result<()> = fast_policy(5)
+            .run("test_fatal", || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(Error::Config("broken".to_string()))
+            })
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,129 @@
+//! State that only means something while KlipDot runs.
+//!
+//! It all lives in one private directory, `$XDG_RUNTIME_DIR/klipdot` where
+//! there is one (a per-user tmpfs the session clears at logout), or else
+//! [`crate::RUN_DIR`] in the KlipDot home directory:
+//!
+//! - [`crate::LOCK_FILE`], the instance lock ([`crate::instance_lock`])
+//! - [`crate::PID_FILE`], the daemon's PID, for `klipdot stop`
+//! - [`crate::IPC_SOCKET`], the daemon's socket ([`crate::ipc`])
+//! - [`crate::TEMP_DIR`], temporary files ([`crate::temp_files`])
+//!
+//! The instance lock comes first: a long-running entry point takes it with
+//! [`claim`] before creating anything else here, and only its holder writes
+//! the PID file and socket. Holding it proves whoever wrote what's already
+//! there has exited, so a stale socket and PID file are removed and old
+//! temporary files swept straight away. The returned [`RunState`] removes the
+//! socket and PID file when dropped.
+
+use crate::{error::Result, instance_lock::{self, Acquired, InstanceLock}, temp_files};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// The running instance's hold on the directory
+#[derive(Debug)]
+pub struct RunState {
+    dir: PathBuf,
+    _lock: InstanceLock,
+}
+
+/// What [`claim`] found
+#[derive(Debug)]
+pub enum Claimed {
+    /// This process is the running instance
+    Owned(RunState),
+    /// Another instance is, with its PID when it could be read
+    Running(Option<u32>),
+}
+
+impl Drop for RunState {
+    fn drop(&mut self) {
+        remove_socket(&self.dir);
+        if read_pid(&self.dir) == Some(std::process::id()) {
+            let _ = std::fs::remove_file(self.dir.join(crate::PID_FILE));
+        }
+        debug!("Cleaned up run state in {:?}", self.dir);
+    }
+}
+
+/// The run-state directory, created private if needed
+pub fn dir() -> Result<PathBuf> {
+    let dir = match dirs::runtime_dir() {
+        Some(runtime_dir) => runtime_dir.join(crate::APP_NAME),
+        None => crate::get_home_dir()?.join(crate::RUN_DIR),
+    };
+    temp_files::private_dir(&dir)
+}
+
+pub fn socket_path() -> Result<PathBuf> {
+    Ok(dir()?.join(crate::IPC_SOCKET))
+}
+
+/// Become the running instance, unless another process already is
+pub fn claim() -> Result<Claimed> {
+    claim_in(&dir()?)
+}
+
+/// [`claim`] in `dir`
+pub fn claim_in(dir: &Path) -> Result<Claimed> {
+    let lock = match instance_lock::acquire(dir)? {
+        Acquired::Locked(lock) => lock,
+        Acquired::Running(pid) => return Ok(Claimed::Running(pid)),
+    };
+    recover(dir);
+    Ok(Claimed::Owned(RunState { dir: dir.to_path_buf(), _lock: lock }))
+}
+
+/// Remove what a previous instance left behind. A PID file naming this
+/// process was written for it by `klipdot start --daemon` and is kept.
+fn recover(dir: &Path) {
+    remove_socket(dir);
+    if let Some(pid) = read_pid(dir).filter(|&pid| pid != std::process::id()) {
+        debug!("Removing the PID file of exited instance {}", pid);
+        let _ = std::fs::remove_file(dir.join(crate::PID_FILE));
+    }
+    temp_files::sweep(&dir.join(crate::TEMP_DIR), temp_files::LEFTOVER_AGE);
+}
+
+fn remove_socket(dir: &Path) {
+    let socket = dir.join(crate::IPC_SOCKET);
+    if socket.exists() {
+        debug!("Removing socket {:?}", socket);
+        let _ = std::fs::remove_file(socket);
+    }
+}
+
+fn read_pid(dir: &Path) -> Option<u32> {
+    std::fs::read_to_string(dir.join(crate::PID_FILE)).ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_claim_recovers_and_cleans_up() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        // Left by an instance that was killed
+        std::fs::write(dir.join(crate::IPC_SOCKET), "").unwrap();
+        std::fs::write(dir.join(crate::PID_FILE), "4000000").unwrap();
+
+        let state = match claim_in(dir).unwrap() {
+            Claimed::Owned(state) => state,
+            Claimed::Running(pid) => panic!("claimed by {:?}", pid),
+        };
+        assert!(!dir.join(crate::IPC_SOCKET).exists());
+        assert!(!dir.join(crate::PID_FILE).exists());
+        assert!(matches!(claim_in(dir).unwrap(), Claimed::Running(Some(pid)) if pid == std::process::id()));
+
+        // What the running instance creates goes when it stops
+        std::fs::write(dir.join(crate::IPC_SOCKET), "").unwrap();
+        std::fs::write(dir.join(crate::PID_FILE), std::process::id().to_string()).unwrap();
+        drop(state);
+        assert!(!dir.join(crate::IPC_SOCKET).exists());
+        assert!(!dir.join(crate::PID_FILE).exists());
+        assert!(matches!(claim_in(dir).unwrap(), Claimed::Owned(_)));
+    }
+}
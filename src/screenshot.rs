@@ -0,0 +1,618 @@
+//! Screenshot tool abstraction used by `klipdot capture`.
+//!
+//! Each supported tool knows how to build its own command line for a full
+//! screen, region, monitor or window capture and where the image ends up (stdout or the
+//! destination file), so capturing behaves the same whichever tool is picked.
+
+use crate::{
+    command_runner::CommandRunner,
+    config::Config,
+    error::Result,
+    monitors::Monitor,
+    DisplayServer, Error,
+};
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::debug;
+
+/// Where a tool writes the captured image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputKind {
+    /// PNG data is written to stdout
+    Stdout,
+    /// The image is written to the destination path given on the command line
+    File,
+}
+
+/// Screen rectangle in global coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Region {
+    type Err = Error;
+
+    /// Parse the `X,Y WxH` geometry format printed by slurp
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidInput(format!("Invalid region '{}', expected 'X,Y WxH'", s));
+
+        let (position, size) = s.trim().split_once(' ').ok_or_else(invalid)?;
+        let (x, y) = position.split_once(',').ok_or_else(invalid)?;
+        let (width, height) = size.trim().split_once('x').ok_or_else(invalid)?;
+
+        let region = Region {
+            x: x.trim().parse().map_err(|_| invalid())?,
+            y: y.trim().parse().map_err(|_| invalid())?,
+            width: width.trim().parse().map_err(|_| invalid())?,
+            height: height.trim().parse().map_err(|_| invalid())?,
+        };
+
+        if region.width == 0 || region.height == 0 {
+            return Err(invalid());
+        }
+        Ok(region)
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{} {}x{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+/// What to capture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureMode {
+    /// The whole screen
+    Full,
+    /// A fixed region, or an interactive selection when `None`
+    Region(Option<Region>),
+    /// The focused window, found by the tool itself
+    Window,
+    /// A window by id (X11 window or macOS window number)
+    WindowId(String),
+    /// One monitor
+    Output(Monitor),
+}
+
+impl fmt::Display for CaptureMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureMode::Full => write!(f, "full screen"),
+            CaptureMode::Region(Some(region)) => write!(f, "region {}", region),
+            CaptureMode::Region(None) => write!(f, "interactive region"),
+            CaptureMode::Window => write!(f, "window"),
+            CaptureMode::WindowId(id) => write!(f, "window {}", id),
+            CaptureMode::Output(monitor) => write!(f, "output {}", monitor.name),
+        }
+    }
+}
+
+/// A screenshot tool's command line for one capture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureCommand {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl CaptureCommand {
+    fn new(program: &str, args: &[&str]) -> Self {
+        Self {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+
+    fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+}
+
+/// A screenshot program KlipDot can drive
+pub trait ScreenshotTool: Send + Sync {
+    /// Executable name
+    fn name(&self) -> &'static str;
+
+    /// Display servers the tool works on
+    fn display_servers(&self) -> &'static [DisplayServer];
+
+    fn output_kind(&self) -> OutputKind;
+
+    fn capture_full(&self, dest: &Path) -> CaptureCommand;
+
+    /// `None` when the tool cannot capture the requested region
+    fn capture_region(&self, region: Option<&Region>, dest: &Path) -> Option<CaptureCommand>;
+
+    /// `None` when the tool cannot capture a single window
+    fn capture_window(&self, dest: &Path) -> Option<CaptureCommand>;
+
+    /// `None` when the tool cannot capture a window by id
+    fn capture_window_id(&self, _id: &str, _dest: &Path) -> Option<CaptureCommand> {
+        None
+    }
+
+    /// Capture one monitor; by default its region of the screen
+    fn capture_output(&self, monitor: &Monitor, dest: &Path) -> Option<CaptureCommand> {
+        self.capture_region(Some(&monitor.region), dest)
+    }
+
+    /// Command line for `mode`, or `None` if the tool does not support it
+    fn command_for(&self, mode: &CaptureMode, dest: &Path) -> Option<CaptureCommand> {
+        match mode {
+            CaptureMode::Full => Some(self.capture_full(dest)),
+            CaptureMode::Region(region) => self.capture_region(region.as_ref(), dest),
+            CaptureMode::Window => self.capture_window(dest),
+            CaptureMode::WindowId(id) => self.capture_window_id(id, dest),
+            CaptureMode::Output(monitor) => self.capture_output(monitor, dest),
+        }
+    }
+}
+
+fn dest_arg(dest: &Path) -> String {
+    dest.to_string_lossy().into_owned()
+}
+
+/// grim (wlroots compositors)
+pub struct Grim;
+
+impl ScreenshotTool for Grim {
+    fn name(&self) -> &'static str {
+        "grim"
+    }
+
+    fn display_servers(&self) -> &'static [DisplayServer] {
+        &[DisplayServer::Wayland]
+    }
+
+    fn output_kind(&self) -> OutputKind {
+        OutputKind::Stdout
+    }
+
+    fn capture_full(&self, _dest: &Path) -> CaptureCommand {
+        CaptureCommand::new("grim", &["-t", "png", "-"])
+    }
+
+    fn capture_region(&self, region: Option<&Region>, _dest: &Path) -> Option<CaptureCommand> {
+        // Interactive selection needs slurp, which grim does not drive itself
+        let region = region?;
+        Some(CaptureCommand::new("grim", &["-t", "png", "-g"]).arg(region.to_string()).arg("-"))
+    }
+
+    fn capture_window(&self, _dest: &Path) -> Option<CaptureCommand> {
+        None
+    }
+
+    fn capture_output(&self, monitor: &Monitor, _dest: &Path) -> Option<CaptureCommand> {
+        Some(CaptureCommand::new("grim", &["-t", "png", "-o"]).arg(monitor.name.as_str()).arg("-"))
+    }
+}
+
+/// scrot (X11)
+pub struct Scrot;
+
+impl ScreenshotTool for Scrot {
+    fn name(&self) -> &'static str {
+        "scrot"
+    }
+
+    fn display_servers(&self) -> &'static [DisplayServer] {
+        &[DisplayServer::X11]
+    }
+
+    fn output_kind(&self) -> OutputKind {
+        OutputKind::File
+    }
+
+    fn capture_full(&self, dest: &Path) -> CaptureCommand {
+        CaptureCommand::new("scrot", &["--overwrite"]).arg(dest_arg(dest))
+    }
+
+    fn capture_region(&self, region: Option<&Region>, dest: &Path) -> Option<CaptureCommand> {
+        let command = match region {
+            Some(r) => CaptureCommand::new("scrot", &["--autoselect"])
+                .arg(format!("{},{},{},{}", r.x, r.y, r.width, r.height)),
+            None => CaptureCommand::new("scrot", &["--select"]),
+        };
+        Some(command.arg("--overwrite").arg(dest_arg(dest)))
+    }
+
+    fn capture_window(&self, dest: &Path) -> Option<CaptureCommand> {
+        Some(CaptureCommand::new("scrot", &["--focused", "--overwrite"]).arg(dest_arg(dest)))
+    }
+}
+
+/// Spectacle (KDE, X11 and Wayland)
+pub struct Spectacle;
+
+impl ScreenshotTool for Spectacle {
+    fn name(&self) -> &'static str {
+        "spectacle"
+    }
+
+    fn display_servers(&self) -> &'static [DisplayServer] {
+        &[DisplayServer::Wayland, DisplayServer::X11]
+    }
+
+    fn output_kind(&self) -> OutputKind {
+        OutputKind::File
+    }
+
+    fn capture_full(&self, dest: &Path) -> CaptureCommand {
+        CaptureCommand::new("spectacle", &["--background", "--nonotify", "--fullscreen", "--output"])
+            .arg(dest_arg(dest))
+    }
+
+    fn capture_region(&self, region: Option<&Region>, dest: &Path) -> Option<CaptureCommand> {
+        // Spectacle only offers interactive region selection
+        if region.is_some() {
+            return None;
+        }
+        Some(
+            CaptureCommand::new("spectacle", &["--background", "--nonotify", "--region", "--output"])
+                .arg(dest_arg(dest)),
+        )
+    }
+
+    fn capture_window(&self, dest: &Path) -> Option<CaptureCommand> {
+        Some(
+            CaptureCommand::new("spectacle", &["--background", "--nonotify", "--activewindow", "--output"])
+                .arg(dest_arg(dest)),
+        )
+    }
+}
+
+/// Flameshot (X11 and Wayland)
+pub struct Flameshot;
+
+impl ScreenshotTool for Flameshot {
+    fn name(&self) -> &'static str {
+        "flameshot"
+    }
+
+    fn display_servers(&self) -> &'static [DisplayServer] {
+        &[DisplayServer::Wayland, DisplayServer::X11]
+    }
+
+    fn output_kind(&self) -> OutputKind {
+        OutputKind::Stdout
+    }
+
+    fn capture_full(&self, _dest: &Path) -> CaptureCommand {
+        CaptureCommand::new("flameshot", &["full", "--raw"])
+    }
+
+    fn capture_region(&self, region: Option<&Region>, _dest: &Path) -> Option<CaptureCommand> {
+        let command = CaptureCommand::new("flameshot", &["gui", "--raw"]);
+        Some(match region {
+            Some(r) => command
+                .arg("--region")
+                .arg(format!("{}x{}+{}+{}", r.width, r.height, r.x, r.y)),
+            None => command,
+        })
+    }
+
+    fn capture_window(&self, _dest: &Path) -> Option<CaptureCommand> {
+        None
+    }
+}
+
+/// screencapture (macOS)
+pub struct ScreenCapture;
+
+impl ScreenshotTool for ScreenCapture {
+    fn name(&self) -> &'static str {
+        "screencapture"
+    }
+
+    fn display_servers(&self) -> &'static [DisplayServer] {
+        &[DisplayServer::MacOS]
+    }
+
+    fn output_kind(&self) -> OutputKind {
+        OutputKind::File
+    }
+
+    fn capture_full(&self, dest: &Path) -> CaptureCommand {
+        CaptureCommand::new("screencapture", &["-x", "-t", "png"]).arg(dest_arg(dest))
+    }
+
+    fn capture_region(&self, region: Option<&Region>, dest: &Path) -> Option<CaptureCommand> {
+        let command = match region {
+            Some(r) => CaptureCommand::new("screencapture", &["-x", "-t", "png", "-R"])
+                .arg(format!("{},{},{},{}", r.x, r.y, r.width, r.height)),
+            None => CaptureCommand::new("screencapture", &["-x", "-t", "png", "-i", "-s"]),
+        };
+        Some(command.arg(dest_arg(dest)))
+    }
+
+    /// Lets the user click the window
+    fn capture_window(&self, dest: &Path) -> Option<CaptureCommand> {
+        Some(CaptureCommand::new("screencapture", &["-x", "-t", "png", "-i", "-w"]).arg(dest_arg(dest)))
+    }
+
+    fn capture_window_id(&self, id: &str, dest: &Path) -> Option<CaptureCommand> {
+        Some(CaptureCommand::new("screencapture", &["-x", "-t", "png", "-o"]).arg(format!("-l{}", id)).arg(dest_arg(dest)))
+    }
+}
+
+/// ImageMagick import (X11)
+pub struct Import;
+
+impl ScreenshotTool for Import {
+    fn name(&self) -> &'static str {
+        "import"
+    }
+
+    fn display_servers(&self) -> &'static [DisplayServer] {
+        &[DisplayServer::X11]
+    }
+
+    fn output_kind(&self) -> OutputKind {
+        OutputKind::Stdout
+    }
+
+    fn capture_full(&self, _dest: &Path) -> CaptureCommand {
+        CaptureCommand::new("import", &["-window", "root", "png:-"])
+    }
+
+    fn capture_region(&self, region: Option<&Region>, _dest: &Path) -> Option<CaptureCommand> {
+        Some(match region {
+            Some(r) => CaptureCommand::new("import", &["-window", "root", "-crop"])
+                .arg(format!("{}x{}+{}+{}", r.width, r.height, r.x, r.y))
+                .arg("+repage")
+                .arg("png:-"),
+            // Without -window, import lets the user drag out a rectangle
+            None => CaptureCommand::new("import", &["png:-"]),
+        })
+    }
+
+    fn capture_window(&self, _dest: &Path) -> Option<CaptureCommand> {
+        None
+    }
+
+    fn capture_window_id(&self, id: &str, _dest: &Path) -> Option<CaptureCommand> {
+        Some(CaptureCommand::new("import", &["-window"]).arg(id).arg("png:-"))
+    }
+}
+
+/// All tools KlipDot can drive, in default order of preference
+pub fn builtin_tools() -> Vec<Box<dyn ScreenshotTool>> {
+    vec![
+        Box::new(Grim),
+        Box::new(Spectacle),
+        Box::new(Flameshot),
+        Box::new(Scrot),
+        Box::new(Import),
+        Box::new(ScreenCapture),
+    ]
+}
+
+/// Look up a built-in tool by executable name
+pub fn find_tool(name: &str) -> Option<Box<dyn ScreenshotTool>> {
+    builtin_tools().into_iter().find(|tool| tool.name() == name)
+}
+
+/// Pick the tool to capture with.
+///
+/// The configured `preferred_tool` wins if it is installed; otherwise the
+/// first installed tool from the display server's list in the config is used,
+/// falling back to any installed built-in tool for that display server.
+pub fn select_tool(config: &Config, runner: &dyn CommandRunner) -> Result<Box<dyn ScreenshotTool>> {
+    select_tool_for(config, runner, &CaptureMode::Full)
+}
+
+/// Pick the tool to capture with, as [`select_tool`], skipping tools that can't capture `mode`
+pub fn select_tool_for(config: &Config, runner: &dyn CommandRunner, mode: &CaptureMode) -> Result<Box<dyn ScreenshotTool>> {
+    let supports = |tool: &dyn ScreenshotTool| tool.command_for(mode, Path::new("")).is_some();
+
+    let tools_config = &config.display_server.screenshot_tools;
+    let display_server = config.get_display_server();
+
+    let mut candidates: Vec<&String> = tools_config.preferred_tool.iter().collect();
+    match display_server {
+        DisplayServer::Wayland => candidates.extend(&tools_config.wayland_tools),
+        DisplayServer::X11 | DisplayServer::MacOS => candidates.extend(&tools_config.x11_tools),
+        DisplayServer::Unknown => {
+            candidates.extend(&tools_config.wayland_tools);
+            candidates.extend(&tools_config.x11_tools);
+        }
+    }
+
+    for name in candidates {
+        if let Some(tool) = find_tool(name) {
+            if runner.is_available(tool.name()) && supports(tool.as_ref()) {
+                return Ok(tool);
+            }
+        }
+    }
+
+    builtin_tools()
+        .into_iter()
+        .find(|tool| {
+            (display_server == DisplayServer::Unknown || tool.display_servers().contains(&display_server))
+                && runner.is_available(tool.name())
+                && supports(tool.as_ref())
+        })
+        .ok_or_else(|| Error::Unsupported(format!("No screenshot tool found that can capture {} on {:?}", mode, display_server)))
+}
+
+/// Wait `delay` before a capture, counting the seconds down on one line of
+/// `out` and optionally ringing the terminal bell when the time is up
+pub async fn countdown(delay: Duration, beep: bool, out: &mut dyn Write) -> Result<()> {
+    let deadline = tokio::time::Instant::now() + delay;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        let seconds = remaining.as_millis().div_ceil(1000) as u64;
+        write!(out, "\rCapturing in {}...", seconds)?;
+        out.flush()?;
+        // Wake when the next whole second is left
+        tokio::time::sleep(remaining.saturating_sub(Duration::from_secs(seconds - 1))).await;
+    }
+
+    // Clear the countdown so it isn't part of the capture
+    write!(out, "\r\x1b[2K")?;
+    if beep {
+        write!(out, "\x07")?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Capture with `tool` and leave the image at `dest`
+pub async fn capture(tool: &dyn ScreenshotTool, runner: &dyn CommandRunner, mode: &CaptureMode, dest: &Path) -> Result<()> {
+    let command = tool
+        .command_for(mode, dest)
+        .ok_or_else(|| Error::Unsupported(format!("{} cannot capture {}", tool.name(), mode)))?;
+
+    debug!("Capturing {} with {} {:?}", mode, command.program, command.args);
+
+    let args: Vec<&str> = command.args.iter().map(String::as_str).collect();
+    let output = runner
+        .run(&command.program, &args, None)
+        .await
+        .map_err(|e| Error::Process(format!("Failed to run {}: {}", command.program, e)))?;
+
+    if !output.success {
+        return Err(Error::Process(format!("{} failed: {}", command.program, output.stderr_lossy().trim())));
+    }
+
+    match tool.output_kind() {
+        OutputKind::Stdout => {
+            if output.stdout.is_empty() {
+                return Err(Error::Process(format!("{} produced no image data", command.program)));
+            }
+            tokio::fs::write(dest, &output.stdout).await?;
+        }
+        OutputKind::File => {
+            if !dest.exists() {
+                // Interactive tools exit successfully when the user cancels
+                debug!("{} did not write {:?}", command.program, dest);
+                return Err(Error::Cancelled);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_region_parsing() {
+        let region: Region = "10,20 300x200".parse().unwrap();
+        assert_eq!(region, Region { x: 10, y: 20, width: 300, height: 200 });
+        assert_eq!(region.to_string(), "10,20 300x200");
+
+        assert!("10,20".parse::<Region>().is_err());
+        assert!("a,b 1x1".parse::<Region>().is_err());
+        assert!("0,0 0x10".parse::<Region>().is_err());
+    }
+
+    #[test]
+    fn test_tool_command_lines() {
+        let dest = Path::new("/tmp/out.png");
+        let region = Region { x: 1, y: 2, width: 3, height: 4 };
+
+        assert_eq!(Grim.capture_full(dest).args, ["-t", "png", "-"]);
+        assert_eq!(Grim.capture_region(Some(&region), dest).unwrap().args, ["-t", "png", "-g", "1,2 3x4", "-"]);
+        assert!(Grim.capture_region(None, dest).is_none());
+        assert!(Grim.capture_window(dest).is_none());
+
+        let monitor = Monitor { name: "DP-1".to_string(), region, focused: false };
+        assert_eq!(Grim.capture_output(&monitor, dest).unwrap().args, ["-t", "png", "-o", "DP-1", "-"]);
+        assert_eq!(Scrot.capture_output(&monitor, dest).unwrap().args[..2], ["--autoselect", "1,2,3,4"]);
+        assert!(Spectacle.capture_output(&monitor, dest).is_none());
+
+        assert_eq!(Scrot.capture_region(Some(&region), dest).unwrap().args, ["--autoselect", "1,2,3,4", "--overwrite", "/tmp/out.png"]);
+        assert_eq!(Flameshot.capture_region(Some(&region), dest).unwrap().args, ["gui", "--raw", "--region", "3x4+1+2"]);
+        assert!(Spectacle.capture_region(Some(&region), dest).is_none());
+        assert_eq!(ScreenCapture.capture_window(dest).unwrap().args.last().unwrap(), "/tmp/out.png");
+        assert_eq!(ScreenCapture.capture_window_id("77", dest).unwrap().args, ["-x", "-t", "png", "-o", "-l77", "/tmp/out.png"]);
+        assert_eq!(Import.capture_window_id("0x3a00007", dest).unwrap().args, ["-window", "0x3a00007", "png:-"]);
+        assert_eq!(Import.capture_region(Some(&region), dest).unwrap().args, ["-window", "root", "-crop", "3x4+1+2", "+repage", "png:-"]);
+
+        for tool in builtin_tools() {
+            let command = tool.command_for(&CaptureMode::Full, dest).unwrap();
+            assert_eq!(command.program, tool.name());
+        }
+    }
+
+    #[test]
+    fn test_select_tool() {
+        let mut config = Config::default();
+        config.display_server.auto_detect = false;
+        config.display_server.preferred_server = Some("x11".to_string());
+        config.display_server.screenshot_tools.preferred_tool = Some("flameshot".to_string());
+
+        let runner = FakeRunner::new().with_output("scrot", CommandOutput::ok(""));
+        assert_eq!(select_tool(&config, &runner).unwrap().name(), "scrot");
+
+        runner.set_output("flameshot", CommandOutput::ok(""));
+        assert_eq!(select_tool(&config, &runner).unwrap().name(), "flameshot");
+
+        assert!(select_tool(&config, &FakeRunner::new()).is_err());
+
+        // Only import captures X11 windows by id
+        let by_id = CaptureMode::WindowId("42".to_string());
+        assert!(select_tool_for(&config, &runner, &by_id).is_err());
+        runner.set_output("import", CommandOutput::ok(""));
+        assert_eq!(select_tool_for(&config, &runner, &by_id).unwrap().name(), "import");
+    }
+
+    #[tokio::test]
+    async fn test_countdown() {
+        let mut out = Vec::new();
+        countdown(Duration::from_millis(20), true, &mut out).await.unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("\rCapturing in 1..."));
+        assert!(out.ends_with("\x1b[2K\x07"));
+
+        let mut out = Vec::new();
+        countdown(Duration::ZERO, false, &mut out).await.unwrap();
+        assert_eq!(out, b"\r\x1b[2K");
+    }
+
+    #[tokio::test]
+    async fn test_capture_from_stdout() {
+        let temp_dir = TempDir::new().unwrap();
+        let dest = temp_dir.path().join("shot.png");
+        let runner = FakeRunner::new().with_output("grim", CommandOutput::ok(b"\x89PNG".to_vec()));
+
+        capture(&Grim, &runner, &CaptureMode::Full, &dest).await.unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), b"\x89PNG");
+
+        let err = capture(&Grim, &runner, &CaptureMode::Window, &dest).await.unwrap_err();
+        assert_eq!(err.error_code(), "UNSUPPORTED");
+    }
+
+    #[tokio::test]
+    async fn test_capture_to_file_requires_output() {
+        let temp_dir = TempDir::new().unwrap();
+        let dest = temp_dir.path().join("shot.png");
+        let runner = FakeRunner::new().with_output("scrot", CommandOutput::ok(""));
+
+        // The fake does not create the file, as when a selection is cancelled
+        let err = capture(&Scrot, &runner, &CaptureMode::Region(None), &dest).await.unwrap_err();
+        assert_eq!(err.error_code(), "CANCELLED");
+
+        runner.set_output("scrot", CommandOutput::failed("giblib error"));
+        let err = capture(&Scrot, &runner, &CaptureMode::Full, &dest).await.unwrap_err();
+        assert!(err.to_string().contains("giblib error"));
+    }
+}
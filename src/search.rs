@@ -0,0 +1,311 @@
+//! The query language of `klipdot search`.
+//!
+//! A query is a list of terms that must all hold, like
+//! `source:clipboard size:>2MB before:2024-06-01 tag:bug text:"panic"`.
+//! Terms are `key:value` or a bare word, which searches the text; a leading
+//! `-` negates a term, and double quotes keep spaces in a value. Values are
+//! compared ignoring case, strings as substrings except tags, which match
+//! whole. Dates are local calendar days or ages like `7d` or `12h` counted
+//! back from now; sizes use the same 1024-based units `klipdot list` prints.
+//!
+//! Terms are checked against what [`Config::get_recent_screenshots`] reads
+//! from the metadata indexes, so no image is opened.
+//!
+//! [`Config::get_recent_screenshots`]: crate::config::Config::get_recent_screenshots
+
+use crate::{config::Screenshot, error::Result, Error};
+use chrono::{DateTime, Duration, Local, NaiveDate, TimeZone, Utc};
+
+/// Keys terms can have, for error messages
+const KEYS: &[&str] = &["source", "app", "output", "window", "workspace", "tag", "text", "size", "before", "after"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Filter {
+    Source(String),
+    App(String),
+    Output(String),
+    /// In the focused window's app id or title
+    Window(String),
+    Workspace(String),
+    Tag(String),
+    /// In the filename or description
+    Text(String),
+    Size(Comparison, u64),
+    Before(DateTime<Utc>),
+    After(DateTime<Utc>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Term {
+    negated: bool,
+    filter: Filter,
+}
+
+/// A parsed search query
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    terms: Vec<Term>,
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Self> {
+        Self::parse_at(input, Local::now())
+    }
+
+    /// Parse `input`, with ages counted back from `now`
+    pub fn parse_at(input: &str, now: DateTime<Local>) -> Result<Self> {
+        let terms = tokenize(input)?
+            .into_iter()
+            .map(|token| {
+                let filter = match token.key {
+                    Some(key) => parse_filter(&key, &token.value, now)?,
+                    None => Filter::Text(token.value.to_lowercase()),
+                };
+                Ok(Term { negated: token.negated, filter })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { terms })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Whether `screenshot` satisfies every term
+    pub fn matches(&self, screenshot: &Screenshot) -> bool {
+        self.terms.iter().all(|term| term.filter.matches(screenshot) != term.negated)
+    }
+}
+
+impl Filter {
+    fn matches(&self, screenshot: &Screenshot) -> bool {
+        let contains = |value: Option<&str>, needle: &str| value.is_some_and(|value| value.to_lowercase().contains(needle));
+        match self {
+            Filter::Source(needle) => contains(Some(&screenshot.source), needle),
+            Filter::App(needle) => contains(screenshot.app.as_deref(), needle),
+            Filter::Output(needle) => contains(screenshot.output.as_deref(), needle),
+            Filter::Window(needle) => screenshot.window.as_ref().is_some_and(|window| {
+                contains(window.app_id.as_deref(), needle) || contains(window.title.as_deref(), needle)
+            }),
+            Filter::Workspace(needle) => contains(screenshot.window.as_ref().and_then(|window| window.workspace.as_deref()), needle),
+            Filter::Tag(tag) => screenshot.tags.iter().any(|candidate| candidate.eq_ignore_ascii_case(tag)),
+            Filter::Text(needle) => contains(Some(&screenshot.filename), needle) || contains(screenshot.alt_text.as_deref(), needle),
+            Filter::Size(comparison, bytes) => match comparison {
+                Comparison::Less => screenshot.size < *bytes,
+                Comparison::LessOrEqual => screenshot.size <= *bytes,
+                Comparison::Greater => screenshot.size > *bytes,
+                Comparison::GreaterOrEqual => screenshot.size >= *bytes,
+                Comparison::Equal => screenshot.size == *bytes,
+            },
+            Filter::Before(time) => screenshot.created_at < *time,
+            Filter::After(time) => screenshot.created_at >= *time,
+        }
+    }
+}
+
+struct Token {
+    negated: bool,
+    key: Option<String>,
+    value: String,
+}
+
+/// Split `input` into terms, honouring double quotes
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Ok(tokens);
+        };
+        let negated = first == '-' && {
+            chars.next();
+            true
+        };
+
+        let (mut text, mut key, mut quoted, mut seen_quote) = (String::new(), None, false, false);
+        while let Some(c) = chars.next_if(|c| quoted || !c.is_whitespace()) {
+            match c {
+                '"' => {
+                    quoted = !quoted;
+                    seen_quote = true;
+                }
+                // The key ends at the first colon outside quotes
+                ':' if !quoted && !seen_quote && key.is_none() => key = Some(std::mem::take(&mut text).to_lowercase()),
+                c => text.push(c),
+            }
+        }
+        if quoted {
+            return Err(Error::InvalidInput(format!("Unclosed quote in search query: {}", input)));
+        }
+        if text.is_empty() && !seen_quote {
+            return Err(Error::InvalidInput(match key {
+                Some(key) => format!("Search term {}: has no value", key),
+                None => "A lone '-' in a search query negates nothing".to_string(),
+            }));
+        }
+        tokens.push(Token { negated, key, value: text });
+    }
+}
+
+fn parse_filter(key: &str, value: &str, now: DateTime<Local>) -> Result<Filter> {
+    let lower = value.to_lowercase();
+    Ok(match key {
+        "source" => Filter::Source(lower),
+        "app" => Filter::App(lower),
+        "output" => Filter::Output(lower),
+        "window" => Filter::Window(lower),
+        "workspace" => Filter::Workspace(lower),
+        "tag" => Filter::Tag(lower),
+        "text" => Filter::Text(lower),
+        "size" => {
+            let (comparison, size) = parse_size(value)?;
+            Filter::Size(comparison, size)
+        }
+        "before" => Filter::Before(parse_time(value, now, false)?),
+        "after" => Filter::After(parse_time(value, now, true)?),
+        _ => {
+            return Err(Error::InvalidInput(format!(
+                "Unknown search key {:?}; expected one of {} (quote values with colons)",
+                key,
+                KEYS.join(", ")
+            )))
+        }
+    })
+}
+
+/// `>2MB`, `<=500KB`, `=1024` and the like, in bytes
+fn parse_size(value: &str) -> Result<(Comparison, u64)> {
+    let invalid = || Error::InvalidInput(format!("Invalid size {:?}; expected a comparison and size like >2MB or <=500KB", value));
+    let (comparison, rest) = [
+        (">=", Comparison::GreaterOrEqual),
+        ("<=", Comparison::LessOrEqual),
+        (">", Comparison::Greater),
+        ("<", Comparison::Less),
+        ("=", Comparison::Equal),
+    ]
+    .into_iter()
+    .find_map(|(operator, comparison)| value.strip_prefix(operator).map(|rest| (comparison, rest)))
+    .ok_or_else(invalid)?;
+
+    let digits = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+    let number: f64 = rest[..digits].parse().map_err(|_| invalid())?;
+    let unit = match rest[digits..].trim().to_lowercase().as_str() {
+        "" | "b" => 1u64,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+    Ok((comparison, (number * unit as f64) as u64))
+}
+
+/// Start of a local `YYYY-MM-DD` day, or the start of the next one when
+/// `after` so `after:` excludes the day itself; or `now` less an age like
+/// `30m`, `12h`, `7d` or `2w`
+fn parse_time(value: &str, now: DateTime<Local>, after: bool) -> Result<DateTime<Utc>> {
+    let invalid = || Error::InvalidInput(format!("Invalid date {:?}; expected YYYY-MM-DD or an age like 7d", value));
+
+    if let Ok(day) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let day = if after { day.succ_opt().ok_or_else(invalid)? } else { day };
+        let midnight = day.and_hms_opt(0, 0, 0).ok_or_else(invalid)?;
+        // Midnight may not exist on a daylight saving change; the earliest valid reading is used
+        let local = Local.from_local_datetime(&midnight).earliest().ok_or_else(invalid)?;
+        return Ok(local.with_timezone(&Utc));
+    }
+
+    let unit_start = value.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let amount: i64 = value[..unit_start].parse().map_err(|_| invalid())?;
+    let age = match &value[unit_start..] {
+        "m" => Duration::minutes(amount),
+        "h" => Duration::hours(amount),
+        "d" => Duration::days(amount),
+        "w" => Duration::weeks(amount),
+        _ => return Err(invalid()),
+    };
+    Ok((now - age).with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn screenshot(filename: &str, source: &str, size: u64, created_at: DateTime<Utc>) -> Screenshot {
+        Screenshot {
+            filename: filename.to_string(),
+            path: PathBuf::from("/shots").join(filename),
+            size,
+            source: source.to_string(),
+            app: Some("Firefox".to_string()),
+            output: None,
+            resized_from: None,
+            alt_text: Some("Terminal showing a Rust panic".to_string()),
+            tags: vec!["bug".to_string()],
+            window: None,
+            created_at,
+            mime_type: "image/png".to_string(),
+        }
+    }
+
+    fn local(date: &str) -> DateTime<Local> {
+        let day = NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap().and_hms_opt(12, 0, 0).unwrap();
+        Local.from_local_datetime(&day).unwrap()
+    }
+
+    #[test]
+    fn test_query_matching() {
+        let now = local("2024-07-01");
+        let shot = screenshot("clipboard-1.png", "clipboard", 3 << 20, local("2024-05-20").with_timezone(&Utc));
+        let matches = |query: &str| Query::parse_at(query, now).unwrap().matches(&shot);
+
+        assert!(matches(r#"source:clipboard size:>2MB before:2024-06-01 tag:bug text:"panic""#));
+        assert!(matches(r#"app:firefox "rust panic" clipboard"#));
+        assert!(matches("after:2024-05-19 before:2024-05-21 after:6w"));
+        assert!(matches("-tag:feature -source:screenshot size:<=3M"));
+        assert!(matches(""));
+
+        assert!(!matches("source:wayland"));
+        assert!(!matches("size:<3MB"));
+        assert!(!matches("after:2024-05-20"));
+        assert!(!matches("before:2024-05-20"));
+        assert!(!matches("after:4w"));
+        // Tags match whole, unlike the other keys
+        assert!(!matches("tag:bu"));
+        assert!(!matches("-text:PANIC"));
+        assert!(!matches("output:DP-1"));
+        assert!(!matches("window:firefox"));
+
+        let window = crate::focus::WindowContext {
+            app_id: Some("firefox".to_string()),
+            title: Some("Issue 12 - Mozilla Firefox".to_string()),
+            workspace: Some("2:web".to_string()),
+        };
+        let focused = Screenshot { window: Some(window), ..shot.clone() };
+        let matches = |query: &str| Query::parse_at(query, now).unwrap().matches(&focused);
+        assert!(matches("window:firefox workspace:web"));
+        assert!(matches(r#"window:"issue 12""#));
+        assert!(!matches("workspace:3"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for query in ["souce:clipboard", "size:2MB", "size:>2XB", "before:June", "after:3y", "tag:", r#"text:"panic"#, "-"] {
+            let err = Query::parse(query).unwrap_err();
+            assert_eq!(err.error_code(), "INVALID_INPUT", "{}", query);
+        }
+        // Quoted colons aren't keys, and empty quotes are a value
+        assert_eq!(Query::parse(r#""localhost:3000""#).unwrap().terms[0].filter, Filter::Text("localhost:3000".to_string()));
+        assert_eq!(Query::parse(r#"app:"""#).unwrap().terms[0].filter, Filter::App(String::new()));
+        assert_eq!(parse_size(">=1.5KB").unwrap(), (Comparison::GreaterOrEqual, 1536));
+    }
+}
@@ -0,0 +1,125 @@
+//! Tokens for upload targets, kept encrypted in the system keyring: the
+//! login keychain through `security` on macOS, and the Secret Service
+//! (GNOME Keyring, KWallet) through libsecret's `secret-tool` elsewhere.
+//!
+//! `KLIPDOT_SECRET_<NAME>` overrides a stored secret, for CI and machines
+//! without a keyring; `slack` becomes `KLIPDOT_SECRET_SLACK`.
+
+use crate::{command_runner::CommandRunner, error::Result, Error};
+use tracing::debug;
+
+/// Service the secrets are stored under
+const SERVICE: &str = "klipdot";
+
+/// Environment variable overriding the secret `name`
+pub fn env_var(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    format!("KLIPDOT_SECRET_{}", name)
+}
+
+fn keyring_tool() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "security"
+    } else {
+        "secret-tool"
+    }
+}
+
+/// The secret `name`, if one is set
+pub async fn get(runner: &dyn CommandRunner, name: &str) -> Result<Option<String>> {
+    if let Some(value) = std::env::var(env_var(name)).ok().filter(|value| !value.is_empty()) {
+        debug!("Secret {} comes from the environment", name);
+        return Ok(Some(value));
+    }
+
+    let tool = keyring_tool();
+    if !runner.is_available(tool) {
+        return Ok(None);
+    }
+    let output = match tool {
+        "security" => runner.run(tool, &["find-generic-password", "-s", SERVICE, "-a", name, "-w"], None).await,
+        _ => runner.run(tool, &["lookup", "service", SERVICE, "name", name], None).await,
+    }
+    .map_err(|e| Error::Process(format!("Failed to run {}: {}", tool, e)))?;
+
+    // Both tools fail when there's no such secret
+    let value = output.stdout_lossy().trim_end_matches(['\r', '\n']).to_string();
+    Ok((output.success && !value.is_empty()).then_some(value))
+}
+
+/// Store `value` as the secret `name`, replacing any earlier one
+pub async fn set(runner: &dyn CommandRunner, name: &str, value: &str) -> Result<()> {
+    let tool = keyring_tool();
+    if !runner.is_available(tool) {
+        return Err(Error::Unsupported(format!(
+            "{} is needed to store secrets; set {} instead",
+            tool,
+            env_var(name)
+        )));
+    }
+    let label = format!("KlipDot {}", name);
+    let output = match tool {
+        // security only takes the password as an argument
+        "security" => runner.run(tool, &["add-generic-password", "-U", "-s", SERVICE, "-a", name, "-l", &label, "-w", value], None).await,
+        _ => runner.run(tool, &["store", "--label", &label, "service", SERVICE, "name", name], Some(value.as_bytes())).await,
+    }
+    .map_err(|e| Error::Process(format!("Failed to run {}: {}", tool, e)))?;
+    if !output.success {
+        return Err(Error::Process(format!("Failed to store secret {}: {}", name, output.stderr_lossy().trim())));
+    }
+    Ok(())
+}
+
+/// Remove the secret `name`; removing one that isn't set is not an error
+pub async fn delete(runner: &dyn CommandRunner, name: &str) -> Result<()> {
+    let tool = keyring_tool();
+    if !runner.is_available(tool) {
+        return Ok(());
+    }
+    let output = match tool {
+        "security" => runner.run(tool, &["delete-generic-password", "-s", SERVICE, "-a", name], None).await,
+        _ => runner.run(tool, &["clear", "service", SERVICE, "name", name], None).await,
+    }
+    .map_err(|e| Error::Process(format!("Failed to run {}: {}", tool, e)))?;
+    if !output.success {
+        debug!("No secret {} to remove: {}", name, output.stderr_lossy().trim());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+
+    #[test]
+    fn test_env_var() {
+        assert_eq!(env_var("slack"), "KLIPDOT_SECRET_SLACK");
+        assert_eq!(env_var("discord-alerts"), "KLIPDOT_SECRET_DISCORD_ALERTS");
+    }
+
+    #[tokio::test]
+    async fn test_keyring() {
+        let tool = keyring_tool();
+        let runner = FakeRunner::new();
+        assert_eq!(get(&runner, "test-missing").await.unwrap(), None);
+        assert!(set(&runner, "test-missing", "x").await.is_err());
+
+        runner.set_output(tool, CommandOutput::ok("xoxb-123\n"));
+        assert_eq!(get(&runner, "test-slack").await.unwrap().as_deref(), Some("xoxb-123"));
+        set(&runner, "test-slack", "xoxb-456").await.unwrap();
+        let calls = runner.calls_to(tool);
+        if tool == "secret-tool" {
+            // The secret goes in on stdin, not the command line
+            assert_eq!(calls[1].stdin.as_deref(), Some(&b"xoxb-456"[..]));
+            assert!(!calls[1].args.iter().any(|arg| arg.contains("xoxb")));
+        }
+
+        runner.set_output(tool, CommandOutput::failed("No matching secret"));
+        assert_eq!(get(&runner, "test-slack").await.unwrap(), None);
+        delete(&runner, "test-slack").await.unwrap();
+    }
+}
@@ -0,0 +1,61 @@
+//! Clipboard content KlipDot keeps its hands off.
+//!
+//! Password managers copy secrets to the clipboard, and nothing copied there
+//! should end up in a log, the clipboard history or the screenshot store, or
+//! be replaced by a path. Two things mark content as sensitive:
+//!
+//! - a type on offer from `sensitive.types`: KeePassXC and KDE's tools add
+//!   `x-kde-passwordManagerHint` to secrets they copy, macOS password
+//!   managers `org.nspasteboard.ConcealedType`. Seeing one, the clipboard
+//!   isn't read at all. Only checked where the types can be listed, see
+//!   [`crate::clipboard_types`] and, on macOS, `crate::pasteboard`.
+//! - the focused app (see [`crate::focus`]) matching `sensitive.apps`, a
+//!   case-insensitive substring test like `pause.deny_apps`. The clipboard
+//!   doesn't say which app wrote to it, so the app focused when the change is
+//!   noticed stands in for it.
+
+use crate::config::SensitiveConfig;
+
+/// Default `sensitive.types`
+pub const DEFAULT_TYPES: &[&str] = &["x-kde-passwordManagerHint", "org.nspasteboard.ConcealedType"];
+
+/// Default `sensitive.apps`: password managers and keyring browsers
+pub const DEFAULT_APPS: &[&str] = &["keepass", "1password", "bitwarden", "enpass", "seahorse", "kwalletmanager"];
+
+/// The offered type that marks the clipboard as sensitive, if any
+pub fn marked<'a>(config: &SensitiveConfig, offered: &'a [String]) -> Option<&'a str> {
+    crate::clipboard_types::choose(offered, &config.types)
+}
+
+/// Whether content copied while `app` is focused is sensitive
+pub fn from_app(config: &SensitiveConfig, app: Option<&str>) -> bool {
+    let Some(app) = app else {
+        return false;
+    };
+    let lower = app.to_lowercase();
+    config.apps.iter().any(|pattern| lower.contains(&pattern.to_lowercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sensitive_content() {
+        let config = SensitiveConfig::default();
+        let keepassxc = ["x-kde-passwordManagerHint".to_string(), "text/plain;charset=utf-8".to_string()];
+        assert_eq!(marked(&config, &keepassxc), Some("x-kde-passwordManagerHint"));
+        assert_eq!(marked(&config, &keepassxc[1..]), None);
+        let concealed = ["public.utf8-plain-text".to_string(), "org.nspasteboard.ConcealedType".to_string()];
+        assert_eq!(marked(&config, &concealed), Some("org.nspasteboard.ConcealedType"));
+
+        assert!(from_app(&config, Some("org.keepassxc.KeePassXC")));
+        assert!(from_app(&config, Some("1Password")));
+        assert!(!from_app(&config, Some("firefox")));
+        assert!(!from_app(&config, None));
+
+        let nothing = SensitiveConfig { types: Vec::new(), apps: Vec::new() };
+        assert_eq!(marked(&nothing, &keepassxc), None);
+        assert!(!from_app(&nothing, Some("KeePassXC")));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{config::Config, error::Result, Error};
+use crate::{config::Config, error::Result, instance_lock, Error};
 use std::path::PathBuf;
 use std::process::Stdio;
 use std::time::{Duration, SystemTime};
@@ -7,6 +7,7 @@ use tokio::time::sleep;
 use tracing::{info, warn};
 
 pub struct ServiceManager {
+    run_dir: PathBuf,
     pid_file: PathBuf,
     log_file: PathBuf,
 }
@@ -20,15 +21,23 @@ pub struct ServiceStatus {
     pub cpu_usage: Option<f64>,
 }
 
+impl Default for ServiceManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ServiceManager {
     pub fn new() -> Self {
         let home_dir = crate::get_home_dir().unwrap_or_else(|_| {
             std::env::temp_dir().join(".klipdot")
         });
+        let run_dir = crate::run_state::dir().unwrap_or_else(|_| home_dir.join(crate::RUN_DIR));
         
         Self {
-            pid_file: home_dir.join(crate::PID_FILE),
+            pid_file: run_dir.join(crate::PID_FILE),
             log_file: home_dir.join(crate::LOG_FILE),
+            run_dir,
         }
     }
     
@@ -40,6 +49,12 @@ impl ServiceManager {
             return Err(Error::AlreadyExists("Service is already running".to_string()));
         }
         
+        // A foreground instance has no PID file but holds the instance lock
+        if instance_lock::is_held(&service_manager.run_dir) {
+            let pid = instance_lock::holder_pid(&service_manager.run_dir);
+            return Err(Error::AlreadyExists(instance_lock::already_running(pid)));
+        }
+        
         info!("Starting KlipDot daemon");
         
         // Get current executable path
@@ -147,9 +162,12 @@ impl ServiceManager {
     }
     
     pub async fn status(&self) -> Result<ServiceStatus> {
-        let running = self.is_running().await?;
-        
-        if !running {
+        let pid = if self.is_running().await? {
+            Some(self.read_pid_file().await?)
+        } else if instance_lock::is_held(&self.run_dir) {
+            // Started in the foreground rather than as a daemon
+            instance_lock::holder_pid(&self.run_dir)
+        } else {
             return Ok(ServiceStatus {
                 running: false,
                 pid: None,
@@ -157,9 +175,17 @@ impl ServiceManager {
                 memory_usage: None,
                 cpu_usage: None,
             });
-        }
+        };
+        let Some(pid) = pid else {
+            return Ok(ServiceStatus {
+                running: true,
+                pid: None,
+                uptime: None,
+                memory_usage: None,
+                cpu_usage: None,
+            });
+        };
         
-        let pid = self.read_pid_file().await?;
         let uptime = self.get_process_uptime(pid).await?;
         let memory_usage = self.get_process_memory_usage(pid).await?;
         let cpu_usage = self.get_process_cpu_usage(pid).await?;
@@ -179,7 +205,13 @@ impl ServiceManager {
         }
         
         let pid = self.read_pid_file().await?;
-        self.is_process_running(pid).await
+        if self.is_process_running(pid).await? {
+            return Ok(true);
+        }
+        // Left by a daemon that was killed before it could remove it
+        warn!("Removing stale PID file for exited process {}", pid);
+        self.remove_pid_file().await?;
+        Ok(false)
     }
     
     async fn read_pid_file(&self) -> Result<u32> {
@@ -380,6 +412,7 @@ mod tests {
     async fn test_pid_file_operations() {
         let temp_dir = TempDir::new().unwrap();
         let service_manager = ServiceManager {
+            run_dir: temp_dir.path().to_path_buf(),
             pid_file: temp_dir.path().join("test.pid"),
             log_file: temp_dir.path().join("test.log"),
         };
@@ -402,6 +435,7 @@ mod tests {
     async fn test_service_status_not_running() {
         let temp_dir = TempDir::new().unwrap();
         let service_manager = ServiceManager {
+            run_dir: temp_dir.path().to_path_buf(),
             pid_file: temp_dir.path().join("test.pid"),
             log_file: temp_dir.path().join("test.log"),
         };
@@ -409,12 +443,19 @@ mod tests {
         let status = service_manager.status().await.unwrap();
         assert!(!status.running);
         assert!(status.pid.is_none());
+        
+        // A foreground instance is found through its lock
+        let _lock = instance_lock::acquire(temp_dir.path()).unwrap();
+        let status = service_manager.status().await.unwrap();
+        assert!(status.running);
+        assert_eq!(status.pid, Some(std::process::id()));
     }
     
     #[tokio::test]
     async fn test_log_operations() {
         let temp_dir = TempDir::new().unwrap();
         let service_manager = ServiceManager {
+            run_dir: temp_dir.path().to_path_buf(),
             pid_file: temp_dir.path().join("test.pid"),
             log_file: temp_dir.path().join("test.log"),
         };
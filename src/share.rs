@@ -0,0 +1,249 @@
+//! A folder of images shared for a while, for handing screenshots to
+//! teammates through a synced or served directory.
+//!
+//! `klipdot share <image> --ttl 1h` copies the image into `share.dir` and
+//! records when it expires in an index there ([`SHARES_INDEX`]). The running
+//! instance deletes expired copies, checking every [`EXPIRY_CHECK`];
+//! `klipdot share` also clears out expired ones when it runs, so they don't
+//! linger while KlipDot isn't running. The stored image itself is untouched.
+//! Both rewrite the index, so they take [`SHARES_LOCK`] first and neither
+//! loses what the other added.
+
+use crate::{config::Config, error::Result, store_permissions, Error};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Index of the shared copies and their expiry, in the share directory
+pub const SHARES_INDEX: &str = ".shares.json";
+
+/// Locked while the index is read and rewritten; the index itself is
+/// replaced on every write, so it can't carry the lock
+pub const SHARES_LOCK: &str = ".shares.lock";
+
+/// How often the running instance deletes expired copies
+pub const EXPIRY_CHECK: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Most names tried with a counter before giving up
+const MAX_COUNTER: u32 = 1000;
+
+/// One shared copy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Share {
+    /// File name in the share directory
+    pub file: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A time to live like `90s`, `30m`, `1h`, `2d` or `1w`
+pub fn parse_ttl(value: &str) -> Result<Duration> {
+    let invalid = || Error::InvalidInput(format!("Invalid TTL {:?}; expected a duration like 30m, 1h or 2d", value));
+    let unit_start = value.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let amount: i64 = value[..unit_start].parse().map_err(|_| invalid())?;
+    let ttl = match &value[unit_start..] {
+        "s" => Duration::try_seconds(amount),
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        "w" => Duration::try_weeks(amount),
+        _ => None,
+    };
+    ttl.filter(|ttl| *ttl > Duration::zero()).ok_or_else(invalid)
+}
+
+/// Copy `image` into the share directory until `now` plus `ttl`
+pub async fn add(config: &Config, image: &Path, ttl: Duration, now: DateTime<Utc>) -> Result<(PathBuf, Share)> {
+    let dir = &config.share.dir;
+    store_permissions::create_dir(dir, &config.store_permissions).await?;
+    let name = image
+        .file_name()
+        .ok_or_else(|| Error::InvalidInput(format!("Not an image file: {:?}", image)))?;
+    let data = tokio::fs::read(image).await?;
+
+    // Held from choosing the name on, so concurrent shares of one file get a name each
+    let _lock = lock(dir).await?;
+    let path = free_name(dir, Path::new(name))?;
+    store_permissions::write(&path, &data, &config.store_permissions).await?;
+    let share = Share {
+        file: path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+        expires_at: now + ttl,
+    };
+    let mut shares = load(dir).await?;
+    shares.push(share.clone());
+    save(dir, &shares).await?;
+    Ok((path, share))
+}
+
+/// `name` in `dir`, with a counter added if a file is already called that
+fn free_name(dir: &Path, name: &Path) -> Result<PathBuf> {
+    let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = name.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+    (0..MAX_COUNTER)
+        .map(|counter| match counter {
+            0 => dir.join(name),
+            n => dir.join(format!("{}-{}{}", stem, n, extension)),
+        })
+        .find(|path| !path.exists())
+        .ok_or_else(|| Error::AlreadyExists(format!("Too many shared copies of {:?}", name)))
+}
+
+/// Delete the copies that expired by `now`, returning how many were deleted
+pub async fn expire(config: &Config, now: DateTime<Utc>) -> Result<usize> {
+    let dir = &config.share.dir;
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+    let _lock = lock(dir).await?;
+    let mut shares = load(dir).await?;
+    let before = shares.len();
+    let mut kept = Vec::with_capacity(shares.len());
+    for share in shares.drain(..) {
+        if share.expires_at > now {
+            kept.push(share);
+            continue;
+        }
+        match tokio::fs::remove_file(dir.join(&share.file)).await {
+            Ok(()) => info!("Shared copy {} expired", share.file),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            // Kept in the index so the next check tries again
+            Err(e) => {
+                warn!("Failed to delete expired shared copy {}: {}", share.file, e);
+                kept.push(share);
+            }
+        }
+    }
+
+    let expired = before - kept.len();
+    if expired > 0 {
+        save(dir, &kept).await?;
+    }
+    Ok(expired)
+}
+
+/// Delete expired copies now and every [`EXPIRY_CHECK`]; never finishes
+pub async fn expire_shares(config: Config) {
+    let mut interval = tokio::time::interval(EXPIRY_CHECK);
+    loop {
+        interval.tick().await;
+        if let Err(e) = expire(&config, Utc::now()).await {
+            warn!("Failed to delete expired shared copies: {}", e);
+            crate::error_history::record_error("share", &e);
+        }
+    }
+}
+
+/// The shared copies in `dir`, in the order they were shared
+pub async fn load(dir: &Path) -> Result<Vec<Share>> {
+    match tokio::fs::read_to_string(dir.join(SHARES_INDEX)).await {
+        Ok(content) => Ok(serde_json::from_str(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Wait for the index lock in `dir`, held until the returned file is dropped
+async fn lock(dir: &Path) -> Result<std::fs::File> {
+    let path = dir.join(SHARES_LOCK);
+    tokio::task::spawn_blocking(move || {
+        let file = std::fs::OpenOptions::new().create(true).truncate(false).write(true).open(path)?;
+        crate::lock_file(&file)?;
+        Ok(file)
+    })
+    .await
+    .map_err(|e| Error::Internal(format!("Share index lock task failed: {}", e)))?
+}
+
+/// Replace the index through a temporary file, so a crash leaves the old or the new one
+async fn save(dir: &Path, shares: &[Share]) -> Result<()> {
+    let temp = dir.join(format!("{}.tmp", SHARES_INDEX));
+    tokio::fs::write(&temp, serde_json::to_vec_pretty(shares)?).await?;
+    tokio::fs::rename(&temp, dir.join(SHARES_INDEX)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_ttl() {
+        assert_eq!(parse_ttl("90s").unwrap(), Duration::seconds(90));
+        assert_eq!(parse_ttl("1h").unwrap(), Duration::hours(1));
+        assert_eq!(parse_ttl("2w").unwrap(), Duration::weeks(2));
+        for invalid in ["", "h", "1", "0m", "1y", "-1h", "1.5h"] {
+            assert!(parse_ttl(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shares_expire() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = Config::default();
+        config.share.dir = temp_dir.path().join("share");
+        let image = temp_dir.path().join("clipboard-1.png");
+        std::fs::write(&image, b"png").unwrap();
+
+        let now = Utc::now();
+        let (first, _) = add(&config, &image, Duration::minutes(5), now).await.unwrap();
+        let (second, share) = add(&config, &image, Duration::hours(1), now).await.unwrap();
+        assert_eq!(first.file_name().unwrap(), "clipboard-1.png");
+        assert_eq!(share.file, "clipboard-1-1.png");
+        assert_eq!(std::fs::read(&second).unwrap(), b"png");
+
+        assert_eq!(expire(&config, now + Duration::minutes(1)).await.unwrap(), 0);
+        assert_eq!(expire(&config, now + Duration::minutes(10)).await.unwrap(), 1);
+        assert!(!first.exists() && second.exists() && image.exists());
+        assert_eq!(load(&config.share.dir).await.unwrap(), [share]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_shares_are_kept() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = Config::default();
+        config.share.dir = temp_dir.path().join("share");
+        let image = temp_dir.path().join("clipboard-1.png");
+        std::fs::write(&image, b"png").unwrap();
+        let now = Utc::now();
+        add(&config, &image, Duration::seconds(1), now).await.unwrap();
+
+        // Expiring while others share mustn't drop their entries
+        let mut tasks = Vec::new();
+        for i in 0..16 {
+            let (config, image) = (config.clone(), image.with_file_name(format!("clipboard-{}.png", i + 2)));
+            std::fs::copy(temp_dir.path().join("clipboard-1.png"), &image).unwrap();
+            tasks.push(tokio::spawn(async move {
+                add(&config, &image, Duration::hours(1), now).await.unwrap();
+                expire(&config, now + Duration::minutes(1)).await.unwrap();
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(load(&config.share.dir).await.unwrap().len(), 16);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_shares_of_one_file_get_a_copy_each() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = Config::default();
+        config.share.dir = temp_dir.path().join("share");
+        let image = temp_dir.path().join("clipboard-1.png");
+        std::fs::write(&image, b"png").unwrap();
+
+        let now = Utc::now();
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let (config, image) = (config.clone(), image.clone());
+                tokio::spawn(async move { add(&config, &image, Duration::hours(1), now).await.unwrap().1.file })
+            })
+            .collect();
+        let mut files = std::collections::HashSet::new();
+        for task in tasks {
+            files.insert(task.await.unwrap());
+        }
+        assert_eq!(files.len(), 8);
+        assert!(files.iter().all(|file| config.share.dir.join(file).is_file()));
+    }
+}
@@ -0,0 +1,327 @@
+//! `klipdot sink`: a named pipe (or stdin) that scripts write images to,
+//! such as matplotlib figures saved with the agg backend from a script or a
+//! Jupyter kernel. Each image written is stored and indexed like any other,
+//! under the source `sink` with the sink's name as its app, and previewed.
+//!
+//! PNG images are split out of the stream as soon as their `IEND` chunk
+//! arrives, so one writer can stream several figures through the pipe. Any
+//! other format is taken whole when the writer closes the pipe.
+
+use crate::{config::Config, error::Result, image_processor::ImageProcessor, output, Error};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+/// Source recorded for images written to a sink
+pub const SOURCE: &str = "sink";
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Where a sink reads images from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    Stdin,
+    /// A named pipe, reopened after each writer closes it
+    Fifo(PathBuf),
+}
+
+/// Splits a stream of bytes into images
+#[derive(Debug, Default)]
+struct Framer {
+    buffer: Vec<u8>,
+}
+
+impl Framer {
+    /// Add `data`, returning the PNG images it completed
+    fn push(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+        let mut images = Vec::new();
+        while let Some(end) = png_end(&self.buffer) {
+            images.push(self.buffer.drain(..end).collect());
+        }
+        images
+    }
+
+    /// Whatever is left when the writer closes: an image in another format, or a cut-off PNG
+    fn finish(&mut self) -> Option<Vec<u8>> {
+        let rest = std::mem::take(&mut self.buffer);
+        (!rest.is_empty()).then_some(rest)
+    }
+
+    fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+/// Length of the complete PNG image at the start of `data`, if there is one
+fn png_end(data: &[u8]) -> Option<usize> {
+    if !data.starts_with(PNG_SIGNATURE) {
+        return None;
+    }
+    let mut offset = PNG_SIGNATURE.len();
+    loop {
+        let header = data.get(offset..offset + 8)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        // Length, type, data and CRC
+        let end = offset.checked_add(12)?.checked_add(length)?;
+        if data.len() < end {
+            return None;
+        }
+        if &header[4..8] == b"IEND" {
+            return Some(end);
+        }
+        offset = end;
+    }
+}
+
+/// The named pipe of the sink called `name`
+pub fn fifo_path(home: &Path, name: &str) -> Result<PathBuf> {
+    let plain = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) && !name.starts_with('.');
+    if !plain {
+        return Err(Error::InvalidInput(format!(
+            "Invalid sink name '{}', use letters, digits, '-', '_' and '.'",
+            name
+        )));
+    }
+    Ok(home.join(crate::SINKS_DIR).join(name))
+}
+
+/// Create the named pipe at `path`, readable and writable only by the user;
+/// an existing pipe is reused
+#[cfg(unix)]
+pub fn create_fifo(path: &Path) -> Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_fifo() {
+            return Ok(());
+        }
+        return Err(Error::InvalidInput(format!("{:?} exists and isn't a named pipe", path)));
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| Error::InvalidInput(format!("Invalid sink path {:?}", path)))?;
+    // SAFETY: `c_path` is a valid NUL-terminated string for the duration of the call
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn create_fifo(_path: &Path) -> Result<()> {
+    Err(Error::Unsupported("Named pipe sinks need a Unix system; pipe images to `klipdot sink --stdin` instead".to_string()))
+}
+
+/// Read images from `reader` until it closes, sending each; once over
+/// `limit` bytes are buffered without an image ending, that's reported and
+/// the rest of the write skipped. Returns false once nothing is receiving.
+fn read_images(mut reader: impl Read, limit: u64, sender: &mpsc::Sender<Result<Vec<u8>>>) -> bool {
+    let mut framer = Framer::default();
+    let mut skipping = false;
+    let mut chunk = vec![0u8; 64 * 1024];
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return sender.blocking_send(Err(e.into())).is_ok(),
+        };
+        if skipping {
+            continue;
+        }
+        for image in framer.push(&chunk[..read]) {
+            if sender.blocking_send(Ok(image)).is_err() {
+                return false;
+            }
+        }
+        if framer.pending() as u64 > limit {
+            skipping = true;
+            framer = Framer::default();
+            let too_large = Error::InvalidInput(format!("Image larger than {}, skipped", crate::format_file_size(limit)));
+            if sender.blocking_send(Err(too_large)).is_err() {
+                return false;
+            }
+        }
+    }
+    match framer.finish() {
+        Some(rest) if !skipping => sender.blocking_send(Ok(rest)).is_ok(),
+        _ => true,
+    }
+}
+
+/// Read `input` on its own thread, since opening a named pipe blocks until a writer comes
+fn spawn_reader(input: Input, limit: u64) -> mpsc::Receiver<Result<Vec<u8>>> {
+    let (sender, receiver) = mpsc::channel(4);
+    std::thread::spawn(move || match input {
+        Input::Stdin => {
+            read_images(std::io::stdin().lock(), limit, &sender);
+        }
+        Input::Fifo(path) => loop {
+            match std::fs::File::open(&path) {
+                Ok(fifo) => {
+                    debug!("Writer opened sink {:?}", path);
+                    if !read_images(fifo, limit, &sender) {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    let _ = sender.blocking_send(Err(e.into()));
+                    break;
+                }
+            }
+        },
+    });
+    receiver
+}
+
+pub struct Sink {
+    name: String,
+    config: Config,
+    processor: ImageProcessor,
+    #[cfg(feature = "preview")]
+    previews: Option<crate::image_preview::ImagePreviewManager>,
+}
+
+impl Sink {
+    /// A sink called `name`, previewing each image when `preview` is set
+    pub async fn new(config: &Config, name: &str, preview: bool) -> Result<Self> {
+        #[cfg(feature = "preview")]
+        let previews = match preview {
+            true => Some(crate::image_preview::ImagePreviewManager::new(config.clone()).await?),
+            false => None,
+        };
+        #[cfg(not(feature = "preview"))]
+        let _ = preview;
+        Ok(Self {
+            name: name.to_string(),
+            config: config.clone(),
+            processor: ImageProcessor::new(config.clone()).await?,
+            #[cfg(feature = "preview")]
+            previews,
+        })
+    }
+
+    /// Take images from `input` until it closes, which a named pipe never
+    /// does; returns how many were stored
+    pub async fn run(&self, input: Input) -> Result<usize> {
+        let mut images = spawn_reader(input, self.config.max_file_size_for(SOURCE));
+        let mut stored = 0;
+        while let Some(image) = images.recv().await {
+            match image {
+                Ok(data) => match self.take(&data).await {
+                    Ok(_) => stored += 1,
+                    Err(e) => eprintln!("{}", output::decorate("❌", format!("Sink {}: {}", self.name, e))),
+                },
+                Err(e) => eprintln!("{}", output::decorate("❌", format!("Sink {}: {}", self.name, e))),
+            }
+        }
+        Ok(stored)
+    }
+
+    /// Store `data`, print where, and preview it
+    pub async fn take(&self, data: &[u8]) -> Result<PathBuf> {
+        let path = self.processor.process_image_data_from(data, SOURCE, Some(&self.name), None).await?;
+        println!("{}", path.display());
+        #[cfg(feature = "preview")]
+        if let Some(previews) = &self.previews {
+            let (width, height) = self.config.preview.size();
+            if let Err(e) = previews.show_preview(&path, width, height).await {
+                tracing::warn!("Failed to preview {:?}: {}", path, e);
+            }
+        }
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn png_bytes(width: u32) -> Vec<u8> {
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(width, 4))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn test_framing() {
+        let (first, second) = (png_bytes(4), png_bytes(8));
+        let stream = [first.as_slice(), second.as_slice()].concat();
+
+        // However the writes are split, images come out whole as soon as they end
+        let mut framer = Framer::default();
+        assert!(framer.push(&stream[..first.len() - 1]).is_empty());
+        assert_eq!(framer.push(&stream[first.len() - 1..first.len() + 10]), [first.as_slice()]);
+        assert_eq!(framer.push(&stream[first.len() + 10..]), [second]);
+        assert_eq!(framer.finish(), None);
+
+        // Other formats wait for the writer to close
+        assert!(framer.push(b"GIF89a...").is_empty());
+        assert_eq!(framer.finish().as_deref(), Some(&b"GIF89a..."[..]));
+    }
+
+    #[tokio::test]
+    async fn test_sink_stores_each_image() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().join("shots"),
+            ..Config::default()
+        };
+        let stream = [png_bytes(4), png_bytes(8), b"not an image".to_vec()].concat();
+        let (sender, mut receiver) = mpsc::channel(4);
+        let reader = std::thread::spawn(move || read_images(stream.as_slice(), 1024 * 1024, &sender));
+
+        let sink = Sink::new(&config, "plots", false).await.unwrap();
+        let mut stored = Vec::new();
+        let mut failed = 0;
+        while let Some(image) = receiver.recv().await {
+            match sink.take(&image.unwrap()).await {
+                Ok(path) => stored.push(path),
+                Err(_) => failed += 1,
+            }
+        }
+        assert!(reader.join().unwrap());
+        assert_eq!((stored.len(), failed), (2, 1));
+
+        let index = crate::metadata::load(stored[0].parent().unwrap()).await.unwrap();
+        let entry = &index[stored[1].file_name().unwrap().to_string_lossy().as_ref()];
+        assert_eq!((entry.source.as_str(), entry.app.as_deref()), (SOURCE, Some("plots")));
+    }
+
+    #[test]
+    fn test_limits_and_names() {
+        let (sender, mut receiver) = mpsc::channel(4);
+        // Buffering stops at the limit; whole images are left to the size limits of storing
+        assert!(read_images([0u8; 100].as_slice(), 16, &sender));
+        assert!(receiver.try_recv().unwrap().is_err());
+        assert!(receiver.try_recv().is_err());
+
+        let home = Path::new("/home/me/.klipdot");
+        assert_eq!(fifo_path(home, "plots").unwrap(), home.join("sinks/plots"));
+        assert!(fifo_path(home, "../plots").is_err());
+        assert!(fifo_path(home, "").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_create_fifo() {
+        use std::os::unix::fs::FileTypeExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("sinks/plots");
+        create_fifo(&path).unwrap();
+        assert!(std::fs::metadata(&path).unwrap().file_type().is_fifo());
+        create_fifo(&path).unwrap();
+
+        std::fs::write(temp_dir.path().join("file"), b"").unwrap();
+        assert!(create_fifo(&temp_dir.path().join("file")).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+//! Telling images apart by their content rather than their name, so a text
+//! file renamed to `.png` is left alone and a screenshot saved without an
+//! extension is still picked up.
+//!
+//! Magic numbers are matched with `infer`; HDR formats it doesn't know and
+//! SVG, which is text, are recognised here.
+
+use image::ImageFormat;
+use std::io::Read;
+use std::path::Path;
+
+/// Bytes read from the start of a file to sniff it; enough for an SVG's
+/// XML declaration and comments before its `<svg` element
+pub const HEADER_LEN: usize = 4096;
+
+/// Usual extension of the image format `data` starts with, or `None` when
+/// it isn't an image
+pub fn image_extension(data: &[u8]) -> Option<&'static str> {
+    // infer calls an SVG's XML declaration text, so other kinds fall through
+    if let Some(kind) = infer::get(data).filter(|kind| kind.matcher_type() == infer::MatcherType::Image) {
+        return Some(kind.extension());
+    }
+    match image::guess_format(data) {
+        Ok(ImageFormat::Hdr) => Some("hdr"),
+        Ok(ImageFormat::OpenExr) => Some("exr"),
+        _ => is_svg(data).then_some("svg"),
+    }
+}
+
+pub fn is_image(data: &[u8]) -> bool {
+    image_extension(data).is_some()
+}
+
+/// Usual extension of the image in the regular file at `path`, or `None`
+/// when it's something else or can't be read. Pipes and devices are never
+/// opened, so this doesn't block.
+pub fn file_image_extension(path: &Path) -> Option<&'static str> {
+    if !std::fs::metadata(path).ok()?.is_file() {
+        return None;
+    }
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    std::fs::File::open(path)
+        .ok()?
+        .take(HEADER_LEN as u64)
+        .read_to_end(&mut header)
+        .ok()?;
+    image_extension(&header)
+}
+
+/// Whether `data` is the start of an SVG document
+fn is_svg(data: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(data);
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    text.starts_with('<') && text.contains("<svg")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_image_extension() {
+        assert_eq!(image_extension(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some("png"));
+        assert_eq!(image_extension(&[0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, 0x4A, 0x46]), Some("jpg"));
+        assert_eq!(image_extension(b"GIF89a\x01\0\x01\0"), Some("gif"));
+        assert_eq!(image_extension(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n"), Some("hdr"));
+        assert_eq!(
+            image_extension(b"<?xml version=\"1.0\"?>\n<!-- logo -->\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>"),
+            Some("svg")
+        );
+
+        assert_eq!(image_extension(b"Hello, world!"), None);
+        assert_eq!(image_extension(b"<html><body>no svg here</body></html>"), None);
+        // Other files infer knows aren't mistaken for images
+        assert_eq!(image_extension(b"%PDF-1.7\n"), None);
+        assert_eq!(image_extension(b""), None);
+    }
+
+    #[test]
+    fn test_file_image_extension() {
+        let temp_dir = TempDir::new().unwrap();
+        let renamed = temp_dir.path().join("notes.png");
+        std::fs::write(&renamed, "just text").unwrap();
+        let unnamed = temp_dir.path().join("screenshot");
+        std::fs::write(&unnamed, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+
+        assert_eq!(file_image_extension(&renamed), None);
+        assert_eq!(file_image_extension(&unnamed), Some("png"));
+        assert_eq!(file_image_extension(temp_dir.path()), None);
+        assert_eq!(file_image_extension(&temp_dir.path().join("missing.png")), None);
+    }
+}
@@ -1,8 +1,8 @@
-use crate::{config::Config, error::Result, Error, image_preview::ImagePreviewManager};
+use crate::{archive, command_runner, config::{Config, PreviewConfig, PreviewMethod}, output, error::Result, Error, image_preview::ImagePreviewManager, url_download::UrlDownloader};
 use regex::Regex;
 use std::io::{BufRead, BufReader, Write};
-use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
 use tokio::sync::mpsc;
 use tracing::{debug, info, warn};
 use std::collections::HashMap;
@@ -13,9 +13,12 @@ pub struct StdoutMonitor {
     preview_manager: ImagePreviewManager,
     image_path_regex: Regex,
     url_regex: Regex,
+    archive_regex: Regex,
     base64_regex: Regex,
     escape_sequence_regex: Regex,
     tui_apps: HashMap<String, TuiConfig>,
+    /// Fetches detected image URLs when `url_downloads` is enabled
+    downloader: Option<UrlDownloader>,
 }
 
 #[derive(Debug, Clone)]
@@ -69,6 +72,10 @@ impl StdoutMonitor {
             r#"https?://[^\s"']+\.(?:png|jpe?g|gif|bmp|webp|svg|tiff?|ico)(?:\?[^\s"']*)?(?:["']|\s|$)"#
         ).map_err(|e| Error::Config(format!("Failed to compile URL regex: {}", e)))?;
         
+        let archive_regex = Regex::new(
+            r#"(?:^|\s|["'])((?:[~/.]|[A-Za-z]:|\\\\)[^"'\s]*\.(?:zip|tar|tar\.gz|tgz|tar\.bz2|tbz2|tar\.xz|txz|tar\.zst))(?:["']|\s|$)"#
+        ).map_err(|e| Error::Config(format!("Failed to compile archive path regex: {}", e)))?;
+        
         let base64_regex = Regex::new(
             r"data:image/(?:png|jpe?g|gif|bmp|webp|svg\+xml);base64,([A-Za-z0-9+/=]+)"
         ).map_err(|e| Error::Config(format!("Failed to compile base64 regex: {}", e)))?;
@@ -178,11 +185,18 @@ impl StdoutMonitor {
             escape_sequences: vec![],
         });
         
+        let downloader = config
+            .url_downloads
+            .enabled
+            .then(|| UrlDownloader::new(config.url_downloads.clone(), command_runner::system()));
+        
         Ok(Self {
             config,
+            downloader,
             preview_manager,
             image_path_regex,
             url_regex,
+            archive_regex,
             base64_regex,
             escape_sequence_regex,
             tui_apps,
@@ -190,7 +204,7 @@ impl StdoutMonitor {
     }
     
     /// Monitor a command's output for image paths
-    pub async fn monitor_command(&self, command_args: Vec<String>) -> Result<()> {
+    pub async fn monitor_command(&self, command_args: Vec<String>) -> Result<ExitStatus> {
         if command_args.is_empty() {
             return Err(Error::InvalidInput("No command provided".to_string()));
         }
@@ -202,6 +216,10 @@ impl StdoutMonitor {
         if let Some(tui) = &tui_config {
             info!("Detected TUI application: {} (supports images: {})", tui.name, tui.supports_images);
         }
+        let auto_preview = self.config.preview.auto_preview_for(&command_args);
+        if !auto_preview {
+            info!("Auto-preview disabled for {}", command_args[0]);
+        }
         
         let mut cmd = Command::new(&command_args[0]);
         if command_args.len() > 1 {
@@ -216,42 +234,62 @@ impl StdoutMonitor {
         
         let (tx, mut rx) = mpsc::channel::<DetectedImage>(100);
         
+        // Image URLs are only downloaded to be previewed
+        let mut stream_monitor = self.clone();
+        if !auto_preview {
+            stream_monitor.downloader = None;
+        }
+        
+        let mut streams = Vec::new();
+        
         // Monitor stdout
         if let Some(stdout) = child.stdout.take() {
             let tx_stdout = tx.clone();
-            let monitor = self.clone();
+            let monitor = stream_monitor.clone();
             let tui_config_clone = tui_config.clone();
-            tokio::spawn(async move {
+            streams.push(tokio::spawn(async move {
                 if let Err(e) = monitor.monitor_tui_stream(stdout, tx_stdout, "stdout", tui_config_clone).await {
                     warn!("Error monitoring stdout: {}", e);
                 }
-            });
+            }));
         }
         
         // Monitor stderr
         if let Some(stderr) = child.stderr.take() {
             let tx_stderr = tx.clone();
-            let monitor = self.clone();
+            let monitor = stream_monitor.clone();
             let tui_config_clone = tui_config.clone();
-            tokio::spawn(async move {
+            streams.push(tokio::spawn(async move {
                 if let Err(e) = monitor.monitor_tui_stream(stderr, tx_stderr, "stderr", tui_config_clone).await {
                     warn!("Error monitoring stderr: {}", e);
                 }
-            });
+            }));
         }
+        drop(tx);
         
         // Handle detected images with TUI-aware preview
         let preview_manager = self.preview_manager.clone();
-        tokio::spawn(async move {
+        let preview_config = self.config.preview.clone();
+        let previews = tokio::spawn(async move {
             while let Some(detected_image) = rx.recv().await {
                 info!("Detected image: {:?}", detected_image);
+                if !auto_preview {
+                    continue;
+                }
                 
-                // Show appropriate preview based on TUI context
-                if let Some(tui) = &tui_config {
-                    Self::show_tui_aware_preview(&preview_manager, &detected_image, tui).await;
-                } else {
-                    // Standard preview for non-TUI commands
-                    let _ = preview_manager.show_preview(&detected_image.path, Some(40), Some(20)).await;
+                // Show appropriate preview based on TUI context, unless the
+                // config picks a method
+                match &tui_config {
+                    Some(tui) if preview_config.method == PreviewMethod::Auto => {
+                        Self::show_tui_aware_preview(&preview_manager, &preview_config, &detected_image, tui).await;
+                    }
+                    _ => {
+                        let _ = preview_manager.show_automatic_preview(&detected_image.path).await;
+                    }
+                }
+                
+                if matches!(detected_image.source, ImageSource::Url) {
+                    let _ = tokio::fs::remove_file(&detected_image.path).await;
                 }
             }
         });
@@ -264,7 +302,13 @@ impl StdoutMonitor {
             warn!("Command exited with non-zero status: {}", status);
         }
         
-        Ok(())
+        // Echo the command's last lines and show their images before returning
+        for stream in streams {
+            let _ = stream.await;
+        }
+        let _ = previews.await;
+        
+        Ok(status)
     }
     
     /// Detect if a command is a known TUI application
@@ -280,6 +324,7 @@ impl StdoutMonitor {
     /// Show preview appropriate for TUI context
     async fn show_tui_aware_preview(
         preview_manager: &ImagePreviewManager,
+        preview_config: &PreviewConfig,
         detected_image: &DetectedImage,
         tui_config: &TuiConfig,
     ) {
@@ -287,26 +332,28 @@ impl StdoutMonitor {
             TuiPreviewMethod::Inline => {
                 // Try to show inline preview if TUI supports it
                 if tui_config.supports_images {
-                    let _ = preview_manager.show_preview(&detected_image.path, Some(60), Some(30)).await;
+                    let (width, height) = preview_config.size();
+                    let _ = preview_manager.show_preview(&detected_image.path, width, height).await;
                 } else {
                     // Just show compact info
                     if let Ok(info) = preview_manager.show_compact_preview(&detected_image.path).await {
-                        println!("📷 {}", info);
+                        output::status("", info);
                     }
                 }
             }
             TuiPreviewMethod::SeparatePane => {
                 // For apps like ranger/lf, show in a way that doesn't interfere
-                println!("🖼️  Image detected: {}", detected_image.path.display());
+                output::status("🖼️ ", format!("Image detected: {}", detected_image.path.display()));
                 // Could integrate with tmux/screen to show in separate pane
             }
             TuiPreviewMethod::Overlay => {
-                // For apps like nvim, show floating overlay
-                let _ = preview_manager.show_preview(&detected_image.path, Some(80), Some(40)).await;
+                // For apps like nvim, show a kitty overlay window so their screen isn't drawn over
+                let (width, height) = preview_config.max_size();
+                let _ = preview_manager.show_overlay_preview(&detected_image.path, width, height).await;
             }
             TuiPreviewMethod::External => {
                 // Open in external viewer
-                println!("🖼️  Image detected: {} (use external viewer)", detected_image.path.display());
+                output::status("🖼️ ", format!("Image detected: {} (use external viewer)", detected_image.path.display()));
                 // Could launch external image viewer here
             }
             TuiPreviewMethod::None => {
@@ -327,6 +374,7 @@ impl StdoutMonitor {
         let reader = BufReader::new(stream);
         let mut line_number = 0;
         let mut buffer = String::new();
+        let mut listed_archives = std::collections::HashSet::new();
         
         for line in reader.lines() {
             line_number += 1;
@@ -365,11 +413,80 @@ impl StdoutMonitor {
                     break;
                 }
             }
+            
+            for archive_path in self.detect_archives_in_line(&line) {
+                if listed_archives.insert(archive_path.clone()) {
+                    tokio::spawn(Self::list_archive_images(archive_path));
+                }
+            }
+            
+            // Downloads run alongside so a slow server doesn't hold up the output
+            for url in self.detect_urls_in_line(&line) {
+                let monitor = self.clone();
+                let tx = tx.clone();
+                let context = line.clone();
+                tokio::spawn(async move {
+                    if let Some(image) = monitor.download_url(&url, context, line_number).await {
+                        let _ = tx.send(image).await;
+                    }
+                });
+            }
         }
         
         Ok(())
     }
     
+    /// Existing .zip and .tar archives named in a line
+    pub fn detect_archives_in_line(&self, line: &str) -> Vec<PathBuf> {
+        self.archive_regex
+            .captures_iter(line)
+            .filter_map(|cap| cap.get(1))
+            .map(|path| PathBuf::from(self.expand_path(path.as_str())))
+            .filter(|path| path.is_file())
+            .collect()
+    }
+    
+    /// Point out the images in an archive, such as a CI artifact bundle, as previewable members
+    async fn list_archive_images(archive_path: PathBuf) {
+        let runner = command_runner::system();
+        match archive::list_images(runner.as_ref(), &archive_path).await {
+            Ok(images) if !images.is_empty() => {
+                output::status("📦", format!("{} contains {} image(s):", archive_path.display(), images.len()));
+                for image in images {
+                    output::status("", format!("   klipdot preview '{}{}{}'", archive_path.display(), archive::MEMBER_SEPARATOR, image));
+                }
+            }
+            Ok(_) => debug!("No images in {:?}", archive_path),
+            Err(e) => debug!("Failed to list {:?}: {}", archive_path, e),
+        }
+    }
+    
+    /// Image URLs in a line
+    pub fn detect_urls_in_line(&self, line: &str) -> Vec<String> {
+        self.url_regex
+            .find_iter(line)
+            .map(|url| url.as_str().trim_end_matches(['"', '\'', ' ', '\n', '\r']).to_string())
+            .collect()
+    }
+    
+    /// Download an image URL within the `url_downloads` limits; `None` when
+    /// downloads are disabled or this one isn't allowed or fails
+    pub async fn download_url(&self, url: &str, context: String, line_number: usize) -> Option<DetectedImage> {
+        let downloader = self.downloader.as_ref()?;
+        match downloader.download(url).await {
+            Ok(path) => Some(DetectedImage {
+                path,
+                source: ImageSource::Url,
+                context,
+                line_number,
+            }),
+            Err(e) => {
+                debug!("Not downloading {}: {}", url, e);
+                None
+            }
+        }
+    }
+    
     /// Process a line for TUI-specific handling
     fn process_tui_line(&self, line: &str, tui_config: &TuiConfig) -> String {
         // Remove or preserve escape sequences based on TUI needs
@@ -393,7 +510,7 @@ impl StdoutMonitor {
     fn detect_images_in_tui_context(
         &self,
         line: &str,
-        buffer: &str,
+        _buffer: &str,
         line_number: usize,
         tui_config: &Option<TuiConfig>,
     ) -> Vec<DetectedImage> {
@@ -437,7 +554,7 @@ impl StdoutMonitor {
                 let path_str = path_match.as_str();
                 let path = PathBuf::from(self.expand_path(path_str));
                 
-                if path.exists() && self.is_image_file(&path) {
+                if crate::is_image_file(&path) {
                     detected.push(DetectedImage {
                         path,
                         source: ImageSource::FilePath,
@@ -459,12 +576,12 @@ impl StdoutMonitor {
     
     /// Specialized detection for browsers
     fn detect_browser_images(&self, line: &str, line_number: usize) -> Vec<DetectedImage> {
-        let mut detected = self.detect_images_in_line(line, line_number);
+        let detected = self.detect_images_in_line(line, line_number);
         
         // Also check for URLs that might be images
         for cap in self.url_regex.captures_iter(line) {
             if let Some(url_match) = cap.get(0) {
-                let url = url_match.as_str().trim_end_matches(&['"', '\'', ' ', '\n', '\r']);
+                let url = url_match.as_str().trim_end_matches(['"', '\'', ' ', '\n', '\r']);
                 debug!("Detected image URL in browser: {}", url);
                 // Could download and preview URL images here
             }
@@ -514,7 +631,7 @@ impl StdoutMonitor {
                 let path_str = path_match.as_str();
                 let path = PathBuf::from(self.expand_path(path_str));
                 
-                if path.exists() && self.is_image_file(&path) {
+                if crate::is_image_file(&path) {
                     detected.push(DetectedImage {
                         path,
                         source: ImageSource::FilePath,
@@ -528,7 +645,7 @@ impl StdoutMonitor {
         // Detect URLs
         for cap in self.url_regex.captures_iter(line) {
             if let Some(url_match) = cap.get(0) {
-                let url = url_match.as_str().trim_end_matches(&['"', '\'', ' ', '\n', '\r']);
+                let url = url_match.as_str().trim_end_matches(['"', '\'', ' ', '\n', '\r']);
                 // For URLs, we could download and create a temp file
                 // For now, just log the detection
                 debug!("Detected image URL: {}", url);
@@ -556,18 +673,6 @@ impl StdoutMonitor {
         path.to_string()
     }
     
-    fn is_image_file(&self, path: &Path) -> bool {
-        if let Some(ext) = path.extension() {
-            if let Some(ext_str) = ext.to_str() {
-                let ext_lower = ext_str.to_lowercase();
-                return matches!(ext_lower.as_str(), 
-                    "png" | "jpg" | "jpeg" | "gif" | "bmp" | "webp" | "svg" | "tiff" | "tif" | "ico"
-                );
-            }
-        }
-        false
-    }
-    
     /// Create a wrapper command that monitors the original command's output
     pub fn create_monitoring_wrapper(&self, original_command: &str) -> String {
         format!(
@@ -586,128 +691,15 @@ impl Clone for StdoutMonitor {
             preview_manager: self.preview_manager.clone(),
             image_path_regex: self.image_path_regex.clone(),
             url_regex: self.url_regex.clone(),
+            archive_regex: self.archive_regex.clone(),
             base64_regex: self.base64_regex.clone(),
             escape_sequence_regex: self.escape_sequence_regex.clone(),
             tui_apps: self.tui_apps.clone(),
+            downloader: self.downloader.clone(),
         }
     }
 }
 
-/// LSP-style live preview system for real-time image detection
-pub struct LivePreviewSystem {
-    config: Config,
-    preview_manager: ImagePreviewManager,
-    current_preview: Option<PathBuf>,
-}
-
-impl LivePreviewSystem {
-    pub async fn new(config: Config) -> Result<Self> {
-        let preview_manager = ImagePreviewManager::new(config.clone()).await?;
-        
-        Ok(Self {
-            config,
-            preview_manager,
-            current_preview: None,
-        })
-    }
-    
-    /// Show live preview as user types (like LSP hover)
-    pub async fn show_live_preview(&mut self, text: &str, cursor_position: usize) -> Result<bool> {
-        let detected_path = self.extract_image_path_at_cursor(text, cursor_position);
-        
-        match detected_path {
-            Some(path) if Some(&path) != self.current_preview.as_ref() => {
-                // New image detected, show preview
-                self.show_floating_preview(&path).await?;
-                self.current_preview = Some(path);
-                Ok(true)
-            }
-            None if self.current_preview.is_some() => {
-                // No image at cursor, hide preview
-                self.hide_floating_preview().await?;
-                self.current_preview = None;
-                Ok(true)
-            }
-            _ => Ok(false), // No change needed
-        }
-    }
-    
-    fn extract_image_path_at_cursor(&self, text: &str, cursor_position: usize) -> Option<PathBuf> {
-        // Find word boundaries around cursor
-        let before_cursor = &text[..cursor_position.min(text.len())];
-        let after_cursor = &text[cursor_position.min(text.len())..];
-        
-        // Find start of current word
-        let word_start = before_cursor.rfind(|c: char| c.is_whitespace() || c == '"' || c == '\'')
-            .map(|i| i + 1)
-            .unwrap_or(0);
-        
-        // Find end of current word
-        let word_end = after_cursor.find(|c: char| c.is_whitespace() || c == '"' || c == '\'')
-            .map(|i| cursor_position + i)
-            .unwrap_or(text.len());
-        
-        if word_start < word_end {
-            let word = &text[word_start..word_end];
-            let path = PathBuf::from(self.expand_path(word));
-            
-            if path.exists() && self.is_image_file(&path) {
-                return Some(path);
-            }
-        }
-        
-        None
-    }
-    
-    async fn show_floating_preview(&self, path: &Path) -> Result<()> {
-        // In a real implementation, this would show a floating window or modal
-        // For now, we'll show a compact preview with escape sequences for positioning
-        
-        print!("\x1b[s"); // Save cursor position
-        print!("\x1b[H"); // Move to top-left
-        print!("\x1b[2K"); // Clear line
-        print!("🖼️  Live Preview: {}", path.file_name().unwrap_or_default().to_string_lossy());
-        
-        // Show small preview
-        self.preview_manager.show_preview(path, Some(40), Some(10)).await?;
-        
-        print!("\x1b[u"); // Restore cursor position
-        
-        Ok(())
-    }
-    
-    async fn hide_floating_preview(&self) -> Result<()> {
-        // Clear the preview area
-        print!("\x1b[s"); // Save cursor position
-        print!("\x1b[H"); // Move to top-left
-        print!("\x1b[K"); // Clear line
-        print!("\x1b[u"); // Restore cursor position
-        
-        Ok(())
-    }
-    
-    fn expand_path(&self, path: &str) -> String {
-        if path.starts_with('~') {
-            if let Some(home) = dirs::home_dir() {
-                return path.replacen('~', &home.to_string_lossy(), 1);
-            }
-        }
-        path.to_string()
-    }
-    
-    fn is_image_file(&self, path: &Path) -> bool {
-        if let Some(ext) = path.extension() {
-            if let Some(ext_str) = ext.to_str() {
-                let ext_lower = ext_str.to_lowercase();
-                return matches!(ext_lower.as_str(), 
-                    "png" | "jpg" | "jpeg" | "gif" | "bmp" | "webp" | "svg" | "tiff" | "tif" | "ico"
-                );
-            }
-        }
-        false
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -722,7 +714,10 @@ mod tests {
         // Create a temporary image file for testing
         let temp_dir = tempdir().unwrap();
         let image_path = temp_dir.path().join("test.png");
-        fs::write(&image_path, b"fake image data").unwrap();
+        fs::write(&image_path, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+        let renamed = temp_dir.path().join("notes.png");
+        fs::write(&renamed, b"fake image data").unwrap();
+        assert!(monitor.detect_images_in_line(&renamed.display().to_string(), 1).is_empty());
         
         let line = format!("Found image at: {}", image_path.display());
         let detected = monitor.detect_images_in_line(&line, 1);
@@ -733,19 +728,14 @@ mod tests {
     }
     
     #[tokio::test]
-    async fn test_live_preview_path_extraction() {
-        let config = Config::default();
-        let mut system = LivePreviewSystem::new(config).await.unwrap();
+    async fn test_detect_archives_in_line() {
+        let monitor = StdoutMonitor::new(Config::default()).await.unwrap();
         
-        // Create a temporary image file
         let temp_dir = tempdir().unwrap();
-        let image_path = temp_dir.path().join("test.png");
-        fs::write(&image_path, b"fake image data").unwrap();
+        let archive = temp_dir.path().join("artifacts.tar.gz");
+        fs::write(&archive, b"fake archive").unwrap();
         
-        let text = format!("vim {}", image_path.display());
-        let cursor_pos = text.len() - 4; // Position in the middle of the filename
-        
-        let detected = system.extract_image_path_at_cursor(&text, cursor_pos);
-        assert_eq!(detected, Some(image_path));
+        let line = format!("Uploaded {} and {}/missing.zip", archive.display(), temp_dir.path().display());
+        assert_eq!(monitor.detect_archives_in_line(&line), [archive]);
     }
-}
\ No newline at end of file
+}
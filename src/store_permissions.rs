@@ -0,0 +1,88 @@
+//! Permissions of the screenshot store.
+//!
+//! Intercepted screenshots often show passwords, tokens and private messages,
+//! so the store doesn't inherit the process's umask: directories get
+//! `store_permissions.dir_mode` (0700 by default) and stored images
+//! `store_permissions.file_mode` (0600), set explicitly so a looser umask
+//! can't widen them and a stricter one can't narrow a mode chosen for sharing.
+//! Images are created with the mode rather than changed after writing, so
+//! they're never readable by others in between. Elsewhere than Unix the
+//! modes are ignored.
+
+use crate::config::{FileMode, StorePermissions};
+use std::path::Path;
+
+/// Create `dir` and its missing parents, giving `dir` the store's directory mode
+pub async fn create_dir(dir: &Path, permissions: &StorePermissions) -> std::io::Result<()> {
+    tokio::fs::create_dir_all(dir).await?;
+    set_mode(dir, permissions.dir_mode).await
+}
+
+/// [`create_dir`] for callers that aren't async
+pub fn create_dir_blocking(dir: &Path, permissions: &StorePermissions) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(permissions.dir_mode.0))?;
+    }
+    Ok(())
+}
+
+/// Write `data` to a stored image at `path`, creating its directory
+pub async fn write(path: &Path, data: &[u8], permissions: &StorePermissions) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        create_dir(parent, permissions).await?;
+    }
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(permissions.file_mode.0);
+    let mut file = options.open(path).await?;
+    tokio::io::AsyncWriteExt::write_all(&mut file, data).await?;
+    // tokio finishes file writes in the background; flush so the image is on disk on return
+    tokio::io::AsyncWriteExt::flush(&mut file).await?;
+    // The umask applied when the file was created; an existing file kept its mode
+    set_mode(path, permissions.file_mode).await
+}
+
+#[cfg(unix)]
+async fn set_mode(path: &Path, mode: FileMode) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode.0)).await
+}
+
+#[cfg(not(unix))]
+async fn set_mode(_path: &Path, _mode: FileMode) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    fn mode(path: &Path) -> u32 {
+        std::fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[tokio::test]
+    async fn test_store_modes() {
+        let temp_dir = TempDir::new().unwrap();
+        let image = temp_dir.path().join("screenshots").join("clipboard").join("shot.png");
+
+        write(&image, b"png", &StorePermissions::default()).await.unwrap();
+        assert_eq!(std::fs::read(&image).unwrap(), b"png");
+        assert_eq!(mode(&image), 0o600);
+        assert_eq!(mode(image.parent().unwrap()), 0o700);
+
+        // Configured modes apply whatever the umask, and to existing files too
+        let shared = StorePermissions { dir_mode: FileMode(0o755), file_mode: FileMode(0o644) };
+        write(&image, b"png again", &shared).await.unwrap();
+        assert_eq!(std::fs::read(&image).unwrap(), b"png again");
+        assert_eq!(mode(&image), 0o644);
+        assert_eq!(mode(image.parent().unwrap()), 0o755);
+    }
+}
@@ -0,0 +1,239 @@
+//! The `klipdot stats` dashboard: captures per day, storage growth, top
+//! sources and the biggest files in the store, with what the retention
+//! setting (`cleanup_days`) would remove.
+//!
+//! Everything comes from [`Screenshot`]s as listed from the metadata indexes,
+//! so sources are the recorded ones and images aren't opened. Days are local
+//! calendar days of each image's creation time.
+
+use crate::config::Screenshot;
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Widest bar drawn, in characters
+const BAR_WIDTH: usize = 30;
+
+/// Images and bytes stored on one day
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Day {
+    pub date: NaiveDate,
+    pub count: usize,
+    pub bytes: u64,
+    /// Bytes stored up to and including this day
+    pub total_bytes: u64,
+}
+
+/// Images and bytes from one source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceTotal {
+    pub source: String,
+    pub count: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct StoreStats {
+    pub count: usize,
+    pub bytes: u64,
+    /// The last days, oldest first, including days without captures
+    pub days: Vec<Day>,
+    /// Most images first
+    pub sources: Vec<SourceTotal>,
+    /// Largest first
+    pub biggest: Vec<Screenshot>,
+    /// Retention in days, and the images and bytes older than it
+    pub retention: (u32, usize, u64),
+}
+
+impl StoreStats {
+    /// Statistics over `screenshots` as of `now`, with `days` days of history,
+    /// `top` sources and biggest files, and `cleanup_days` of retention
+    pub fn compute(screenshots: &[Screenshot], now: DateTime<Utc>, days: u32, top: usize, cleanup_days: u32) -> Self {
+        let today = now.with_timezone(&Local).date_naive();
+        let first = today - Duration::days(days.saturating_sub(1) as i64);
+        let day_of = |screenshot: &Screenshot| screenshot.created_at.with_timezone(&Local).date_naive();
+
+        let mut per_day: HashMap<NaiveDate, (usize, u64)> = HashMap::new();
+        let mut earlier = 0;
+        let mut sources: HashMap<&str, (usize, u64)> = HashMap::new();
+        let cutoff = now - Duration::days(cleanup_days as i64);
+        let (mut expiring, mut expiring_bytes) = (0, 0);
+        for screenshot in screenshots {
+            let day = day_of(screenshot);
+            if day < first {
+                earlier += screenshot.size;
+            } else {
+                let entry = per_day.entry(day).or_default();
+                entry.0 += 1;
+                entry.1 += screenshot.size;
+            }
+            let source = sources.entry(&screenshot.source).or_default();
+            source.0 += 1;
+            source.1 += screenshot.size;
+            if screenshot.created_at < cutoff {
+                expiring += 1;
+                expiring_bytes += screenshot.size;
+            }
+        }
+
+        let mut total_bytes = earlier;
+        let days = first
+            .iter_days()
+            .take_while(|date| *date <= today)
+            .map(|date| {
+                let (count, bytes) = per_day.get(&date).copied().unwrap_or_default();
+                total_bytes += bytes;
+                Day { date, count, bytes, total_bytes }
+            })
+            .collect();
+
+        let mut sources: Vec<SourceTotal> = sources
+            .into_iter()
+            .map(|(source, (count, bytes))| SourceTotal { source: source.to_string(), count, bytes })
+            .collect();
+        sources.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| b.bytes.cmp(&a.bytes)).then_with(|| a.source.cmp(&b.source)));
+        sources.truncate(top);
+
+        let mut biggest = screenshots.to_vec();
+        biggest.sort_by_key(|screenshot| std::cmp::Reverse(screenshot.size));
+        biggest.truncate(top);
+
+        Self {
+            count: screenshots.len(),
+            bytes: screenshots.iter().map(|screenshot| screenshot.size).sum(),
+            days,
+            sources,
+            biggest,
+            retention: (cleanup_days, expiring, expiring_bytes),
+        }
+    }
+
+    /// The dashboard as text
+    pub fn render(&self) -> String {
+        let size = crate::format_file_size;
+        let mut text = String::new();
+        let _ = writeln!(text, "=== KlipDot Store ===");
+        let _ = writeln!(text, "{}, {}", images(self.count), size(self.bytes));
+
+        let _ = writeln!(text, "\nCaptures per day");
+        let most = self.days.iter().map(|day| day.count).max().unwrap_or(0);
+        for day in &self.days {
+            let _ = writeln!(
+                text,
+                "  {}  {:<width$} {:>4}  {:>10}  total {}",
+                day.date.format("%Y-%m-%d %a"),
+                bar(day.count as u64, most as u64),
+                day.count,
+                if day.bytes > 0 { format!("+{}", size(day.bytes)) } else { String::new() },
+                size(day.total_bytes),
+                width = BAR_WIDTH
+            );
+        }
+        if let (Some(first), Some(last)) = (self.days.first(), self.days.last()) {
+            let grown = last.total_bytes - (first.total_bytes - first.bytes);
+            let _ = writeln!(text, "  Grew by {} over {} days", size(grown), self.days.len());
+        }
+
+        let _ = writeln!(text, "\nTop sources");
+        let most = self.sources.first().map_or(0, |source| source.count);
+        let name_width = self.sources.iter().map(|source| source.source.chars().count()).max().unwrap_or(0);
+        for source in &self.sources {
+            let _ = writeln!(
+                text,
+                "  {:<name_width$}  {:<width$} {:>5}  {}",
+                source.source,
+                bar(source.count as u64, most as u64),
+                source.count,
+                size(source.bytes),
+                width = BAR_WIDTH
+            );
+        }
+
+        let _ = writeln!(text, "\nBiggest files");
+        for screenshot in &self.biggest {
+            let _ = writeln!(text, "  {:>10}  {}", size(screenshot.size), screenshot.path.display());
+        }
+
+        let (days, count, bytes) = self.retention;
+        let _ = writeln!(text, "\nRetention: {} days (cleanup_days)", days);
+        if count == 0 {
+            let _ = writeln!(text, "  Nothing is older; a cleanup would remove nothing");
+        } else {
+            let _ = writeln!(text, "  {} ({}) older than that would be removed by the next cleanup", images(count), size(bytes));
+        }
+        text
+    }
+}
+
+fn images(count: usize) -> String {
+    format!("{} image{}", count, if count == 1 { "" } else { "s" })
+}
+
+/// A bar `value / max` of `BAR_WIDTH` long, at least one block for any value
+fn bar(value: u64, max: u64) -> String {
+    if value == 0 || max == 0 {
+        return String::new();
+    }
+    let length = ((value as f64 / max as f64) * BAR_WIDTH as f64).round().max(1.0) as usize;
+    "█".repeat(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn screenshot(name: &str, source: &str, size: u64, created_at: DateTime<Utc>) -> Screenshot {
+        Screenshot {
+            filename: name.to_string(),
+            path: PathBuf::from("/shots").join(name),
+            size,
+            source: source.to_string(),
+            app: None,
+            output: None,
+            resized_from: None,
+            alt_text: None,
+            tags: Vec::new(),
+            window: None,
+            created_at,
+            mime_type: "image/png".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_compute() {
+        let now = Utc::now();
+        let screenshots = [
+            screenshot("a.png", "clipboard", 100, now),
+            screenshot("b.png", "clipboard", 300, now - Duration::days(1)),
+            screenshot("c.png", "wayland-screenshot", 5000, now - Duration::days(1)),
+            screenshot("d.png", "clipboard", 2000, now - Duration::days(40)),
+        ];
+        let stats = StoreStats::compute(&screenshots, now, 3, 2, 30);
+
+        assert_eq!((stats.count, stats.bytes), (4, 7400));
+        assert_eq!(stats.days.len(), 3);
+        assert_eq!((stats.days[0].count, stats.days[0].total_bytes), (0, 2000));
+        assert_eq!((stats.days[1].count, stats.days[1].bytes), (2, 5300));
+        assert_eq!((stats.days[2].count, stats.days[2].total_bytes), (1, 7400));
+        assert_eq!(stats.days[2].date, now.with_timezone(&Local).date_naive());
+
+        assert_eq!(stats.sources[0], SourceTotal { source: "clipboard".to_string(), count: 3, bytes: 2400 });
+        assert_eq!(stats.sources.len(), 2);
+        let biggest: Vec<_> = stats.biggest.iter().map(|s| s.filename.as_str()).collect();
+        assert_eq!(biggest, ["c.png", "d.png"]);
+        assert_eq!(stats.retention, (30, 1, 2000));
+
+        let text = stats.render();
+        assert!(text.contains("Grew by 5.3 KB over 3 days"), "{}", text);
+        assert!(text.contains("1 image (2.0 KB) older than that would be removed"), "{}", text);
+    }
+
+    #[test]
+    fn test_bar() {
+        assert_eq!(bar(0, 10), "");
+        assert_eq!(bar(10, 10).chars().count(), BAR_WIDTH);
+        assert_eq!(bar(1, 1000).chars().count(), 1);
+    }
+}
@@ -0,0 +1,353 @@
+//! Command-line substitution for the shell hooks.
+//!
+//! Before a command runs, the hooks pass the line to `klipdot substitute`,
+//! which replaces pasted image payloads (data URLs and bare base64 blobs) with
+//! the path of a processed copy in the screenshot directory. Everything else
+//! in the line is left byte-for-byte intact.
+
+use crate::{
+    config::{Config, PathFormatConfig, PathQuoting},
+    error::Result,
+    image_processor::ImageProcessor,
+    path_format,
+};
+use base64::engine::general_purpose;
+use base64::Engine;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// Shortest bare base64 token considered as a possible image payload
+const MIN_BASE64_LEN: usize = 100;
+
+/// A shell word: its byte range in the command line and its unquoted value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Word {
+    pub span: Range<usize>,
+    pub value: String,
+}
+
+/// Result of rewriting a command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rewrite {
+    pub command_line: String,
+    /// Paths substituted into the command line, in order
+    pub paths: Vec<PathBuf>,
+}
+
+pub struct SubstitutionEngine {
+    image_processor: ImageProcessor,
+    path_quoting: PathQuoting,
+    path_format: PathFormatConfig,
+}
+
+impl SubstitutionEngine {
+    pub async fn new(config: Config) -> Result<Self> {
+        Ok(Self {
+            path_quoting: config.path_quoting,
+            path_format: config.path_format.clone(),
+            image_processor: ImageProcessor::new(config).await?,
+        })
+    }
+
+    /// Rewrite image payloads in a full command line, writing paths in the
+    /// format of the command's profile
+    pub async fn rewrite_command_line(&self, line: &str) -> Result<Rewrite> {
+        let mut command_line = String::with_capacity(line.len());
+        let mut paths = Vec::new();
+        let mut last = 0;
+
+        let words = split_words(line);
+        let command = words.first().map(|word| word.value.rsplit('/').next().unwrap_or_default());
+        let format = self.path_format.for_name(command);
+        let cwd = std::env::current_dir().ok();
+
+        for word in &words {
+            if let Some(path) = self.substitute(&word.value).await? {
+                command_line.push_str(&line[last..word.span.start]);
+                command_line.push_str(&path_format::render(&path, format, self.path_quoting, cwd.as_deref()));
+                last = word.span.end;
+                paths.push(path);
+            }
+        }
+
+        command_line.push_str(&line[last..]);
+        Ok(Rewrite { command_line, paths })
+    }
+
+    /// Rewrite image payloads in an already split argument list
+    pub async fn rewrite_args(&self, args: &[String]) -> Result<Vec<String>> {
+        let mut rewritten = Vec::with_capacity(args.len());
+        for arg in args {
+            match self.substitute(arg).await? {
+                Some(path) => rewritten.push(path.to_string_lossy().into_owned()),
+                None => rewritten.push(arg.clone()),
+            }
+        }
+        Ok(rewritten)
+    }
+
+    async fn substitute(&self, value: &str) -> Result<Option<PathBuf>> {
+        let Some(data) = decode_image_payload(value) else {
+            return Ok(None);
+        };
+
+        debug!("Substituting {} byte image payload", data.len());
+        let path = self.image_processor.process_image_data(&data, "substitute").await?;
+        info!("Substituted image payload with {:?}", path);
+        Ok(Some(path))
+    }
+}
+
+/// Decode `value` if it is a data URL or base64 blob holding an image
+pub fn decode_image_payload(value: &str) -> Option<Vec<u8>> {
+    let data = if let Some(rest) = value.strip_prefix("data:image/") {
+        let (meta, payload) = rest.split_once(',')?;
+        if !meta.ends_with(";base64") {
+            return None;
+        }
+        general_purpose::STANDARD.decode(payload).ok()?
+    } else if value.len() >= MIN_BASE64_LEN
+        && value.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'='))
+    {
+        general_purpose::STANDARD.decode(value).ok()?
+    } else {
+        return None;
+    };
+
+    image::guess_format(&data).is_ok().then_some(data)
+}
+
+/// Split a command line into shell words, honouring quotes and backslash escapes.
+///
+/// Only whitespace separates words, so an unquoted data URL (which contains
+/// `;`) stays a single word and can still be rewritten.
+pub fn split_words(line: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if quote.is_none() && c.is_whitespace() {
+            if let Some((start, value)) = current.take() {
+                words.push(Word { span: start..i, value });
+            }
+            continue;
+        }
+
+        let (_, value) = current.get_or_insert_with(|| (i, String::new()));
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '\\') | (Some('"'), '\\') => {
+                if let Some((_, next)) = chars.next() {
+                    value.push(next);
+                }
+            }
+            _ => value.push(c),
+        }
+    }
+
+    if let Some((start, value)) = current {
+        words.push(Word { span: start..line.len(), value });
+    }
+
+    words
+}
+
+/// The word containing byte offset `cursor`, or ending right before it
+pub fn word_at(line: &str, cursor: usize) -> Option<Word> {
+    split_words(line)
+        .into_iter()
+        .find(|word| word.span.start <= cursor && cursor <= word.span.end)
+}
+
+/// Quote `value` for a POSIX shell, leaving plain paths untouched
+pub fn shell_quote(value: &str) -> String {
+    let is_plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '.' | '_' | '-' | '+' | ':' | '@' | '%' | ',' | '='));
+
+    if is_plain {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+}
+
+/// `path` written the way `quoting` asks for
+pub fn quote_path(path: &Path, quoting: PathQuoting) -> String {
+    let value = path.to_string_lossy();
+    match quoting {
+        PathQuoting::Single => shell_quote(&value),
+        PathQuoting::Backslash => backslash_escape(&value),
+        PathQuoting::Uri => file_uri(path),
+        PathQuoting::Raw => value.into_owned(),
+    }
+}
+
+/// Escape `value` for a POSIX shell with backslashes instead of quotes
+pub fn backslash_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '\n' {
+            // A backslash-newline is a line continuation, so newlines need quotes
+            escaped.push_str("$'\\n'");
+            continue;
+        }
+        if !(c.is_alphanumeric() || matches!(c, '/' | '.' | '_' | '-' | '+' | ':' | '@' | '%' | ',' | '=')) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// `path` as a `file://` URI, percent-encoding all but unreserved characters
+pub fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'/' | b'-' | b'.' | b'_' | b'~') {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
+
+/// The local path a `file://` URI names: `file:///path` or
+/// `file://localhost/path`, percent-decoded. URIs for other hosts or schemes
+/// give `None`.
+pub fn file_uri_path(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    let encoded = rest.strip_prefix("localhost").unwrap_or(rest);
+    if !encoded.starts_with('/') {
+        return None;
+    }
+    
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut input = encoded.bytes();
+    while let Some(byte) = input.next() {
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let hex = [input.next()?, input.next()?];
+        bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+    }
+    #[cfg(unix)]
+    return Some(PathBuf::from(<std::ffi::OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(&bytes)));
+    #[cfg(not(unix))]
+    return String::from_utf8(bytes).ok().map(PathBuf::from);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn png_bytes() -> Vec<u8> {
+        let img = image::RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 255]));
+        let mut data = Vec::new();
+        image::DynamicImage::ImageRgba8(img)
+            .write_to(&mut std::io::Cursor::new(&mut data), image::ImageOutputFormat::Png)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn test_split_words() {
+        let words = split_words(r#"cp 'a b.png' "c\"d" e\ f  g"#);
+        let values: Vec<&str> = words.iter().map(|w| w.value.as_str()).collect();
+        assert_eq!(values, ["cp", "a b.png", "c\"d", "e f", "g"]);
+        assert_eq!(words[1].span, 3..12);
+    }
+
+    #[test]
+    fn test_word_at() {
+        let line = "feh 'my shot.png' -x";
+        assert_eq!(word_at(line, 0).unwrap().value, "feh");
+        assert_eq!(word_at(line, 8).unwrap().value, "my shot.png");
+        assert_eq!(word_at(line, 17).unwrap().value, "my shot.png");
+        assert!(word_at("feh  x", 4).is_none());
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("/tmp/shot.png"), "/tmp/shot.png");
+        assert_eq!(shell_quote("/tmp/my shot.png"), "'/tmp/my shot.png'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn test_quote_path() {
+        let path = Path::new("/home/me/My Shots/it's #1.png");
+        assert_eq!(quote_path(path, PathQuoting::Single), r"'/home/me/My Shots/it'\''s #1.png'");
+        assert_eq!(quote_path(path, PathQuoting::Backslash), r"/home/me/My\ Shots/it\'s\ \#1.png");
+        assert_eq!(quote_path(path, PathQuoting::Uri), "file:///home/me/My%20Shots/it%27s%20%231.png");
+        assert_eq!(quote_path(path, PathQuoting::Raw), "/home/me/My Shots/it's #1.png");
+
+        // Plain paths come out the same, but as a URI
+        let plain = Path::new("/tmp/klipdot_1.png");
+        assert_eq!(quote_path(plain, PathQuoting::Single), "/tmp/klipdot_1.png");
+        assert_eq!(quote_path(plain, PathQuoting::Backslash), "/tmp/klipdot_1.png");
+        assert_eq!(backslash_escape("a\nb"), "a$'\\n'b");
+        assert_eq!(file_uri(Path::new("/tmp/é.png")), "file:///tmp/%C3%A9.png");
+    }
+    
+    #[test]
+    fn test_file_uri_path() {
+        let path = Path::new("/home/me/My Shots/it's #1é.png");
+        assert_eq!(file_uri_path(&file_uri(path)).as_deref(), Some(path));
+        assert_eq!(file_uri_path("file://localhost/tmp/a.png").as_deref(), Some(Path::new("/tmp/a.png")));
+        assert_eq!(file_uri_path("file://server/share/a.png"), None);
+        assert_eq!(file_uri_path("https://example.com/a.png"), None);
+        assert_eq!(file_uri_path("file:///tmp/bad%2"), None);
+    }
+
+    #[test]
+    fn test_decode_image_payload() {
+        let encoded = general_purpose::STANDARD.encode(png_bytes());
+
+        assert!(decode_image_payload(&format!("data:image/png;base64,{}", encoded)).is_some());
+        assert!(decode_image_payload(&encoded).is_some());
+
+        let not_image = general_purpose::STANDARD.encode([0u8; 120]);
+        assert!(decode_image_payload(&not_image).is_none());
+        assert!(decode_image_payload("data:image/svg+xml,<svg/>").is_none());
+        assert!(decode_image_payload("hello").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_command_line() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            screenshot_dir: temp_dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        let engine = SubstitutionEngine::new(config).await.unwrap();
+
+        let encoded = general_purpose::STANDARD.encode(png_bytes());
+        let line = format!("llm --attach 'data:image/png;base64,{}' -m  \"describe it\"", encoded);
+        let rewrite = engine.rewrite_command_line(&line).await.unwrap();
+
+        assert_eq!(rewrite.paths.len(), 1);
+        assert!(rewrite.paths[0].starts_with(temp_dir.path()));
+        assert!(rewrite.paths[0].exists());
+        assert_eq!(
+            rewrite.command_line,
+            format!("llm --attach {} -m  \"describe it\"", shell_quote(&rewrite.paths[0].to_string_lossy()))
+        );
+
+        let untouched = engine.rewrite_command_line("ls -la ~/Pictures").await.unwrap();
+        assert_eq!(untouched.command_line, "ls -la ~/Pictures");
+        assert!(untouched.paths.is_empty());
+
+        let args = engine.rewrite_args(&["cat".to_string(), encoded]).await.unwrap();
+        assert_eq!(args[0], "cat");
+        assert!(PathBuf::from(&args[1]).exists());
+    }
+}
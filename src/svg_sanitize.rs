@@ -0,0 +1,245 @@
+//! Cleaning SVGs before they're stored, previewed or uploaded. An SVG is a
+//! document rather than pixels: it can run scripts when opened in a browser
+//! and fetch remote resources that tell their owner it was viewed.
+//!
+//! Every level removes `<script>` and `<foreignObject>` elements, event
+//! handler attributes (`onload`, ...), DOCTYPEs and processing instructions,
+//! links and `url(...)` references to anything outside the document, and
+//! animations that could rewrite a link. Embedded PNG, JPEG, GIF and WebP
+//! data URIs are kept. `strict` also removes every data URI, `<style>`
+//! elements, `style` attributes and all animation.
+
+use crate::{config::SvgSanitize, error::Result, sniff, Error};
+use quick_xml::events::attributes::Attribute;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Elements removed with everything inside them at every level
+const REMOVED_ELEMENTS: &[&str] = &["script", "foreignobject", "iframe", "embed", "object", "handler", "listener"];
+
+const ANIMATION_ELEMENTS: &[&str] = &["animate", "animatemotion", "animatetransform", "animatecolor", "set"];
+
+/// Data URIs embedded images may use outside `strict`
+const SAFE_DATA_URIS: &[&str] = &["data:image/png", "data:image/jpeg", "data:image/jpg", "data:image/gif", "data:image/webp"];
+
+/// `svg` with everything `level` doesn't allow removed
+pub fn sanitize(svg: &[u8], level: SvgSanitize) -> Result<Vec<u8>> {
+    let invalid = |e: quick_xml::Error| Error::Parse(format!("Invalid SVG: {}", e));
+    let mut reader = Reader::from_reader(svg);
+    let mut writer = Writer::new(Vec::with_capacity(svg.len()));
+    // Depth inside an element being removed
+    let mut removing = 0usize;
+    let mut in_style = false;
+    let mut removed = 0usize;
+
+    loop {
+        let event = reader.read_event().map_err(invalid)?;
+        match event {
+            Event::Eof => break,
+            Event::Start(_) if removing > 0 => removing += 1,
+            Event::End(_) if removing > 0 => removing -= 1,
+            _ if removing > 0 => {}
+            Event::Start(start) => {
+                if removes_element(&start, level) {
+                    removing = 1;
+                    removed += 1;
+                    continue;
+                }
+                in_style = local_name(&start) == "style";
+                let (kept, dropped) = clean_attributes(&start, level);
+                removed += dropped;
+                writer.write_event(Event::Start(kept)).map_err(invalid)?;
+            }
+            Event::End(end) => {
+                in_style = false;
+                writer.write_event(Event::End(end)).map_err(invalid)?;
+            }
+            // Stylesheets importing others, or loading fonts and images from elsewhere
+            Event::Text(ref css) if in_style && !css.unescape().is_ok_and(|css| is_safe_css(&css, level)) => removed += 1,
+            Event::CData(ref css) if in_style && !is_safe_css(&String::from_utf8_lossy(css), level) => removed += 1,
+            Event::Empty(start) => {
+                if removes_element(&start, level) {
+                    removed += 1;
+                    continue;
+                }
+                let (kept, dropped) = clean_attributes(&start, level);
+                removed += dropped;
+                writer.write_event(Event::Empty(kept)).map_err(invalid)?;
+            }
+            // DTDs can declare entities that pull in other files
+            Event::DocType(_) | Event::PI(_) => removed += 1,
+            event => writer.write_event(event).map_err(invalid)?,
+        }
+    }
+
+    if removed > 0 {
+        debug!("Removed {} unsafe parts from an SVG", removed);
+    }
+    Ok(writer.into_inner())
+}
+
+/// Write a sanitized copy of the SVG at `source` to `dest`, or return `None`
+/// when `source` isn't an SVG
+pub async fn sanitized_copy(source: &Path, dest: PathBuf, level: SvgSanitize) -> Result<Option<PathBuf>> {
+    if sniff::file_image_extension(source) != Some("svg") {
+        return Ok(None);
+    }
+    let svg = tokio::fs::read(source).await?;
+    let sanitized = sanitize(&svg, level)?;
+    if let Some(parent) = dest.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(&dest, sanitized).await?;
+    Ok(Some(dest))
+}
+
+fn local_name(start: &BytesStart) -> String {
+    String::from_utf8_lossy(start.local_name().as_ref()).to_ascii_lowercase()
+}
+
+fn removes_element(start: &BytesStart, level: SvgSanitize) -> bool {
+    let name = local_name(start);
+    if REMOVED_ELEMENTS.contains(&name.as_str()) {
+        return true;
+    }
+    if ANIMATION_ELEMENTS.contains(&name.as_str()) {
+        if level == SvgSanitize::Strict {
+            return true;
+        }
+        // Animating a link or an event handler sets it to whatever the animation says
+        return start.attributes().flatten().any(|attr| {
+            attr.key.local_name().as_ref().eq_ignore_ascii_case(b"attributeName")
+                && attr.unescape_value().map_or(true, |target| {
+                    let target = target.trim().to_ascii_lowercase();
+                    target.ends_with("href") || target.starts_with("on")
+                })
+        });
+    }
+    level == SvgSanitize::Strict && name == "style"
+}
+
+/// `start` without the attributes `level` doesn't allow, and how many were dropped
+fn clean_attributes<'a>(start: &BytesStart<'a>, level: SvgSanitize) -> (BytesStart<'a>, usize) {
+    let mut kept = start.clone();
+    kept.clear_attributes();
+    let mut dropped = 0;
+    for attr in start.attributes() {
+        match attr {
+            Ok(attr) if allows_attribute(&attr, level) => {
+                if attr.value.contains(&b'"') {
+                    // Written back in double quotes
+                    let value = String::from_utf8_lossy(&attr.value).replace('"', "&quot;");
+                    kept.push_attribute(Attribute { key: attr.key, value: Cow::Owned(value.into_bytes()) });
+                } else {
+                    kept.push_attribute(attr);
+                }
+            }
+            _ => dropped += 1,
+        }
+    }
+    (kept, dropped)
+}
+
+fn allows_attribute(attr: &Attribute, level: SvgSanitize) -> bool {
+    let name = String::from_utf8_lossy(attr.key.local_name().as_ref()).to_ascii_lowercase();
+    if name.starts_with("on") || (level == SvgSanitize::Strict && name == "style") {
+        return false;
+    }
+    // Values with entities that can't be resolved can't be checked
+    let Ok(value) = attr.unescape_value() else {
+        return false;
+    };
+    // Browsers ignore whitespace and control characters inside schemes
+    let value: String = value.chars().filter(|c| !c.is_whitespace() && !c.is_control()).collect::<String>().to_ascii_lowercase();
+    if matches!(name.as_str(), "href" | "src") && !is_local(&value, level) {
+        return false;
+    }
+    let allowed = css_urls(&value).all(|url| is_local(url, level));
+    allowed
+}
+
+/// Whether the stylesheet `css` only refers to the document itself
+fn is_safe_css(css: &str, level: SvgSanitize) -> bool {
+    let css: String = css.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_ascii_lowercase();
+    let allowed = !css.contains("@import") && css_urls(&css).all(|url| is_local(url, level));
+    allowed
+}
+
+/// Whether a reference stays inside the document
+fn is_local(url: &str, level: SvgSanitize) -> bool {
+    let url = url.trim_matches(|c| c == '"' || c == '\'');
+    url.starts_with('#') || (level == SvgSanitize::Standard && SAFE_DATA_URIS.iter().any(|prefix| url.starts_with(prefix)))
+}
+
+/// What each `url(...)` in a CSS value or presentation attribute points to
+fn css_urls(value: &str) -> impl Iterator<Item = &str> {
+    value.match_indices("url(").map(move |(start, _)| {
+        let rest = &value[start + 4..];
+        &rest[..rest.find(')').unwrap_or(rest.len())]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clean(svg: &str, level: SvgSanitize) -> String {
+        String::from_utf8(sanitize(svg.as_bytes(), level).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let svg = r##"<?xml version="1.0"?>
+<!DOCTYPE svg [<!ENTITY x SYSTEM "file:///etc/passwd">]>
+<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" onload="alert(1)">
+  <script>alert(2)</script>
+  <foreignObject><body><script>alert(3)</script></body></foreignObject>
+  <rect width="10" height="10" fill="url(#grad)" style="fill: url('https://tracker.example/p.png')"/>
+  <a xlink:href="java&#x09;script:alert(4)"><text>link</text></a>
+  <image href="https://tracker.example/pixel.png"/>
+  <image href="data:image/png;base64,iVBORw0KGgo="/>
+  <use href="#shape"/>
+  <set attributeName="href" to="javascript:alert(5)"/>
+  <animate attributeName="opacity" from="0" to="1"/>
+  <style>rect { stroke: red }</style>
+  <style>@import "https://tracker.example/a.css";</style>
+</svg>"##;
+        let standard = clean(svg, SvgSanitize::Standard);
+        for gone in ["DOCTYPE", "script", "alert", "foreignObject", "tracker.example", "<set"] {
+            assert!(!standard.contains(gone), "{} left in {}", gone, standard);
+        }
+        for kept in ["<?xml", r##"fill="url(#grad)""##, "<a>", "<text>link</text>", "data:image/png", r##"<use href="#shape"/>"##, "<animate", "<style>"] {
+            assert!(standard.contains(kept), "{} missing from {}", kept, standard);
+        }
+
+        let strict = clean(svg, SvgSanitize::Strict);
+        for gone in ["data:image/png", "<animate", "<style>", "style="] {
+            assert!(!strict.contains(gone), "{} left in {}", gone, strict);
+        }
+        assert!(strict.contains(r##"<use href="#shape"/>"##));
+    }
+
+    #[test]
+    fn test_sanitize_rejects_broken_svgs() {
+        assert!(sanitize(b"<svg><g></svg>", SvgSanitize::Standard).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sanitized_copy() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let svg = temp_dir.path().join("logo");
+        std::fs::write(&svg, r#"<svg xmlns="http://www.w3.org/2000/svg"><script>x()</script></svg>"#).unwrap();
+        let copy = sanitized_copy(&svg, temp_dir.path().join("out").join("logo.svg"), SvgSanitize::Standard)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(copy).unwrap(), r#"<svg xmlns="http://www.w3.org/2000/svg"></svg>"#);
+
+        let png = temp_dir.path().join("shot.png");
+        std::fs::write(&png, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+        assert!(sanitized_copy(&png, temp_dir.path().join("copy.png"), SvgSanitize::Standard).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,206 @@
+//! Temporary files: stdin data being previewed, display and SVG copies,
+//! downloads, clipboard conversions and extracted archive members.
+//!
+//! They're created in [`dir`], in the run-state directory
+//! ([`crate::run_state`]) which only the user can enter, with random
+//! names and 0600 permissions, so other users can neither read them nor plant
+//! files where KlipDot expects its own. A [`TempFile`] is removed when it's
+//! dropped. Release builds abort on panic without unwinding, so
+//! [`install_panic_hook`] removes the files still in use first; files left
+//! behind by a killed process are removed by [`sweep`] when the next instance starts.
+
+use crate::error::Result;
+use once_cell::sync::Lazy;
+use std::collections::HashSet;
+use std::io::Write;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::debug;
+
+/// Age after which files in [`dir`] are taken to be left over from a crash
+pub const LEFTOVER_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Files created by this process and not yet removed or kept
+static LIVE: Lazy<Mutex<HashSet<PathBuf>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// A temporary file, removed when dropped
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    fn new(path: PathBuf) -> Self {
+        live().insert(path.clone());
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Leave the file in place for something that outlives this process's
+    /// use of it, such as a viewer that removes it itself
+    pub fn keep(self) -> PathBuf {
+        live().remove(&self.path);
+        self.path.clone()
+    }
+}
+
+impl Deref for TempFile {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempFile {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // Kept files, and those the panic hook got to first, aren't in the set
+        if live().remove(&self.path) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn live() -> std::sync::MutexGuard<'static, HashSet<PathBuf>> {
+    LIVE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// The private directory temporary files are created in
+pub fn dir() -> Result<PathBuf> {
+    private_dir(&crate::run_state::dir()?.join(crate::TEMP_DIR))
+}
+
+/// Create `path` if needed and make it accessible to its owner only
+pub fn private_dir(path: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(path.to_path_buf())
+}
+
+/// A new empty file in [`dir`] named `prefix`, random characters and `extension`
+pub fn create(prefix: &str, extension: &str) -> Result<TempFile> {
+    create_in(&dir()?, prefix, extension)
+}
+
+/// [`create`] in `dir`, which the caller has made private
+pub fn create_in(dir: &Path, prefix: &str, extension: &str) -> Result<TempFile> {
+    let suffix = match extension {
+        "" => String::new(),
+        extension => format!(".{}", extension),
+    };
+    let file = tempfile::Builder::new().prefix(prefix).suffix(&suffix).tempfile_in(dir)?;
+    let path = file.into_temp_path().keep().map_err(|e| e.error)?;
+    Ok(TempFile::new(path))
+}
+
+/// A new file in [`dir`] holding `data`
+pub fn write(prefix: &str, extension: &str, data: &[u8]) -> Result<TempFile> {
+    let file = create(prefix, extension)?;
+    std::fs::OpenOptions::new().write(true).open(file.path())?.write_all(data)?;
+    Ok(file)
+}
+
+/// Remove every temporary file this process still uses
+pub fn remove_all() {
+    for path in live().drain() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Remove temporary files before a panic aborts the process. Builds that
+/// unwind drop each [`TempFile`] on the way instead, and a panic one task
+/// recovers from mustn't take the files of others still using them.
+pub fn install_panic_hook() {
+    if !cfg!(panic = "abort") {
+        return;
+    }
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        remove_all();
+        previous(info);
+    }));
+}
+
+/// Remove files in `dir` at least `max_age` old, returning how many were removed
+pub fn sweep(dir: &Path, max_age: Duration) -> usize {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    let now = SystemTime::now();
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let old = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .is_some_and(|age| age >= max_age);
+        if metadata.is_file() && old && std::fs::remove_file(entry.path()).is_ok() {
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        debug!("Removed {} leftover temporary files from {:?}", removed, dir);
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_private_files_removed_when_dropped() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = private_dir(&temp_dir.path().join("temp")).unwrap();
+        let file = create_in(&dir, "stdin-", "png").unwrap();
+        let path = file.to_path_buf();
+        assert_eq!(path.parent(), Some(dir.as_path()));
+        assert!(path.file_name().unwrap().to_string_lossy().starts_with("stdin-"));
+        assert_eq!(path.extension().unwrap(), "png");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&dir).unwrap().permissions().mode() & 0o777, 0o700);
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        assert!(live().contains(&path));
+
+        drop(file);
+        assert!(!path.exists());
+        assert!(!live().contains(&path));
+
+        let kept = create_in(&dir, "overlay-", "").unwrap().keep();
+        assert!(kept.exists());
+        assert!(!live().contains(&kept));
+    }
+
+    #[test]
+    fn test_sweep() {
+        let temp_dir = TempDir::new().unwrap();
+        let leftover = temp_dir.path().join("capture-1.png");
+        std::fs::write(&leftover, b"png").unwrap();
+        assert_eq!(sweep(temp_dir.path(), LEFTOVER_AGE), 0);
+        assert_eq!(sweep(temp_dir.path(), Duration::ZERO), 1);
+        assert!(!leftover.exists());
+        assert_eq!(sweep(&temp_dir.path().join("missing"), Duration::ZERO), 0);
+    }
+}
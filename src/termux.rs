@@ -0,0 +1,105 @@
+//! Termux on Android: clipboard and notifications through Termux:API, and
+//! images shared into Termux from other apps.
+//!
+//! Termux only sees the text on Android's clipboard, so images arrive by
+//! sharing them to Termux, which runs `~/bin/termux-file-editor` with the
+//! received file, or by watching the shared storage folders that
+//! `termux-setup-storage` links into the Termux home.
+
+use crate::{error::Result, substitution::shell_quote, Error};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Termux:API clipboard reader and writer
+pub const CLIPBOARD_GET: &str = "termux-clipboard-get";
+pub const CLIPBOARD_SET: &str = "termux-clipboard-set";
+
+/// Source recorded for images shared into Termux
+pub const SHARE_SOURCE: &str = "share";
+
+/// First line of the share receiver, so klipdot knows which script it owns
+const SHARE_RECEIVER_MARKER: &str = "# Installed by klipdot";
+
+/// Whether klipdot runs inside Termux
+pub fn is_termux() -> bool {
+    std::env::var_os("TERMUX_VERSION").is_some()
+        || std::env::var("PREFIX").is_ok_and(|prefix| prefix.contains("com.termux"))
+}
+
+/// `~/storage`, once `termux-setup-storage` has linked shared storage there
+pub fn storage_dir() -> Option<PathBuf> {
+    let dir = dirs::home_dir()?.join("storage");
+    dir.is_dir().then_some(dir)
+}
+
+/// Android's Download folder as linked into the Termux home
+pub fn downloads_dir() -> Option<PathBuf> {
+    let dir = storage_dir()?.join("downloads");
+    dir.is_dir().then_some(dir)
+}
+
+/// `termux-file-editor` script storing images shared to Termux and putting
+/// the stored path on the clipboard
+pub fn share_receiver_script(klipdot_bin: &str) -> String {
+    format!(
+        r#"#!/data/data/com.termux/files/usr/bin/sh
+{marker}; runs when a file is shared to Termux
+stored=$({bin} --quiet process-file --source {source} -- "$1") || exit 1
+printf '%s' "$stored" | {clipboard_set} 2>/dev/null
+echo "Stored $stored"
+"#,
+        marker = SHARE_RECEIVER_MARKER,
+        bin = shell_quote(klipdot_bin),
+        source = SHARE_SOURCE,
+        clipboard_set = CLIPBOARD_SET,
+    )
+}
+
+/// Install the share receiver as `home/bin/termux-file-editor`. A script the
+/// user wrote themselves is left alone.
+pub async fn install_share_receiver(home: &Path, klipdot_bin: &str) -> Result<PathBuf> {
+    let bin_dir = home.join("bin");
+    let path = bin_dir.join("termux-file-editor");
+
+    if let Ok(existing) = tokio::fs::read_to_string(&path).await {
+        if !existing.contains(SHARE_RECEIVER_MARKER) {
+            return Err(Error::Config(format!(
+                "{:?} already exists; remove it to receive shared images",
+                path
+            )));
+        }
+    }
+
+    tokio::fs::create_dir_all(&bin_dir).await?;
+    tokio::fs::write(&path, share_receiver_script(klipdot_bin)).await?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).await?;
+    }
+
+    info!("Installed share receiver at {:?}", path);
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_install_share_receiver() {
+        let temp_dir = TempDir::new().unwrap();
+        let bin = "/data/data/com.termux/files/usr/bin/klipdot";
+
+        let path = install_share_receiver(temp_dir.path(), bin).await.unwrap();
+        let script = std::fs::read_to_string(&path).unwrap();
+        assert!(script.contains(r#"klipdot --quiet process-file --source share -- "$1""#));
+        assert!(script.contains("| termux-clipboard-set"));
+
+        // Reinstalling replaces our own script, but not the user's
+        install_share_receiver(temp_dir.path(), bin).await.unwrap();
+        std::fs::write(&path, "#!/bin/sh\nvim \"$1\"\n").unwrap();
+        assert!(install_share_receiver(temp_dir.path(), bin).await.is_err());
+    }
+}
@@ -0,0 +1,185 @@
+//! Bringing HDR and 16-bit images into displayable range.
+//!
+//! HDR captures (Radiance, OpenEXR) decode to linear light with values above
+//! 1.0. Converting them straight to integers clips every highlight and skips
+//! the sRGB transfer curve, so they come out flat and dark. They are tone
+//! mapped with extended Reinhard on luminance and sRGB-encoded instead. 16-bit
+//! images are already display-referred and only lose precision when reduced.
+
+use crate::{config::{BitDepth, DecodeLimits}, decode_guard, error::Result};
+use image::{DynamicImage, ImageFormat, Rgba};
+use std::io::Read;
+use std::path::Path;
+
+/// Offset of the bit depth byte in a PNG (signature, IHDR header, width, height)
+const PNG_BIT_DEPTH_OFFSET: usize = 24;
+
+/// Whether `img` holds linear floating-point samples
+pub fn is_hdr(img: &DynamicImage) -> bool {
+    matches!(img, DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_))
+}
+
+/// Whether `img` has more than 8 bits per channel
+pub fn is_high_bit_depth(img: &DynamicImage) -> bool {
+    let color = img.color();
+    color.bytes_per_pixel() > color.channel_count()
+}
+
+/// Bring a decoded image into the range it is stored in: HDR is tone mapped to
+/// 16 bits, and everything is reduced to 8 bits unless `depth` preserves it
+pub fn prepare(img: DynamicImage, depth: BitDepth) -> DynamicImage {
+    let img = if is_hdr(&img) { tone_map(&img) } else { img };
+    match depth {
+        BitDepth::Preserve => img,
+        BitDepth::Reduce => to_eight_bit(img),
+    }
+}
+
+/// Tone map a linear HDR image to 16-bit sRGB, keeping its alpha channel
+pub fn tone_map(img: &DynamicImage) -> DynamicImage {
+    let linear = img.to_rgba32f();
+
+    // The brightest pixel maps to white; images already within range only get the curve
+    let white = linear
+        .pixels()
+        .map(|pixel| luminance(pixel.0))
+        .filter(|l| l.is_finite())
+        .fold(1.0f32, f32::max);
+
+    let mut mapped = image::ImageBuffer::<Rgba<u16>, _>::new(linear.width(), linear.height());
+    for (out, pixel) in mapped.pixels_mut().zip(linear.pixels()) {
+        let [r, g, b, a] = pixel.0;
+        let l = luminance(pixel.0);
+        let scale = if l > 0.0 { reinhard(l, white) / l } else { 0.0 };
+        *out = Rgba([
+            srgb_encode(r * scale),
+            srgb_encode(g * scale),
+            srgb_encode(b * scale),
+            (a.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16,
+        ]);
+    }
+
+    let mapped = DynamicImage::ImageRgba16(mapped);
+    if img.color().has_alpha() {
+        mapped
+    } else {
+        DynamicImage::ImageRgb16(mapped.into_rgb16())
+    }
+}
+
+/// Reduce `img` to 8 bits per channel, keeping its channels
+pub fn to_eight_bit(img: DynamicImage) -> DynamicImage {
+    let img = if is_hdr(&img) { tone_map(&img) } else { img };
+    if !is_high_bit_depth(&img) {
+        return img;
+    }
+
+    let color = img.color();
+    match (color.channel_count(), color.has_alpha()) {
+        (1, _) => DynamicImage::ImageLuma8(img.into_luma8()),
+        (2, _) => DynamicImage::ImageLumaA8(img.into_luma_alpha8()),
+        (_, false) => DynamicImage::ImageRgb8(img.into_rgb8()),
+        (_, true) => DynamicImage::ImageRgba8(img.into_rgba8()),
+    }
+}
+
+/// Whether the image at `path` is HDR or 16-bit, judged from its header.
+/// Preview tools clip those or show them without their transfer curve.
+pub fn needs_display_conversion(path: &Path) -> bool {
+    let mut header = [0u8; 32];
+    let Ok(read) = std::fs::File::open(path).and_then(|mut file| file.read(&mut header)) else {
+        return false;
+    };
+
+    match image::guess_format(&header[..read]) {
+        Ok(ImageFormat::Hdr | ImageFormat::OpenExr) => true,
+        Ok(ImageFormat::Png) => header.get(PNG_BIT_DEPTH_OFFSET) == Some(&16),
+        _ => false,
+    }
+}
+
+/// Write an 8-bit sRGB PNG of the image at `source`, decoded within `limits`, to `target`
+pub fn write_display_copy(source: &Path, target: &Path, limits: &DecodeLimits) -> Result<()> {
+    let img = to_eight_bit(decode_guard::open(source, limits)?);
+    img.save_with_format(target, ImageFormat::Png)?;
+    Ok(())
+}
+
+/// Rec. 709 luminance of linear RGB
+fn luminance([r, g, b, _]: [f32; 4]) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// Extended Reinhard: compresses highlights so that `white` maps to 1.0
+fn reinhard(l: f32, white: f32) -> f32 {
+    l * (1.0 + l / (white * white)) / (1.0 + l)
+}
+
+/// sRGB transfer curve from linear light to a 16-bit sample
+fn srgb_encode(linear: f32) -> u16 {
+    let linear = linear.clamp(0.0, 1.0);
+    let encoded = if linear <= 0.003_130_8 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * u16::MAX as f32).round() as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hdr_image() -> DynamicImage {
+        // Black, mid grey in linear light, white, and a highlight four times brighter
+        let values = [0.0, 0.214, 1.0, 4.0];
+        DynamicImage::ImageRgb32F(image::ImageBuffer::from_fn(4, 1, |x, _| {
+            let v = values[x as usize];
+            image::Rgb([v, v, v])
+        }))
+    }
+
+    #[test]
+    fn test_tone_map_keeps_highlights_and_applies_curve() {
+        let mapped = tone_map(&hdr_image());
+        assert!(matches!(mapped, DynamicImage::ImageRgb16(_)));
+
+        let mapped = mapped.to_rgb16();
+        let samples: Vec<u16> = mapped.pixels().map(|pixel| pixel[0]).collect();
+        assert_eq!(samples[0], 0);
+        assert_eq!(samples[3], u16::MAX);
+        // Ordering survives, so the 1.0 white is no longer clipped with the highlight
+        assert!(samples[1] < samples[2] && samples[2] < samples[3]);
+        // Gamma encoding lifts linear mid grey well above a third of the range
+        assert!(samples[1] > u16::MAX / 3);
+    }
+
+    #[test]
+    fn test_prepare_bit_depth() {
+        let deep = DynamicImage::ImageRgba16(image::ImageBuffer::from_pixel(2, 2, Rgba([65535, 32896, 0, 65535])));
+        assert!(is_high_bit_depth(&prepare(deep.clone(), BitDepth::Preserve)));
+
+        let reduced = prepare(deep, BitDepth::Reduce);
+        assert!(matches!(reduced, DynamicImage::ImageRgba8(_)));
+        assert_eq!(reduced.to_rgba8().get_pixel(0, 0).0, [255, 128, 0, 255]);
+
+        let reduced = prepare(hdr_image(), BitDepth::Reduce);
+        assert!(matches!(reduced, DynamicImage::ImageRgb8(_)));
+    }
+
+    #[test]
+    fn test_display_conversion_detection() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let deep = temp_dir.path().join("deep.png");
+        let plain = temp_dir.path().join("plain.png");
+        DynamicImage::ImageRgb16(image::ImageBuffer::new(2, 2)).save(&deep).unwrap();
+        DynamicImage::ImageRgb8(image::ImageBuffer::new(2, 2)).save(&plain).unwrap();
+
+        assert!(needs_display_conversion(&deep));
+        assert!(!needs_display_conversion(&plain));
+
+        let copy = temp_dir.path().join("copy.png");
+        write_display_copy(&deep, &copy, &DecodeLimits::default()).unwrap();
+        assert!(!needs_display_conversion(&copy));
+    }
+}
@@ -0,0 +1,105 @@
+//! Cached lookups of external tools on `PATH`.
+//!
+//! The monitors ask which clipboard and screenshot tools exist on every poll.
+//! Results are kept until `PATH` changes or [`TOOL_CACHE_TTL`] passes, so a
+//! tool installed while the daemon runs is still picked up.
+
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long lookup results are trusted before `PATH` is searched again
+pub const TOOL_CACHE_TTL: Duration = Duration::from_secs(60);
+
+static CACHE: Lazy<Mutex<ToolCache>> = Lazy::new(|| Mutex::new(ToolCache::new(TOOL_CACHE_TTL)));
+
+/// Lookup results for one `PATH` value
+#[derive(Debug)]
+struct ToolCache {
+    ttl: Duration,
+    path: Option<OsString>,
+    filled_at: Option<Instant>,
+    entries: HashMap<String, bool>,
+}
+
+impl ToolCache {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            path: None,
+            filled_at: None,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Whether `command` is available, calling `lookup` when the answer is not cached
+    fn is_available_with(&mut self, command: &str, path: Option<&OsStr>, now: Instant, lookup: impl FnOnce(&str) -> bool) -> bool {
+        let expired = self.filled_at.is_none_or(|filled_at| now.duration_since(filled_at) >= self.ttl);
+        if expired || self.path.as_deref() != path {
+            self.entries.clear();
+            self.path = path.map(OsStr::to_os_string);
+            self.filled_at = Some(now);
+        }
+
+        *self
+            .entries
+            .entry(command.to_string())
+            .or_insert_with(|| lookup(command))
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.filled_at = None;
+    }
+}
+
+/// Whether `command` can be found on `PATH`
+pub fn is_available(command: &str) -> bool {
+    let path = std::env::var_os("PATH");
+    CACHE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .is_available_with(command, path.as_deref(), Instant::now(), |command| which::which(command).is_ok())
+}
+
+/// Forget every cached result, e.g. after installing a tool
+pub fn invalidate() {
+    CACHE.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_results_are_cached_until_expiry_or_path_change() {
+        let mut cache = ToolCache::new(Duration::from_secs(60));
+        let lookups = Cell::new(0);
+        let lookup = |_: &str| {
+            lookups.set(lookups.get() + 1);
+            true
+        };
+        let start = Instant::now();
+        let path = OsStr::new("/usr/bin");
+
+        assert!(cache.is_available_with("wl-paste", Some(path), start, lookup));
+        assert!(cache.is_available_with("wl-paste", Some(path), start + Duration::from_secs(30), lookup));
+        assert_eq!(lookups.get(), 1);
+
+        // A different PATH or an expired entry searches again
+        cache.is_available_with("wl-paste", Some(OsStr::new("/opt/bin")), start + Duration::from_secs(31), lookup);
+        assert_eq!(lookups.get(), 2);
+        cache.is_available_with("wl-paste", Some(OsStr::new("/opt/bin")), start + Duration::from_secs(92), lookup);
+        assert_eq!(lookups.get(), 3);
+
+        // Missing tools are cached too
+        assert!(!cache.is_available_with("xsel", None, start, |_| false));
+        assert!(!cache.is_available_with("xsel", None, start, |_| unreachable!()));
+
+        cache.clear();
+        assert!(cache.is_available_with("xsel", None, start, |_| true));
+    }
+}
@@ -0,0 +1,176 @@
+//! External commands clipboard images are piped through before they're stored.
+//!
+//! Each command in `transforms.commands` gets the image on stdin, in order,
+//! and answers on stdout with one of:
+//!
+//! - a new image, which replaces it for the next command and for storing
+//!   (cropped, compressed, converted);
+//! - a JSON object with `tags` and `alt_text`, recorded with the stored image
+//!   (an upload script can tag what it uploaded);
+//! - nothing, leaving the image as it is.
+//!
+//! `{source}` and `{app}` in a command's arguments are replaced by where the
+//! image came from. A command that fails, times out or prints anything else
+//! is skipped with a warning, so a broken transform never loses a capture.
+
+use crate::{command_runner::CommandRunner, config::TransformsConfig, error::Result, error_history, metadata, sniff, Error};
+use serde::Deserialize;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// What a transform may say about the image instead of replacing it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct TransformMetadata {
+    pub tags: Vec<String>,
+    pub alt_text: Option<String>,
+}
+
+impl TransformMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.alt_text.is_none()
+    }
+}
+
+/// The image after all transforms ran
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transformed {
+    pub data: Vec<u8>,
+    pub metadata: TransformMetadata,
+}
+
+/// What one transform answered
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Unchanged,
+    Replaced(Vec<u8>),
+    Described(TransformMetadata),
+}
+
+/// Run the configured transforms on `data` from `source` and `app`
+pub async fn apply(config: &TransformsConfig, runner: &dyn CommandRunner, data: Vec<u8>, source: &str, app: Option<&str>) -> Transformed {
+    let mut transformed = Transformed { data, metadata: TransformMetadata::default() };
+    for command in &config.commands {
+        let Some(program) = command.first() else {
+            continue;
+        };
+        match run(config, runner, command, &transformed.data, source, app).await {
+            Ok(Outcome::Unchanged) => debug!("Transform {} left the image as it was", program),
+            Ok(Outcome::Replaced(data)) => {
+                debug!("Transform {} replaced the image ({} bytes)", program, data.len());
+                transformed.data = data;
+            }
+            Ok(Outcome::Described(metadata)) => {
+                debug!("Transform {} described the image: {:?}", program, metadata);
+                transformed.metadata.tags.extend(metadata.tags);
+                transformed.metadata.alt_text = metadata.alt_text.or(transformed.metadata.alt_text);
+            }
+            Err(e) => {
+                warn!("Skipping transform {}: {}", program, e);
+                error_history::record_error("transforms", &e);
+            }
+        }
+    }
+    transformed
+}
+
+async fn run(config: &TransformsConfig, runner: &dyn CommandRunner, command: &[String], data: &[u8], source: &str, app: Option<&str>) -> Result<Outcome> {
+    let Some((program, args)) = command.split_first() else {
+        return Ok(Outcome::Unchanged);
+    };
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| arg.replace("{source}", source).replace("{app}", app.unwrap_or("")))
+        .collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    // A command still running at the timeout is killed as its run is dropped
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let output = tokio::time::timeout(timeout, runner.run(program, &args, Some(data)))
+        .await
+        .map_err(|_| Error::Process(format!("{} took longer than {}s", program, config.timeout_secs)))?
+        .map_err(|e| Error::Process(format!("Failed to run {}: {}", program, e)))?;
+    if !output.success {
+        return Err(Error::Process(format!("{} failed: {}", program, output.stderr_lossy().trim())));
+    }
+
+    if output.stdout.iter().all(u8::is_ascii_whitespace) {
+        return Ok(Outcome::Unchanged);
+    }
+    if sniff::image_extension(&output.stdout).is_some() {
+        return Ok(Outcome::Replaced(output.stdout));
+    }
+    match serde_json::from_slice::<TransformMetadata>(&output.stdout) {
+        Ok(metadata) => Ok(Outcome::Described(metadata)),
+        Err(_) => Err(Error::Format(format!("{} printed neither an image nor JSON metadata", program))),
+    }
+}
+
+/// Add what transforms said about an image to the metadata of its stored copy at `path`
+pub async fn record(path: &Path, transformed: &TransformMetadata) -> Result<()> {
+    let (Some(dir), Some(filename)) = (path.parent(), path.file_name()) else {
+        return Err(Error::InvalidInput(format!("Not an image file: {:?}", path)));
+    };
+    metadata::retag(dir, &filename.to_string_lossy(), &transformed.tags, &[]).await?;
+    if let Some(alt_text) = &transformed.alt_text {
+        // The index is append-only and later entries win, so the updated entry replaces the original
+        if let Some(mut entry) = metadata::load(dir).await?.remove(filename.to_string_lossy().as_ref()) {
+            entry.alt_text = Some(alt_text.clone());
+            metadata::record(dir, &entry).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+
+    #[tokio::test]
+    async fn test_transforms_pipeline() {
+        let png = b"\x89PNG\r\n\x1a\n cropped".to_vec();
+        let runner = FakeRunner::new()
+            .with_output("crop", CommandOutput::ok(png.clone()))
+            .with_output("upload", CommandOutput::ok(r#"{"tags": ["uploaded"], "alt_text": "A chart"}"#))
+            .with_output("broken", CommandOutput::ok("oops"))
+            .with_output("failing", CommandOutput::failed("no"));
+        let config = TransformsConfig {
+            commands: vec![
+                vec!["crop".to_string(), "--from".to_string(), "{source}".to_string()],
+                vec!["broken".to_string()],
+                vec!["failing".to_string()],
+                vec!["upload".to_string(), "{app}".to_string()],
+            ],
+            ..TransformsConfig::default()
+        };
+
+        let transformed = apply(&config, &runner, b"original".to_vec(), "clipboard", Some("firefox")).await;
+        assert_eq!(transformed.data, png);
+        assert_eq!(transformed.metadata, TransformMetadata { tags: vec!["uploaded".to_string()], alt_text: Some("A chart".to_string()) });
+
+        // Each command reads the previous one's image
+        assert_eq!(runner.calls_to("crop")[0].args, ["--from", "clipboard"]);
+        assert_eq!(runner.calls_to("crop")[0].stdin.as_deref(), Some(&b"original"[..]));
+        assert_eq!(runner.calls_to("upload")[0].args, ["firefox"]);
+        assert_eq!(runner.calls_to("upload")[0].stdin.as_deref(), Some(&png[..]));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_transforms_with_large_images() {
+        let runner = crate::command_runner::SystemRunner;
+        let config = TransformsConfig { timeout_secs: 1, ..TransformsConfig::default() };
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.resize(1 << 20, 0);
+
+        let command = ["cat".to_string()];
+        assert_eq!(run(&config, &runner, &command, &png, "clipboard", None).await.unwrap(), Outcome::Replaced(png.clone()));
+        // Exiting before reading it all isn't a failure
+        let command = ["head".to_string(), "-c".to_string(), "8".to_string()];
+        assert_eq!(run(&config, &runner, &command, &png, "clipboard", None).await.unwrap(), Outcome::Replaced(png[..8].to_vec()));
+        let command = ["sleep".to_string(), "5".to_string()];
+        assert!(run(&config, &runner, &command, &png, "clipboard", None).await.is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! `klipdot undo`: putting back the image a clipboard rewrite replaced.
+//!
+//! When the clipboard monitor swaps a copied image for its path, the image
+//! exactly as it was copied is kept in [`crate::UNDO_DIR`] along with the
+//! text that replaced it. Only the latest rewrite can be undone, and only
+//! while the clipboard still holds that text, so undoing never throws away
+//! something copied since.
+
+use crate::{
+    clipboard::ClipboardMonitor, command_runner::CommandRunner, config::{Config, DecodeLimits}, error::Result, paste_image,
+    DisplayServer, Error,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// The copied image, as it was on the clipboard
+const ORIGINAL_FILE: &str = "original";
+
+/// What replaced it, as a [`Rewrite`]
+const REWRITE_FILE: &str = "rewrite.json";
+
+/// The latest clipboard rewrite
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rewrite {
+    pub timestamp: DateTime<Utc>,
+    /// Text the image was replaced with
+    pub replacement: String,
+    /// Where the image was stored
+    pub stored: PathBuf,
+}
+
+fn undo_dir(home: &Path) -> PathBuf {
+    home.join(crate::UNDO_DIR)
+}
+
+/// Keep `original`, the image the clipboard held before it was replaced
+/// with `replacement`, in place of any earlier rewrite
+pub async fn remember(home: &Path, original: &[u8], replacement: &str, stored: &Path) -> Result<()> {
+    let dir = undo_dir(home);
+    tokio::fs::create_dir_all(&dir).await?;
+    let rewrite = Rewrite {
+        timestamp: Utc::now(),
+        replacement: replacement.to_string(),
+        stored: stored.to_path_buf(),
+    };
+    // Dropped first so a half-written pair is never taken for a rewrite
+    forget(home).await?;
+    write_private(&dir.join(ORIGINAL_FILE), original).await?;
+    write_private(&dir.join(REWRITE_FILE), serde_json::to_string_pretty(&rewrite)?.as_bytes()).await
+}
+
+/// The rewrite `klipdot undo` would revert
+pub async fn last(home: &Path) -> Result<Rewrite> {
+    let dir = undo_dir(home);
+    let content = match tokio::fs::read_to_string(dir.join(REWRITE_FILE)).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::NotFound("No clipboard rewrite to undo".to_string()));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if !dir.join(ORIGINAL_FILE).is_file() {
+        return Err(Error::NotFound("No clipboard rewrite to undo".to_string()));
+    }
+    serde_json::from_str(&content).map_err(|e| Error::Parse(format!("Invalid undo record: {}", e)))
+}
+
+/// Drop the kept image, once it's been put back or is no longer wanted
+pub async fn forget(home: &Path) -> Result<()> {
+    let dir = undo_dir(home);
+    for file in [REWRITE_FILE, ORIGINAL_FILE] {
+        match tokio::fs::remove_file(dir.join(file)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Whether the clipboard, holding `clipboard`, still shows `rewrite`'s replacement
+pub fn still_replaced(rewrite: &Rewrite, clipboard: Option<&str>) -> bool {
+    clipboard.is_some_and(|content| content.trim_end() == rewrite.replacement.trim_end())
+}
+
+/// Put the image the latest rewrite replaced back on the clipboard. Unless
+/// `force` is set, the clipboard must still hold the replacement.
+pub async fn undo(config: &Config, home: &Path, force: bool) -> Result<Rewrite> {
+    let rewrite = last(home).await?;
+    if !force {
+        let clipboard = ClipboardMonitor::new(config.clone()).await?.text().await?;
+        if !still_replaced(&rewrite, clipboard.as_deref()) {
+            return Err(Error::InvalidInput(
+                "The clipboard has changed since the image was replaced; use --force to restore it anyway".to_string(),
+            ));
+        }
+    }
+    let runner = crate::command_runner::system();
+    restore(runner.as_ref(), config.get_display_server(), home, &rewrite, &config.decode_limits).await?;
+    Ok(rewrite)
+}
+
+/// Place the kept image on the clipboard as image data and drop it
+pub async fn restore(runner: &dyn CommandRunner, display_server: DisplayServer, home: &Path, rewrite: &Rewrite, limits: &DecodeLimits) -> Result<()> {
+    paste_image::copy_to_clipboard(runner, display_server, &undo_dir(home).join(ORIGINAL_FILE), home, limits).await?;
+    tracing::info!("Restored the image replaced with {}", rewrite.replacement);
+    forget(home).await
+}
+
+/// Write `data` to `path`, readable only by its owner
+async fn write_private(path: &Path, data: &[u8]) -> Result<()> {
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    tokio::io::AsyncWriteExt::write_all(&mut file, data).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+    use tempfile::TempDir;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    #[tokio::test]
+    async fn test_remember_and_forget() {
+        let temp_dir = TempDir::new().unwrap();
+        let home = temp_dir.path();
+        assert!(matches!(last(home).await, Err(Error::NotFound(_))));
+
+        remember(home, b"first", "/tmp/a.png", Path::new("/shots/a.png")).await.unwrap();
+        remember(home, PNG, "/tmp/b.png", Path::new("/shots/b.png")).await.unwrap();
+        let rewrite = last(home).await.unwrap();
+        // Only the latest rewrite is kept
+        assert_eq!(rewrite.replacement, "/tmp/b.png");
+        assert_eq!(rewrite.stored, Path::new("/shots/b.png"));
+        assert_eq!(std::fs::read(undo_dir(home).join(ORIGINAL_FILE)).unwrap(), PNG);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(undo_dir(home).join(ORIGINAL_FILE)).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        assert!(still_replaced(&rewrite, Some("/tmp/b.png\n")));
+        assert!(!still_replaced(&rewrite, Some("something else")));
+        assert!(!still_replaced(&rewrite, None));
+
+        forget(home).await.unwrap();
+        assert!(last(home).await.is_err());
+        forget(home).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restore_puts_original_back() {
+        let temp_dir = TempDir::new().unwrap();
+        let home = temp_dir.path();
+        remember(home, PNG, "/tmp/shot.png", Path::new("/tmp/shot.png")).await.unwrap();
+        let rewrite = last(home).await.unwrap();
+
+        let runner = FakeRunner::new().with_output("wl-copy", CommandOutput::ok(""));
+        restore(&runner, DisplayServer::Wayland, home, &rewrite, &DecodeLimits::default()).await.unwrap();
+
+        let call = &runner.calls_to("wl-copy")[0];
+        assert_eq!(call.args, ["--type", "image/png"]);
+        assert_eq!(call.stdin.as_deref(), Some(PNG));
+        // The monitor leaves it on the clipboard, and it can only be undone once
+        assert!(paste_image::take_handed_back(home, PNG).await);
+        assert!(last(home).await.is_err());
+    }
+}
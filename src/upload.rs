@@ -0,0 +1,301 @@
+//! Preparing images before `scp` and `rsync` send them to another host.
+//!
+//! The shell wrappers pass their arguments to `klipdot prepare-upload`. When
+//! the destination is remote, every local image being copied is resized and
+//! re-encoded into a temporary copy with the same file name, and the wrapper
+//! runs the real command on the rewritten arguments. The originals are never
+//! modified. When the system policy forbids the destination, the wrapper
+//! doesn't run the command at all.
+
+use crate::{config::Config, decode_guard, error::Result, svg_sanitize, Error};
+use image::GenericImageView;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// Short options that take a value, so the next word isn't a file
+fn value_flags(program: &str) -> &'static str {
+    match program {
+        "scp" => "cDFiJlLoPSX",
+        "rsync" => "eBfMT",
+        _ => "",
+    }
+}
+
+/// Long rsync options that take their value as the next word
+const RSYNC_VALUE_OPTIONS: &[&str] = &[
+    "--rsh", "--rsync-path", "--exclude", "--include", "--filter", "--files-from",
+    "--exclude-from", "--include-from", "--temp-dir", "--partial-dir", "--backup-dir",
+    "--suffix", "--compare-dest", "--copy-dest", "--link-dest", "--chmod", "--chown",
+    "--port", "--password-file", "--log-file", "--bwlimit", "--timeout", "--max-size",
+    "--min-size", "--remote-option",
+];
+
+/// Indices of the file operands in the arguments to `program`
+pub fn operands(program: &str, args: &[String]) -> Vec<usize> {
+    let flags = value_flags(program);
+    let mut found = Vec::new();
+    let mut index = 0;
+    while index < args.len() {
+        let arg = &args[index];
+        if arg == "--" {
+            found.extend(index + 1..args.len());
+            break;
+        }
+        if let Some(long) = arg.strip_prefix("--") {
+            if !long.contains('=') && RSYNC_VALUE_OPTIONS.contains(&arg.as_str()) {
+                index += 1;
+            }
+        } else if let Some(cluster) = arg.strip_prefix('-').filter(|cluster| !cluster.is_empty()) {
+            // `-P22` carries its value; `-P 22` takes the next word
+            if let Some(position) = cluster.find(|c| flags.contains(c)) {
+                if position + 1 == cluster.len() {
+                    index += 1;
+                }
+            }
+        } else {
+            found.push(index);
+        }
+        index += 1;
+    }
+    found
+}
+
+/// Host an scp or rsync operand refers to, `None` for local paths
+pub fn remote_host(operand: &str) -> Option<String> {
+    let authority = if let Some((scheme, rest)) = operand.split_once("://") {
+        if !matches!(scheme, "scp" | "rsync" | "sftp") {
+            return None;
+        }
+        rest.split('/').next()?
+    } else {
+        let end = match operand.find('[') {
+            // `[::1]:path` and `me@[::1]:path`
+            Some(open) if !operand[..open].contains([':', '/']) => open + operand[open..].find("]:")? + 1,
+            _ => operand.find(':')?,
+        };
+        let host = &operand[..end];
+        // `./a:b.png` and `dir/a:b.png` are local files
+        if host.contains('/') || Path::new(operand).exists() {
+            return None;
+        }
+        host
+    };
+
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    let host = match host.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next()?,
+        None => host.split(':').next()?,
+    };
+    (!host.is_empty()).then(|| host.to_ascii_lowercase())
+}
+
+/// Arguments for `program` with local images replaced by prepared copies,
+/// or `None` when nothing needs to change. Copies to a host the system
+/// policy forbids fail with [`Error::Permission`].
+pub async fn prepare_args(config: &Config, program: &str, args: &[String]) -> Result<Option<Vec<String>>> {
+    let operands = operands(program, args);
+    let Some((&destination, sources)) = operands.split_last() else {
+        return Ok(None);
+    };
+    let Some(host) = remote_host(&args[destination]) else {
+        return Ok(None);
+    };
+    config.policy.check_upload(program, &host)?;
+
+    let policy = &config.uploads;
+    if !policy.enabled {
+        return Ok(None);
+    }
+    if !policy.hosts.is_empty() && !policy.hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(&host)) {
+        debug!("Not preparing images for {}", host);
+        return Ok(None);
+    }
+
+    let mut rewritten = args.to_vec();
+    let mut changed = false;
+    for &index in sources {
+        let source = Path::new(&args[index]);
+        // Directories copied with -r are sent as they are
+        if !source.is_file() || !crate::is_image_file(source) {
+            continue;
+        }
+        // Re-encoding would keep only the first frame
+        if source.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gif")) {
+            continue;
+        }
+
+        match prepare_image(config, source, &upload_dir(config)).await {
+            Ok(Some(prepared)) => {
+                debug!("Sending {:?} to {} as {:?}", source, host, prepared);
+                rewritten[index] = prepared.to_string_lossy().to_string();
+                changed = true;
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Sending {:?} unchanged: {}", source, e),
+        }
+    }
+
+    Ok(changed.then_some(rewritten))
+}
+
+/// A copy of `source` to upload in its place: a sanitized SVG, or a
+/// full-size, metadata-free image when the system policy requires stripping
+/// metadata. The caller removes its directory once done.
+pub async fn policy_copy(config: &Config, source: &Path) -> Result<Option<PathBuf>> {
+    if let Some(copy) = sanitized_svg(config, source, &upload_dir(config)).await? {
+        return Ok(Some(copy));
+    }
+    if !config.policy.require_strip_metadata {
+        return Ok(None);
+    }
+    let mut config = config.clone();
+    config.uploads.max_dimension = crate::config::MaxDimension::Unlimited;
+    config.uploads.strip_metadata = true;
+    prepare_image(&config, source, &upload_dir(&config)).await
+}
+
+/// A sanitized copy of `source` in `dest_dir` when it's an SVG
+async fn sanitized_svg(config: &Config, source: &Path, dest_dir: &Path) -> Result<Option<PathBuf>> {
+    let Some(file_name) = source.file_name() else {
+        return Ok(None);
+    };
+    svg_sanitize::sanitized_copy(source, dest_dir.join(file_name), config.svg.sanitize).await
+}
+
+/// A new directory under the temporary directory for prepared copies
+fn upload_dir(config: &Config) -> PathBuf {
+    config
+        .screenshot_dir
+        .join("temp")
+        .join("upload")
+        .join(uuid::Uuid::new_v4().to_string())
+}
+
+/// Write the copy of `source` to send into `dest_dir`, keeping its file
+/// name, or return `None` when the original can be sent as it is
+pub async fn prepare_image(config: &Config, source: &Path, dest_dir: &Path) -> Result<Option<PathBuf>> {
+    let policy = config.uploads.clone();
+    let file_name = source
+        .file_name()
+        .ok_or_else(|| Error::InvalidInput(format!("Not a file: {:?}", source)))?;
+    let size = tokio::fs::metadata(source).await?.len();
+    if size > crate::MAX_FILE_SIZE {
+        return Err(Error::InvalidInput(format!(
+            "{:?} is larger than {}",
+            source,
+            crate::format_file_size(crate::MAX_FILE_SIZE)
+        )));
+    }
+
+    // SVGs aren't re-encoded, only cleaned
+    if let Some(copy) = sanitized_svg(config, source, dest_dir).await? {
+        return Ok(Some(copy));
+    }
+
+    let source = source.to_path_buf();
+    let dest = dest_dir.join(file_name);
+    let dest_dir = dest_dir.to_path_buf();
+    let limits = config.decode_limits.clone();
+    tokio::task::spawn_blocking(move || -> Result<Option<PathBuf>> {
+        let img = decode_guard::open(&source, &limits)?;
+        let (width, height) = img.dimensions();
+        let limit = policy.max_dimension.pixels().filter(|&limit| width.max(height) > limit);
+        if limit.is_none() && !policy.strip_metadata {
+            return Ok(None);
+        }
+
+        // Re-encoding leaves EXIF and other metadata behind
+        let processed = match limit {
+            Some(limit) => img.resize(limit, limit, image::imageops::FilterType::Lanczos3),
+            None => img,
+        };
+        std::fs::create_dir_all(&dest_dir)?;
+        processed.save(&dest)?;
+        Ok(Some(dest))
+    })
+    .await
+    .map_err(|e| Error::Internal(format!("Image preparation task failed: {}", e)))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{MaxDimension, UploadConfig};
+    use tempfile::TempDir;
+
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_operands() {
+        assert_eq!(operands("scp", &words("-P 2222 -i key shot.png box:/tmp/")), [4, 5]);
+        assert_eq!(operands("scp", &words("-rP2222 shot.png box:")), [1, 2]);
+        assert_eq!(operands("rsync", &words("-av --rsh ssh -e ssh --exclude=x a.png box:")), [6, 7]);
+        assert_eq!(operands("rsync", &words("-a -- -odd.png box:")), [2, 3]);
+    }
+
+    #[test]
+    fn test_remote_host() {
+        assert_eq!(remote_host("gpu-box:/srv/in/").as_deref(), Some("gpu-box"));
+        assert_eq!(remote_host("me@GPU-Box:").as_deref(), Some("gpu-box"));
+        assert_eq!(remote_host("[::1]:/tmp").as_deref(), Some("::1"));
+        assert_eq!(remote_host("scp://me@box:2222/tmp/").as_deref(), Some("box"));
+        assert_eq!(remote_host("rsync://mirror/module/").as_deref(), Some("mirror"));
+        assert_eq!(remote_host("/tmp/out/"), None);
+        assert_eq!(remote_host("./a:b.png"), None);
+    }
+
+    #[tokio::test]
+    async fn test_prepare_args() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = Config {
+            screenshot_dir: temp_dir.path().join("shots"),
+            uploads: UploadConfig {
+                enabled: true,
+                max_dimension: MaxDimension::Pixels(100),
+                ..UploadConfig::default()
+            },
+            ..Config::default()
+        };
+        let shot = temp_dir.path().join("shot.png");
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(400, 200)).save(&shot).unwrap();
+        let shot_arg = shot.to_string_lossy().to_string();
+        let notes = temp_dir.path().join("notes.txt");
+        std::fs::write(&notes, "x").unwrap();
+        let args = vec![shot_arg.clone(), notes.to_string_lossy().to_string(), "gpu-box:/srv/in/".to_string()];
+
+        let rewritten = prepare_args(&config, "scp", &args).await.unwrap().unwrap();
+        assert_ne!(rewritten[0], shot_arg);
+        assert_eq!(rewritten[1..], args[1..]);
+        let prepared = Path::new(&rewritten[0]);
+        assert_eq!(prepared.file_name().unwrap(), "shot.png");
+        assert_eq!(image::open(prepared).unwrap().dimensions(), (100, 50));
+        assert_eq!(image::open(&shot).unwrap().dimensions(), (400, 200));
+
+        // Local copies and other hosts are left alone
+        let local = vec![shot_arg.clone(), temp_dir.path().join("copy.png").to_string_lossy().to_string()];
+        assert!(prepare_args(&config, "scp", &local).await.unwrap().is_none());
+        config.uploads.hosts = vec!["other-box".to_string()];
+        assert!(prepare_args(&config, "scp", &args).await.unwrap().is_none());
+
+        // Forbidden hosts are refused even when nothing would be prepared
+        config.uploads.enabled = false;
+        config.policy.forbidden_upload_targets = vec!["gpu-box".to_string()];
+        assert!(matches!(prepare_args(&config, "rsync", &args).await, Err(Error::Permission(_))));
+        assert!(prepare_args(&config, "rsync", &local).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_svgs_are_sanitized() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config { screenshot_dir: temp_dir.path().join("shots"), ..Config::default() };
+        let logo = temp_dir.path().join("logo.svg");
+        std::fs::write(&logo, r#"<svg xmlns="http://www.w3.org/2000/svg" onload="x()"><rect/></svg>"#).unwrap();
+
+        let copy = policy_copy(&config, &logo).await.unwrap().unwrap();
+        assert_eq!(copy.file_name().unwrap(), "logo.svg");
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), r#"<svg xmlns="http://www.w3.org/2000/svg"><rect/></svg>"#);
+        assert!(std::fs::read_to_string(&logo).unwrap().contains("onload"));
+    }
+}
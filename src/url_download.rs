@@ -0,0 +1,378 @@
+//! Fetching image URLs found in monitored output, within configured limits.
+//!
+//! Output being monitored can come from anywhere (a chat log, a web page
+//! dump), so every download is checked against domain allow and deny lists,
+//! a per-minute rate and a concurrency limit, and is bounded in size and
+//! time. Downloads go through `curl` without following redirects, so a
+//! permitted host can't bounce the request somewhere else. Hosts are resolved
+//! here first and refused when any of their addresses is internal, and curl
+//! is pinned to the vetted address, so a name can't resolve to this machine
+//! or a private network either.
+
+use crate::{command_runner::SharedRunner, config::UrlDownloadConfig, error::Result, temp_files, Error};
+use std::collections::{HashSet, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tracing::debug;
+
+/// Window the per-minute rate limit counts downloads in
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
+pub struct UrlDownloader {
+    config: UrlDownloadConfig,
+    runner: SharedRunner,
+    permits: Arc<Semaphore>,
+    /// Start times of downloads within the last [`RATE_WINDOW`]
+    recent: Arc<Mutex<VecDeque<Instant>>>,
+    /// URLs already fetched or attempted, so repeated mentions fetch once
+    seen: Arc<Mutex<HashSet<String>>>,
+}
+
+impl UrlDownloader {
+    pub fn new(config: UrlDownloadConfig, runner: SharedRunner) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(config.max_concurrent.max(1))),
+            config,
+            runner,
+            recent: Arc::new(Mutex::new(VecDeque::new())),
+            seen: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Download the image at `url` to a temporary file; the caller removes it when done
+    pub async fn download(&self, url: &str) -> Result<PathBuf> {
+        let (host, port) = target_of(url).ok_or_else(|| Error::InvalidInput(format!("Not an http(s) URL: {}", url)))?;
+        if !self.config.is_permitted(&host) {
+            return Err(Error::Permission(format!("Downloads from {} are not allowed", host)));
+        }
+        if !lock(&self.seen).insert(url.to_string()) {
+            return Err(Error::InvalidInput(format!("{} was already downloaded", url)));
+        }
+        self.take_rate_slot()?;
+
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .map_err(|_| Error::Internal("Download limiter closed".to_string()))?;
+        if !self.runner.is_available("curl") {
+            return Err(Error::Unsupported("curl is needed to download image URLs".to_string()));
+        }
+
+        let addr = self.resolve(&host, port).await?;
+
+        // Removed if the download fails
+        let dest = temp_files::create("url-", "")?;
+        self.fetch(url, &host, addr, &dest).await?;
+        Ok(dest.keep())
+    }
+
+    /// The address to fetch from `host`, refused when any of its addresses is
+    /// internal and it isn't explicitly allowed
+    async fn resolve(&self, host: &str, port: u16) -> Result<SocketAddr> {
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|e| Error::Network(format!("Failed to resolve {}: {}", host, e)))?
+            .collect();
+        if !self.config.is_allowed(host) && addrs.iter().any(|addr| is_internal_addr(addr.ip())) {
+            return Err(Error::Permission(format!("{} resolves to an internal address", host)));
+        }
+        addrs
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::Network(format!("{} has no addresses", host)))
+    }
+
+    async fn fetch(&self, url: &str, host: &str, addr: SocketAddr, dest: &std::path::Path) -> Result<()> {
+        let timeout = self.config.timeout_secs.to_string();
+        let max_size = self.config.max_file_size.to_string();
+        let dest_arg = dest.to_string_lossy();
+        // curl connects to the address vetted above rather than looking the host up again
+        let pinned = match addr {
+            SocketAddr::V4(v4) => format!("{}:{}:{}", host, v4.port(), v4.ip()),
+            SocketAddr::V6(v6) => format!("{}:{}:[{}]", host, v6.port(), v6.ip()),
+        };
+        let args: &[&str] = &[
+            "--silent", "--show-error", "--fail",
+            "--proto", "=http,https",
+            "--max-redirs", "0",
+            // A proxy would make its own connection, skipping the address checked here
+            "--noproxy", "*",
+            "--resolve", &pinned,
+            "--max-time", &timeout,
+            "--max-filesize", &max_size,
+            "--output", &dest_arg,
+            "--", url,
+        ];
+
+        debug!("Downloading {}", url);
+        let output = self
+            .runner
+            .run("curl", args, None)
+            .await
+            .map_err(|e| Error::Process(format!("Failed to run curl: {}", e)))?;
+        if !output.success {
+            return Err(Error::Process(format!("Downloading {} failed: {}", url, output.stderr_lossy().trim())));
+        }
+
+        // --max-filesize can't stop a download whose server doesn't announce its size
+        let size = tokio::fs::metadata(dest).await?.len();
+        if size > self.config.max_file_size {
+            return Err(Error::InvalidInput(format!(
+                "{} is larger than the {} download limit",
+                url,
+                crate::format_file_size(self.config.max_file_size)
+            )));
+        }
+
+        let mut header = [0u8; 32];
+        let read = std::io::Read::read(&mut std::fs::File::open(dest)?, &mut header)?;
+        if image::guess_format(&header[..read]).is_err() {
+            return Err(Error::Format(format!("{} is not an image", url)));
+        }
+        Ok(())
+    }
+
+    /// Count a download against the per-minute limit, or refuse it
+    fn take_rate_slot(&self) -> Result<()> {
+        let now = Instant::now();
+        let mut recent = lock(&self.recent);
+        while recent.front().is_some_and(|start| now.duration_since(*start) >= RATE_WINDOW) {
+            recent.pop_front();
+        }
+        if recent.len() >= self.config.max_per_minute {
+            return Err(Error::Permission(format!(
+                "More than {} image downloads a minute, skipping",
+                self.config.max_per_minute
+            )));
+        }
+        recent.push_back(now);
+        Ok(())
+    }
+}
+
+/// Lowercase host of an http(s) URL, without userinfo or port
+pub fn host_of(url: &str) -> Option<String> {
+    target_of(url).map(|(host, _)| host)
+}
+
+/// Lowercase host and port of an http(s) URL
+fn target_of(url: &str) -> Option<(String, u16)> {
+    let (scheme, rest) = url.split_once("://")?;
+    let default_port = if scheme.eq_ignore_ascii_case("http") {
+        80
+    } else if scheme.eq_ignore_ascii_case("https") {
+        443
+    } else {
+        return None;
+    };
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host_port = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    let (host, port) = match host_port.strip_prefix('[') {
+        // IPv6 literal
+        Some(bracketed) => {
+            let (host, after) = bracketed.split_once(']')?;
+            (host, after.strip_prefix(':'))
+        }
+        None => match host_port.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (host_port, None),
+        },
+    };
+    let port = match port {
+        Some("") | None => default_port,
+        Some(port) => port.parse().ok()?,
+    };
+    (!host.is_empty()).then(|| (host.trim_end_matches('.').to_ascii_lowercase(), port))
+}
+
+/// Whether `host` names this machine or a private network, which output
+/// being monitored should never be able to reach
+fn is_internal(host: &str) -> bool {
+    if host == "localhost" || host.ends_with(".localhost") || host.ends_with(".local") {
+        return true;
+    }
+    match host.parse::<IpAddr>().ok().or_else(|| parse_ipv4_number(host).map(IpAddr::V4)) {
+        Some(ip) => is_internal_addr(ip),
+        None => false,
+    }
+}
+
+/// Whether `ip` isn't a public address: loopback, private, link-local,
+/// shared (CGNAT), `0.0.0.0/8` or otherwise not globally routed, including
+/// IPv4 addresses carried in IPv6 forms that reach them
+fn is_internal_addr(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || a == 0
+                || (a == 100 && (b & 0xc0) == 64)
+                || (a == 198 && (b & 0xfe) == 18)
+                || a >= 240
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            // IPv4-mapped `::ffff:a.b.c.d` and the deprecated IPv4-compatible `::a.b.c.d`
+            if let Some(v4) = ip.to_ipv4() {
+                return is_internal_addr(IpAddr::V4(v4));
+            }
+            // NAT64 hands the embedded IPv4 address on; its local-use prefix is never public
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                return is_internal_addr(IpAddr::V4(Ipv4Addr::from(u128::from(ip) as u32)));
+            }
+            let first = segments[0];
+            (segments[0], segments[1]) == (0x64, 0xff9b)
+                || ip.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/// IPv4 addresses in the other forms curl accepts: one to four parts, each
+/// decimal, hex (`0x7f`) or octal (`0177`), the last filling the remaining
+/// bytes (`127.1`, `2130706433`)
+fn parse_ipv4_number(host: &str) -> Option<Ipv4Addr> {
+    let parts: Vec<&str> = host.split('.').collect();
+    if parts.len() > 4 {
+        return None;
+    }
+    let mut addr: u32 = 0;
+    for (i, part) in parts.iter().enumerate() {
+        let value = if let Some(hex) = part.strip_prefix("0x").or_else(|| part.strip_prefix("0X")) {
+            u32::from_str_radix(hex, 16).ok()?
+        } else if part.len() > 1 && part.starts_with('0') {
+            u32::from_str_radix(&part[1..], 8).ok()?
+        } else {
+            part.parse().ok()?
+        };
+        if i + 1 < parts.len() {
+            if value > 0xff {
+                return None;
+            }
+            addr |= value << (8 * (3 - i));
+        } else {
+            let remaining = 8 * (4 - i as u32);
+            if remaining < 32 && value >> remaining != 0 {
+                return None;
+            }
+            addr |= value;
+        }
+    }
+    Some(Ipv4Addr::from(addr))
+}
+
+impl UrlDownloadConfig {
+    /// Whether images may be downloaded from `host`; the deny list wins, and
+    /// internal hosts are only reachable when explicitly allowed
+    pub fn is_permitted(&self, host: &str) -> bool {
+        let allowed = self.is_allowed(host);
+        if self.deny_domains.iter().any(|domain| crate::matches_domain(host, domain)) {
+            return false;
+        }
+        if is_internal(host) {
+            return allowed;
+        }
+        allowed || self.allow_domains.is_empty()
+    }
+
+    /// Whether `host` is on the allow list
+    fn is_allowed(&self, host: &str) -> bool {
+        self.allow_domains.iter().any(|domain| crate::matches_domain(host, domain))
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+
+    #[test]
+    fn test_host_of() {
+        assert_eq!(host_of("https://Example.COM/a.png").as_deref(), Some("example.com"));
+        assert_eq!(host_of("http://user:pw@cdn.example.com:8080/x.png?y=1").as_deref(), Some("cdn.example.com"));
+        assert_eq!(host_of("http://[::1]:80/a.png").as_deref(), Some("::1"));
+        assert_eq!(host_of("file:///etc/passwd"), None);
+        assert_eq!(target_of("https://example.com/a.png"), Some(("example.com".to_string(), 443)));
+        assert_eq!(target_of("http://[::1]:8080/a.png"), Some(("::1".to_string(), 8080)));
+    }
+
+    #[test]
+    fn test_internal_address_forms() {
+        let config = UrlDownloadConfig::default();
+        for host in [
+            "2130706433", "0x7f.1", "127.1", "0177.0.0.1", "0x7f000001", "10.1", "::ffff:127.0.0.1", "::ffff:a00:1", "fe80::1",
+            "100.64.0.1", "100.127.255.254", "0.1.2.3", "::127.0.0.1", "::a9fe:a9fe", "64:ff9b::a9fe:a9fe", "64:ff9b:1::5db8:d822",
+            "::", "::1",
+        ] {
+            assert!(!config.is_permitted(host), "{} should be internal", host);
+        }
+        assert!(config.is_permitted("100.128.0.1"));
+        assert!(config.is_permitted("64:ff9b::5db8:d822"));
+        assert!(config.is_permitted("2606:2800:220:1:248:1893:25c8:1946"));
+        assert_eq!(parse_ipv4_number("3232235777"), Some(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(parse_ipv4_number("1.2.3.4.5"), None);
+        assert_eq!(parse_ipv4_number("1.256.1"), None);
+        assert_eq!(parse_ipv4_number("example"), None);
+        assert!(config.is_permitted("93.184.216.34"));
+        assert!(config.is_permitted("1234.example.com"));
+    }
+
+    #[test]
+    fn test_domain_lists() {
+        let mut config = UrlDownloadConfig {
+            deny_domains: vec!["tracker.example.com".to_string()],
+            ..UrlDownloadConfig::default()
+        };
+        assert!(config.is_permitted("imgur.com"));
+        assert!(!config.is_permitted("tracker.example.com"));
+        assert!(!config.is_permitted("localhost"));
+        assert!(!config.is_permitted("192.168.1.10"));
+
+        config.allow_domains = vec!["*.githubusercontent.com".to_string(), "127.0.0.1".to_string()];
+        assert!(config.is_permitted("raw.githubusercontent.com"));
+        assert!(!config.is_permitted("evilgithubusercontent.com"));
+        assert!(!config.is_permitted("imgur.com"));
+        assert!(config.is_permitted("127.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn test_download_limits() {
+        let runner = Arc::new(FakeRunner::new().with_output("curl", CommandOutput::failed("404")));
+        let config = UrlDownloadConfig {
+            max_per_minute: 2,
+            ..UrlDownloadConfig::default()
+        };
+        let downloader = UrlDownloader::new(config, runner.clone());
+
+        assert_eq!(downloader.download("http://localhost/a.png").await.unwrap_err().error_code(), "PERMISSION");
+        assert_eq!(downloader.download("http://[::ffff:7f00:1]/a.png").await.unwrap_err().error_code(), "PERMISSION");
+        // What a host resolves to is checked too
+        assert_eq!(downloader.resolve("2130706433", 80).await.unwrap_err().error_code(), "PERMISSION");
+        assert!(downloader.download("https://93.184.216.34/a.png").await.is_err());
+        // The same URL isn't fetched again
+        assert!(downloader.download("https://93.184.216.34/a.png").await.is_err());
+        assert!(downloader.download("https://93.184.216.34/b.png").await.is_err());
+        assert!(downloader.download("https://93.184.216.34/c.png").await.is_err());
+        assert_eq!(runner.calls_to("curl").len(), 2);
+
+        let args = &runner.calls_to("curl")[0].args;
+        assert!(args.windows(2).any(|pair| pair == ["--max-redirs", "0"]));
+        assert!(args.windows(2).any(|pair| pair == ["--noproxy", "*"]));
+        assert!(args.windows(2).any(|pair| pair == ["--resolve", "93.184.216.34:443:93.184.216.34"]));
+        assert_eq!(args.last().map(String::as_str), Some("https://93.184.216.34/a.png"));
+    }
+}
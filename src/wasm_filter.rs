@@ -0,0 +1,294 @@
+//! User-supplied WebAssembly filters, run on each image after it's encoded
+//! and before it's stored, for redaction, branding or format steps.
+//!
+//! Every `.wasm` (or `.wat`) module in the filters directory is run in name
+//! order, each on the previous one's output. A module imports nothing and
+//! exports:
+//!
+//! - `memory`
+//! - `klipdot_alloc(len: i32) -> i32`, returning `len` bytes of its memory
+//!   the input can be written to
+//! - `klipdot_filter(image: i32, image_len: i32, meta: i32, meta_len: i32) -> i64`
+//!
+//! The image is the encoded file and the metadata is JSON (see
+//! [`FilterMetadata`]). `klipdot_filter` returns 0 to keep the image as it is,
+//! a negative number to reject it so it isn't stored, or the new image as
+//! `offset << 32 | length` in its memory. A filter that fails, runs out of
+//! fuel or returns something that isn't an image is skipped with a warning,
+//! so a broken filter never loses a capture.
+
+use crate::{config::FiltersConfig, error::Result, error_history, Error};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tracing::{debug, warn};
+use wasmtime::{Engine, Instance, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+/// What filters are told about the image
+#[derive(Debug, Clone, Serialize)]
+pub struct FilterMetadata {
+    pub source: String,
+    pub app: Option<String>,
+    pub output: Option<String>,
+    /// Extension of the encoded image, such as `png`
+    pub format: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The image after all filters ran
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filtered {
+    Kept(Vec<u8>),
+    /// Rejected by the named filter
+    Rejected(String),
+}
+
+/// What one filter did
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Unchanged,
+    Replaced(Vec<u8>),
+    Rejected,
+}
+
+/// Run the filters `settings` names on `data`
+pub async fn apply(settings: &FiltersConfig, data: Vec<u8>, metadata: &FilterMetadata) -> Result<Filtered> {
+    if !settings.enabled {
+        return Ok(Filtered::Kept(data));
+    }
+    let dir = match &settings.dir {
+        Some(dir) => dir.clone(),
+        None => crate::get_home_dir()?.join(crate::FILTERS_DIR),
+    };
+    let filters = modules_in(&dir);
+    if filters.is_empty() {
+        return Ok(Filtered::Kept(data));
+    }
+
+    let settings = settings.clone();
+    let metadata = serde_json::to_vec(metadata)?;
+    tokio::task::spawn_blocking(move || {
+        let mut data = data;
+        for path in filters {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            match run_file(&path, &data, &metadata, &settings) {
+                Ok(Outcome::Unchanged) => debug!("Filter {} kept the image", name),
+                Ok(Outcome::Replaced(filtered)) => {
+                    debug!("Filter {} replaced the image ({} bytes)", name, filtered.len());
+                    data = filtered;
+                }
+                Ok(Outcome::Rejected) => return Ok(Filtered::Rejected(name)),
+                Err(e) => {
+                    warn!("Skipping filter {}: {}", name, e);
+                    error_history::record_error("filter", &e);
+                }
+            }
+        }
+        Ok(Filtered::Kept(data))
+    })
+    .await
+    .map_err(|e| Error::Internal(format!("Task join error: {}", e)))?
+}
+
+/// Filter modules in `dir`, in name order
+fn modules_in(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut modules: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "wasm" || ext == "wat"))
+        .collect();
+    modules.sort();
+    modules
+}
+
+/// The engine all filters run on, metering fuel
+static ENGINE: Lazy<Engine> = Lazy::new(|| {
+    let mut config = wasmtime::Config::new();
+    config.consume_fuel(true);
+    Engine::new(&config).expect("fuel metering is supported on every target")
+});
+
+/// Compiled modules, with the modification time of the file they were compiled from
+static MODULES: Lazy<Mutex<HashMap<PathBuf, (SystemTime, Module)>>> = Lazy::new(Default::default);
+
+/// The compiled module at `path`, compiling it again only once it changes
+fn load(path: &Path) -> Result<Module> {
+    let modified = std::fs::metadata(path)?.modified()?;
+    let mut modules = MODULES.lock().unwrap_or_else(|p| p.into_inner());
+    if let Some((compiled_at, module)) = modules.get(path) {
+        if *compiled_at == modified {
+            return Ok(module.clone());
+        }
+    }
+    let module = Module::from_file(&ENGINE, path).map_err(|e| Error::Process(format!("Invalid filter module: {:#}", e)))?;
+    modules.insert(path.to_path_buf(), (modified, module.clone()));
+    Ok(module)
+}
+
+fn run_file(path: &Path, image: &[u8], metadata: &[u8], settings: &FiltersConfig) -> Result<Outcome> {
+    let outcome = run(&load(path)?, image, metadata, settings)?;
+    if let Outcome::Replaced(filtered) = &outcome {
+        if image::guess_format(filtered).is_err() {
+            return Err(Error::Process("Filter returned something that isn't an image".to_string()));
+        }
+    }
+    Ok(outcome)
+}
+
+fn run(module: &Module, image: &[u8], metadata: &[u8], settings: &FiltersConfig) -> Result<Outcome> {
+    let failed = |e: wasmtime::Error| Error::Process(format!("{:#}", e));
+    let limits = StoreLimitsBuilder::new()
+        .memory_size(settings.max_memory_mb as usize * 1024 * 1024)
+        .build();
+    let mut store: Store<StoreLimits> = Store::new(&ENGINE, limits);
+    store.limiter(|limits| limits);
+    store.set_fuel(settings.fuel).map_err(failed)?;
+
+    let instance = Instance::new(&mut store, module, &[]).map_err(failed)?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .ok_or_else(|| Error::Process("Filter doesn't export its memory".to_string()))?;
+    let alloc = instance.get_typed_func::<i32, i32>(&mut store, "klipdot_alloc").map_err(failed)?;
+    let filter = instance
+        .get_typed_func::<(i32, i32, i32, i32), i64>(&mut store, "klipdot_filter")
+        .map_err(failed)?;
+
+    let mut pass = |bytes: &[u8]| -> Result<(i32, i32)> {
+        let len = i32::try_from(bytes.len()).map_err(|_| Error::InvalidInput("Image too large for a filter".to_string()))?;
+        let offset = alloc.call(&mut store, len).map_err(failed)?;
+        memory.write(&mut store, offset as u32 as usize, bytes).map_err(|e| Error::Process(e.to_string()))?;
+        Ok((offset, len))
+    };
+    let (image_offset, image_len) = pass(image)?;
+    let (meta_offset, meta_len) = pass(metadata)?;
+
+    let result = filter
+        .call(&mut store, (image_offset, image_len, meta_offset, meta_len))
+        .map_err(failed)?;
+    if result < 0 {
+        return Ok(Outcome::Rejected);
+    }
+    if result == 0 {
+        return Ok(Outcome::Unchanged);
+    }
+    let (offset, len) = ((result >> 32) as usize, (result & 0xffff_ffff) as usize);
+    // Checked before allocating, so a bogus length can't make the daemon allocate gigabytes
+    let Some(bytes) = memory.data(&store).get(offset..).and_then(|rest| rest.get(..len)) else {
+        return Err(Error::Process(format!(
+            "Filter returned {} bytes at {}, outside its {} bytes of memory",
+            len,
+            offset,
+            memory.data_size(&store)
+        )));
+    };
+    Ok(Outcome::Replaced(bytes.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A filter whose `klipdot_filter` has `body` with the four inputs as locals 0 to 3
+    fn filter_module(body: &str) -> String {
+        format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (global $next (mut i32) (i32.const 0))
+                (func (export "klipdot_alloc") (param $len i32) (result i32)
+                  (local $offset i32)
+                  (local.set $offset (global.get $next))
+                  (global.set $next (i32.add (global.get $next) (local.get $len)))
+                  (local.get $offset))
+                (func (export "klipdot_filter") (param i32 i32 i32 i32) (result i64)
+                  {}))"#,
+            body
+        )
+    }
+
+    /// `offset << 32 | length` of input `n` (0 for the image, 2 for the metadata)
+    fn returning_input(n: u32) -> String {
+        filter_module(&format!(
+            "(i64.or (i64.shl (i64.extend_i32_u (local.get {})) (i64.const 32)) (i64.extend_i32_u (local.get {})))",
+            n,
+            n + 1
+        ))
+    }
+
+    fn metadata() -> Vec<u8> {
+        let metadata = FilterMetadata {
+            source: "clipboard".to_string(),
+            app: Some("firefox".to_string()),
+            output: None,
+            format: "png".to_string(),
+            width: 4,
+            height: 4,
+        };
+        serde_json::to_vec(&metadata).unwrap()
+    }
+
+    #[test]
+    fn test_filter_interface() {
+        let settings = FiltersConfig::default();
+        let compile = |wat: &str| Module::new(&ENGINE, wat).unwrap();
+
+        let keep = compile(&filter_module("(i64.const 0)"));
+        assert_eq!(run(&keep, b"image", &metadata(), &settings).unwrap(), Outcome::Unchanged);
+        let reject = compile(&filter_module("(i64.const -1)"));
+        assert_eq!(run(&reject, b"image", &metadata(), &settings).unwrap(), Outcome::Rejected);
+
+        // Filters get the image and its metadata, and hand back bytes from their memory
+        let echo = compile(&returning_input(0));
+        assert_eq!(run(&echo, b"image", &metadata(), &settings).unwrap(), Outcome::Replaced(b"image".to_vec()));
+        let Outcome::Replaced(seen) = run(&compile(&returning_input(2)), b"image", &metadata(), &settings).unwrap() else {
+            panic!("expected the metadata back");
+        };
+        let seen: serde_json::Value = serde_json::from_slice(&seen).unwrap();
+        assert_eq!((seen["app"].as_str(), seen["width"].as_u64()), (Some("firefox"), Some(4)));
+
+        // Lengths past the end of the filter's memory are refused, not allocated
+        let bogus = compile(&filter_module("(i64.const 0xffffffff)"));
+        assert!(matches!(run(&bogus, b"image", &metadata(), &settings), Err(Error::Process(_))));
+
+        // Runaway filters are stopped
+        let spin = compile(&filter_module("(loop $forever (br $forever)) (i64.const 0)"));
+        let limited = FiltersConfig { fuel: 10_000, ..FiltersConfig::default() };
+        assert!(run(&spin, b"image", &metadata(), &limited).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_apply_in_order() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let settings = FiltersConfig { dir: Some(temp_dir.path().to_path_buf()), ..FiltersConfig::default() };
+        let metadata: FilterMetadata = FilterMetadata {
+            source: "stdin".to_string(),
+            app: None,
+            output: None,
+            format: "png".to_string(),
+            width: 1,
+            height: 1,
+        };
+        let png = b"\x89PNG\r\n\x1a\nrest".to_vec();
+        assert_eq!(apply(&settings, png.clone(), &metadata).await.unwrap(), Filtered::Kept(png.clone()));
+
+        // A filter returning something that isn't an image is skipped
+        std::fs::write(temp_dir.path().join("10-metadata.wat"), returning_input(2)).unwrap();
+        std::fs::write(temp_dir.path().join("20-echo.wat"), returning_input(0)).unwrap();
+        std::fs::write(temp_dir.path().join("notes.txt"), "not a filter").unwrap();
+        assert_eq!(apply(&settings, png.clone(), &metadata).await.unwrap(), Filtered::Kept(png.clone()));
+
+        std::fs::write(temp_dir.path().join("30-reject.wat"), filter_module("(i64.const -1)")).unwrap();
+        assert_eq!(
+            apply(&settings, png.clone(), &metadata).await.unwrap(),
+            Filtered::Rejected("30-reject.wat".to_string())
+        );
+        let disabled = FiltersConfig { enabled: false, ..settings };
+        assert_eq!(apply(&disabled, png.clone(), &metadata).await.unwrap(), Filtered::Kept(png));
+    }
+}
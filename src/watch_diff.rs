@@ -0,0 +1,284 @@
+//! `klipdot watch-diff`: re-run a command that renders an image (a plot, a
+//! render) whenever the files around it change, and preview only what
+//! changed since the previous run.
+//!
+//! The image is the one given with `--output`, else the last existing image
+//! path the command printed, else the newest image in the working directory
+//! written during the run. The first run previews the whole image; later runs
+//! preview the changed area of an [`image_diff`](crate::image_diff) with
+//! unchanged pixels dimmed. Filesystem events the run itself causes, and
+//! changes to the image, don't trigger another run.
+
+use crate::{
+    command_runner::{self, SharedRunner},
+    config::{Config, DecodeLimits},
+    decode_guard,
+    error::Result,
+    image_diff::{self, ImageDiff},
+    image_preview::ImagePreviewManager,
+    output, temp_files, Error,
+};
+use image::RgbaImage;
+use notify::{RecursiveMode, Watcher};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+/// How long to wait for a burst of filesystem events, such as an editor's
+/// save, to finish before re-running
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Pixels kept around the changed area in previews
+const DIFF_MARGIN: u32 = 16;
+
+/// Directories whose changes never trigger a run
+const IGNORED_DIRS: &[&str] = &[".git", "target", "node_modules", "__pycache__"];
+
+/// What one run produced
+#[derive(Debug)]
+pub enum RunOutcome {
+    /// The first image, at this path
+    First(PathBuf),
+    /// A later image and how it differs from the one before
+    Changed(ImageDiff),
+    /// The command failed or its image couldn't be found or read
+    Failed(String),
+}
+
+pub struct WatchDiff {
+    command: Vec<String>,
+    /// Image given with `--output`, or the one found on the first run
+    output: Option<PathBuf>,
+    explicit_output: bool,
+    runner: SharedRunner,
+    previous: Option<RgbaImage>,
+}
+
+impl WatchDiff {
+    pub fn new(command: Vec<String>, output: Option<PathBuf>) -> Result<Self> {
+        if command.is_empty() {
+            return Err(Error::InvalidInput("watch-diff needs a command to run".to_string()));
+        }
+        Ok(Self {
+            command,
+            explicit_output: output.is_some(),
+            output,
+            runner: command_runner::system(),
+            previous: None,
+        })
+    }
+
+    /// Replace the runner the command is run with
+    pub fn set_command_runner(&mut self, runner: SharedRunner) {
+        self.runner = runner;
+    }
+
+    /// The image being watched, once known
+    pub fn output(&self) -> Option<&Path> {
+        self.output.as_deref()
+    }
+
+    /// Run the command once, passing its output through, and compare its
+    /// image, decoded within `limits`, with the last run's
+    pub async fn run_once(&mut self, limits: &DecodeLimits) -> RunOutcome {
+        let started = SystemTime::now();
+        let args: Vec<&str> = self.command[1..].iter().map(String::as_str).collect();
+        let result = match self.runner.run(&self.command[0], &args, None).await {
+            Ok(result) => result,
+            Err(e) => return RunOutcome::Failed(format!("Failed to run {}: {}", self.command[0], e)),
+        };
+        let _ = std::io::stdout().write_all(&result.stdout);
+        let _ = std::io::stderr().write_all(&result.stderr);
+        if !result.success {
+            return RunOutcome::Failed(format!("{} failed", self.command.join(" ")));
+        }
+
+        if !self.explicit_output {
+            let cwd = std::env::current_dir().unwrap_or_default();
+            if let Some(found) = printed_image(&result.stdout_lossy(), &cwd).or_else(|| newest_image(&cwd, started)) {
+                self.output = Some(found);
+            }
+        }
+        let Some(path) = self.output.clone() else {
+            return RunOutcome::Failed("No image found; name it with --output".to_string());
+        };
+
+        let current = match decode_guard::open(&path, limits) {
+            Ok(img) => img.to_rgba8(),
+            Err(e) => return RunOutcome::Failed(format!("Can't read {}: {}", path.display(), e)),
+        };
+        let outcome = match &self.previous {
+            None => RunOutcome::First(path),
+            Some(previous) => RunOutcome::Changed(image_diff::diff(previous, &current, image_diff::DEFAULT_TOLERANCE)),
+        };
+        self.previous = Some(current);
+        outcome
+    }
+
+    /// Run the command now and again after every change under `watch`,
+    /// previewing each outcome, until interrupted
+    pub async fn run(&mut self, config: &Config, watch: &[PathBuf]) -> Result<()> {
+        let previews = ImagePreviewManager::new(config.clone()).await?;
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = sender.send(event);
+        })?;
+        for path in watch {
+            watcher.watch(path, RecursiveMode::Recursive)?;
+        }
+
+        let mut run = 1;
+        loop {
+            let outcome = self.run_once(&config.decode_limits).await;
+            show(&previews, config, run, outcome).await;
+
+            // Whatever the run itself wrote isn't a reason to run again
+            while receiver.try_recv().is_ok() {}
+            loop {
+                let Some(event) = receiver.recv().await else {
+                    return Ok(());
+                };
+                match event {
+                    Ok(event) if !event.kind.is_access() && event.paths.iter().any(|path| self.triggers(path)) => break,
+                    Ok(_) => {}
+                    Err(e) => warn!("watch-diff watcher error: {}", e),
+                }
+            }
+            tokio::time::sleep(DEBOUNCE).await;
+            while receiver.try_recv().is_ok() {}
+            run += 1;
+        }
+    }
+
+    /// Whether a change to `path` should re-run the command
+    fn triggers(&self, path: &Path) -> bool {
+        let ignored = path.components().any(|component| match component {
+            Component::Normal(name) => IGNORED_DIRS.iter().any(|dir| name == *dir),
+            _ => false,
+        });
+        let is_output = self.output.as_deref().is_some_and(|output| same_file(output, path));
+        !ignored && !is_output
+    }
+}
+
+async fn show(previews: &ImagePreviewManager, config: &Config, run: usize, outcome: RunOutcome) {
+    let (width, height) = config.preview.size();
+    let result = match outcome {
+        RunOutcome::First(path) => {
+            output::status("🖼️ ", format!("Run {}: {}", run, path.display()));
+            previews.show_preview(&path, width, height).await
+        }
+        RunOutcome::Changed(diff) => {
+            output::status("🔍", format!("Run {}: {}", run, diff.summary()));
+            match diff.changed_area(DIFF_MARGIN) {
+                Some(area) => preview_image(previews, &area, width, height).await,
+                None => Ok(()),
+            }
+        }
+        RunOutcome::Failed(message) => {
+            output::status("❌", format!("Run {}: {}", run, message));
+            Ok(())
+        }
+    };
+    if let Err(e) = result {
+        warn!("Failed to preview run {}: {}", run, e);
+    }
+}
+
+async fn preview_image(previews: &ImagePreviewManager, img: &RgbaImage, width: Option<u32>, height: Option<u32>) -> Result<()> {
+    let temp_file = temp_files::create("diff-", "png")?;
+    img.save(&temp_file)?;
+    previews.show_preview(&temp_file, width, height).await
+}
+
+/// The last existing image file named in `stdout`, relative paths resolved against `cwd`
+fn printed_image(stdout: &str, cwd: &Path) -> Option<PathBuf> {
+    stdout
+        .split(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '`' | '(' | ')' | ','))
+        .map(|word| word.trim_end_matches(['.', ':', ';']))
+        .filter(|word| crate::has_image_extension(Path::new(word)))
+        .map(|word| cwd.join(word))
+        .rfind(|path| crate::is_image_file(path))
+}
+
+/// The most recently modified image in `dir` written since `since`
+fn newest_image(dir: &Path, since: SystemTime) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter_map(|path| {
+            let modified = std::fs::metadata(&path).and_then(|metadata| metadata.modified()).ok()?;
+            (modified >= since).then_some((modified, path))
+        })
+        .filter(|(_, path)| crate::is_image_file(path))
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, path)| path)
+}
+
+/// Whether `a` and `b` name the same file; by name once either is gone
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+    use image::Rgba;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_runs_are_diffed() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let plot = temp_dir.path().join("plot.png");
+        let runner = Arc::new(FakeRunner::new().with_output("render", CommandOutput::ok(format!("wrote {}\n", plot.display()))));
+        let mut watch = WatchDiff::new(vec!["render".to_string(), "--dark".to_string()], None).unwrap();
+        watch.set_command_runner(runner.clone());
+
+        // The command "renders" by way of the test writing the image
+        let mut img = RgbaImage::from_pixel(64, 32, Rgba([255, 255, 255, 255]));
+        img.save(&plot).unwrap();
+        assert!(matches!(watch.run_once(&DecodeLimits::default()).await, RunOutcome::First(path) if path == plot));
+        assert_eq!(runner.calls_to("render")[0].args, ["--dark"]);
+
+        img.put_pixel(10, 20, Rgba([0, 0, 0, 255]));
+        img.save(&plot).unwrap();
+        let RunOutcome::Changed(diff) = watch.run_once(&DecodeLimits::default()).await else { panic!("expected a diff") };
+        assert_eq!(diff.changed, 1);
+
+        let RunOutcome::Changed(diff) = watch.run_once(&DecodeLimits::default()).await else { panic!("expected a diff") };
+        assert_eq!(diff.changed, 0);
+
+        // Its own image doesn't trigger a run, sources do
+        assert!(!watch.triggers(&plot));
+        assert!(watch.triggers(&temp_dir.path().join("plot.py")));
+        assert!(!watch.triggers(&temp_dir.path().join(".git/index")));
+
+        runner.set_output("render", CommandOutput::failed("syntax error"));
+        assert!(matches!(watch.run_once(&DecodeLimits::default()).await, RunOutcome::Failed(_)));
+    }
+
+    #[test]
+    fn test_finding_the_image() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let before = SystemTime::now() - Duration::from_secs(60);
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        std::fs::write(temp_dir.path().join("a.png"), png).unwrap();
+        std::fs::write(temp_dir.path().join("b.png"), png).unwrap();
+        std::fs::write(temp_dir.path().join("c.png"), b"not a png").unwrap();
+        std::fs::write(temp_dir.path().join("notes.txt"), b"text").unwrap();
+
+        let stdout = "Saved figure to 'a.png', see b.png.\nmissing.png c.png";
+        assert_eq!(printed_image(stdout, temp_dir.path()), Some(temp_dir.path().join("b.png")));
+        assert_eq!(printed_image("done", temp_dir.path()), None);
+        assert!(newest_image(temp_dir.path(), before).is_some_and(|path| path.extension().unwrap() == "png"));
+        assert_eq!(newest_image(temp_dir.path(), SystemTime::now() + Duration::from_secs(60)), None);
+        assert!(WatchDiff::new(Vec::new(), None).is_err());
+    }
+}
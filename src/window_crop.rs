@@ -0,0 +1,209 @@
+//! Cropping full-screen captures to the window that was focused when they
+//! were taken (`crop_to_window`), for screenshot tools that only grab the
+//! whole screen.
+//!
+//! The window's geometry is read from the compositor (sway, Hyprland) or
+//! `xdotool` on X11 as the capture is intercepted, together with the outputs'
+//! layout, since focus may have moved by the time the image is processed. A
+//! capture is only cropped when its size matches the whole layout or the
+//! output holding the window; anything else isn't a full-screen grab and is
+//! stored as is. Outputs are in logical coordinates, so the image's size also
+//! gives the scale of HiDPI captures.
+
+use crate::{
+    command_runner::CommandRunner,
+    config::Config,
+    monitors,
+    screenshot::Region,
+    window_target::{self, WindowSelection, WindowTarget},
+    DisplayServer,
+};
+use image::{DynamicImage, GenericImageView};
+use tracing::{debug, info};
+
+/// Largest difference between horizontal and vertical scale still taken as
+/// the same scale, as a fraction
+const SCALE_TOLERANCE: f64 = 0.01;
+
+/// The focused window and the outputs it was laid out on, in logical pixels
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowGeometry {
+    pub window: Region,
+    pub outputs: Vec<Region>,
+}
+
+/// Geometry to crop an image from `source` with, read now; `None` when
+/// cropping is off for `source` or the window can't be located
+pub async fn geometry_for(config: &Config, runner: &dyn CommandRunner, source: &str) -> Option<WindowGeometry> {
+    if !config.crop_to_window.applies_to(source) {
+        return None;
+    }
+    focused_geometry(runner, config.get_display_server()).await
+}
+
+/// Geometry of the focused window and the outputs
+pub async fn focused_geometry(runner: &dyn CommandRunner, display_server: DisplayServer) -> Option<WindowGeometry> {
+    let window = match display_server {
+        DisplayServer::X11 => x11_active_window(runner).await?,
+        _ => match window_target::locate(runner, display_server, WindowSelection::Active).await.ok()?? {
+            WindowTarget::Region(region) => region,
+            WindowTarget::Id(_) => return None,
+        },
+    };
+    let outputs = monitors::list_monitors(runner, display_server)
+        .await
+        .ok()?
+        .into_iter()
+        .map(|monitor| monitor.region)
+        .collect();
+
+    let geometry = WindowGeometry { window, outputs };
+    debug!("Focused window geometry: {:?}", geometry);
+    Some(geometry)
+}
+
+/// Geometry of the active X11 window, from `xdotool getwindowgeometry --shell`
+async fn x11_active_window(runner: &dyn CommandRunner) -> Option<Region> {
+    if !runner.is_available("xdotool") {
+        return None;
+    }
+    let output = runner.run("xdotool", &["getactivewindow", "getwindowgeometry", "--shell"], None).await.ok()?;
+    if !output.success {
+        return None;
+    }
+    parse_xdotool_geometry(&output.stdout_lossy())
+}
+
+fn parse_xdotool_geometry(output: &str) -> Option<Region> {
+    let value = |key: &str| {
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .map(str::trim)
+    };
+    let region = Region {
+        x: value("X")?.parse().ok()?,
+        y: value("Y")?.parse().ok()?,
+        width: value("WIDTH")?.parse().ok()?,
+        height: value("HEIGHT")?.parse().ok()?,
+    };
+    (region.width > 0 && region.height > 0).then_some(region)
+}
+
+/// Part of a `width` × `height` capture showing the window, as `(x, y, width,
+/// height)` in image pixels; `None` when the capture isn't of the whole
+/// layout or the window's output, or the window fills it anyway
+pub fn crop_rect(geometry: &WindowGeometry, (width, height): (u32, u32)) -> Option<(u32, u32, u32, u32)> {
+    let window = &geometry.window;
+    let center = (window.x as i64 + window.width as i64 / 2, window.y as i64 + window.height as i64 / 2);
+    let holds_window = |region: &&Region| {
+        (region.x as i64..region.x as i64 + region.width as i64).contains(&center.0)
+            && (region.y as i64..region.y as i64 + region.height as i64).contains(&center.1)
+    };
+
+    // The whole layout first, as grim and scrot capture it without options
+    let candidates = bounding_box(&geometry.outputs).into_iter().chain(geometry.outputs.iter().filter(holds_window).copied());
+    let (screen, scale) = candidates.filter(|screen| holds_window(&screen)).find_map(|screen| {
+        let scale_x = width as f64 / screen.width as f64;
+        let scale_y = height as f64 / screen.height as f64;
+        ((scale_x - scale_y).abs() <= SCALE_TOLERANCE * scale_x).then_some((screen, scale_x))
+    })?;
+
+    let left = (window.x as i64 - screen.x as i64).max(0) as f64 * scale;
+    let top = (window.y as i64 - screen.y as i64).max(0) as f64 * scale;
+    let right = ((window.x as i64 + window.width as i64 - screen.x as i64) as f64 * scale).min(width as f64);
+    let bottom = ((window.y as i64 + window.height as i64 - screen.y as i64) as f64 * scale).min(height as f64);
+
+    let rect = (left.round() as u32, top.round() as u32, (right - left).round() as u32, (bottom - top).round() as u32);
+    (rect.2 > 0 && rect.3 > 0 && (rect.2, rect.3) != (width, height)).then_some(rect)
+}
+
+fn bounding_box(regions: &[Region]) -> Option<Region> {
+    let left = regions.iter().map(|region| region.x as i64).min()?;
+    let top = regions.iter().map(|region| region.y as i64).min()?;
+    let right = regions.iter().map(|region| region.x as i64 + region.width as i64).max()?;
+    let bottom = regions.iter().map(|region| region.y as i64 + region.height as i64).max()?;
+    Some(Region {
+        x: left as i32,
+        y: top as i32,
+        width: (right - left) as u32,
+        height: (bottom - top) as u32,
+    })
+}
+
+/// `img` cropped to the window when it's a full-screen capture, with
+/// `original` (its size before any downscaling while decoding) cropped in
+/// proportion
+pub fn crop(img: DynamicImage, original: (u32, u32), geometry: &WindowGeometry) -> (DynamicImage, (u32, u32)) {
+    let (width, height) = img.dimensions();
+    let Some((x, y, crop_width, crop_height)) = crop_rect(geometry, (width, height)) else {
+        debug!("{}x{} image isn't a full-screen capture, not cropping", width, height);
+        return (img, original);
+    };
+
+    info!("Cropping {}x{} capture to the focused window at {},{} {}x{}", width, height, x, y, crop_width, crop_height);
+    let original = (
+        (crop_width as u64 * original.0 as u64 / width as u64) as u32,
+        (crop_height as u64 * original.1 as u64 / height as u64) as u32,
+    );
+    (img.crop_imm(x, y, crop_width, crop_height), original)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+
+    fn region(x: i32, y: i32, width: u32, height: u32) -> Region {
+        Region { x, y, width, height }
+    }
+
+    #[test]
+    fn test_crop_rect() {
+        // Two outputs side by side, the right one at 2x
+        let geometry = WindowGeometry {
+            window: region(2020, 100, 800, 600),
+            outputs: vec![region(0, 0, 1920, 1080), region(1920, 0, 1280, 720)],
+        };
+
+        // Capture of the whole layout, at its highest scale
+        assert_eq!(crop_rect(&geometry, (6400, 2160)), Some((4040, 200, 1600, 1200)));
+        // Capture of the window's output
+        assert_eq!(crop_rect(&geometry, (2560, 1440)), Some((200, 200, 1600, 1200)));
+        assert_eq!(crop_rect(&geometry, (1920, 1080)), Some((150, 150, 1200, 900)));
+        // Not a full-screen capture
+        assert_eq!(crop_rect(&geometry, (1920, 1200)), None);
+        assert_eq!(crop_rect(&geometry, (800, 600)), None);
+
+        // Windows reaching off the output are clipped; fullscreen ones aren't cropped
+        let geometry = WindowGeometry { window: region(-10, 500, 400, 900), outputs: vec![region(0, 0, 1920, 1080)] };
+        assert_eq!(crop_rect(&geometry, (1920, 1080)), Some((0, 500, 390, 580)));
+        let geometry = WindowGeometry { window: region(0, 0, 1920, 1080), outputs: vec![region(0, 0, 1920, 1080)] };
+        assert_eq!(crop_rect(&geometry, (1920, 1080)), None);
+    }
+
+    #[test]
+    fn test_crop() {
+        let geometry = WindowGeometry { window: region(10, 20, 30, 40), outputs: vec![region(0, 0, 100, 100)] };
+        let (cropped, original) = crop(DynamicImage::new_rgba8(100, 100), (400, 400), &geometry);
+        assert_eq!(cropped.dimensions(), (30, 40));
+        assert_eq!(original, (120, 160));
+
+        let (kept, original) = crop(DynamicImage::new_rgba8(64, 48), (64, 48), &geometry);
+        assert_eq!((kept.dimensions(), original), ((64, 48), (64, 48)));
+    }
+
+    #[tokio::test]
+    async fn test_x11_geometry() {
+        let runner = FakeRunner::new()
+            .with_output("xdotool", CommandOutput::ok("WINDOW=81788934\nX=50\nY=60\nWIDTH=800\nHEIGHT=600\nSCREEN=0\n"))
+            .with_output("xrandr", CommandOutput::ok("Screen 0: minimum 8 x 8\nHDMI-1 connected primary 1920x1080+0+0 (normal) 527mm x 296mm\n"));
+
+        let geometry = focused_geometry(&runner, DisplayServer::X11).await.unwrap();
+        assert_eq!(geometry, WindowGeometry { window: region(50, 60, 800, 600), outputs: vec![region(0, 0, 1920, 1080)] });
+        assert_eq!(runner.calls_to("xdotool")[0].args, ["getactivewindow", "getwindowgeometry", "--shell"]);
+
+        runner.set_output("xdotool", CommandOutput::failed("XGetWindowProperty failed"));
+        assert_eq!(focused_geometry(&runner, DisplayServer::X11).await, None);
+    }
+}
@@ -0,0 +1,250 @@
+//! Locating the single window `klipdot capture --window` should capture.
+//!
+//! sway and Hyprland report window geometry over IPC, which grim captures as a
+//! region; picking a window hands those rectangles to `slurp`. On X11 the
+//! window id comes from `xdotool` and is captured with `import`, and on macOS
+//! from the window server for `screencapture -l`. Elsewhere the screenshot
+//! tool finds the window itself.
+
+use crate::{command_runner::CommandRunner, error::Result, screenshot::Region, DisplayServer, Error};
+use serde_json::Value;
+use std::str::FromStr;
+use tracing::debug;
+
+/// JXA printing the id of the frontmost normal window; on-screen windows are listed front to back
+const MACOS_FRONT_WINDOW_SCRIPT: &str = r#"ObjC.import("CoreGraphics");
+const windows = ObjC.deepUnwrap(ObjC.castRefToObject($.CGWindowListCopyWindowInfo($.kCGWindowListOptionOnScreenOnly | $.kCGWindowListExcludeDesktopElements, 0)));
+const front = windows.find(w => w.kCGWindowLayer === 0);
+front ? String(front.kCGWindowNumber) : "";"#;
+
+/// Which window to capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WindowSelection {
+    /// The focused window
+    #[default]
+    Active,
+    /// A window the user clicks on
+    Pick,
+}
+
+impl FromStr for WindowSelection {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "" | "active" => Ok(WindowSelection::Active),
+            "pick" => Ok(WindowSelection::Pick),
+            other => Err(Error::InvalidInput(format!("Invalid window '{}', expected 'active' or 'pick'", other))),
+        }
+    }
+}
+
+/// A located window
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WindowTarget {
+    /// Captured as a region of the screen
+    Region(Region),
+    /// Captured by window id
+    Id(String),
+}
+
+/// Locate the selected window, or `None` when the screenshot tool should find it itself
+pub async fn locate(runner: &dyn CommandRunner, display_server: DisplayServer, selection: WindowSelection) -> Result<Option<WindowTarget>> {
+    let target = match display_server {
+        DisplayServer::Wayland => match crate::detect_wayland_compositor().as_deref() {
+            Some("sway") => {
+                let tree = query(runner, "swaymsg", &["-t", "get_tree", "-r"]).await?;
+                let tree: Value = serde_json::from_str(&tree).map_err(|_| Error::Format("Unexpected swaymsg output".to_string()))?;
+                match selection {
+                    WindowSelection::Active => sway_focused_window(&tree),
+                    WindowSelection::Pick => Some(pick_region(runner, &sway_visible_windows(&tree)).await?),
+                }
+            }
+            Some("hyprland") => match selection {
+                WindowSelection::Active => hyprland_window(&parse_json(&query(runner, "hyprctl", &["activewindow", "-j"]).await?)?),
+                WindowSelection::Pick => {
+                    let clients = parse_json(&query(runner, "hyprctl", &["clients", "-j"]).await?)?;
+                    let regions: Vec<Region> = clients
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter(|client| client["mapped"].as_bool() == Some(true) && client["hidden"].as_bool() != Some(true))
+                        .filter_map(hyprland_window)
+                        .collect();
+                    Some(pick_region(runner, &regions).await?)
+                }
+            },
+            _ => return Ok(None),
+        }
+        .map(WindowTarget::Region),
+        DisplayServer::X11 => {
+            let args: &[&str] = match selection {
+                WindowSelection::Active => &["getactivewindow"],
+                WindowSelection::Pick => &["selectwindow"],
+            };
+            Some(WindowTarget::Id(query(runner, "xdotool", args).await?))
+        }
+        // `screencapture -i -w` already lets the user click a window
+        DisplayServer::MacOS if selection == WindowSelection::Pick => return Ok(None),
+        DisplayServer::MacOS => Some(WindowTarget::Id(query(runner, "osascript", &["-l", "JavaScript", "-e", MACOS_FRONT_WINDOW_SCRIPT]).await?)),
+        DisplayServer::Unknown => return Ok(None),
+    };
+
+    debug!("Window to capture: {:?}", target);
+    target
+        .filter(|target| !matches!(target, WindowTarget::Id(id) if id.is_empty()))
+        .map(Some)
+        .ok_or_else(|| Error::NotFound("No window to capture".to_string()))
+}
+
+async fn query(runner: &dyn CommandRunner, program: &str, args: &[&str]) -> Result<String> {
+    if !runner.is_available(program) {
+        return Err(Error::Unsupported(format!("{} is needed to capture a window", program)));
+    }
+
+    let output = runner
+        .run(program, args, None)
+        .await
+        .map_err(|e| Error::Process(format!("Failed to run {}: {}", program, e)))?;
+    if !output.success {
+        return Err(Error::Process(format!("{} failed: {}", program, output.stderr_lossy().trim())));
+    }
+    Ok(output.stdout_lossy().trim().to_string())
+}
+
+fn parse_json(output: &str) -> Result<Value> {
+    serde_json::from_str(output).map_err(|_| Error::Format("Unexpected hyprctl output".to_string()))
+}
+
+/// Let the user click one of `regions` with slurp
+async fn pick_region(runner: &dyn CommandRunner, regions: &[Region]) -> Result<Region> {
+    if !runner.is_available("slurp") {
+        return Err(Error::Unsupported("slurp is needed to pick a window".to_string()));
+    }
+
+    let boxes: String = regions.iter().map(|region| format!("{}\n", region)).collect();
+    let output = runner
+        .run("slurp", &["-r"], Some(boxes.as_bytes()))
+        .await
+        .map_err(|e| Error::Process(format!("Failed to run slurp: {}", e)))?;
+    // slurp exits unsuccessfully when the selection is cancelled
+    if !output.success {
+        return Err(Error::Cancelled);
+    }
+    output.stdout_lossy().parse()
+}
+
+/// Content area of a sway window: its container `rect` plus the `window_rect` offset
+fn sway_window(node: &Value) -> Option<Region> {
+    let (rect, window) = (&node["rect"], &node["window_rect"]);
+    region(
+        rect["x"].as_i64()? + window["x"].as_i64()?,
+        rect["y"].as_i64()? + window["y"].as_i64()?,
+        window["width"].as_u64()?,
+        window["height"].as_u64()?,
+    )
+}
+
+fn sway_children(node: &Value) -> impl Iterator<Item = &Value> {
+    ["nodes", "floating_nodes"]
+        .into_iter()
+        .filter_map(|key| node[key].as_array())
+        .flatten()
+}
+
+/// The focused window in `swaymsg -t get_tree` output
+fn sway_focused_window(tree: &Value) -> Option<Region> {
+    if tree["focused"].as_bool() == Some(true) && tree["pid"].is_u64() {
+        return sway_window(tree);
+    }
+    sway_children(tree).find_map(sway_focused_window)
+}
+
+/// Every visible window in `swaymsg -t get_tree` output
+fn sway_visible_windows(tree: &Value) -> Vec<Region> {
+    let mut windows = Vec::new();
+    if tree["visible"].as_bool() == Some(true) && tree["pid"].is_u64() {
+        windows.extend(sway_window(tree));
+    }
+    for child in sway_children(tree) {
+        windows.extend(sway_visible_windows(child));
+    }
+    windows
+}
+
+/// Geometry of a window from `hyprctl activewindow -j` or `hyprctl clients -j`
+fn hyprland_window(window: &Value) -> Option<Region> {
+    let (at, size) = (&window["at"], &window["size"]);
+    region(at[0].as_i64()?, at[1].as_i64()?, size[0].as_u64()?, size[1].as_u64()?)
+}
+
+fn region(x: i64, y: i64, width: u64, height: u64) -> Option<Region> {
+    let region = Region {
+        x: x.try_into().ok()?,
+        y: y.try_into().ok()?,
+        width: width.try_into().ok()?,
+        height: height.try_into().ok()?,
+    };
+    (region.width > 0 && region.height > 0).then_some(region)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_runner::{CommandOutput, FakeRunner};
+
+    #[test]
+    fn test_window_selection_parsing() {
+        assert_eq!("".parse::<WindowSelection>().unwrap(), WindowSelection::Active);
+        assert_eq!("Pick".parse::<WindowSelection>().unwrap(), WindowSelection::Pick);
+        assert!("desktop".parse::<WindowSelection>().is_err());
+    }
+
+    #[test]
+    fn test_sway_windows() {
+        let tree: Value = serde_json::from_str(r#"{"focused": false, "nodes": [
+            {"focused": false, "visible": true, "pid": 10, "rect": {"x": 0, "y": 0, "width": 960, "height": 1080},
+             "window_rect": {"x": 2, "y": 24, "width": 956, "height": 1054}},
+            {"focused": false, "nodes": [], "floating_nodes": [
+                {"focused": true, "visible": true, "pid": 11, "rect": {"x": 100, "y": 50, "width": 400, "height": 300},
+                 "window_rect": {"x": 0, "y": 0, "width": 400, "height": 300}}
+            ]},
+            {"focused": false, "visible": false, "pid": 12, "rect": {"x": 0, "y": 0, "width": 1, "height": 1},
+             "window_rect": {"x": 0, "y": 0, "width": 1, "height": 1}}
+        ]}"#).unwrap();
+
+        assert_eq!(sway_focused_window(&tree).unwrap().to_string(), "100,50 400x300");
+        let visible: Vec<String> = sway_visible_windows(&tree).iter().map(Region::to_string).collect();
+        assert_eq!(visible, ["2,24 956x1054", "100,50 400x300"]);
+    }
+
+    #[test]
+    fn test_hyprland_window() {
+        let window: Value = serde_json::from_str(r#"{"class": "kitty", "at": [1930, 40], "size": [1900, 1030]}"#).unwrap();
+        assert_eq!(hyprland_window(&window).unwrap().to_string(), "1930,40 1900x1030");
+        assert_eq!(hyprland_window(&serde_json::json!({})), None);
+    }
+
+    #[tokio::test]
+    async fn test_pick_region_with_slurp() {
+        let regions = [Region { x: 0, y: 0, width: 10, height: 10 }, Region { x: 10, y: 0, width: 5, height: 5 }];
+        let runner = FakeRunner::new().with_output("slurp", CommandOutput::ok("10,0 5x5\n"));
+
+        assert_eq!(pick_region(&runner, &regions).await.unwrap(), regions[1]);
+        let calls = runner.calls_to("slurp");
+        assert_eq!(calls[0].stdin.as_deref(), Some(&b"0,0 10x10\n10,0 5x5\n"[..]));
+
+        runner.set_output("slurp", CommandOutput::failed("selection cancelled"));
+        assert_eq!(pick_region(&runner, &regions).await.unwrap_err().error_code(), "CANCELLED");
+    }
+
+    #[tokio::test]
+    async fn test_x11_window_id() {
+        let runner = FakeRunner::new().with_output("xdotool", CommandOutput::ok("81788934\n"));
+        let target = locate(&runner, DisplayServer::X11, WindowSelection::Active).await.unwrap();
+        assert_eq!(target, Some(WindowTarget::Id("81788934".to_string())));
+        assert_eq!(runner.calls_to("xdotool")[0].args, ["getactivewindow"]);
+
+        assert_eq!(locate(&runner, DisplayServer::MacOS, WindowSelection::Pick).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,217 @@
+//! Native access to the Windows clipboard.
+//!
+//! `Get-Clipboard` only ever returns text, so screenshots (Win+Shift+S,
+//! Print Screen) went unnoticed. Built with the `native-clipboard` feature,
+//! the clipboard monitor reads the clipboard through the Win32 API instead:
+//! the registered `PNG` format when an app offers it, else `CF_DIB`, which
+//! every image copy provides and is converted here. Images are put back as
+//! both, and text is written as `CF_UNICODETEXT`. Polls start with the
+//! clipboard's sequence number, so an unchanged clipboard isn't read at all.
+//!
+//! Converting device-independent bitmaps needs no Windows API, so it's
+//! available (and tested) everywhere.
+
+use image::{Rgba, RgbaImage};
+
+/// `BITMAPINFOHEADER`, the smallest header the conversion reads
+const INFO_HEADER_SIZE: usize = 40;
+
+/// `biCompression` for plain pixels
+const BI_RGB: u32 = 0;
+
+/// `biCompression` for pixels laid out by colour masks
+const BI_BITFIELDS: u32 = 3;
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+/// Decode `CF_DIB` data: uncompressed 24- and 32-bit bitmaps, which is what
+/// screenshots are; `None` for anything else
+pub fn dib_to_image(dib: &[u8]) -> Option<RgbaImage> {
+    let header_size = u32_at(dib, 0)? as usize;
+    let width = i32::from_le_bytes(dib.get(4..8)?.try_into().ok()?);
+    let height = i32::from_le_bytes(dib.get(8..12)?.try_into().ok()?);
+    let bit_count = u16_at(dib, 14)?;
+    let compression = u32_at(dib, 16)?;
+    if header_size < INFO_HEADER_SIZE || width <= 0 || height == 0 {
+        return None;
+    }
+
+    let mut pixels_at = header_size;
+    match (compression, bit_count) {
+        (BI_RGB, 24 | 32) => {}
+        (BI_BITFIELDS, 32) => {
+            // Masks follow a plain BITMAPINFOHEADER and are inside the larger ones
+            let masks_at = if header_size == INFO_HEADER_SIZE { header_size } else { INFO_HEADER_SIZE };
+            let masks = (u32_at(dib, masks_at)?, u32_at(dib, masks_at + 4)?, u32_at(dib, masks_at + 8)?);
+            if masks != (0x00ff_0000, 0x0000_ff00, 0x0000_00ff) {
+                return None;
+            }
+            if header_size == INFO_HEADER_SIZE {
+                pixels_at += 12;
+            }
+        }
+        _ => return None,
+    }
+
+    let (width, rows) = (width as u32, height.unsigned_abs());
+    let bytes_per_pixel = bit_count as usize / 8;
+    let stride = (width as usize * bytes_per_pixel).div_ceil(4) * 4;
+    let pixels = dib.get(pixels_at..pixels_at.checked_add(stride.checked_mul(rows as usize)?)?)?;
+
+    // 32-bit bitmaps often leave alpha at zero, meaning opaque
+    let has_alpha = bit_count == 32 && pixels.chunks_exact(4).any(|pixel| pixel[3] != 0);
+    let mut img = RgbaImage::new(width, rows);
+    for (row, line) in pixels.chunks_exact(stride).enumerate() {
+        // Positive heights are stored bottom-up
+        let y = if height > 0 { rows - 1 - row as u32 } else { row as u32 };
+        for (x, pixel) in line.chunks_exact(bytes_per_pixel).take(width as usize).enumerate() {
+            let alpha = if has_alpha { pixel[3] } else { 255 };
+            img.put_pixel(x as u32, y, Rgba([pixel[2], pixel[1], pixel[0], alpha]));
+        }
+    }
+    Some(img)
+}
+
+/// `CF_DIB` data for `img`: a top-down 32-bit `BITMAPINFOHEADER` bitmap
+pub fn image_to_dib(img: &RgbaImage) -> Vec<u8> {
+    let mut dib = Vec::with_capacity(INFO_HEADER_SIZE + img.as_raw().len());
+    dib.extend_from_slice(&(INFO_HEADER_SIZE as u32).to_le_bytes());
+    dib.extend_from_slice(&(img.width() as i32).to_le_bytes());
+    dib.extend_from_slice(&(-(img.height() as i32)).to_le_bytes());
+    dib.extend_from_slice(&1u16.to_le_bytes());
+    dib.extend_from_slice(&32u16.to_le_bytes());
+    dib.extend_from_slice(&BI_RGB.to_le_bytes());
+    dib.extend_from_slice(&(img.as_raw().len() as u32).to_le_bytes());
+    // Resolution and palette sizes
+    dib.extend_from_slice(&[0; 16]);
+    for pixel in img.pixels() {
+        dib.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+    }
+    dib
+}
+
+#[cfg(all(target_os = "windows", feature = "native-clipboard"))]
+mod native {
+    use crate::{error::Result, Error};
+    use clipboard_win::{formats, raw, Clipboard, Getter, Setter};
+    use image::ImageFormat;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// Tries at opening the clipboard while another app has it open
+    const OPEN_ATTEMPTS: usize = 10;
+
+    /// The sequence number [`changed`] last saw
+    static SEEN: AtomicU64 = AtomicU64::new(u64::MAX);
+
+    /// Whether the clipboard changed since the last call; true the first time
+    pub fn changed() -> bool {
+        let sequence = raw::seq_num().map_or(0, |sequence| sequence.get()) as u64;
+        SEEN.swap(sequence, Ordering::Relaxed) != sequence
+    }
+
+    fn open() -> Result<Clipboard> {
+        Clipboard::new_attempts(OPEN_ATTEMPTS).map_err(|e| Error::Clipboard(format!("Failed to open the clipboard: {}", e)))
+    }
+
+    fn png_format() -> Option<u32> {
+        clipboard_win::register_format("PNG").map(|format| format.get())
+    }
+
+    fn read(format: u32) -> Option<Vec<u8>> {
+        if !raw::is_format_avail(format) {
+            return None;
+        }
+        let mut data = Vec::new();
+        formats::RawData(format).read_clipboard(&mut data).ok()?;
+        (!data.is_empty()).then_some(data)
+    }
+
+    /// The clipboard image as PNG, if it holds one
+    pub fn image() -> Result<Option<Vec<u8>>> {
+        let _clipboard = open()?;
+        if let Some(png) = png_format().and_then(read) {
+            return Ok(Some(png));
+        }
+        let Some(img) = read(formats::CF_DIB).as_deref().and_then(super::dib_to_image) else {
+            return Ok(None);
+        };
+        let mut png = Vec::new();
+        img.write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)?;
+        Ok(Some(png))
+    }
+
+    /// The clipboard text, if it holds any
+    pub fn text() -> Result<Option<String>> {
+        let _clipboard = open()?;
+        if !raw::is_format_avail(formats::CF_UNICODETEXT) {
+            return Ok(None);
+        }
+        let mut text = String::new();
+        formats::Unicode
+            .read_clipboard(&mut text)
+            .map_err(|e| Error::Clipboard(format!("Failed to read the clipboard: {}", e)))?;
+        Ok(Some(text))
+    }
+
+    /// Replace the clipboard with `text`
+    pub fn set_text(text: &str) -> Result<()> {
+        let _clipboard = open()?;
+        formats::Unicode.write_clipboard(&text).map_err(|e| Error::Clipboard(format!("Failed to set the clipboard: {}", e)))
+    }
+
+    /// Replace the clipboard with the image `png`, as PNG for apps that read
+    /// it and as a bitmap for the rest
+    pub fn set_png(png: &[u8]) -> Result<()> {
+        let dib = super::image_to_dib(&image::load_from_memory_with_format(png, ImageFormat::Png)?.to_rgba8());
+        let _clipboard = open()?;
+        let set = |format: u32, data: &[u8]| {
+            formats::RawData(format).write_clipboard(data).map_err(|e| Error::Clipboard(format!("Failed to set the clipboard: {}", e)))
+        };
+        raw::empty().map_err(|e| Error::Clipboard(format!("Failed to empty the clipboard: {}", e)))?;
+        set(formats::CF_DIB, &dib)?;
+        if let Some(format) = png_format() {
+            set(format, png)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(target_os = "windows", feature = "native-clipboard"))]
+pub use native::{changed, image, set_png, set_text, text};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dib_conversion() {
+        let mut img = RgbaImage::from_pixel(3, 2, Rgba([10, 20, 30, 255]));
+        img.put_pixel(2, 0, Rgba([200, 100, 50, 128]));
+        let dib = image_to_dib(&img);
+        assert_eq!(dib_to_image(&dib), Some(img));
+
+        // A bottom-up 24-bit bitmap, rows padded to 4 bytes, with the blue pixel on top
+        let mut dib = Vec::new();
+        for value in [40u32, 1, 2] {
+            dib.extend_from_slice(&value.to_le_bytes());
+        }
+        dib.extend_from_slice(&1u16.to_le_bytes());
+        dib.extend_from_slice(&24u16.to_le_bytes());
+        dib.extend_from_slice(&[0; 24]);
+        dib.extend_from_slice(&[0, 0, 255, 0, 255, 0, 0, 0]);
+        let img = dib_to_image(&dib).unwrap();
+        assert_eq!((img.get_pixel(0, 0), img.get_pixel(0, 1)), (&Rgba([0, 0, 255, 255]), &Rgba([255, 0, 0, 255])));
+
+        // Compressed and truncated bitmaps are refused
+        let mut compressed = dib.clone();
+        compressed[16] = 1;
+        assert_eq!(dib_to_image(&compressed), None);
+        assert_eq!(dib_to_image(&dib[..dib.len() - 1]), None);
+    }
+}